@@ -1,5 +1,8 @@
 const std = @import("std");
 
+/// Reported by `nyx build --manifest`.
+const version = @import("build.zig.zon").version;
+
 pub fn build(b: *std.Build) void {
     const target = b.standardTargetOptions(.{});
     const optimize = b.standardOptimizeOption(.{});
@@ -8,6 +11,26 @@ pub fn build(b: *std.Build) void {
     const yazap_dep = b.dependency("yazap", .{});
     const libffi_dep = b.dependency("libffi", .{ .target = target, .optimize = optimize });
 
+    const options = b.addOptions();
+    options.addOption([]const u8, "version", version);
+    options.addOption(bool, "net", b.option(bool, "net", "Include the socket syscalls (default: true)") orelse true);
+    options.addOption(bool, "ipc", b.option(bool, "ipc", "Include the clipboard and named pipe syscalls (default: true)") orelse true);
+    options.addOption(bool, "ron", b.option(bool, "ron", "Support --emit ast --format ron (default: true)") orelse true);
+    options.addOption(bool, "predecode", b.option(bool, "predecode", "Cache hot code as pre-decoded blocks (default: false)") orelse false);
+    const display = b.option(bool, "display", "Open a window for the framebuffer with SDL2, which must be installed (default: false)") orelse false;
+    options.addOption(bool, "display", display);
+
+    const nyx_mod = b.addModule("nyx", .{
+        .root_source_file = b.path("src/root.zig"),
+        .target = target,
+        .optimize = optimize,
+    });
+
+    nyx_mod.addImport("fehler", fehler_dep.module("fehler"));
+    nyx_mod.linkLibrary(libffi_dep.artifact("ffi"));
+    nyx_mod.addOptions("build_options", options);
+    if (display) linkSdl(nyx_mod);
+
     const exe = b.addExecutable(.{
         .name = "nyx",
         .root_module = b.createModule(.{
@@ -20,6 +43,8 @@ pub fn build(b: *std.Build) void {
     exe.root_module.addImport("fehler", fehler_dep.module("fehler"));
     exe.root_module.addImport("yazap", yazap_dep.module("yazap"));
     exe.root_module.linkLibrary(libffi_dep.artifact("ffi"));
+    exe.root_module.addOptions("build_options", options);
+    if (display) linkSdl(exe.root_module);
 
     b.installArtifact(exe);
 
@@ -43,9 +68,38 @@ pub fn build(b: *std.Build) void {
     });
 
     exe_tests.root_module.addImport("fehler", fehler_dep.module("fehler"));
+    exe_tests.root_module.addImport("yazap", yazap_dep.module("yazap"));
+    exe_tests.root_module.linkLibrary(libffi_dep.artifact("ffi"));
+    exe_tests.root_module.addOptions("build_options", options);
+    if (display) linkSdl(exe_tests.root_module);
 
     const run_exe_tests = b.addRunArtifact(exe_tests);
 
     const test_step = b.step("test", "Run tests");
     test_step.dependOn(&run_exe_tests.step);
+
+    // Always optimized: a debug build would time safety checks, not dispatch.
+    const bench = b.addExecutable(.{
+        .name = "nyx-bench",
+        .root_module = b.createModule(.{
+            .root_source_file = b.path("src/bench.zig"),
+            .target = target,
+            .optimize = .ReleaseFast,
+        }),
+    });
+
+    bench.root_module.addImport("fehler", fehler_dep.module("fehler"));
+    bench.root_module.linkLibrary(libffi_dep.artifact("ffi"));
+    bench.root_module.addOptions("build_options", options);
+    if (display) linkSdl(bench.root_module);
+
+    const run_bench = b.addRunArtifact(bench);
+
+    const bench_step = b.step("bench", "Time the interpreter on a loop-heavy program");
+    bench_step.dependOn(&run_bench.step);
+}
+
+fn linkSdl(module: *std.Build.Module) void {
+    module.link_libc = true;
+    module.linkSystemLibrary("SDL2", .{});
 }
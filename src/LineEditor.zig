@@ -0,0 +1,242 @@
+//! Minimal line editor for the interactive modes.
+//!
+//! In raw mode it supports cursor movement, command history on the arrow
+//! keys, and tab completion through a caller supplied `Completer`. When the
+//! input is not a terminal it falls back to reading plain lines, so scripted
+//! sessions keep working.
+
+const std = @import("std");
+const Io = std.Io;
+const mem = std.mem;
+const Allocator = mem.Allocator;
+const ArrayList = std.array_list.Managed;
+const utils = @import("utils.zig");
+
+const LineEditor = @This();
+
+pub const max_history: usize = 500;
+
+pub const Completer = struct {
+    context: *anyopaque,
+    /// Append every candidate that starts with `prefix` to `candidates`.
+    completeFn: *const fn (context: *anyopaque, prefix: []const u8, candidates: *ArrayList([]const u8)) anyerror!void,
+};
+
+gpa: Allocator,
+reader: *Io.Reader,
+writer: *Io.Writer,
+raw: bool,
+/// Terminal switched to raw mode while a line is read and back afterwards,
+/// so commands run with signals and echo as usual.
+terminal: ?std.posix.fd_t,
+history: ArrayList([]u8),
+line: ArrayList(u8),
+cursor: usize,
+completer: ?Completer,
+
+pub fn init(gpa: Allocator, reader: *Io.Reader, writer: *Io.Writer, raw: bool) LineEditor {
+    return LineEditor{
+        .gpa = gpa,
+        .reader = reader,
+        .writer = writer,
+        .raw = raw,
+        .terminal = null,
+        .history = .init(gpa),
+        .line = .init(gpa),
+        .cursor = 0,
+        .completer = null,
+    };
+}
+
+pub fn deinit(self: *LineEditor) void {
+    for (self.history.items) |entry| self.gpa.free(entry);
+    self.history.deinit();
+    self.line.deinit();
+}
+
+/// Remember `line`, skipping blank lines and immediate repeats.
+pub fn addHistory(self: *LineEditor, line: []const u8) !void {
+    const trimmed = mem.trim(u8, line, " \t\r");
+    if (trimmed.len == 0) return;
+    if (self.history.items.len > 0 and mem.eql(u8, self.history.items[self.history.items.len - 1], trimmed)) return;
+
+    if (self.history.items.len == max_history) self.gpa.free(self.history.orderedRemove(0));
+    const copy = try self.gpa.dupe(u8, trimmed);
+    errdefer self.gpa.free(copy);
+    try self.history.append(copy);
+}
+
+/// Load history from `path`, one entry per line. A missing file is not an
+/// error.
+pub fn loadHistory(self: *LineEditor, io: Io, path: []const u8) !void {
+    if (!utils.fileExists(io, path)) return;
+    const contents = try utils.readFromFile(io, self.gpa, path);
+    defer self.gpa.free(contents);
+
+    var lines = mem.splitScalar(u8, contents, '\n');
+    while (lines.next()) |line| try self.addHistory(line);
+}
+
+pub fn saveHistory(self: *LineEditor, io: Io, path: []const u8) !void {
+    var contents = ArrayList(u8).init(self.gpa);
+    defer contents.deinit();
+    for (self.history.items) |entry| {
+        try contents.appendSlice(entry);
+        try contents.append('\n');
+    }
+    try utils.writeToFile(io, path, contents.items);
+}
+
+/// Read one line, showing `prompt`. Returns null at the end of input. The
+/// returned slice is valid until the next call.
+pub fn readLine(self: *LineEditor, prompt: []const u8) !?[]const u8 {
+    const original_mode = if (self.terminal) |fd| enableRawMode(fd) else null;
+    defer if (original_mode) |mode| restoreMode(self.terminal.?, mode);
+
+    if (!self.raw or (self.terminal != null and original_mode == null)) {
+        try self.writer.writeAll(prompt);
+        try self.writer.flush();
+        const line = try self.reader.takeDelimiter('\n') orelse return null;
+        return mem.trimEnd(u8, line, "\r");
+    }
+
+    self.line.clearRetainingCapacity();
+    self.cursor = 0;
+    var history_index = self.history.items.len;
+    try self.refresh(prompt);
+
+    while (true) {
+        const byte = self.reader.takeByte() catch |err| switch (err) {
+            error.EndOfStream => return null,
+            else => return err,
+        };
+
+        switch (byte) {
+            '\r', '\n' => {
+                try self.writer.writeAll("\r\n");
+                try self.writer.flush();
+                return self.line.items;
+            },
+            // Ctrl-D ends the session on an empty line.
+            0x04 => {
+                if (self.line.items.len == 0) {
+                    try self.writer.writeAll("\r\n");
+                    try self.writer.flush();
+                    return null;
+                }
+                self.deleteAt(self.cursor);
+            },
+            // Ctrl-C abandons the current line.
+            0x03 => {
+                self.line.clearRetainingCapacity();
+                self.cursor = 0;
+            },
+            0x01 => self.cursor = 0,
+            0x05 => self.cursor = self.line.items.len,
+            0x08, 0x7F => if (self.cursor > 0) {
+                self.cursor -= 1;
+                self.deleteAt(self.cursor);
+            },
+            '\t' => try self.complete(),
+            0x1B => {
+                if (try self.reader.takeByte() != '[') continue;
+                switch (try self.reader.takeByte()) {
+                    'A' => if (history_index > 0) {
+                        history_index -= 1;
+                        try self.setLine(self.history.items[history_index]);
+                    },
+                    'B' => if (history_index < self.history.items.len) {
+                        history_index += 1;
+                        try self.setLine(if (history_index == self.history.items.len) "" else self.history.items[history_index]);
+                    },
+                    'C' => self.cursor = @min(self.cursor + 1, self.line.items.len),
+                    'D' => self.cursor -|= 1,
+                    'H' => self.cursor = 0,
+                    'F' => self.cursor = self.line.items.len,
+                    '3' => {
+                        _ = try self.reader.takeByte();
+                        self.deleteAt(self.cursor);
+                    },
+                    else => {},
+                }
+            },
+            else => if (byte >= 0x20) {
+                try self.line.insert(self.cursor, byte);
+                self.cursor += 1;
+            },
+        }
+
+        try self.refresh(prompt);
+    }
+}
+
+/// Put the terminal behind `fd` into raw mode so keys arrive one at a time.
+/// Returns the previous settings, or null if `fd` is not a terminal.
+pub fn enableRawMode(fd: std.posix.fd_t) ?std.posix.termios {
+    if (!std.posix.isatty(fd)) return null;
+    const original = std.posix.tcgetattr(fd) catch return null;
+
+    var raw = original;
+    raw.lflag.ICANON = false;
+    raw.lflag.ECHO = false;
+    raw.lflag.ISIG = false;
+    raw.iflag.ICRNL = false;
+    raw.iflag.IXON = false;
+    raw.cc[@intFromEnum(std.posix.V.MIN)] = 1;
+    raw.cc[@intFromEnum(std.posix.V.TIME)] = 0;
+    // Switch without discarding input typed ahead of the prompt.
+    std.posix.tcsetattr(fd, .NOW, raw) catch return null;
+
+    return original;
+}
+
+pub fn restoreMode(fd: std.posix.fd_t, original: std.posix.termios) void {
+    std.posix.tcsetattr(fd, .NOW, original) catch {};
+}
+
+fn deleteAt(self: *LineEditor, index: usize) void {
+    if (index < self.line.items.len) _ = self.line.orderedRemove(index);
+}
+
+fn setLine(self: *LineEditor, text: []const u8) !void {
+    self.line.clearRetainingCapacity();
+    try self.line.appendSlice(text);
+    self.cursor = text.len;
+}
+
+fn refresh(self: *LineEditor, prompt: []const u8) !void {
+    try self.writer.print("\r{s}{s}\x1b[K", .{ prompt, self.line.items });
+    const back = self.line.items.len - self.cursor;
+    if (back > 0) try self.writer.print("\x1b[{d}D", .{back});
+    try self.writer.flush();
+}
+
+fn complete(self: *LineEditor) !void {
+    const completer = self.completer orelse return;
+
+    const start = if (mem.lastIndexOfScalar(u8, self.line.items[0..self.cursor], ' ')) |i| i + 1 else 0;
+    const prefix = self.line.items[start..self.cursor];
+
+    var candidates = ArrayList([]const u8).init(self.gpa);
+    defer candidates.deinit();
+    try completer.completeFn(completer.context, prefix, &candidates);
+    if (candidates.items.len == 0) return;
+
+    var common = candidates.items[0];
+    for (candidates.items[1..]) |candidate| {
+        common = common[0..mem.indexOfDiff(u8, common, candidate) orelse common.len];
+    }
+
+    if (common.len > prefix.len) {
+        try self.line.insertSlice(self.cursor, common[prefix.len..]);
+        self.cursor += common.len - prefix.len;
+    }
+    if (candidates.items.len == 1) {
+        try self.line.insert(self.cursor, ' ');
+        self.cursor += 1;
+    } else if (common.len == prefix.len) {
+        try self.writer.writeAll("\r\n");
+        for (candidates.items) |candidate| try self.writer.print("{s}  ", .{candidate});
+        try self.writer.writeAll("\r\n");
+    }
+}
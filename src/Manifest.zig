@@ -0,0 +1,101 @@
+//! Machine-readable summary of a `nyx build`, written as JSON with
+//! `--manifest` so build systems driving nyx do not have to inspect the
+//! output themselves.
+
+const std = @import("std");
+const Io = std.Io;
+const Sha256 = std.crypto.hash.sha2.Sha256;
+const build_options = @import("build_options");
+const features = @import("vm/features.zig");
+const Compiler = @import("compiler/Compiler.zig");
+
+const Manifest = @This();
+
+pub const Output = struct {
+    path: []const u8,
+    /// `bytecode` or `obj`.
+    kind: []const u8,
+    /// The bytes written to `path`.
+    contents: []const u8,
+};
+
+sources: []const []const u8,
+outputs: []const Output,
+/// Address execution starts at, or null for an object whose entry point is
+/// only known after linking.
+entry: ?u64,
+text_size: usize,
+data_size: usize,
+symbol_count: usize,
+required: features.Set,
+
+/// Describe what `compiler` built from `sources`. The caller fills in
+/// `outputs` and, once it is known, `entry`.
+pub fn fromCompiler(compiler: *Compiler, sources: []const []const u8) Manifest {
+    return .{
+        .sources = sources,
+        .outputs = &.{},
+        .entry = null,
+        .text_size = compiler.bytecode.len(.text),
+        .data_size = compiler.bytecode.len(.data),
+        .symbol_count = compiler.labels.count(),
+        .required = compiler.required,
+    };
+}
+
+pub fn write(self: *const Manifest, writer: *Io.Writer) !void {
+    var json: std.json.Stringify = .{ .writer = writer, .options = .{ .whitespace = .indent_2 } };
+
+    try json.beginObject();
+    try json.objectField("toolchain");
+    try json.beginObject();
+    try json.objectField("name");
+    try json.write("nyx");
+    try json.objectField("version");
+    try json.write(build_options.version);
+    try json.endObject();
+
+    try json.objectField("sources");
+    try json.write(self.sources);
+
+    try json.objectField("outputs");
+    try json.beginArray();
+    for (self.outputs) |output| {
+        var digest: [Sha256.digest_length]u8 = undefined;
+        Sha256.hash(output.contents, &digest, .{});
+        try json.beginObject();
+        try json.objectField("path");
+        try json.write(output.path);
+        try json.objectField("kind");
+        try json.write(output.kind);
+        try json.objectField("size");
+        try json.write(output.contents.len);
+        try json.objectField("sha256");
+        try json.write(&std.fmt.bytesToHex(digest, .lower));
+        try json.endObject();
+    }
+    try json.endArray();
+
+    try json.objectField("entry");
+    try json.write(self.entry);
+
+    try json.objectField("sections");
+    try json.beginObject();
+    try json.objectField("text");
+    try json.write(self.text_size);
+    try json.objectField("data");
+    try json.write(self.data_size);
+    try json.endObject();
+
+    try json.objectField("symbols");
+    try json.write(self.symbol_count);
+
+    try json.objectField("requires");
+    try json.beginArray();
+    var iter = self.required.iterator();
+    while (iter.next()) |feature| try json.write(@tagName(feature));
+    try json.endArray();
+
+    try json.endObject();
+    try writer.writeByte('\n');
+}
@@ -1,3 +1,4 @@
+const std = @import("std");
 const fehler = @import("fehler");
 
 const Span = @This();
@@ -10,37 +11,45 @@ pub fn init(start: usize, end: usize, filename: []const u8) Span {
     return Span{ .start = start, .end = end, .filename = filename };
 }
 
+/// Line and column of the span in `source`, the text of `filename`. After a
+/// `#line N "file"` directive, the lines that follow it are numbered from
+/// `N` in `file` instead, so generated code is reported where it came from.
 pub fn toSourceRange(self: *const Span, source: []const u8) fehler.SourceRange {
+    var file = self.filename;
     var line: usize = 1;
     var column: usize = 1;
+    var directive = LineDirective.parse(source);
+
+    var start_file = file;
     var start_pos: ?fehler.Position = null;
     var end_pos: ?fehler.Position = null;
 
     for (source, 0..) |ch, i| {
         if (start_pos == null and i == self.start) {
             start_pos = fehler.Position{ .line = line, .column = column };
+            start_file = file;
         }
         if (end_pos == null and i == self.end) {
             end_pos = fehler.Position{ .line = line, .column = column };
             break;
         }
 
-        switch (ch) {
-            '\n' => {
+        const ends_line = switch (ch) {
+            '\n' => true,
+            '\r' => i + 1 < source.len and source[i + 1] != '\n',
+            else => false,
+        };
+        if (ends_line) {
+            if (directive) |d| {
+                line = d.line;
+                if (d.file) |name| file = name;
+            } else {
                 line += 1;
-                column = 1;
-            },
-            '\r' => {
-                if (i + 1 < source.len and source[i + 1] != '\n') {
-                    line += 1;
-                    column = 1;
-                } else {
-                    column += 1;
-                }
-            },
-            else => {
-                column += 1;
-            },
+            }
+            column = 1;
+            directive = LineDirective.parse(source[i + 1 ..]);
+        } else {
+            column += 1;
         }
     }
 
@@ -52,8 +61,54 @@ pub fn toSourceRange(self: *const Span, source: []const u8) fehler.SourceRange {
     }
 
     return fehler.SourceRange{
-        .file = self.filename,
+        .file = start_file,
         .start = start_pos.?,
         .end = end_pos.?,
     };
 }
+
+/// Report `message` at the span. A span that `#line` moved into a file the
+/// reporter has no source for is reported with its location in the message
+/// instead, since there is no line to show.
+pub fn report(self: Span, reporter: *fehler.ErrorReporter, severity: fehler.Severity, message: []const u8) void {
+    const range = self.toSourceRange(reporter.sources.get(self.filename).?);
+    if (reporter.sources.get(range.file) == null) {
+        var buf: [1024]u8 = undefined;
+        const located = std.fmt.bufPrint(&buf, "{s}:{d}:{d}: {s}", .{ range.file, range.start.line, range.start.column, message }) catch message;
+        reporter.report(.{ .severity = severity, .message = located });
+        return;
+    }
+    reporter.report(.{
+        .severity = severity,
+        .message = message,
+        .range = range,
+    });
+}
+
+/// `#line N` or `#line N "file"` at the start of a line, which numbers the
+/// next line `N`.
+pub const LineDirective = struct {
+    line: usize,
+    file: ?[]const u8,
+
+    /// The directive that `text` starts with, if it starts with one.
+    pub fn parse(text: []const u8) ?LineDirective {
+        const line_end = std.mem.indexOfAny(u8, text, "\r\n") orelse text.len;
+        var rest = std.mem.trimStart(u8, text[0..line_end], " \t");
+        if (!std.mem.startsWith(u8, rest, "#line")) return null;
+        rest = rest["#line".len..];
+        if (rest.len == 0 or (rest[0] != ' ' and rest[0] != '\t')) return null;
+        rest = std.mem.trimStart(u8, rest, " \t");
+
+        const digits = for (rest, 0..) |c, i| {
+            if (!std.ascii.isDigit(c)) break i;
+        } else rest.len;
+        const line = std.fmt.parseInt(usize, rest[0..digits], 10) catch return null;
+        if (line == 0) return null;
+        rest = std.mem.trimStart(u8, rest[digits..], " \t");
+
+        if (rest.len == 0 or rest[0] != '"') return .{ .line = line, .file = null };
+        const close = std.mem.indexOfScalarPos(u8, rest, 1, '"') orelse return null;
+        return .{ .line = line, .file = rest[1..close] };
+    }
+};
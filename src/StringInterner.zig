@@ -4,9 +4,11 @@ const ArrayList = std.array_list.Managed;
 
 const StringInterner = @This();
 
-pub const StringId = u32;
+/// Index of an interned string. A type of its own rather than a `u32`, so
+/// code that walks the AST by type can tell names from numbers.
+pub const StringId = enum(u32) { _ };
 
-pub const INVALID_ID: StringId = std.math.maxInt(StringId);
+pub const INVALID_ID: StringId = @enumFromInt(std.math.maxInt(u32));
 
 gpa: Allocator,
 strings: ArrayList([]const u8),
@@ -33,7 +35,7 @@ pub fn intern(self: *StringInterner, s: []const u8) !StringId {
         return id;
     }
 
-    const id: StringId = @intCast(self.strings.items.len);
+    const id: StringId = @enumFromInt(self.strings.items.len);
     const owned = try self.gpa.dupe(u8, s);
     errdefer self.gpa.free(owned);
 
@@ -44,8 +46,9 @@ pub fn intern(self: *StringInterner, s: []const u8) !StringId {
 }
 
 pub fn get(self: *const StringInterner, id: StringId) ?[]const u8 {
-    if (id >= self.strings.items.len) return null;
-    return self.strings.items[id];
+    const index = @intFromEnum(id);
+    if (index >= self.strings.items.len) return null;
+    return self.strings.items[index];
 }
 
 pub fn getId(self: *const StringInterner, s: []const u8) ?StringId {
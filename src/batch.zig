@@ -0,0 +1,131 @@
+//! Batch runs behind `nyx exec --batch`: many bytecode files run one after
+//! another, each in a fresh VM with the same limits, and the way each one
+//! ended is reported as JSON for autograders and fuzz triage to consume.
+//!
+//! A program that traps, or that cannot even be loaded, does not stop the
+//! batch; its result names the first error instead.
+
+const std = @import("std");
+const Allocator = std.mem.Allocator;
+const Io = std.Io;
+const posix = std.posix.system;
+const Vm = @import("vm/Vm.zig");
+
+/// What every program in the batch gets.
+pub const Limits = struct {
+    memory_size: usize,
+    heap_size: ?usize = null,
+    stack_guard: ?usize = null,
+    aslr: bool = false,
+    protect: bool = true,
+    division: Vm.Division = .trap,
+    /// Instructions each program may run before it is stopped.
+    max_steps: ?u64 = null,
+    external_libraries: [][]const u8 = &.{},
+};
+
+pub const Result = struct {
+    path: []const u8,
+    /// Passed to `sys_exit`, 0 after `hlt`, or null if the program did not
+    /// finish.
+    status: ?u8 = null,
+    steps: u64 = 0,
+    nanoseconds: u64 = 0,
+    failure: ?Failure = null,
+};
+
+pub const Failure = struct {
+    err: anyerror,
+    /// Instruction that raised `err`, or null if the program never started.
+    addr: ?usize = null,
+};
+
+/// Run the program in `bytecode` to completion. `path` only names it in
+/// the result.
+pub fn run(gpa: Allocator, path: []const u8, bytecode: []const u8, limits: Limits) Result {
+    const start = nanoseconds();
+    var result = Result{ .path = path };
+    execute(gpa, bytecode, limits, &result) catch |err| {
+        result.failure = .{ .err = err };
+    };
+    result.nanoseconds = nanoseconds() - start;
+    return result;
+}
+
+fn execute(gpa: Allocator, bytecode: []const u8, limits: Limits, result: *Result) !void {
+    var vm = try Vm.init(bytecode, limits.memory_size, limits.external_libraries, gpa);
+    defer vm.deinit();
+    if (limits.heap_size) |size| try vm.setHeapSize(size);
+    if (limits.aslr) try vm.randomizeLayout(std.crypto.random);
+    if (limits.stack_guard) |slack| try vm.setStackGuard(slack);
+    if (!limits.protect) vm.disableProtection();
+    vm.division = limits.division;
+
+    const finished = if (limits.max_steps) |steps| vm.runWithFuel(steps) else vm.run();
+    finished catch |err| {
+        result.steps = vm.counters.instructions;
+        result.failure = .{ .err = err, .addr = vm.instruction_start };
+        return;
+    };
+    result.steps = vm.counters.instructions;
+    result.status = vm.exit_status orelse 0;
+}
+
+fn nanoseconds() u64 {
+    var ts: posix.timespec = undefined;
+    _ = posix.clock_gettime(posix.CLOCK.MONOTONIC, &ts);
+    return @as(u64, @intCast(ts.sec)) * std.time.ns_per_s + @as(u64, @intCast(ts.nsec));
+}
+
+/// Paths listed in `text`, one per line. Blank lines and lines starting
+/// with `#` are skipped. The paths point into `text`.
+pub fn parseList(gpa: Allocator, text: []const u8) ![]const []const u8 {
+    var paths = std.array_list.Managed([]const u8).init(gpa);
+    errdefer paths.deinit();
+    var lines = std.mem.splitScalar(u8, text, '\n');
+    while (lines.next()) |line| {
+        const path = std.mem.trim(u8, line, " \t\r");
+        if (path.len == 0 or path[0] == '#') continue;
+        try paths.append(path);
+    }
+    return paths.toOwnedSlice();
+}
+
+/// Write `results` as a JSON array with one object per program:
+///
+/// ```json
+/// {"file": "a.nyb", "status": 0, "steps": 120, "time_ms": 0.31, "error": null}
+/// ```
+///
+/// `error` is null or an object with the error's `name` and the `address`
+/// of the instruction that raised it, which is null for load errors.
+pub fn writeResults(results: []const Result, writer: *Io.Writer) !void {
+    var json: std.json.Stringify = .{ .writer = writer, .options = .{ .whitespace = .indent_2 } };
+
+    try json.beginArray();
+    for (results) |result| {
+        try json.beginObject();
+        try json.objectField("file");
+        try json.write(result.path);
+        try json.objectField("status");
+        try json.write(result.status);
+        try json.objectField("steps");
+        try json.write(result.steps);
+        try json.objectField("time_ms");
+        try json.write(@as(f64, @floatFromInt(result.nanoseconds)) / std.time.ns_per_ms);
+        try json.objectField("error");
+        if (result.failure) |failure| {
+            try json.beginObject();
+            try json.objectField("name");
+            try json.write(@errorName(failure.err));
+            try json.objectField("address");
+            try json.write(failure.addr);
+            try json.endObject();
+        } else {
+            try json.write(null);
+        }
+        try json.endObject();
+    }
+    try json.endArray();
+    try writer.writeByte('\n');
+}
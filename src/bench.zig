@@ -0,0 +1,67 @@
+//! `zig build bench`: times the interpreter on a loop-heavy program and
+//! reports instructions per second. Always built with ReleaseFast so runs
+//! on different commits can be compared; the number only means something
+//! next to another run on the same machine.
+
+const std = @import("std");
+const posix = std.posix.system;
+const Io = std.Io;
+const nyx = @import("root.zig");
+const Vm = nyx.Vm;
+
+const runs = 5;
+const memory_size = 1024 * 1024;
+
+/// Mostly register arithmetic, compares and branches, with some stack
+/// traffic and a call per iteration, so dispatch dominates the run.
+const program =
+    \\.section text
+    \\_start:
+    \\    mov q1, 0
+    \\    mov q2, 2000000
+    \\loop:
+    \\    add q1, q1, q2
+    \\    xor q3, q1, q2
+    \\    push q3
+    \\    call step
+    \\    pop q3
+    \\    dec q2
+    \\    cmp q2, 0
+    \\    jne loop
+    \\    hlt
+    \\step:
+    \\    inc q4
+    \\    ret
+;
+
+pub fn main(init: std.process.Init) !void {
+    const bytecode = try nyx.assemble(init.io, init.gpa, program, .{ .filename = "bench.nyx" });
+    defer init.gpa.free(bytecode);
+
+    var buffer: [256]u8 = undefined;
+    var stdout_writer = Io.File.stdout().writer(init.io, &buffer);
+    const stdout = &stdout_writer.interface;
+
+    var best: u64 = std.math.maxInt(u64);
+    var instructions: u64 = 0;
+    for (0..runs) |_| {
+        var vm = try Vm.init(bytecode, memory_size, &.{}, init.gpa);
+        defer vm.deinit();
+
+        const start = nanoseconds();
+        try vm.run();
+        best = @min(best, nanoseconds() - start);
+        instructions = vm.metrics().instructions;
+    }
+
+    const seconds = @as(f64, @floatFromInt(best)) / std.time.ns_per_s;
+    try stdout.print("{d} instructions in {d:.3} ms (best of {d})\n", .{ instructions, seconds * std.time.ms_per_s, runs });
+    try stdout.print("{d:.1} M instructions/s\n", .{@as(f64, @floatFromInt(instructions)) / seconds / 1e6});
+    try stdout.flush();
+}
+
+fn nanoseconds() u64 {
+    var ts: posix.timespec = undefined;
+    _ = posix.clock_gettime(posix.CLOCK.MONOTONIC, &ts);
+    return @as(u64, @intCast(ts.sec)) * std.time.ns_per_s + @as(u64, @intCast(ts.nsec));
+}
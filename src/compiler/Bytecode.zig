@@ -1,4 +1,5 @@
 const std = @import("std");
+const Io = std.Io;
 const mem = std.mem;
 const Allocator = mem.Allocator;
 const ArrayList = std.array_list.Managed;
@@ -54,23 +55,12 @@ pub inline fn extend(self: *Bytecode, iter: anytype) !void {
 }
 
 pub inline fn grow(self: *Bytecode, amount: usize) !void {
-    const zeros = try self.getAllocator().alloc(u8, amount);
-    defer self.getAllocator().free(zeros);
-    @memset(zeros, 0);
-
     switch (self.current_section) {
-        .text => try self.text.appendSlice(zeros),
-        .data => try self.data.appendSlice(zeros),
+        .text => try self.text.appendNTimes(0x00, amount),
+        .data => try self.data.appendNTimes(0x00, amount),
     }
 }
 
-inline fn getAllocator(self: *Bytecode) Allocator {
-    return switch (self.current_section) {
-        .text => self.text.allocator,
-        .data => self.data.allocator,
-    };
-}
-
 pub inline fn writeU8At(self: *Bytecode, section: Section, offset: usize, value: u8) void {
     switch (section) {
         .text => self.text.items[offset] = value,
@@ -102,9 +92,8 @@ pub inline fn writeU64At(self: *Bytecode, section: Section, offset: usize, value
     }
 }
 
-pub fn finalize(self: *Bytecode, gpa: Allocator) ![]u8 {
-    var bytes = ArrayList(u8).init(gpa);
-    try bytes.appendSlice(self.text.items);
-    try bytes.appendSlice(self.data.items);
-    return bytes.toOwnedSlice();
+/// Write the text section followed by the data section to `writer`.
+pub fn writeTo(self: *Bytecode, writer: *Io.Writer) Io.Writer.Error!void {
+    try writer.writeAll(self.text.items);
+    try writer.writeAll(self.data.items);
 }
@@ -2,6 +2,7 @@
 //       they could be specific errors instead
 
 const std = @import("std");
+const Io = std.Io;
 const process = std.process;
 const mem = std.mem;
 const Allocator = mem.Allocator;
@@ -12,8 +13,14 @@ const Bytecode = @import("Bytecode.zig");
 const Opcode = @import("opcode.zig").Opcode;
 const Span = @import("../Span.zig");
 const DataSize = @import("../parser/immediate.zig").DataSize;
+const Register = @import("../vm/register.zig").Register;
 const fehler = @import("fehler");
 const ast = @import("../parser/ast.zig");
+const optimizer = @import("optimizer.zig");
+const features = @import("../vm/features.zig");
+const DebugInfo = @import("../vm/DebugInfo.zig");
+const Object = @import("Object.zig");
+const warning = @import("../warning.zig");
 
 const Compiler = @This();
 const FfiType = ast.Statement.FfiType;
@@ -28,6 +35,9 @@ const ExternInfo = struct {
 pub const addressing_variant_1: u8 = 0x00; // [REGISTER, ?INTEGER]
 pub const addressing_variant_2: u8 = 0x01; // [INTEGER, ?INTEGER]
 
+/// Default upper bound for the size of a single section, in bytes.
+pub const default_max_section_size: usize = 16 * 1024 * 1024;
+
 pub const Entry = union(enum) {
     address: u64,
     fixup: Entry.Fixup,
@@ -43,22 +53,115 @@ const Label = struct {
     addr: usize,
 };
 
+/// An immediate encoded with only its low bits, reported once the program
+/// has been compiled for the last time.
+const Truncation = struct {
+    value: i64,
+    size: DataSize,
+    span: Span,
+};
+
 const Fixup = struct {
     size: DataSize,
     label: StringId,
+    /// Label whose address is subtracted, for a difference like
+    /// `msg_end - msg`.
+    minus: ?StringId = null,
+    addend: i64 = 0,
+    /// For the displacement of a relative jump or call, its index among the
+    /// relative jumps of the program. It is patched with the distance from
+    /// the end of the instruction to the label instead of the address.
+    jump: ?usize = null,
     span: Span,
 };
 
+/// A constant expression over labels, `label - minus + addend`.
+const LabelExpr = struct {
+    label: ?StringId = null,
+    minus: ?StringId = null,
+    addend: i64 = 0,
+};
+
+/// A constant defined with `equ`. References to it are recorded as fixups
+/// like label references, and its value is worked out when they are
+/// resolved, so it can refer to labels and equs defined after it.
+const Equ = struct {
+    expr: *ast.Expression,
+    span: Span,
+    state: enum { unresolved, resolving, resolved } = .unresolved,
+    /// The value once resolved, in terms of labels only.
+    value: LabelExpr = .{},
+};
+
 program: []ast.Statement,
 bytecode: Bytecode,
+/// The bytes each statement of the program compiled to, in program order.
+ranges: ArrayList(StatementRange),
 interner: *StringInterner,
 labels: std.AutoHashMap(StringId, Label),
+equs: std.AutoHashMap(StringId, Equ),
 fixups: std.AutoHashMap(Label, Fixup),
 externs: ArrayList(ExternInfo),
 entry: ?Entry,
+entry_span: ?Span,
+/// Features declared with `.requires`, recorded in the entry point header.
+required: features.Set,
+/// Largest `.align` in the text section, recorded in objects so the linker
+/// can keep it.
+text_alignment: usize,
+/// Largest `.align` in the data section. The text section is padded to a
+/// multiple of it so data offsets stay aligned once loaded after the text.
+data_alignment: usize,
+max_section_size: usize,
 filename: []const u8,
 input: []const u8,
 reporter: *fehler.ErrorReporter,
+/// Exit the process on the first error. Library callers turn this off and
+/// handle `error.CompilerError` themselves.
+exit_on_error: bool,
+/// Reject implicit push and pop sizes, a missing entry point, immediates
+/// that do not fit their operand, and unused labels.
+strict: bool,
+/// Which warnings are reported, and whether they fail the build.
+warnings: warning.Policy,
+/// Whether a warning has been reported, so denied warnings can fail the
+/// build once all of them are out.
+warned: bool,
+/// Encode the low bits of an immediate that does not fit its operand, with
+/// a warning, instead of rejecting it. Ignored in strict mode.
+truncate_immediates: bool,
+/// Immediates truncated in this pass.
+truncations: ArrayList(Truncation),
+/// Fold constants loaded into registers into the addresses that use them.
+optimize: bool,
+/// Append a `DebugInfo` section with the source line of every instruction
+/// and the address of every label.
+debug_info: bool,
+/// Append a `DebugInfo` section with only the address of every label, so
+/// errors and `nyx symbols` can name labels without the source lines.
+/// Implied by `debug_info`.
+symbols: bool,
+/// Encode jumps and calls to labels with their absolute address, as objects
+/// and single statements always are, instead of the shortest displacement.
+absolute_jumps: bool,
+/// Relative jumps compiled so far in this pass, or null while jumps to
+/// labels are encoded with absolute addresses.
+relative_jumps: ?usize,
+/// Relative jumps, by index, that need a rel32 displacement. Every other
+/// one is tried as rel8 first.
+long_jumps: std.AutoHashMap(usize, void),
+/// The last label without a leading dot. Local labels like `.loop` that
+/// follow it are stored as `scope.loop`.
+label_scope: ?StringId,
+/// Scopes of the files that included the one being scoped, innermost last.
+include_scopes: ArrayList(?StringId),
+/// The unqualified name of every qualified local label seen so far.
+local_names: std.AutoHashMap(StringId, StringId),
+/// The first definition of each unqualified local label name, pointed at
+/// when a reference finds no label of that name in its own scope.
+local_defs: std.AutoHashMap(StringId, Span),
+/// Expressions rewritten to refer to qualified local labels.
+arena: std.heap.ArenaAllocator,
 gpa: Allocator,
 
 pub fn init(
@@ -72,266 +175,1264 @@ pub fn init(
     return Compiler{
         .program = program,
         .bytecode = try .init(4 * program.len, gpa),
+        .ranges = .init(gpa),
         .interner = interner,
         .labels = .init(gpa),
+        .equs = .init(gpa),
         .fixups = .init(gpa),
         .externs = .init(gpa),
         .entry = null,
+        .entry_span = null,
+        .required = .initEmpty(),
+        .text_alignment = 1,
+        .data_alignment = 1,
+        .max_section_size = default_max_section_size,
         .filename = filename,
         .input = input,
         .reporter = reporter,
+        .exit_on_error = true,
+        .strict = false,
+        .warnings = .{},
+        .warned = false,
+        .truncate_immediates = false,
+        .truncations = .init(gpa),
+        .optimize = false,
+        .debug_info = false,
+        .symbols = false,
+        .absolute_jumps = false,
+        .relative_jumps = null,
+        .long_jumps = .init(gpa),
+        .label_scope = null,
+        .include_scopes = .init(gpa),
+        .local_names = .init(gpa),
+        .local_defs = .init(gpa),
+        .arena = .init(gpa),
         .gpa = gpa,
     };
 }
 
 pub fn deinit(self: *Compiler) void {
     self.bytecode.deinit();
+    self.ranges.deinit();
     self.labels.deinit();
+    self.equs.deinit();
     self.fixups.deinit();
     self.externs.deinit();
+    self.truncations.deinit();
+    self.long_jumps.deinit();
+    self.local_names.deinit();
+    self.local_defs.deinit();
+    self.include_scopes.deinit();
+    self.arena.deinit();
 }
 
+/// Compile the program and return the complete bytecode image. Caller owns
+/// the returned memory.
 pub fn compile(self: *Compiler) ![]u8 {
+    var output: Io.Writer.Allocating = .init(self.gpa);
+    errdefer output.deinit();
+    try self.compileTo(&output.writer);
+    return output.toOwnedSlice();
+}
+
+/// Compile the program and stream the bytecode image into `writer` without
+/// assembling it in memory first. The caller is responsible for flushing.
+pub fn compileTo(self: *Compiler, writer: *Io.Writer) !void {
+    const entry = try self.assemble();
+    var debug: Io.Writer.Allocating = .init(self.gpa);
+    defer debug.deinit();
+    if (self.debug_info or self.symbols) try self.writeDebugInfo(&debug.writer);
+
+    try features.write(writer, entry, self.required, .{
+        .text_len = @intCast(self.bytecode.len(.text)),
+        .data_len = @intCast(self.bytecode.len(.data)),
+        .debug_len = @intCast(debug.written().len),
+    });
+    try self.bytecode.writeTo(writer);
+    try writer.writeAll(debug.written());
+}
+
+/// Encode the address of every label, and where every statement that
+/// emitted text came from unless only `symbols` is set, as a `DebugInfo`
+/// section. Only meaningful after assembling.
+fn writeDebugInfo(self: *Compiler, writer: *Io.Writer) !void {
+    var info: DebugInfo = .{
+        .arena = .init(self.gpa),
+        .text_len = @intCast(self.bytecode.len(.text)),
+        .files = &.{},
+        .symbols = &.{},
+        .lines = &.{},
+    };
+    defer info.deinit();
+    const gpa = info.arena.allocator();
+
+    var files = std.StringArrayHashMap(void).init(gpa);
+    var lines = ArrayList(DebugInfo.Line).init(gpa);
+    for (self.ranges.items) |range| {
+        if (!self.debug_info) break;
+        if (range.section != .text or range.start == range.end) continue;
+        const where = range.span.toSourceRange(self.reporter.sources.get(range.span.filename).?);
+        const file = try files.getOrPut(where.file);
+        try lines.append(.{
+            .offset = @intCast(range.start),
+            .file = @intCast(file.index),
+            .line = @intCast(where.start.line),
+            .column = @intCast(where.start.column),
+        });
+    }
+
+    var addresses = std.StringArrayHashMap(u64).init(gpa);
+    try self.exportSymbols(&addresses);
+    const symbols = try gpa.alloc(DebugInfo.Symbol, addresses.count());
+    for (symbols, addresses.keys(), addresses.values()) |*symbol, name, addr| symbol.* = .{ .name = name, .addr = addr };
+    mem.sort(DebugInfo.Symbol, symbols, {}, struct {
+        fn lessThan(_: void, a: DebugInfo.Symbol, b: DebugInfo.Symbol) bool {
+            return a.addr < b.addr;
+        }
+    }.lessThan);
+
+    info.files = files.keys();
+    info.symbols = symbols;
+    info.lines = lines.items;
+    try info.write(writer);
+}
+
+/// Compile the program into a relocatable object for `nyx link` instead of
+/// bytecode. Every label reference is kept as a relocation, so it may name a
+/// label defined in another object. The strict checks that need the whole
+/// program (the entry point and unused labels) are skipped. Caller owns the
+/// returned object.
+pub fn compileObject(self: *Compiler) !Object {
+    try self.scopeLocalLabels();
+    if (self.optimize) self.program = optimizer.foldAddressConstants(self.program);
+    try self.compileProgram();
+    self.reportTruncations();
+    if (self.warnings.deny and self.warned) return self.fail();
+
+    var arena = std.heap.ArenaAllocator.init(self.gpa);
+    errdefer arena.deinit();
+    const gpa = arena.allocator();
+
+    var symbols = ArrayList(Object.Symbol).init(gpa);
+    for (self.program) |stmt| switch (stmt) {
+        .label => |v| {
+            const label = self.labels.get(v.name).?;
+            try symbols.append(.{
+                .name = try gpa.dupe(u8, self.interner.get(v.name).?),
+                .section = label.section,
+                .binding = if (self.local_names.contains(v.name)) .local else .global,
+                .offset = label.addr,
+                .location = try self.location(gpa, v.span),
+            });
+        },
+        else => {},
+    };
+
+    var relocations = ArrayList(Object.Relocation).init(gpa);
+    var fixup_iter = self.fixups.iterator();
+    while (fixup_iter.next()) |fixup| {
+        const value = try self.expandEqus(fixupExpr(fixup.value_ptr.*), fixup.value_ptr.span);
+        // A difference does not move when the object is linked, as long as
+        // both labels are in the same section of this file.
+        if (value.minus) |minus| {
+            const plus_label = if (value.label) |label| self.labels.get(label) else null;
+            const minus_label = self.labels.get(minus);
+            if (plus_label == null or minus_label == null or plus_label.?.section != minus_label.?.section) {
+                self.report(.err, "a label difference in an object file needs both labels in the same section of that file", fixup.value_ptr.span, 1);
+                return error.CompilerError;
+            }
+            const difference = @as(i128, plus_label.?.addr) - minus_label.?.addr + value.addend;
+            try self.patchFixup(fixup.key_ptr.*, fixup.value_ptr.*, difference, true);
+            continue;
+        }
+        const label = value.label orelse {
+            try self.patchFixup(fixup.key_ptr.*, fixup.value_ptr.*, value.addend, true);
+            continue;
+        };
+        try relocations.append(.{
+            .section = fixup.key_ptr.section,
+            .offset = fixup.key_ptr.addr,
+            .size = @intCast(fixup.value_ptr.size.sizeInBytes()),
+            .addend = value.addend,
+            .symbol = try gpa.dupe(u8, self.interner.get(label).?),
+            .location = try self.location(gpa, fixup.value_ptr.span),
+        });
+    }
+    mem.sort(Object.Relocation, relocations.items, {}, struct {
+        fn lessThan(_: void, a: Object.Relocation, b: Object.Relocation) bool {
+            if (a.section != b.section) return @intFromEnum(a.section) < @intFromEnum(b.section);
+            return a.offset < b.offset;
+        }
+    }.lessThan);
+
+    const entry: ?Object.Entry = if (self.entry) |entry| switch (entry) {
+        .address => |v| .{ .address = v },
+        .fixup => |v| .{ .symbol = .{
+            .name = try gpa.dupe(u8, self.interner.get(v.label).?),
+            .location = try self.location(gpa, v.span),
+        } },
+    } else null;
+
+    return .{
+        .arena = arena,
+        .source = try gpa.dupe(u8, self.filename),
+        .required = self.required,
+        .entry = entry,
+        .text = try gpa.dupe(u8, self.bytecode.text.items),
+        .data = try gpa.dupe(u8, self.bytecode.data.items),
+        .text_alignment = self.text_alignment,
+        .data_alignment = self.data_alignment,
+        .symbols = try symbols.toOwnedSlice(),
+        .relocations = try relocations.toOwnedSlice(),
+    };
+}
+
+/// Line and column of the start of `span`, with the file name copied with
+/// `gpa`.
+fn location(self: *Compiler, gpa: Allocator, span: Span) !Object.Location {
+    const range = span.toSourceRange(self.reporter.sources.get(span.filename).?);
+    return .{ .file = try gpa.dupe(u8, range.file), .line = range.start.line, .column = range.start.column };
+}
+
+/// Compile a single statement as if it were placed at `origin`, for
+/// assembling one line at a time. Labels defined by earlier calls stay
+/// visible and a label statement takes the address `origin`. Returns the
+/// statement's bytes, which are valid until the next call.
+pub fn assembleStatement(self: *Compiler, stmt: ast.Statement, origin: usize) ![]const u8 {
+    self.bytecode.text.clearRetainingCapacity();
+    self.bytecode.current_section = .text;
+    self.fixups.clearRetainingCapacity();
+    self.truncations.clearRetainingCapacity();
+
+    const scoped = try self.scopeStatement(stmt);
+    // `times` is allowed for whatever it repeats.
+    const single = switch (scoped) {
+        .times => |v| v.stmt.*,
+        else => scoped,
+    };
+    switch (single) {
+        .label => |v| try self.labels.put(v.name, .{ .section = .text, .addr = origin }),
+        .section, .entry, .requires, .@"align", .db, .dw, .dd, .dq, .df, .ddbl, .resb, .resw, .resd, .resq, .ascii, .asciz => {
+            self.report(.err, "only instructions, labels and .extern can be assembled one at a time", stmt.span(), 1);
+            return error.CompilerError;
+        },
+        else => try self.compileStatement(scoped),
+    }
+    self.reportTruncations();
+    try self.resolveFixups();
+    return self.bytecode.text.items;
+}
+
+/// Add the absolute address of every label to `symbols`. Names are copied
+/// with the map's allocator. Only meaningful after compiling.
+pub fn exportSymbols(self: *Compiler, symbols: *std.StringArrayHashMap(u64)) !void {
+    var iter = self.labels.iterator();
+    while (iter.next()) |entry| {
+        const name = try symbols.allocator.dupe(u8, self.interner.get(entry.key_ptr.*).?);
+        const addr = switch (entry.value_ptr.section) {
+            .text => entry.value_ptr.addr,
+            .data => self.bytecode.len(.text) + entry.value_ptr.addr,
+        };
+        try symbols.put(name, @intCast(addr));
+    }
+}
+
+pub const SizeEntry = struct {
+    /// Label name, or null for bytes emitted before the first label of a section.
+    name: ?[]const u8,
+    section: Bytecode.Section,
+    size: usize,
+};
+
+/// Bytes contributed by each label, counted from the label up to the next
+/// label in the same section, largest first. Only meaningful after
+/// compiling. Caller owns the returned slice.
+pub fn labelSizes(self: *Compiler) ![]SizeEntry {
+    const Item = struct { name: StringId, label: Label };
+    var labels = ArrayList(Item).init(self.gpa);
+    defer labels.deinit();
+    var iter = self.labels.iterator();
+    while (iter.next()) |entry| try labels.append(.{ .name = entry.key_ptr.*, .label = entry.value_ptr.* });
+
+    mem.sort(Item, labels.items, {}, struct {
+        fn lessThan(_: void, a: Item, b: Item) bool {
+            if (a.label.section != b.label.section) return @intFromEnum(a.label.section) < @intFromEnum(b.label.section);
+            return a.label.addr < b.label.addr;
+        }
+    }.lessThan);
+
+    var sizes = ArrayList(SizeEntry).init(self.gpa);
+    errdefer sizes.deinit();
+
+    for ([_]Bytecode.Section{ .text, .data }) |section| {
+        var start: usize = 0;
+        var name: ?[]const u8 = null;
+        for (labels.items) |item| {
+            if (item.label.section != section) continue;
+            if (item.label.addr > start or name != null) {
+                try sizes.append(.{ .name = name, .section = section, .size = item.label.addr - start });
+            }
+            start = item.label.addr;
+            name = self.interner.get(item.name).?;
+        }
+        const end = self.bytecode.len(section);
+        if (end > start or name != null) {
+            try sizes.append(.{ .name = name, .section = section, .size = end - start });
+        }
+    }
+
+    mem.sort(SizeEntry, sizes.items, {}, struct {
+        fn lessThan(_: void, a: SizeEntry, b: SizeEntry) bool {
+            return a.size > b.size;
+        }
+    }.lessThan);
+    return sizes.toOwnedSlice();
+}
+
+/// Print the size of each section and of each label region. Only meaningful
+/// after compiling.
+pub fn writeSizeReport(self: *Compiler, writer: *Io.Writer) !void {
+    const text_len = self.bytecode.len(.text);
+    const data_len = self.bytecode.len(.data);
+    const total = text_len + data_len;
+
+    try writer.print("{s:<32} {s:>10} {s:>7}\n", .{ "section", "bytes", "%" });
+    try writer.print("{s:<32} {d:>10} {d:>6.1}%\n", .{ "text", text_len, percent(text_len, total) });
+    try writer.print("{s:<32} {d:>10} {d:>6.1}%\n", .{ "data", data_len, percent(data_len, total) });
+    try writer.print("{s:<32} {d:>10}\n\n", .{ "total", total });
+
+    const sizes = try self.labelSizes();
+    defer self.gpa.free(sizes);
+
+    try writer.print("{s:<32} {s:>10} {s:>7}  {s}\n", .{ "symbol", "bytes", "%", "section" });
+    for (sizes) |entry| {
+        try writer.print("{s:<32} {d:>10} {d:>6.1}%  {s}\n", .{
+            entry.name orelse "<unlabeled>",
+            entry.size,
+            percent(entry.size, total),
+            @tagName(entry.section),
+        });
+    }
+}
+
+/// The section and offsets a statement's bytes occupy, and where it came from.
+pub const StatementRange = struct {
+    section: Bytecode.Section,
+    start: usize,
+    end: usize,
+    span: Span,
+};
+
+/// Bytes shown on each row of a listing.
+const listing_width = 8;
+
+/// Write each statement's line number, section, offset and bytes next to its
+/// source line, like the listing of a classic assembler. A statement with
+/// more than `listing_width` bytes continues on the rows below, and the
+/// file name is written above the first statement from each file. Only
+/// meaningful after compiling.
+pub fn writeListing(self: *Compiler, writer: *Io.Writer) !void {
+    var file: []const u8 = "";
+    var last_line: usize = 0;
+    for (self.ranges.items) |range| {
+        const source = self.reporter.sources.get(range.span.filename).?;
+        const where = range.span.toSourceRange(source);
+        if (!mem.eql(u8, where.file, file)) {
+            try writer.print("{s}{s}\n", .{ if (file.len > 0) "\n" else "", where.file });
+            file = where.file;
+            last_line = 0;
+        }
+
+        const bytes = switch (range.section) {
+            .text => self.bytecode.text.items,
+            .data => self.bytecode.data.items,
+        }[range.start..range.end];
+        // Later statements on the same line get a row without the text.
+        const text = if (where.start.line == last_line) "" else sourceLine(source, range.span.start);
+        last_line = where.start.line;
+
+        var offset = range.start;
+        var rest = bytes;
+        var first = true;
+        while (first or rest.len > 0) : (first = false) {
+            const row = rest[0..@min(rest.len, listing_width)];
+            rest = rest[row.len..];
+            if (first) {
+                try writer.print("{d:>6}  {s:<4} {x:0>8}", .{ where.start.line, @tagName(range.section), offset });
+            } else {
+                try writer.print("{s:>6}  {s:<4} {x:0>8}", .{ "", "", offset });
+            }
+            var column: usize = 0;
+            for (row) |b| {
+                try writer.print("{s}{x:0>2}", .{ if (column == 0) "  " else " ", b });
+                column += if (column == 0) 4 else 3;
+            }
+            if (first and text.len > 0) {
+                try writer.splatByteAll(' ', 2 + (3 * listing_width - 1) + 2 - column);
+                try writer.writeAll(text);
+            }
+            try writer.writeByte('\n');
+            offset += row.len;
+        }
+    }
+}
+
+/// The line of `source` that `pos` is on, without its line break.
+fn sourceLine(source: []const u8, pos: usize) []const u8 {
+    const start = if (mem.lastIndexOfScalar(u8, source[0..@min(pos, source.len)], '\n')) |i| i + 1 else 0;
+    const end = mem.indexOfAnyPos(u8, source, start, "\r\n") orelse source.len;
+    return mem.trimEnd(u8, source[start..end], " \t");
+}
+
+fn percent(part: usize, total: usize) f64 {
+    if (total == 0) return 0;
+    return @as(f64, @floatFromInt(part)) * 100 / @as(f64, @floatFromInt(total));
+}
+
+fn assemble(self: *Compiler) !u64 {
+    try self.scopeLocalLabels();
+    if (self.optimize) self.program = optimizer.foldAddressConstants(self.program);
+
+    if (!self.absolute_jumps) self.relative_jumps = 0;
+    while (true) {
+        try self.compileProgram();
+        try self.bytecode.text.appendNTimes(Opcode.nop.intoU8(), padding(self.bytecode.len(.text), self.data_alignment));
+        // Widening a jump moves the code after it, which can put other
+        // targets out of reach, so compile again until every jump fits.
+        if (!try self.widenJumps()) break;
+        self.restart();
+    }
+    self.reportTruncations();
+    try self.resolveFixups();
+
+    const entry: u64 = if (self.entry) |entry| switch (entry) {
+        .address => |v| blk: {
+            if (v >= self.bytecode.len(.text)) {
+                self.warn(.entry_outside_text, "entry point is outside the text section", self.entry_span.?);
+            }
+            break :blk v;
+        },
+        .fixup => |v| blk: {
+            if (self.labels.get(v.label)) |label| {
+                const pos = switch (label.section) {
+                    .text => label.addr,
+                    .data => pos: {
+                        self.warn(.entry_outside_text, "entry point refers to a label in the data section", v.span);
+                        break :pos self.bytecode.len(.text) + label.addr;
+                    },
+                };
+                break :blk @intCast(pos);
+            } else {
+                return self.undefinedLabel(v.label, v.span);
+            }
+        },
+    } else blk: {
+        if (self.strict) {
+            self.reporter.report(.{
+                .severity = .err,
+                .message = "no entry point: add an .entry directive or a _start label",
+            });
+            return self.fail();
+        }
+        break :blk 0x00;
+    };
+
+    try self.checkUnusedLabels();
+    self.checkUnreachable();
+    if (self.warnings.deny and self.warned) return self.fail();
+
+    return entry;
+}
+
+/// Mark every rel8 jump whose target is out of reach of a byte as needing
+/// rel32. Returns whether there were any, in which case the program has to
+/// be compiled again.
+fn widenJumps(self: *Compiler) !bool {
+    var widened = false;
+    var fixup_iter = self.fixups.iterator();
+    while (fixup_iter.next()) |fixup| {
+        const jump = fixup.value_ptr.jump orelse continue;
+        if (fixup.value_ptr.size != .byte) continue;
+        const displacement, _ = try self.fixupValue(fixup.key_ptr.*, fixup.value_ptr.*);
+        if (std.math.cast(i8, displacement) != null) continue;
+        try self.long_jumps.put(jump, {});
+        widened = true;
+    }
+    return widened;
+}
+
+/// Throw away everything `compileProgram` produced, to compile again.
+fn restart(self: *Compiler) void {
+    self.bytecode.text.clearRetainingCapacity();
+    self.bytecode.data.clearRetainingCapacity();
+    self.bytecode.current_section = .text;
+    self.ranges.clearRetainingCapacity();
+    self.labels.clearRetainingCapacity();
+    self.equs.clearRetainingCapacity();
+    self.fixups.clearRetainingCapacity();
+    self.externs.clearRetainingCapacity();
+    self.truncations.clearRetainingCapacity();
+    self.entry = null;
+    self.entry_span = null;
+    self.required = .initEmpty();
+    self.text_alignment = 1;
+    self.data_alignment = 1;
+    self.relative_jumps = 0;
+}
+
+/// Compile every statement, recording where each one's bytes went.
+fn compileProgram(self: *Compiler) !void {
+    try self.ranges.ensureTotalCapacity(self.program.len);
     for (self.program) |stmt| {
-        switch (stmt) {
-            .label => |v| {
-                const offset = self.bytecode.len(self.bytecode.current_section);
-                try self.labels.put(v.name, .{ .section = self.bytecode.current_section, .addr = offset });
-                const label_name = self.interner.get(v.name).?;
-                if (mem.eql(u8, label_name, "_start") and self.entry == null) {
-                    self.entry = .{ .fixup = .{ .label = v.name, .span = v.span } };
-                }
-            },
-            .section => |v| self.bytecode.current_section = switch (v.type) {
-                .text => .text,
-                .data => .data,
-            },
-            .entry => |v| {
-                switch (v.expr.*) {
-                    .integer_literal => |int| self.entry = .{ .address = @bitCast(int) },
-                    .identifier => |ident_id| self.entry = .{ .fixup = .{ .label = ident_id, .span = v.span } },
-                    else => {
-                        self.report(.err, "unsupported operand", v.span, 1);
-                        return error.CompilerError;
+        const before = self.bytecode.current_section;
+        var start = self.bytecode.len(before);
+        try self.compileStatement(stmt);
+        // A `.section` directive belongs to the section it switches to.
+        const section = self.bytecode.current_section;
+        if (section != before) start = self.bytecode.len(section);
+        try self.ranges.append(.{ .section = section, .start = start, .end = self.bytecode.len(section), .span = stmt.span() });
+    }
+}
+
+fn compileStatement(self: *Compiler, stmt: ast.Statement) !void {
+    switch (stmt) {
+        .label => |v| {
+            if (self.equs.get(v.name)) |equ| return self.redefined(v.span, equ.span);
+            const offset = self.bytecode.len(self.bytecode.current_section);
+            try self.labels.put(v.name, .{ .section = self.bytecode.current_section, .addr = offset });
+            const label_name = self.interner.get(v.name).?;
+            if (mem.eql(u8, label_name, "_start") and self.entry == null) {
+                self.entry = .{ .fixup = .{ .label = v.name, .span = v.span } };
+            }
+        },
+        .section => |v| self.bytecode.current_section = switch (v.type) {
+            .text => .text,
+            .data => .data,
+        },
+        .entry => |v| {
+            if (self.entry_span) |previous| {
+                self.report(.err, "duplicate .entry directive", v.span, null);
+                self.report(.note, "previous .entry directive is here", previous, 1);
+                return error.CompilerError;
+            }
+            self.entry_span = v.span;
+
+            switch (v.expr.*) {
+                .integer_literal => |int| self.entry = .{ .address = @bitCast(int) },
+                .identifier => |ident_id| self.entry = .{ .fixup = .{ .label = ident_id, .span = v.span } },
+                else => {
+                    self.report(.err, "unsupported operand", v.span, 1);
+                    return error.CompilerError;
+                },
+            }
+        },
+        .requires => |v| {
+            for (v.features) |name_id| {
+                const feature = std.meta.stringToEnum(features.Feature, self.interner.get(name_id).?) orelse
+                    return self.reportError("unknown feature, expected float, net or ffi", v.span);
+                self.required.insert(feature);
+            }
+        },
+        .@"align" => |v| {
+            const alignment: usize = switch (v.expr.*) {
+                .integer_literal => |int| if (int > 0 and std.math.isPowerOfTwo(int))
+                    @intCast(int)
+                else
+                    return self.reportError("alignment must be a positive power of two", v.span),
+                else => {
+                    self.report(.err, "unsupported operand", v.span, 1);
+                    return error.CompilerError;
+                },
+            };
+            const amount = padding(self.bytecode.len(self.bytecode.current_section), alignment);
+            try self.checkSectionSize(amount, v.span);
+            switch (self.bytecode.current_section) {
+                .text => {
+                    try self.bytecode.text.appendNTimes(Opcode.nop.intoU8(), amount);
+                    self.text_alignment = @max(self.text_alignment, alignment);
+                },
+                .data => {
+                    try self.bytecode.grow(amount);
+                    self.data_alignment = @max(self.data_alignment, alignment);
+                },
+            }
+        },
+        .ascii => |v| {
+            switch (v.expr.*) {
+                .string_literal => |str_id| {
+                    const str = self.interner.get(str_id).?;
+                    try self.bytecode.extend(str);
+                },
+                else => {
+                    self.report(.err, "unsupported operand", v.span, 1);
+                    return error.CompilerError;
+                },
+            }
+        },
+        .asciz => |v| {
+            switch (v.expr.*) {
+                .string_literal => |str_id| {
+                    const str = self.interner.get(str_id).?;
+                    try self.bytecode.extend(str);
+                    try self.bytecode.push(0x00);
+                },
+                else => {
+                    self.report(.err, "unsupported operand", v.span, 1);
+                    return error.CompilerError;
+                },
+            }
+        },
+        .equ => |v| {
+            if (self.labels.contains(v.name)) return self.redefined(v.span, self.labelSpan(v.name));
+            const gop = try self.equs.getOrPut(v.name);
+            if (gop.found_existing) return self.redefined(v.span, gop.value_ptr.span);
+            gop.value_ptr.* = .{ .expr = v.expr, .span = v.span };
+        },
+        .times => |v| {
+            const count = try self.timesCount(v);
+            if (count == 0) return;
+            // Every repetition is as long as the first, so check the whole
+            // run against the section limit before emitting the rest.
+            const section = self.bytecode.current_section;
+            const start = self.bytecode.len(section);
+            try self.compileStatement(v.stmt.*);
+            const rest = std.math.mul(usize, self.bytecode.len(section) - start, count - 1) catch std.math.maxInt(usize);
+            try self.checkSectionSize(rest, v.span);
+            for (1..count) |_| try self.compileStatement(v.stmt.*);
+        },
+        .@"extern" => |v| {
+            switch (v.name.*) {
+                .identifier => |ident_id| try self.externs.append(.{
+                    .name = ident_id,
+                    .return_type = v.return_type,
+                    .param_types = v.param_types,
+                    .is_variadic = v.is_variadic,
+                }),
+                else => {
+                    self.report(.err, "unsupported operand", v.span, 1);
+                    return error.CompilerError;
+                },
+            }
+        },
+        .nop => try self.bytecode.push(Opcode.nop),
+        .mov => |v| try self.compileMov(v.data_size, v.expr1, v.expr2, v.span),
+        .push => |v| try self.compilePush(v.data_size, v.expr, v.span),
+        .pop => |v| try self.compilePop(v.data_size, v.expr, v.span),
+        .add => |v| try self.compileArithmetic(v.expr1, v.expr2, v.expr3, .add, v.span),
+        .adc => |v| try self.compileArithmetic(v.expr1, v.expr2, v.expr3, .adc, v.span),
+        .sub => |v| try self.compileArithmetic(v.expr1, v.expr2, v.expr3, .sub, v.span),
+        .sbb => |v| try self.compileArithmetic(v.expr1, v.expr2, v.expr3, .sbb, v.span),
+        .mul => |v| try self.compileArithmetic(v.expr1, v.expr2, v.expr3, .mul, v.span),
+        .div => |v| try self.compileArithmetic(v.expr1, v.expr2, v.expr3, .div, v.span),
+        .imul => |v| try self.compileArithmetic(v.expr1, v.expr2, v.expr3, .imul, v.span),
+        .idiv => |v| try self.compileArithmetic(v.expr1, v.expr2, v.expr3, .idiv, v.span),
+        .imod => |v| try self.compileArithmetic(v.expr1, v.expr2, v.expr3, .imod, v.span),
+        .@"and" => |v| try self.compileBitwise(v.expr1, v.expr2, v.expr3, .@"and", v.span),
+        .@"or" => |v| try self.compileBitwise(v.expr1, v.expr2, v.expr3, .@"or", v.span),
+        .xor => |v| try self.compileBitwise(v.expr1, v.expr2, v.expr3, .xor, v.span),
+        .movz => |v| try self.compileExtend(v.expr1, v.expr2, .movz_reg_reg, v.span),
+        .movs => |v| try self.compileExtend(v.expr1, v.expr2, .movs_reg_reg, v.span),
+        .not => |v| try self.compileNot(v.expr1, v.expr2, v.span),
+        .cvtif => |v| try self.compileConvert(v.expr1, v.expr2, .cvtif_reg_reg, v.span),
+        .cvtfi => |v| try self.compileConvert(v.expr1, v.expr2, .cvtfi_reg_reg, v.span),
+        .cvtfd => |v| try self.compileConvert(v.expr1, v.expr2, .cvtfd_reg_reg, v.span),
+        .cvtdf => |v| try self.compileConvert(v.expr1, v.expr2, .cvtdf_reg_reg, v.span),
+        .shl => |v| try self.compileBitwise(v.expr1, v.expr2, v.expr3, .shl, v.span),
+        .shr => |v| try self.compileBitwise(v.expr1, v.expr2, v.expr3, .shr, v.span),
+        .rol => |v| try self.compileBitwise(v.expr1, v.expr2, v.expr3, .rol, v.span),
+        .ror => |v| try self.compileBitwise(v.expr1, v.expr2, v.expr3, .ror, v.span),
+        .cmp => |v| try self.compileCmp(v.expr1, v.expr2, v.span),
+        .scmp => |v| try self.compileScmp(v.expr1, v.expr2, v.span),
+        .schr => |v| try self.compileSchr(v.expr1, v.expr2, v.expr3, v.span),
+        .jmp => |v| try self.compileJump(v.expr, .jmp, v.span),
+        .jne => |v| try self.compileJump(v.expr, .jne, v.span),
+        .jeq => |v| try self.compileJump(v.expr, .jeq, v.span),
+        .jlt => |v| try self.compileJump(v.expr, .jlt, v.span),
+        .jgt => |v| try self.compileJump(v.expr, .jgt, v.span),
+        .jle => |v| try self.compileJump(v.expr, .jle, v.span),
+        .jge => |v| try self.compileJump(v.expr, .jge, v.span),
+        .seteq => |v| try self.compileSet(v.expr, .seteq_reg, v.span),
+        .setne => |v| try self.compileSet(v.expr, .setne_reg, v.span),
+        .setlt => |v| try self.compileSet(v.expr, .setlt_reg, v.span),
+        .setgt => |v| try self.compileSet(v.expr, .setgt_reg, v.span),
+        .setle => |v| try self.compileSet(v.expr, .setle_reg, v.span),
+        .setge => |v| try self.compileSet(v.expr, .setge_reg, v.span),
+        .setc => |v| try self.compileSet(v.expr, .setc_reg, v.span),
+        .seto => |v| try self.compileSet(v.expr, .seto_reg, v.span),
+        .call => |v| try self.compileCall(v.expr, v.span),
+        .call_variadic => |v| try self.compileCallVariadic(v.name, v.variadic_types, v.span),
+        .ret => try self.bytecode.push(Opcode.ret),
+        .enter => |v| try self.compileEnter(v.expr, v.span),
+        .leave => try self.bytecode.push(Opcode.leave),
+        .inc => |v| try self.compileIncOrDec(v.expr, v.amount, .inc, v.span),
+        .dec => |v| try self.compileIncOrDec(v.expr, v.amount, .dec, v.span),
+        .syscall => try self.bytecode.push(Opcode.syscall),
+        .hlt => try self.bytecode.push(Opcode.hlt),
+        .ext => |v| try self.compileExt(v.subcode, v.operands, v.span),
+        .db => |v| {
+            for (v.exprs) |expr| {
+                switch ((try self.unwrapLiteral(expr, .byte, v.span)).*) {
+                    .integer_literal => |int| {
+                        try self.checkSectionSize(1, v.span);
+                        try self.bytecode.push(@as(u8, @truncate(@as(u64, @bitCast(@as(i64, int))))));
                     },
-                }
-            },
-            .ascii => |v| {
-                switch (v.expr.*) {
                     .string_literal => |str_id| {
                         const str = self.interner.get(str_id).?;
+                        try self.checkSectionSize(str.len, v.span);
                         try self.bytecode.extend(str);
                     },
+                    .identifier => |label| {
+                        try self.checkSectionSize(1, v.span);
+                        try self.emitDataFixup(.byte, label, v.span);
+                    },
+                    .unary_op, .binary_op => {
+                        try self.checkSectionSize(1, v.span);
+                        try self.emitExprFixup(.byte, expr, v.span);
+                    },
                     else => {
                         self.report(.err, "unsupported operand", v.span, 1);
                         return error.CompilerError;
                     },
                 }
-            },
-            .asciz => |v| {
-                switch (v.expr.*) {
-                    .string_literal => |str_id| {
-                        const str = self.interner.get(str_id).?;
-                        try self.bytecode.extend(str);
-                        try self.bytecode.push(0x00);
+            }
+        },
+        .dw => |v| {
+            try self.checkSectionSize(v.exprs.len * 2, v.span);
+            for (v.exprs) |expr| {
+                switch ((try self.unwrapLiteral(expr, .word, v.span)).*) {
+                    .integer_literal => |int| {
+                        const val: u16 = @truncate(@as(u64, @bitCast(int)));
+                        const bytes = std.mem.toBytes(std.mem.nativeToLittle(u16, val));
+                        try self.bytecode.extend(&bytes);
                     },
+                    .identifier => |label| try self.emitDataFixup(.word, label, v.span),
+                    .unary_op, .binary_op => try self.emitExprFixup(.word, expr, v.span),
                     else => {
                         self.report(.err, "unsupported operand", v.span, 1);
                         return error.CompilerError;
                     },
                 }
-            },
-            .@"extern" => |v| {
-                switch (v.name.*) {
-                    .identifier => |ident_id| try self.externs.append(.{
-                        .name = ident_id,
-                        .return_type = v.return_type,
-                        .param_types = v.param_types,
-                        .is_variadic = v.is_variadic,
-                    }),
+            }
+        },
+        .dd => |v| {
+            try self.checkSectionSize(v.exprs.len * 4, v.span);
+            for (v.exprs) |expr| {
+                const size: DataSize = if ((literalSize(expr) orelse DataSize.dword) == .float) .float else .dword;
+                switch ((try self.unwrapLiteral(expr, size, v.span)).*) {
+                    .integer_literal => |int| {
+                        const val: u32 = @truncate(@as(u64, @bitCast(int)));
+                        const bytes = std.mem.toBytes(std.mem.nativeToLittle(u32, val));
+                        try self.bytecode.extend(&bytes);
+                    },
+                    .float_literal => |flt| {
+                        const val: u32 = @bitCast(@as(f32, @floatCast(flt)));
+                        const bytes = std.mem.toBytes(std.mem.nativeToLittle(u32, val));
+                        try self.bytecode.extend(&bytes);
+                    },
+                    .identifier => |label| try self.emitDataFixup(.dword, label, v.span),
+                    .unary_op, .binary_op => try self.emitExprFixup(.dword, expr, v.span),
                     else => {
                         self.report(.err, "unsupported operand", v.span, 1);
                         return error.CompilerError;
                     },
                 }
-            },
-            .nop => try self.bytecode.push(Opcode.nop),
-            .mov => |v| try self.compileMov(v.data_size, v.expr1, v.expr2, v.span),
-            .push => |v| try self.compilePush(v.data_size, v.expr, v.span),
-            .pop => |v| try self.compilePop(v.data_size, v.expr, v.span),
-            .add => |v| try self.compileArithmetic(v.expr1, v.expr2, v.expr3, .add, v.span),
-            .sub => |v| try self.compileArithmetic(v.expr1, v.expr2, v.expr3, .sub, v.span),
-            .mul => |v| try self.compileArithmetic(v.expr1, v.expr2, v.expr3, .mul, v.span),
-            .div => |v| try self.compileArithmetic(v.expr1, v.expr2, v.expr3, .div, v.span),
-            .@"and" => |v| try self.compileBitwise(v.expr1, v.expr2, v.expr3, .@"and", v.span),
-            .@"or" => |v| try self.compileBitwise(v.expr1, v.expr2, v.expr3, .@"or", v.span),
-            .xor => |v| try self.compileBitwise(v.expr1, v.expr2, v.expr3, .xor, v.span),
-            .shl => |v| try self.compileBitwise(v.expr1, v.expr2, v.expr3, .shl, v.span),
-            .shr => |v| try self.compileBitwise(v.expr1, v.expr2, v.expr3, .shr, v.span),
-            .rol => |v| try self.compileBitwise(v.expr1, v.expr2, v.expr3, .rol, v.span),
-            .ror => |v| try self.compileBitwise(v.expr1, v.expr2, v.expr3, .ror, v.span),
-            .cmp => |v| try self.compileCmp(v.expr1, v.expr2, v.span),
-            .jmp => |v| try self.compileJump(v.expr, .jmp, v.span),
-            .jne => |v| try self.compileJump(v.expr, .jne, v.span),
-            .jeq => |v| try self.compileJump(v.expr, .jeq, v.span),
-            .jlt => |v| try self.compileJump(v.expr, .jlt, v.span),
-            .jgt => |v| try self.compileJump(v.expr, .jgt, v.span),
-            .jle => |v| try self.compileJump(v.expr, .jle, v.span),
-            .jge => |v| try self.compileJump(v.expr, .jge, v.span),
-            .call => |v| try self.compileCall(v.expr, v.span),
-            .call_variadic => |v| try self.compileCallVariadic(v.name, v.variadic_types, v.span),
-            .ret => try self.bytecode.push(Opcode.ret),
-            .inc => |v| try self.compileIncOrDec(v.expr, .inc, v.span),
-            .dec => |v| try self.compileIncOrDec(v.expr, .dec, v.span),
-            .syscall => try self.bytecode.push(Opcode.syscall),
-            .hlt => try self.bytecode.push(Opcode.hlt),
-            .db => |v| {
-                for (v.exprs) |expr| {
-                    switch (expr.*) {
-                        .integer_literal => |int| try self.bytecode.push(
-                            @as(u8, @truncate(@as(u64, @bitCast(@as(i64, int))))),
-                        ),
-                        .string_literal => |str_id| {
-                            const str = self.interner.get(str_id).?;
-                            try self.bytecode.extend(str);
-                        },
-                        else => {
-                            self.report(.err, "unsupported operand", v.span, 1);
-                            return error.CompilerError;
-                        },
-                    }
-                }
-            },
-            .dw => |v| {
-                for (v.exprs) |expr| {
-                    switch (expr.*) {
-                        .integer_literal => |int| {
-                            const val: u16 = @bitCast(@as(i16, @intCast(int)));
-                            const bytes = std.mem.toBytes(std.mem.nativeToLittle(u16, val));
-                            try self.bytecode.extend(&bytes);
-                        },
-                        else => {
-                            self.report(.err, "unsupported operand", v.span, 1);
-                            return error.CompilerError;
-                        },
-                    }
-                }
-            },
-            .dd => |v| {
-                for (v.exprs) |expr| {
-                    switch (expr.*) {
-                        .integer_literal => |int| {
-                            const val: u32 = @bitCast(@as(i32, @intCast(int)));
-                            const bytes = std.mem.toBytes(std.mem.nativeToLittle(u32, val));
-                            try self.bytecode.extend(&bytes);
-                        },
-                        .float_literal => |flt| {
-                            const val: u32 = @bitCast(@as(f32, @floatCast(flt)));
-                            const bytes = std.mem.toBytes(std.mem.nativeToLittle(u32, val));
-                            try self.bytecode.extend(&bytes);
-                        },
-                        else => {
-                            self.report(.err, "unsupported operand", v.span, 1);
-                            return error.CompilerError;
-                        },
-                    }
-                }
-            },
-            .dq => |v| {
-                for (v.exprs) |expr| {
-                    switch (expr.*) {
-                        .integer_literal => |int| {
-                            const val: u64 = @bitCast(int);
-                            const bytes = std.mem.toBytes(std.mem.nativeToLittle(u64, val));
-                            try self.bytecode.extend(&bytes);
-                        },
-                        .float_literal => |flt| {
-                            const val: u64 = @bitCast(flt);
-                            const bytes = std.mem.toBytes(std.mem.nativeToLittle(u64, val));
-                            try self.bytecode.extend(&bytes);
-                        },
-                        else => {
-                            self.report(.err, "unsupported operand", v.span, 1);
-                            return error.CompilerError;
-                        },
-                    }
+            }
+        },
+        .dq => |v| {
+            try self.checkSectionSize(v.exprs.len * 8, v.span);
+            for (v.exprs) |expr| {
+                switch ((try self.unwrapLiteral(expr, .qword, v.span)).*) {
+                    .integer_literal => |int| {
+                        const val: u64 = @bitCast(int);
+                        const bytes = std.mem.toBytes(std.mem.nativeToLittle(u64, val));
+                        try self.bytecode.extend(&bytes);
+                    },
+                    .float_literal => |flt| {
+                        const val: u64 = @bitCast(flt);
+                        const bytes = std.mem.toBytes(std.mem.nativeToLittle(u64, val));
+                        try self.bytecode.extend(&bytes);
+                    },
+                    .identifier => |label| try self.emitDataFixup(.qword, label, v.span),
+                    .unary_op, .binary_op => try self.emitExprFixup(.qword, expr, v.span),
+                    else => {
+                        self.report(.err, "unsupported operand", v.span, 1);
+                        return error.CompilerError;
+                    },
                 }
-            },
-            .resb, .resw, .resd, .resq => |v| {
-                const multiplier: usize = switch (stmt) {
-                    .resb => 1,
-                    .resw => 2,
-                    .resd => 4,
-                    .resq => 8,
-                    else => unreachable,
+            }
+        },
+        .df => |v| {
+            try self.checkSectionSize(v.exprs.len * 4, v.span);
+            for (v.exprs) |expr| {
+                const val: f32 = switch ((try self.unwrapLiteral(expr, .float, v.span)).*) {
+                    .integer_literal => |int| @floatFromInt(int),
+                    .float_literal => |flt| @floatCast(flt),
+                    else => {
+                        self.report(.err, "unsupported operand", v.span, 1);
+                        return error.CompilerError;
+                    },
                 };
-
-                switch (v.expr.*) {
-                    .integer_literal => |int| try self.bytecode.grow(@as(usize, @intCast(int)) * multiplier),
+                try self.bytecode.extend(&std.mem.toBytes(std.mem.nativeToLittle(u32, @bitCast(val))));
+            }
+        },
+        .ddbl => |v| {
+            try self.checkSectionSize(v.exprs.len * 8, v.span);
+            for (v.exprs) |expr| {
+                const val: f64 = switch ((try self.unwrapLiteral(expr, .double, v.span)).*) {
+                    .integer_literal => |int| @floatFromInt(int),
+                    .float_literal => |flt| flt,
                     else => {
                         self.report(.err, "unsupported operand", v.span, 1);
                         return error.CompilerError;
                     },
-                }
-            },
-            else => |other| {
-                const span = other.span();
-                self.report(.err, "unsupported operation", span, 1);
-                return error.CompilerError;
-            },
-        }
+                };
+                try self.bytecode.extend(&std.mem.toBytes(std.mem.nativeToLittle(u64, @bitCast(val))));
+            }
+        },
+        .resb, .resw, .resd, .resq => |v| {
+            const multiplier: usize = switch (stmt) {
+                .resb => 1,
+                .resw => 2,
+                .resd => 4,
+                .resq => 8,
+                else => unreachable,
+            };
+
+            switch (v.expr.*) {
+                .integer_literal => |int| {
+                    if (int < 0) return self.reportError("reservation size cannot be negative", v.span);
+                    const amount = std.math.mul(usize, @intCast(int), multiplier) catch std.math.maxInt(usize);
+                    try self.checkSectionSize(amount, v.span);
+                    try self.bytecode.grow(amount);
+                },
+                else => {
+                    self.report(.err, "unsupported operand", v.span, 1);
+                    return error.CompilerError;
+                },
+            }
+        },
+        // Only mark where an included file's statements start and end.
+        .include_start, .include_end => {},
+        else => |other| {
+            const span = other.span();
+            self.report(.err, "unsupported operation", span, 1);
+            return error.CompilerError;
+        },
     }
+}
 
+/// Patch every recorded label reference with the label's address, or with
+/// the difference of two addresses.
+fn resolveFixups(self: *Compiler) !void {
     var fixup_iter = self.fixups.iterator();
     while (fixup_iter.next()) |fixup| {
-        if (self.labels.get(fixup.value_ptr.label)) |label| {
-            const pos = switch (label.section) {
-                .text => label.addr,
-                .data => self.bytecode.len(.text) + label.addr,
+        const value, const signed = try self.fixupValue(fixup.key_ptr.*, fixup.value_ptr.*);
+        try self.patchFixup(fixup.key_ptr.*, fixup.value_ptr.*, value, signed);
+    }
+}
+
+/// The value `fixup` at `at` is patched with, and whether it may be negative.
+fn fixupValue(self: *Compiler, at: Label, fixup: Fixup) !struct { i128, bool } {
+    const expr = try self.expandEqus(fixupExpr(fixup), fixup.span);
+    var value: i128 = expr.addend;
+    if (expr.label) |label| value += try self.labelAddress(label, fixup.span);
+    if (expr.minus) |minus| value -= try self.labelAddress(minus, fixup.span);
+    if (fixup.jump != null) {
+        // The displacement ends the instruction.
+        const section_start = if (at.section == .data) self.bytecode.len(.text) else 0;
+        return .{ value - @as(i128, section_start + at.addr + fixup.size.sizeInBytes()), true };
+    }
+    // Only a lone label is an address; anything else may be negative.
+    return .{ value, expr.label == null or expr.minus != null };
+}
+
+fn fixupExpr(fixup: Fixup) LabelExpr {
+    return .{ .label = fixup.label, .minus = fixup.minus, .addend = fixup.addend };
+}
+
+/// `value` with every equ in it replaced by its value, leaving only labels.
+fn expandEqus(self: *Compiler, value: LabelExpr, span: Span) !LabelExpr {
+    var result: LabelExpr = .{ .addend = value.addend };
+    if (value.label) |name| {
+        result = combine(result, try self.symbolValue(name, span)) orelse
+            return self.equError("this equ adds a second label to the expression", span);
+    }
+    if (value.minus) |name| {
+        const negated = negate(try self.symbolValue(name, span)) orelse
+            return self.equError("equ value overflows", span);
+        result = combine(result, negated) orelse
+            return self.equError("this equ adds a second label to the expression", span);
+    }
+    return result;
+}
+
+/// The value of equ `name`, or `name` itself if it is not an equ.
+fn symbolValue(self: *Compiler, name: StringId, span: Span) !LabelExpr {
+    const equ = self.equs.getPtr(name) orelse return .{ .label = name };
+    switch (equ.state) {
+        .resolved => return equ.value,
+        .resolving => return self.equError("equ depends on its own value", equ.span),
+        .unresolved => {},
+    }
+    equ.state = .resolving;
+    const value = self.reduceDifference(try self.equValue(equ.expr, equ.span));
+    equ.state = .resolved;
+    equ.value = value;
+    return value;
+}
+
+/// Evaluate the expression of an equ like `labelExpr`, with the equs it
+/// names replaced by their values, so products of integer equs work too.
+fn equValue(self: *Compiler, expr: *const ast.Expression, span: Span) !LabelExpr {
+    switch (expr.*) {
+        .identifier => |name| return self.symbolValue(name, span),
+        .integer_literal => |int| return .{ .addend = int },
+        .binary_op => |v| {
+            const lhs = self.reduceDifference(try self.equValue(v.lhs, span));
+            const rhs = self.reduceDifference(try self.equValue(v.rhs, span));
+            const value = switch (v.op) {
+                .add => combine(lhs, rhs),
+                .sub => if (negate(rhs)) |negated| combine(lhs, negated) else null,
+                else => foldIntegers(v.op, lhs, rhs),
             };
+            if (value) |result| return result;
+            return self.equError("expected integers plus a label, or a difference of two labels", span);
+        },
+        .unary_op => |v| {
+            const operand = self.reduceDifference(try self.equValue(v.expr, span));
+            if (applyUnary(v.op, operand)) |result| return result;
+            return self.equError("expected integers plus a label, or a difference of two labels", span);
+        },
+        else => return self.equError("expected integers and labels", span),
+    }
+}
 
-            switch (fixup.value_ptr.size) {
-                .byte => self.bytecode.writeU8At(fixup.key_ptr.section, fixup.key_ptr.addr, @intCast(pos)),
-                .word => self.bytecode.writeU16At(fixup.key_ptr.section, fixup.key_ptr.addr, @intCast(pos)),
-                .dword => self.bytecode.writeU32At(fixup.key_ptr.section, fixup.key_ptr.addr, @intCast(pos)),
-                .qword => self.bytecode.writeU64At(fixup.key_ptr.section, fixup.key_ptr.addr, @intCast(pos)),
-                else => unreachable,
-            }
-        } else {
-            self.report(.err, "undefined label", fixup.value_ptr.span, 1);
-            return error.CompilerError;
+/// How many times to compile the statement of `times`. The count is
+/// evaluated like an equ when `times` is reached, so it may use labels
+/// placed before it, as in `times 16 - (here - start) db 0`.
+fn timesCount(self: *Compiler, times: ast.Statement.Times) !usize {
+    const value = self.reduceDifference(try self.equValue(times.count, times.span));
+    if (value.label != null or value.minus != null) {
+        return self.equError("times count must be a constant or a difference of labels placed before it in one section", times.span);
+    }
+    const count = std.math.cast(usize, value.addend) orelse
+        return self.equError("times count must not be negative", times.span);
+    if (count > self.max_section_size) return self.equError("times count is larger than a section can hold", times.span);
+    return count;
+}
+
+/// `value` with a difference of two labels in the same section replaced by
+/// the distance between them, which linking does not change. Only
+/// meaningful once every label is placed.
+fn reduceDifference(self: *Compiler, value: LabelExpr) LabelExpr {
+    const label = self.labels.get(value.label orelse return value) orelse return value;
+    const minus = self.labels.get(value.minus orelse return value) orelse return value;
+    if (label.section != minus.section) return value;
+    const distance = @as(i64, @intCast(label.addr)) - @as(i64, @intCast(minus.addr));
+    return .{ .addend = std.math.add(i64, value.addend, distance) catch return value };
+}
+
+fn equError(self: *Compiler, message: []const u8, span: Span) error{CompilerError} {
+    self.report(.err, message, span, 1);
+    return error.CompilerError;
+}
+
+/// Report an equ or label named like an earlier equ or label.
+fn redefined(self: *Compiler, span: Span, previous: ?Span) error{CompilerError} {
+    const def = previous orelse return self.equError("name is already defined", span);
+    self.report(.err, "name is already defined", span, null);
+    self.report(.note, "previous definition is here", def, 1);
+    return error.CompilerError;
+}
+
+fn labelSpan(self: *Compiler, name: StringId) ?Span {
+    for (self.program) |stmt| switch (stmt) {
+        .label => |v| if (v.name == name) return v.span,
+        else => {},
+    };
+    return null;
+}
+
+fn labelAddress(self: *Compiler, name: StringId, span: Span) !usize {
+    const label = self.labels.get(name) orelse return self.undefinedLabel(name, span);
+    return switch (label.section) {
+        .text => label.addr,
+        .data => self.bytecode.len(.text) + label.addr,
+    };
+}
+
+/// Report a reference to a label that is never defined, pointing at a local
+/// label of the same name under another label if there is one.
+fn undefinedLabel(self: *Compiler, name: StringId, span: Span) error{CompilerError} {
+    const other = if (self.local_names.get(name)) |local| self.local_defs.get(local) else null;
+    if (other) |def| {
+        self.report(.err, "undefined label", span, null);
+        self.report(.note, "a local label with this name is defined under another label here", def, 1);
+    } else {
+        self.report(.err, "undefined label", span, 1);
+    }
+    return error.CompilerError;
+}
+
+/// Write `value` into the bytes reserved for `fixup`. An address must fit
+/// unsigned; a difference or an equ that is only an integer may also be
+/// negative.
+fn patchFixup(self: *Compiler, at: Label, fixup: Fixup, value: i128, signed: bool) !void {
+    if (at.addr + fixup.size.sizeInBytes() > self.bytecode.len(at.section)) {
+        return self.reportError("label reference lies outside its section", fixup.span);
+    }
+    const bits: u7 = @intCast(8 * fixup.size.sizeInBytes());
+    if (fixup.jump != null) {
+        if (value < -(@as(i128, 1) << (bits - 1)) or value >= @as(i128, 1) << (bits - 1)) {
+            return self.reportError("jump target is out of reach of a relative displacement", fixup.span);
+        }
+    } else if (!signed) {
+        if (value < 0 or value >= @as(i128, 1) << bits) {
+            return self.reportError("label address does not fit in the operand size", fixup.span);
         }
+    } else if (value < -(@as(i128, 1) << (bits - 1)) or value >= @as(i128, 1) << bits) {
+        const message = if (fixup.minus != null) "label difference does not fit in the operand size" else "value does not fit in the operand size";
+        return self.reportError(message, fixup.span);
     }
 
-    const entry: u64 = if (self.entry) |entry| switch (entry) {
-        .address => |v| v,
-        .fixup => |v| blk: {
-            if (self.labels.get(v.label)) |label| {
-                const pos = switch (label.section) {
-                    .text => label.addr,
-                    .data => self.bytecode.len(.text) + label.addr,
-                };
-                break :blk @intCast(pos);
-            } else {
-                self.report(.err, "undefined label", v.span, 1);
-                return error.CompilerError;
+    const raw: u64 = @truncate(@as(u128, @bitCast(value)));
+    switch (fixup.size) {
+        .byte => self.bytecode.writeU8At(at.section, at.addr, @truncate(raw)),
+        .word => self.bytecode.writeU16At(at.section, at.addr, @truncate(raw)),
+        .dword => self.bytecode.writeU32At(at.section, at.addr, @truncate(raw)),
+        .qword => self.bytecode.writeU64At(at.section, at.addr, raw),
+        else => unreachable,
+    }
+}
+
+/// Qualify every local label, and every reference to one, with the label
+/// above it, so each function can have its own `.loop` and `.done`.
+fn scopeLocalLabels(self: *Compiler) !void {
+    for (self.program) |*stmt| stmt.* = try self.scopeStatement(stmt.*);
+}
+
+fn scopeStatement(self: *Compiler, stmt: ast.Statement) !ast.Statement {
+    switch (stmt) {
+        .label => |v| {
+            if (!isLocalLabel(self.interner.get(v.name).?)) {
+                self.label_scope = v.name;
+                return stmt;
+            }
+            const gop = try self.local_defs.getOrPut(v.name);
+            if (!gop.found_existing) gop.value_ptr.* = v.span;
+            return .{ .label = .{ .name = try self.qualifyLabel(v.name, v.span), .span = v.span } };
+        },
+        // A local equ belongs to the label above it, but does not start a scope.
+        .equ => |v| {
+            var scoped = v;
+            if (isLocalLabel(self.interner.get(v.name).?)) scoped.name = try self.qualifyLabel(v.name, v.span);
+            scoped.expr = try self.scopeExpr(v.expr, v.span);
+            return .{ .equ = scoped };
+        },
+        // An included file starts without a scope, and the file that included
+        // it picks up its own again after it.
+        .include_start => {
+            try self.include_scopes.append(self.label_scope);
+            self.label_scope = null;
+            return stmt;
+        },
+        .include_end => {
+            self.label_scope = self.include_scopes.pop() orelse null;
+            return stmt;
+        },
+        inline else => |v, tag| {
+            const T = @TypeOf(v);
+            if (@typeInfo(T) != .@"struct") return stmt;
+            var scoped = v;
+            inline for (@typeInfo(T).@"struct".fields) |field| {
+                const value = @field(v, field.name);
+                switch (field.type) {
+                    *ast.Expression => @field(scoped, field.name) = try self.scopeExpr(value, stmt.span()),
+                    ?*ast.Expression => if (value) |e| {
+                        @field(scoped, field.name) = try self.scopeExpr(e, stmt.span());
+                    },
+                    []*ast.Expression => for (value, 0..) |e, i| {
+                        const new = try self.scopeExpr(e, stmt.span());
+                        if (new == e) continue;
+                        if (@field(scoped, field.name).ptr == value.ptr) {
+                            @field(scoped, field.name) = try self.arena.allocator().dupe(*ast.Expression, value);
+                        }
+                        @field(scoped, field.name)[i] = new;
+                    },
+                    // The statement `times` repeats, or the body of a macro
+                    // the preprocessor did not expand.
+                    *ast.Statement => {
+                        @field(scoped, field.name) = try self.arena.allocator().create(ast.Statement);
+                        @field(scoped, field.name).* = try self.scopeStatement(value.*);
+                    },
+                    []ast.Statement => {
+                        const outer = self.label_scope;
+                        defer self.label_scope = outer;
+                        const body = try self.arena.allocator().alloc(ast.Statement, value.len);
+                        for (body, value) |*new, old| new.* = try self.scopeStatement(old);
+                        @field(scoped, field.name) = body;
+                    },
+                    else => {},
+                }
             }
+            return @unionInit(ast.Statement, @tagName(tag), scoped);
         },
-    } else 0x00;
+    }
+}
 
-    var bytecode = ArrayList(u8).init(self.gpa);
-    try bytecode.appendSlice(&mem.toBytes(entry));
-    const final = try self.bytecode.finalize(self.gpa);
-    defer self.gpa.free(final);
-    try bytecode.appendSlice(final);
+/// `expr` with references to local labels qualified, or `expr` itself if
+/// it has none. Changed nodes are copied, since the preprocessor shares
+/// nodes between macro expansions.
+fn scopeExpr(self: *Compiler, expr: *ast.Expression, span: Span) !*ast.Expression {
+    const scoped: ast.Expression = switch (expr.*) {
+        .identifier => |id| blk: {
+            if (!isLocalLabel(self.interner.get(id).?)) return expr;
+            break :blk .{ .identifier = try self.qualifyLabel(id, span) };
+        },
+        .address => |a| blk: {
+            const base = try self.scopeExpr(a.base, span);
+            const offset = if (a.offset) |o| try self.scopeExpr(o, span) else null;
+            if (base == a.base and offset == a.offset) return expr;
+            break :blk .{ .address = .{ .base = base, .offset = offset } };
+        },
+        .unary_op => |u| blk: {
+            var copy = u;
+            copy.expr = try self.scopeExpr(u.expr, span);
+            if (copy.expr == u.expr) return expr;
+            break :blk .{ .unary_op = copy };
+        },
+        .binary_op => |b| blk: {
+            var copy = b;
+            copy.lhs = try self.scopeExpr(b.lhs, span);
+            copy.rhs = try self.scopeExpr(b.rhs, span);
+            if (copy.lhs == b.lhs and copy.rhs == b.rhs) return expr;
+            break :blk .{ .binary_op = copy };
+        },
+        .sized_literal => |l| blk: {
+            var copy = l;
+            copy.value = try self.scopeExpr(l.value, span);
+            if (copy.value == l.value) return expr;
+            break :blk .{ .sized_literal = copy };
+        },
+        else => return expr,
+    };
+    const node = try self.arena.allocator().create(ast.Expression);
+    node.* = scoped;
+    return node;
+}
+
+/// The name of local label `local` under the current scope, e.g.
+/// `main.loop` for `.loop`.
+fn qualifyLabel(self: *Compiler, local: StringId, span: Span) !StringId {
+    const scope = self.label_scope orelse {
+        self.report(.err, "local label has no enclosing label", span, 1);
+        return error.CompilerError;
+    };
+    const name = try mem.concat(self.gpa, u8, &.{ self.interner.get(scope).?, self.interner.get(local).? });
+    defer self.gpa.free(name);
+    const id = try self.interner.intern(name);
+    try self.local_names.put(id, local);
+    return id;
+}
+
+fn markNames(expr: *const ast.Expression, used: *std.AutoHashMap(StringId, void)) !void {
+    switch (expr.*) {
+        .identifier => |name| try used.put(name, {}),
+        .binary_op => |v| {
+            try markNames(v.lhs, used);
+            try markNames(v.rhs, used);
+        },
+        else => {},
+    }
+}
 
-    return bytecode.toOwnedSlice();
+fn isLocalLabel(name: []const u8) bool {
+    return name.len > 1 and name[0] == '.';
+}
+
+/// Report every label in the main file that is never referenced, as an
+/// error in strict mode and a warning otherwise. Labels from included files
+/// are skipped so unused library routines are allowed.
+fn checkUnusedLabels(self: *Compiler) !void {
+    var used = std.AutoHashMap(StringId, void).init(self.gpa);
+    defer used.deinit();
+
+    var fixup_iter = self.fixups.valueIterator();
+    while (fixup_iter.next()) |fixup| {
+        try used.put(fixup.label, {});
+        if (fixup.minus) |minus| try used.put(minus, {});
+    }
+    if (self.entry) |entry| switch (entry) {
+        .fixup => |v| try used.put(v.label, {}),
+        .address => {},
+    };
+    // An equ is resolved when something refers to it, so the labels named
+    // by resolved equs are used.
+    var equ_iter = self.equs.valueIterator();
+    while (equ_iter.next()) |equ| {
+        if (equ.state == .resolved) try markNames(equ.expr, &used);
+    }
+
+    // A times count is folded to a number, so mark the labels it names.
+    for (self.program) |stmt| switch (stmt) {
+        .times => |v| try markNames(v.count, &used),
+        else => {},
+    };
+
+    var found = false;
+    for (self.program) |stmt| switch (stmt) {
+        .label => |v| if (!used.contains(v.name) and mem.eql(u8, v.span.filename, self.filename)) {
+            if (self.strict) {
+                self.report(.err, "label is never used", v.span, null);
+                found = true;
+            } else {
+                self.warn(.unused_label, "label is never used", v.span);
+            }
+        },
+        else => {},
+    };
+    if (found) return self.fail();
+}
+
+/// Warn at the first instruction in the main file after `hlt`, `ret` or
+/// `jmp` that no label or section directive comes before, since nothing
+/// can reach it.
+fn checkUnreachable(self: *Compiler) void {
+    var after: ?[]const u8 = null;
+    for (self.program) |outer| {
+        const stmt = switch (outer) {
+            .times => |v| v.stmt.*,
+            else => outer,
+        };
+        switch (stmt) {
+            .label, .section => after = null,
+            .@"error", .warning, .define, .define_env, .include, .pragma_once, .ifdef, .ifndef, .@"else", .endif => {},
+            .include_start, .include_end => {},
+            .entry, .requires, .@"align", .ascii, .asciz, .@"extern", .equ, .times, .macro_def, .macro_call => {},
+            .db, .dw, .dd, .dq, .df, .ddbl, .resb, .resw, .resd, .resq => {},
+            else => {
+                if (after) |name| {
+                    after = null;
+                    if (mem.eql(u8, outer.span().filename, self.filename)) {
+                        var buf: [64]u8 = undefined;
+                        const message = std.fmt.bufPrint(&buf, "code after {s} is never reached", .{name}) catch "code is never reached";
+                        self.warn(.unreachable_code, message, outer.span());
+                    }
+                }
+                switch (stmt) {
+                    .hlt, .ret, .jmp => after = @tagName(stmt),
+                    else => {},
+                }
+            },
+        }
+    }
 }
 
 fn compileMov(self: *Compiler, data_size: ?*ast.Expression, lhs: *ast.Expression, rhs: *ast.Expression, span: Span) !void {
     switch (lhs.*) {
         .register => |dest| {
-            switch (rhs.*) {
+            try self.checkWritable(dest, span);
+            switch ((try self.unwrapLiteral(rhs, DataSize.fromRegister(dest), span)).*) {
                 .register => |src| {
                     try self.bytecode.push(Opcode.mov_reg_reg);
                     try self.bytecode.push(dest);
@@ -342,9 +1443,9 @@ fn compileMov(self: *Compiler, data_size: ?*ast.Expression, lhs: *ast.Expression
                     try self.bytecode.push(Opcode.mov_reg_imm);
                     try self.bytecode.push(dest);
                     switch (DataSize.fromRegister(dest)) {
-                        .byte => try self.bytecode.push(@as(u8, @bitCast(@as(i8, @intCast(src))))),
-                        .word => try self.bytecode.extend(&mem.toBytes(@as(u16, @bitCast(@as(i16, @intCast(src)))))),
-                        .dword => try self.bytecode.extend(&mem.toBytes(@as(u32, @bitCast(@as(i32, @intCast(src)))))),
+                        .byte => try self.bytecode.push(@as(u8, @truncate(@as(u64, @bitCast(src))))),
+                        .word => try self.bytecode.extend(&mem.toBytes(@as(u16, @truncate(@as(u64, @bitCast(src)))))),
+                        .dword => try self.bytecode.extend(&mem.toBytes(@as(u32, @truncate(@as(u64, @bitCast(src)))))),
                         .qword => try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(src)))),
                         .float => try self.bytecode.extend(&mem.toBytes(@as(f32, @floatFromInt(src)))),
                         .double => try self.bytecode.extend(&mem.toBytes(@as(f64, @floatFromInt(src)))),
@@ -383,85 +1484,37 @@ fn compileMov(self: *Compiler, data_size: ?*ast.Expression, lhs: *ast.Expression
                     }
                     return;
                 },
-                .address => |src| {
-                    const offset = if (src.offset) |o| blk: {
-                        switch (o.*) {
-                            .integer_literal => |offset| break :blk offset,
-                            else => return self.reportError("offset must be an integer literal", span),
-                        }
-                    } else 0;
-
-                    switch (src.base.*) {
-                        .register => |base| {
-                            try self.bytecode.push(Opcode.mov_reg_addr);
-                            try self.bytecode.push(dest);
-                            try self.bytecode.push(addressing_variant_1);
-                            try self.bytecode.push(base);
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(offset))));
-                        },
-                        .integer_literal => |base| {
-                            try self.bytecode.push(Opcode.mov_reg_addr);
-                            try self.bytecode.push(dest);
-                            try self.bytecode.push(addressing_variant_2);
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(base))));
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(offset))));
-                        },
-                        .identifier => |base_id| {
-                            try self.bytecode.push(Opcode.mov_reg_addr);
-                            try self.bytecode.push(dest);
-                            try self.bytecode.push(addressing_variant_2);
-                            try self.fixups.put(
-                                .{ .section = self.bytecode.current_section, .addr = self.bytecode.len(self.bytecode.current_section) },
-                                .{ .size = .qword, .label = base_id, .span = span },
-                            );
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, 0x00)));
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(offset))));
-                        },
-                        else => return self.reportError("unsupported address base type", span),
+                .unary_op, .binary_op => {
+                    const size = DataSize.fromRegister(dest);
+                    if (size == .float or size == .double) {
+                        return self.reportError("label arithmetic needs an integer register", span);
                     }
+                    try self.bytecode.push(Opcode.mov_reg_imm);
+                    try self.bytecode.push(dest);
+                    try self.emitExprFixup(size, rhs, span);
+                    return;
+                },
+                .address => |src| {
+                    try self.bytecode.push(Opcode.mov_reg_addr);
+                    try self.bytecode.push(dest);
+                    try self.emitAddress(src, span);
                     return;
                 },
                 else => {},
             }
         },
         .address => |dest| {
-            const dest_offset = if (dest.offset) |o| blk: {
-                switch (o.*) {
-                    .integer_literal => |offset| break :blk offset,
-                    else => return self.reportError("offset must be an integer literal", span),
-                }
-            } else 0;
+            const literal_size = literalSize(rhs);
+            const explicit_size: ?DataSize = if (data_size) |ds| switch (ds.*) {
+                .data_size => |size| size,
+                else => null,
+            } else null;
 
-            switch (rhs.*) {
+            switch ((try self.unwrapLiteral(rhs, explicit_size, span)).*) {
                 .register => |src| {
-                    switch (dest.base.*) {
-                        .register => |base| {
-                            try self.bytecode.push(Opcode.mov_addr_reg);
-                            try self.bytecode.push(src);
-                            try self.bytecode.push(addressing_variant_1);
-                            try self.bytecode.push(base);
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(dest_offset))));
-                        },
-                        .integer_literal => |base| {
-                            try self.bytecode.push(Opcode.mov_addr_reg);
-                            try self.bytecode.push(src);
-                            try self.bytecode.push(addressing_variant_2);
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(base))));
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(dest_offset))));
-                        },
-                        .identifier => |base_id| {
-                            try self.bytecode.push(Opcode.mov_addr_reg);
-                            try self.bytecode.push(src);
-                            try self.bytecode.push(addressing_variant_2);
-                            try self.fixups.put(
-                                .{ .section = self.bytecode.current_section, .addr = self.bytecode.len(self.bytecode.current_section) },
-                                .{ .size = .qword, .label = base_id, .span = span },
-                            );
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, 0x00)));
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(dest_offset))));
-                        },
-                        else => return self.reportError("unsupported address base type", span),
-                    }
+                    try self.bytecode.push(Opcode.mov_addr_reg);
+                    try self.bytecode.push(src);
+                    try self.emitAddress(dest, span);
                     return;
                 },
                 .integer_literal => |val| {
@@ -470,48 +1523,21 @@ fn compileMov(self: *Compiler, data_size: ?*ast.Expression, lhs: *ast.Expression
                             .data_size => |size| size,
                             else => return self.reportError("expected data size specifier", span),
                         };
-                    } else return self.reportError("data size required for mov [addr], imm (e.g. mov dword [addr], 42)", span);
+                    } else literal_size orelse return self.reportError("data size required for mov [addr], imm (e.g. mov dword [addr], 42)", span);
 
                     const value_bytes = switch (s) {
-                        .byte => &mem.toBytes(@as(u8, @bitCast(@as(i8, @intCast(val))))),
-                        .word => &mem.toBytes(@as(u16, @bitCast(@as(i16, @intCast(val))))),
-                        .dword => &mem.toBytes(@as(u32, @bitCast(@as(i32, @intCast(val))))),
+                        .byte => &mem.toBytes(@as(u8, @truncate(@as(u64, @bitCast(val))))),
+                        .word => &mem.toBytes(@as(u16, @truncate(@as(u64, @bitCast(val))))),
+                        .dword => &mem.toBytes(@as(u32, @truncate(@as(u64, @bitCast(val))))),
                         .qword => &mem.toBytes(@as(u64, @bitCast(val))),
                         .float => &mem.toBytes(@as(f32, @floatFromInt(val))),
                         .double => &mem.toBytes(@as(f64, @floatFromInt(val))),
                     };
 
-                    switch (dest.base.*) {
-                        .register => |base| {
-                            try self.bytecode.push(Opcode.mov_addr_imm);
-                            try self.bytecode.push(s);
-                            try self.bytecode.extend(value_bytes);
-                            try self.bytecode.push(addressing_variant_1);
-                            try self.bytecode.push(base);
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(dest_offset))));
-                        },
-                        .integer_literal => |base| {
-                            try self.bytecode.push(Opcode.mov_addr_imm);
-                            try self.bytecode.push(s);
-                            try self.bytecode.extend(value_bytes);
-                            try self.bytecode.push(addressing_variant_2);
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(base))));
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(dest_offset))));
-                        },
-                        .identifier => |base_id| {
-                            try self.bytecode.push(Opcode.mov_addr_imm);
-                            try self.bytecode.push(s);
-                            try self.bytecode.extend(value_bytes);
-                            try self.bytecode.push(addressing_variant_2);
-                            try self.fixups.put(
-                                .{ .section = self.bytecode.current_section, .addr = self.bytecode.len(self.bytecode.current_section) },
-                                .{ .size = .qword, .label = base_id, .span = span },
-                            );
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, 0x00)));
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(dest_offset))));
-                        },
-                        else => return self.reportError("unsupported address base type", span),
-                    }
+                    try self.bytecode.push(Opcode.mov_addr_imm);
+                    try self.bytecode.push(s);
+                    try self.bytecode.extend(value_bytes);
+                    try self.emitAddress(dest, span);
                     return;
                 },
                 .float_literal => |val| {
@@ -520,7 +1546,7 @@ fn compileMov(self: *Compiler, data_size: ?*ast.Expression, lhs: *ast.Expression
                             .data_size => |size| size,
                             else => return self.reportError("expected data size specifier", span),
                         };
-                    } else return self.reportError("data size required for mov [addr], imm (e.g. mov float [addr], 3.14)", span);
+                    } else literal_size orelse return self.reportError("data size required for mov [addr], imm (e.g. mov float [addr], 3.14)", span);
 
                     const value_bytes = switch (s) {
                         .byte => &mem.toBytes(@as(u8, @intFromFloat(val))),
@@ -531,37 +1557,21 @@ fn compileMov(self: *Compiler, data_size: ?*ast.Expression, lhs: *ast.Expression
                         .double => &mem.toBytes(val),
                     };
 
-                    switch (dest.base.*) {
-                        .register => |base| {
-                            try self.bytecode.push(Opcode.mov_addr_imm);
-                            try self.bytecode.push(s);
-                            try self.bytecode.extend(value_bytes);
-                            try self.bytecode.push(addressing_variant_1);
-                            try self.bytecode.push(base);
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(dest_offset))));
-                        },
-                        .integer_literal => |base| {
-                            try self.bytecode.push(Opcode.mov_addr_imm);
-                            try self.bytecode.push(s);
-                            try self.bytecode.extend(value_bytes);
-                            try self.bytecode.push(addressing_variant_2);
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(base))));
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(dest_offset))));
-                        },
-                        .identifier => |base_id| {
-                            try self.bytecode.push(Opcode.mov_addr_imm);
-                            try self.bytecode.push(s);
-                            try self.bytecode.extend(value_bytes);
-                            try self.bytecode.push(addressing_variant_2);
-                            try self.fixups.put(
-                                .{ .section = self.bytecode.current_section, .addr = self.bytecode.len(self.bytecode.current_section) },
-                                .{ .size = .qword, .label = base_id, .span = span },
-                            );
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, 0x00)));
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(dest_offset))));
-                        },
-                        else => return self.reportError("unsupported address base type", span),
-                    }
+                    try self.bytecode.push(Opcode.mov_addr_imm);
+                    try self.bytecode.push(s);
+                    try self.bytecode.extend(value_bytes);
+                    try self.emitAddress(dest, span);
+                    return;
+                },
+                .identifier, .binary_op => {
+                    const s = explicit_size orelse
+                        return self.reportError("data size required for mov [addr], label (e.g. mov qword [addr], end - start)", span);
+                    if (s == .float or s == .double) return self.reportError("label arithmetic needs an integer size", span);
+
+                    try self.bytecode.push(Opcode.mov_addr_imm);
+                    try self.bytecode.push(s);
+                    try self.emitExprFixup(s, rhs, span);
+                    try self.emitAddress(dest, span);
                     return;
                 },
                 .address => |src| {
@@ -572,61 +1582,10 @@ fn compileMov(self: *Compiler, data_size: ?*ast.Expression, lhs: *ast.Expression
                         };
                     } else return self.reportError("data size required for mov [addr], [addr] (e.g. mov dword [dest], [src])", span);
 
-                    const src_offset = if (src.offset) |o| blk: {
-                        switch (o.*) {
-                            .integer_literal => |offset| break :blk offset,
-                            else => return self.reportError("offset must be an integer literal", span),
-                        }
-                    } else 0;
-
                     try self.bytecode.push(Opcode.mov_addr_addr);
                     try self.bytecode.push(s);
-
-                    switch (src.base.*) {
-                        .register => |base| {
-                            try self.bytecode.push(addressing_variant_1);
-                            try self.bytecode.push(base);
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(src_offset))));
-                        },
-                        .integer_literal => |base| {
-                            try self.bytecode.push(addressing_variant_2);
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(base))));
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(src_offset))));
-                        },
-                        .identifier => |base_id| {
-                            try self.bytecode.push(addressing_variant_2);
-                            try self.fixups.put(
-                                .{ .section = self.bytecode.current_section, .addr = self.bytecode.len(self.bytecode.current_section) },
-                                .{ .size = .qword, .label = base_id, .span = span },
-                            );
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, 0x00)));
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(src_offset))));
-                        },
-                        else => return self.reportError("unsupported address base type", span),
-                    }
-
-                    switch (dest.base.*) {
-                        .register => |base| {
-                            try self.bytecode.push(addressing_variant_1);
-                            try self.bytecode.push(base);
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(dest_offset))));
-                        },
-                        .integer_literal => |base| {
-                            try self.bytecode.push(addressing_variant_2);
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(base))));
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(dest_offset))));
-                        },
-                        .identifier => |base_id| {
-                            try self.bytecode.push(addressing_variant_2);
-                            try self.fixups.put(
-                                .{ .section = self.bytecode.current_section, .addr = self.bytecode.len(self.bytecode.current_section) },
-                                .{ .size = .qword, .label = base_id, .span = span },
-                            );
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, 0x00)));
-                            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(dest_offset))));
-                        },
-                        else => return self.reportError("unsupported address base type", span),
-                    }
+                    try self.emitAddress(src, span);
+                    try self.emitAddress(dest, span);
                     return;
                 },
                 else => {},
@@ -650,46 +1609,14 @@ fn compileLdrOrStr(
         else => return self.reportError("left operand must be a register", span),
     };
 
-    const r = switch (rhs.*) {
-        .address => |addr| addr,
-        else => return self.reportError("right operand must be an address", span),
-    };
-
-    const offset = if (r.offset) |o| blk: {
-        switch (o.*) {
-            .integer_literal => |offset| break :blk offset,
-            else => return self.reportError("offset must be an integer literal", span),
-        }
-    } else 0;
-
-    switch (r.base.*) {
-        .register => |base| {
-            try self.bytecode.push(opcode);
-            try self.bytecode.push(l);
-            try self.bytecode.push(addressing_variant_1);
-            try self.bytecode.push(base);
-            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(offset))));
-        },
-        .integer_literal => |base| {
-            try self.bytecode.push(opcode);
-            try self.bytecode.push(l);
-            try self.bytecode.push(addressing_variant_2);
-            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(base))));
-            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(offset))));
-        },
-        .identifier => |base_id| {
-            try self.bytecode.push(opcode);
-            try self.bytecode.push(l);
-            try self.bytecode.push(addressing_variant_2);
-            try self.fixups.put(
-                .{ .section = self.bytecode.current_section, .addr = self.bytecode.len(self.bytecode.current_section) },
-                .{ .size = .qword, .label = base_id, .span = span },
-            );
-            try self.bytecode.extend(&mem.toBytes(@as(u64, 0x00)));
-            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(offset))));
-        },
-        else => return self.reportError("unsupported address base type", span),
-    }
+    const r = switch (rhs.*) {
+        .address => |addr| addr,
+        else => return self.reportError("right operand must be an address", span),
+    };
+
+    try self.bytecode.push(opcode);
+    try self.bytecode.push(l);
+    try self.emitAddress(r, span);
 }
 
 fn compileSti(
@@ -706,10 +1633,11 @@ fn compileSti(
 
     const value_bytes = switch (lhs.*) {
         .integer_literal => |val| blk: {
+            try self.checkImmediate(val, s, span);
             break :blk switch (s) {
-                .byte => &mem.toBytes(@as(u8, @bitCast(@as(i8, @intCast(val))))),
-                .word => &mem.toBytes(@as(u16, @bitCast(@as(i16, @intCast(val))))),
-                .dword => &mem.toBytes(@as(u32, @bitCast(@as(i32, @intCast(val))))),
+                .byte => &mem.toBytes(@as(u8, @truncate(@as(u64, @bitCast(val))))),
+                .word => &mem.toBytes(@as(u16, @truncate(@as(u64, @bitCast(val))))),
+                .dword => &mem.toBytes(@as(u32, @truncate(@as(u64, @bitCast(val))))),
                 .qword => &mem.toBytes(@as(u64, @bitCast(val))),
                 .float => &mem.toBytes(@as(f32, @floatFromInt(val))),
                 .double => &mem.toBytes(@as(f64, @floatFromInt(val))),
@@ -788,9 +1716,19 @@ fn compileSti(
     }
 }
 
-fn compilePush(self: *Compiler, data_size: ?*ast.Expression, expr: *ast.Expression, span: Span) !void {
+fn compilePush(self: *Compiler, data_size: ?*ast.Expression, operand: *ast.Expression, span: Span) !void {
+    const literal_size = literalSize(operand);
+    const explicit_size: ?DataSize = if (data_size) |ds| switch (ds.*) {
+        .data_size => |size| size,
+        else => null,
+    } else null;
+    const expr = try self.unwrapLiteral(operand, explicit_size, span);
+
     switch (expr.*) {
         .register => |src| {
+            if (self.strict and data_size == null) {
+                return self.reportError("push needs an explicit size in strict mode (e.g. push qword q0)", span);
+            }
             const size = if (data_size) |ds| switch (ds.*) {
                 .data_size => |v| v,
                 else => return self.reportError("expected data size specifier", span),
@@ -805,14 +1743,14 @@ fn compilePush(self: *Compiler, data_size: ?*ast.Expression, expr: *ast.Expressi
             const size = if (data_size) |ds| switch (ds.*) {
                 .data_size => |v| v,
                 else => return self.reportError("expected data size specifier", span),
-            } else return self.reportError("expected data size specifier", span);
+            } else literal_size orelse return self.reportError("expected data size specifier", span);
 
             try self.bytecode.push(Opcode.push_imm);
             try self.bytecode.push(size);
             try self.bytecode.extend(switch (size) {
-                .byte => &mem.toBytes(@as(u8, @bitCast(@as(i8, @intCast(src))))),
-                .word => &mem.toBytes(@as(u16, @bitCast(@as(i16, @intCast(src))))),
-                .dword => &mem.toBytes(@as(u32, @bitCast(@as(i32, @intCast(src))))),
+                .byte => &mem.toBytes(@as(u8, @truncate(@as(u64, @bitCast(src))))),
+                .word => &mem.toBytes(@as(u16, @truncate(@as(u64, @bitCast(src))))),
+                .dword => &mem.toBytes(@as(u32, @truncate(@as(u64, @bitCast(src))))),
                 .qword => &mem.toBytes(@as(u64, @bitCast(src))),
                 .float => &mem.toBytes(@as(f32, @floatFromInt(src))),
                 .double => &mem.toBytes(@as(f64, @floatFromInt(src))),
@@ -820,10 +1758,11 @@ fn compilePush(self: *Compiler, data_size: ?*ast.Expression, expr: *ast.Expressi
             return;
         },
         .float_literal => |src| {
+            // Unsuffixed float literals default to double precision.
             const size = if (data_size) |ds| switch (ds.*) {
                 .data_size => |v| v,
                 else => return self.reportError("expected data size specifier", span),
-            } else return self.reportError("expected data size specifier", span);
+            } else literal_size orelse DataSize.double;
 
             try self.bytecode.push(Opcode.push_imm);
             try self.bytecode.push(size);
@@ -837,20 +1776,18 @@ fn compilePush(self: *Compiler, data_size: ?*ast.Expression, expr: *ast.Expressi
             });
             return;
         },
-        .identifier => |src| {
+        // The immediate is as wide as the pushed size, so `push dword label`
+        // reserves four bytes.
+        .identifier, .binary_op => {
             const size = if (data_size) |ds| switch (ds.*) {
                 .data_size => |v| v,
                 else => return self.reportError("expected data size specifier", span),
             } else DataSize.qword;
+            if (size == .float or size == .double) return self.reportError("label arithmetic needs an integer size", span);
 
             try self.bytecode.push(Opcode.push_imm);
             try self.bytecode.push(size);
-            const offset = self.bytecode.len(self.bytecode.current_section);
-            try self.fixups.put(
-                .{ .section = self.bytecode.current_section, .addr = offset },
-                .{ .size = .qword, .label = src, .span = span },
-            );
-            try self.bytecode.extend(&mem.toBytes(@as(u64, 0x00)));
+            try self.emitExprFixup(size, expr, span);
             return;
         },
         .address => |src| {
@@ -862,35 +1799,7 @@ fn compilePush(self: *Compiler, data_size: ?*ast.Expression, expr: *ast.Expressi
             try self.bytecode.push(Opcode.push_addr);
             try self.bytecode.push(size);
 
-            const offset = if (src.offset) |o| blk: {
-                switch (o.*) {
-                    .integer_literal => |offset| break :blk offset,
-                    else => return self.reportError("offset must be an integer literal", span),
-                }
-            } else 0;
-
-            switch (src.base.*) {
-                .register => |base| {
-                    try self.bytecode.push(addressing_variant_1);
-                    try self.bytecode.push(base);
-                    try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(offset))));
-                },
-                .integer_literal => |base| {
-                    try self.bytecode.push(addressing_variant_2);
-                    try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(base))));
-                    try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(offset))));
-                },
-                .identifier => |base| {
-                    try self.bytecode.push(addressing_variant_2);
-                    try self.fixups.put(
-                        .{ .section = self.bytecode.current_section, .addr = self.bytecode.len(self.bytecode.current_section) },
-                        .{ .size = .qword, .label = base, .span = span },
-                    );
-                    try self.bytecode.extend(&mem.toBytes(@as(u64, 0x00)));
-                    try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(offset))));
-                },
-                else => return self.reportError("unsupported address base type", span),
-            }
+            try self.emitAddress(src, span);
             return;
         },
         else => {},
@@ -902,6 +1811,10 @@ fn compilePush(self: *Compiler, data_size: ?*ast.Expression, expr: *ast.Expressi
 fn compilePop(self: *Compiler, data_size: ?*ast.Expression, expr: *ast.Expression, span: Span) !void {
     switch (expr.*) {
         .register => |dest| {
+            try self.checkWritable(dest, span);
+            if (self.strict and data_size == null) {
+                return self.reportError("pop needs an explicit size in strict mode (e.g. pop qword q0)", span);
+            }
             const size = if (data_size) |ds| switch (ds.*) {
                 .data_size => |v| v,
                 else => return self.reportError("expected data size specifier", span),
@@ -921,35 +1834,7 @@ fn compilePop(self: *Compiler, data_size: ?*ast.Expression, expr: *ast.Expressio
             try self.bytecode.push(Opcode.pop_addr);
             try self.bytecode.push(size);
 
-            const offset = if (src.offset) |o| blk: {
-                switch (o.*) {
-                    .integer_literal => |offset| break :blk offset,
-                    else => return self.reportError("offset must be an integer literal", span),
-                }
-            } else 0;
-
-            switch (src.base.*) {
-                .register => |base| {
-                    try self.bytecode.push(addressing_variant_1);
-                    try self.bytecode.push(base);
-                    try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(offset))));
-                },
-                .integer_literal => |base| {
-                    try self.bytecode.push(addressing_variant_2);
-                    try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(base))));
-                    try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(offset))));
-                },
-                .identifier => |base| {
-                    try self.bytecode.push(addressing_variant_2);
-                    try self.fixups.put(
-                        .{ .section = self.bytecode.current_section, .addr = self.bytecode.len(self.bytecode.current_section) },
-                        .{ .size = .qword, .label = base, .span = span },
-                    );
-                    try self.bytecode.extend(&mem.toBytes(@as(u64, 0x00)));
-                    try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(offset))));
-                },
-                else => return self.reportError("unsupported address base type", span),
-            }
+            try self.emitAddress(src, span);
             return;
         },
         else => {},
@@ -958,48 +1843,132 @@ fn compilePop(self: *Compiler, data_size: ?*ast.Expression, expr: *ast.Expressio
     return self.reportError("unsupported operands", span);
 }
 
-fn emitAddress(self: *Compiler, addr: ast.Expression.Address, span: Span) !void {
-    const offset = if (addr.offset) |o| blk: {
-        switch (o.*) {
-            .integer_literal => |off| break :blk off,
-            else => return self.reportError("offset must be an integer literal", span),
+/// Emit an extended instruction: the prefix, the subcode, the length of
+/// the operands and the operands themselves. Registers take a byte and
+/// addresses are encoded like any other; integers are a qword unless they
+/// have a width suffix, and labels are a qword.
+fn compileExt(self: *Compiler, subcode: *ast.Expression, operands: []*ast.Expression, span: Span) !void {
+    const code: u8 = switch (subcode.*) {
+        .integer_literal => |int| std.math.cast(u8, int) orelse
+            return self.reportError("extension subcode must fit in a byte", span),
+        else => return self.reportError("extension subcode must be an integer", span),
+    };
+
+    try self.bytecode.push(Opcode.extended);
+    try self.bytecode.push(code);
+    const section = self.bytecode.current_section;
+    const len_at = self.bytecode.len(section);
+    try self.bytecode.push(@as(u8, 0));
+
+    for (operands) |operand| {
+        const size = literalSize(operand);
+        switch ((try self.unwrapLiteral(operand, null, span)).*) {
+            .register => |reg| try self.bytecode.push(reg),
+            .address => |addr| try self.emitAddress(addr, span),
+            .integer_literal => |int| try self.bytecode.extend(switch (size orelse .qword) {
+                .byte => &mem.toBytes(@as(u8, @truncate(@as(u64, @bitCast(int))))),
+                .word => &mem.toBytes(@as(u16, @truncate(@as(u64, @bitCast(int))))),
+                .dword => &mem.toBytes(@as(u32, @truncate(@as(u64, @bitCast(int))))),
+                .qword => &mem.toBytes(@as(u64, @bitCast(int))),
+                .float => &mem.toBytes(@as(f32, @floatFromInt(int))),
+                .double => &mem.toBytes(@as(f64, @floatFromInt(int))),
+            }),
+            .float_literal => |float| try self.bytecode.extend(switch (size orelse .double) {
+                .float => &mem.toBytes(@as(f32, @floatCast(float))),
+                else => &mem.toBytes(float),
+            }),
+            .identifier, .binary_op => {
+                const value = labelExpr(operand) orelse return self.reportError("unsupported operand", span);
+                try self.emitConstant(value, span);
+            },
+            else => return self.reportError("unsupported operand", span),
         }
-    } else 0;
+    }
 
-    switch (addr.base.*) {
-        .register => |base| {
-            try self.bytecode.push(addressing_variant_1);
-            try self.bytecode.push(base);
-            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(offset))));
-        },
-        .integer_literal => |base| {
-            try self.bytecode.push(addressing_variant_2);
-            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(base))));
-            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(offset))));
-        },
-        .identifier => |base_id| {
-            try self.bytecode.push(addressing_variant_2);
-            try self.fixups.put(
-                .{ .section = self.bytecode.current_section, .addr = self.bytecode.len(self.bytecode.current_section) },
-                .{ .size = .qword, .label = base_id, .span = span },
-            );
-            try self.bytecode.extend(&mem.toBytes(@as(u64, 0x00)));
-            try self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(offset))));
-        },
-        else => return self.reportError("unsupported address base type", span),
+    const len = self.bytecode.len(section) - len_at - 1;
+    if (len > std.math.maxInt(u8)) return self.reportError("extension operands take more than 255 bytes", span);
+    self.bytecode.writeU8At(section, len_at, @intCast(len));
+}
+
+/// Emit an address operand. The base is a register, optionally plus or
+/// minus a constant, or a constant on its own; the offset is a constant.
+/// A constant is any sum of labels and integers that `labelExpr` reduces,
+/// so `[buffer + 8]`, `[q0 + 8]` and `[table, entry - table]` all work.
+fn emitAddress(self: *Compiler, addr: ast.Expression.Address, span: Span) !void {
+    const base = addressBase(addr.base) orelse
+        return self.reportError("address base must be a register or labels and integers", span);
+    var offset: LabelExpr = .{};
+    if (addr.offset) |o| offset = labelExpr(o) orelse
+        return self.reportError("address offset must be labels and integers", span);
+
+    if (base.register) |reg| {
+        const total = combine(base.value, offset) orelse
+            return self.reportError("an address can add at most one label to a register", span);
+        try self.bytecode.push(addressing_variant_1);
+        try self.bytecode.push(reg);
+        try self.emitConstant(total, span);
+    } else {
+        try self.bytecode.push(addressing_variant_2);
+        try self.emitConstant(base.value, span);
+        try self.emitConstant(offset, span);
     }
 }
 
+/// The base of an address split into at most one register and a constant.
+const AddressBase = struct {
+    register: ?Register = null,
+    value: LabelExpr = .{},
+};
+
+fn addressBase(expr: *const ast.Expression) ?AddressBase {
+    return switch (expr.*) {
+        .register => |reg| .{ .register = reg },
+        .binary_op => |v| switch (v.op) {
+            .add, .sub => blk: {
+                const lhs = addressBase(v.lhs) orelse break :blk null;
+                const rhs = addressBase(v.rhs) orelse break :blk null;
+                if (rhs.register != null and (lhs.register != null or v.op == .sub)) break :blk null;
+                const value = if (v.op == .sub) negate(rhs.value) orelse break :blk null else rhs.value;
+                break :blk .{
+                    .register = lhs.register orelse rhs.register,
+                    .value = combine(lhs.value, value) orelse break :blk null,
+                };
+            },
+            else => .{ .value = labelExpr(expr) orelse return null },
+        },
+        else => .{ .value = labelExpr(expr) orelse return null },
+    };
+}
+
+/// Emit a qword holding `value`, with a fixup if it refers to labels.
+fn emitConstant(self: *Compiler, value: LabelExpr, span: Span) !void {
+    const label = value.label orelse {
+        if (value.minus != null) return self.reportError("cannot subtract a label from a constant", span);
+        return self.bytecode.extend(&mem.toBytes(@as(u64, @bitCast(value.addend))));
+    };
+    const addr = self.bytecode.len(self.bytecode.current_section);
+    try self.fixups.put(
+        .{ .section = self.bytecode.current_section, .addr = addr },
+        .{ .size = .qword, .label = label, .minus = value.minus, .addend = value.addend, .span = span },
+    );
+    try self.bytecode.grow(8);
+}
+
 fn compileArithmetic(
     self: *Compiler,
     dest: *ast.Expression,
     lhs: *ast.Expression,
-    rhs: *ast.Expression,
+    rhs_operand: *ast.Expression,
     op: enum {
         add,
+        adc,
         sub,
+        sbb,
         mul,
         div,
+        imul,
+        idiv,
+        imod,
     },
     span: Span,
 ) !void {
@@ -1007,6 +1976,16 @@ fn compileArithmetic(
         .register => |v| v,
         else => return self.reportError("first operand must be a register", span),
     };
+    try self.checkWritable(dest_reg, span);
+    if (op == .adc or op == .sbb) switch (DataSize.fromRegister(dest_reg)) {
+        .float, .double => return self.reportError("adc and sbb not supported on floating-point registers", span),
+        else => {},
+    };
+    if (op == .imul or op == .idiv or op == .imod) switch (DataSize.fromRegister(dest_reg)) {
+        .float, .double => return self.reportError("imul, idiv and imod not supported on floating-point registers", span),
+        else => {},
+    };
+    const rhs = try self.unwrapLiteral(rhs_operand, DataSize.fromRegister(dest_reg), span);
 
     switch (lhs.*) {
         .register => |lhs_reg| {
@@ -1014,9 +1993,14 @@ fn compileArithmetic(
                 .register => |rhs_reg| {
                     try self.bytecode.push(switch (op) {
                         .add => Opcode.add_reg_reg_reg,
+                        .adc => Opcode.adc_reg_reg_reg,
                         .sub => Opcode.sub_reg_reg_reg,
+                        .sbb => Opcode.sbb_reg_reg_reg,
                         .mul => Opcode.mul_reg_reg_reg,
                         .div => Opcode.div_reg_reg_reg,
+                        .imul => Opcode.imul_reg_reg_reg,
+                        .idiv => Opcode.idiv_reg_reg_reg,
+                        .imod => Opcode.imod_reg_reg_reg,
                     });
                     try self.bytecode.push(dest_reg);
                     try self.bytecode.push(lhs_reg);
@@ -1026,16 +2010,21 @@ fn compileArithmetic(
                 .integer_literal => |rhs_int| {
                     try self.bytecode.push(switch (op) {
                         .add => Opcode.add_reg_reg_imm,
+                        .adc => Opcode.adc_reg_reg_imm,
                         .sub => Opcode.sub_reg_reg_imm,
+                        .sbb => Opcode.sbb_reg_reg_imm,
                         .mul => Opcode.mul_reg_reg_imm,
                         .div => Opcode.div_reg_reg_imm,
+                        .imul => Opcode.imul_reg_reg_imm,
+                        .idiv => Opcode.idiv_reg_reg_imm,
+                        .imod => Opcode.imod_reg_reg_imm,
                     });
                     try self.bytecode.push(dest_reg);
                     try self.bytecode.push(lhs_reg);
                     try self.bytecode.extend(switch (DataSize.fromRegister(dest_reg)) {
-                        .byte => &mem.toBytes(@as(u8, @bitCast(@as(i8, @intCast(rhs_int))))),
-                        .word => &mem.toBytes(@as(u16, @bitCast(@as(i16, @intCast(rhs_int))))),
-                        .dword => &mem.toBytes(@as(u32, @bitCast(@as(i32, @intCast(rhs_int))))),
+                        .byte => &mem.toBytes(@as(u8, @truncate(@as(u64, @bitCast(rhs_int))))),
+                        .word => &mem.toBytes(@as(u16, @truncate(@as(u64, @bitCast(rhs_int))))),
+                        .dword => &mem.toBytes(@as(u32, @truncate(@as(u64, @bitCast(rhs_int))))),
                         .qword => &mem.toBytes(@as(u64, @bitCast(rhs_int))),
                         .float => &mem.toBytes(@as(f32, @floatFromInt(rhs_int))),
                         .double => &mem.toBytes(@as(f64, @floatFromInt(rhs_int))),
@@ -1045,9 +2034,14 @@ fn compileArithmetic(
                 .float_literal => |rhs_float| {
                     try self.bytecode.push(switch (op) {
                         .add => Opcode.add_reg_reg_imm,
+                        .adc => Opcode.adc_reg_reg_imm,
                         .sub => Opcode.sub_reg_reg_imm,
+                        .sbb => Opcode.sbb_reg_reg_imm,
                         .mul => Opcode.mul_reg_reg_imm,
                         .div => Opcode.div_reg_reg_imm,
+                        .imul => Opcode.imul_reg_reg_imm,
+                        .idiv => Opcode.idiv_reg_reg_imm,
+                        .imod => Opcode.imod_reg_reg_imm,
                     });
                     try self.bytecode.push(dest_reg);
                     try self.bytecode.push(lhs_reg);
@@ -1064,9 +2058,14 @@ fn compileArithmetic(
                 .address => |rhs_addr| {
                     try self.bytecode.push(switch (op) {
                         .add => Opcode.add_reg_reg_addr,
+                        .adc => Opcode.adc_reg_reg_addr,
                         .sub => Opcode.sub_reg_reg_addr,
+                        .sbb => Opcode.sbb_reg_reg_addr,
                         .mul => Opcode.mul_reg_reg_addr,
                         .div => Opcode.div_reg_reg_addr,
+                        .imul => Opcode.imul_reg_reg_addr,
+                        .idiv => Opcode.idiv_reg_reg_addr,
+                        .imod => Opcode.imod_reg_reg_addr,
                     });
                     try self.bytecode.push(dest_reg);
                     try self.bytecode.push(lhs_reg);
@@ -1081,9 +2080,14 @@ fn compileArithmetic(
                 .register => |rhs_reg| {
                     try self.bytecode.push(switch (op) {
                         .add => Opcode.add_reg_addr_reg,
+                        .adc => Opcode.adc_reg_addr_reg,
                         .sub => Opcode.sub_reg_addr_reg,
+                        .sbb => Opcode.sbb_reg_addr_reg,
                         .mul => Opcode.mul_reg_addr_reg,
                         .div => Opcode.div_reg_addr_reg,
+                        .imul => Opcode.imul_reg_addr_reg,
+                        .idiv => Opcode.idiv_reg_addr_reg,
+                        .imod => Opcode.imod_reg_addr_reg,
                     });
                     try self.bytecode.push(dest_reg);
                     try self.emitAddress(lhs_addr, span);
@@ -1093,16 +2097,21 @@ fn compileArithmetic(
                 .integer_literal => |rhs_int| {
                     try self.bytecode.push(switch (op) {
                         .add => Opcode.add_reg_addr_imm,
+                        .adc => Opcode.adc_reg_addr_imm,
                         .sub => Opcode.sub_reg_addr_imm,
+                        .sbb => Opcode.sbb_reg_addr_imm,
                         .mul => Opcode.mul_reg_addr_imm,
                         .div => Opcode.div_reg_addr_imm,
+                        .imul => Opcode.imul_reg_addr_imm,
+                        .idiv => Opcode.idiv_reg_addr_imm,
+                        .imod => Opcode.imod_reg_addr_imm,
                     });
                     try self.bytecode.push(dest_reg);
                     try self.emitAddress(lhs_addr, span);
                     try self.bytecode.extend(switch (DataSize.fromRegister(dest_reg)) {
-                        .byte => &mem.toBytes(@as(u8, @bitCast(@as(i8, @intCast(rhs_int))))),
-                        .word => &mem.toBytes(@as(u16, @bitCast(@as(i16, @intCast(rhs_int))))),
-                        .dword => &mem.toBytes(@as(u32, @bitCast(@as(i32, @intCast(rhs_int))))),
+                        .byte => &mem.toBytes(@as(u8, @truncate(@as(u64, @bitCast(rhs_int))))),
+                        .word => &mem.toBytes(@as(u16, @truncate(@as(u64, @bitCast(rhs_int))))),
+                        .dword => &mem.toBytes(@as(u32, @truncate(@as(u64, @bitCast(rhs_int))))),
                         .qword => &mem.toBytes(@as(u64, @bitCast(rhs_int))),
                         .float => &mem.toBytes(@as(f32, @floatFromInt(rhs_int))),
                         .double => &mem.toBytes(@as(f64, @floatFromInt(rhs_int))),
@@ -1112,9 +2121,14 @@ fn compileArithmetic(
                 .float_literal => |rhs_float| {
                     try self.bytecode.push(switch (op) {
                         .add => Opcode.add_reg_addr_imm,
+                        .adc => Opcode.adc_reg_addr_imm,
                         .sub => Opcode.sub_reg_addr_imm,
+                        .sbb => Opcode.sbb_reg_addr_imm,
                         .mul => Opcode.mul_reg_addr_imm,
                         .div => Opcode.div_reg_addr_imm,
+                        .imul => Opcode.imul_reg_addr_imm,
+                        .idiv => Opcode.idiv_reg_addr_imm,
+                        .imod => Opcode.imod_reg_addr_imm,
                     });
                     try self.bytecode.push(dest_reg);
                     try self.emitAddress(lhs_addr, span);
@@ -1131,9 +2145,14 @@ fn compileArithmetic(
                 .address => |rhs_addr| {
                     try self.bytecode.push(switch (op) {
                         .add => Opcode.add_reg_addr_addr,
+                        .adc => Opcode.adc_reg_addr_addr,
                         .sub => Opcode.sub_reg_addr_addr,
+                        .sbb => Opcode.sbb_reg_addr_addr,
                         .mul => Opcode.mul_reg_addr_addr,
                         .div => Opcode.div_reg_addr_addr,
+                        .imul => Opcode.imul_reg_addr_addr,
+                        .idiv => Opcode.idiv_reg_addr_addr,
+                        .imod => Opcode.imod_reg_addr_addr,
                     });
                     try self.bytecode.push(dest_reg);
                     try self.emitAddress(lhs_addr, span);
@@ -1153,7 +2172,7 @@ fn compileBitwise(
     self: *Compiler,
     dest: *ast.Expression,
     lhs: *ast.Expression,
-    rhs: *ast.Expression,
+    rhs_operand: *ast.Expression,
     op: enum {
         @"and",
         @"or",
@@ -1169,6 +2188,8 @@ fn compileBitwise(
         .register => |v| v,
         else => return self.reportError("first operand must be a register", span),
     };
+    try self.checkWritable(dest_reg, span);
+    const rhs = try self.unwrapLiteral(rhs_operand, DataSize.fromRegister(dest_reg), span);
 
     switch (lhs.*) {
         .register => |lhs_reg| {
@@ -1211,9 +2232,9 @@ fn compileBitwise(
                     try self.bytecode.push(dest_reg);
                     try self.bytecode.push(lhs_reg);
                     try self.bytecode.extend(switch (DataSize.fromRegister(dest_reg)) {
-                        .byte => &mem.toBytes(@as(u8, @bitCast(@as(i8, @intCast(rhs_int))))),
-                        .word => &mem.toBytes(@as(u16, @bitCast(@as(i16, @intCast(rhs_int))))),
-                        .dword => &mem.toBytes(@as(u32, @bitCast(@as(i32, @intCast(rhs_int))))),
+                        .byte => &mem.toBytes(@as(u8, @truncate(@as(u64, @bitCast(rhs_int))))),
+                        .word => &mem.toBytes(@as(u16, @truncate(@as(u64, @bitCast(rhs_int))))),
+                        .dword => &mem.toBytes(@as(u32, @truncate(@as(u64, @bitCast(rhs_int))))),
                         .qword => &mem.toBytes(@as(u64, @bitCast(rhs_int))),
                         .float => &mem.toBytes(@as(f32, @floatFromInt(rhs_int))),
                         .double => &mem.toBytes(@as(f64, @floatFromInt(rhs_int))),
@@ -1274,9 +2295,9 @@ fn compileBitwise(
                     try self.bytecode.push(dest_reg);
                     try self.emitAddress(lhs_addr, span);
                     try self.bytecode.extend(switch (DataSize.fromRegister(dest_reg)) {
-                        .byte => &mem.toBytes(@as(u8, @bitCast(@as(i8, @intCast(rhs_int))))),
-                        .word => &mem.toBytes(@as(u16, @bitCast(@as(i16, @intCast(rhs_int))))),
-                        .dword => &mem.toBytes(@as(u32, @bitCast(@as(i32, @intCast(rhs_int))))),
+                        .byte => &mem.toBytes(@as(u8, @truncate(@as(u64, @bitCast(rhs_int))))),
+                        .word => &mem.toBytes(@as(u16, @truncate(@as(u64, @bitCast(rhs_int))))),
+                        .dword => &mem.toBytes(@as(u32, @truncate(@as(u64, @bitCast(rhs_int))))),
                         .qword => &mem.toBytes(@as(u64, @bitCast(rhs_int))),
                         .float => &mem.toBytes(@as(f32, @floatFromInt(rhs_int))),
                         .double => &mem.toBytes(@as(f64, @floatFromInt(rhs_int))),
@@ -1308,6 +2329,107 @@ fn compileBitwise(
     return self.reportError("unsupported operands", span);
 }
 
+fn compileNot(self: *Compiler, dest: *ast.Expression, src: *ast.Expression, span: Span) !void {
+    const dest_reg = switch (dest.*) {
+        .register => |v| v,
+        else => return self.reportError("first operand must be a register", span),
+    };
+    try self.checkWritable(dest_reg, span);
+    switch (DataSize.fromRegister(dest_reg)) {
+        .float, .double => return self.reportError("bitwise operations not supported on floating-point registers", span),
+        else => {},
+    }
+
+    switch (src.*) {
+        .register => |src_reg| {
+            switch (DataSize.fromRegister(src_reg)) {
+                .float, .double => return self.reportError("bitwise operations not supported on floating-point registers", span),
+                else => {},
+            }
+            try self.bytecode.push(Opcode.not_reg_reg);
+            try self.bytecode.push(dest_reg);
+            try self.bytecode.push(src_reg);
+        },
+        .address => |src_addr| {
+            try self.bytecode.push(Opcode.not_reg_addr);
+            try self.bytecode.push(dest_reg);
+            try self.emitAddress(src_addr, span);
+        },
+        else => return self.reportError("second operand must be a register or address", span),
+    }
+}
+
+/// `movz` and `movs` from an integer register into a wider one.
+fn compileExtend(self: *Compiler, dest: *ast.Expression, src: *ast.Expression, opcode: Opcode, span: Span) !void {
+    const dest_reg = switch (dest.*) {
+        .register => |v| v,
+        else => return self.reportError("first operand must be a register", span),
+    };
+    const src_reg = switch (src.*) {
+        .register => |v| v,
+        else => return self.reportError("second operand must be a register", span),
+    };
+    try self.checkWritable(dest_reg, span);
+
+    const dest_size = DataSize.fromRegister(dest_reg);
+    const src_size = DataSize.fromRegister(src_reg);
+    switch (dest_size) {
+        .float, .double => return self.reportError("extending moves not supported on floating-point registers", span),
+        else => {},
+    }
+    switch (src_size) {
+        .float, .double => return self.reportError("extending moves not supported on floating-point registers", span),
+        else => {},
+    }
+    if (dest_size.sizeInBytes() <= src_size.sizeInBytes()) {
+        return self.reportError("destination must be wider than the source", span);
+    }
+
+    try self.bytecode.push(opcode);
+    try self.bytecode.push(dest_reg);
+    try self.bytecode.push(src_reg);
+}
+
+/// `cvtif`, `cvtfi`, `cvtfd` and `cvtdf` between two registers of the
+/// classes the opcode names.
+fn compileConvert(self: *Compiler, dest: *ast.Expression, src: *ast.Expression, opcode: Opcode, span: Span) !void {
+    const dest_reg = switch (dest.*) {
+        .register => |v| v,
+        else => return self.reportError("first operand must be a register", span),
+    };
+    const src_reg = switch (src.*) {
+        .register => |v| v,
+        else => return self.reportError("second operand must be a register", span),
+    };
+    try self.checkWritable(dest_reg, span);
+
+    const dest_size = DataSize.fromRegister(dest_reg);
+    const src_size = DataSize.fromRegister(src_reg);
+    const dest_is_float = dest_size == .float or dest_size == .double;
+    const src_is_float = src_size == .float or src_size == .double;
+    switch (opcode) {
+        .cvtif_reg_reg => {
+            if (!dest_is_float) return self.reportError("cvtif needs a float or double destination", span);
+            if (src_is_float) return self.reportError("cvtif needs an integer source", span);
+        },
+        .cvtfi_reg_reg => {
+            if (dest_is_float) return self.reportError("cvtfi needs an integer destination", span);
+            if (!src_is_float) return self.reportError("cvtfi needs a float or double source", span);
+        },
+        .cvtfd_reg_reg => if (dest_size != .double or src_size != .float) {
+            return self.reportError("cvtfd converts a float register into a double register", span);
+        },
+        .cvtdf_reg_reg => if (dest_size != .float or src_size != .double) {
+            return self.reportError("cvtdf converts a double register into a float register", span);
+        },
+        else => unreachable,
+    }
+
+    try self.bytecode.push(opcode);
+    try self.bytecode.push(dest_reg);
+    try self.bytecode.push(src_reg);
+}
+
 fn compileCmp(
     self: *Compiler,
     lhs: *ast.Expression,
@@ -1316,7 +2438,7 @@ fn compileCmp(
 ) !void {
     switch (lhs.*) {
         .register => |lhs_reg| {
-            switch (rhs.*) {
+            switch ((try self.unwrapLiteral(rhs, DataSize.fromRegister(lhs_reg), span)).*) {
                 .register => |rhs_reg| {
                     try self.bytecode.push(Opcode.cmp_reg_reg);
                     try self.bytecode.push(lhs_reg);
@@ -1327,9 +2449,9 @@ fn compileCmp(
                     try self.bytecode.push(Opcode.cmp_reg_imm);
                     try self.bytecode.push(lhs_reg);
                     try self.bytecode.extend(switch (DataSize.fromRegister(lhs_reg)) {
-                        .byte => &mem.toBytes(@as(u8, @bitCast(@as(i8, @intCast(rhs_int))))),
-                        .word => &mem.toBytes(@as(u16, @bitCast(@as(i16, @intCast(rhs_int))))),
-                        .dword => &mem.toBytes(@as(u32, @bitCast(@as(i32, @intCast(rhs_int))))),
+                        .byte => &mem.toBytes(@as(u8, @truncate(@as(u64, @bitCast(rhs_int))))),
+                        .word => &mem.toBytes(@as(u16, @truncate(@as(u64, @bitCast(rhs_int))))),
+                        .dword => &mem.toBytes(@as(u32, @truncate(@as(u64, @bitCast(rhs_int))))),
                         .qword => &mem.toBytes(@as(u64, @bitCast(rhs_int))),
                         .float => &mem.toBytes(@as(f32, @floatFromInt(rhs_int))),
                         .double => &mem.toBytes(@as(f64, @floatFromInt(rhs_int))),
@@ -1360,6 +2482,92 @@ fn compileCmp(
     return self.reportError("unsupported operands", span);
 }
 
+/// A register operand of `scmp` or `schr` that holds an address or a
+/// length, which must be a 64-bit general purpose register.
+fn stringRegister(self: *Compiler, expr: *ast.Expression, span: Span) !Register {
+    const reg = switch (expr.*) {
+        .register => |v| v,
+        else => return self.reportError("string operands must be registers holding an address or length", span),
+    };
+    const info = reg.physicalInfo();
+    if (info.type != .general_purpose or info.view != .qword) {
+        return self.reportError("string operands must be 64-bit registers", span);
+    }
+    return reg;
+}
+
+fn compileScmp(self: *Compiler, lhs: *ast.Expression, rhs: *ast.Expression, span: Span) !void {
+    const lhs_reg = try self.stringRegister(lhs, span);
+    const rhs_reg = try self.stringRegister(rhs, span);
+    try self.bytecode.push(Opcode.scmp_reg_reg);
+    try self.bytecode.push(lhs_reg);
+    try self.bytecode.push(rhs_reg);
+}
+
+fn compileSchr(
+    self: *Compiler,
+    ptr: *ast.Expression,
+    len: *ast.Expression,
+    byte: *ast.Expression,
+    span: Span,
+) !void {
+    const ptr_reg = try self.stringRegister(ptr, span);
+    try self.checkWritable(ptr_reg, span);
+    const len_reg = try self.stringRegister(len, span);
+    try self.checkWritable(len_reg, span);
+
+    switch ((try self.unwrapLiteral(byte, .byte, span)).*) {
+        .register => |byte_reg| {
+            switch (DataSize.fromRegister(byte_reg)) {
+                .float, .double => return self.reportError("third operand must be an integer register or a byte", span),
+                else => {},
+            }
+            try self.bytecode.push(Opcode.schr_reg_reg_reg);
+            try self.bytecode.push(ptr_reg);
+            try self.bytecode.push(len_reg);
+            try self.bytecode.push(byte_reg);
+        },
+        .integer_literal => |value| {
+            if (value < std.math.minInt(i8) or value > std.math.maxInt(u8)) {
+                return self.reportError("byte to search for does not fit in a byte", span);
+            }
+            try self.bytecode.push(Opcode.schr_reg_reg_imm);
+            try self.bytecode.push(ptr_reg);
+            try self.bytecode.push(len_reg);
+            try self.bytecode.extend(&mem.toBytes(@as(u64, @as(u8, @truncate(@as(u64, @bitCast(value)))))));
+        },
+        else => return self.reportError("third operand must be an integer register or a byte", span),
+    }
+}
+
+/// `seteq` and the rest of the flag queries, which write 1 or 0 to an
+/// integer register.
+fn compileSet(self: *Compiler, dest: *ast.Expression, opcode: Opcode, span: Span) !void {
+    const dest_reg = switch (dest.*) {
+        .register => |v| v,
+        else => return self.reportError("operand must be a register", span),
+    };
+    try self.checkWritable(dest_reg, span);
+    switch (DataSize.fromRegister(dest_reg)) {
+        .float, .double => return self.reportError("flag queries not supported on floating-point registers", span),
+        else => {},
+    }
+    try self.bytecode.push(opcode);
+    try self.bytecode.push(dest_reg);
+}
+
+/// `enter size`: save `bp`, point it at the new frame and reserve `size`
+/// bytes of locals below it.
+fn compileEnter(self: *Compiler, size: *ast.Expression, span: Span) !void {
+    const bytes = switch (size.*) {
+        .integer_literal => |int| std.math.cast(u64, int) orelse
+            return self.reportError("frame size cannot be negative", span),
+        else => return self.reportError("frame size must be an integer", span),
+    };
+    try self.bytecode.push(Opcode.enter_imm);
+    try self.bytecode.extend(&mem.toBytes(bytes));
+}
+
 fn compileJump(
     self: *Compiler,
     expr: *ast.Expression,
@@ -1402,22 +2610,14 @@ fn compileJump(
             return;
         },
         .identifier => |src| {
-            try self.bytecode.push(switch (op) {
-                .jmp => Opcode.jmp_imm,
-                .jeq => Opcode.jeq_imm,
-                .jne => Opcode.jne_imm,
-                .jlt => Opcode.jlt_imm,
-                .jgt => Opcode.jgt_imm,
-                .jle => Opcode.jle_imm,
-                .jge => Opcode.jge_imm,
-            });
-            const offset = self.bytecode.len(self.bytecode.current_section);
-            try self.fixups.put(
-                .{ .section = self.bytecode.current_section, .addr = offset },
-                .{ .size = .qword, .label = src, .span = span },
-            );
-            try self.bytecode.extend(&mem.toBytes(@as(u64, 0x00)));
-            return;
+            const forms: JumpForms = switch (op) {
+                inline else => |tag| .{
+                    .absolute = @field(Opcode, @tagName(tag) ++ "_imm"),
+                    .rel8 = @field(Opcode, @tagName(tag) ++ "_rel8"),
+                    .rel32 = @field(Opcode, @tagName(tag) ++ "_rel32"),
+                },
+            };
+            return self.emitLabelJump(forms, src, span);
         },
         else => {},
     }
@@ -1455,14 +2655,7 @@ fn compileCall(self: *Compiler, expr: *ast.Expression, span: Span) !void {
                 }
             }
 
-            try self.bytecode.push(Opcode.call_imm);
-            const offset = self.bytecode.len(self.bytecode.current_section);
-            try self.fixups.put(
-                .{ .section = self.bytecode.current_section, .addr = offset },
-                .{ .size = .qword, .label = src_id, .span = span },
-            );
-            try self.bytecode.extend(&mem.toBytes(@as(u64, 0x00)));
-            return;
+            return self.emitLabelJump(.{ .absolute = .call_imm, .rel8 = .call_rel8, .rel32 = .call_rel32 }, src_id, span);
         },
         else => {},
     }
@@ -1470,6 +2663,38 @@ fn compileCall(self: *Compiler, expr: *ast.Expression, span: Span) !void {
     return self.reportError("unsupported operand", span);
 }
 
+/// The encodings of one jump or call instruction with a label target.
+const JumpForms = struct {
+    absolute: Opcode,
+    rel8: Opcode,
+    rel32: Opcode,
+};
+
+/// Emit a jump or call to `label`, with a displacement from the end of the
+/// instruction when assembling a whole program and with the label's address
+/// otherwise.
+fn emitLabelJump(self: *Compiler, forms: JumpForms, label: StringId, span: Span) !void {
+    const section = self.bytecode.current_section;
+    const index = self.relative_jumps orelse {
+        try self.bytecode.push(forms.absolute);
+        try self.fixups.put(
+            .{ .section = section, .addr = self.bytecode.len(section) },
+            .{ .size = .qword, .label = label, .span = span },
+        );
+        try self.bytecode.extend(&mem.toBytes(@as(u64, 0x00)));
+        return;
+    };
+    self.relative_jumps = index + 1;
+
+    const size: DataSize = if (self.long_jumps.contains(index)) .dword else .byte;
+    try self.bytecode.push(if (size == .dword) forms.rel32 else forms.rel8);
+    try self.fixups.put(
+        .{ .section = section, .addr = self.bytecode.len(section) },
+        .{ .size = size, .label = label, .jump = index, .span = span },
+    );
+    try self.bytecode.grow(size.sizeInBytes());
+}
+
 fn compileCallVariadic(self: *Compiler, name_expr: *ast.Expression, variadic_types: []const FfiType, span: Span) !void {
     switch (name_expr.*) {
         .identifier => |src_id| {
@@ -1509,11 +2734,38 @@ fn compileCallVariadic(self: *Compiler, name_expr: *ast.Expression, variadic_typ
 fn compileIncOrDec(
     self: *Compiler,
     expr: *ast.Expression,
+    amount: ?*ast.Expression,
     op: enum { inc, dec },
     span: Span,
 ) !void {
     switch (expr.*) {
         .register => |src| {
+            try self.checkWritable(src, span);
+            if (amount) |amt| {
+                try self.bytecode.push(switch (op) {
+                    .inc => Opcode.inc_reg_imm,
+                    .dec => Opcode.dec_reg_imm,
+                });
+                try self.bytecode.push(src);
+                switch ((try self.unwrapLiteral(amt, DataSize.fromRegister(src), span)).*) {
+                    .integer_literal => |int| try self.bytecode.extend(switch (DataSize.fromRegister(src)) {
+                        .byte => &mem.toBytes(@as(u8, @truncate(@as(u64, @bitCast(int))))),
+                        .word => &mem.toBytes(@as(u16, @truncate(@as(u64, @bitCast(int))))),
+                        .dword => &mem.toBytes(@as(u32, @truncate(@as(u64, @bitCast(int))))),
+                        .qword => &mem.toBytes(@as(u64, @bitCast(int))),
+                        .float => &mem.toBytes(@as(f32, @floatFromInt(int))),
+                        .double => &mem.toBytes(@as(f64, @floatFromInt(int))),
+                    }),
+                    .float_literal => |flt| switch (DataSize.fromRegister(src)) {
+                        .float => try self.bytecode.extend(&mem.toBytes(@as(f32, @floatCast(flt)))),
+                        .double => try self.bytecode.extend(&mem.toBytes(@as(f64, @floatCast(flt)))),
+                        else => return self.reportError("float amount requires a floating-point register", span),
+                    },
+                    else => return self.reportError("amount must be an immediate value", span),
+                }
+                return;
+            }
+
             try self.bytecode.push(switch (op) {
                 .inc => Opcode.inc,
                 .dec => Opcode.dec,
@@ -1527,6 +2779,264 @@ fn compileIncOrDec(
     return self.reportError("unsupported operand", span);
 }
 
+/// Resolve a literal with an explicit width suffix against the size its
+/// operand is encoded with. Returns the plain literal, or `expr` unchanged if
+/// it has no suffix.
+fn unwrapLiteral(self: *Compiler, expr: *ast.Expression, expected: ?DataSize, span: Span) !*ast.Expression {
+    switch (expr.*) {
+        .sized_literal => |v| {
+            if (expected) |size| {
+                if (size != v.size) return self.reportError("literal width does not match operand size", span);
+            }
+            switch (v.value.*) {
+                .integer_literal => |int| {
+                    const fits = switch (v.size) {
+                        .byte => if (v.signed) std.math.cast(i8, int) != null else std.math.cast(u8, int) != null,
+                        .word => if (v.signed) std.math.cast(i16, int) != null else std.math.cast(u16, int) != null,
+                        .dword => if (v.signed) std.math.cast(i32, int) != null else std.math.cast(u32, int) != null,
+                        .qword => v.signed or int >= 0,
+                        .float, .double => true,
+                    };
+                    if (!fits) return self.reportError("literal does not fit in its width suffix", span);
+                },
+                else => {},
+            }
+            return v.value;
+        },
+        .integer_literal => |int| {
+            if (expected) |size| try self.checkImmediate(int, size, span);
+            return expr;
+        },
+        .unary_op => {
+            const folded = try self.foldUnary(expr) orelse return expr;
+            return self.unwrapLiteral(folded, expected, span);
+        },
+        else => return expr,
+    }
+}
+
+/// `expr` with `-` and `~` applied to literals and integer arithmetic folded
+/// like the preprocessor does, for statements assembled without it. Returns
+/// null if an operator applies to a label or a register, or negating
+/// overflows.
+fn foldUnary(self: *Compiler, expr: *ast.Expression) !?*ast.Expression {
+    const v = switch (expr.*) {
+        .unary_op => |v| v,
+        else => return expr,
+    };
+    const operand = try self.foldUnary(v.expr) orelse return null;
+    const folded = try self.arena.allocator().create(ast.Expression);
+    folded.* = switch (operand.*) {
+        .integer_literal => |int| .{ .integer_literal = v.op.apply(int, .qword, true) orelse return null },
+        .float_literal => |float| switch (v.op) {
+            .neg => .{ .float_literal = -float },
+            .bit_not => return null,
+        },
+        .sized_literal => |sized| blk: {
+            const value = try self.arena.allocator().create(ast.Expression);
+            value.* = switch (sized.value.*) {
+                .integer_literal => |int| .{ .integer_literal = v.op.apply(int, sized.size, sized.signed) orelse return null },
+                .float_literal => |float| switch (v.op) {
+                    .neg => .{ .float_literal = -float },
+                    .bit_not => return null,
+                },
+                else => return null,
+            };
+            var copy = sized;
+            copy.value = value;
+            copy.span = v.span;
+            break :blk .{ .sized_literal = copy };
+        },
+        // Integer arithmetic such as `-(2 + 3)`.
+        else => blk: {
+            const value = labelExpr(expr) orelse return null;
+            if (value.label != null or value.minus != null) return null;
+            break :blk .{ .integer_literal = value.addend };
+        },
+    };
+    return folded;
+}
+
+/// Smallest and largest integer that fits an operand of `size`, read as
+/// either signed or unsigned, or null if every integer does.
+fn immediateRange(size: DataSize) ?struct { i64, i64 } {
+    return switch (size) {
+        .byte => .{ std.math.minInt(i8), std.math.maxInt(u8) },
+        .word => .{ std.math.minInt(i16), std.math.maxInt(u16) },
+        .dword => .{ std.math.minInt(i32), std.math.maxInt(u32) },
+        .qword, .float, .double => null,
+    };
+}
+
+/// Check that `int` fits an operand of `size`. One that does not is an
+/// error, or with `truncate_immediates` outside strict mode a warning, and
+/// only its low bits are encoded.
+fn checkImmediate(self: *Compiler, int: i64, size: DataSize, span: Span) !void {
+    const min, const max = immediateRange(size) orelse return;
+    if (int >= min and int <= max) return;
+    if (self.truncate_immediates and !self.strict) {
+        // A statement repeated by `times` is only reported once.
+        if (self.truncations.getLastOrNull()) |last| {
+            if (last.span.start == span.start and last.span.filename.ptr == span.filename.ptr) return;
+        }
+        return self.truncations.append(.{ .value = int, .size = size, .span = span });
+    }
+    var buf: [128]u8 = undefined;
+    return self.reportError(immediateMessage(&buf, int, size), span);
+}
+
+/// Warn about every immediate truncated in the last pass.
+fn reportTruncations(self: *Compiler) void {
+    for (self.truncations.items) |truncation| {
+        var buf: [128]u8 = undefined;
+        self.warn(.truncated_immediate, immediateMessage(&buf, truncation.value, truncation.size), truncation.span);
+    }
+}
+
+fn immediateMessage(buf: []u8, int: i64, size: DataSize) []const u8 {
+    const min, const max = immediateRange(size).?;
+    return std.fmt.bufPrint(buf, "immediate {d} does not fit in a {s} operand ({d} to {d})", .{ int, @tagName(size), min, max }) catch
+        "immediate does not fit in the operand size";
+}
+
+fn literalSize(expr: *ast.Expression) ?DataSize {
+    return switch (expr.*) {
+        .sized_literal => |v| v.size,
+        else => null,
+    };
+}
+
+fn checkWritable(self: *Compiler, reg: Register, span: Span) !void {
+    switch (reg) {
+        .ip => return self.reportError("cannot write to ip directly, use jmp instead", span),
+        .msz, .brk, .isp => return self.reportError("cannot write to a read-only register", span),
+        else => {},
+    }
+}
+
+/// Reserve `size` bytes for the address of `label`, patched once labels
+/// are resolved.
+fn emitDataFixup(self: *Compiler, size: DataSize, label: StringId, span: Span) !void {
+    const addr = self.bytecode.len(self.bytecode.current_section);
+    try self.fixups.put(
+        .{ .section = self.bytecode.current_section, .addr = addr },
+        .{ .size = size, .label = label, .span = span },
+    );
+    try self.bytecode.grow(size.sizeInBytes());
+}
+
+/// Reserve `size` bytes for a sum of labels and integers that `labelExpr`
+/// reduces, such as `msg_end - msg` or `table + 8`, patched once labels are
+/// resolved. `size` must be an integer size.
+fn emitExprFixup(self: *Compiler, size: DataSize, expr: *const ast.Expression, span: Span) !void {
+    const value = labelExpr(expr) orelse
+        return self.reportError("expected labels and integers, like end - start or table + 8", span);
+    const label = value.label orelse {
+        if (value.minus != null) return self.reportError("cannot subtract a label from a constant", span);
+        // Integers the preprocessor did not fold, e.g. with it disabled.
+        try self.checkImmediate(value.addend, size, span);
+        const raw: u64 = @bitCast(value.addend);
+        return switch (size) {
+            .byte => self.bytecode.push(@as(u8, @truncate(raw))),
+            .word => self.bytecode.extend(&mem.toBytes(@as(u16, @truncate(raw)))),
+            .dword => self.bytecode.extend(&mem.toBytes(@as(u32, @truncate(raw)))),
+            .qword => self.bytecode.extend(&mem.toBytes(raw)),
+            .float, .double => unreachable,
+        };
+    };
+
+    const addr = self.bytecode.len(self.bytecode.current_section);
+    try self.fixups.put(
+        .{ .section = self.bytecode.current_section, .addr = addr },
+        .{ .size = size, .label = label, .minus = value.minus, .addend = value.addend, .span = span },
+    );
+    try self.bytecode.grow(size.sizeInBytes());
+}
+
+/// Reduce sums and differences of labels and integers to one label, minus
+/// at most one other label, plus a constant. Returns null for anything
+/// else, such as a register or a product involving a label.
+fn labelExpr(expr: *const ast.Expression) ?LabelExpr {
+    return switch (expr.*) {
+        .identifier => |name| .{ .label = name },
+        .integer_literal => |int| .{ .addend = int },
+        .binary_op => |v| blk: {
+            const lhs = labelExpr(v.lhs) orelse break :blk null;
+            const rhs = labelExpr(v.rhs) orelse break :blk null;
+            switch (v.op) {
+                .add => break :blk combine(lhs, rhs),
+                .sub => break :blk combine(lhs, negate(rhs) orelse break :blk null),
+                else => break :blk foldIntegers(v.op, lhs, rhs),
+            }
+        },
+        .unary_op => |v| applyUnary(v.op, labelExpr(v.expr) orelse return null),
+        else => null,
+    };
+}
+
+/// Apply `-` or `~` to a label expression. `~` only applies to integers.
+fn applyUnary(op: ast.Expression.UnaryOp.Op, operand: LabelExpr) ?LabelExpr {
+    return switch (op) {
+        .neg => negate(operand),
+        .bit_not => if (operand.label == null and operand.minus == null)
+            .{ .addend = ~operand.addend }
+        else
+            null,
+    };
+}
+
+/// Apply an operator other than `+` and `-` to two integers, like the
+/// preprocessor would. Returns null if either side has a label.
+fn foldIntegers(op: ast.Expression.BinaryOp.Op, lhs: LabelExpr, rhs: LabelExpr) ?LabelExpr {
+    if (lhs.label != null or lhs.minus != null or rhs.label != null or rhs.minus != null) return null;
+    const a = lhs.addend;
+    const b = rhs.addend;
+    return .{ .addend = switch (op) {
+        .mul => std.math.mul(i64, a, b) catch return null,
+        .div => if (b == 0) return null else @divTrunc(a, b),
+        .bit_or => a | b,
+        .bit_and => a & b,
+        .bit_xor => a ^ b,
+        .add, .sub => unreachable,
+    } };
+}
+
+fn negate(v: LabelExpr) ?LabelExpr {
+    return .{
+        .label = v.minus,
+        .minus = v.label,
+        .addend = std.math.negate(v.addend) catch return null,
+    };
+}
+
+fn combine(a: LabelExpr, b: LabelExpr) ?LabelExpr {
+    if (a.label != null and b.label != null) return null;
+    if (a.minus != null and b.minus != null) return null;
+    return .{
+        .label = a.label orelse b.label,
+        .minus = a.minus orelse b.minus,
+        .addend = std.math.add(i64, a.addend, b.addend) catch return null,
+    };
+}
+
+/// Bytes needed to bring `offset` up to a multiple of `alignment`.
+fn padding(offset: usize, alignment: usize) usize {
+    return mem.alignForward(usize, offset, alignment) - offset;
+}
+
+fn checkSectionSize(self: *Compiler, amount: usize, span: Span) !void {
+    const current = self.bytecode.len(self.bytecode.current_section);
+    if (amount <= self.max_section_size and current <= self.max_section_size - amount) return;
+
+    var buf: [256]u8 = undefined;
+    const message = std.fmt.bufPrint(
+        &buf,
+        "section size limit exceeded: requested {d} bytes with {d} already used, maximum is {d} bytes",
+        .{ amount, current, self.max_section_size },
+    ) catch "section size limit exceeded";
+    return self.reportError(message, span);
+}
+
 fn report(
     self: *Compiler,
     severity: fehler.Severity,
@@ -1534,17 +3044,24 @@ fn report(
     span: Span,
     status: ?u8,
 ) void {
-    const source = self.reporter.sources.get(span.filename).?;
-    self.reporter.report(.{
-        .severity = severity,
-        .message = message,
-        .range = span.toSourceRange(source),
-    });
+    span.report(self.reporter, severity, message);
     if (status) |code| {
-        process.exit(code);
+        if (self.exit_on_error) process.exit(code);
     }
 }
 
+/// Report a warning unless `code` is allowed, as an error when warnings are
+/// denied. `assemble` fails once every warning is out if any were denied.
+fn warn(self: *Compiler, code: warning.Code, message: []const u8, span: Span) void {
+    if (self.warnings.report(self.reporter, code, message, span)) self.warned = true;
+}
+
+/// Stop after diagnostics that were reported without an exit status.
+fn fail(self: *Compiler) error{CompilerError} {
+    if (self.exit_on_error) process.exit(1);
+    return error.CompilerError;
+}
+
 fn reportError(self: *Compiler, message: []const u8, span: Span) !void {
     self.report(.err, message, span, 1);
     return error.CompilerError;
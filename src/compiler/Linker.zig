@@ -0,0 +1,192 @@
+//! Merges relocatable objects into a bytecode image for `nyx link`.
+//!
+//! The text sections are placed one after another in the order given, then
+//! the data sections after all of the text, so the result has the same
+//! layout as compiling every source as one program. Each symbol gets its
+//! final address and every relocation is patched with it. A local symbol is
+//! only seen by relocations in its own object, so two objects may each
+//! define `main.loop`. Duplicate and undefined symbols and addresses too
+//! large for the bytes they go in are all reported before the link fails.
+//!
+//! `linkAt` places the text at a base address instead of 0, which
+//! `nyx run --aslr` picks at random so programs that hardcode addresses
+//! break the way they would under real ASLR.
+
+const std = @import("std");
+const Io = std.Io;
+const mem = std.mem;
+const Allocator = mem.Allocator;
+const ArrayList = std.array_list.Managed;
+const fehler = @import("fehler");
+const Object = @import("Object.zig");
+const Opcode = @import("opcode.zig").Opcode;
+const features = @import("../vm/features.zig");
+
+const Placed = struct {
+    symbol: *const Object.Symbol,
+    /// Offset in the merged section.
+    offset: usize,
+};
+
+/// Most bytes `randomBase` puts below the text.
+pub const max_random_base = 0x1000;
+
+/// Bytes below the text in an image linked with `linkAt`. No opcode has
+/// this number, so running them traps instead of sliding into the program.
+pub const base_fill: u8 = blk: {
+    @setEvalBranchQuota(100_000);
+    for (0..0x100) |value| {
+        if (Opcode.fromU8(@intCast(value))) |_| {} else |_| break :blk @intCast(value);
+    }
+    @compileError("every opcode number is in use");
+};
+
+/// A base for `linkAt` that is a random multiple of 16, at least 16 and at
+/// most `max_random_base`.
+pub fn randomBase(random: std.Random) usize {
+    return (random.uintAtMost(usize, max_random_base / 16 - 1) + 1) * 16;
+}
+
+/// Link `objects` and write the bytecode image to `writer`. Returns
+/// `error.LinkerError` after reporting every problem found.
+pub fn link(gpa: Allocator, objects: []const Object, reporter: *fehler.ErrorReporter, writer: *Io.Writer) !void {
+    return linkAt(gpa, objects, 0, reporter, writer);
+}
+
+/// Like `link`, but start the text `base` bytes into the image, filled with
+/// `base_fill` up to there, so every symbol and the default entry point
+/// move up by `base`.
+pub fn linkAt(gpa: Allocator, objects: []const Object, base: usize, reporter: *fehler.ErrorReporter, writer: *Io.Writer) !void {
+    var text = ArrayList(u8).init(gpa);
+    defer text.deinit();
+    try text.appendNTimes(base_fill, base);
+    var data = ArrayList(u8).init(gpa);
+    defer data.deinit();
+
+    const text_bases = try gpa.alloc(usize, objects.len);
+    defer gpa.free(text_bases);
+    const data_bases = try gpa.alloc(usize, objects.len);
+    defer gpa.free(data_bases);
+
+    var data_alignment: usize = 1;
+    var required = features.Set.initEmpty();
+    for (objects, 0..) |*object, i| {
+        try text.appendNTimes(Opcode.nop.intoU8(), padding(text.items.len, object.text_alignment));
+        text_bases[i] = text.items.len;
+        try text.appendSlice(object.text);
+
+        try data.appendNTimes(0x00, padding(data.items.len, object.data_alignment));
+        data_bases[i] = data.items.len;
+        try data.appendSlice(object.data);
+
+        data_alignment = @max(data_alignment, object.data_alignment);
+        required.setUnion(object.required);
+    }
+    // Data starts right after the text, so keep it on its largest alignment.
+    try text.appendNTimes(Opcode.nop.intoU8(), padding(text.items.len, data_alignment));
+
+    var failed = false;
+    var symbols = std.StringHashMap(Placed).init(gpa);
+    defer symbols.deinit();
+    const locals = try gpa.alloc(std.StringHashMap(Placed), objects.len);
+    defer gpa.free(locals);
+    for (locals) |*map| map.* = .init(gpa);
+    defer for (locals) |*map| map.deinit();
+    for (objects, 0..) |*object, i| {
+        for (object.symbols) |*symbol| {
+            const offset: usize = @intCast(symbol.offset);
+            const placed = Placed{ .symbol = symbol, .offset = switch (symbol.section) {
+                .text => text_bases[i] + offset,
+                .data => data_bases[i] + offset,
+            } };
+            const scope = switch (symbol.binding) {
+                .global => &symbols,
+                .local => &locals[i],
+            };
+            const result = try scope.getOrPut(symbol.name);
+            if (result.found_existing) {
+                report(reporter, .err, "{f}: duplicate symbol `{s}`", .{ symbol.location, symbol.name });
+                report(reporter, .note, "{f}: first defined here", .{result.value_ptr.symbol.location});
+                failed = true;
+                continue;
+            }
+            result.value_ptr.* = placed;
+        }
+    }
+
+    for (objects, 0..) |*object, i| {
+        for (object.relocations) |reloc| {
+            const target = locals[i].get(reloc.symbol) orelse symbols.get(reloc.symbol) orelse {
+                report(reporter, .err, "{f}: undefined symbol `{s}`", .{ reloc.location, reloc.symbol });
+                failed = true;
+                continue;
+            };
+            const addr = address(target, text.items.len) +% @as(u64, @bitCast(reloc.addend));
+            if (reloc.size < 8 and addr >> @intCast(8 * reloc.size) != 0) {
+                report(reporter, .err, "{f}: address 0x{x} of `{s}` does not fit in {d} bytes", .{ reloc.location, addr, reloc.symbol, reloc.size });
+                failed = true;
+                continue;
+            }
+            const offset: usize = @intCast(reloc.offset);
+            const bytes = switch (reloc.section) {
+                .text => text.items[text_bases[i] + offset ..],
+                .data => data.items[data_bases[i] + offset ..],
+            };
+            switch (reloc.size) {
+                1 => mem.writeInt(u8, bytes[0..1], @truncate(addr), .little),
+                2 => mem.writeInt(u16, bytes[0..2], @truncate(addr), .little),
+                4 => mem.writeInt(u32, bytes[0..4], @truncate(addr), .little),
+                8 => mem.writeInt(u64, bytes[0..8], addr, .little),
+                else => unreachable,
+            }
+        }
+    }
+
+    var entry: u64 = base;
+    var entry_object: ?*const Object = null;
+    for (objects, 0..) |*object, i| {
+        const object_entry = object.entry orelse continue;
+        if (entry_object) |first| {
+            report(reporter, .err, "{s}: another entry point, already set by {s}", .{ object.source, first.source });
+            failed = true;
+            continue;
+        }
+        entry_object = object;
+        entry = switch (object_entry) {
+            .address => |v| text_bases[i] + v,
+            .symbol => |ref| if (locals[i].get(ref.name) orelse symbols.get(ref.name)) |target|
+                address(target, text.items.len)
+            else blk: {
+                report(reporter, .err, "{f}: undefined symbol `{s}`", .{ ref.location, ref.name });
+                failed = true;
+                break :blk 0;
+            },
+        };
+    }
+
+    if (failed) return error.LinkerError;
+
+    try features.write(writer, entry, required, .{
+        .text_len = @intCast(text.items.len),
+        .data_len = @intCast(data.items.len),
+    });
+    try writer.writeAll(text.items);
+    try writer.writeAll(data.items);
+}
+
+fn address(target: Placed, text_len: usize) u64 {
+    return switch (target.symbol.section) {
+        .text => target.offset,
+        .data => text_len + target.offset,
+    };
+}
+
+fn padding(offset: usize, alignment: usize) usize {
+    return mem.alignForward(usize, offset, alignment) - offset;
+}
+
+fn report(reporter: *fehler.ErrorReporter, severity: fehler.Severity, comptime format: []const u8, args: anytype) void {
+    var buf: [512]u8 = undefined;
+    const message = std.fmt.bufPrint(&buf, format, args) catch "linker error";
+    reporter.report(.{ .severity = severity, .message = message });
+}
@@ -0,0 +1,257 @@
+//! Relocatable object files written by `nyx build --emit obj` and merged by
+//! `nyx link`.
+//!
+//! An object holds the text and data of one compilation before any label has
+//! an address: every label is listed in the symbol table with its offset in
+//! its own section, and every reference to a label is a relocation naming the
+//! symbol instead of a resolved fixup. Symbols and relocations keep the source
+//! location they came from so the linker can point at it.
+//!
+//! Layout, all integers little-endian:
+//!
+//! | Field | Encoding |
+//! |---|---|
+//! | magic | `NYXOBJ\x00\x03` |
+//! | source | main source file name |
+//! | required features | `u64`, the same bits as the bytecode header |
+//! | entry | `u8` kind (0 none, 1 address, 2 symbol), then a `u64` or a reference |
+//! | alignment | `u32` text, `u32` data |
+//! | text, data | `u32` length and the bytes |
+//! | symbols | `u32` count, each a name, `u8` section, `u8` binding (0 global, 1 local), `u64` offset and location |
+//! | relocations | `u32` count, each a `u8` section, `u64` offset, `u8` size, `i64` addend, symbol name and location |
+//!
+//! Strings are a `u32` length and the bytes; a location is a file name
+//! string, a `u32` line and a `u32` column.
+
+const std = @import("std");
+const Io = std.Io;
+const mem = std.mem;
+const Allocator = mem.Allocator;
+const Bytecode = @import("Bytecode.zig");
+const features = @import("../vm/features.zig");
+
+const Object = @This();
+
+pub const magic = "NYXOBJ\x00\x03";
+
+pub const Location = struct {
+    file: []const u8,
+    line: usize,
+    column: usize,
+
+    pub fn format(self: Location, writer: *Io.Writer) Io.Writer.Error!void {
+        try writer.print("{s}:{d}:{d}", .{ self.file, self.line, self.column });
+    }
+};
+
+pub const Symbol = struct {
+    name: []const u8,
+    section: Bytecode.Section,
+    binding: Binding = .global,
+    /// Offset from the start of `section` in this object.
+    offset: u64,
+    location: Location,
+};
+
+/// Who can refer to a symbol. A local label such as `.loop` is only visible
+/// to the object that defines it, so two objects may each have their own.
+pub const Binding = enum(u8) { global, local };
+
+pub const Relocation = struct {
+    section: Bytecode.Section,
+    /// Offset of the bytes to patch from the start of `section`.
+    offset: u64,
+    /// Number of bytes patched: 1, 2, 4 or 8.
+    size: u8,
+    /// Added to the symbol's address, for a reference like `buffer + 8`.
+    addend: i64 = 0,
+    symbol: []const u8,
+    location: Location,
+};
+
+pub const Entry = union(enum) {
+    /// Offset into this object's text section.
+    address: u64,
+    symbol: Reference,
+
+    pub const Reference = struct {
+        name: []const u8,
+        location: Location,
+    };
+};
+
+/// Owns every slice below.
+arena: std.heap.ArenaAllocator,
+/// Main source file the object was compiled from.
+source: []const u8,
+required: features.Set,
+entry: ?Entry,
+text: []const u8,
+data: []const u8,
+/// Largest `.align` in each section. The linker starts the section of this
+/// object on a multiple of it.
+text_alignment: usize,
+data_alignment: usize,
+symbols: []const Symbol,
+relocations: []const Relocation,
+
+pub fn deinit(self: *Object) void {
+    self.arena.deinit();
+}
+
+pub fn isObject(bytes: []const u8) bool {
+    return bytes.len >= magic.len and mem.eql(u8, bytes[0..magic.len], magic);
+}
+
+pub fn write(self: *const Object, writer: *Io.Writer) Io.Writer.Error!void {
+    try writer.writeAll(magic);
+    try writeString(writer, self.source);
+    try writer.writeInt(u64, features.encode(0, self.required), .little);
+    if (self.entry) |entry| switch (entry) {
+        .address => |addr| {
+            try writer.writeByte(1);
+            try writer.writeInt(u64, addr, .little);
+        },
+        .symbol => |ref| {
+            try writer.writeByte(2);
+            try writeString(writer, ref.name);
+            try writeLocation(writer, ref.location);
+        },
+    } else try writer.writeByte(0);
+    try writer.writeInt(u32, @intCast(self.text_alignment), .little);
+    try writer.writeInt(u32, @intCast(self.data_alignment), .little);
+    try writeString(writer, self.text);
+    try writeString(writer, self.data);
+
+    try writer.writeInt(u32, @intCast(self.symbols.len), .little);
+    for (self.symbols) |symbol| {
+        try writeString(writer, symbol.name);
+        try writer.writeByte(@intFromEnum(symbol.section));
+        try writer.writeByte(@intFromEnum(symbol.binding));
+        try writer.writeInt(u64, symbol.offset, .little);
+        try writeLocation(writer, symbol.location);
+    }
+
+    try writer.writeInt(u32, @intCast(self.relocations.len), .little);
+    for (self.relocations) |reloc| {
+        try writer.writeByte(@intFromEnum(reloc.section));
+        try writer.writeInt(u64, reloc.offset, .little);
+        try writer.writeByte(reloc.size);
+        try writer.writeInt(i64, reloc.addend, .little);
+        try writeString(writer, reloc.symbol);
+        try writeLocation(writer, reloc.location);
+    }
+}
+
+/// Decode an object written by `write`. Returns `error.InvalidObject` for
+/// anything else. The object copies what it needs out of `bytes`.
+pub fn read(gpa: Allocator, bytes: []const u8) !Object {
+    if (!isObject(bytes)) return error.InvalidObject;
+
+    var arena = std.heap.ArenaAllocator.init(gpa);
+    errdefer arena.deinit();
+    var decoder = Decoder{ .reader = .fixed(bytes[magic.len..]), .gpa = arena.allocator() };
+
+    const source = try decoder.string();
+    const header = features.decode(try decoder.int(u64));
+    if (header.entry != 0 or header.unknown != 0) return error.InvalidObject;
+    const entry: ?Entry = switch (try decoder.int(u8)) {
+        0 => null,
+        1 => .{ .address = try decoder.int(u64) },
+        2 => .{ .symbol = .{ .name = try decoder.string(), .location = try decoder.location() } },
+        else => return error.InvalidObject,
+    };
+    const text_alignment = try decoder.int(u32);
+    const data_alignment = try decoder.int(u32);
+    const text = try decoder.string();
+    const data = try decoder.string();
+
+    const symbols = try decoder.gpa.alloc(Symbol, try decoder.int(u32));
+    for (symbols) |*symbol| {
+        symbol.* = .{
+            .name = try decoder.string(),
+            .section = try decoder.section(),
+            .binding = std.enums.fromInt(Binding, try decoder.int(u8)) orelse return error.InvalidObject,
+            .offset = try decoder.int(u64),
+            .location = try decoder.location(),
+        };
+        if (symbol.offset > sectionLen(symbol.section, text, data)) return error.InvalidObject;
+    }
+
+    const relocations = try decoder.gpa.alloc(Relocation, try decoder.int(u32));
+    for (relocations) |*reloc| {
+        reloc.* = .{
+            .section = try decoder.section(),
+            .offset = try decoder.int(u64),
+            .size = try decoder.int(u8),
+            .addend = try decoder.int(i64),
+            .symbol = try decoder.string(),
+            .location = try decoder.location(),
+        };
+        switch (reloc.size) {
+            1, 2, 4, 8 => {},
+            else => return error.InvalidObject,
+        }
+        const len = sectionLen(reloc.section, text, data);
+        if (reloc.offset > len or reloc.size > len - reloc.offset) return error.InvalidObject;
+    }
+
+    if (decoder.reader.seek != decoder.reader.end) return error.InvalidObject;
+    for ([_]u32{ text_alignment, data_alignment }) |alignment| {
+        if (alignment == 0 or !std.math.isPowerOfTwo(alignment)) return error.InvalidObject;
+    }
+
+    return .{
+        .arena = arena,
+        .source = source,
+        .required = header.required,
+        .entry = entry,
+        .text = text,
+        .data = data,
+        .text_alignment = text_alignment,
+        .data_alignment = data_alignment,
+        .symbols = symbols,
+        .relocations = relocations,
+    };
+}
+
+fn sectionLen(section: Bytecode.Section, text: []const u8, data: []const u8) usize {
+    return switch (section) {
+        .text => text.len,
+        .data => data.len,
+    };
+}
+
+fn writeString(writer: *Io.Writer, bytes: []const u8) Io.Writer.Error!void {
+    try writer.writeInt(u32, @intCast(bytes.len), .little);
+    try writer.writeAll(bytes);
+}
+
+fn writeLocation(writer: *Io.Writer, location: Location) Io.Writer.Error!void {
+    try writeString(writer, location.file);
+    try writer.writeInt(u32, @intCast(location.line), .little);
+    try writer.writeInt(u32, @intCast(location.column), .little);
+}
+
+const Decoder = struct {
+    reader: Io.Reader,
+    gpa: Allocator,
+
+    fn int(self: *Decoder, comptime T: type) !T {
+        return self.reader.takeInt(T, .little) catch error.InvalidObject;
+    }
+
+    fn string(self: *Decoder) ![]const u8 {
+        const len = try self.int(u32);
+        const bytes = self.reader.take(len) catch return error.InvalidObject;
+        return self.gpa.dupe(u8, bytes);
+    }
+
+    fn section(self: *Decoder) !Bytecode.Section {
+        return std.enums.fromInt(Bytecode.Section, try self.int(u8)) orelse error.InvalidObject;
+    }
+
+    fn location(self: *Decoder) !Location {
+        return .{ .file = try self.string(), .line = try self.int(u32), .column = try self.int(u32) };
+    }
+};
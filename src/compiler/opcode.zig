@@ -1,5 +1,34 @@
 const std = @import("std");
 
+/// How an operand is laid out in the bytes following an opcode. The
+/// disassembler walks these so it cannot disagree with the VM about where an
+/// instruction ends.
+pub const Operand = enum {
+    /// A single register byte.
+    reg,
+    /// A data size byte.
+    size,
+    /// An immediate as wide as the first register operand.
+    imm,
+    /// An immediate as wide as the preceding size operand.
+    sized_imm,
+    /// An addressing variant byte, a register or qword base, and a qword offset.
+    addr,
+    /// A qword code address.
+    target,
+    /// A signed byte displacement from the end of the instruction to a code
+    /// address. Always the last operand, so the end is known once it is read.
+    rel8,
+    /// Like `rel8`, but a signed dword.
+    rel32,
+    /// A qword immediate, such as the frame size of `enter`.
+    qword,
+    /// A null-terminated function name followed by its FFI signature.
+    external,
+    /// A subcode byte, a length byte and that many bytes of operands.
+    extension,
+};
+
 pub const Opcode = enum(u8) {
     nop,
     mov_reg_reg,
@@ -106,6 +135,112 @@ pub const Opcode = enum(u8) {
     neg,
     syscall,
     hlt,
+    inc_reg_imm,
+    dec_reg_imm,
+    adc_reg_reg_reg,
+    adc_reg_reg_imm,
+    adc_reg_reg_addr,
+    adc_reg_addr_reg,
+    adc_reg_addr_imm,
+    adc_reg_addr_addr,
+    sbb_reg_reg_reg,
+    sbb_reg_reg_imm,
+    sbb_reg_reg_addr,
+    sbb_reg_addr_reg,
+    sbb_reg_addr_imm,
+    sbb_reg_addr_addr,
+    not_reg_reg,
+    not_reg_addr,
+    scmp_reg_reg,
+    schr_reg_reg_reg,
+    schr_reg_reg_imm,
+    seteq_reg,
+    setne_reg,
+    setlt_reg,
+    setgt_reg,
+    setle_reg,
+    setge_reg,
+    setc_reg,
+    seto_reg,
+    enter_imm,
+    leave,
+    cvtif_reg_reg,
+    cvtfi_reg_reg,
+    cvtfd_reg_reg,
+    cvtdf_reg_reg,
+    movz_reg_reg,
+    movs_reg_reg,
+    jmp_rel8,
+    jmp_rel32,
+    jeq_rel8,
+    jeq_rel32,
+    jne_rel8,
+    jne_rel32,
+    jlt_rel8,
+    jlt_rel32,
+    jgt_rel8,
+    jgt_rel32,
+    jle_rel8,
+    jle_rel32,
+    jge_rel8,
+    jge_rel32,
+    call_rel8,
+    call_rel32,
+    imul_reg_reg_reg,
+    imul_reg_reg_imm,
+    imul_reg_reg_addr,
+    imul_reg_addr_reg,
+    imul_reg_addr_imm,
+    imul_reg_addr_addr,
+    idiv_reg_reg_reg,
+    idiv_reg_reg_imm,
+    idiv_reg_reg_addr,
+    idiv_reg_addr_reg,
+    idiv_reg_addr_imm,
+    idiv_reg_addr_addr,
+    imod_reg_reg_reg,
+    imod_reg_reg_imm,
+    imod_reg_reg_addr,
+    imod_reg_addr_reg,
+    imod_reg_addr_imm,
+    imod_reg_addr_addr,
+
+    // New opcodes go above this line.
+
+    /// Prefix of an extended instruction. The next byte is a subcode picking
+    /// the instruction and the byte after it is the length of its operands,
+    /// so tools that do not know a subcode can still step over it.
+    extended = 0xFF,
+
+    /// Extension subcodes below this are reserved for future core
+    /// instructions. Forks and experimental features take theirs from here up.
+    pub const first_custom_subcode: u8 = 0x80;
+
+    /// An opcode number from an older toolchain that now means `opcode`,
+    /// with the same operands. Old bytecode keeps running, with a warning,
+    /// until `nyx migrate` rewrites it.
+    pub const Alias = struct {
+        value: u8,
+        opcode: Opcode,
+    };
+
+    /// Every retired opcode number. Add an entry whenever an opcode is
+    /// renumbered, and never give a retired number to a new opcode. Empty
+    /// until the first renumbering; tests exercise the mechanism with their
+    /// own table.
+    pub const aliases = [_]Alias{};
+
+    comptime {
+        for (aliases) |alias| {
+            if (fromU8(alias.value)) |_| @compileError("retired opcode number is in use again") else |_| {}
+        }
+    }
+
+    /// The address a relative jump or call lands on, `displacement` bytes
+    /// from `end`, the address right after the instruction.
+    pub fn relativeTarget(end: u64, displacement: i32) u64 {
+        return end +% @as(u64, @bitCast(@as(i64, displacement)));
+    }
 
     pub fn intoU8(self: Opcode) u8 {
         return @intFromEnum(self);
@@ -216,10 +351,121 @@ pub const Opcode = enum(u8) {
             @intFromEnum(Opcode.neg) => .neg,
             @intFromEnum(Opcode.syscall) => .syscall,
             @intFromEnum(Opcode.hlt) => .hlt,
+            @intFromEnum(Opcode.inc_reg_imm) => .inc_reg_imm,
+            @intFromEnum(Opcode.dec_reg_imm) => .dec_reg_imm,
+            @intFromEnum(Opcode.adc_reg_reg_reg) => .adc_reg_reg_reg,
+            @intFromEnum(Opcode.adc_reg_reg_imm) => .adc_reg_reg_imm,
+            @intFromEnum(Opcode.adc_reg_reg_addr) => .adc_reg_reg_addr,
+            @intFromEnum(Opcode.adc_reg_addr_reg) => .adc_reg_addr_reg,
+            @intFromEnum(Opcode.adc_reg_addr_imm) => .adc_reg_addr_imm,
+            @intFromEnum(Opcode.adc_reg_addr_addr) => .adc_reg_addr_addr,
+            @intFromEnum(Opcode.sbb_reg_reg_reg) => .sbb_reg_reg_reg,
+            @intFromEnum(Opcode.sbb_reg_reg_imm) => .sbb_reg_reg_imm,
+            @intFromEnum(Opcode.sbb_reg_reg_addr) => .sbb_reg_reg_addr,
+            @intFromEnum(Opcode.sbb_reg_addr_reg) => .sbb_reg_addr_reg,
+            @intFromEnum(Opcode.sbb_reg_addr_imm) => .sbb_reg_addr_imm,
+            @intFromEnum(Opcode.sbb_reg_addr_addr) => .sbb_reg_addr_addr,
+            @intFromEnum(Opcode.not_reg_reg) => .not_reg_reg,
+            @intFromEnum(Opcode.not_reg_addr) => .not_reg_addr,
+            @intFromEnum(Opcode.scmp_reg_reg) => .scmp_reg_reg,
+            @intFromEnum(Opcode.schr_reg_reg_reg) => .schr_reg_reg_reg,
+            @intFromEnum(Opcode.schr_reg_reg_imm) => .schr_reg_reg_imm,
+            @intFromEnum(Opcode.seteq_reg) => .seteq_reg,
+            @intFromEnum(Opcode.setne_reg) => .setne_reg,
+            @intFromEnum(Opcode.setlt_reg) => .setlt_reg,
+            @intFromEnum(Opcode.setgt_reg) => .setgt_reg,
+            @intFromEnum(Opcode.setle_reg) => .setle_reg,
+            @intFromEnum(Opcode.setge_reg) => .setge_reg,
+            @intFromEnum(Opcode.setc_reg) => .setc_reg,
+            @intFromEnum(Opcode.seto_reg) => .seto_reg,
+            @intFromEnum(Opcode.enter_imm) => .enter_imm,
+            @intFromEnum(Opcode.leave) => .leave,
+            @intFromEnum(Opcode.cvtif_reg_reg) => .cvtif_reg_reg,
+            @intFromEnum(Opcode.cvtfi_reg_reg) => .cvtfi_reg_reg,
+            @intFromEnum(Opcode.cvtfd_reg_reg) => .cvtfd_reg_reg,
+            @intFromEnum(Opcode.cvtdf_reg_reg) => .cvtdf_reg_reg,
+            @intFromEnum(Opcode.movz_reg_reg) => .movz_reg_reg,
+            @intFromEnum(Opcode.movs_reg_reg) => .movs_reg_reg,
+            @intFromEnum(Opcode.jmp_rel8) => .jmp_rel8,
+            @intFromEnum(Opcode.jmp_rel32) => .jmp_rel32,
+            @intFromEnum(Opcode.jeq_rel8) => .jeq_rel8,
+            @intFromEnum(Opcode.jeq_rel32) => .jeq_rel32,
+            @intFromEnum(Opcode.jne_rel8) => .jne_rel8,
+            @intFromEnum(Opcode.jne_rel32) => .jne_rel32,
+            @intFromEnum(Opcode.jlt_rel8) => .jlt_rel8,
+            @intFromEnum(Opcode.jlt_rel32) => .jlt_rel32,
+            @intFromEnum(Opcode.jgt_rel8) => .jgt_rel8,
+            @intFromEnum(Opcode.jgt_rel32) => .jgt_rel32,
+            @intFromEnum(Opcode.jle_rel8) => .jle_rel8,
+            @intFromEnum(Opcode.jle_rel32) => .jle_rel32,
+            @intFromEnum(Opcode.jge_rel8) => .jge_rel8,
+            @intFromEnum(Opcode.jge_rel32) => .jge_rel32,
+            @intFromEnum(Opcode.call_rel8) => .call_rel8,
+            @intFromEnum(Opcode.call_rel32) => .call_rel32,
+            @intFromEnum(Opcode.imul_reg_reg_reg) => .imul_reg_reg_reg,
+            @intFromEnum(Opcode.imul_reg_reg_imm) => .imul_reg_reg_imm,
+            @intFromEnum(Opcode.imul_reg_reg_addr) => .imul_reg_reg_addr,
+            @intFromEnum(Opcode.imul_reg_addr_reg) => .imul_reg_addr_reg,
+            @intFromEnum(Opcode.imul_reg_addr_imm) => .imul_reg_addr_imm,
+            @intFromEnum(Opcode.imul_reg_addr_addr) => .imul_reg_addr_addr,
+            @intFromEnum(Opcode.idiv_reg_reg_reg) => .idiv_reg_reg_reg,
+            @intFromEnum(Opcode.idiv_reg_reg_imm) => .idiv_reg_reg_imm,
+            @intFromEnum(Opcode.idiv_reg_reg_addr) => .idiv_reg_reg_addr,
+            @intFromEnum(Opcode.idiv_reg_addr_reg) => .idiv_reg_addr_reg,
+            @intFromEnum(Opcode.idiv_reg_addr_imm) => .idiv_reg_addr_imm,
+            @intFromEnum(Opcode.idiv_reg_addr_addr) => .idiv_reg_addr_addr,
+            @intFromEnum(Opcode.imod_reg_reg_reg) => .imod_reg_reg_reg,
+            @intFromEnum(Opcode.imod_reg_reg_imm) => .imod_reg_reg_imm,
+            @intFromEnum(Opcode.imod_reg_reg_addr) => .imod_reg_reg_addr,
+            @intFromEnum(Opcode.imod_reg_addr_reg) => .imod_reg_addr_reg,
+            @intFromEnum(Opcode.imod_reg_addr_imm) => .imod_reg_addr_imm,
+            @intFromEnum(Opcode.imod_reg_addr_addr) => .imod_reg_addr_addr,
+            @intFromEnum(Opcode.extended) => .extended,
             else => error.InvalidOpcode,
         };
     }
 
+    /// Like `fromU8`, but also accepts the retired numbers in `table`.
+    pub fn fromU8OrAlias(value: u8, table: []const Alias) !Opcode {
+        return fromU8(value) catch for (table) |alias| {
+            if (alias.value == value) break alias.opcode;
+        } else error.InvalidOpcode;
+    }
+
+    /// Operands in the order they are encoded. Memory destinations of `mov`
+    /// are encoded after their source.
+    pub fn operands(self: Opcode) []const Operand {
+        return switch (self) {
+            .nop, .ret, .leave, .syscall, .hlt => &.{},
+            .mov_reg_reg, .cmp_reg_reg, .not_reg_reg, .scmp_reg_reg => &.{ .reg, .reg },
+            .cvtif_reg_reg, .cvtfi_reg_reg, .cvtfd_reg_reg, .cvtdf_reg_reg, .movz_reg_reg, .movs_reg_reg => &.{ .reg, .reg },
+            .mov_reg_imm, .cmp_reg_imm, .inc_reg_imm, .dec_reg_imm => &.{ .reg, .imm },
+            .mov_reg_addr, .mov_addr_reg, .not_reg_addr => &.{ .reg, .addr },
+            .mov_addr_imm => &.{ .size, .sized_imm, .addr },
+            .mov_addr_addr => &.{ .size, .addr, .addr },
+            .push_imm => &.{ .size, .sized_imm },
+            .push_reg, .pop_reg => &.{ .size, .reg },
+            .push_addr, .pop_addr => &.{ .size, .addr },
+            .schr_reg_reg_reg => &.{ .reg, .reg, .reg },
+            .schr_reg_reg_imm => &.{ .reg, .reg, .imm },
+            .add_reg_reg_reg, .adc_reg_reg_reg, .sub_reg_reg_reg, .sbb_reg_reg_reg, .mul_reg_reg_reg, .div_reg_reg_reg, .imul_reg_reg_reg, .idiv_reg_reg_reg, .imod_reg_reg_reg, .and_reg_reg_reg, .or_reg_reg_reg, .xor_reg_reg_reg, .shl_reg_reg_reg, .shr_reg_reg_reg, .rol_reg_reg_reg, .ror_reg_reg_reg => &.{ .reg, .reg, .reg },
+            .add_reg_reg_imm, .adc_reg_reg_imm, .sub_reg_reg_imm, .sbb_reg_reg_imm, .mul_reg_reg_imm, .div_reg_reg_imm, .imul_reg_reg_imm, .idiv_reg_reg_imm, .imod_reg_reg_imm, .and_reg_reg_imm, .or_reg_reg_imm, .xor_reg_reg_imm, .shl_reg_reg_imm, .shr_reg_reg_imm, .rol_reg_reg_imm, .ror_reg_reg_imm => &.{ .reg, .reg, .imm },
+            .add_reg_reg_addr, .adc_reg_reg_addr, .sub_reg_reg_addr, .sbb_reg_reg_addr, .mul_reg_reg_addr, .div_reg_reg_addr, .imul_reg_reg_addr, .idiv_reg_reg_addr, .imod_reg_reg_addr, .and_reg_reg_addr, .or_reg_reg_addr, .xor_reg_reg_addr, .shl_reg_reg_addr, .shr_reg_reg_addr, .rol_reg_reg_addr, .ror_reg_reg_addr => &.{ .reg, .reg, .addr },
+            .add_reg_addr_reg, .adc_reg_addr_reg, .sub_reg_addr_reg, .sbb_reg_addr_reg, .mul_reg_addr_reg, .div_reg_addr_reg, .imul_reg_addr_reg, .idiv_reg_addr_reg, .imod_reg_addr_reg, .and_reg_addr_reg, .or_reg_addr_reg, .xor_reg_addr_reg, .shl_reg_addr_reg, .shr_reg_addr_reg, .rol_reg_addr_reg, .ror_reg_addr_reg => &.{ .reg, .addr, .reg },
+            .add_reg_addr_imm, .adc_reg_addr_imm, .sub_reg_addr_imm, .sbb_reg_addr_imm, .mul_reg_addr_imm, .div_reg_addr_imm, .imul_reg_addr_imm, .idiv_reg_addr_imm, .imod_reg_addr_imm, .and_reg_addr_imm, .or_reg_addr_imm, .xor_reg_addr_imm, .shl_reg_addr_imm, .shr_reg_addr_imm, .rol_reg_addr_imm, .ror_reg_addr_imm => &.{ .reg, .addr, .imm },
+            .add_reg_addr_addr, .adc_reg_addr_addr, .sub_reg_addr_addr, .sbb_reg_addr_addr, .mul_reg_addr_addr, .div_reg_addr_addr, .imul_reg_addr_addr, .idiv_reg_addr_addr, .imod_reg_addr_addr, .and_reg_addr_addr, .or_reg_addr_addr, .xor_reg_addr_addr, .shl_reg_addr_addr, .shr_reg_addr_addr, .rol_reg_addr_addr, .ror_reg_addr_addr => &.{ .reg, .addr, .addr },
+            .jmp_imm, .jeq_imm, .jne_imm, .jlt_imm, .jgt_imm, .jle_imm, .jge_imm, .call_imm => &.{.target},
+            .jmp_reg, .jeq_reg, .jne_reg, .jlt_reg, .jgt_reg, .jle_reg, .jge_reg, .call_reg => &.{.reg},
+            .jmp_rel8, .jeq_rel8, .jne_rel8, .jlt_rel8, .jgt_rel8, .jle_rel8, .jge_rel8, .call_rel8 => &.{.rel8},
+            .jmp_rel32, .jeq_rel32, .jne_rel32, .jlt_rel32, .jgt_rel32, .jle_rel32, .jge_rel32, .call_rel32 => &.{.rel32},
+            .seteq_reg, .setne_reg, .setlt_reg, .setgt_reg, .setle_reg, .setge_reg, .setc_reg, .seto_reg => &.{.reg},
+            .enter_imm => &.{.qword},
+            .call_ex => &.{.external},
+            .inc, .dec, .neg => &.{.reg},
+            .extended => &.{.extension},
+        };
+    }
+
     pub fn format(
         self: Opcode,
         writer: *std.Io.Writer,
@@ -227,34 +473,59 @@ pub const Opcode = enum(u8) {
         try writer.print("{s}", switch (self) {
             .nop => "nop",
             .mov_reg_reg, .mov_reg_imm, .mov_reg_addr, .mov_addr_reg, .mov_addr_imm, .mov_addr_addr => "mov",
+            .movz_reg_reg => "movz",
+            .movs_reg_reg => "movs",
             .push_imm, .push_reg, .push_addr => "push",
             .pop_reg, .pop_addr => "pop",
             .add_reg_reg_reg, .add_reg_reg_imm, .add_reg_reg_addr, .add_reg_addr_reg, .add_reg_addr_imm, .add_reg_addr_addr => "add",
+            .adc_reg_reg_reg, .adc_reg_reg_imm, .adc_reg_reg_addr, .adc_reg_addr_reg, .adc_reg_addr_imm, .adc_reg_addr_addr => "adc",
             .sub_reg_reg_reg, .sub_reg_reg_imm, .sub_reg_reg_addr, .sub_reg_addr_reg, .sub_reg_addr_imm, .sub_reg_addr_addr => "sub",
+            .sbb_reg_reg_reg, .sbb_reg_reg_imm, .sbb_reg_reg_addr, .sbb_reg_addr_reg, .sbb_reg_addr_imm, .sbb_reg_addr_addr => "sbb",
             .mul_reg_reg_reg, .mul_reg_reg_imm, .mul_reg_reg_addr, .mul_reg_addr_reg, .mul_reg_addr_imm, .mul_reg_addr_addr => "mul",
             .div_reg_reg_reg, .div_reg_reg_imm, .div_reg_reg_addr, .div_reg_addr_reg, .div_reg_addr_imm, .div_reg_addr_addr => "div",
+            .imul_reg_reg_reg, .imul_reg_reg_imm, .imul_reg_reg_addr, .imul_reg_addr_reg, .imul_reg_addr_imm, .imul_reg_addr_addr => "imul",
+            .idiv_reg_reg_reg, .idiv_reg_reg_imm, .idiv_reg_reg_addr, .idiv_reg_addr_reg, .idiv_reg_addr_imm, .idiv_reg_addr_addr => "idiv",
+            .imod_reg_reg_reg, .imod_reg_reg_imm, .imod_reg_reg_addr, .imod_reg_addr_reg, .imod_reg_addr_imm, .imod_reg_addr_addr => "imod",
             .and_reg_reg_reg, .and_reg_reg_imm, .and_reg_reg_addr, .and_reg_addr_reg, .and_reg_addr_imm, .and_reg_addr_addr => "and",
             .or_reg_reg_reg, .or_reg_reg_imm, .or_reg_reg_addr, .or_reg_addr_reg, .or_reg_addr_imm, .or_reg_addr_addr => "or",
             .xor_reg_reg_reg, .xor_reg_reg_imm, .xor_reg_reg_addr, .xor_reg_addr_reg, .xor_reg_addr_imm, .xor_reg_addr_addr => "xor",
+            .not_reg_reg, .not_reg_addr => "not",
             .shl_reg_reg_reg, .shl_reg_reg_imm, .shl_reg_reg_addr, .shl_reg_addr_reg, .shl_reg_addr_imm, .shl_reg_addr_addr => "shl",
             .shr_reg_reg_reg, .shr_reg_reg_imm, .shr_reg_reg_addr, .shr_reg_addr_reg, .shr_reg_addr_imm, .shr_reg_addr_addr => "shr",
             .rol_reg_reg_reg, .rol_reg_reg_imm, .rol_reg_reg_addr, .rol_reg_addr_reg, .rol_reg_addr_imm, .rol_reg_addr_addr => "rol",
             .ror_reg_reg_reg, .ror_reg_reg_imm, .ror_reg_reg_addr, .ror_reg_addr_reg, .ror_reg_addr_imm, .ror_reg_addr_addr => "ror",
             .cmp_reg_imm, .cmp_reg_reg => "cmp",
-            .jmp_imm, .jmp_reg => "jmp",
-            .jeq_imm, .jeq_reg => "jeq",
-            .jne_imm, .jne_reg => "jne",
-            .jlt_imm, .jlt_reg => "jlt",
-            .jgt_imm, .jgt_reg => "jgt",
-            .jle_imm, .jle_reg => "jle",
-            .jge_imm, .jge_reg => "jge",
-            .call_imm, .call_reg, .call_ex => "call",
+            .scmp_reg_reg => "scmp",
+            .schr_reg_reg_reg, .schr_reg_reg_imm => "schr",
+            .seteq_reg => "seteq",
+            .setne_reg => "setne",
+            .setlt_reg => "setlt",
+            .setgt_reg => "setgt",
+            .setle_reg => "setle",
+            .setge_reg => "setge",
+            .setc_reg => "setc",
+            .seto_reg => "seto",
+            .jmp_imm, .jmp_reg, .jmp_rel8, .jmp_rel32 => "jmp",
+            .jeq_imm, .jeq_reg, .jeq_rel8, .jeq_rel32 => "jeq",
+            .jne_imm, .jne_reg, .jne_rel8, .jne_rel32 => "jne",
+            .jlt_imm, .jlt_reg, .jlt_rel8, .jlt_rel32 => "jlt",
+            .jgt_imm, .jgt_reg, .jgt_rel8, .jgt_rel32 => "jgt",
+            .jle_imm, .jle_reg, .jle_rel8, .jle_rel32 => "jle",
+            .jge_imm, .jge_reg, .jge_rel8, .jge_rel32 => "jge",
+            .call_imm, .call_reg, .call_rel8, .call_rel32, .call_ex => "call",
             .ret => "ret",
-            .inc => "inc",
-            .dec => "dec",
+            .enter_imm => "enter",
+            .leave => "leave",
+            .inc, .inc_reg_imm => "inc",
+            .dec, .dec_reg_imm => "dec",
             .neg => "neg",
+            .cvtif_reg_reg => "cvtif",
+            .cvtfi_reg_reg => "cvtfi",
+            .cvtfd_reg_reg => "cvtfd",
+            .cvtdf_reg_reg => "cvtdf",
             .syscall => "syscall",
             .hlt => "hlt",
+            .extended => "ext",
         });
     }
 };
@@ -0,0 +1,207 @@
+//! Optional passes over the preprocessed program, run by the compiler
+//! before any code is emitted.
+
+const std = @import("std");
+const ast = @import("../parser/ast.zig");
+const Register = @import("../vm/register.zig").Register;
+
+const Statement = ast.Statement;
+const Expression = ast.Expression;
+
+/// Fold a constant loaded into a register straight into the address that
+/// uses it, e.g.
+///
+///     mov q1, BUFFER          ->      mov q0, [BUFFER]
+///     mov q0, [q1]
+///
+/// The load is only removed when the register is dead afterwards: it is
+/// overwritten before anything reads it, or the program halts first. Any
+/// label, jump, call or syscall ends the search and keeps the load.
+/// Returns the program with removed statements compacted out.
+pub fn foldAddressConstants(program: []Statement) []Statement {
+    var len = program.len;
+    var i: usize = 0;
+    while (i + 1 < len) {
+        if (tryFold(program[i..len])) {
+            std.mem.copyForwards(Statement, program[i .. len - 1], program[i + 1 .. len]);
+            len -= 1;
+        } else {
+            i += 1;
+        }
+    }
+    return program[0..len];
+}
+
+/// Fold `stmts[0]` into `stmts[1]` if possible. Returns true when the load
+/// in `stmts[0]` can be removed.
+fn tryFold(stmts: []Statement) bool {
+    const load = switch (stmts[0]) {
+        .mov => |v| v,
+        else => return false,
+    };
+    if (load.data_size != null) return false;
+    const reg = switch (load.expr1.*) {
+        .register => |r| r,
+        else => return false,
+    };
+    const info = reg.physicalInfo();
+    if (info.type != .general_purpose or info.view != .qword) return false;
+    switch (load.expr2.*) {
+        .integer_literal, .identifier => {},
+        else => return false,
+    }
+
+    const use = stmts[1];
+    if (!isInstruction(use) or countBases(use, reg) == 0) return false;
+    if (readsOutsideBases(use, reg)) return false;
+    if (!kills(use, reg) and isLive(stmts[2..], reg)) return false;
+
+    replaceBases(use, reg, load.expr2);
+    return true;
+}
+
+/// Whether `reg` may be read by `stmts` before it is overwritten.
+fn isLive(stmts: []const Statement, reg: Register) bool {
+    for (stmts) |stmt| {
+        switch (stmt) {
+            .hlt => return false,
+            .label,
+            .section,
+            .jmp,
+            .jne,
+            .jeq,
+            .jlt,
+            .jgt,
+            .jle,
+            .jge,
+            .call,
+            .call_variadic,
+            .ret,
+            .syscall,
+            .ext,
+            .times,
+            => return true,
+            else => {},
+        }
+        if (readsOutsideBases(stmt, reg) or countBases(stmt, reg) > 0) return true;
+        if (kills(stmt, reg)) return false;
+    }
+    return true;
+}
+
+fn isInstruction(stmt: Statement) bool {
+    return switch (stmt) {
+        .mov, .push, .add, .adc, .sub, .sbb, .mul, .div, .imul, .idiv, .imod, .@"and", .@"or", .xor, .not, .shl, .shr, .rol, .ror, .cmp, .inc, .dec, .neg => true,
+        else => false,
+    };
+}
+
+/// Whether `stmt` overwrites all of `reg` without reading it.
+fn kills(stmt: Statement, reg: Register) bool {
+    const dest = switch (stmt) {
+        .mov => |v| v.expr1,
+        .pop => |v| v.expr,
+        else => return false,
+    };
+    return switch (dest.*) {
+        .register => |r| r == reg,
+        else => false,
+    };
+}
+
+fn sameRegister(a: Register, b: Register) bool {
+    const x = a.physicalInfo();
+    const y = b.physicalInfo();
+    return x.type == y.type and x.index == y.index;
+}
+
+/// Whether `stmt` reads `reg` anywhere other than as the base of an
+/// address, ignoring a destination that `kills` it.
+fn readsOutsideBases(stmt: Statement, reg: Register) bool {
+    switch (stmt) {
+        inline else => |v| {
+            const T = @TypeOf(v);
+            if (@typeInfo(T) != .@"struct") return false;
+            inline for (@typeInfo(T).@"struct".fields) |field| {
+                const value = @field(v, field.name);
+                const is_dest = comptime (T == Statement.Mov and std.mem.eql(u8, field.name, "expr1")) or
+                    (T == Statement.PushPop and std.mem.eql(u8, field.name, "expr"));
+                switch (field.type) {
+                    *Expression => if (is_dest and kills(stmt, reg)) {} else if (exprReads(value, reg)) return true,
+                    ?*Expression => if (value) |e| if (exprReads(e, reg)) return true,
+                    []*Expression => for (value) |e| if (exprReads(e, reg)) return true,
+                    else => {},
+                }
+            }
+            return false;
+        },
+    }
+}
+
+fn exprReads(expr: *const Expression, reg: Register) bool {
+    return switch (expr.*) {
+        .register => |r| sameRegister(r, reg),
+        .address => |a| (if (a.base.* == .register and a.base.register == reg) false else exprReads(a.base, reg)) or
+            (if (a.offset) |o| exprReads(o, reg) else false),
+        .unary_op => |u| exprReads(u.expr, reg),
+        .binary_op => |b| exprReads(b.lhs, reg) or exprReads(b.rhs, reg),
+        .sized_literal => |s| exprReads(s.value, reg),
+        else => false,
+    };
+}
+
+/// Call `f` on every top-level operand of `stmt`.
+fn forEachOperand(stmt: Statement, context: anytype, comptime f: fn (@TypeOf(context), *Expression) void) void {
+    switch (stmt) {
+        inline else => |v| {
+            const T = @TypeOf(v);
+            if (@typeInfo(T) != .@"struct") return;
+            inline for (@typeInfo(T).@"struct".fields) |field| {
+                const value = @field(v, field.name);
+                switch (field.type) {
+                    *Expression => f(context, value),
+                    ?*Expression => if (value) |e| f(context, e),
+                    []*Expression => for (value) |e| f(context, e),
+                    else => {},
+                }
+            }
+        },
+    }
+}
+
+fn countBases(stmt: Statement, reg: Register) usize {
+    const Counter = struct {
+        reg: Register,
+        count: usize = 0,
+
+        fn visit(self: *@This(), expr: *Expression) void {
+            switch (expr.*) {
+                .address => |a| if (a.base.* == .register and a.base.register == self.reg) {
+                    self.count += 1;
+                },
+                else => {},
+            }
+        }
+    };
+    var counter: Counter = .{ .reg = reg };
+    forEachOperand(stmt, &counter, Counter.visit);
+    return counter.count;
+}
+
+fn replaceBases(stmt: Statement, reg: Register, constant: *Expression) void {
+    const Replacer = struct {
+        reg: Register,
+        constant: *Expression,
+
+        fn visit(self: *const @This(), expr: *Expression) void {
+            switch (expr.*) {
+                .address => |*a| if (a.base.* == .register and a.base.register == self.reg) {
+                    a.base = self.constant;
+                },
+                else => {},
+            }
+        }
+    };
+    const replacer: Replacer = .{ .reg = reg, .constant = constant };
+    forEachOperand(stmt, &replacer, Replacer.visit);
+}
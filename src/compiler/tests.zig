@@ -0,0 +1,1179 @@
+const std = @import("std");
+const testing = std.testing;
+const Io = std.Io;
+const fehler = @import("fehler");
+const StringInterner = @import("../StringInterner.zig");
+const Lexer = @import("../lexer/Lexer.zig");
+const Parser = @import("../parser/Parser.zig");
+const Compiler = @import("Compiler.zig");
+const nyx = @import("../root.zig");
+const Manifest = @import("../Manifest.zig");
+const build_options = @import("build_options");
+
+/// Lexer, parser and compiler for a single source string. Lives in place
+/// because each stage keeps pointers to the previous ones.
+const Fixture = struct {
+    reporter: fehler.ErrorReporter,
+    interner: StringInterner,
+    lexer: Lexer,
+    parser: Parser,
+    compiler: Compiler,
+
+    fn init(self: *Fixture, input: []const u8) !void {
+        const gpa = testing.allocator;
+        self.reporter = .init(gpa);
+        try self.reporter.addSource("test.nyx", input);
+        self.interner = .init(gpa);
+        self.lexer = .init("test.nyx", input, &self.interner, gpa);
+        self.parser = .init(&self.lexer, &self.reporter, gpa);
+        self.compiler = try .init(try self.parser.parse(), &self.interner, "test.nyx", input, &self.reporter, gpa);
+    }
+
+    fn deinit(self: *Fixture) void {
+        self.compiler.deinit();
+        self.parser.deinit();
+        self.interner.deinit();
+        self.reporter.deinit();
+    }
+};
+
+test "size report by label and section" {
+    var f: Fixture = undefined;
+    try f.init(
+        \\.section text
+        \\_start:
+        \\    mov q0, 1
+        \\    call helper
+        \\    hlt
+        \\helper:
+        \\    ret
+        \\.section data
+        \\message:
+        \\    db "hello", 0
+    );
+    defer f.deinit();
+
+    const bytecode = try f.compiler.compile();
+    defer testing.allocator.free(bytecode);
+
+    const sizes = try f.compiler.labelSizes();
+    defer testing.allocator.free(sizes);
+
+    try testing.expectEqual(@as(usize, 3), sizes.len);
+    try testing.expectEqualStrings("_start", sizes[0].name.?);
+    try testing.expectEqual(@as(usize, 13), sizes[0].size);
+    try testing.expectEqualStrings("message", sizes[1].name.?);
+    try testing.expect(sizes[1].section == .data);
+    try testing.expectEqual(@as(usize, 6), sizes[1].size);
+    try testing.expectEqualStrings("helper", sizes[2].name.?);
+    try testing.expectEqual(@as(usize, 1), sizes[2].size);
+
+    var output: Io.Writer.Allocating = .init(testing.allocator);
+    defer output.deinit();
+    try f.compiler.writeSizeReport(&output.writer);
+    try testing.expect(std.mem.indexOf(u8, output.written(), "total") != null);
+}
+
+test "listing shows each statement's bytes next to its line" {
+    var f: Fixture = undefined;
+    try f.init(
+        \\_start:
+        \\    mov q0, 42
+        \\    hlt
+        \\.section data
+        \\msg: db "hi", 0
+    );
+    defer f.deinit();
+
+    const bytecode = try f.compiler.compile();
+    defer testing.allocator.free(bytecode);
+
+    var output: Io.Writer.Allocating = .init(testing.allocator);
+    defer output.deinit();
+    try f.compiler.writeListing(&output.writer);
+    try testing.expectEqualStrings(
+        \\test.nyx
+        \\     1  text 00000000                           _start:
+        \\     2  text 00000000  02 03 2a 00 00 00 00 00      mov q0, 42
+        \\             00000008  00 00
+        \\     3  text 0000000a  66                           hlt
+        \\     4  data 00000000                           .section data
+        \\     5  data 00000000                           msg: db "hi", 0
+        \\     5  data 00000000  68 69 00
+        \\
+    , output.written());
+}
+
+test "debug info maps each instruction to its line" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    mov q0, 1
+        \\    call helper
+        \\    hlt
+        \\helper:
+        \\    ret
+    , .{ .filename = "test.nyx", .debug_info = true });
+    defer testing.allocator.free(bytecode);
+
+    const header = try nyx.features.read(bytecode);
+    try testing.expectEqual(@as(usize, 14), header.program(bytecode).len);
+    var info = try nyx.DebugInfo.read(testing.allocator, header.debugSection(bytecode).?);
+    defer info.deinit();
+
+    try testing.expectEqual(@as(usize, 2), info.symbols.len);
+    try testing.expectEqualStrings("_start", info.symbols[0].name);
+    try testing.expectEqualStrings("helper", info.symbols[1].name);
+    try testing.expectEqual(@as(u64, 13), info.symbols[1].addr);
+
+    // mov is 10 bytes, call 2, hlt and ret 1 each.
+    const expected = [_]struct { addr: usize, line: usize }{
+        .{ .addr = 0, .line = 2 },
+        .{ .addr = 9, .line = 2 },
+        .{ .addr = 10, .line = 3 },
+        .{ .addr = 12, .line = 4 },
+        .{ .addr = 13, .line = 6 },
+    };
+    for (expected) |e| {
+        const where = info.locate(e.addr).?;
+        try testing.expectEqualStrings("test.nyx", where.file);
+        try testing.expectEqual(e.line, where.line);
+    }
+    try testing.expectEqual(null, info.locate(14));
+
+    var vm = try nyx.Vm.init(bytecode, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+    try testing.expectEqual(@as(usize, 4), vm.sourceLocation(vm.instruction_start).?.line);
+}
+
+test "symbols embed labels without source lines" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    call main
+        \\    hlt
+        \\main:
+        \\    ret
+        \\.section data
+        \\answer: dq 42
+    , .{ .embed_symbols = true });
+    defer testing.allocator.free(bytecode);
+
+    const header = try nyx.features.read(bytecode);
+    var info = try nyx.DebugInfo.read(testing.allocator, header.debugSection(bytecode).?);
+    defer info.deinit();
+    try testing.expectEqual(@as(usize, 0), info.files.len);
+    try testing.expectEqual(@as(usize, 0), info.lines.len);
+    try testing.expectEqual(@as(usize, 3), info.symbols.len);
+    try testing.expectEqualStrings("main", info.nearestSymbol(3).?.name);
+    try testing.expectEqual(null, info.locate(0));
+
+    var output: std.Io.Writer.Allocating = .init(testing.allocator);
+    defer output.deinit();
+    try info.writeSymbols(testing.allocator, &output.writer, .name);
+    try testing.expectEqualStrings(
+        \\0x0000  text  _start
+        \\0x0004  data  answer
+        \\0x0003  text  main
+        \\
+    , output.written());
+}
+
+test "assemble through the library entry point" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\#define ANSWER 42
+        \\_start:
+        \\    mov q0, ANSWER
+        \\    hlt
+    , .{});
+    defer testing.allocator.free(bytecode);
+
+    var vm = try nyx.Vm.init(bytecode, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+    try testing.expectEqual(@as(u64, 42), vm.regs.get(.q0).asU64());
+
+    try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, "jmp nowhere\n", .{}));
+}
+
+test "strict mode rejects implicit behavior" {
+    const rejected = [_][]const u8{
+        "_start:\n    push q0\n    hlt\n",
+        "_start:\n    pop q0\n    hlt\n",
+        "    hlt\n",
+        "_start:\n    mov b0, 300\n    hlt\n",
+        "_start:\n    hlt\nunused:\n    ret\n",
+    };
+    for (rejected) |input| {
+        var f: Fixture = undefined;
+        try f.init(input);
+        defer f.deinit();
+        f.compiler.strict = true;
+        f.compiler.exit_on_error = false;
+        try testing.expectError(error.CompilerError, f.compiler.compile());
+    }
+
+    var f: Fixture = undefined;
+    try f.init("_start:\n    push qword q0\n    mov b0, 255\n    mov b1, -128\n    hlt\n");
+    defer f.deinit();
+    f.compiler.strict = true;
+    f.compiler.exit_on_error = false;
+    const bytecode = try f.compiler.compile();
+    defer testing.allocator.free(bytecode);
+}
+
+test "reservations past the section size limit are rejected" {
+    const rejected = [_][]const u8{
+        "_start:\n    hlt\n.section data\nbuf: resb 0x7FFFFFFFFFFF\n",
+        "_start:\n    hlt\n.section data\nbuf: resq 0x7FFFFFFFFFFFFFFF\n",
+        "_start:\n    hlt\n.section data\nbuf: resb 17\n",
+        "_start:\n    hlt\n.section data\na: resb 8\nb: resb 8\nc: resb 1\n",
+        "_start:\n    hlt\n.section data\na: dq 1, 2, 3\n",
+        "_start:\n    hlt\n.section data\na: resb 12\nb: dd 1.5, 2.5\n",
+        "_start:\n    hlt\n.section data\na: resb 16\nb: db 1\n",
+    };
+    for (rejected) |input| {
+        var f: Fixture = undefined;
+        try f.init(input);
+        defer f.deinit();
+        f.compiler.max_section_size = 16;
+        f.compiler.exit_on_error = false;
+        try testing.expectError(error.CompilerError, f.compiler.compile());
+    }
+
+    var f: Fixture = undefined;
+    try f.init("_start:\n    hlt\n.section data\na: resb 8\nb: resq 1\n");
+    defer f.deinit();
+    f.compiler.max_section_size = 16;
+    f.compiler.exit_on_error = false;
+    const bytecode = try f.compiler.compile();
+    defer testing.allocator.free(bytecode);
+}
+
+test "writes to ip and the read-only registers are rejected" {
+    const rejected = [_][]const u8{
+        "_start:\n    mov ip, 0\n    hlt\n",
+        "_start:\n    mov ip, q0\n    hlt\n",
+        "_start:\n    pop ip\n    hlt\n",
+        "_start:\n    add ip, ip, 8\n    hlt\n",
+        "_start:\n    inc ip\n    hlt\n",
+        "_start:\n    mov msz, 0\n    hlt\n",
+        "_start:\n    mov brk, q0\n    hlt\n",
+        "_start:\n    sub isp, isp, 8\n    hlt\n",
+        "_start:\n    and brk, brk, 0xFF\n    hlt\n",
+        "_start:\n    dec msz\n    hlt\n",
+        "_start:\n    pop isp\n    hlt\n",
+    };
+    for (rejected) |source| {
+        try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, source, .{}));
+    }
+
+    const bytecode = try nyx.assemble(testing.io, testing.allocator, "_start:\n    mov q0, ip\n    mov q1, brk\n    sub sp, isp, 8\n    hlt\n", .{});
+    defer testing.allocator.free(bytecode);
+}
+
+test "immediates that do not fit their operand are rejected unless truncation is asked for" {
+    const rejected = [_][]const u8{
+        "_start:\n    mov b0, 300\n    hlt\n",
+        "_start:\n    mov b0, -129\n    hlt\n",
+        "_start:\n    add w0, w0, 70000\n    hlt\n",
+        "_start:\n    push byte 256\n    hlt\n",
+        "_start:\n    cmp d0, 0x100000000\n    hlt\n",
+        "_start:\n    mov byte [0x100], 256\n    hlt\n",
+    };
+    for (rejected) |source| {
+        try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, source, .{}));
+    }
+
+    const source =
+        \\_start:
+        \\    mov b0, 300
+        \\    mov b1, 255
+        \\    mov b2, -128
+        \\    add w3, w3, 70000
+        \\    hlt
+    ;
+    const bytecode = try nyx.assemble(testing.io, testing.allocator, source, .{ .truncate_immediates = true });
+    defer testing.allocator.free(bytecode);
+    var vm = try nyx.Vm.init(bytecode, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+    try testing.expectEqual(@as(u8, 44), vm.regs.get(.b0).asU8());
+    try testing.expectEqual(@as(u8, 255), vm.regs.get(.b1).asU8());
+    try testing.expectEqual(@as(u8, 0x80), vm.regs.get(.b2).asU8());
+    try testing.expectEqual(@as(u16, 70000 - 65536), vm.regs.get(.w3).asU16());
+
+    try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, source, .{
+        .truncate_immediates = true,
+        .warnings = .{ .deny = true },
+    }));
+}
+
+test "unary operators are folded without the preprocessor" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    mov q0, -1
+        \\    mov b1, ~0x0F
+        \\    mov w2, -8i16
+        \\    add q3, q3, -(2 + 3)
+        \\    mov dd4, -1.5
+        \\    hlt
+    , .{ .preprocess = false });
+    defer testing.allocator.free(bytecode);
+
+    var vm = try nyx.Vm.init(bytecode, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+    try testing.expectEqual(@as(u64, std.math.maxInt(u64)), vm.regs.get(.q0).asU64());
+    try testing.expectEqual(@as(u8, 0xF0), vm.regs.get(.b1).asU8());
+    try testing.expectEqual(@as(u16, @bitCast(@as(i16, -8))), vm.regs.get(.w2).asU16());
+    try testing.expectEqual(@as(u64, @bitCast(@as(i64, -5))), vm.regs.get(.q3).asU64());
+    try testing.expectEqual(@as(f64, -1.5), vm.regs.get(.dd4).asF64());
+
+    try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, "_start:\n    mov b0, ~0xFF\n    hlt\n", .{ .preprocess = false }));
+}
+
+test "warnings only fail the build when denied" {
+    const source =
+        \\_start:
+        \\    hlt
+        \\    mov q0, 1
+        \\unused:
+        \\    ret
+    ;
+    const bytecode = try nyx.assemble(testing.io, testing.allocator, source, .{});
+    testing.allocator.free(bytecode);
+
+    try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, source, .{
+        .warnings = .{ .deny = true },
+    }));
+
+    var allowed: nyx.warning.Policy = .{ .deny = true };
+    allowed.allowed.insert(.unused_label);
+    try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, source, .{ .warnings = allowed }));
+    allowed.allowed.insert(.unreachable_code);
+    const denied = try nyx.assemble(testing.io, testing.allocator, source, .{ .warnings = allowed });
+    testing.allocator.free(denied);
+
+    // A label makes the code after it reachable.
+    const reachable = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    jmp .done
+        \\.done:
+        \\    hlt
+    , .{ .warnings = .{ .deny = true } });
+    testing.allocator.free(reachable);
+}
+
+test "a second .entry is rejected" {
+    try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator,
+        \\.entry _start
+        \\.entry other
+        \\_start:
+        \\    hlt
+        \\other:
+        \\    hlt
+    , .{}));
+}
+
+test "an entry point in the data section is a warning" {
+    const source =
+        \\.entry buf
+        \\_start:
+        \\    hlt
+        \\.section data
+        \\buf:
+        \\    db 0
+    ;
+    const bytecode = try nyx.assemble(testing.io, testing.allocator, source, .{});
+    defer testing.allocator.free(bytecode);
+    const header = try nyx.features.read(bytecode);
+    try testing.expectEqual(@as(u64, 1), header.entry);
+
+    // `_start` is never used either, so only the entry warning is left.
+    var allowed: nyx.warning.Policy = .{ .deny = true };
+    allowed.allowed.insert(.unused_label);
+    try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, source, .{ .warnings = allowed }));
+    allowed.allowed.insert(.entry_outside_text);
+    const quiet = try nyx.assemble(testing.io, testing.allocator, source, .{ .warnings = allowed });
+    testing.allocator.free(quiet);
+}
+
+test "optimizer folds constant registers into addresses" {
+    const source =
+        \\_start:
+        \\    mov q1, value
+        \\    mov q0, [q1]
+        \\    mov q2, value
+        \\    mov q3, [q2, 1]
+        \\    mov q4, q2
+        \\    hlt
+        \\.section data
+        \\value:
+        \\    dq 7
+    ;
+    const plain = try nyx.assemble(testing.io, testing.allocator, source, .{});
+    defer testing.allocator.free(plain);
+    const optimized = try nyx.assemble(testing.io, testing.allocator, source, .{ .optimize = true });
+    defer testing.allocator.free(optimized);
+
+    try testing.expect(optimized.len < plain.len);
+
+    var vm = try nyx.Vm.init(optimized, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+    try testing.expectEqual(@as(u64, 7), vm.regs.get(.q0).asU64());
+    // The load into q1 is dropped, q2 is still read by the last mov.
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q1).asU64());
+    try testing.expect(vm.regs.get(.q4).asU64() != 0);
+}
+
+test "required features are recorded in the header" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\.requires float, net
+        \\_start:
+        \\    hlt
+    , .{});
+    defer testing.allocator.free(bytecode);
+
+    const header = try nyx.features.read(bytecode);
+    try testing.expectEqual(@as(u64, 0), header.entry);
+    try testing.expect(header.required.eql(.initMany(&.{ .float, .net })));
+    try testing.expectEqual(@as(u16, 0), header.unknown);
+    try testing.expectEqual(nyx.features.Layout{ .text_len = 1, .data_len = 0 }, header.layout.?);
+
+    const disassembler = try nyx.Disassembler.init(bytecode, testing.allocator);
+    var text: Io.Writer.Allocating = .init(testing.allocator);
+    defer text.deinit();
+    try disassembler.disassemble(&text.writer);
+    try testing.expect(std.mem.startsWith(u8, text.written(), ".requires float, net\n"));
+
+    try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, ".requires graphics\n_start:\n    hlt\n", .{}));
+}
+
+test "align pads to a multiple in both sections" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    hlt
+        \\.align 4
+        \\    hlt
+        \\.section data
+        \\    db 1
+        \\.align 8
+        \\value:
+        \\    dq 5
+    , .{});
+    defer testing.allocator.free(bytecode);
+
+    const nop = nyx.Opcode.nop.intoU8();
+    const hlt = nyx.Opcode.hlt.intoU8();
+    const image = bytecode[16..];
+    // The text is padded to 8 so that `value` is 8-byte aligned once loaded.
+    try testing.expectEqualSlices(u8, &.{ hlt, nop, nop, nop, hlt, nop, nop, nop }, image[0..8]);
+    try testing.expectEqualSlices(u8, &.{ 1, 0, 0, 0, 0, 0, 0, 0 }, image[8..16]);
+    try testing.expectEqual(@as(u64, 5), std.mem.readInt(u64, image[16..24], .little));
+    try testing.expectEqual(@as(usize, 24), image.len);
+
+    try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, "_start:\n.align 3\n    hlt\n", .{}));
+}
+
+test "objects link into one program" {
+    var program: Fixture = undefined;
+    try program.init(
+        \\.entry _start
+        \\_start:
+        \\    call load
+        \\    hlt
+    );
+    defer program.deinit();
+    var lib: Fixture = undefined;
+    try lib.init(
+        \\load:
+        \\    mov q0, [value]
+        \\    ret
+        \\.section data
+        \\value:
+        \\    dq 42
+    );
+    defer lib.deinit();
+
+    var objects: [2]nyx.Object = undefined;
+    for ([_]*Fixture{ &program, &lib }, 0..) |f, i| {
+        var object = try f.compiler.compileObject();
+        defer object.deinit();
+        var encoded: Io.Writer.Allocating = .init(testing.allocator);
+        defer encoded.deinit();
+        try object.write(&encoded.writer);
+        objects[i] = try nyx.Object.read(testing.allocator, encoded.written());
+    }
+    defer for (&objects) |*object| object.deinit();
+    try testing.expectEqual(@as(usize, 1), objects[0].relocations.len);
+    try testing.expectEqualStrings("load", objects[0].relocations[0].symbol);
+    try testing.expectEqual(@as(usize, 3), objects[0].relocations[0].location.line);
+
+    var output: Io.Writer.Allocating = .init(testing.allocator);
+    defer output.deinit();
+    try nyx.Linker.link(testing.allocator, &objects, &program.reporter, &output.writer);
+
+    var vm = try nyx.Vm.init(output.written(), 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+    try testing.expectEqual(@as(u64, 42), vm.regs.get(.q0).asU64());
+
+    // Linked at a base, every address moves up and the bytes below trap.
+    var moved: Io.Writer.Allocating = .init(testing.allocator);
+    defer moved.deinit();
+    try nyx.Linker.linkAt(testing.allocator, &objects, 64, &program.reporter, &moved.writer);
+    var moved_vm = try nyx.Vm.init(moved.written(), 65536, &.{}, testing.allocator);
+    defer moved_vm.deinit();
+    var unmoved_vm = try nyx.Vm.init(output.written(), 65536, &.{}, testing.allocator);
+    defer unmoved_vm.deinit();
+    try testing.expectEqual(unmoved_vm.regs.ip() + 64, moved_vm.regs.ip());
+    try testing.expectEqual(nyx.Linker.base_fill, (try moved_vm.mmu.read(0, .byte)).asU8());
+    try moved_vm.run();
+    try testing.expectEqual(@as(u64, 42), moved_vm.regs.get(.q0).asU64());
+
+    // Linking the library twice defines every symbol twice, and on its own
+    // nothing defines `load`.
+    const duplicated = [_]nyx.Object{ objects[1], objects[1] };
+    try testing.expectError(error.LinkerError, nyx.Linker.link(testing.allocator, &duplicated, &program.reporter, &output.writer));
+    try testing.expectError(error.LinkerError, nyx.Linker.link(testing.allocator, objects[0..1], &program.reporter, &output.writer));
+    try testing.expectError(error.InvalidObject, nyx.Object.read(testing.allocator, output.written()));
+}
+
+test "local labels stay in their object and addresses must fit" {
+    var first: Fixture = undefined;
+    try first.init(
+        \\_start:
+        \\    call helper
+        \\.done:
+        \\    hlt
+        \\    jmp .done
+    );
+    defer first.deinit();
+    var second: Fixture = undefined;
+    try second.init(
+        \\helper:
+        \\    mov q0, 7
+        \\    jmp .done
+        \\.done:
+        \\    ret
+    );
+    defer second.deinit();
+
+    var objects: [2]nyx.Object = undefined;
+    for ([_]*Fixture{ &first, &second }, 0..) |f, i| {
+        var object = try f.compiler.compileObject();
+        defer object.deinit();
+        var encoded: Io.Writer.Allocating = .init(testing.allocator);
+        defer encoded.deinit();
+        try object.write(&encoded.writer);
+        objects[i] = try nyx.Object.read(testing.allocator, encoded.written());
+    }
+    defer for (&objects) |*object| object.deinit();
+    for (objects[0].symbols) |symbol| {
+        const local = std.mem.eql(u8, symbol.name, "_start.done");
+        try testing.expectEqual(@as(nyx.Object.Binding, if (local) .local else .global), symbol.binding);
+    }
+
+    var output: Io.Writer.Allocating = .init(testing.allocator);
+    defer output.deinit();
+    try nyx.Linker.link(testing.allocator, &objects, &first.reporter, &output.writer);
+    var vm = try nyx.Vm.init(output.written(), 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+    try testing.expectEqual(@as(u64, 7), vm.regs.get(.q0).asU64());
+
+    // Another object cannot reach `_start.done`.
+    var text = [_]u8{0} ** 8;
+    const outsider = nyx.Object{
+        .arena = .init(testing.allocator),
+        .source = "outsider.nyx",
+        .required = .initEmpty(),
+        .entry = null,
+        .text = &text,
+        .data = &.{},
+        .text_alignment = 1,
+        .data_alignment = 1,
+        .symbols = &.{},
+        .relocations = &.{.{
+            .section = .text,
+            .offset = 0,
+            .size = 8,
+            .symbol = "_start.done",
+            .location = .{ .file = "outsider.nyx", .line = 1, .column = 1 },
+        }},
+    };
+    const reaching = [_]nyx.Object{ objects[0], objects[1], outsider };
+    try testing.expectError(error.LinkerError, nyx.Linker.link(testing.allocator, &reaching, &first.reporter, &output.writer));
+
+    // A byte holds the address of `far` at 300 only when compiled alone,
+    // where it is refused; the linker refuses it too.
+    var wide: Fixture = undefined;
+    try wide.init(
+        \\_start:
+        \\    times 300 nop
+        \\far:
+        \\    hlt
+        \\.section data
+        \\    db far
+    );
+    defer wide.deinit();
+    var wide_object = try wide.compiler.compileObject();
+    defer wide_object.deinit();
+    try testing.expectError(error.LinkerError, nyx.Linker.link(testing.allocator, (&wide_object)[0..1], &wide.reporter, &output.writer));
+}
+
+test "build manifest describes the output" {
+    var f: Fixture = undefined;
+    try f.init(
+        \\.requires float
+        \\_start:
+        \\    hlt
+        \\.section data
+        \\value:
+        \\    dq 1
+    );
+    defer f.deinit();
+    const bytecode = try f.compiler.compile();
+    defer testing.allocator.free(bytecode);
+
+    const outputs = [_]Manifest.Output{.{ .path = "out.nyb", .kind = "bytecode", .contents = bytecode }};
+    var manifest = Manifest.fromCompiler(&f.compiler, &.{"test.nyx"});
+    manifest.outputs = &outputs;
+    manifest.entry = 0;
+    var json: Io.Writer.Allocating = .init(testing.allocator);
+    defer json.deinit();
+    try manifest.write(&json.writer);
+
+    const parsed = try std.json.parseFromSlice(std.json.Value, testing.allocator, json.written(), .{});
+    defer parsed.deinit();
+    const root = parsed.value.object;
+    const output = root.get("outputs").?.array.items[0].object;
+    try testing.expectEqual(@as(i64, @intCast(bytecode.len)), output.get("size").?.integer);
+    try testing.expectEqual(@as(usize, 64), output.get("sha256").?.string.len);
+    const sections = root.get("sections").?.object;
+    try testing.expectEqual(@as(i64, @intCast(f.compiler.bytecode.len(.text))), sections.get("text").?.integer);
+    try testing.expectEqual(@as(i64, @intCast(f.compiler.bytecode.len(.data))), sections.get("data").?.integer);
+    try testing.expectEqual(@as(i64, @intCast(f.compiler.labels.count())), root.get("symbols").?.integer);
+    try testing.expectEqualStrings("float", root.get("requires").?.array.items[0].string);
+    try testing.expectEqualStrings(build_options.version, root.get("toolchain").?.object.get("version").?.string);
+}
+
+test "sized data directives take labels and floats" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    hlt
+        \\.section data
+        \\table:
+        \\    db _start
+        \\    dw table
+        \\    dd end
+        \\    dq table
+        \\    df 1.5, 2
+        \\    ddbl -0.25
+        \\end:
+    , .{});
+    defer testing.allocator.free(bytecode);
+
+    const data = bytecode[17..];
+    const readInt = std.mem.readInt;
+    try testing.expectEqual(@as(u8, 0), data[0]);
+    try testing.expectEqual(@as(u16, 1), readInt(u16, data[1..3], .little));
+    try testing.expectEqual(@as(u32, 1 + 31), readInt(u32, data[3..7], .little));
+    try testing.expectEqual(@as(u64, 1), readInt(u64, data[7..15], .little));
+    try testing.expectEqual(@as(f32, 1.5), @as(f32, @bitCast(readInt(u32, data[15..19], .little))));
+    try testing.expectEqual(@as(f32, 2), @as(f32, @bitCast(readInt(u32, data[19..23], .little))));
+    try testing.expectEqual(@as(f64, -0.25), @as(f64, @bitCast(readInt(u64, data[23..31], .little))));
+    try testing.expectEqual(@as(usize, 31), data.len);
+
+    try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    resb 300
+        \\late:
+        \\    hlt
+        \\.section data
+        \\    db late
+    , .{}));
+}
+
+test "not takes integer registers and addresses" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    not q0, q1
+        \\    not b2, [q3]
+        \\    hlt
+    , .{});
+    defer testing.allocator.free(bytecode);
+    try testing.expectEqual(nyx.Opcode.not_reg_reg.intoU8(), bytecode[16]);
+    try testing.expectEqual(nyx.Opcode.not_reg_addr.intoU8(), bytecode[19]);
+
+    for ([_][]const u8{ "not ff0, q1\n", "not q0, dd1\n", "not q0, 5\n" }) |source| {
+        try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, source, .{}));
+    }
+}
+
+test "movz and movs widen an integer register" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    movz q0, b1
+        \\    movs d2, w3
+        \\    hlt
+    , .{});
+    defer testing.allocator.free(bytecode);
+    try testing.expectEqual(nyx.Opcode.movz_reg_reg.intoU8(), bytecode[16]);
+    try testing.expectEqual(nyx.Opcode.movs_reg_reg.intoU8(), bytecode[19]);
+
+    for ([_][]const u8{ "movz b0, q1\n", "movs q0, q1\n", "movz dd0, b1\n", "movs q0, ff1\n", "movz q0, 5\n", "movs ip, b0\n" }) |source| {
+        try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, source, .{}));
+    }
+}
+
+test "cvt takes one register of each class it converts between" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    cvtif dd0, q1
+        \\    cvtfi b2, ff3
+        \\    cvtfd dd4, ff5
+        \\    cvtdf ff6, dd7
+        \\    hlt
+    , .{});
+    defer testing.allocator.free(bytecode);
+    try testing.expectEqual(nyx.Opcode.cvtif_reg_reg.intoU8(), bytecode[16]);
+    try testing.expectEqual(nyx.Opcode.cvtfi_reg_reg.intoU8(), bytecode[19]);
+    try testing.expectEqual(nyx.Opcode.cvtfd_reg_reg.intoU8(), bytecode[22]);
+    try testing.expectEqual(nyx.Opcode.cvtdf_reg_reg.intoU8(), bytecode[25]);
+
+    for ([_][]const u8{ "cvtif q0, q1\n", "cvtif ff0, dd1\n", "cvtfi ff0, dd1\n", "cvtfi q0, q1\n", "cvtfd dd0, dd1\n", "cvtdf ff0, q1\n", "cvtif ff0, 5\n", "cvtfi ip, ff0\n" }) |source| {
+        try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, source, .{}));
+    }
+}
+
+test "string instructions take 64-bit registers" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    scmp q0, q1
+        \\    schr q1, q2, 'a'
+        \\    schr q1, q2, b3
+        \\    hlt
+    , .{});
+    defer testing.allocator.free(bytecode);
+    try testing.expectEqual(nyx.Opcode.scmp_reg_reg.intoU8(), bytecode[16]);
+    try testing.expectEqual(nyx.Opcode.schr_reg_reg_imm.intoU8(), bytecode[19]);
+    try testing.expectEqual(@as(u64, 'a'), std.mem.readInt(u64, bytecode[22..30], .little));
+    try testing.expectEqual(nyx.Opcode.schr_reg_reg_reg.intoU8(), bytecode[30]);
+
+    for ([_][]const u8{ "scmp d0, q1\n", "scmp q0, 5\n", "schr q0, q1, 256\n", "schr q0, q1, ff2\n", "schr ip, q1, 0\n" }) |source| {
+        try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, source, .{}));
+    }
+}
+
+test "flag queries take one integer register" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    seteq q0
+        \\    setgt b1
+        \\    setc d2
+        \\    hlt
+    , .{});
+    defer testing.allocator.free(bytecode);
+    try testing.expectEqual(nyx.Opcode.seteq_reg.intoU8(), bytecode[16]);
+    try testing.expectEqual(nyx.Opcode.setgt_reg.intoU8(), bytecode[18]);
+    try testing.expectEqual(nyx.Opcode.setc_reg.intoU8(), bytecode[20]);
+
+    for ([_][]const u8{ "seteq ff0\n", "setlt 1\n", "setne ip\n", "seto [q0]\n" }) |source| {
+        try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, source, .{}));
+    }
+}
+
+test "enter takes a frame size and locals sit below bp" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    enter 16
+        \\    mov q0, [bp, -8]
+        \\    mov q1, [bp - 16]
+        \\    leave
+        \\    hlt
+    , .{});
+    defer testing.allocator.free(bytecode);
+    try testing.expectEqual(nyx.Opcode.enter_imm.intoU8(), bytecode[16]);
+    try testing.expectEqual(@as(u64, 16), std.mem.readInt(u64, bytecode[17..25], .little));
+    try testing.expectEqual(nyx.Opcode.mov_reg_addr.intoU8(), bytecode[25]);
+    try testing.expectEqual(@as(i64, -8), std.mem.readInt(i64, bytecode[29..37], .little));
+    try testing.expectEqual(@as(i64, -16), std.mem.readInt(i64, bytecode[41..49], .little));
+    try testing.expectEqual(nyx.Opcode.leave.intoU8(), bytecode[49]);
+
+    for ([_][]const u8{ "enter -8\n", "enter q0\n" }) |source| {
+        try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, source, .{}));
+    }
+}
+
+test "label differences give the size of data" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\#define MSG_LEN msg_end - msg
+        \\_start:
+        \\    mov q2, MSG_LEN
+        \\    mov w3, msg - msg_end + 1
+        \\    hlt
+        \\.section data
+        \\msg:
+        \\    .ascii "hello"
+        \\msg_end:
+        \\    dq msg_end - msg
+        \\    dw _start - msg_end
+    , .{});
+    defer testing.allocator.free(bytecode);
+
+    var vm = try nyx.Vm.init(bytecode, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+    try testing.expectEqual(@as(u64, 5), vm.regs.get(.q2).asU64());
+    try testing.expectEqual(@as(u16, @bitCast(@as(i16, -4))), vm.regs.get(.w3).asU16());
+
+    const text_len = 10 + 4 + 1;
+    const data = bytecode[16 + text_len ..];
+    try testing.expectEqual(@as(u64, 5), std.mem.readInt(u64, data[5..13], .little));
+    try testing.expectEqual(@as(i16, -(text_len + 5)), std.mem.readInt(i16, data[13..15], .little));
+
+    for ([_][]const u8{
+        "_start:\n    mov q0, a + b\n    hlt\na:\nb:\n",
+        "_start:\n    mov q0, 4 - a\n    hlt\na:\n",
+        "_start:\n    mov q0, a - missing\n    hlt\na:\n",
+        "_start:\n    mov dd0, b - a\n    hlt\na:\nb:\n",
+        "_start:\n    mov b0, b - a\n    hlt\n.section data\na:\n    resb 300\nb:\n",
+    }) |source| {
+        try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, source, .{}));
+    }
+}
+
+test "label arithmetic in db, push and stores" {
+    var symbols = std.StringArrayHashMap(u64).init(testing.allocator);
+    defer {
+        for (symbols.keys()) |name| testing.allocator.free(name);
+        symbols.deinit();
+    }
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    push msg_end - msg
+        \\    pop q0
+        \\    push dword msg
+        \\    pop d1
+        \\    mov qword [slot], msg + 2
+        \\    mov q2, [slot]
+        \\    mov q3, msg + 1
+        \\    mov b4, [sizes + 1]
+        \\    hlt
+        \\.section data
+        \\msg:
+        \\    .ascii "hello"
+        \\msg_end:
+        \\sizes:
+        \\    db msg_end - msg, msg_end - msg - 1
+        \\slot:
+        \\    dq 0
+    , .{ .symbols = &symbols });
+    defer testing.allocator.free(bytecode);
+
+    var vm = try nyx.Vm.init(bytecode, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+    const msg = symbols.get("msg").?;
+    try testing.expectEqual(@as(u64, 5), vm.regs.get(.q0).asU64());
+    try testing.expectEqual(msg, vm.regs.get(.d1).asU32());
+    try testing.expectEqual(msg + 2, vm.regs.get(.q2).asU64());
+    try testing.expectEqual(msg + 1, vm.regs.get(.q3).asU64());
+    try testing.expectEqual(@as(u8, 4), vm.regs.get(.b4).asU8());
+
+    for ([_][]const u8{
+        "_start:\n    mov [slot], b - a\n    hlt\na:\nb:\nslot:\n",
+        "_start:\n    mov double [slot], b - a\n    hlt\na:\nb:\nslot:\n",
+        "_start:\n    push float b - a\n    hlt\na:\nb:\n",
+        "_start:\n    hlt\n.section data\na:\n    resb 300\nb:\n    db b - a\n",
+    }) |source| {
+        try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, source, .{}));
+    }
+}
+
+test "equ constants are resolved after every label is placed" {
+    var symbols = std.StringArrayHashMap(u64).init(testing.allocator);
+    defer {
+        for (symbols.keys()) |name| testing.allocator.free(name);
+        symbols.deinit();
+    }
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\BUFLEN equ buf_end - buf
+        \\.equ WORDS, BUFLEN / 8
+        \\_start:
+        \\    mov q0, BUFLEN
+        \\    mov q1, WORDS
+        \\    mov q2, LAST
+        \\    mov q3, BELOW
+        \\    hlt
+        \\LAST equ buf_end - 1
+        \\BELOW equ 0 - WORDS
+        \\.section data
+        \\buf:
+        \\    resb 64
+        \\buf_end:
+    , .{ .symbols = &symbols });
+    defer testing.allocator.free(bytecode);
+
+    var vm = try nyx.Vm.init(bytecode, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+    try testing.expectEqual(@as(u64, 64), vm.regs.get(.q0).asU64());
+    try testing.expectEqual(@as(u64, 8), vm.regs.get(.q1).asU64());
+    try testing.expectEqual(symbols.get("buf_end").? - 1, vm.regs.get(.q2).asU64());
+    try testing.expectEqual(@as(u64, @bitCast(@as(i64, -8))), vm.regs.get(.q3).asU64());
+    try testing.expect(symbols.get("BUFLEN") == null);
+
+    for ([_][]const u8{
+        "A equ B\nB equ A\n_start:\n    mov q0, A\n    hlt\n",
+        "_start:\n    hlt\n_start equ 1\n",
+        "N equ 1\nN equ 2\n_start:\n    hlt\n",
+        "N equ _start * 2\n_start:\n    mov q0, N\n    hlt\n",
+        "N equ _start + a\n_start:\n    mov q0, N\n    hlt\n.section data\na:\n",
+    }) |source| {
+        try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, source, .{}));
+    }
+
+    // In an object file a difference within a section is already known,
+    // and an equ with one label becomes a relocation against that label.
+    var fixture: Fixture = undefined;
+    try fixture.init(
+        \\LEN equ table_end - table
+        \\SECOND equ table + 8
+        \\_start:
+        \\    mov q0, LEN
+        \\    mov q1, [SECOND]
+        \\    hlt
+        \\.section data
+        \\table:
+        \\    dq 0
+        \\    dq 42
+        \\table_end:
+    );
+    defer fixture.deinit();
+    var object = try fixture.compiler.compileObject();
+    defer object.deinit();
+    try testing.expectEqual(@as(usize, 1), object.relocations.len);
+    try testing.expectEqualStrings("table", object.relocations[0].symbol);
+    try testing.expectEqual(@as(i64, 8), object.relocations[0].addend);
+
+    var linked: Io.Writer.Allocating = .init(testing.allocator);
+    defer linked.deinit();
+    try nyx.Linker.link(testing.allocator, (&object)[0..1], &fixture.reporter, &linked.writer);
+    var linked_vm = try nyx.Vm.init(linked.written(), 65536, &.{}, testing.allocator);
+    defer linked_vm.deinit();
+    try linked_vm.run();
+    try testing.expectEqual(@as(u64, 16), linked_vm.regs.get(.q0).asU64());
+    try testing.expectEqual(@as(u64, 42), linked_vm.regs.get(.q1).asU64());
+}
+
+test "addresses combine labels, registers and integers" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    mov q1, table
+        \\    mov q0, [table + 8]
+        \\    mov q2, [table, third - table]
+        \\    mov q3, [q1 + 16]
+        \\    mov q4, [q1 + second - table]
+        \\    push qword [table + 16]
+        \\    pop qword [q1, 24]
+        \\    mov q5, [table + 8, 16]
+        \\    mov q6, [_start, second]
+        \\    hlt
+        \\.section data
+        \\table:
+        \\    dq 10
+        \\second:
+        \\    dq 20
+        \\third:
+        \\    dq 30
+        \\    dq 0
+    , .{});
+    defer testing.allocator.free(bytecode);
+
+    var vm = try nyx.Vm.init(bytecode, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+    try testing.expectEqual(@as(u64, 20), vm.regs.get(.q0).asU64());
+    try testing.expectEqual(@as(u64, 30), vm.regs.get(.q2).asU64());
+    try testing.expectEqual(@as(u64, 30), vm.regs.get(.q3).asU64());
+    try testing.expectEqual(@as(u64, 20), vm.regs.get(.q4).asU64());
+    try testing.expectEqual(@as(u64, 30), vm.regs.get(.q5).asU64());
+    try testing.expectEqual(@as(u64, 20), vm.regs.get(.q6).asU64());
+
+    for ([_][]const u8{
+        "_start:\n    mov q0, [q1 + q2]\n    hlt\n",
+        "_start:\n    mov q0, [8 - q1]\n    hlt\n",
+        "_start:\n    mov q0, [0 - a]\n    hlt\na:\n",
+        "_start:\n    mov q0, [q1 + a, b]\n    hlt\na:\nb:\n",
+    }) |source| {
+        try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, source, .{}));
+    }
+
+    // In an object file the constant travels with the relocation.
+    var fixture: Fixture = undefined;
+    try fixture.init("_start:\n    mov q0, [value + 8]\n    hlt\n.section data\nvalue:\n    dq 0\n    dq 42\n");
+    defer fixture.deinit();
+    var object = try fixture.compiler.compileObject();
+    defer object.deinit();
+    var encoded: Io.Writer.Allocating = .init(testing.allocator);
+    defer encoded.deinit();
+    try object.write(&encoded.writer);
+    var decoded = try nyx.Object.read(testing.allocator, encoded.written());
+    defer decoded.deinit();
+    try testing.expectEqual(@as(i64, 8), decoded.relocations[0].addend);
+
+    var linked: Io.Writer.Allocating = .init(testing.allocator);
+    defer linked.deinit();
+    try nyx.Linker.link(testing.allocator, (&decoded)[0..1], &fixture.reporter, &linked.writer);
+    var linked_vm = try nyx.Vm.init(linked.written(), 65536, &.{}, testing.allocator);
+    defer linked_vm.deinit();
+    try linked_vm.run();
+    try testing.expectEqual(@as(u64, 42), linked_vm.regs.get(.q0).asU64());
+}
+
+test "local labels are scoped to the label above them" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    call count
+        \\    call double
+        \\    hlt
+        \\count:
+        \\    mov q0, 0
+        \\.loop:
+        \\    inc q0
+        \\    cmp q0, 3
+        \\    jeq .done
+        \\    jmp .loop
+        \\.done:
+        \\    ret
+        \\double:
+        \\    mov q1, 1
+        \\.loop:
+        \\    add q1, q1, q1
+        \\    cmp q1, 16
+        \\    jeq .done
+        \\    jmp .loop
+        \\.done:
+        \\    ret
+    , .{});
+    defer testing.allocator.free(bytecode);
+
+    var vm = try nyx.Vm.init(bytecode, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+    try testing.expectEqual(@as(u64, 3), vm.regs.get(.q0).asU64());
+    try testing.expectEqual(@as(u64, 16), vm.regs.get(.q1).asU64());
+
+    for ([_][]const u8{
+        ".loop:\n    jmp .loop\n",
+        "_start:\n    jmp .done\nother:\n.done:\n    hlt\n",
+    }) |source| {
+        try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, source, .{}));
+    }
+}
+
+test "label jumps take the shortest displacement that reaches" {
+    const source =
+        \\_start:
+        \\    mov q0, 0
+        \\    jmp near
+        \\near:
+        \\    call far
+        \\    hlt
+        \\    resb 200
+        \\far:
+        \\    mov q0, 7
+        \\    ret
+    ;
+    const bytecode = try nyx.assemble(testing.io, testing.allocator, source, .{});
+    defer testing.allocator.free(bytecode);
+
+    // `far` is out of reach of a byte, so the call is widened to rel32 on a
+    // second pass. Both displacements count from the end of the instruction.
+    const code = bytecode[16..];
+    try testing.expectEqual(nyx.Opcode.jmp_rel8.intoU8(), code[10]);
+    try testing.expectEqual(@as(u8, 0), code[11]);
+    try testing.expectEqual(nyx.Opcode.call_rel32.intoU8(), code[12]);
+    try testing.expectEqual(@as(i32, 218 - 17), std.mem.readInt(i32, code[13..17], .little));
+
+    var vm = try nyx.Vm.init(bytecode, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+    try testing.expectEqual(@as(u64, 7), vm.regs.get(.q0).asU64());
+
+    const absolute = try nyx.assemble(testing.io, testing.allocator, source, .{ .absolute_jumps = true });
+    defer testing.allocator.free(absolute);
+    try testing.expectEqual(nyx.Opcode.jmp_imm.intoU8(), absolute[16 + 10]);
+    try testing.expectEqual(@as(u64, 19), std.mem.readInt(u64, absolute[16 + 11 ..][0..8], .little));
+    try testing.expectEqual(bytecode.len + 7 + 4, absolute.len);
+}
+
+test "times repeats an instruction or data directive" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    times 4 inc q0
+        \\    times 0 inc q0
+        \\    hlt
+        \\.section data
+        \\header:
+        \\    db 1, 2, 3
+        \\pad:
+        \\    times 8 - (pad - header) db 0xFF
+        \\    times 2 dw 0x1234
+    , .{});
+    defer testing.allocator.free(bytecode);
+
+    var vm = try nyx.Vm.init(bytecode, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+    try testing.expectEqual(@as(u64, 4), vm.regs.get(.q0).asU64());
+    try testing.expectEqualSlices(
+        u8,
+        &.{ 1, 2, 3, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x34, 0x12, 0x34, 0x12 },
+        bytecode[bytecode.len - 12 ..],
+    );
+
+    for ([_][]const u8{
+        "_start:\n    times end - _start nop\nend:\n    hlt\n",
+        "_start:\n    times 0 - 1 nop\n    hlt\n",
+        "_start:\n    times q0 nop\n    hlt\n",
+        "_start:\n    times 2000000 mov q0, 1\n    hlt\n",
+        "_start:\n    hlt\n.section data\nbuf: times 3000000 dq 0\n",
+    }) |source| {
+        try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, source, .{}));
+    }
+    for ([_][]const u8{
+        "_start:\n    times 2 .section data\n    hlt\n",
+        "_start:\n    times 2 times 2 nop\n    hlt\n",
+        "times 2 _start:\n    hlt\n",
+    }) |source| {
+        try testing.expectError(error.ParserError, nyx.assemble(testing.io, testing.allocator, source, .{}));
+    }
+}
+
+test "imul, idiv and imod take signed immediates on integer registers" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    mov q0, -7
+        \\    imul q1, q0, -3
+        \\    idiv q2, q0, 2
+        \\    imod q3, q0, 2
+        \\    hlt
+    , .{});
+    defer testing.allocator.free(bytecode);
+    try testing.expectEqual(nyx.Opcode.imul_reg_reg_imm.intoU8(), bytecode[16 + 10]);
+
+    var vm = try nyx.Vm.init(bytecode, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+    try testing.expectEqual(@as(u64, 21), vm.regs.get(.q1).asU64());
+    try testing.expectEqual(@as(i64, -3), @as(i64, @bitCast(vm.regs.get(.q2).asU64())));
+    try testing.expectEqual(@as(i64, -1), @as(i64, @bitCast(vm.regs.get(.q3).asU64())));
+
+    for ([_][]const u8{ "imul ff0, ff1, 2.0\n", "idiv dd0, dd1, dd2\n", "imod q0, 5, q1\n" }) |source| {
+        try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, source, .{}));
+    }
+}
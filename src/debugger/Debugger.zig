@@ -0,0 +1,316 @@
+//! Interactive, line-oriented debugger for the VM.
+//!
+//! The debugger owns no program state of its own beyond breakpoints; it
+//! drives a `Vm` that has its journal enabled so execution can be stepped
+//! both forwards and backwards.
+
+const std = @import("std");
+const Io = std.Io;
+const mem = std.mem;
+const fmt = std.fmt;
+const Allocator = mem.Allocator;
+const ArrayList = std.array_list.Managed;
+const fehler = @import("fehler");
+const LineEditor = @import("../LineEditor.zig");
+const StringInterner = @import("../StringInterner.zig");
+const Lexer = @import("../lexer/Lexer.zig");
+const Parser = @import("../parser/Parser.zig");
+const ast = @import("../parser/ast.zig");
+const Vm = @import("../vm/Vm.zig");
+const Register = @import("../vm/register.zig").Register;
+const Opcode = @import("../compiler/opcode.zig").Opcode;
+
+const Debugger = @This();
+
+pub const Symbols = std.StringArrayHashMap(u64);
+
+const commands = [_][]const u8{
+    "step", "reverse-step", "continue", "break", "delete", "regs", "print", "x", "console", "help", "quit",
+};
+
+const help_text =
+    \\step [n]           (s)   execute the next n instructions
+    \\reverse-step [n]   (rs)  undo the last n instructions
+    \\continue           (c)   run until a breakpoint or halt
+    \\break <expr>       (b)   set a breakpoint
+    \\delete <expr>      (d)   remove a breakpoint
+    \\regs               (r)   show general purpose and special registers
+    \\print <expr>       (p)   evaluate an expression, e.g. [buffer + q1*8]
+    \\x <expr>[, len]          dump memory
+    \\console                  show the text console
+    \\help               (h)   show this message
+    \\quit               (q)   leave the debugger
+    \\
+;
+
+vm: *Vm,
+symbols: ?*const Symbols,
+breakpoints: std.AutoHashMap(usize, void),
+gpa: Allocator,
+
+pub fn init(vm: *Vm, symbols: ?*const Symbols, gpa: Allocator) Debugger {
+    return Debugger{
+        .vm = vm,
+        .symbols = symbols,
+        .breakpoints = .init(gpa),
+        .gpa = gpa,
+    };
+}
+
+pub fn deinit(self: *Debugger) void {
+    self.breakpoints.deinit();
+}
+
+/// Read commands from `editor` until `quit` or the end of input.
+pub fn repl(self: *Debugger, editor: *LineEditor, writer: *Io.Writer) !void {
+    editor.completer = self.completer();
+    try self.printLocation(writer);
+    while (try editor.readLine("(nyx) ")) |line| {
+        try editor.addHistory(line);
+        if (!try self.execute(mem.trim(u8, line, " \t\r"), writer)) break;
+        try writer.flush();
+    }
+    try writer.flush();
+}
+
+/// Complete command names, register names and symbol names.
+pub fn completer(self: *Debugger) LineEditor.Completer {
+    return .{ .context = self, .completeFn = complete };
+}
+
+fn complete(context: *anyopaque, prefix: []const u8, candidates: *ArrayList([]const u8)) anyerror!void {
+    const self: *Debugger = @ptrCast(@alignCast(context));
+
+    for (commands) |command| {
+        if (mem.startsWith(u8, command, prefix)) try candidates.append(command);
+    }
+    inline for (comptime std.meta.fieldNames(Register)) |name| {
+        if (mem.startsWith(u8, name, prefix)) try candidates.append(name);
+    }
+    if (self.symbols) |symbols| {
+        for (symbols.keys()) |name| {
+            if (mem.startsWith(u8, name, prefix)) try candidates.append(name);
+        }
+    }
+}
+
+/// Execute a single command. Returns false when the session should end.
+pub fn execute(self: *Debugger, line: []const u8, writer: *Io.Writer) !bool {
+    var args = mem.tokenizeAny(u8, line, " \t");
+    const command = args.next() orelse return true;
+
+    if (eql(command, "quit", "q")) return false;
+
+    if (eql(command, "help", "h")) {
+        try writer.writeAll(help_text);
+    } else if (eql(command, "step", "s")) {
+        const count = try parseCount(args.next(), writer) orelse return true;
+        for (0..count) |_| {
+            if (self.vm.halted) break;
+            if (!try self.stepOnce(writer)) break;
+        }
+        try self.printLocation(writer);
+    } else if (eql(command, "reverse-step", "rs")) {
+        const count = try parseCount(args.next(), writer) orelse return true;
+        for (0..count) |_| {
+            if (!try self.vm.reverseStep()) {
+                try writer.writeAll("no more history\n");
+                break;
+            }
+        }
+        try self.printLocation(writer);
+    } else if (eql(command, "continue", "c")) {
+        while (!self.vm.halted) {
+            if (!try self.stepOnce(writer)) break;
+            if (self.breakpoints.contains(self.vm.regs.ip())) {
+                try writer.print("breakpoint at 0x{x}\n", .{self.vm.regs.ip()});
+                break;
+            }
+        }
+        try self.printLocation(writer);
+    } else if (eql(command, "break", "b")) {
+        const addr = try self.evaluateArg(args.rest(), writer) orelse return true;
+        try self.breakpoints.put(addr, {});
+        try writer.print("breakpoint set at 0x{x}\n", .{addr});
+    } else if (eql(command, "delete", "d")) {
+        const addr = try self.evaluateArg(args.rest(), writer) orelse return true;
+        if (!self.breakpoints.remove(addr)) try writer.print("no breakpoint at 0x{x}\n", .{addr});
+    } else if (eql(command, "regs", "r")) {
+        try self.printRegisters(writer);
+    } else if (eql(command, "print", "p")) {
+        const text = mem.trim(u8, args.rest(), " \t");
+        if (Register.fromString(text)) |reg| {
+            try self.printRegister(reg, writer);
+        } else |_| {
+            const value = try self.evaluateArg(text, writer) orelse return true;
+            try writer.print("{s} = 0x{x} ({d})\n", .{ text, value, @as(i64, @bitCast(value)) });
+        }
+    } else if (mem.eql(u8, command, "x")) {
+        // An optional length follows the address expression after a comma.
+        const rest = args.rest();
+        const comma = mem.lastIndexOfScalar(u8, rest, ',');
+        const addr = try self.evaluateArg(if (comma) |i| rest[0..i] else rest, writer) orelse return true;
+        const len = if (comma) |i| try parseCount(mem.trim(u8, rest[i + 1 ..], " \t"), writer) orelse return true else 16;
+        try self.dumpMemory(addr, len, writer);
+    } else if (mem.eql(u8, command, "console")) {
+        if (self.vm.console) |*console| {
+            try console.writeText(writer);
+        } else {
+            try writer.writeAll("no console (run with --console ROWSxCOLS)\n");
+        }
+    } else {
+        try writer.print("unknown command: {s} (try help)\n", .{command});
+    }
+
+    return true;
+}
+
+fn stepOnce(self: *Debugger, writer: *Io.Writer) !bool {
+    self.vm.step() catch |err| {
+        try writer.print("vm error: {s}\n", .{@errorName(err)});
+        return false;
+    };
+    return true;
+}
+
+fn printLocation(self: *Debugger, writer: *Io.Writer) !void {
+    const ip = self.vm.regs.ip();
+    if (self.vm.halted) {
+        try writer.print("halted at 0x{x}\n", .{ip});
+        return;
+    }
+    const byte = (self.vm.mmu.read(ip, .byte) catch {
+        try writer.print("0x{x}: <out of bounds>\n", .{ip});
+        return;
+    }).asU8();
+    if (Opcode.fromU8(byte)) |opcode| {
+        try writer.print("0x{x}: {f}\n", .{ ip, opcode });
+    } else |_| {
+        try writer.print("0x{x}: <invalid opcode 0x{x:0>2}>\n", .{ ip, byte });
+    }
+}
+
+fn printRegister(self: *Debugger, reg: Register, writer: *Io.Writer) !void {
+    const value = self.vm.regs.get(reg);
+    switch (value) {
+        .float => |v| try writer.print("{s} = {d}\n", .{ @tagName(reg), v }),
+        .double => |v| try writer.print("{s} = {d}\n", .{ @tagName(reg), v }),
+        else => try writer.print("{s} = 0x{x} ({d})\n", .{ @tagName(reg), value.asU64(), value.asU64() }),
+    }
+}
+
+fn printRegisters(self: *Debugger, writer: *Io.Writer) !void {
+    const general = [_]Register{
+        .q0, .q1, .q2,  .q3,  .q4,  .q5,  .q6,  .q7,
+        .q8, .q9, .q10, .q11, .q12, .q13, .q14, .q15,
+    };
+    for (general) |reg| try self.printRegister(reg, writer);
+    for ([_]Register{ .ip, .sp, .bp }) |reg| try self.printRegister(reg, writer);
+    try writer.print("flags: eq={} lt={} carry={} overflow={}\n", .{
+        self.vm.flags.eq,
+        self.vm.flags.lt,
+        self.vm.flags.carry,
+        self.vm.flags.overflow,
+    });
+}
+
+fn dumpMemory(self: *Debugger, addr: usize, len: usize, writer: *Io.Writer) !void {
+    var offset: usize = 0;
+    while (offset < len) : (offset += 16) {
+        try writer.print("0x{x:0>8}:", .{addr + offset});
+        for (offset..@min(offset + 16, len)) |i| {
+            const byte = (self.vm.mmu.read(addr + i, .byte) catch {
+                try writer.writeAll(" ??\n");
+                return;
+            }).asU8();
+            try writer.print(" {x:0>2}", .{byte});
+        }
+        try writer.writeAll("\n");
+    }
+}
+
+fn eql(command: []const u8, long: []const u8, short: []const u8) bool {
+    return mem.eql(u8, command, long) or mem.eql(u8, command, short);
+}
+
+fn parseCount(arg: ?[]const u8, writer: *Io.Writer) !?usize {
+    const text = arg orelse return 1;
+    return fmt.parseInt(usize, text, 0) catch {
+        try writer.print("{s}: not a valid number\n", .{text});
+        return null;
+    };
+}
+
+/// Evaluate `text` as an expression over registers, labels and memory.
+/// `[expr]` reads a qword from memory and arithmetic wraps like the VM.
+pub fn evaluate(self: *Debugger, text: []const u8) !u64 {
+    const filename = "<expression>";
+
+    var reporter = fehler.ErrorReporter.init(self.gpa);
+    defer reporter.deinit();
+    try reporter.addSource(filename, text);
+
+    var interner = StringInterner.init(self.gpa);
+    defer interner.deinit();
+
+    var lexer = Lexer.init(filename, text, &interner, self.gpa);
+    var parser = Parser.init(&lexer, &reporter, self.gpa);
+    defer parser.deinit();
+    parser.exit_on_error = false;
+
+    const expr = try parser.parseStandaloneExpression();
+    return self.evaluateExpr(expr, &interner);
+}
+
+fn evaluateExpr(self: *Debugger, expr: *const ast.Expression, interner: *StringInterner) anyerror!u64 {
+    return switch (expr.*) {
+        .integer_literal => |v| @bitCast(v),
+        .sized_literal => |v| self.evaluateExpr(v.value, interner),
+        .register => |reg| self.vm.regs.get(reg).asU64(),
+        .identifier => |id| blk: {
+            const name = interner.get(id).?;
+            const symbols = self.symbols orelse return error.UnknownSymbol;
+            break :blk symbols.get(name) orelse error.UnknownSymbol;
+        },
+        .unary_op => |v| switch (v.op) {
+            .neg => 0 -% try self.evaluateExpr(v.expr, interner),
+            .bit_not => ~try self.evaluateExpr(v.expr, interner),
+        },
+        .binary_op => |v| blk: {
+            const lhs = try self.evaluateExpr(v.lhs, interner);
+            const rhs = try self.evaluateExpr(v.rhs, interner);
+            break :blk switch (v.op) {
+                .add => lhs +% rhs,
+                .sub => lhs -% rhs,
+                .mul => lhs *% rhs,
+                .div => if (rhs == 0) error.DivisionByZero else lhs / rhs,
+                .bit_or => lhs | rhs,
+                .bit_and => lhs & rhs,
+                .bit_xor => lhs ^ rhs,
+            };
+        },
+        .address => |v| blk: {
+            var addr = try self.evaluateExpr(v.base, interner);
+            if (v.offset) |offset| addr +%= try self.evaluateExpr(offset, interner);
+            break :blk (try self.vm.mmu.read(@intCast(addr), .qword)).asU64();
+        },
+        else => error.UnsupportedExpression,
+    };
+}
+
+fn evaluateArg(self: *Debugger, text: []const u8, writer: *Io.Writer) !?u64 {
+    const trimmed = mem.trim(u8, text, " \t");
+    if (trimmed.len == 0) {
+        try writer.writeAll("expected an expression\n");
+        return null;
+    }
+    return self.evaluate(trimmed) catch |err| {
+        switch (err) {
+            // The parser has already reported the problem.
+            error.ParserError => {},
+            error.UnknownSymbol => try writer.print("{s}: unknown symbol\n", .{trimmed}),
+            else => try writer.print("{s}: {s}\n", .{ trimmed, @errorName(err) }),
+        }
+        return null;
+    };
+}
@@ -0,0 +1,142 @@
+const std = @import("std");
+const testing = std.testing;
+const Io = std.Io;
+const Debugger = @import("Debugger.zig");
+const LineEditor = @import("../LineEditor.zig");
+const ProgramBuilder = @import("../vm/testing.zig").ProgramBuilder;
+
+fn buildCounter(b: *ProgramBuilder) !void {
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(5);
+    try b.op(.mov_addr_reg);
+    try b.reg(.q0);
+    try b.addrImm(0x2000, 0);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(0);
+    try b.op(.hlt);
+}
+
+test "reverse step restores registers and memory" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+    try buildCounter(&b);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.enableJournal(16);
+
+    try vm.run();
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q0).asU64());
+    try testing.expectEqual(@as(u64, 5), (try vm.mmu.read(0x2000, .qword)).asU64());
+
+    try testing.expect(try vm.reverseStep());
+    try testing.expect(!vm.halted);
+    try testing.expect(try vm.reverseStep());
+    try testing.expectEqual(@as(u64, 5), vm.regs.get(.q0).asU64());
+    try testing.expect(try vm.reverseStep());
+    try testing.expectEqual(@as(u64, 0), (try vm.mmu.read(0x2000, .qword)).asU64());
+    try testing.expect(try vm.reverseStep());
+    try testing.expectEqual(@as(usize, 0), vm.regs.ip());
+    try testing.expect(!try vm.reverseStep());
+}
+
+test "journal is bounded" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+    try buildCounter(&b);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.enableJournal(2);
+
+    try vm.run();
+    try testing.expect(try vm.reverseStep());
+    try testing.expect(try vm.reverseStep());
+    try testing.expect(!try vm.reverseStep());
+    try testing.expectEqual(@as(u64, 5), vm.regs.get(.q0).asU64());
+}
+
+test "debugger commands" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+    try buildCounter(&b);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.enableJournal(16);
+
+    var debugger = Debugger.init(&vm, null, testing.allocator);
+    defer debugger.deinit();
+
+    var output: Io.Writer.Allocating = .init(testing.allocator);
+    defer output.deinit();
+
+    try testing.expect(try debugger.execute("step 2", &output.writer));
+    try testing.expect(try debugger.execute("print q0", &output.writer));
+    try testing.expect(try debugger.execute("rs", &output.writer));
+    try testing.expect(try debugger.execute("bogus", &output.writer));
+    try testing.expect(!try debugger.execute("quit", &output.writer));
+
+    try testing.expect(std.mem.indexOf(u8, output.written(), "q0 = 0x5 (5)") != null);
+    try testing.expect(std.mem.indexOf(u8, output.written(), "unknown command: bogus") != null);
+}
+
+test "line editor completion and history" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+    try buildCounter(&b);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+
+    var symbols = Debugger.Symbols.init(testing.allocator);
+    defer symbols.deinit();
+    try symbols.put("main_loop", 0x10);
+
+    var debugger = Debugger.init(&vm, &symbols, testing.allocator);
+    defer debugger.deinit();
+
+    var reader = Io.Reader.fixed("rev\t\rb mai\t\r\x1b[A\x1b[A\r");
+    var output: Io.Writer.Allocating = .init(testing.allocator);
+    defer output.deinit();
+
+    var editor = LineEditor.init(testing.allocator, &reader, &output.writer, true);
+    defer editor.deinit();
+    editor.completer = debugger.completer();
+
+    try testing.expectEqualStrings("reverse-step ", (try editor.readLine("> ")).?);
+    try editor.addHistory("reverse-step ");
+    try testing.expectEqualStrings("b main_loop ", (try editor.readLine("> ")).?);
+    try editor.addHistory("b main_loop ");
+    try testing.expectEqualStrings("reverse-step", (try editor.readLine("> ")).?);
+    try testing.expectEqual(@as(?[]const u8, null), try editor.readLine("> "));
+}
+
+test "debugger expressions" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+    try buildCounter(&b);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    var symbols = Debugger.Symbols.init(testing.allocator);
+    defer symbols.deinit();
+    try symbols.put("buffer", 0x1FF8);
+
+    var debugger = Debugger.init(&vm, &symbols, testing.allocator);
+    defer debugger.deinit();
+
+    vm.regs.set(.q1, .{ .qword = 1 });
+    try testing.expectEqual(@as(u64, 5), try debugger.evaluate("[buffer + q1*8]"));
+    try testing.expectEqual(@as(u64, 0x2008), try debugger.evaluate("buffer+0x10"));
+    try testing.expectError(error.UnknownSymbol, debugger.evaluate("missing"));
+
+    var output: Io.Writer.Allocating = .init(testing.allocator);
+    defer output.deinit();
+    try testing.expect(try debugger.execute("break buffer + 8", &output.writer));
+    try testing.expect(debugger.breakpoints.contains(0x2000));
+}
@@ -0,0 +1,348 @@
+//! Instruction-level comparison of two programs behind `nyx diff`.
+//!
+//! Each program's text section is split at its symbols and symbols with the
+//! same name are compared instruction by instruction, so growing one routine
+//! does not show up as a change in every routine after it. Jump and call
+//! targets are written relative to the symbol they land in for the same
+//! reason. Data symbols are compared byte for byte.
+//!
+//! Bytecode built with `-g` or `--symbols` carries its labels in the debug
+//! section, which is used when no symbols are given. Without any the whole
+//! text section is compared as one block named `text` and the data section as
+//! one named `data`.
+
+const std = @import("std");
+const Allocator = std.mem.Allocator;
+const ArrayList = std.array_list.Managed;
+const Io = std.Io;
+const mem = std.mem;
+const Disassembler = @import("disassembler/Disassembler.zig");
+const Instruction = Disassembler.Instruction;
+const features = @import("vm/features.zig");
+const DebugInfo = @import("vm/DebugInfo.zig");
+
+/// Absolute address of every label, as filled in by
+/// `Compiler.exportSymbols`.
+pub const Symbols = std.StringArrayHashMap(u64);
+
+pub const Program = struct {
+    bytecode: []const u8,
+    /// Labels to split at. Read from the debug section of `bytecode` when
+    /// null.
+    symbols: ?*const Symbols = null,
+    /// Size of the text section, if known. Otherwise it is read from the
+    /// header, or taken to end at the first byte that is not an instruction.
+    text_len: ?usize = null,
+};
+
+pub const Summary = struct {
+    changed: usize = 0,
+    added: usize = 0,
+    removed: usize = 0,
+    unchanged: usize = 0,
+
+    pub fn identical(self: Summary) bool {
+        return self.changed == 0 and self.added == 0 and self.removed == 0;
+    }
+};
+
+const Block = struct {
+    name: []const u8,
+    section: enum { text, data },
+    start: usize,
+    end: usize,
+    /// Instructions of a text block, each written out, with its address.
+    lines: []const Line = &.{},
+};
+
+const Line = struct {
+    text: []const u8,
+    addr: usize,
+};
+
+/// Write what changed from `old` to `new` to `writer`, one symbol at a time.
+pub fn compare(gpa: Allocator, old: Program, new: Program, writer: *Io.Writer) !Summary {
+    var arena = std.heap.ArenaAllocator.init(gpa);
+    defer arena.deinit();
+
+    const old_disasm = try Disassembler.init(old.bytecode, gpa);
+    const new_disasm = try Disassembler.init(new.bytecode, gpa);
+    const old_blocks = try split(arena.allocator(), &old_disasm, try withEmbeddedSymbols(arena.allocator(), old, &old_disasm));
+    const new_blocks = try split(arena.allocator(), &new_disasm, try withEmbeddedSymbols(arena.allocator(), new, &new_disasm));
+
+    if (!old_disasm.required.eql(new_disasm.required)) {
+        try writer.writeAll("required features: ");
+        try writeFeatures(old_disasm.required, writer);
+        try writer.writeAll(" -> ");
+        try writeFeatures(new_disasm.required, writer);
+        try writer.writeByte('\n');
+    }
+
+    var summary: Summary = .{};
+    for (new_blocks) |block| {
+        const before = find(old_blocks, block) orelse {
+            try writer.print("added {s}{s}: {f}\n", .{ prefix(block), block.name, sizeOf(block) });
+            summary.added += 1;
+            continue;
+        };
+        const same = switch (block.section) {
+            .text => try compareText(arena.allocator(), before, block, writer),
+            .data => try compareData(&old_disasm, &new_disasm, before, block, writer),
+        };
+        if (same) summary.unchanged += 1 else summary.changed += 1;
+    }
+    for (old_blocks) |block| {
+        if (find(new_blocks, block) != null) continue;
+        try writer.print("removed {s}{s}: {f}\n", .{ prefix(block), block.name, sizeOf(block) });
+        summary.removed += 1;
+    }
+
+    try writer.print("{d} changed, {d} added, {d} removed, {d} unchanged\n", .{
+        summary.changed,
+        summary.added,
+        summary.removed,
+        summary.unchanged,
+    });
+    return summary;
+}
+
+fn writeFeatures(set: features.Set, writer: *Io.Writer) !void {
+    if (set.count() == 0) return writer.writeAll("none");
+    try features.writeNames(set, writer);
+}
+
+/// `program` with the labels from its debug section when it was given none.
+/// A missing or unreadable section leaves it without symbols.
+fn withEmbeddedSymbols(arena: Allocator, program: Program, disasm: *const Disassembler) !Program {
+    var result = program;
+    if (result.text_len == null) result.text_len = disasm.text_len;
+    if (program.symbols != null) return result;
+
+    const header = features.read(program.bytecode) catch return result;
+    const section = header.debugSection(program.bytecode) orelse return result;
+    var info = DebugInfo.read(arena, section) catch return result;
+    defer info.deinit();
+
+    const map = try arena.create(Symbols);
+    map.* = .init(arena);
+    for (info.symbols) |symbol| try map.put(try arena.dupe(u8, symbol.name), symbol.addr);
+    result.symbols = map;
+    return result;
+}
+
+/// Split `disasm`'s program into blocks that start at each symbol.
+fn split(arena: Allocator, disasm: *const Disassembler, program: Program) ![]Block {
+    var instructions = ArrayList(Instruction).init(arena);
+    const decoded_end = try disasm.decodeText(&instructions);
+    const text_end = if (program.text_len) |len| @min(len, disasm.code.len) else decoded_end;
+
+    const Start = struct { name: []const u8, addr: usize };
+    var starts = ArrayList(Start).init(arena);
+    if (program.symbols) |map| {
+        var iter = map.iterator();
+        while (iter.next()) |entry| {
+            const addr: usize = @intCast(entry.value_ptr.*);
+            if (addr <= disasm.code.len) try starts.append(.{ .name = entry.key_ptr.*, .addr = addr });
+        }
+    }
+    mem.sort(Start, starts.items, {}, struct {
+        fn lessThan(_: void, a: Start, b: Start) bool {
+            return a.addr < b.addr;
+        }
+    }.lessThan);
+
+    var blocks = ArrayList(Block).init(arena);
+    // Code before the first symbol, or all of it without symbols.
+    const first_text = if (starts.items.len > 0) @min(starts.items[0].addr, text_end) else text_end;
+    if (first_text > 0) try blocks.append(.{ .name = "text", .section = .text, .start = 0, .end = first_text });
+    const first_data = for (starts.items) |start| {
+        if (start.addr >= text_end) break start.addr;
+    } else disasm.code.len;
+    if (first_data > text_end) try blocks.append(.{ .name = "data", .section = .data, .start = text_end, .end = first_data });
+
+    for (starts.items, 0..) |start, i| {
+        const section: @FieldType(Block, "section") = if (start.addr < text_end) .text else .data;
+        const limit = if (section == .text) text_end else disasm.code.len;
+        const next = if (i + 1 < starts.items.len) starts.items[i + 1].addr else limit;
+        try blocks.append(.{ .name = start.name, .section = section, .start = start.addr, .end = @min(next, limit) });
+    }
+
+    for (blocks.items) |*block| {
+        if (block.section != .text) continue;
+        var lines = ArrayList(Line).init(arena);
+        for (instructions.items) |inst| {
+            if (inst.addr < block.start or inst.addr + inst.len > block.end) continue;
+            try lines.append(.{ .text = try writeInstruction(arena, disasm, inst, blocks.items), .addr = inst.addr });
+        }
+        block.lines = try lines.toOwnedSlice();
+    }
+    return blocks.toOwnedSlice();
+}
+
+/// Write `inst` with its jump or call target named after the block it lands
+/// in, so the text stays the same when only addresses move.
+fn writeInstruction(arena: Allocator, disasm: *const Disassembler, inst: Instruction, blocks: []const Block) ![]const u8 {
+    var named = inst;
+    for (named.operands[0..named.operand_count]) |*value| switch (value.*) {
+        .target => |target| for (blocks) |block| {
+            if (block.section != .text or target < block.start or target >= block.end) continue;
+            value.* = .{ .external = if (target == block.start)
+                block.name
+            else
+                try std.fmt.allocPrint(arena, "{s}+0x{x}", .{ block.name, target - block.start }) };
+            break;
+        },
+        else => {},
+    };
+
+    var labels = std.AutoHashMap(u64, void).init(arena);
+    var out: Io.Writer.Allocating = .init(arena);
+    try disasm.writeInstruction(&out.writer, named, &labels);
+    return out.written();
+}
+
+fn find(blocks: []const Block, like: Block) ?Block {
+    for (blocks) |block| {
+        if (block.section == like.section and mem.eql(u8, block.name, like.name)) return block;
+    }
+    return null;
+}
+
+fn prefix(block: Block) []const u8 {
+    return switch (block.section) {
+        .text => "",
+        .data => "data ",
+    };
+}
+
+const Size = struct {
+    block: Block,
+
+    pub fn format(self: Size, writer: *Io.Writer) Io.Writer.Error!void {
+        switch (self.block.section) {
+            .text => try writer.print("{d} instructions", .{self.block.lines.len}),
+            .data => try writer.print("{d} bytes", .{self.block.end - self.block.start}),
+        }
+    }
+};
+
+fn sizeOf(block: Block) Size {
+    return .{ .block = block };
+}
+
+/// Print the instructions removed from and added to a text block, using the
+/// longest common subsequence of the two. Returns whether they are the same.
+fn compareText(arena: Allocator, old: Block, new: Block, writer: *Io.Writer) !bool {
+    const n = old.lines.len;
+    const m = new.lines.len;
+    const old_kept = try arena.alloc(bool, n);
+    const new_kept = try arena.alloc(bool, m);
+    @memset(old_kept, false);
+    @memset(new_kept, false);
+    const rows: Rows = .{ .forward = try arena.alloc(usize, m + 1), .backward = try arena.alloc(usize, m + 1) };
+    markCommon(old.lines, new.lines, old_kept, new_kept, rows);
+
+    const common = mem.count(bool, old_kept, &.{true});
+    if (common == n and n == m) return true;
+
+    try writer.print("changed {s}: {d} removed, {d} added\n", .{ new.name, n - common, m - common });
+    var i: usize = 0;
+    var j: usize = 0;
+    while (i < n or j < m) {
+        if (i < n and !old_kept[i]) {
+            try writeChange(writer, '-', old.lines[i]);
+            i += 1;
+        } else if (j < m and !new_kept[j]) {
+            try writeChange(writer, '+', new.lines[j]);
+            j += 1;
+        } else {
+            i += 1;
+            j += 1;
+        }
+    }
+    return false;
+}
+
+/// Two rows of the longest common subsequence table, each one longer than
+/// the new block. They are all `markCommon` keeps of the table.
+const Rows = struct {
+    forward: []usize,
+    backward: []usize,
+};
+
+/// Mark the lines of `old` and `new` that are in a longest common
+/// subsequence of the two, with Hirschberg's algorithm: the lengths for the
+/// first half of `old` against every prefix of `new` and for the second half
+/// against every suffix pick where `new` splits, and each half is solved on
+/// its own. Uses no memory beyond `rows`.
+fn markCommon(old: []const Line, new: []const Line, old_kept: []bool, new_kept: []bool, rows: Rows) void {
+    if (old.len == 0 or new.len == 0) return;
+    if (old.len == 1) {
+        for (new, 0..) |line, j| {
+            if (!mem.eql(u8, old[0].text, line.text)) continue;
+            old_kept[0] = true;
+            new_kept[j] = true;
+            return;
+        }
+        return;
+    }
+
+    const mid = old.len / 2;
+    const forward = rows.forward[0 .. new.len + 1];
+    const backward = rows.backward[0 .. new.len + 1];
+    commonLengths(old[0..mid], new, forward, .forward);
+    commonLengths(old[mid..], new, backward, .backward);
+    // forward[k] + backward[new.len - k] is the longest common subsequence
+    // that takes new[0..k] for the first half of `old`.
+    var split_at: usize = 0;
+    var best: usize = 0;
+    for (0..new.len + 1) |k| {
+        const total = forward[k] + backward[new.len - k];
+        if (total > best) {
+            best = total;
+            split_at = k;
+        }
+    }
+
+    markCommon(old[0..mid], new[0..split_at], old_kept[0..mid], new_kept[0..split_at], rows);
+    markCommon(old[mid..], new[split_at..], old_kept[mid..], new_kept[split_at..], rows);
+}
+
+/// Fill `row[j]` with the length of the longest common subsequence of `old`
+/// and the first `j` lines of `new`, or with `.backward` of the last `j`
+/// lines of `new` and `old` read from its end.
+fn commonLengths(old: []const Line, new: []const Line, row: []usize, comptime direction: enum { forward, backward }) void {
+    @memset(row, 0);
+    for (0..old.len) |step| {
+        const a = if (direction == .forward) old[step] else old[old.len - 1 - step];
+        // row[j - 1] before this line's update.
+        var diagonal: usize = 0;
+        for (1..new.len + 1) |j| {
+            const b = if (direction == .forward) new[j - 1] else new[new.len - j];
+            const above = row[j];
+            row[j] = if (mem.eql(u8, a.text, b.text)) diagonal + 1 else @max(above, row[j - 1]);
+            diagonal = above;
+        }
+    }
+}
+
+fn writeChange(writer: *Io.Writer, sign: u8, line: Line) !void {
+    try writer.print("    {c} {s}", .{ sign, line.text });
+    try writer.splatByteAll(' ', 40 -| (line.text.len + 6) + 1);
+    try writer.print("; 0x{x:0>4}\n", .{line.addr});
+}
+
+/// Print whether the bytes of a data block changed. Returns whether they are
+/// the same.
+fn compareData(old_disasm: *const Disassembler, new_disasm: *const Disassembler, old: Block, new: Block, writer: *Io.Writer) !bool {
+    const before = old_disasm.code[old.start..old.end];
+    const after = new_disasm.code[new.start..new.end];
+    if (mem.eql(u8, before, after)) return true;
+    if (before.len != after.len) {
+        try writer.print("changed data {s}: {d} -> {d} bytes\n", .{ new.name, before.len, after.len });
+    } else {
+        const offset = mem.indexOfDiff(u8, before, after).?;
+        try writer.print("changed data {s}: first differs at byte {d}\n", .{ new.name, offset });
+    }
+    return false;
+}
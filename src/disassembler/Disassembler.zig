@@ -0,0 +1,348 @@
+//! Decodes bytecode back into assembly.
+//!
+//! Operand layouts come from `Opcode.operands`, next to the opcode
+//! definitions, so the disassembler and the VM agree on instruction lengths.
+//! The bytecode does not record where the text section ends, so decoding
+//! stops at the first byte that is not a valid instruction and everything from
+//! there on is printed as data.
+
+const std = @import("std");
+const Io = std.Io;
+const mem = std.mem;
+const Allocator = mem.Allocator;
+const ArrayList = std.array_list.Managed;
+const Opcode = @import("../compiler/opcode.zig").Opcode;
+const Register = @import("../vm/register.zig").Register;
+const DataSize = @import("../parser/immediate.zig").DataSize;
+const Immediate = @import("../parser/immediate.zig").Immediate;
+const features = @import("../vm/features.zig");
+const addressing_variant_1 = @import("../compiler/Compiler.zig").addressing_variant_1;
+const addressing_variant_2 = @import("../compiler/Compiler.zig").addressing_variant_2;
+
+const Disassembler = @This();
+
+pub const max_operands = 3;
+
+/// Column at which the address comments start.
+const comment_column = 40;
+
+pub const Address = struct {
+    base: Base,
+    offset: i64,
+
+    pub const Base = union(enum) {
+        register: Register,
+        absolute: u64,
+    };
+};
+
+pub const Value = union(enum) {
+    register: Register,
+    size: DataSize,
+    immediate: Immediate,
+    address: Address,
+    target: u64,
+    external: []const u8,
+    extension: Extension,
+};
+
+/// An extended instruction, whose operands are only known to the code that
+/// registered its subcode, so they are kept as bytes.
+pub const Extension = struct {
+    subcode: u8,
+    operands: []const u8,
+};
+
+pub const Instruction = struct {
+    addr: usize,
+    len: usize,
+    opcode: Opcode,
+    operands: [max_operands]Value,
+    operand_count: usize,
+
+    pub fn values(self: *const Instruction) []const Value {
+        return self.operands[0..self.operand_count];
+    }
+};
+
+code: []const u8,
+entry: u64,
+required: features.Set,
+/// Where the data section starts, when the header records it.
+text_len: ?usize,
+/// Retired opcode numbers that are decoded as their replacement.
+aliases: []const Opcode.Alias = &Opcode.aliases,
+gpa: Allocator,
+
+/// `bytecode` is a complete program image, starting with the entry point.
+pub fn init(bytecode: []const u8, gpa: Allocator) !Disassembler {
+    if (bytecode.len < 8) return error.ProgramTooSmall;
+    // A header that claims a layout it is too short to hold is shown as an
+    // unknown feature bit rather than refused.
+    const header = features.read(bytecode) catch features.decode(mem.readInt(u64, bytecode[0..8], .little));
+    const code = header.program(bytecode);
+    return Disassembler{
+        .code = code,
+        .entry = header.entry,
+        .required = header.required,
+        .text_len = if (header.layout) |layout| @min(layout.text_len, code.len) else null,
+        .gpa = gpa,
+    };
+}
+
+/// Decode the instruction starting at `addr`.
+pub fn decode(self: *const Disassembler, addr: usize) !Instruction {
+    var cursor = Cursor{ .bytes = self.code, .pos = addr };
+    const opcode = try Opcode.fromU8OrAlias(try cursor.byte(), self.aliases);
+
+    var inst = Instruction{
+        .addr = addr,
+        .len = 0,
+        .opcode = opcode,
+        .operands = undefined,
+        .operand_count = 0,
+    };
+    var first_reg: ?Register = null;
+    var size: ?DataSize = null;
+
+    for (opcode.operands()) |operand| {
+        const value: Value = switch (operand) {
+            .reg => blk: {
+                const reg = try Register.fromU8(try cursor.byte());
+                if (first_reg == null) first_reg = reg;
+                break :blk .{ .register = reg };
+            },
+            .size => blk: {
+                size = try DataSize.fromU8(try cursor.byte());
+                break :blk .{ .size = size.? };
+            },
+            .imm => .{ .immediate = try cursor.immediate(DataSize.fromRegister(first_reg.?)) },
+            .sized_imm => .{ .immediate = try cursor.immediate(size.?) },
+            .addr => .{ .address = try cursor.address() },
+            .target => .{ .target = try cursor.int(u64) },
+            .rel8 => .{ .target = Opcode.relativeTarget(cursor.pos + 1, try cursor.int(i8)) },
+            .rel32 => .{ .target = Opcode.relativeTarget(cursor.pos + 4, try cursor.int(i32)) },
+            .qword => .{ .immediate = try cursor.immediate(.qword) },
+            .external => .{ .external = try cursor.external() },
+            .extension => .{ .extension = try cursor.extension() },
+        };
+        inst.operands[inst.operand_count] = value;
+        inst.operand_count += 1;
+    }
+
+    inst.len = cursor.pos - addr;
+    return inst;
+}
+
+/// Decode instructions from address 0 until the end of the text section, or
+/// the first invalid one if the header does not say where that is. Returns
+/// the address where decoding stopped, which is taken as the start of the
+/// data section.
+pub fn decodeText(self: *const Disassembler, instructions: *ArrayList(Instruction)) !usize {
+    const end = self.text_len orelse self.code.len;
+    var addr: usize = 0;
+    while (addr < end) {
+        const inst = self.decode(addr) catch break;
+        if (addr + inst.len > end) break;
+        try instructions.append(inst);
+        addr += inst.len;
+    }
+    return addr;
+}
+
+/// Write the whole program as assembly, naming every jump and call target.
+pub fn disassemble(self: *const Disassembler, writer: *Io.Writer) !void {
+    var instructions = ArrayList(Instruction).init(self.gpa);
+    defer instructions.deinit();
+    const text_end = try self.decodeText(&instructions);
+
+    var starts = std.AutoHashMap(u64, void).init(self.gpa);
+    defer starts.deinit();
+    for (instructions.items) |inst| try starts.put(inst.addr, {});
+
+    // Only targets that land on an instruction can be given a label.
+    var labels = std.AutoHashMap(u64, void).init(self.gpa);
+    defer labels.deinit();
+    if (starts.contains(self.entry)) try labels.put(self.entry, {});
+    for (instructions.items) |inst| {
+        for (inst.values()) |value| switch (value) {
+            .target => |target| if (starts.contains(target)) try labels.put(target, {}),
+            else => {},
+        };
+    }
+
+    if (self.required.count() > 0) {
+        try writer.writeAll(".requires ");
+        try features.writeNames(self.required, writer);
+        try writer.writeAll("\n\n");
+    }
+
+    if (!labels.contains(self.entry) and self.entry != 0) {
+        try writer.print(".entry 0x{x}\n\n", .{self.entry});
+    }
+
+    var line: Io.Writer.Allocating = .init(self.gpa);
+    defer line.deinit();
+
+    try writer.writeAll(".section text\n");
+    for (instructions.items) |inst| {
+        if (labels.contains(inst.addr)) {
+            try self.writeLabel(writer, inst.addr);
+            try writer.writeAll(":\n");
+        }
+        line.clearRetainingCapacity();
+        try self.writeInstruction(&line.writer, inst, &labels);
+        try writeLine(writer, line.written(), inst.addr);
+    }
+
+    if (text_end < self.code.len) {
+        try writer.writeAll("\n.section data\n");
+        var addr = text_end;
+        while (addr < self.code.len) : (addr += 16) {
+            line.clearRetainingCapacity();
+            try line.writer.writeAll("db ");
+            for (self.code[addr..@min(addr + 16, self.code.len)], 0..) |b, i| {
+                if (i > 0) try line.writer.writeAll(", ");
+                try line.writer.print("0x{x:0>2}", .{b});
+            }
+            try writeLine(writer, line.written(), addr);
+        }
+    }
+}
+
+/// Write `inst` in assembler syntax, without a trailing newline.
+pub fn writeInstruction(
+    self: *const Disassembler,
+    writer: *Io.Writer,
+    inst: Instruction,
+    labels: *const std.AutoHashMap(u64, void),
+) !void {
+    try writer.print("{f}", .{inst.opcode});
+    const values = inst.values();
+    switch (inst.opcode) {
+        // Memory destinations are encoded after the source but written first.
+        .mov_addr_reg => try self.writeOperands(writer, &.{ values[1], values[0] }, labels),
+        .mov_addr_imm, .mov_addr_addr => try self.writeOperands(writer, &.{ values[0], values[2], values[1] }, labels),
+        else => try self.writeOperands(writer, values, labels),
+    }
+}
+
+fn writeOperands(
+    self: *const Disassembler,
+    writer: *Io.Writer,
+    values: []const Value,
+    labels: *const std.AutoHashMap(u64, void),
+) !void {
+    var separator: []const u8 = " ";
+    for (values) |value| {
+        try writer.writeAll(separator);
+        separator = ", ";
+        switch (value) {
+            .register => |reg| try writer.writeAll(@tagName(reg)),
+            .size => |size| {
+                // A size keyword prefixes the operand that follows it.
+                try writer.writeAll(@tagName(size));
+                separator = " ";
+            },
+            .immediate => |imm| switch (imm) {
+                .byte => |v| try writer.print("{d}", .{@as(i8, @bitCast(v))}),
+                .word => |v| try writer.print("{d}", .{@as(i16, @bitCast(v))}),
+                .dword => |v| try writer.print("{d}", .{@as(i32, @bitCast(v))}),
+                .qword => |v| try writer.print("{d}", .{@as(i64, @bitCast(v))}),
+                .float => |v| try writer.print("{d}", .{v}),
+                .double => |v| try writer.print("{d}", .{v}),
+            },
+            .address => |addr| {
+                switch (addr.base) {
+                    .register => |reg| try writer.print("[{s}", .{@tagName(reg)}),
+                    .absolute => |base| try writer.print("[0x{x}", .{base}),
+                }
+                if (addr.offset != 0) try writer.print(", {d}", .{addr.offset});
+                try writer.writeByte(']');
+            },
+            .target => |target| if (labels.contains(target))
+                try self.writeLabel(writer, target)
+            else
+                try writer.print("0x{x}", .{target}),
+            .external => |name| try writer.writeAll(name),
+            // Written so that assembling the line gives the same bytes.
+            .extension => |ext| {
+                try writer.print("0x{x:0>2}", .{ext.subcode});
+                for (ext.operands) |b| try writer.print(", {d}u8", .{b});
+            },
+        }
+    }
+}
+
+fn writeLabel(self: *const Disassembler, writer: *Io.Writer, addr: u64) !void {
+    if (addr == self.entry) {
+        try writer.writeAll("_start");
+    } else {
+        try writer.print("L_{x:0>4}", .{addr});
+    }
+}
+
+fn writeLine(writer: *Io.Writer, text: []const u8, addr: usize) !void {
+    try writer.print("    {s}", .{text});
+    try writer.splatByteAll(' ', comment_column -| (text.len + 4) + 1);
+    try writer.print("; 0x{x:0>4}\n", .{addr});
+}
+
+const Cursor = struct {
+    bytes: []const u8,
+    pos: usize,
+
+    fn byte(self: *Cursor) !u8 {
+        if (self.pos >= self.bytes.len) return error.TruncatedInstruction;
+        defer self.pos += 1;
+        return self.bytes[self.pos];
+    }
+
+    fn int(self: *Cursor, comptime T: type) !T {
+        const n = @sizeOf(T);
+        if (self.pos + n > self.bytes.len) return error.TruncatedInstruction;
+        defer self.pos += n;
+        return mem.readInt(T, self.bytes[self.pos..][0..n], .little);
+    }
+
+    fn immediate(self: *Cursor, size: DataSize) !Immediate {
+        return switch (size) {
+            .byte => .{ .byte = try self.byte() },
+            .word => .{ .word = try self.int(u16) },
+            .dword => .{ .dword = try self.int(u32) },
+            .qword => .{ .qword = try self.int(u64) },
+            .float => .{ .float = @bitCast(try self.int(u32)) },
+            .double => .{ .double = @bitCast(try self.int(u64)) },
+        };
+    }
+
+    fn address(self: *Cursor) !Address {
+        const base: Address.Base = switch (try self.byte()) {
+            addressing_variant_1 => .{ .register = try Register.fromU8(try self.byte()) },
+            addressing_variant_2 => .{ .absolute = try self.int(u64) },
+            else => return error.UnknownAddressingVariant,
+        };
+        return .{ .base = base, .offset = @bitCast(try self.int(u64)) };
+    }
+
+    /// Read an external call: the function name, its return type, the fixed
+    /// and total argument counts, and one type per argument.
+    fn external(self: *Cursor) ![]const u8 {
+        const start = self.pos;
+        const end = mem.indexOfScalarPos(u8, self.bytes, start, 0) orelse return error.TruncatedInstruction;
+        self.pos = end + 1;
+        _ = try self.byte();
+        _ = try self.byte();
+        const total = try self.byte();
+        for (0..total) |_| _ = try self.byte();
+        return self.bytes[start..end];
+    }
+
+    fn extension(self: *Cursor) !Extension {
+        const subcode = try self.byte();
+        const len = try self.byte();
+        if (self.pos + len > self.bytes.len) return error.TruncatedInstruction;
+        defer self.pos += len;
+        return .{ .subcode = subcode, .operands = self.bytes[self.pos..][0..len] };
+    }
+};
@@ -0,0 +1,357 @@
+const std = @import("std");
+const testing = std.testing;
+const Io = std.Io;
+const Disassembler = @import("Disassembler.zig");
+const Opcode = @import("../compiler/opcode.zig").Opcode;
+const Register = @import("../vm/register.zig").Register;
+const Vm = @import("../vm/Vm.zig");
+const vm_testing = @import("../vm/testing.zig");
+const ProgramBuilder = vm_testing.ProgramBuilder;
+const nyx = @import("../root.zig");
+
+fn disassemble(b: *ProgramBuilder) ![]u8 {
+    const program = try b.finish();
+    defer testing.allocator.free(program);
+
+    const disassembler = try Disassembler.init(program, testing.allocator);
+    var output: Io.Writer.Allocating = .init(testing.allocator);
+    errdefer output.deinit();
+    try disassembler.disassemble(&output.writer);
+    return output.toOwnedSlice();
+}
+
+fn expectLine(text: []const u8, line: []const u8) !void {
+    var lines = std.mem.splitScalar(u8, text, '\n');
+    while (lines.next()) |l| {
+        const code = std.mem.trim(u8, if (std.mem.indexOfScalar(u8, l, ';')) |i| l[0..i] else l, " ");
+        if (std.mem.eql(u8, code, line)) return;
+    }
+    std.debug.print("missing line \"{s}\" in:\n{s}\n", .{ line, text });
+    return error.TestExpectedEqual;
+}
+
+test "disassemble operands and labels" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(@bitCast(@as(i64, -3)));
+    const loop = b.here();
+    try b.op(.mov_addr_reg);
+    try b.reg(.q0);
+    try b.addrReg(.sp, 8);
+    try b.op(.mov_addr_imm);
+    try b.size(.dword);
+    try b.dword(42);
+    try b.addrImm(0x2000, 0);
+    try b.op(.push_reg);
+    try b.size(.qword);
+    try b.reg(.q1);
+    try b.op(.add_reg_reg_imm);
+    try b.reg(.b2);
+    try b.reg(.b2);
+    try b.byte(0xFF);
+    try b.op(.jne_imm);
+    try b.qword(loop);
+    try b.op(.hlt);
+    try b.raw("\xff\xfe");
+
+    const text = try disassemble(&b);
+    defer testing.allocator.free(text);
+
+    try expectLine(text, "_start:");
+    try expectLine(text, "mov q0, -3");
+    try expectLine(text, "L_000a:");
+    try expectLine(text, "mov [sp, 8], q0");
+    try expectLine(text, "mov dword [0x2000], 42");
+    try expectLine(text, "push qword q1");
+    try expectLine(text, "add b2, b2, -1");
+    try expectLine(text, "jne L_000a");
+    try expectLine(text, "hlt");
+    try expectLine(text, ".section data");
+    try expectLine(text, "db 0xff, 0xfe");
+}
+
+test "every opcode has a decodable layout" {
+    inline for (std.meta.fields(Opcode)) |field| {
+        const opcode: Opcode = @enumFromInt(field.value);
+        var b = ProgramBuilder.init(testing.allocator);
+        defer b.deinit();
+
+        try b.op(opcode);
+        for (opcode.operands()) |operand| switch (operand) {
+            .reg => try b.reg(.q0),
+            .size => try b.size(.qword),
+            .imm, .sized_imm, .target, .qword => try b.qword(0),
+            .rel8 => try b.byte(0),
+            .rel32 => try b.dword(0),
+            .addr => try b.addrReg(.q1, 0),
+            .external => try b.raw("puts\x00\x00\x00\x00"),
+            .extension => try b.raw("\x80\x02\x01\x02"),
+        };
+
+        const program = try b.finish();
+        defer testing.allocator.free(program);
+        const disassembler = try Disassembler.init(program, testing.allocator);
+        const inst = try disassembler.decode(0);
+        try testing.expectEqual(opcode, inst.opcode);
+        try testing.expectEqual(b.bytes.items.len, inst.len);
+    }
+}
+
+test "extended instructions assemble back to the same bytes" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    ext 0x80, q1, [buffer + 8], 7u16, buffer
+        \\    ext 0x81
+        \\    hlt
+        \\.section data
+        \\buffer:
+        \\    dq 0
+        \\    dq 0
+    , .{});
+    defer testing.allocator.free(bytecode);
+    // A register, an address, a word and a label.
+    try testing.expectEqual(@as(u8, 1 + 17 + 2 + 8), bytecode[16 + 2]);
+
+    const disassembler = try Disassembler.init(bytecode, testing.allocator);
+    var output: Io.Writer.Allocating = .init(testing.allocator);
+    defer output.deinit();
+    try disassembler.disassemble(&output.writer);
+    try expectLine(output.written(), "ext 0x81");
+
+    const again = try nyx.assemble(testing.io, testing.allocator, output.written(), .{});
+    defer testing.allocator.free(again);
+    try testing.expectEqualSlices(u8, bytecode, again);
+}
+
+/// Append `count` random straight-line instructions and a final `hlt`.
+/// Arithmetic traps on overflow, so only moves, bitwise operations and
+/// comparisons are used. Immediates stay small enough to be written back as
+/// decimal literals.
+fn randomProgram(b: *ProgramBuilder, random: std.Random, count: usize) !void {
+    const regs = [_]Register{ .q0, .q1, .q2, .q3, .q4, .q5, .q6, .q7 };
+    const logic = [_][2]Opcode{
+        .{ .and_reg_reg_reg, .and_reg_reg_imm },
+        .{ .or_reg_reg_reg, .or_reg_reg_imm },
+        .{ .xor_reg_reg_reg, .xor_reg_reg_imm },
+    };
+    const shifts = [_]Opcode{ .shl_reg_reg_imm, .shr_reg_reg_imm, .rol_reg_reg_imm, .ror_reg_reg_imm };
+
+    for (0..count) |_| {
+        const dest = regs[random.uintLessThan(usize, regs.len)];
+        const src = regs[random.uintLessThan(usize, regs.len)];
+        const imm: u64 = @bitCast(@as(i64, random.int(i32)));
+        switch (random.uintLessThan(u8, 6)) {
+            0 => {
+                try b.op(.mov_reg_imm);
+                try b.reg(dest);
+                try b.qword(imm);
+            },
+            1 => {
+                try b.op(.mov_reg_reg);
+                try b.reg(dest);
+                try b.reg(src);
+            },
+            2 => {
+                const pair = logic[random.uintLessThan(usize, logic.len)];
+                const with_imm = random.boolean();
+                try b.op(if (with_imm) pair[1] else pair[0]);
+                try b.reg(dest);
+                try b.reg(src);
+                if (with_imm) try b.qword(imm) else try b.reg(regs[random.uintLessThan(usize, regs.len)]);
+            },
+            3 => {
+                try b.op(shifts[random.uintLessThan(usize, shifts.len)]);
+                try b.reg(dest);
+                try b.reg(src);
+                try b.qword(random.uintLessThan(u64, 64));
+            },
+            4 => {
+                try b.op(.cmp_reg_reg);
+                try b.reg(dest);
+                try b.reg(src);
+            },
+            else => {
+                try b.op(.push_reg);
+                try b.size(.qword);
+                try b.reg(src);
+                try b.op(.pop_reg);
+                try b.size(.qword);
+                try b.reg(dest);
+            },
+        }
+    }
+    try b.op(.hlt);
+}
+
+test "random programs behave the same after a disassembly round trip" {
+    var prng = std.Random.DefaultPrng.init(0x6e7978);
+    const random = prng.random();
+
+    for (0..64) |_| {
+        var b = ProgramBuilder.init(testing.allocator);
+        defer b.deinit();
+        try randomProgram(&b, random, 1 + random.uintLessThan(usize, 48));
+
+        const text = try disassemble(&b);
+        defer testing.allocator.free(text);
+        const reassembled = nyx.assemble(testing.io, testing.allocator, text, .{}) catch |err| {
+            std.debug.print("failed to reassemble:\n{s}\n", .{text});
+            return err;
+        };
+        defer testing.allocator.free(reassembled);
+
+        var original = try b.load(testing.allocator);
+        defer original.deinit();
+        try original.run();
+
+        var copy = try Vm.init(reassembled, vm_testing.default_memory_size, &.{}, testing.allocator);
+        defer copy.deinit();
+        try copy.run();
+
+        testing.expectEqualSlices(u64, &original.regs.gpr, &copy.regs.gpr) catch |err| {
+            std.debug.print("diverged on:\n{s}\n", .{text});
+            return err;
+        };
+        try testing.expectEqual(original.flags, copy.flags);
+    }
+}
+
+test "disassembling random bytes never fails" {
+    var prng = std.Random.DefaultPrng.init(0x6e7978);
+    const random = prng.random();
+
+    var corpus: [512]u8 = undefined;
+    for (0..256) |_| {
+        const len = 8 + random.uintLessThan(usize, corpus.len - 8);
+        random.bytes(corpus[0..len]);
+        // Bias towards real opcodes so decoding gets past the first byte.
+        if (len > 8) corpus[8] = random.uintLessThan(u8, std.meta.fields(Opcode).len);
+
+        const disassembler = try Disassembler.init(corpus[0..len], testing.allocator);
+        var output: Io.Writer.Allocating = .init(testing.allocator);
+        defer output.deinit();
+        try disassembler.disassemble(&output.writer);
+    }
+}
+
+test "diff compares programs symbol by symbol" {
+    var arena = std.heap.ArenaAllocator.init(testing.allocator);
+    defer arena.deinit();
+    var old_symbols = nyx.diff.Symbols.init(arena.allocator());
+    var new_symbols = nyx.diff.Symbols.init(arena.allocator());
+
+    const old = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    call helper
+        \\    hlt
+        \\helper:
+        \\    mov q0, 1
+        \\    ret
+        \\gone:
+        \\    ret
+        \\.section data
+        \\msg:
+        \\    db "hi", 0
+    , .{ .symbols = &old_symbols });
+    defer testing.allocator.free(old);
+    const new = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    call helper
+        \\    mov q2, 3
+        \\    hlt
+        \\extra:
+        \\    mov q1, 5
+        \\    ret
+        \\helper:
+        \\    mov q0, 1
+        \\    ret
+        \\.section data
+        \\msg:
+        \\    db "ho", 0
+    , .{ .symbols = &new_symbols });
+    defer testing.allocator.free(new);
+
+    var output: Io.Writer.Allocating = .init(testing.allocator);
+    defer output.deinit();
+    const summary = try nyx.diff.compare(
+        testing.allocator,
+        .{ .bytecode = old, .symbols = &old_symbols, .text_len = old_symbols.get("msg").? },
+        .{ .bytecode = new, .symbols = &new_symbols, .text_len = new_symbols.get("msg").? },
+        &output.writer,
+    );
+
+    // The data section starts right after the text, at msg. helper moved
+    // but is the same, and so is the call to it.
+    try testing.expectEqual(nyx.diff.Summary{ .changed = 2, .added = 1, .removed = 1, .unchanged = 1 }, summary);
+    try expectLine(output.written(), "changed _start: 0 removed, 1 added");
+    try expectLine(output.written(), "+ mov q2, 3");
+    try expectLine(output.written(), "added extra: 2 instructions");
+    try expectLine(output.written(), "removed gone: 1 instructions");
+    try expectLine(output.written(), "changed data msg: first differs at byte 1");
+
+    output.clearRetainingCapacity();
+    const same = try nyx.diff.compare(testing.allocator, .{ .bytecode = old }, .{ .bytecode = old }, &output.writer);
+    try testing.expect(same.identical());
+}
+
+test "diff splits bytecode at the symbols in its debug section" {
+    const old = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    call helper
+        \\    hlt
+        \\helper:
+        \\    mov q0, 1
+        \\    mov q1, 2
+        \\    ret
+    , .{ .embed_symbols = true });
+    defer testing.allocator.free(old);
+    const new = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    mov q2, 3
+        \\    call helper
+        \\    hlt
+        \\helper:
+        \\    mov q0, 1
+        \\    mov q3, 4
+        \\    mov q1, 2
+        \\    ret
+    , .{ .embed_symbols = true });
+    defer testing.allocator.free(new);
+
+    var output: Io.Writer.Allocating = .init(testing.allocator);
+    defer output.deinit();
+    const summary = try nyx.diff.compare(testing.allocator, .{ .bytecode = old }, .{ .bytecode = new }, &output.writer);
+    try testing.expectEqual(nyx.diff.Summary{ .changed = 2 }, summary);
+    try expectLine(output.written(), "changed _start: 0 removed, 1 added");
+    try expectLine(output.written(), "changed helper: 0 removed, 1 added");
+    try expectLine(output.written(), "+ mov q3, 4");
+}
+
+test "diff of long blocks keeps the longest common run" {
+    var old_source: Io.Writer.Allocating = .init(testing.allocator);
+    defer old_source.deinit();
+    var new_source: Io.Writer.Allocating = .init(testing.allocator);
+    defer new_source.deinit();
+    for (0..3000) |i| {
+        try old_source.writer.print("mov q{d}, {d}\n", .{ i % 16, i });
+        // Every tenth instruction is replaced.
+        const value = if (i % 10 == 0) i + 100_000 else i;
+        try new_source.writer.print("mov q{d}, {d}\n", .{ i % 16, value });
+    }
+    try old_source.writer.writeAll("hlt\n");
+    try new_source.writer.writeAll("hlt\n");
+
+    const old = try nyx.assemble(testing.io, testing.allocator, old_source.written(), .{});
+    defer testing.allocator.free(old);
+    const new = try nyx.assemble(testing.io, testing.allocator, new_source.written(), .{});
+    defer testing.allocator.free(new);
+
+    var output: Io.Writer.Allocating = .init(testing.allocator);
+    defer output.deinit();
+    const summary = try nyx.diff.compare(testing.allocator, .{ .bytecode = old }, .{ .bytecode = new }, &output.writer);
+    try testing.expectEqual(nyx.diff.Summary{ .changed = 1 }, summary);
+    try expectLine(output.written(), "changed text: 300 removed, 300 added");
+}
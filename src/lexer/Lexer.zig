@@ -13,6 +13,12 @@ input: []const u8,
 pos: usize = 0,
 read_pos: usize = 0,
 ch: u8 = 0,
+/// Never stop early on malformed input, for tools such as editors that lex
+/// buffers in the middle of an edit. A NUL byte before the end of the input
+/// becomes an `illegal` token instead of ending it, and a string without a
+/// closing quote stops at the end of its line and is returned as an
+/// `illegal` token, so the lines after it still lex normally.
+tolerant: bool = false,
 interner: *StringInterner,
 gpa: Allocator,
 
@@ -38,7 +44,10 @@ pub fn nextToken(self: *Lexer) Token {
     self.skipWhitespace();
 
     const token = switch (self.ch) {
-        0 => Token.init(.eof, "", .init(start, start, self.filename)),
+        0 => if (self.tolerant and !self.atEnd())
+            return self.readIllegal()
+        else
+            Token.init(.eof, "", .init(start, start, self.filename)),
         ',' => Token.init(.comma, ",", .init(start, start, self.filename)),
         ':' => Token.init(.colon, ":", .init(start, start, self.filename)),
         '+' => Token.init(.plus, "+", .init(start, start, self.filename)),
@@ -48,6 +57,7 @@ pub fn nextToken(self: *Lexer) Token {
         '|' => Token.init(.pipe, "|", .init(start, start, self.filename)),
         '&' => Token.init(.ampersand, "&", .init(start, start, self.filename)),
         '^' => Token.init(.caret, "^", .init(start, start, self.filename)),
+        '~' => Token.init(.tilde, "~", .init(start, start, self.filename)),
         '(' => Token.init(.lparen, "(", .init(start, start, self.filename)),
         ')' => Token.init(.rparen, ")", .init(start, start, self.filename)),
         '[' => Token.init(.lbracket, "[", .init(start, start, self.filename)),
@@ -64,11 +74,12 @@ pub fn nextToken(self: *Lexer) Token {
             return self.readDirective();
         },
         '"' => return self.readString(),
+        '\'' => return self.readCharacter(),
         ';' => return self.skipComment(),
         else => {
             if (ascii.isDigit(self.ch)) return self.readNumber();
             if (ascii.isAlphabetic(self.ch) or self.ch == '_' or self.ch == '$') return self.readIdentifier();
-            return Token.init(.illegal, "", .init(start, start, self.filename));
+            return self.readIllegal();
         },
     };
 
@@ -76,6 +87,23 @@ pub fn nextToken(self: *Lexer) Token {
     return token;
 }
 
+/// Lex the rest of the input, up to and including the `eof` token. Bad
+/// input shows up as `illegal` tokens in place. Caller owns the returned
+/// slice.
+pub fn tokenize(self: *Lexer, gpa: Allocator) ![]Token {
+    var tokens = ArrayList(Token).init(gpa);
+    errdefer tokens.deinit();
+    while (true) {
+        const token = self.nextToken();
+        try tokens.append(token);
+        if (token.kind == .eof) break;
+    }
+    return tokens.toOwnedSlice();
+}
+
+/// Advance to the next byte of `input`. Source text is UTF-8, but every byte
+/// with meaning to the lexer is ASCII, so multi-byte characters only need
+/// handling where they can appear outside strings and comments.
 fn readChar(self: *Lexer) void {
     if (self.read_pos >= self.input.len) {
         self.ch = 0;
@@ -86,6 +114,17 @@ fn readChar(self: *Lexer) void {
     self.read_pos += 1;
 }
 
+/// Consume one character that cannot start a token. A multi-byte UTF-8
+/// character is taken whole so the error points at it rather than its
+/// first byte.
+fn readIllegal(self: *Lexer) Token {
+    const start = self.pos;
+    const len = std.unicode.utf8ByteSequenceLength(self.ch) catch 1;
+    const end = @min(start + len, self.input.len);
+    while (self.pos < end) self.readChar();
+    return Token.init(.illegal, self.input[start..end], .init(start, end - 1, self.filename));
+}
+
 fn readNumber(self: *Lexer) Token {
     const start = self.pos;
 
@@ -95,6 +134,7 @@ fn readNumber(self: *Lexer) Token {
                 self.readChar();
                 self.readChar();
                 while (ascii.isHex(self.ch)) self.readChar();
+                self.readIntSuffix();
                 const literal = self.input[start..self.pos];
                 return Token.init(.hexadecimal, literal, .init(start, self.pos - 1, self.filename));
             },
@@ -102,6 +142,7 @@ fn readNumber(self: *Lexer) Token {
                 self.readChar();
                 self.readChar();
                 while (ascii.isHex(self.ch)) self.readChar();
+                self.readIntSuffix();
                 const literal = self.input[start..self.pos];
                 return Token.init(.binary, literal, .init(start, self.pos - 1, self.filename));
             },
@@ -109,6 +150,7 @@ fn readNumber(self: *Lexer) Token {
                 self.readChar();
                 self.readChar();
                 while (ascii.isHex(self.ch)) self.readChar();
+                self.readIntSuffix();
                 const literal = self.input[start..self.pos];
                 return Token.init(.octal, literal, .init(start, self.pos - 1, self.filename));
             },
@@ -122,14 +164,30 @@ fn readNumber(self: *Lexer) Token {
         self.readChar();
         while (ascii.isDigit(self.ch)) self.readChar();
 
+        // Optional single-precision suffix: 3.14f
+        if ((self.ch == 'f' or self.ch == 'F') and !isIdentChar(self.peekChar())) self.readChar();
+
         const literal = self.input[start..self.pos];
         return Token.init(.float, literal, .init(start, self.pos - 1, self.filename));
     } else {
+        self.readIntSuffix();
         const literal = self.input[start..self.pos];
         return Token.init(.integer, literal, .init(start, self.pos - 1, self.filename));
     }
 }
 
+/// Consume an optional integer width suffix: u8, u16, u32, u64, i8, i16, i32, i64.
+fn readIntSuffix(self: *Lexer) void {
+    if (self.ch != 'u' and self.ch != 'i') return;
+    const rest = self.input[self.read_pos..];
+    for ([_][]const u8{ "8", "16", "32", "64" }) |width| {
+        if (std.mem.startsWith(u8, rest, width) and (rest.len == width.len or !isIdentChar(rest[width.len]))) {
+            for (0..width.len + 1) |_| self.readChar();
+            return;
+        }
+    }
+}
+
 fn readIdentifier(self: *Lexer) Token {
     const start = self.pos;
     while (ascii.isAlphanumeric(self.ch) or self.ch == '_' or self.ch == '$') {
@@ -165,47 +223,138 @@ fn readDirective(self: *Lexer) Token {
     return Token.init(kind, literal, .init(start, self.pos - 1, self.filename));
 }
 
+/// Lex a string literal and intern its contents with the escapes decoded.
+/// A bad escape makes the string an `illegal` token covering just that
+/// escape, which the parser reports. In tolerant mode a string without a
+/// closing quote on its line is an `illegal` token.
 fn readString(self: *Lexer) Token {
     const start = self.pos;
     self.readChar();
 
+    while (self.ch != '"') {
+        if (self.ch == 0 and (!self.tolerant or self.atEnd())) break;
+        if (self.tolerant and self.ch == '\n') break;
+        if (self.ch == '\\' and self.peekChar() != '\n') self.readChar();
+        self.readChar();
+    }
+
+    if (self.tolerant and self.ch != '"') {
+        return Token.init(.illegal, self.input[start..self.pos], .init(start, self.pos - 1, self.filename));
+    }
+
+    const body = self.input[start + 1 .. @min(self.pos, self.input.len)];
+    const end = self.read_pos - 1;
+    if (self.ch == '"') self.readChar();
+
     var result = ArrayList(u8).init(self.gpa);
     defer result.deinit();
-    var escaped = false;
+    var bad: BadEscape = undefined;
+    unescape(body, &result, &bad) catch {
+        const offset = start + 1 + bad.offset;
+        return Token.init(.illegal, body[bad.offset..][0..bad.len], .init(offset, offset + bad.len - 1, self.filename));
+    };
 
-    while (true) {
-        if (self.ch == 0) break;
-
-        if (escaped) {
-            switch (self.ch) {
-                'n' => result.append('\n') catch unreachable,
-                'r' => result.append('\r') catch unreachable,
-                't' => result.append('\t') catch unreachable,
-                '\\' => result.append('\\') catch unreachable,
-                '"' => result.append('"') catch unreachable,
-                else => |other| {
-                    result.append('\\') catch unreachable;
-                    result.append(other) catch unreachable;
-                },
-            }
-            escaped = false;
-        } else if (self.ch == '\\') {
-            escaped = true;
-        } else if (self.ch == '"') {
-            break;
-        } else {
-            result.append(self.ch) catch unreachable;
+    const id = self.interner.intern(result.items) catch unreachable;
+
+    return Token.initWithId(.string, id, .init(start, end, self.filename));
+}
+
+/// Where in a string's text an escape that `unescape` rejected is.
+const BadEscape = struct {
+    offset: usize,
+    len: usize,
+};
+
+/// Decode the escapes in `text`, the inside of a string literal, into `out`:
+/// `\n`, `\r`, `\t`, `\0`, `\\`, `\'` and `\"`, `\xHH` for any byte and
+/// `\uXXXX` for a code point, which is written as UTF-8.
+fn unescape(text: []const u8, out: *ArrayList(u8), bad: *BadEscape) error{InvalidEscape}!void {
+    var i: usize = 0;
+    while (i < text.len) {
+        if (text[i] != '\\') {
+            out.append(text[i]) catch unreachable;
+            i += 1;
+            continue;
+        }
+
+        const escape = text[i..];
+        const len: usize = if (escape.len < 2) 1 else switch (escape[1]) {
+            'n', 'r', 't', '0', '\\', '\'', '"' => blk: {
+                out.append(switch (escape[1]) {
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    '0' => 0,
+                    else => |c| c,
+                }) catch unreachable;
+                break :blk 2;
+            },
+            'x' => blk: {
+                const byte = hexDigits(u8, escape, 2) orelse {
+                    bad.* = .{ .offset = i, .len = @min(escape.len, 4) };
+                    return error.InvalidEscape;
+                };
+                out.append(byte) catch unreachable;
+                break :blk 4;
+            },
+            'u' => blk: {
+                const code_point = hexDigits(u21, escape, 4) orelse {
+                    bad.* = .{ .offset = i, .len = @min(escape.len, 6) };
+                    return error.InvalidEscape;
+                };
+                var buf: [4]u8 = undefined;
+                const n = std.unicode.utf8Encode(code_point, &buf) catch {
+                    bad.* = .{ .offset = i, .len = 6 };
+                    return error.InvalidEscape;
+                };
+                out.appendSlice(buf[0..n]) catch unreachable;
+                break :blk 6;
+            },
+            else => 0,
+        };
+        if (len < 2) {
+            bad.* = .{ .offset = i, .len = @min(escape.len, 2) };
+            return error.InvalidEscape;
         }
+        i += len;
+    }
+}
+
+/// The `count` hex digits after the two characters of `\x` or `\u`.
+fn hexDigits(comptime T: type, escape: []const u8, count: usize) ?T {
+    if (escape.len < 2 + count) return null;
+    const digits = escape[2 .. 2 + count];
+    for (digits) |c| if (!ascii.isHex(c)) return null;
+    return std.fmt.parseInt(T, digits, 16) catch null;
+}
+
+/// Lex a character literal such as `'A'` or `'\n'`. The quotes stay in the
+/// literal and the parser works out the code point, so a bad escape is
+/// reported there. In tolerant mode a literal without a closing quote on its
+/// line is an `illegal` token.
+fn readCharacter(self: *Lexer) Token {
+    const start = self.pos;
+    self.readChar();
 
+    while (self.ch != '\'' and self.ch != '\n' and !self.atEnd()) {
+        if (self.ch == '\\' and self.peekChar() != '\n') self.readChar();
         self.readChar();
     }
 
-    const end = self.read_pos - 1;
-    if (self.ch == '"') self.readChar();
+    if (self.ch == '\'') {
+        self.readChar();
+    } else if (self.tolerant) {
+        return Token.init(.illegal, self.input[start..self.pos], .init(start, self.pos - 1, self.filename));
+    }
+    return Token.init(.character, self.input[start..self.pos], .init(start, self.pos - 1, self.filename));
+}
 
-    const id = self.interner.intern(result.items) catch unreachable;
+fn isIdentChar(ch: u8) bool {
+    return ascii.isAlphanumeric(ch) or ch == '_' or ch == '$';
+}
 
-    return Token.initWithId(.string, id, .init(start, end, self.filename));
+fn atEnd(self: *Lexer) bool {
+    return self.pos >= self.input.len;
 }
 
 fn peekChar(self: *Lexer) u8 {
@@ -224,7 +373,7 @@ fn skipWhitespace(self: *Lexer) void {
 fn skipComment(self: *Lexer) Token {
     self.readChar();
 
-    while (self.ch != '\n' and self.ch != 0) {
+    while (self.ch != '\n' and (self.ch != 0 or (self.tolerant and !self.atEnd()))) {
         self.readChar();
     }
 
@@ -18,6 +18,7 @@ pub const Kind = enum {
     octal,
     float,
     string,
+    character,
     data_size,
 
     colon,
@@ -29,6 +30,7 @@ pub const Kind = enum {
     pipe,
     ampersand,
     caret,
+    tilde,
     lparen,
     rparen,
     lbracket,
@@ -36,37 +38,55 @@ pub const Kind = enum {
     ellipsis,
 
     kw_error,
+    kw_warning,
     kw_define,
+    kw_define_env,
     kw_include,
+    kw_pragma,
     kw_ifdef,
     kw_ifndef,
     kw_else,
     kw_endif,
     kw_macro,
     kw_endm,
+    kw_line,
 
     kw_section,
     kw_entry,
+    kw_requires,
+    kw_align,
     kw_ascii,
     kw_asciz,
     kw_extern,
+    kw_equ,
+    kw_times,
 
     kw_nop,
     kw_mov,
+    kw_movz,
+    kw_movs,
     kw_push,
     kw_pop,
     kw_add,
+    kw_adc,
     kw_sub,
+    kw_sbb,
     kw_mul,
     kw_div,
+    kw_imul,
+    kw_idiv,
+    kw_imod,
     kw_and,
     kw_or,
     kw_xor,
+    kw_not,
     kw_shl,
     kw_shr,
     kw_rol,
     kw_ror,
     kw_cmp,
+    kw_scmp,
+    kw_schr,
     kw_jmp,
     kw_jeq,
     kw_jne,
@@ -74,18 +94,35 @@ pub const Kind = enum {
     kw_jgt,
     kw_jle,
     kw_jge,
+    kw_seteq,
+    kw_setne,
+    kw_setlt,
+    kw_setgt,
+    kw_setle,
+    kw_setge,
+    kw_setc,
+    kw_seto,
     kw_call,
     kw_ret,
+    kw_enter,
+    kw_leave,
     kw_inc,
     kw_dec,
     kw_neg,
+    kw_cvtif,
+    kw_cvtfi,
+    kw_cvtfd,
+    kw_cvtdf,
     kw_syscall,
     kw_hlt,
+    kw_ext,
 
     kw_db,
     kw_dw,
     kw_dd,
     kw_dq,
+    kw_df,
+    kw_ddbl,
     kw_resb,
     kw_resw,
     kw_resd,
@@ -124,7 +161,7 @@ pub fn format(
         .{
             @tagName(self.kind),
             self.literal,
-            self.string_id,
+            @intFromEnum(self.string_id),
             self.span.start,
             self.span.start,
             self.span.filename,
@@ -134,37 +171,57 @@ pub fn format(
 
 const keywords = std.StaticStringMap(Kind).initComptime(.{
     .{ "#error", Kind.kw_error },
+    .{ "#warning", Kind.kw_warning },
     .{ "#define", Kind.kw_define },
+    .{ "#define_env", Kind.kw_define_env },
     .{ "#include", Kind.kw_include },
+    .{ "#pragma", Kind.kw_pragma },
     .{ "#ifdef", Kind.kw_ifdef },
     .{ "#ifndef", Kind.kw_ifndef },
     .{ "#else", Kind.kw_else },
     .{ "#endif", Kind.kw_endif },
     .{ "#macro", Kind.kw_macro },
     .{ "#endm", Kind.kw_endm },
+    .{ "#endmacro", Kind.kw_endm },
+    .{ "#line", Kind.kw_line },
     // Assembler Directives
     .{ ".section", Kind.kw_section },
     .{ ".entry", Kind.kw_entry },
+    .{ ".requires", Kind.kw_requires },
+    .{ ".align", Kind.kw_align },
     .{ ".ascii", Kind.kw_ascii },
     .{ ".asciz", Kind.kw_asciz },
     .{ ".extern", Kind.kw_extern },
+    .{ ".equ", Kind.kw_equ },
+    .{ "equ", Kind.kw_equ },
+    .{ "times", Kind.kw_times },
     // Instructions
     .{ "nop", Kind.kw_nop },
     .{ "mov", Kind.kw_mov },
+    .{ "movz", Kind.kw_movz },
+    .{ "movs", Kind.kw_movs },
     .{ "push", Kind.kw_push },
     .{ "pop", Kind.kw_pop },
     .{ "add", Kind.kw_add },
+    .{ "adc", Kind.kw_adc },
     .{ "sub", Kind.kw_sub },
+    .{ "sbb", Kind.kw_sbb },
     .{ "mul", Kind.kw_mul },
     .{ "div", Kind.kw_div },
+    .{ "imul", Kind.kw_imul },
+    .{ "idiv", Kind.kw_idiv },
+    .{ "imod", Kind.kw_imod },
     .{ "and", Kind.kw_and },
     .{ "or", Kind.kw_or },
     .{ "xor", Kind.kw_xor },
+    .{ "not", Kind.kw_not },
     .{ "shl", Kind.kw_shl },
     .{ "shr", Kind.kw_shr },
     .{ "rol", Kind.kw_rol },
     .{ "ror", Kind.kw_ror },
     .{ "cmp", Kind.kw_cmp },
+    .{ "scmp", Kind.kw_scmp },
+    .{ "schr", Kind.kw_schr },
     .{ "jmp", Kind.kw_jmp },
     .{ "jeq", Kind.kw_jeq },
     .{ "jne", Kind.kw_jne },
@@ -172,18 +229,35 @@ const keywords = std.StaticStringMap(Kind).initComptime(.{
     .{ "jgt", Kind.kw_jgt },
     .{ "jle", Kind.kw_jle },
     .{ "jge", Kind.kw_jge },
+    .{ "seteq", Kind.kw_seteq },
+    .{ "setne", Kind.kw_setne },
+    .{ "setlt", Kind.kw_setlt },
+    .{ "setgt", Kind.kw_setgt },
+    .{ "setle", Kind.kw_setle },
+    .{ "setge", Kind.kw_setge },
+    .{ "setc", Kind.kw_setc },
+    .{ "seto", Kind.kw_seto },
     .{ "call", Kind.kw_call },
     .{ "ret", Kind.kw_ret },
+    .{ "enter", Kind.kw_enter },
+    .{ "leave", Kind.kw_leave },
     .{ "inc", Kind.kw_inc },
     .{ "dec", Kind.kw_dec },
     .{ "neg", Kind.kw_neg },
+    .{ "cvtif", Kind.kw_cvtif },
+    .{ "cvtfi", Kind.kw_cvtfi },
+    .{ "cvtfd", Kind.kw_cvtfd },
+    .{ "cvtdf", Kind.kw_cvtdf },
     .{ "syscall", Kind.kw_syscall },
     .{ "hlt", Kind.kw_hlt },
+    .{ "ext", Kind.kw_ext },
     // Data Declaration Directives
     .{ "db", Kind.kw_db },
     .{ "dw", Kind.kw_dw },
     .{ "dd", Kind.kw_dd },
     .{ "dq", Kind.kw_dq },
+    .{ "df", Kind.kw_df },
+    .{ "ddbl", Kind.kw_ddbl },
     .{ "resb", Kind.kw_resb },
     .{ "resw", Kind.kw_resw },
     .{ "resd", Kind.kw_resd },
@@ -294,6 +368,10 @@ const keywords = std.StaticStringMap(Kind).initComptime(.{
     .{ "ip", Kind.register },
     .{ "sp", Kind.register },
     .{ "bp", Kind.register },
+    .{ "zr", Kind.register },
+    .{ "msz", Kind.register },
+    .{ "brk", Kind.register },
+    .{ "isp", Kind.register },
     // Data Sizes
     .{ "byte", Kind.data_size },
     .{ "word", Kind.data_size },
@@ -303,13 +381,12 @@ const keywords = std.StaticStringMap(Kind).initComptime(.{
     .{ "double", Kind.data_size },
 });
 
+/// Longer than any keyword, so anything that does not fit is an identifier.
+const max_keyword_len = 16;
+
 pub fn lookupIdent(ident: []const u8) Kind {
-    const allocator = std.heap.page_allocator;
-    const lower = std.ascii.allocLowerString(allocator, ident) catch unreachable;
-    defer allocator.free(lower);
-    if (keywords.get(lower)) |kind| {
-        return kind;
-    } else {
-        return .identifier;
-    }
+    if (ident.len > max_keyword_len) return .identifier;
+    var buf: [max_keyword_len]u8 = undefined;
+    const lower = std.ascii.lowerString(&buf, ident);
+    return keywords.get(lower) orelse .identifier;
 }
@@ -40,17 +40,18 @@ fn lex(gpa: std.mem.Allocator, input: []const u8) !LexResult {
 }
 
 test "single character" {
-    const input = ":,+-[]";
+    const input = ":,+-[]~";
     var result = try lex(testing.allocator, input);
     defer result.deinit(testing.allocator);
 
-    try testing.expectEqual(@as(usize, 7), result.tokens.len);
+    try testing.expectEqual(@as(usize, 8), result.tokens.len);
     try testing.expectEqual(Token.Kind.colon, result.tokens[0].kind);
     try testing.expectEqual(Token.Kind.comma, result.tokens[1].kind);
     try testing.expectEqual(Token.Kind.plus, result.tokens[2].kind);
     try testing.expectEqual(Token.Kind.minus, result.tokens[3].kind);
     try testing.expectEqual(Token.Kind.lbracket, result.tokens[4].kind);
     try testing.expectEqual(Token.Kind.rbracket, result.tokens[5].kind);
+    try testing.expectEqual(Token.Kind.tilde, result.tokens[6].kind);
 }
 
 test "numbers" {
@@ -127,14 +128,18 @@ test "preprocessor directives" {
         kind: Token.Kind,
     }{
         .{ .input = "#define", .kind = .kw_define },
+        .{ .input = "#define_env", .kind = .kw_define_env },
         .{ .input = "#include", .kind = .kw_include },
+        .{ .input = "#line", .kind = .kw_line },
         .{ .input = "#ifdef", .kind = .kw_ifdef },
         .{ .input = "#ifndef", .kind = .kw_ifndef },
         .{ .input = "#else", .kind = .kw_else },
         .{ .input = "#endif", .kind = .kw_endif },
         .{ .input = "#error", .kind = .kw_error },
+        .{ .input = "#warning", .kind = .kw_warning },
         .{ .input = "#macro", .kind = .kw_macro },
         .{ .input = "#endm", .kind = .kw_endm },
+        .{ .input = "#endmacro", .kind = .kw_endm },
     };
 
     for (cases) |case| {
@@ -156,12 +161,17 @@ test "basic instructions" {
         .{ .input = "push", .kind = .kw_push },
         .{ .input = "pop", .kind = .kw_pop },
         .{ .input = "cmp", .kind = .kw_cmp },
+        .{ .input = "scmp", .kind = .kw_scmp },
+        .{ .input = "schr", .kind = .kw_schr },
         .{ .input = "call", .kind = .kw_call },
         .{ .input = "ret", .kind = .kw_ret },
+        .{ .input = "enter", .kind = .kw_enter },
+        .{ .input = "leave", .kind = .kw_leave },
         .{ .input = "inc", .kind = .kw_inc },
         .{ .input = "dec", .kind = .kw_dec },
         .{ .input = "syscall", .kind = .kw_syscall },
         .{ .input = "hlt", .kind = .kw_hlt },
+        .{ .input = "ext", .kind = .kw_ext },
     };
 
     for (cases) |case| {
@@ -182,6 +192,9 @@ test "arithmetic instructions" {
         .{ .input = "sub", .kind = .kw_sub },
         .{ .input = "mul", .kind = .kw_mul },
         .{ .input = "div", .kind = .kw_div },
+        .{ .input = "imul", .kind = .kw_imul },
+        .{ .input = "idiv", .kind = .kw_idiv },
+        .{ .input = "imod", .kind = .kw_imod },
     };
 
     for (cases) |case| {
@@ -201,6 +214,7 @@ test "bitwise instructions" {
         .{ .input = "and", .kind = .kw_and },
         .{ .input = "or", .kind = .kw_or },
         .{ .input = "xor", .kind = .kw_xor },
+        .{ .input = "not", .kind = .kw_not },
     };
 
     for (cases) |case| {
@@ -244,6 +258,20 @@ test "jump instructions" {
         .{ .input = "jgt", .kind = .kw_jgt },
         .{ .input = "jle", .kind = .kw_jle },
         .{ .input = "jge", .kind = .kw_jge },
+        .{ .input = "seteq", .kind = .kw_seteq },
+        .{ .input = "setne", .kind = .kw_setne },
+        .{ .input = "setlt", .kind = .kw_setlt },
+        .{ .input = "setgt", .kind = .kw_setgt },
+        .{ .input = "setle", .kind = .kw_setle },
+        .{ .input = "setge", .kind = .kw_setge },
+        .{ .input = "setc", .kind = .kw_setc },
+        .{ .input = "seto", .kind = .kw_seto },
+        .{ .input = "movz", .kind = .kw_movz },
+        .{ .input = "movs", .kind = .kw_movs },
+        .{ .input = "cvtif", .kind = .kw_cvtif },
+        .{ .input = "cvtfi", .kind = .kw_cvtfi },
+        .{ .input = "cvtfd", .kind = .kw_cvtfd },
+        .{ .input = "cvtdf", .kind = .kw_cvtdf },
     };
 
     for (cases) |case| {
@@ -262,6 +290,7 @@ test "data declaration directives" {
     }{
         .{ .input = "db", .kind = .kw_db },
         .{ .input = "resb", .kind = .kw_resb },
+        .{ .input = "times", .kind = .kw_times },
     };
 
     for (cases) |case| {
@@ -274,7 +303,7 @@ test "data declaration directives" {
 }
 
 test "float numbers" {
-    const cases = [_][]const u8{ "3.14", "0.5", "123.456", "0.0", "999.999" };
+    const cases = [_][]const u8{ "3.14", "0.5", "123.456", "0.0", "999.999", "3.14f", "2.5F" };
 
     for (cases) |case| {
         var result = try lex(testing.allocator, case);
@@ -285,6 +314,25 @@ test "float numbers" {
     }
 }
 
+test "integer width suffixes" {
+    const cases = [_]struct { input: []const u8, kind: Token.Kind }{
+        .{ .input = "10u8", .kind = .integer },
+        .{ .input = "42i32", .kind = .integer },
+        .{ .input = "0xFFu32", .kind = .hexadecimal },
+        .{ .input = "0b1010u16", .kind = .binary },
+        .{ .input = "0o17i64", .kind = .octal },
+    };
+
+    for (cases) |case| {
+        var result = try lex(testing.allocator, case.input);
+        defer result.deinit(testing.allocator);
+
+        try testing.expectEqual(@as(usize, 2), result.tokens.len);
+        try testing.expectEqual(case.kind, result.tokens[0].kind);
+        try testing.expectEqualStrings(case.input, result.tokens[0].literal);
+    }
+}
+
 test "mixed numbers" {
     const input1 = "42 3.14 0xFF";
     var result1 = try lex(testing.allocator, input1);
@@ -315,12 +363,12 @@ test "register tokens" {
         try testing.expectEqual(Token.Kind.register, token.kind);
     }
 
-    const input2 = "ff0 dd1 ip sp bp";
+    const input2 = "ff0 dd1 ip sp bp zr msz brk isp";
     var result2 = try lex(testing.allocator, input2);
     defer result2.deinit(testing.allocator);
 
-    try testing.expectEqual(@as(usize, 6), result2.tokens.len);
-    for (result2.tokens[0..5]) |token| {
+    try testing.expectEqual(@as(usize, 10), result2.tokens.len);
+    for (result2.tokens[0..9]) |token| {
         try testing.expectEqual(Token.Kind.register, token.kind);
     }
 }
@@ -455,3 +503,103 @@ test "strings" {
     try testing.expectEqual(Token.Kind.string, result4.tokens[0].kind);
     try testing.expectEqualStrings("newline:\n tab:\t backslash:\\ quote:\"", result4.interner.get(result4.tokens[0].string_id).?);
 }
+
+test "string escapes" {
+    const cases = [_]struct { input: []const u8, expected: []const u8 }{
+        .{ .input = "\"a\\x00b\\xFF\"", .expected = "a\x00b\xff" },
+        .{ .input = "\"nul\\0\"", .expected = "nul\x00" },
+        .{ .input = "\"\\u00e9\\u20AC\"", .expected = "\u{e9}\u{20ac}" },
+        .{ .input = "\"it\\'s\"", .expected = "it's" },
+    };
+    for (cases) |case| {
+        var result = try lex(testing.allocator, case.input);
+        defer result.deinit(testing.allocator);
+
+        try testing.expectEqual(Token.Kind.string, result.tokens[0].kind);
+        try testing.expectEqualStrings(case.expected, result.interner.get(result.tokens[0].string_id).?);
+    }
+
+    // A bad escape becomes an illegal token covering just the escape.
+    const bad = [_]struct { input: []const u8, escape: []const u8, start: usize }{
+        .{ .input = "\"ab\\q\"", .escape = "\\q", .start = 3 },
+        .{ .input = "\"\\x4g\"", .escape = "\\x4g", .start = 1 },
+        .{ .input = "\"\\u12\"", .escape = "\\u12", .start = 1 },
+        .{ .input = "\"\\uD800\"", .escape = "\\uD800", .start = 1 },
+    };
+    for (bad) |case| {
+        var result = try lex(testing.allocator, case.input);
+        defer result.deinit(testing.allocator);
+
+        try testing.expectEqual(@as(usize, 2), result.tokens.len);
+        try testing.expectEqual(Token.Kind.illegal, result.tokens[0].kind);
+        try testing.expectEqualStrings(case.escape, result.tokens[0].literal);
+        try testing.expectEqual(case.start, result.tokens[0].span.start);
+        try testing.expectEqual(case.start + case.escape.len - 1, result.tokens[0].span.end);
+    }
+}
+
+test "character literals" {
+    const cases = [_][]const u8{ "'A'", "'\\n'", "'\\''", "'\\x41'", "'\u{e9}'" };
+
+    for (cases) |case| {
+        var result = try lex(testing.allocator, case);
+        defer result.deinit(testing.allocator);
+
+        try testing.expectEqual(@as(usize, 2), result.tokens.len);
+        try testing.expectEqual(Token.Kind.character, result.tokens[0].kind);
+        try testing.expectEqualStrings(case, result.tokens[0].literal);
+    }
+
+    var result = try lex(testing.allocator, "db 'A', 0");
+    defer result.deinit(testing.allocator);
+    try testing.expectEqual(Token.Kind.character, result.tokens[1].kind);
+    try testing.expectEqual(Token.Kind.comma, result.tokens[2].kind);
+}
+
+test "non-ASCII characters are one illegal token" {
+    var result = try lex(testing.allocator, "mov q0, λ");
+    defer result.deinit(testing.allocator);
+
+    const token = result.tokens[result.tokens.len - 1];
+    try testing.expectEqual(Token.Kind.illegal, token.kind);
+    try testing.expectEqualStrings("λ", token.literal);
+    try testing.expectEqual(@as(usize, 8), token.span.start);
+    try testing.expectEqual(@as(usize, 9), token.span.end);
+}
+
+test "large inputs" {
+    var input = ArrayList(u8).init(testing.allocator);
+    defer input.deinit();
+    for (0..20_000) |_| try input.appendSlice("    mov q0, [some_long_label_name, 8] ; comment\n");
+
+    var result = try lex(testing.allocator, input.items);
+    defer result.deinit(testing.allocator);
+
+    try testing.expectEqual(@as(usize, 20_000 * 9 + 1), result.tokens.len);
+    try testing.expectEqual(Token.Kind.eof, result.tokens[result.tokens.len - 1].kind);
+}
+
+test "tolerant mode keeps lexing past bad input" {
+    var interner = StringInterner.init(testing.allocator);
+    defer interner.deinit();
+
+    var lexer = Lexer.init("test.nyx", "mov q0, \x00 1 ; a\x00b\n\"open\nhlt", &interner, testing.allocator);
+    lexer.tolerant = true;
+    const tokens = try lexer.tokenize(testing.allocator);
+    defer testing.allocator.free(tokens);
+
+    const expected = [_]Token.Kind{ .kw_mov, .register, .comma, .illegal, .integer, .newline, .illegal, .newline, .kw_hlt, .eof };
+    try testing.expectEqual(expected.len, tokens.len);
+    for (expected, tokens) |kind, token| try testing.expectEqual(kind, token.kind);
+    try testing.expectEqual(@as(usize, 8), tokens[3].span.start);
+    try testing.expectEqualStrings("\"open", tokens[6].literal);
+    try testing.expectEqual(@as(usize, 18), tokens[6].span.start);
+    try testing.expectEqual(@as(usize, 22), tokens[6].span.end);
+
+    // Without the mode the NUL byte ends the input.
+    var strict = Lexer.init("test.nyx", "mov q0, \x00 1", &interner, testing.allocator);
+    const cut = try strict.tokenize(testing.allocator);
+    defer testing.allocator.free(cut);
+    try testing.expectEqual(Token.Kind.eof, cut[3].kind);
+    try testing.expectEqual(@as(usize, 4), cut.len);
+}
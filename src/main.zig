@@ -1,7 +1,9 @@
 const std = @import("std");
+const build_options = @import("build_options");
 const process = std.process;
 const fmt = std.fmt;
 const fs = std.fs;
+const Io = std.Io;
 const Allocator = std.mem.Allocator;
 const ArrayList = std.array_list.Managed;
 const fehler = @import("fehler");
@@ -9,9 +11,34 @@ const yazap = @import("yazap");
 const StringInterner = @import("StringInterner.zig");
 const Lexer = @import("lexer/Lexer.zig");
 const Parser = @import("parser/Parser.zig");
+const ast = @import("parser/ast.zig");
+const ast_json = @import("parser/json.zig");
+const ast_ron = @import("parser/ron.zig");
 const Compiler = @import("compiler/Compiler.zig");
+const Object = @import("compiler/Object.zig");
+const Linker = @import("compiler/Linker.zig");
 const Vm = @import("vm/Vm.zig");
+const Opcode = @import("compiler/opcode.zig").Opcode;
 const Preprocessor = @import("preprocessor/Preprocessor.zig");
+const Debugger = @import("debugger/Debugger.zig");
+const Repl = @import("repl/Repl.zig");
+const Disassembler = @import("disassembler/Disassembler.zig");
+const Journal = @import("vm/Journal.zig");
+const DebugInfo = @import("vm/DebugInfo.zig");
+const Trace = @import("vm/Trace.zig");
+const Console = @import("vm/Console.zig");
+const Clipboard = @import("vm/Clipboard.zig");
+const Display = @import("vm/Display.zig");
+const features = @import("vm/features.zig");
+const LineEditor = @import("LineEditor.zig");
+const syscall = @import("vm/syscall.zig");
+const signature = @import("signature.zig");
+const Manifest = @import("Manifest.zig");
+const migrate = @import("migrate.zig");
+const verify = @import("verify.zig");
+const batch = @import("batch.zig");
+const diff = @import("diff.zig");
+const warning = @import("warning.zig");
 const utils = @import("utils.zig");
 
 pub fn main(init: std.process.Init) !void {
@@ -23,10 +50,29 @@ pub fn main(init: std.process.Init) !void {
     nyx.setProperty(.help_on_empty_args);
 
     try nyx.addSubcommand(try createBuildCommand(&app));
+    try nyx.addSubcommand(try createLinkCommand(&app));
     try nyx.addSubcommand(try createExecCommand(&app));
     try nyx.addSubcommand(try createRunCommand(&app));
+    try nyx.addSubcommand(try createDebugCommand(&app));
+    try nyx.addSubcommand(try createReplCommand(&app));
+    try nyx.addSubcommand(try createDisasmCommand(&app));
+    try nyx.addSubcommand(try createMigrateCommand(&app));
+    try nyx.addSubcommand(try createVerifyCommand(&app));
+    try nyx.addSubcommand(try createDiffCommand(&app));
+    try nyx.addSubcommand(try createSyscallsCommand(&app));
+    try nyx.addSubcommand(try createSymbolsCommand(&app));
+    try nyx.addSubcommand(try createKeygenCommand(&app));
 
-    const matches = try app.parseProcess(init.io, init.minimal.args);
+    // Everything after `--` is passed to the program `run` or `exec` starts.
+    var args_arena = std.heap.ArenaAllocator.init(init.gpa);
+    defer args_arena.deinit();
+    const argv = try init.minimal.args.toSlice(args_arena.allocator());
+    const separator = for (argv, 0..) |arg, i| {
+        if (std.mem.eql(u8, arg, "--")) break i;
+    } else argv.len;
+    const program_args = argv[@min(separator + 1, argv.len)..];
+
+    const matches = try app.parseFrom(init.io, argv[1..separator]);
 
     var reporter = fehler.ErrorReporter.init(init.gpa);
     defer reporter.deinit();
@@ -35,114 +81,378 @@ pub fn main(init: std.process.Init) !void {
         try executeBuildCommand(init.io, init.minimal.environ, init.gpa, build_cmd_matches, &reporter);
     }
 
+    if (matches.subcommandMatches("link")) |link_cmd_matches| {
+        try executeLinkCommand(init.io, init.gpa, link_cmd_matches, &reporter);
+    }
+
     if (matches.subcommandMatches("exec")) |exec_cmd_matches| {
-        try executeExecCommand(init.io, init.gpa, exec_cmd_matches, &reporter);
+        try executeExecCommand(init.io, init.minimal.environ, init.gpa, exec_cmd_matches, program_args, &reporter);
     }
 
     if (matches.subcommandMatches("run")) |run_cmd_matches| {
-        try executeRunCommand(init.io, init.minimal.environ, init.gpa, run_cmd_matches, &reporter);
+        try executeRunCommand(init.io, init.minimal.environ, init.gpa, run_cmd_matches, program_args, &reporter);
+    }
+
+    if (matches.subcommandMatches("debug")) |debug_cmd_matches| {
+        try executeDebugCommand(init.io, init.minimal.environ, init.gpa, debug_cmd_matches, &reporter);
+    }
+
+    if (matches.subcommandMatches("repl")) |repl_cmd_matches| {
+        try executeReplCommand(init.io, init.minimal.environ, init.gpa, repl_cmd_matches, &reporter);
+    }
+
+    if (matches.subcommandMatches("disasm")) |disasm_cmd_matches| {
+        try executeDisasmCommand(init.io, init.gpa, disasm_cmd_matches, &reporter);
+    }
+
+    if (matches.subcommandMatches("migrate")) |migrate_cmd_matches| {
+        try executeMigrateCommand(init.io, init.gpa, migrate_cmd_matches, &reporter);
+    }
+
+    if (matches.subcommandMatches("verify")) |verify_cmd_matches| {
+        try executeVerifyCommand(init.io, init.gpa, verify_cmd_matches, &reporter);
+    }
+
+    if (matches.subcommandMatches("diff")) |diff_cmd_matches| {
+        try executeDiffCommand(init.io, init.minimal.environ, init.gpa, diff_cmd_matches, &reporter);
+    }
+
+    if (matches.subcommandMatches("syscalls")) |syscalls_cmd_matches| {
+        try executeSyscallsCommand(init.io, syscalls_cmd_matches);
+    }
+
+    if (matches.subcommandMatches("symbols")) |symbols_cmd_matches| {
+        try executeSymbolsCommand(init.io, init.gpa, symbols_cmd_matches, &reporter);
+    }
+
+    if (matches.subcommandMatches("keygen")) |keygen_cmd_matches| {
+        try executeKeygenCommand(init.io, init.gpa, keygen_cmd_matches, &reporter);
     }
 }
 
 fn createBuildCommand(app: *yazap.App) !yazap.Command {
     var build_cmd = app.createCommand("build", "Compile source code to bytecode");
+    var files = yazap.Arg.positional("FILE", "Paths to the source files to compile, in order, or - for stdin", null);
+    files.setProperty(.takes_multiple_values);
     try build_cmd.addArgs(&.{
-        yazap.Arg.positional("FILE", "Path to the source file to compile", null),
-        yazap.Arg.singleValueOption("output", 'o', "Optional path to write the compiled bytecode output"),
+        files,
+        yazap.Arg.singleValueOption("output", 'o', "Optional path to write the compiled bytecode output, or - for stdout"),
+        yazap.Arg.singleValueOption("emit", null, "What to write: bytecode (default), obj, a relocatable object for nyx link, ast, the preprocessed AST as JSON, or listing, each source line with its bytes"),
+        yazap.Arg.singleValueOption("format", null, "How --emit ast writes the AST: json (default) or ron"),
         yazap.Arg.multiValuesOption("include", 'i', "Adds an include directory to the search path", 65536),
+        yazap.Arg.multiValuesOption("define", 'D', "Define NAME, or NAME=VALUE, before preprocessing", 65536),
         yazap.Arg.booleanOption("disable-preprocessor", null, "Stop the preprocessor from running"),
+        yazap.Arg.booleanOption("strict", null, "Reject implicit sizes, a missing entry point, truncated immediates and unused labels"),
+        yazap.Arg.booleanOption("deny-warnings", null, "Report warnings as errors and fail the build if there are any"),
+        yazap.Arg.booleanOption("truncate-immediates", null, "Warn about immediates that do not fit their operand and keep their low bits instead of failing"),
+        yazap.Arg.multiValuesOption("allow", null, "Silence a warning: directive, unused_label, unreachable_code, entry_outside_text or truncated_immediate", 65536),
+        yazap.Arg.booleanOption("optimize", 'O', "Fold constant registers into the addresses that use them"),
+        yazap.Arg.booleanOption("absolute-jumps", null, "Encode jumps and calls to labels with 64-bit addresses instead of the shortest displacement"),
+        yazap.Arg.booleanOption("debug-info", 'g', "Embed source lines and labels so runtime errors point at the source"),
+        yazap.Arg.booleanOption("symbols", null, "Embed labels without source lines so runtime errors and nyx symbols can name them"),
+        yazap.Arg.singleValueOption("sign", null, "Sign the bytecode with the secret key in this file"),
+        yazap.Arg.booleanOption("size-report", null, "Print the bytes contributed by each section and label to stderr"),
+        yazap.Arg.singleValueOption("manifest", null, "Write a JSON description of the build to this file"),
     });
     build_cmd.setProperty(.positional_arg_required);
     build_cmd.setProperty(.help_on_empty_args);
     return build_cmd;
 }
 
+fn createLinkCommand(app: *yazap.App) !yazap.Command {
+    var link_cmd = app.createCommand("link", "Link object files built with --emit obj into bytecode");
+    var files = yazap.Arg.positional("FILE", "Paths to the object files to link, in order", null);
+    files.setProperty(.takes_multiple_values);
+    try link_cmd.addArgs(&.{
+        files,
+        yazap.Arg.singleValueOption("output", 'o', "Optional path to write the linked bytecode output, or - for stdout"),
+    });
+    link_cmd.setProperty(.positional_arg_required);
+    link_cmd.setProperty(.help_on_empty_args);
+    return link_cmd;
+}
+
 fn createExecCommand(app: *yazap.App) !yazap.Command {
     var exec_cmd = app.createCommand("exec", "Execute existing bytecode in the virtual machine");
     try exec_cmd.addArgs(&.{
-        yazap.Arg.positional("FILE", "Path to the precompiled bytecode file to execute", null),
+        yazap.Arg.positional("FILE", "Path to the precompiled bytecode file to execute, or - for stdin", null),
+        yazap.Arg.singleValueOption("batch", null, "Run every bytecode file listed in this file, one per line, and report each as JSON"),
+        yazap.Arg.singleValueOption("results", null, "With --batch, write the JSON results to this path instead of stdout"),
         yazap.Arg.multiValuesOption("library", 'l', "Link a dynamic libraries", 65536),
         yazap.Arg.singleValueOption("memory-size", 'm', "Size of virtual machine memory in bytes"),
+        yazap.Arg.booleanOption("debug-heap", null, "Report heap statistics and leaked allocations when the program halts"),
+        yazap.Arg.singleValueOption("fill", null, "Initialize unused memory with a byte pattern instead of zeros (e.g. 0xAA)"),
+        yazap.Arg.singleValueOption("console", null, "Map a text console of ROWSxCOLS cells after memory (e.g. 25x80)"),
+        yazap.Arg.booleanOption("display", null, "Map a framebuffer and show it in a window (needs a build with -Ddisplay=true)"),
+        yazap.Arg.singleValueOption("division", null, "What integer division by zero does: trap (default), saturate, wrap or flag"),
+        yazap.Arg.singleValueOption("heap-size", null, "Bytes of memory after the program reserved for sys_alloc"),
+        yazap.Arg.singleValueOption("stack-guard", null, "Fault on access to a guard region this many bytes below the stack"),
+        yazap.Arg.singleValueOption("max-steps", null, "Stop the program with an error after this many instructions"),
+        yazap.Arg.multiValuesOption("env", null, "Pass the host environment variable NAME to the program", 65536),
+        yazap.Arg.booleanOption("aslr", null, "Start the stack and heap at random addresses on every run"),
+        yazap.Arg.booleanOption("no-protect", null, "Let the program write over its code and execute any memory"),
+        yazap.Arg.booleanOption("trace", null, "Print every executed instruction and the registers it changed to stderr"),
+        yazap.Arg.singleValueOption("snapshot-on-exit", null, "Save the VM's registers, memory and descriptors to this file when the program stops"),
+        yazap.Arg.singleValueOption("resume", null, "Start from a state saved with --snapshot-on-exit instead of the entry point"),
+        yazap.Arg.booleanOption("require-signed", null, "Refuse to run bytecode that is not signed by the trusted key"),
+        yazap.Arg.singleValueOption("trusted-key", null, "Path to the public key used to verify signed bytecode"),
     });
-    exec_cmd.setProperty(.positional_arg_required);
     exec_cmd.setProperty(.help_on_empty_args);
     return exec_cmd;
 }
 
 fn createRunCommand(app: *yazap.App) !yazap.Command {
     var run_cmd = app.createCommand("run", "Compile and execute source code in the virtual machine");
+    var files = yazap.Arg.positional("FILE", "Paths to the source files to compile and execute, in order, or - for stdin", null);
+    files.setProperty(.takes_multiple_values);
     try run_cmd.addArgs(&.{
-        yazap.Arg.positional("FILE", "Path to the source file to compile and execute", null),
+        files,
         yazap.Arg.singleValueOption("output", 'o', "Optional path to write the compiled bytecode output"),
         yazap.Arg.multiValuesOption("library", 'l', "Link a dynamic libraries", 65536),
         yazap.Arg.multiValuesOption("include", 'i', "Adds an include directory to the search path", 65536),
+        yazap.Arg.multiValuesOption("define", 'D', "Define NAME, or NAME=VALUE, before preprocessing", 65536),
         yazap.Arg.singleValueOption("memory-size", 'm', "Size of virtual machine memory in bytes"),
+        yazap.Arg.booleanOption("debug-heap", null, "Report heap statistics and leaked allocations when the program halts"),
+        yazap.Arg.singleValueOption("fill", null, "Initialize unused memory with a byte pattern instead of zeros (e.g. 0xAA)"),
+        yazap.Arg.singleValueOption("console", null, "Map a text console of ROWSxCOLS cells after memory (e.g. 25x80)"),
+        yazap.Arg.booleanOption("display", null, "Map a framebuffer and show it in a window (needs a build with -Ddisplay=true)"),
+        yazap.Arg.singleValueOption("division", null, "What integer division by zero does: trap (default), saturate, wrap or flag"),
+        yazap.Arg.singleValueOption("heap-size", null, "Bytes of memory after the program reserved for sys_alloc"),
+        yazap.Arg.singleValueOption("stack-guard", null, "Fault on access to a guard region this many bytes below the stack"),
+        yazap.Arg.singleValueOption("max-steps", null, "Stop the program with an error after this many instructions"),
+        yazap.Arg.multiValuesOption("env", null, "Pass the host environment variable NAME to the program", 65536),
+        yazap.Arg.booleanOption("aslr", null, "Load the program, stack and heap at random addresses on every run"),
+        yazap.Arg.booleanOption("no-protect", null, "Let the program write over its code and execute any memory"),
+        yazap.Arg.booleanOption("trace", null, "Print every executed instruction and the registers it changed to stderr"),
+        yazap.Arg.singleValueOption("snapshot-on-exit", null, "Save the VM's registers, memory and descriptors to this file when the program stops"),
+        yazap.Arg.singleValueOption("resume", null, "Start from a state saved with --snapshot-on-exit instead of the entry point"),
         yazap.Arg.booleanOption("disable-preprocessor", null, "Stop the preprocessor from running"),
+        yazap.Arg.booleanOption("strict", null, "Reject implicit sizes, a missing entry point, truncated immediates and unused labels"),
+        yazap.Arg.booleanOption("deny-warnings", null, "Report warnings as errors and fail the build if there are any"),
+        yazap.Arg.booleanOption("truncate-immediates", null, "Warn about immediates that do not fit their operand and keep their low bits instead of failing"),
+        yazap.Arg.multiValuesOption("allow", null, "Silence a warning: directive, unused_label, unreachable_code, entry_outside_text or truncated_immediate", 65536),
+        yazap.Arg.booleanOption("optimize", 'O', "Fold constant registers into the addresses that use them"),
+        yazap.Arg.booleanOption("absolute-jumps", null, "Encode jumps and calls to labels with 64-bit addresses instead of the shortest displacement"),
+        yazap.Arg.booleanOption("debug-info", 'g', "Embed source lines and labels so runtime errors point at the source"),
+        yazap.Arg.booleanOption("symbols", null, "Embed labels without source lines so runtime errors and nyx symbols can name them"),
+        yazap.Arg.booleanOption("size-report", null, "Print the bytes contributed by each section and label to stderr"),
     });
     run_cmd.setProperty(.positional_arg_required);
     run_cmd.setProperty(.help_on_empty_args);
     return run_cmd;
 }
 
-fn compileSourceFile(
+fn createDebugCommand(app: *yazap.App) !yazap.Command {
+    var debug_cmd = app.createCommand("debug", "Compile source code and step through it interactively");
+    try debug_cmd.addArgs(&.{
+        yazap.Arg.positional("FILE", "Path to the source file to debug", null),
+        yazap.Arg.multiValuesOption("library", 'l', "Link a dynamic libraries", 65536),
+        yazap.Arg.multiValuesOption("include", 'i', "Adds an include directory to the search path", 65536),
+        yazap.Arg.multiValuesOption("define", 'D', "Define NAME, or NAME=VALUE, before preprocessing", 65536),
+        yazap.Arg.singleValueOption("memory-size", 'm', "Size of virtual machine memory in bytes"),
+        yazap.Arg.singleValueOption("fill", null, "Initialize unused memory with a byte pattern instead of zeros (e.g. 0xAA)"),
+        yazap.Arg.singleValueOption("console", null, "Map a text console of ROWSxCOLS cells after memory (e.g. 25x80)"),
+        yazap.Arg.singleValueOption("division", null, "What integer division by zero does: trap (default), saturate, wrap or flag"),
+        yazap.Arg.singleValueOption("heap-size", null, "Bytes of memory after the program reserved for sys_alloc"),
+        yazap.Arg.singleValueOption("stack-guard", null, "Fault on access to a guard region this many bytes below the stack"),
+        yazap.Arg.booleanOption("aslr", null, "Start the stack and heap at random addresses on every run"),
+        yazap.Arg.booleanOption("no-protect", null, "Let the program write over its code and execute any memory"),
+        yazap.Arg.singleValueOption("history", null, "Number of instructions that can be reverse-stepped"),
+        yazap.Arg.booleanOption("disable-preprocessor", null, "Stop the preprocessor from running"),
+        yazap.Arg.booleanOption("strict", null, "Reject implicit sizes, a missing entry point, truncated immediates and unused labels"),
+        yazap.Arg.booleanOption("deny-warnings", null, "Report warnings as errors and fail the build if there are any"),
+        yazap.Arg.booleanOption("truncate-immediates", null, "Warn about immediates that do not fit their operand and keep their low bits instead of failing"),
+        yazap.Arg.multiValuesOption("allow", null, "Silence a warning: directive, unused_label, unreachable_code, entry_outside_text or truncated_immediate", 65536),
+    });
+    debug_cmd.setProperty(.positional_arg_required);
+    debug_cmd.setProperty(.help_on_empty_args);
+    return debug_cmd;
+}
+
+fn createReplCommand(app: *yazap.App) !yazap.Command {
+    var repl_cmd = app.createCommand("repl", "Assemble and execute instructions interactively");
+    try repl_cmd.addArgs(&.{
+        yazap.Arg.multiValuesOption("library", 'l', "Link a dynamic libraries", 65536),
+        yazap.Arg.singleValueOption("memory-size", 'm', "Size of virtual machine memory in bytes"),
+        yazap.Arg.singleValueOption("max-steps", null, "Stop a line after this many instructions (default: 1000000)"),
+    });
+    return repl_cmd;
+}
+
+fn createDisasmCommand(app: *yazap.App) !yazap.Command {
+    var disasm_cmd = app.createCommand("disasm", "Decode bytecode back into assembly");
+    try disasm_cmd.addArgs(&.{
+        yazap.Arg.positional("FILE", "Path to the bytecode file to disassemble", null),
+        yazap.Arg.singleValueOption("output", 'o', "Optional path to write the assembly to instead of stdout"),
+    });
+    disasm_cmd.setProperty(.positional_arg_required);
+    disasm_cmd.setProperty(.help_on_empty_args);
+    return disasm_cmd;
+}
+
+fn createMigrateCommand(app: *yazap.App) !yazap.Command {
+    var migrate_cmd = app.createCommand("migrate", "Rewrite bytecode from an older toolchain to the current opcodes");
+    try migrate_cmd.addArgs(&.{
+        yazap.Arg.positional("FILE", "Path to the bytecode file to migrate", null),
+        yazap.Arg.singleValueOption("output", 'o', "Optional path to write the migrated bytecode to instead of FILE"),
+    });
+    migrate_cmd.setProperty(.positional_arg_required);
+    migrate_cmd.setProperty(.help_on_empty_args);
+    return migrate_cmd;
+}
+
+fn createDiffCommand(app: *yazap.App) !yazap.Command {
+    var diff_cmd = app.createCommand("diff", "Compare the instructions of two programs symbol by symbol");
+    var files = yazap.Arg.positional("FILE", "The old and the new program, each bytecode or a .nyx source file", null);
+    files.setProperty(.takes_multiple_values);
+    try diff_cmd.addArgs(&.{
+        files,
+        yazap.Arg.multiValuesOption("include", 'i', "Adds an include directory to the search path", 65536),
+        yazap.Arg.multiValuesOption("define", 'D', "Define NAME, or NAME=VALUE, before preprocessing", 65536),
+        yazap.Arg.booleanOption("optimize", 'O', "Fold constant registers into the addresses that use them"),
+    });
+    diff_cmd.setProperty(.positional_arg_required);
+    diff_cmd.setProperty(.help_on_empty_args);
+    return diff_cmd;
+}
+
+fn createSyscallsCommand(app: *yazap.App) !yazap.Command {
+    var syscalls_cmd = app.createCommand("syscalls", "Print the syscall table");
+    try syscalls_cmd.addArgs(&.{
+        yazap.Arg.booleanOption("header", null, "Print an include file with a sys_<name> macro for every syscall instead"),
+    });
+    return syscalls_cmd;
+}
+
+fn createSymbolsCommand(app: *yazap.App) !yazap.Command {
+    var symbols_cmd = app.createCommand("symbols", "List the labels in bytecode built with -g or --symbols");
+    try symbols_cmd.addArgs(&.{
+        yazap.Arg.positional("FILE", "Path to the bytecode file to read the labels from", null),
+        yazap.Arg.singleValueOption("sort", null, "Order the labels by address (default) or name"),
+    });
+    symbols_cmd.setProperty(.positional_arg_required);
+    symbols_cmd.setProperty(.help_on_empty_args);
+    return symbols_cmd;
+}
+
+fn createVerifyCommand(app: *yazap.App) !yazap.Command {
+    var verify_cmd = app.createCommand("verify", "Run two bytecode files side by side and report where they first behave differently");
+    var files = yazap.Arg.positional("FILE", "Paths to the two bytecode files to compare", null);
+    files.setProperty(.takes_multiple_values);
+    try verify_cmd.addArgs(&.{
+        files,
+        yazap.Arg.singleValueOption("input", null, "File whose contents both programs read from stdin (default: no input)"),
+        yazap.Arg.multiValuesOption("library", 'l', "Link a dynamic libraries", 65536),
+        yazap.Arg.singleValueOption("memory-size", 'm', "Size of virtual machine memory in bytes"),
+        yazap.Arg.singleValueOption("division", null, "What integer division by zero does: trap (default), saturate, wrap or flag"),
+        yazap.Arg.singleValueOption("heap-size", null, "Bytes of memory after the program reserved for sys_alloc"),
+        yazap.Arg.booleanOption("syscalls-only", null, "Compare syscalls and how the programs end, not their final registers and memory"),
+    });
+    verify_cmd.setProperty(.positional_arg_required);
+    verify_cmd.setProperty(.help_on_empty_args);
+    return verify_cmd;
+}
+
+fn createKeygenCommand(app: *yazap.App) !yazap.Command {
+    var keygen_cmd = app.createCommand("keygen", "Generate a key pair for signing bytecode");
+    try keygen_cmd.addArgs(&.{
+        yazap.Arg.positional("NAME", "Writes the secret key to NAME and the public key to NAME.pub", null),
+    });
+    keygen_cmd.setProperty(.positional_arg_required);
+    keygen_cmd.setProperty(.help_on_empty_args);
+    return keygen_cmd;
+}
+
+/// Compile `input_file_paths` as one program, as if the files were
+/// concatenated in order. The first file is the main source file, even when
+/// it is empty. Every environment variable is unset when `env` is null.
+fn compileSourceFiles(
     io: std.Io,
-    env: std.process.Environ,
+    env: ?std.process.Environ,
     gpa: Allocator,
-    input_file_path: []const u8,
-    include_paths: []const []const u8,
-    run_preprocessor: bool,
+    input_file_paths: []const []const u8,
+    options: CompileOptions,
     reporter: *fehler.ErrorReporter,
-) ![]const u8 {
-    if (!utils.fileExists(io, input_file_path)) {
-        logError(reporter, "{s}: cannot find file", .{input_file_path});
-        process.exit(1);
+    writer: *Io.Writer,
+    symbols: ?*Debugger.Symbols,
+    size_report: ?*Io.Writer,
+    manifest: ?*Manifest,
+) !void {
+    // Holds the sources and lexers, which the statements point into.
+    var arena = std.heap.ArenaAllocator.init(gpa);
+    defer arena.deinit();
+
+    var interner = StringInterner.init(gpa);
+    defer interner.deinit();
+
+    var parsers = ArrayList(Parser).init(gpa);
+    defer {
+        for (parsers.items) |*parser| parser.deinit();
+        parsers.deinit();
     }
 
-    const input = try utils.readFromFile(io, gpa, input_file_path);
-    defer gpa.free(input);
+    var stmts = ArrayList(ast.Statement).init(arena.allocator());
+    var main_input: ?[]const u8 = null;
+    var all_include_paths = ArrayList([]const u8).init(gpa);
+    try all_include_paths.append("");
 
-    try reporter.addSource(input_file_path, input);
+    for (input_file_paths) |path| {
+        const from_stdin = std.mem.eql(u8, path, "-");
+        if (!from_stdin and !utils.fileExists(io, path)) {
+            logError(reporter, "{s}: cannot find file", .{path});
+            process.exit(1);
+        }
 
-    var interner = StringInterner.init(gpa);
-    defer interner.deinit();
+        const input_file_path = sourceName(path);
+        const input = try utils.readInput(io, arena.allocator(), path);
+        try reporter.addSource(input_file_path, input);
+        if (main_input == null) main_input = input;
 
-    var lexer = Lexer.init(input_file_path, input, &interner, gpa);
+        const lexer = try arena.allocator().create(Lexer);
+        lexer.* = Lexer.init(input_file_path, input, &interner, gpa);
 
-    var parser = Parser.init(&lexer, reporter, gpa);
-    defer parser.deinit();
+        try parsers.append(Parser.init(lexer, reporter, gpa));
+        try stmts.appendSlice(try parsers.items[parsers.items.len - 1].parse());
+        if (!from_stdin) try all_include_paths.append(fs.path.basename(input_file_path));
+    }
 
-    const stmts = try parser.parse();
+    const input_file_path = sourceName(input_file_paths[0]);
+    const input = main_input.?;
 
-    var all_include_paths = ArrayList([]const u8).init(gpa);
-    try all_include_paths.append("");
-    try all_include_paths.append(fs.path.basename(input_file_path));
-    try all_include_paths.appendSlice(include_paths);
-    const stdlib_path = env.getAlloc(gpa, "NYX_STDLIB_PATH") catch |err| switch (err) {
+    try all_include_paths.appendSlice(options.include_paths);
+    const stdlib_path = if (env) |e| e.getAlloc(gpa, "NYX_STDLIB_PATH") catch |err| switch (err) {
         error.EnvironmentVariableMissing => null,
         else => return err,
-    };
+    } else null;
     if (stdlib_path) |path| try all_include_paths.append(path);
     defer if (stdlib_path) |path| gpa.free(path);
 
-    var preprocessor: ?Preprocessor = if (run_preprocessor)
+    var preprocessor: ?Preprocessor = if (options.run_preprocessor)
         try Preprocessor.init(
             io,
+            env,
             gpa,
             input_file_path,
             input,
-            stmts,
+            stmts.items,
             &interner,
             reporter,
             try all_include_paths.toOwnedSlice(),
+            options.defines,
         )
     else
         null;
     defer if (preprocessor) |*p| p.deinit();
+    if (preprocessor) |*p| p.warnings = options.warnings;
 
     const new_stmts = if (preprocessor) |*p|
         try p.process()
     else
-        stmts;
+        stmts.items;
+    if (options.emit == .ast) return switch (options.ast_format) {
+        .json => ast_json.writeStatements(new_stmts, &interner, writer),
+        .ron => ast_ron.writeStatements(new_stmts, &interner, writer),
+    };
 
     var compiler = try Compiler.init(
         new_stmts,
@@ -153,55 +463,140 @@ fn compileSourceFile(
         gpa,
     );
     defer compiler.deinit();
+    compiler.strict = options.strict;
+    compiler.truncate_immediates = options.truncate_immediates;
+    compiler.warnings = options.warnings;
+    compiler.optimize = options.optimize;
+    compiler.debug_info = options.debug_info;
+    compiler.symbols = options.symbols;
+    compiler.absolute_jumps = options.absolute_jumps;
 
-    return try compiler.compile();
+    switch (options.emit) {
+        .bytecode => try compiler.compileTo(writer),
+        .obj => {
+            var object = try compiler.compileObject();
+            defer object.deinit();
+            try object.write(writer);
+        },
+        .listing => {
+            const image = try compiler.compile();
+            defer gpa.free(image);
+            try compiler.writeListing(writer);
+        },
+        .ast => unreachable,
+    }
+    if (symbols) |map| try compiler.exportSymbols(map);
+    if (size_report) |report_writer| try compiler.writeSizeReport(report_writer);
+    if (manifest) |m| m.* = .fromCompiler(&compiler, input_file_paths);
 }
 
-fn runBytecode(
-    bytecode: []const u8,
-    external_libraries: [][]const u8,
-    memory_size: usize,
-    gpa: Allocator,
-) !void {
-    var vm = try Vm.init(bytecode, memory_size, external_libraries, gpa);
-    defer vm.deinit();
-    try vm.run();
+/// Name diagnostics use for `path`, which is `-` for source read from stdin.
+fn sourceName(path: []const u8) []const u8 {
+    return if (std.mem.eql(u8, path, "-")) "<stdin>" else path;
 }
 
-fn executeBuildCommand(
-    io: std.Io,
-    env: std.process.Environ,
-    gpa: Allocator,
-    matches: yazap.ArgMatches,
-    reporter: *fehler.ErrorReporter,
-) !void {
-    const input_file_path = matches.getSingleValue("FILE").?;
-    const output_file_path = if (matches.getSingleValue("output")) |output| output else "out.nyb";
-    const include_paths = matches.getMultiValues("include") orelse &.{};
-    const run_preprocessor = !matches.containsArg("disable-preprocessor");
+const Emit = enum { bytecode, obj, ast, listing };
 
-    const bytecode = try compileSourceFile(
-        io,
-        env,
-        gpa,
-        input_file_path,
-        include_paths,
-        run_preprocessor,
-        reporter,
-    );
-    defer gpa.free(bytecode);
+const AstFormat = enum { json, ron };
+
+const CompileOptions = struct {
+    emit: Emit,
+    ast_format: AstFormat,
+    include_paths: []const []const u8,
+    defines: []const Preprocessor.Define,
+    run_preprocessor: bool,
+    strict: bool,
+    truncate_immediates: bool,
+    warnings: warning.Policy,
+    optimize: bool,
+    debug_info: bool,
+    symbols: bool,
+    absolute_jumps: bool,
+};
+
+fn parseCompileOptions(gpa: Allocator, matches: yazap.ArgMatches, reporter: *fehler.ErrorReporter) !CompileOptions {
+    const emit = if (matches.getSingleValue("emit")) |kind|
+        std.meta.stringToEnum(Emit, kind) orelse {
+            logError(reporter, "{s}: not an output kind, expected bytecode, obj, ast or listing", .{kind});
+            process.exit(1);
+        }
+    else
+        .bytecode;
+    const ast_format: AstFormat = if (matches.getSingleValue("format")) |format| blk: {
+        if (emit != .ast) {
+            logError(reporter, "--format applies to --emit ast, not --emit {s}", .{@tagName(emit)});
+            process.exit(1);
+        }
+        const parsed = std.meta.stringToEnum(AstFormat, format) orelse {
+            logError(reporter, "{s}: not an AST format, expected json or ron", .{format});
+            process.exit(1);
+        };
+        if (parsed == .ron and !build_options.ron) {
+            logError(reporter, "this nyx was built without RON support; rebuild with -Dron", .{});
+            process.exit(1);
+        }
+        break :blk parsed;
+    } else .json;
 
-    try utils.writeToFile(io, output_file_path, bytecode);
+    const define_args = matches.getMultiValues("define") orelse &.{};
+    const defines = try gpa.alloc(Preprocessor.Define, define_args.len);
+    for (define_args, defines) |arg, *define| {
+        define.* = Preprocessor.Define.parse(arg) orelse {
+            logError(reporter, "{s}: not a valid definition, expected NAME or NAME=VALUE", .{arg});
+            process.exit(1);
+        };
+    }
+
+    var warnings = warning.Policy{ .deny = matches.containsArg("deny-warnings") };
+    for (matches.getMultiValues("allow") orelse &.{}) |name| {
+        const code = std.meta.stringToEnum(warning.Code, name) orelse {
+            logError(reporter, "{s}: not a warning, expected directive, unused_label, unreachable_code, entry_outside_text or truncated_immediate", .{name});
+            process.exit(1);
+        };
+        warnings.allowed.insert(code);
+    }
+
+    return CompileOptions{
+        .emit = emit,
+        .ast_format = ast_format,
+        .include_paths = matches.getMultiValues("include") orelse &.{},
+        .defines = defines,
+        .run_preprocessor = !matches.containsArg("disable-preprocessor"),
+        .strict = matches.containsArg("strict"),
+        .truncate_immediates = matches.containsArg("truncate-immediates"),
+        .warnings = warnings,
+        .optimize = matches.containsArg("optimize"),
+        .debug_info = matches.containsArg("debug-info"),
+        .symbols = matches.containsArg("symbols"),
+        .absolute_jumps = matches.containsArg("absolute-jumps"),
+    };
 }
 
-fn executeExecCommand(
-    io: std.Io,
-    gpa: Allocator,
-    matches: yazap.ArgMatches,
-    reporter: *fehler.ErrorReporter,
-) !void {
-    const input_file_path = matches.getSingleValue("FILE").?;
-    const external_libraries: [][]const u8 = matches.getMultiValues("library") orelse &.{};
+const RunOptions = struct {
+    external_libraries: [][]const u8,
+    memory_size: usize,
+    debug_heap: bool,
+    fill: ?u8,
+    console: ?Console.Size,
+    display: bool,
+    division: Vm.Division,
+    heap_size: ?usize,
+    stack_guard: ?usize,
+    max_steps: ?u64,
+    aslr: bool,
+    protect: bool,
+    trace: bool,
+    /// Where `--snapshot-on-exit` saves the VM's state.
+    snapshot_path: ?[]const u8,
+    /// State saved by `--snapshot-on-exit` to start from.
+    resume_path: ?[]const u8,
+    /// Copied to the top of the stack with `Vm.setArguments` unless empty.
+    /// Filled in by `setProgramArguments`.
+    arguments: []const []const u8 = &.{},
+    environment: []const []const u8 = &.{},
+};
+
+fn parseRunOptions(matches: yazap.ArgMatches, reporter: *fehler.ErrorReporter) RunOptions {
     const memory_size = if (matches.getSingleValue("memory-size")) |size|
         fmt.parseInt(usize, size, 10) catch {
             logError(reporter, "{s}: not a valid number", .{size});
@@ -210,51 +605,1230 @@ fn executeExecCommand(
     else
         65536;
 
-    const bytecode = try utils.readFromFile(io, gpa, input_file_path);
-    defer gpa.free(bytecode);
+    const fill = if (matches.getSingleValue("fill")) |pattern|
+        fmt.parseInt(u8, pattern, 0) catch {
+            logError(reporter, "{s}: not a valid byte", .{pattern});
+            process.exit(1);
+        }
+    else
+        null;
+
+    const console = if (matches.getSingleValue("console")) |size|
+        Console.Size.parse(size) catch |err| {
+            switch (err) {
+                error.InvalidConsoleSize => logError(reporter, "{s}: not a valid console size, expected ROWSxCOLS", .{size}),
+                error.ConsoleTooLarge => logError(reporter, "{s}: console is too large to map", .{size}),
+            }
+            process.exit(1);
+        }
+    else
+        null;
+
+    const division = if (matches.getSingleValue("division")) |mode|
+        std.meta.stringToEnum(Vm.Division, mode) orelse {
+            logError(reporter, "{s}: not a division mode, expected trap, saturate, wrap or flag", .{mode});
+            process.exit(1);
+        }
+    else
+        .trap;
+
+    const heap_size = if (matches.getSingleValue("heap-size")) |size|
+        fmt.parseInt(usize, size, 0) catch {
+            logError(reporter, "{s}: not a valid number", .{size});
+            process.exit(1);
+        }
+    else
+        null;
+
+    const stack_guard = if (matches.getSingleValue("stack-guard")) |slack|
+        fmt.parseInt(usize, slack, 0) catch {
+            logError(reporter, "{s}: not a valid number", .{slack});
+            process.exit(1);
+        }
+    else
+        null;
+
+    const max_steps = if (matches.getSingleValue("max-steps")) |steps|
+        fmt.parseInt(u64, steps, 0) catch {
+            logError(reporter, "{s}: not a valid number", .{steps});
+            process.exit(1);
+        }
+    else
+        null;
 
-    try runBytecode(bytecode, external_libraries, memory_size, gpa);
+    return RunOptions{
+        .external_libraries = matches.getMultiValues("library") orelse &.{},
+        .memory_size = memory_size,
+        .debug_heap = matches.containsArg("debug-heap"),
+        .fill = fill,
+        .console = console,
+        .display = matches.containsArg("display"),
+        .division = division,
+        .heap_size = heap_size,
+        .stack_guard = stack_guard,
+        .max_steps = max_steps,
+        .aslr = matches.containsArg("aslr"),
+        .protect = !matches.containsArg("no-protect"),
+        .trace = matches.containsArg("trace"),
+        .snapshot_path = matches.getSingleValue("snapshot-on-exit"),
+        .resume_path = matches.getSingleValue("resume"),
+    };
 }
 
-fn executeRunCommand(
+fn runBytecode(
     io: std.Io,
     env: std.process.Environ,
-    gpa: Allocator,
-    matches: yazap.ArgMatches,
+    bytecode: []const u8,
+    options: RunOptions,
+    symbols: ?*const Debugger.Symbols,
     reporter: *fehler.ErrorReporter,
+    gpa: Allocator,
 ) !void {
-    const input_file_path = matches.getSingleValue("FILE").?;
-    const output_file_path = if (matches.getSingleValue("output")) |output| output else null;
-    const external_libraries: [][]const u8 = matches.getMultiValues("library") orelse &.{};
-    const include_paths = matches.getMultiValues("include") orelse &.{};
-    const memory_size = if (matches.getSingleValue("memory-size")) |size|
-        fmt.parseInt(usize, size, 10) catch {
-            logError(reporter, "{s}: not a valid number", .{size});
+    var vm = try initVm(bytecode, options, reporter, gpa);
+    defer vm.deinit();
+    vm.division = options.division;
+    if (options.debug_heap) vm.enableHeapStats();
+    if (options.console) |size| try vm.attachConsole(size);
+    var clipboard = try hostClipboard(io, env, gpa);
+    vm.clipboard = clipboard.clipboard();
+    var window: ?Display.Window = if (options.display) openWindow(reporter) else null;
+    defer if (window) |*w| w.close();
+    if (window) |*w| {
+        try vm.attachFramebuffer();
+        vm.display = w.display();
+    }
+
+    var stderr_buffer: [4096]u8 = undefined;
+    var stderr_writer = Io.File.stderr().writer(io, &stderr_buffer);
+    var trace: ?Trace = if (options.trace) try .init(bytecode, &stderr_writer.interface, gpa) else null;
+    defer if (trace) |*t| t.deinit();
+    if (trace) |*t| vm.setTracer(t.tracer());
+    if (options.resume_path) |path| try resumeState(io, &vm, path, reporter, gpa);
+
+    const finished = if (options.max_steps) |steps| vm.runWithFuel(steps) else vm.run();
+    if (options.snapshot_path) |path| {
+        // Resuming retries the instruction that failed rather than starting
+        // in the middle of it.
+        if (!vm.halted) vm.regs.setIp(vm.instruction_start);
+        try saveState(io, &vm, path, gpa);
+    }
+    finished catch |err| {
+        reportTrap(&vm, err, symbols, reporter);
+        process.exit(1);
+    };
+    if (vm.heap_stats) |*stats| stats.print(&vm.heap);
+    if (vm.exit_status) |status| process.exit(status);
+}
+
+/// Put `vm` back in the state `--snapshot-on-exit` saved at `path`.
+fn resumeState(io: std.Io, vm: *Vm, path: []const u8, reporter: *fehler.ErrorReporter, gpa: Allocator) !void {
+    if (!utils.fileExists(io, path)) {
+        logError(reporter, "{s}: cannot find file", .{path});
+        process.exit(1);
+    }
+    const bytes = try utils.readFromFile(io, gpa, path);
+    defer gpa.free(bytes);
+    var state = Vm.State.read(gpa, bytes) catch |err| switch (err) {
+        error.InvalidState => {
+            logError(reporter, "{s}: not a VM state saved with --snapshot-on-exit", .{path});
+            process.exit(1);
+        },
+        else => return err,
+    };
+    defer state.deinit();
+    vm.restoreState(&state) catch |err| switch (err) {
+        error.StateMismatch => {
+            logError(reporter, "{s}: saved with a different memory layout; pass the same --memory-size and --console", .{path});
             process.exit(1);
+        },
+        else => return err,
+    };
+}
+
+/// Write the state of `vm` to `path` for `--resume`.
+fn saveState(io: std.Io, vm: *Vm, path: []const u8, gpa: Allocator) !void {
+    var state = try vm.saveState();
+    defer state.deinit();
+    var output: Io.Writer.Allocating = .init(gpa);
+    defer output.deinit();
+    try state.write(&output.writer);
+    try utils.writeToFile(io, path, output.written());
+}
+
+/// Open the `--display` window, or say why there is none and exit.
+fn openWindow(reporter: *fehler.ErrorReporter) Display.Window {
+    return Display.Window.open("nyx") catch |err| {
+        switch (err) {
+            error.DisplayUnsupported => logError(reporter, "this build of nyx has no display; rebuild it with -Ddisplay=true", .{}),
+            error.DisplayUnavailable => logError(reporter, "cannot open a window for the display", .{}),
         }
-    else
-        65536;
-    const run_preprocessor = !matches.containsArg("disable-preprocessor");
+        process.exit(1);
+    };
+}
 
-    const bytecode = try compileSourceFile(
-        io,
-        env,
-        gpa,
-        input_file_path,
-        include_paths,
-        run_preprocessor,
-        reporter,
-    );
-    defer gpa.free(bytecode);
+/// Report a VM error with the instruction that raised it and the calls that
+/// led there, named after the nearest label when `symbols` or the program's
+/// debug section are available.
+fn reportTrap(vm: *Vm, err: anyerror, symbols: ?*const Debugger.Symbols, reporter: *fehler.ErrorReporter) void {
+    var buffer: [256]u8 = undefined;
+    var location: Io.Writer = .fixed(&buffer);
+    writeLocation(&location, vm, vm.instruction_start, symbols) catch {};
+    if (err == error.StackGuardHit) {
+        logError(reporter, "{s} at {s}: access to 0x{x:0>4} in the stack guard", .{ @errorName(err), location.buffered(), vm.mmu.guard_hit });
+    } else if (err == error.DivisionByZero) {
+        logError(reporter, "{s} at {s}: the divisor is zero (see --division)", .{ @errorName(err), location.buffered() });
+    } else if (err == error.DivisionOverflow) {
+        logError(reporter, "{s} at {s}: the quotient of the smallest signed value and -1 does not fit (see --division)", .{ @errorName(err), location.buffered() });
+    } else if (err == error.InstructionPointerOutOfBounds) {
+        logError(reporter, "{s} at {s}: execution went past the end of memory at 0x{x:0>4}", .{ @errorName(err), location.buffered(), vm.mmu.size() });
+    } else if (err == error.StepLimitExceeded) {
+        logError(reporter, "{s} at {s}: stopped after {d} instructions (see --max-steps)", .{ @errorName(err), location.buffered(), vm.counters.instructions });
+    } else if (err == error.ProtectionFault) {
+        const fault = vm.mmu.fault;
+        logError(reporter, "{s} at {s}: {s} of 0x{x:0>4} in {s} ({f})", .{
+            @errorName(err),
+            location.buffered(),
+            @tagName(fault.access),
+            fault.addr,
+            fault.region.name,
+            fault.region.permissions,
+        });
+    } else {
+        logError(reporter, "{s} at {s}", .{ @errorName(err), location.buffered() });
+    }
 
-    if (output_file_path) |path| {
-        try utils.writeToFile(io, path, bytecode);
+    var frames: [64]Vm.Frame = undefined;
+    for (vm.backtrace(&frames)) |frame| {
+        location = .fixed(&buffer);
+        writeLocation(&location, vm, frame.call_site, symbols) catch {};
+        if (frame.target) |target| {
+            location.writeAll(", calling ") catch {};
+            writeLocation(&location, vm, target, symbols) catch {};
+        }
+        std.debug.print("    called from {s}\n", .{location.buffered()});
     }
+}
+
+/// Write `addr` in hex, followed by `(label+offset)` for the closest label at
+/// or before it and the source line it came from when the program has them.
+fn writeLocation(writer: *Io.Writer, vm: *const Vm, addr: usize, symbols: ?*const Debugger.Symbols) Io.Writer.Error!void {
+    try writer.print("0x{x:0>4}", .{addr});
+
+    var best: ?DebugInfo.Symbol = null;
+    if (symbols) |map| {
+        for (map.keys(), map.values()) |name, value| {
+            if (value > addr or (best != null and value <= best.?.addr)) continue;
+            best = .{ .name = name, .addr = value };
+        }
+    } else if (vm.debug_info) |*info| {
+        best = info.nearestSymbol(addr);
+    }
+    if (best) |symbol| try writeLabelOffset(writer, addr, symbol);
 
-    try runBytecode(bytecode, external_libraries, memory_size, gpa);
+    if (vm.sourceLocation(addr)) |where| try writer.print(" in {f}", .{where});
 }
 
-fn logError(reporter: *fehler.ErrorReporter, comptime format: []const u8, args: anytype) void {
-    const message = std.fmt.allocPrint(std.heap.page_allocator, format, args) catch unreachable;
-    reporter.report(.{ .severity = .err, .message = message });
+/// Write ` (label)`, or ` (label+offset)` when `addr` is past `symbol`.
+fn writeLabelOffset(writer: *Io.Writer, addr: usize, symbol: DebugInfo.Symbol) Io.Writer.Error!void {
+    const offset = addr - symbol.addr;
+    if (offset == 0) {
+        try writer.print(" ({s})", .{symbol.name});
+    } else {
+        try writer.print(" ({s}+0x{x})", .{ symbol.name, offset });
+    }
+}
+
+/// Like `writeLocation` for errors raised while loading `bytecode`, before
+/// there is a VM: the label comes from the program's symbols, if it has any
+/// that can be read.
+fn writeProgramLocation(writer: *Io.Writer, bytecode: []const u8, addr: usize, gpa: Allocator) Io.Writer.Error!void {
+    try writer.print("0x{x:0>4}", .{addr});
+    const header = features.read(bytecode) catch return;
+    const section = header.debugSection(bytecode) orelse return;
+    var info = DebugInfo.read(gpa, section) catch return;
+    defer info.deinit();
+    if (info.nearestSymbol(addr)) |symbol| try writeLabelOffset(writer, addr, symbol);
+}
+
+/// Load `bytecode` into a new VM, naming the missing features if the program
+/// declared `.requires` for something this build does not provide, or what
+/// is wrong with the layout its header declares.
+fn initVm(bytecode: []const u8, options: RunOptions, reporter: *fehler.ErrorReporter, gpa: Allocator) !Vm {
+    var vm = Vm.init(bytecode, options.memory_size, options.external_libraries, gpa) catch |err| switch (err) {
+        error.UnsupportedFeature => {
+            const header = features.read(bytecode) catch unreachable;
+            if (header.unknown != 0) {
+                logError(reporter, "program requires features unknown to this version of nyx (0x{x:0>4})", .{header.unknown});
+            } else {
+                var buffer: [64]u8 = undefined;
+                var names: Io.Writer = .fixed(&buffer);
+                features.writeNames(header.required.differenceWith(features.supported()), &names) catch {};
+                logError(reporter, "program requires {s}, which this build of nyx does not support", .{names.buffered()});
+            }
+            process.exit(1);
+        },
+        error.LayoutMismatch => {
+            const header = features.read(bytecode) catch unreachable;
+            logError(reporter, "program header declares {d} bytes of text and {d} of data, but {d} bytes follow it", .{
+                header.layout.?.text_len,
+                header.layout.?.data_len,
+                header.program(bytecode).len,
+            });
+            process.exit(1);
+        },
+        error.InvalidEntryPoint => {
+            const header = features.read(bytecode) catch unreachable;
+            var buffer: [256]u8 = undefined;
+            var entry: Io.Writer = .fixed(&buffer);
+            writeProgramLocation(&entry, bytecode, @intCast(header.entry), gpa) catch {};
+            logError(reporter, "entry point {s} is past the end of the program, which ends at 0x{x:0>4}", .{ entry.buffered(), header.program(bytecode).len });
+            process.exit(1);
+        },
+        error.EntryOutsideText => {
+            const header = features.read(bytecode) catch unreachable;
+            var buffer: [256]u8 = undefined;
+            var entry: Io.Writer = .fixed(&buffer);
+            writeProgramLocation(&entry, bytecode, @intCast(header.entry), gpa) catch {};
+            logError(reporter, "entry point {s} is outside the text section, which ends at 0x{x:0>4}", .{ entry.buffered(), header.layout.?.text_len });
+            process.exit(1);
+        },
+        error.InvalidDebugInfo => {
+            logError(reporter, "the program's debug section is malformed; rebuild it or build without -g", .{});
+            process.exit(1);
+        },
+        else => return err,
+    };
+    errdefer vm.deinit();
+
+    if (options.heap_size) |size| vm.setHeapSize(size) catch |err| switch (err) {
+        error.HeapTooLarge => {
+            logError(reporter, "a heap of {d} bytes does not fit between the program and the stack in {d} bytes of memory", .{ size, options.memory_size });
+            process.exit(1);
+        },
+        else => return err,
+    };
+    if (options.aslr) try vm.randomizeLayout(std.crypto.random);
+    if (options.fill) |pattern| vm.setFillPattern(pattern);
+    if (options.arguments.len > 0) vm.setArguments(options.arguments, options.environment) catch |err| switch (err) {
+        error.ArgumentsTooLarge => {
+            logError(reporter, "the program's arguments and environment do not fit above the heap", .{});
+            process.exit(1);
+        },
+        else => return err,
+    };
+    if (options.stack_guard) |slack| vm.setStackGuard(slack) catch |err| switch (err) {
+        error.StackGuardTooLarge => {
+            logError(reporter, "a stack guard {d} bytes below the stack does not fit above the heap", .{slack});
+            process.exit(1);
+        },
+        else => return err,
+    };
+    if (!options.protect) vm.disableProtection();
+    vm.setWarner(.{ .ptr = reporter, .warnFn = reportVmWarning });
+    return vm;
+}
+
+fn reportVmWarning(ptr: *anyopaque, _: *const Vm, warning: Vm.Warning) void {
+    const reporter: *fehler.ErrorReporter = @ptrCast(@alignCast(ptr));
+    const message = std.fmt.allocPrint(std.heap.page_allocator, "{f}", .{warning}) catch unreachable;
+    reporter.report(.{ .severity = .warning, .message = message });
+}
+
+fn executeBuildCommand(
+    io: std.Io,
+    env: std.process.Environ,
+    gpa: Allocator,
+    matches: yazap.ArgMatches,
+    reporter: *fehler.ErrorReporter,
+) !void {
+    const input_file_paths = matches.getMultiValues("FILE").?;
+    const compile_options = try parseCompileOptions(gpa, matches, reporter);
+    defer gpa.free(compile_options.defines);
+    const default_output = switch (compile_options.emit) {
+        .bytecode => "out.nyb",
+        .obj => "out.nyo",
+        .ast => if (compile_options.ast_format == .ron) "out.ron" else "out.json",
+        .listing => "out.lst",
+    };
+    const output_file_path = if (matches.getSingleValue("output")) |output| output else default_output;
+
+    if (compile_options.emit == .obj and matches.containsArg("sign")) {
+        logError(reporter, "--sign applies to bytecode; sign the output of nyx link instead", .{});
+        process.exit(1);
+    }
+    if (compile_options.emit == .ast or compile_options.emit == .listing) {
+        // A listing still compiles the program, so it can have a size report.
+        const options: []const []const u8 = if (compile_options.emit == .ast) &.{ "sign", "manifest", "size-report" } else &.{ "sign", "manifest" };
+        for (options) |option| {
+            if (!matches.containsArg(option)) continue;
+            logError(reporter, "--{s} applies to compiled output, not --emit {s}", .{ option, @tagName(compile_options.emit) });
+            process.exit(1);
+        }
+    }
+    if (compile_options.debug_info and compile_options.emit != .bytecode) {
+        logError(reporter, "--debug-info applies to bytecode, not --emit {s}", .{@tagName(compile_options.emit)});
+        process.exit(1);
+    }
+    if (compile_options.symbols and compile_options.emit != .bytecode) {
+        logError(reporter, "--symbols applies to bytecode, not --emit {s}", .{@tagName(compile_options.emit)});
+        process.exit(1);
+    }
+
+    const key_pair = if (matches.getSingleValue("sign")) |path| blk: {
+        const text = try readKeyFile(io, gpa, path, reporter);
+        defer gpa.free(text);
+        break :blk signature.decodeSecretKey(text) catch {
+            logError(reporter, "{s}: not a valid secret key", .{path});
+            process.exit(1);
+        };
+    } else null;
+
+    var stderr_buffer: [4096]u8 = undefined;
+    var stderr_writer = Io.File.stderr().writer(io, &stderr_buffer);
+    const size_report: ?*Io.Writer = if (matches.containsArg("size-report")) &stderr_writer.interface else null;
+
+    const manifest_path = matches.getSingleValue("manifest");
+    const to_stdout = std.mem.eql(u8, output_file_path, "-");
+    if (key_pair == null and manifest_path == null) {
+        // Nothing needs the whole program at once, so it goes straight to
+        // the output.
+        if (to_stdout) {
+            var buffer: [4096]u8 = undefined;
+            var stdout_writer = Io.File.stdout().writer(io, &buffer);
+            try compileSourceFiles(io, env, gpa, input_file_paths, compile_options, reporter, &stdout_writer.interface, null, size_report, null);
+            try stdout_writer.interface.flush();
+        } else {
+            var output_file = try OutputFile.init(io, gpa, output_file_path);
+            defer output_file.deinit();
+            try compileSourceFiles(io, env, gpa, input_file_paths, compile_options, reporter, &output_file.interface, null, size_report, null);
+            try output_file.finish();
+        }
+        try stderr_writer.interface.flush();
+        return;
+    }
+
+    // The signature and the manifest hash cover the whole program, so it is
+    // compiled into memory first.
+    var output: Io.Writer.Allocating = .init(gpa);
+    defer output.deinit();
+    var manifest: Manifest = undefined;
+    try compileSourceFiles(
+        io,
+        env,
+        gpa,
+        input_file_paths,
+        compile_options,
+        reporter,
+        &output.writer,
+        null,
+        size_report,
+        if (manifest_path != null) &manifest else null,
+    );
+    const signed = if (key_pair) |kp| try signature.sign(gpa, output.written(), kp) else null;
+    defer if (signed) |bytes| gpa.free(bytes);
+    const contents = signed orelse output.written();
+
+    if (to_stdout) {
+        var buffer: [4096]u8 = undefined;
+        var stdout_writer = Io.File.stdout().writer(io, &buffer);
+        try stdout_writer.interface.writeAll(contents);
+        try stdout_writer.interface.flush();
+    } else {
+        try utils.writeToFile(io, output_file_path, contents);
+    }
+
+    if (manifest_path) |path| {
+        const outputs = [_]Manifest.Output{.{ .path = output_file_path, .kind = @tagName(compile_options.emit), .contents = contents }};
+        manifest.outputs = &outputs;
+        manifest.entry = switch (compile_options.emit) {
+            .bytecode => (try features.read(output.written())).entry,
+            .obj, .ast, .listing => null,
+        };
+        var json: Io.Writer.Allocating = .init(gpa);
+        defer json.deinit();
+        try manifest.write(&json.writer);
+        try utils.writeToFile(io, path, json.written());
+    }
+    try stderr_writer.interface.flush();
+}
+
+/// A build output written through a temporary file next to it, which is
+/// created on the first write and renamed over the output by `finish`. The
+/// compiler reports every error before it writes a byte, so a failed build,
+/// even one that exits, leaves the previous output alone and no temporary
+/// file behind.
+const OutputFile = struct {
+    io: Io,
+    gpa: Allocator,
+    path: []const u8,
+    temp_path: []const u8,
+    file: ?Io.File = null,
+    file_buffer: [4096]u8 = undefined,
+    file_writer: Io.File.Writer = undefined,
+    finished: bool = false,
+    interface: Io.Writer,
+
+    fn init(io: Io, gpa: Allocator, path: []const u8) !OutputFile {
+        return .{
+            .io = io,
+            .gpa = gpa,
+            .path = path,
+            .temp_path = try fmt.allocPrint(gpa, "{s}.tmp", .{path}),
+            .interface = .{ .vtable = &.{ .drain = drain }, .buffer = &.{} },
+        };
+    }
+
+    /// Remove the temporary file unless `finish` renamed it.
+    fn deinit(self: *OutputFile) void {
+        if (self.file) |file| {
+            file.close(self.io);
+            if (!self.finished) Io.Dir.cwd().deleteFile(self.io, self.temp_path) catch {};
+        }
+        self.gpa.free(self.temp_path);
+    }
+
+    /// Replace the output with what was written.
+    fn finish(self: *OutputFile) !void {
+        const writer = try self.open();
+        try writer.flush();
+        try Io.Dir.rename(Io.Dir.cwd(), self.temp_path, Io.Dir.cwd(), self.path, self.io);
+        self.finished = true;
+    }
+
+    fn open(self: *OutputFile) !*Io.Writer {
+        if (self.file == null) {
+            const file = try Io.Dir.cwd().createFile(self.io, self.temp_path, .{});
+            self.file = file;
+            self.file_writer = file.writer(self.io, &self.file_buffer);
+        }
+        return &self.file_writer.interface;
+    }
+
+    fn drain(w: *Io.Writer, data: []const []const u8, splat: usize) Io.Writer.Error!usize {
+        const self: *OutputFile = @alignCast(@fieldParentPtr("interface", w));
+        const writer = self.open() catch return error.WriteFailed;
+        var n: usize = 0;
+        for (data[0 .. data.len - 1]) |bytes| {
+            try writer.writeAll(bytes);
+            n += bytes.len;
+        }
+        const last = data[data.len - 1];
+        for (0..splat) |_| try writer.writeAll(last);
+        return n + last.len * splat;
+    }
+};
+
+fn executeLinkCommand(
+    io: std.Io,
+    gpa: Allocator,
+    matches: yazap.ArgMatches,
+    reporter: *fehler.ErrorReporter,
+) !void {
+    const input_file_paths = matches.getMultiValues("FILE").?;
+    const output_file_path = if (matches.getSingleValue("output")) |output| output else "out.nyb";
+
+    var objects = ArrayList(Object).init(gpa);
+    defer {
+        for (objects.items) |*object| object.deinit();
+        objects.deinit();
+    }
+
+    for (input_file_paths) |input_file_path| {
+        if (!utils.fileExists(io, input_file_path)) {
+            logError(reporter, "{s}: cannot find file", .{input_file_path});
+            process.exit(1);
+        }
+        const bytes = try utils.readFromFile(io, gpa, input_file_path);
+        defer gpa.free(bytes);
+        try objects.append(Object.read(gpa, bytes) catch |err| switch (err) {
+            error.InvalidObject => {
+                logError(reporter, "{s}: not a valid object file", .{input_file_path});
+                process.exit(1);
+            },
+            else => return err,
+        });
+    }
+
+    var output: Io.Writer.Allocating = .init(gpa);
+    defer output.deinit();
+    Linker.link(gpa, objects.items, reporter, &output.writer) catch |err| switch (err) {
+        error.LinkerError => process.exit(1),
+        else => return err,
+    };
+
+    if (std.mem.eql(u8, output_file_path, "-")) {
+        var buffer: [4096]u8 = undefined;
+        var stdout_writer = Io.File.stdout().writer(io, &buffer);
+        try stdout_writer.interface.writeAll(output.written());
+        try stdout_writer.interface.flush();
+    } else {
+        try utils.writeToFile(io, output_file_path, output.written());
+    }
+}
+
+fn executeExecCommand(
+    io: std.Io,
+    env: std.process.Environ,
+    gpa: Allocator,
+    matches: yazap.ArgMatches,
+    program_args: []const [:0]const u8,
+    reporter: *fehler.ErrorReporter,
+) !void {
+    var options = parseRunOptions(matches, reporter);
+
+    const public_key = if (matches.getSingleValue("trusted-key")) |path| blk: {
+        const text = try readKeyFile(io, gpa, path, reporter);
+        defer gpa.free(text);
+        break :blk signature.decodePublicKey(text) catch {
+            logError(reporter, "{s}: not a valid public key", .{path});
+            process.exit(1);
+        };
+    } else if (matches.containsArg("require-signed")) {
+        logError(reporter, "--require-signed needs a --trusted-key to verify against", .{});
+        process.exit(1);
+    } else null;
+
+    if (matches.getSingleValue("batch")) |list_path| {
+        return executeBatch(io, gpa, list_path, matches.getSingleValue("results"), options, public_key, reporter);
+    }
+
+    const input_file_path = matches.getSingleValue("FILE") orelse {
+        logError(reporter, "exec needs a bytecode FILE, or --batch with a list of them", .{});
+        process.exit(1);
+    };
+
+    const bytecode = try utils.readInput(io, gpa, input_file_path);
+    defer gpa.free(bytecode);
+
+    if (public_key) |key| signature.verify(bytecode, key) catch |err| {
+        switch (err) {
+            error.UnsignedBytecode => logError(reporter, "{s}: bytecode is not signed", .{input_file_path}),
+            error.InvalidSignature => logError(reporter, "{s}: signature does not match the trusted key", .{input_file_path}),
+        }
+        process.exit(1);
+    };
+
+    var arena = std.heap.ArenaAllocator.init(gpa);
+    defer arena.deinit();
+    try setProgramArguments(&options, arena.allocator(), env, matches, input_file_path, program_args);
+
+    try runBytecode(io, env, signature.payload(bytecode), options, null, reporter, gpa);
+}
+
+/// Give the program `path` and `program_args` as its arguments, and the
+/// host environment variables named with `--env` that are set.
+fn setProgramArguments(
+    options: *RunOptions,
+    arena: Allocator,
+    env: std.process.Environ,
+    matches: yazap.ArgMatches,
+    path: []const u8,
+    program_args: []const [:0]const u8,
+) !void {
+    const arguments = try arena.alloc([]const u8, program_args.len + 1);
+    arguments[0] = path;
+    for (arguments[1..], program_args) |*argument, arg| argument.* = arg;
+
+    var environment = ArrayList([]const u8).init(arena);
+    for (matches.getMultiValues("env") orelse &.{}) |name| {
+        const value = env.getAlloc(arena, name) catch |err| switch (err) {
+            error.EnvironmentVariableMissing => continue,
+            else => return err,
+        };
+        try environment.append(try fmt.allocPrint(arena, "{s}={s}", .{ name, value }));
+    }
+
+    options.arguments = arguments;
+    options.environment = environment.items;
+}
+
+/// Run every bytecode file named in the list at `list_path` and write how
+/// each ended as JSON to `results_path`, or to stdout. Files that cannot be
+/// read or verified get a result with the error instead of ending the batch.
+fn executeBatch(
+    io: std.Io,
+    gpa: Allocator,
+    list_path: []const u8,
+    results_path: ?[]const u8,
+    options: RunOptions,
+    public_key: ?signature.PublicKey,
+    reporter: *fehler.ErrorReporter,
+) !void {
+    if (!utils.fileExists(io, list_path)) {
+        logError(reporter, "{s}: cannot find file", .{list_path});
+        process.exit(1);
+    }
+    const list = try utils.readFromFile(io, gpa, list_path);
+    defer gpa.free(list);
+    const paths = try batch.parseList(gpa, list);
+    defer gpa.free(paths);
+
+    const limits = batch.Limits{
+        .memory_size = options.memory_size,
+        .heap_size = options.heap_size,
+        .stack_guard = options.stack_guard,
+        .aslr = options.aslr,
+        .protect = options.protect,
+        .division = options.division,
+        .max_steps = options.max_steps,
+        .external_libraries = options.external_libraries,
+    };
+
+    const results = try gpa.alloc(batch.Result, paths.len);
+    defer gpa.free(results);
+    for (paths, results) |path, *result| {
+        const bytecode = utils.readFromFile(io, gpa, path) catch |err| {
+            result.* = .{ .path = path, .failure = .{ .err = err } };
+            continue;
+        };
+        defer gpa.free(bytecode);
+        if (public_key) |key| signature.verify(bytecode, key) catch |err| {
+            result.* = .{ .path = path, .failure = .{ .err = err } };
+            continue;
+        };
+        result.* = batch.run(gpa, path, signature.payload(bytecode), limits);
+    }
+
+    if (results_path) |path| {
+        var output: Io.Writer.Allocating = .init(gpa);
+        defer output.deinit();
+        try batch.writeResults(results, &output.writer);
+        try utils.writeToFile(io, path, output.written());
+    } else {
+        var buffer: [4096]u8 = undefined;
+        var stdout_writer = Io.File.stdout().writer(io, &buffer);
+        try batch.writeResults(results, &stdout_writer.interface);
+        try stdout_writer.interface.flush();
+    }
+}
+
+fn executeRunCommand(
+    io: std.Io,
+    env: std.process.Environ,
+    gpa: Allocator,
+    matches: yazap.ArgMatches,
+    program_args: []const [:0]const u8,
+    reporter: *fehler.ErrorReporter,
+) !void {
+    const input_file_paths = matches.getMultiValues("FILE").?;
+    const output_file_path = if (matches.getSingleValue("output")) |output| output else null;
+    var compile_options = try parseCompileOptions(gpa, matches, reporter);
+    defer gpa.free(compile_options.defines);
+    var options = parseRunOptions(matches, reporter);
+    // With --aslr the program is linked at a random base, which needs its
+    // relocations.
+    if (options.aslr) compile_options.emit = .obj;
+
+    var arena = std.heap.ArenaAllocator.init(gpa);
+    defer arena.deinit();
+    try setProgramArguments(&options, arena.allocator(), env, matches, input_file_paths[0], program_args);
+
+    var stderr_buffer: [4096]u8 = undefined;
+    var stderr_writer = Io.File.stderr().writer(io, &stderr_buffer);
+    const size_report: ?*Io.Writer = if (matches.containsArg("size-report")) &stderr_writer.interface else null;
+
+    var output: Io.Writer.Allocating = .init(gpa);
+    defer output.deinit();
+
+    var symbol_arena = std.heap.ArenaAllocator.init(gpa);
+    defer symbol_arena.deinit();
+    var symbols = Debugger.Symbols.init(symbol_arena.allocator());
+
+    try compileSourceFiles(
+        io,
+        env,
+        gpa,
+        input_file_paths,
+        compile_options,
+        reporter,
+        &output.writer,
+        &symbols,
+        size_report,
+        null,
+    );
+    try stderr_writer.interface.flush();
+
+    var linked: Io.Writer.Allocating = .init(gpa);
+    defer linked.deinit();
+    const bytecode = if (options.aslr) blk: {
+        try linkAtRandomBase(gpa, output.written(), &symbols, reporter, &linked.writer);
+        break :blk linked.written();
+    } else output.written();
+
+    if (output_file_path) |path| {
+        try utils.writeToFile(io, path, bytecode);
+    }
+
+    try runBytecode(io, env, bytecode, options, &symbols, reporter, gpa);
+}
+
+/// Link the object `run --aslr` compiled at a random base, moving the
+/// addresses in `symbols` up with it.
+fn linkAtRandomBase(
+    gpa: Allocator,
+    object_bytes: []const u8,
+    symbols: *Debugger.Symbols,
+    reporter: *fehler.ErrorReporter,
+    writer: *Io.Writer,
+) !void {
+    var object = try Object.read(gpa, object_bytes);
+    defer object.deinit();
+    const base = Linker.randomBase(std.crypto.random);
+    Linker.linkAt(gpa, (&object)[0..1], base, reporter, writer) catch |err| switch (err) {
+        error.LinkerError => process.exit(1),
+        else => return err,
+    };
+    for (symbols.values()) |*addr| addr.* += base;
+}
+
+fn executeDebugCommand(
+    io: std.Io,
+    env: std.process.Environ,
+    gpa: Allocator,
+    matches: yazap.ArgMatches,
+    reporter: *fehler.ErrorReporter,
+) !void {
+    const input_file_path = matches.getSingleValue("FILE").?;
+    const compile_options = try parseCompileOptions(gpa, matches, reporter);
+    defer gpa.free(compile_options.defines);
+    const options = parseRunOptions(matches, reporter);
+    const history = if (matches.getSingleValue("history")) |count|
+        fmt.parseInt(usize, count, 10) catch {
+            logError(reporter, "{s}: not a valid number", .{count});
+            process.exit(1);
+        }
+    else
+        Journal.default_capacity;
+
+    var output: Io.Writer.Allocating = .init(gpa);
+    defer output.deinit();
+
+    var symbol_arena = std.heap.ArenaAllocator.init(gpa);
+    defer symbol_arena.deinit();
+    var symbols = Debugger.Symbols.init(symbol_arena.allocator());
+
+    try compileSourceFiles(
+        io,
+        env,
+        gpa,
+        &.{input_file_path},
+        compile_options,
+        reporter,
+        &output.writer,
+        &symbols,
+        null,
+        null,
+    );
+
+    var vm = try initVm(output.written(), options, reporter, gpa);
+    defer vm.deinit();
+    vm.division = options.division;
+    if (options.console) |size| try vm.attachConsole(size);
+    var clipboard = try hostClipboard(io, env, gpa);
+    vm.clipboard = clipboard.clipboard();
+    try vm.enableJournal(history);
+
+    var debugger = Debugger.init(&vm, &symbols, gpa);
+    defer debugger.deinit();
+
+    var stdin_buffer: [1024]u8 = undefined;
+    var stdin_reader = Io.File.stdin().reader(io, &stdin_buffer);
+    var stdout_buffer: [4096]u8 = undefined;
+    var stdout_writer = Io.File.stdout().writer(io, &stdout_buffer);
+
+    const terminal = std.posix.isatty(std.posix.STDIN_FILENO);
+    var editor = LineEditor.init(gpa, &stdin_reader.interface, &stdout_writer.interface, terminal);
+    defer editor.deinit();
+    if (terminal) editor.terminal = std.posix.STDIN_FILENO;
+
+    const history_path = try historyPath(env, gpa);
+    defer if (history_path) |path| gpa.free(path);
+    if (history_path) |path| editor.loadHistory(io, path) catch {};
+
+    try debugger.repl(&editor, &stdout_writer.interface);
+
+    if (history_path) |path| editor.saveHistory(io, path) catch {};
+}
+
+fn executeReplCommand(
+    io: std.Io,
+    env: std.process.Environ,
+    gpa: Allocator,
+    matches: yazap.ArgMatches,
+    reporter: *fehler.ErrorReporter,
+) !void {
+    const options = parseRunOptions(matches, reporter);
+
+    var vm = try initVm(&Repl.empty_program, options, reporter, gpa);
+    defer vm.deinit();
+
+    var interner = StringInterner.init(gpa);
+    defer interner.deinit();
+
+    var repl = try Repl.init(&vm, &interner, reporter, gpa);
+    defer repl.deinit();
+    if (options.max_steps) |steps| repl.max_steps = steps;
+
+    var stdin_buffer: [1024]u8 = undefined;
+    var stdin_reader = Io.File.stdin().reader(io, &stdin_buffer);
+    var stdout_buffer: [4096]u8 = undefined;
+    var stdout_writer = Io.File.stdout().writer(io, &stdout_buffer);
+
+    const terminal = std.posix.isatty(std.posix.STDIN_FILENO);
+    var editor = LineEditor.init(gpa, &stdin_reader.interface, &stdout_writer.interface, terminal);
+    defer editor.deinit();
+    if (terminal) editor.terminal = std.posix.STDIN_FILENO;
+
+    const history_path = try historyPath(env, gpa);
+    defer if (history_path) |path| gpa.free(path);
+    if (history_path) |path| editor.loadHistory(io, path) catch {};
+
+    try repl.run(&editor, &stdout_writer.interface);
+
+    if (history_path) |path| editor.saveHistory(io, path) catch {};
+}
+
+/// The desktop clipboard behind `sys_clipboard_read` and `sys_clipboard_write`.
+fn hostClipboard(io: std.Io, env: std.process.Environ, gpa: Allocator) !Clipboard.Command {
+    const wayland = if (env.getAlloc(gpa, "WAYLAND_DISPLAY")) |display| blk: {
+        gpa.free(display);
+        break :blk true;
+    } else |err| switch (err) {
+        error.EnvironmentVariableMissing => false,
+        else => return err,
+    };
+    return .detect(io, wayland);
+}
+
+/// Location of the history file shared by the interactive modes, or null if
+/// `HOME` is not set.
+fn historyPath(env: std.process.Environ, gpa: Allocator) !?[]const u8 {
+    const home = env.getAlloc(gpa, "HOME") catch |err| switch (err) {
+        error.EnvironmentVariableMissing => return null,
+        else => return err,
+    };
+    defer gpa.free(home);
+    return try fs.path.join(gpa, &.{ home, ".nyx_history" });
+}
+
+fn executeDisasmCommand(
+    io: std.Io,
+    gpa: Allocator,
+    matches: yazap.ArgMatches,
+    reporter: *fehler.ErrorReporter,
+) !void {
+    const input_file_path = matches.getSingleValue("FILE").?;
+    const output_file_path = matches.getSingleValue("output");
+
+    if (!utils.fileExists(io, input_file_path)) {
+        logError(reporter, "{s}: cannot find file", .{input_file_path});
+        process.exit(1);
+    }
+    const bytecode = try utils.readFromFile(io, gpa, input_file_path);
+    defer gpa.free(bytecode);
+
+    const disassembler = Disassembler.init(signature.payload(bytecode), gpa) catch {
+        logError(reporter, "{s}: not a valid bytecode file", .{input_file_path});
+        process.exit(1);
+    };
+
+    var buffer: [4096]u8 = undefined;
+    const file = if (output_file_path) |path| try Io.Dir.cwd().createFile(io, path, .{}) else Io.File.stdout();
+    defer if (output_file_path != null) file.close(io);
+    var file_writer = file.writer(io, &buffer);
+
+    try disassembler.disassemble(&file_writer.interface);
+    try file_writer.interface.flush();
+}
+
+fn executeMigrateCommand(
+    io: std.Io,
+    gpa: Allocator,
+    matches: yazap.ArgMatches,
+    reporter: *fehler.ErrorReporter,
+) !void {
+    const input_file_path = matches.getSingleValue("FILE").?;
+    const output_file_path = matches.getSingleValue("output") orelse input_file_path;
+
+    if (!utils.fileExists(io, input_file_path)) {
+        logError(reporter, "{s}: cannot find file", .{input_file_path});
+        process.exit(1);
+    }
+    const bytecode = try utils.readFromFile(io, gpa, input_file_path);
+    defer gpa.free(bytecode);
+
+    if (signature.isSigned(bytecode)) {
+        logError(reporter, "{s}: bytecode is signed and migrating it would break the signature; rebuild it from source instead", .{input_file_path});
+        process.exit(1);
+    }
+
+    const result = migrate.migrate(gpa, bytecode, &Opcode.aliases) catch |err| switch (err) {
+        error.ProgramTooSmall => {
+            logError(reporter, "{s}: not a valid bytecode file", .{input_file_path});
+            process.exit(1);
+        },
+        else => return err,
+    };
+    defer gpa.free(result.bytecode);
+
+    try utils.writeToFile(io, output_file_path, result.bytecode);
+    std.debug.print("{s}: rewrote {d} instructions\n", .{ output_file_path, result.rewritten });
+}
+
+fn executeVerifyCommand(
+    io: std.Io,
+    gpa: Allocator,
+    matches: yazap.ArgMatches,
+    reporter: *fehler.ErrorReporter,
+) !void {
+    const input_file_paths = matches.getMultiValues("FILE").?;
+    if (input_file_paths.len != 2) {
+        logError(reporter, "verify takes exactly two bytecode files, got {d}", .{input_file_paths.len});
+        process.exit(1);
+    }
+    const options = parseRunOptions(matches, reporter);
+
+    const input = if (matches.getSingleValue("input")) |path| blk: {
+        if (!utils.fileExists(io, path)) {
+            logError(reporter, "{s}: cannot find file", .{path});
+            process.exit(1);
+        }
+        break :blk try utils.readFromFile(io, gpa, path);
+    } else try gpa.alloc(u8, 0);
+    defer gpa.free(input);
+
+    var bytecodes: [2][]u8 = undefined;
+    for (input_file_paths, 0..) |path, i| {
+        if (!utils.fileExists(io, path)) {
+            logError(reporter, "{s}: cannot find file", .{path});
+            process.exit(1);
+        }
+        bytecodes[i] = try utils.readFromFile(io, gpa, path);
+    }
+    defer for (bytecodes) |bytecode| gpa.free(bytecode);
+
+    var a = try initVm(signature.payload(bytecodes[0]), options, reporter, gpa);
+    defer a.deinit();
+    var b = try initVm(signature.payload(bytecodes[1]), options, reporter, gpa);
+    defer b.deinit();
+    a.division = options.division;
+    b.division = options.division;
+
+    var buffer: [4096]u8 = undefined;
+    var stdout_writer = Io.File.stdout().writer(io, &buffer);
+    const writer = &stdout_writer.interface;
+
+    switch (try verify.compare(gpa, &a, &b, input, .{ .compare_state = !matches.containsArg("syscalls-only") })) {
+        .same => |same| {
+            try writer.print("no divergence: {d} matching syscalls, then both {f}\n", .{ same.matched, same.end });
+            try writer.flush();
+        },
+        .diverged => |divergence| {
+            try writer.print("diverged after {d} matching syscalls\n", .{divergence.matched});
+            for (input_file_paths, divergence.addresses, divergence.events) |path, addr, event| {
+                try writer.print("  {s}: at 0x{x:0>4} {f}\n", .{ path, addr, event });
+            }
+            if (divergence.offset) |offset| try writer.print("  output first differs at byte {d}\n", .{offset});
+            if (divergence.state) |state| try writer.print("  final state differs: {f}\n", .{state});
+            try writer.flush();
+            process.exit(1);
+        },
+    }
+}
+
+/// Compare two programs. Source files are compiled first so their labels can
+/// be used as symbols; bytecode is compared without them. Exits with status
+/// 1 when the programs differ, like `diff`.
+fn executeDiffCommand(
+    io: std.Io,
+    env: std.process.Environ,
+    gpa: Allocator,
+    matches: yazap.ArgMatches,
+    reporter: *fehler.ErrorReporter,
+) !void {
+    const input_file_paths = matches.getMultiValues("FILE").?;
+    if (input_file_paths.len != 2) {
+        logError(reporter, "diff takes exactly two programs, got {d}", .{input_file_paths.len});
+        process.exit(1);
+    }
+    const compile_options = try parseCompileOptions(gpa, matches, reporter);
+    defer gpa.free(compile_options.defines);
+
+    var symbol_arena = std.heap.ArenaAllocator.init(gpa);
+    defer symbol_arena.deinit();
+
+    var bytecodes: [2][]u8 = undefined;
+    var symbols: [2]?*diff.Symbols = .{ null, null };
+    var text_lens: [2]?usize = .{ null, null };
+    for (input_file_paths, 0..) |path, i| {
+        if (!utils.fileExists(io, path)) {
+            logError(reporter, "{s}: cannot find file", .{path});
+            process.exit(1);
+        }
+        if (std.mem.endsWith(u8, path, ".nyx")) {
+            const map = try symbol_arena.allocator().create(diff.Symbols);
+            map.* = .init(symbol_arena.allocator());
+            var output: Io.Writer.Allocating = .init(gpa);
+            defer output.deinit();
+            var manifest: Manifest = undefined;
+            try compileSourceFiles(io, env, gpa, &.{path}, compile_options, reporter, &output.writer, map, null, &manifest);
+            bytecodes[i] = try output.toOwnedSlice();
+            symbols[i] = map;
+            text_lens[i] = manifest.text_size;
+        } else {
+            bytecodes[i] = try utils.readFromFile(io, gpa, path);
+        }
+    }
+    defer for (bytecodes) |bytecode| gpa.free(bytecode);
+
+    var buffer: [4096]u8 = undefined;
+    var stdout_writer = Io.File.stdout().writer(io, &buffer);
+    const writer = &stdout_writer.interface;
+
+    const summary = diff.compare(
+        gpa,
+        .{ .bytecode = signature.payload(bytecodes[0]), .symbols = symbols[0], .text_len = text_lens[0] },
+        .{ .bytecode = signature.payload(bytecodes[1]), .symbols = symbols[1], .text_len = text_lens[1] },
+        writer,
+    ) catch |err| {
+        logError(reporter, "cannot compare programs: {s}", .{@errorName(err)});
+        process.exit(1);
+    };
+    try writer.flush();
+    if (!summary.identical()) process.exit(1);
+}
+
+fn executeSyscallsCommand(io: std.Io, matches: yazap.ArgMatches) !void {
+    var buffer: [4096]u8 = undefined;
+    var stdout_writer = Io.File.stdout().writer(io, &buffer);
+    if (matches.containsArg("header")) {
+        try syscall.writeHeader(&stdout_writer.interface);
+    } else {
+        try syscall.printTable(&stdout_writer.interface);
+    }
+    try stdout_writer.interface.flush();
+}
+
+fn executeSymbolsCommand(
+    io: std.Io,
+    gpa: Allocator,
+    matches: yazap.ArgMatches,
+    reporter: *fehler.ErrorReporter,
+) !void {
+    const input_file_path = matches.getSingleValue("FILE").?;
+    const order = if (matches.getSingleValue("sort")) |kind|
+        std.meta.stringToEnum(DebugInfo.SymbolOrder, kind) orelse {
+            logError(reporter, "{s}: not an order, expected address or name", .{kind});
+            process.exit(1);
+        }
+    else
+        .address;
+
+    if (!utils.fileExists(io, input_file_path)) {
+        logError(reporter, "{s}: cannot find file", .{input_file_path});
+        process.exit(1);
+    }
+    const file = try utils.readFromFile(io, gpa, input_file_path);
+    defer gpa.free(file);
+    const bytecode = signature.payload(file);
+
+    const header = features.read(bytecode) catch {
+        logError(reporter, "{s}: not a valid bytecode file", .{input_file_path});
+        process.exit(1);
+    };
+    const section = header.debugSection(bytecode) orelse {
+        logError(reporter, "{s}: has no symbols; build it with -g or --symbols", .{input_file_path});
+        process.exit(1);
+    };
+    var info = DebugInfo.read(gpa, section) catch |err| switch (err) {
+        error.InvalidDebugInfo => {
+            logError(reporter, "{s}: the symbol section is malformed", .{input_file_path});
+            process.exit(1);
+        },
+        else => return err,
+    };
+    defer info.deinit();
+
+    var buffer: [4096]u8 = undefined;
+    var stdout_writer = Io.File.stdout().writer(io, &buffer);
+    try info.writeSymbols(gpa, &stdout_writer.interface, order);
+    try stdout_writer.interface.flush();
+}
+
+fn executeKeygenCommand(io: std.Io, gpa: Allocator, matches: yazap.ArgMatches, reporter: *fehler.ErrorReporter) !void {
+    const secret_path = matches.getSingleValue("NAME").?;
+    const public_path = try std.mem.concat(gpa, u8, &.{ secret_path, ".pub" });
+    defer gpa.free(public_path);
+
+    for ([_][]const u8{ secret_path, public_path }) |path| {
+        if (!utils.fileExists(io, path)) continue;
+        logError(reporter, "{s}: file already exists, refusing to overwrite a key", .{path});
+        process.exit(1);
+    }
+
+    const key_pair = signature.generateKeyPair();
+    const secret_hex = signature.encodeKey(64, key_pair.secret_key.toBytes());
+    const public_hex = signature.encodeKey(32, key_pair.public_key.toBytes());
+
+    // Only the owner may read the secret key. Creating it exclusively also
+    // keeps a key made between the check above and here.
+    const secret_file = Io.Dir.cwd().createFile(io, secret_path, .{
+        .exclusive = true,
+        .permissions = .fromMode(0o600),
+    }) catch |err| switch (err) {
+        error.PathAlreadyExists => {
+            logError(reporter, "{s}: file already exists, refusing to overwrite a key", .{secret_path});
+            process.exit(1);
+        },
+        else => return err,
+    };
+    defer secret_file.close(io);
+    var buffer: [256]u8 = undefined;
+    var secret_writer = secret_file.writer(io, &buffer);
+    try secret_writer.interface.writeAll(&(secret_hex ++ "\n".*));
+    try secret_writer.interface.flush();
+
+    try utils.writeToFile(io, public_path, &(public_hex ++ "\n".*));
+}
+
+fn readKeyFile(io: std.Io, gpa: Allocator, path: []const u8, reporter: *fehler.ErrorReporter) ![]u8 {
+    if (!utils.fileExists(io, path)) {
+        logError(reporter, "{s}: cannot find file", .{path});
+        process.exit(1);
+    }
+    return utils.readFromFile(io, gpa, path);
+}
+
+fn logError(reporter: *fehler.ErrorReporter, comptime format: []const u8, args: anytype) void {
+    const message = std.fmt.allocPrint(std.heap.page_allocator, format, args) catch unreachable;
+    reporter.report(.{ .severity = .err, .message = message });
+}
+
+test "an empty first file is still the main source file" {
+    const testing = std.testing;
+    var reporter = fehler.ErrorReporter.init(testing.allocator);
+    defer reporter.deinit();
+    const options = CompileOptions{
+        .emit = .bytecode,
+        .ast_format = .json,
+        .include_paths = &.{},
+        .defines = &.{},
+        .run_preprocessor = true,
+        .strict = false,
+        .truncate_immediates = false,
+        .warnings = .{},
+        .optimize = false,
+        .debug_info = false,
+        .symbols = false,
+        .absolute_jumps = false,
+    };
+
+    var alone: Io.Writer.Allocating = .init(testing.allocator);
+    defer alone.deinit();
+    try compileSourceFiles(testing.io, null, testing.allocator, &.{"src/testdata/start.nyx"}, options, &reporter, &alone.writer, null, null, null);
+
+    var after_empty: Io.Writer.Allocating = .init(testing.allocator);
+    defer after_empty.deinit();
+    try compileSourceFiles(testing.io, null, testing.allocator, &.{ "src/testdata/empty.nyx", "src/testdata/start.nyx" }, options, &reporter, &after_empty.writer, null, null, null);
+    try testing.expectEqualSlices(u8, alone.written(), after_empty.written());
 }
@@ -0,0 +1,42 @@
+//! Rewrites bytecode from an older toolchain to the current opcode numbers.
+//!
+//! Every retired number in `Opcode.aliases` has the same operands as the
+//! opcode that replaced it, so migrating only swaps opcode bytes and no
+//! address moves. Instructions are found the way `nyx disasm` finds them:
+//! decoding from the start of the text until the first byte that is not an
+//! instruction.
+
+const std = @import("std");
+const Allocator = std.mem.Allocator;
+const Opcode = @import("compiler/opcode.zig").Opcode;
+const Disassembler = @import("disassembler/Disassembler.zig");
+
+pub const Result = struct {
+    /// The migrated program image. Owned by the caller.
+    bytecode: []u8,
+    /// Instructions whose opcode byte was rewritten.
+    rewritten: usize,
+};
+
+/// Return a copy of `bytecode` with every opcode in `table` replaced by its
+/// current number.
+pub fn migrate(gpa: Allocator, bytecode: []const u8, table: []const Opcode.Alias) !Result {
+    var disassembler = try Disassembler.init(bytecode, gpa);
+    disassembler.aliases = table;
+
+    const output = try gpa.dupe(u8, bytecode);
+    errdefer gpa.free(output);
+    const code = output[bytecode.len - disassembler.code.len ..];
+
+    var rewritten: usize = 0;
+    var addr: usize = 0;
+    while (addr < code.len) {
+        const inst = disassembler.decode(addr) catch break;
+        if (code[addr] != inst.opcode.intoU8()) {
+            code[addr] = inst.opcode.intoU8();
+            rewritten += 1;
+        }
+        addr += inst.len;
+    }
+    return .{ .bytecode = output, .rewritten = rewritten };
+}
@@ -24,6 +24,14 @@ prev_token: Token,
 cur_token: Token,
 peek_token: Token,
 arena: heap.ArenaAllocator,
+/// Exit the process on the first error. Interactive callers turn this off
+/// and handle `error.ParserError` themselves.
+exit_on_error: bool,
+/// After an error, skip to the next line and keep parsing so that `parse`
+/// reports every error in the file before failing.
+recover: bool,
+/// Errors reported so far.
+error_count: usize,
 
 pub fn init(
     lexer: *Lexer,
@@ -47,6 +55,9 @@ pub fn init(
         .cur_token = cur_token,
         .peek_token = peek_token,
         .arena = arena,
+        .exit_on_error = true,
+        .recover = true,
+        .error_count = 0,
     };
 }
 
@@ -57,16 +68,91 @@ pub fn deinit(self: *Parser) void {
 pub fn parse(self: *Parser) ![]ast.Statement {
     var stmts = ArrayList(ast.Statement).init(self.arena.allocator());
     while (self.cur_token.kind != .eof) {
-        try stmts.append(try self.parseStatement());
+        const start = self.cur_token.span.start;
+        const stmt = self.parseNext() catch |err| switch (err) {
+            error.ParserError => {
+                if (!self.recover) return err;
+                self.synchronize(start);
+                continue;
+            },
+            else => return err,
+        } orelse continue;
+        try stmts.append(stmt);
+    }
+
+    if (self.error_count > 0) {
+        if (self.error_count > 1) {
+            var buf: [128]u8 = undefined;
+            const message = fmt.bufPrint(&buf, "{s}: {d} errors", .{ self.lexer.filename, self.error_count }) catch "too many errors";
+            self.reporter.report(.{ .severity = .err, .message = message });
+        }
+        if (self.exit_on_error) process.exit(1);
+        return error.ParserError;
     }
     return try stmts.toOwnedSlice();
 }
 
+/// The next statement, or null for a directive that makes none.
+fn parseNext(self: *Parser) !?ast.Statement {
+    if (self.curTokenIs(.kw_line)) {
+        try self.parseLineDirective();
+        return null;
+    }
+    return try self.parseStatement();
+}
+
+/// `#line N` or `#line N "file"`. It only renumbers the lines after it,
+/// which `Span.toSourceRange` reads from the source itself, so it makes no
+/// statement.
+fn parseLineDirective(self: *Parser) !void {
+    self.nextTokenRaw();
+    const line = if (self.curTokenIs(.integer)) fmt.parseInt(usize, self.cur_token.literal, 10) catch 0 else 0;
+    if (line == 0) {
+        self.report(.err, "expected a line number after #line", self.cur_token.span, 1);
+        return error.ParserError;
+    }
+    self.nextTokenRaw();
+    if (self.curTokenIs(.string)) self.nextTokenRaw();
+    if (!self.curTokenIs(.newline) and !self.curTokenIs(.eof)) {
+        self.report(.err, "expected a file name in quotes after the line number", self.cur_token.span, 1);
+        return error.ParserError;
+    }
+    self.nextToken();
+}
+
+/// Skip the rest of the line on which the failed statement at `start`
+/// began, unless parsing it already moved past that line.
+fn synchronize(self: *Parser, start: usize) void {
+    const line_end = mem.indexOfScalarPos(u8, self.lexer.input, start, '\n') orelse self.lexer.input.len;
+    while (!self.curTokenIs(.eof) and self.cur_token.span.start < line_end) self.nextToken();
+}
+
+/// Parse input that consists of exactly one expression, e.g. a debugger
+/// command argument.
+pub fn parseStandaloneExpression(self: *Parser) !*ast.Expression {
+    const expr = try self.parseExpression();
+    if (!self.curTokenIs(.eof)) {
+        self.report(.err, "unexpected token", self.cur_token.span, 1);
+        return error.ParserError;
+    }
+    return expr;
+}
+
 fn parseStatement(self: *Parser) !ast.Statement {
     const cur_span = self.cur_token.span;
     switch (self.cur_token.kind) {
         .identifier => {
-            if (self.peekTokenIs(.colon)) {
+            if (self.peekTokenIs(.kw_equ)) {
+                const name_id = self.cur_token.string_id;
+                self.nextToken();
+                self.nextToken();
+                const expr = try self.parseExpression();
+                return .{ .equ = .{
+                    .name = name_id,
+                    .expr = expr,
+                    .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+                } };
+            } else if (self.peekTokenIs(.colon)) {
                 const name_id = self.cur_token.string_id;
                 self.nextToken();
                 self.nextToken();
@@ -104,6 +190,14 @@ fn parseStatement(self: *Parser) !ast.Statement {
                 .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
             } };
         },
+        .kw_warning => {
+            self.nextToken();
+            const message = try self.parseExpression();
+            return .{ .warning = .{
+                .expr = message,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
         .kw_define => {
             self.nextToken();
 
@@ -131,6 +225,42 @@ fn parseStatement(self: *Parser) !ast.Statement {
                 .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
             } };
         },
+        .kw_define_env => {
+            self.nextToken();
+
+            if (!self.curTokenIs(.identifier)) {
+                self.report(.err, "expected identifier after #define_env", self.cur_token.span, 1);
+                return error.ParserError;
+            }
+
+            const name = try self.arena.allocator().create(ast.Expression);
+            name.* = .{ .identifier = self.cur_token.string_id };
+
+            self.nextTokenRaw();
+
+            if (!self.curTokenIs(.identifier)) {
+                self.report(.err, "expected environment variable name", self.cur_token.span, 1);
+                return error.ParserError;
+            }
+
+            const env_id = self.cur_token.string_id;
+
+            self.nextTokenRaw();
+
+            var default: ?*ast.Expression = null;
+            if (self.curTokenIs(.newline) or self.curTokenIs(.eof)) {
+                self.nextToken();
+            } else {
+                default = try self.parseExpression();
+            }
+
+            return .{ .define_env = .{
+                .name = name,
+                .env = env_id,
+                .default = default,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
         .kw_include => {
             self.nextToken();
             const path = try self.parseExpression();
@@ -139,6 +269,15 @@ fn parseStatement(self: *Parser) !ast.Statement {
                 .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
             } };
         },
+        .kw_pragma => {
+            self.nextToken();
+            if (!self.curTokenIs(.identifier) or !mem.eql(u8, self.cur_token.literal, "once")) {
+                self.report(.err, "expected once after #pragma", self.cur_token.span, 1);
+                return error.ParserError;
+            }
+            self.nextToken();
+            return .{ .pragma_once = .init(cur_span.start, self.prev_token.span.end, cur_span.filename) };
+        },
         .kw_ifdef => {
             self.nextToken();
             const expr = try self.parseExpression();
@@ -199,6 +338,37 @@ fn parseStatement(self: *Parser) !ast.Statement {
                 .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
             } };
         },
+        .kw_requires => {
+            self.nextToken();
+            var names = ArrayList(StringId).init(self.arena.allocator());
+            while (true) {
+                // `float` lexes as a data size, so take it by its spelling.
+                const name = switch (self.cur_token.kind) {
+                    .identifier => self.cur_token.string_id,
+                    .data_size => try self.lexer.interner.intern(self.cur_token.literal),
+                    else => {
+                        self.report(.err, "expected feature name after .requires", self.cur_token.span, 1);
+                        return error.ParserError;
+                    },
+                };
+                try names.append(name);
+                self.nextToken();
+                if (!self.curTokenIs(.comma)) break;
+                self.nextToken();
+            }
+            return .{ .requires = .{
+                .features = try names.toOwnedSlice(),
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
+        .kw_align => {
+            self.nextToken();
+            const expr = try self.parseExpression();
+            return .{ .@"align" = .{
+                .expr = expr,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
         .kw_ascii => {
             self.nextToken();
             const expr = try self.parseExpression();
@@ -215,6 +385,48 @@ fn parseStatement(self: *Parser) !ast.Statement {
                 .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
             } };
         },
+        .kw_equ => {
+            self.nextToken();
+            if (!self.curTokenIs(.identifier)) {
+                self.report(.err, "expected name after .equ", self.cur_token.span, 1);
+                return error.ParserError;
+            }
+            const name_id = self.cur_token.string_id;
+            self.nextToken();
+            if (!self.curTokenIs(.comma)) {
+                self.report(.err, "expected ',' after .equ name", self.cur_token.span, 1);
+                return error.ParserError;
+            }
+            self.nextToken();
+            const expr = try self.parseExpression();
+            return .{ .equ = .{
+                .name = name_id,
+                .expr = expr,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
+        .kw_times => {
+            self.nextToken();
+            const count = try self.parseExpression();
+            const stmt_span = self.cur_token.span;
+            const stmt = try self.parseStatement();
+            switch (stmt) {
+                .label, .section, .entry, .requires, .@"extern", .equ, .times, .macro_def, .macro_call,
+                .@"error", .warning, .define, .define_env, .include, .pragma_once, .ifdef, .ifndef, .@"else", .endif,
+                => {
+                    self.report(.err, "times can only repeat an instruction or data directive", stmt_span, 1);
+                    return error.ParserError;
+                },
+                else => {},
+            }
+            const repeated = try self.arena.allocator().create(ast.Statement);
+            repeated.* = stmt;
+            return .{ .times = .{
+                .count = count,
+                .stmt = repeated,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
         .kw_extern => {
             self.nextToken();
             const name_expr = try self.parseExpression();
@@ -405,6 +617,20 @@ fn parseStatement(self: *Parser) !ast.Statement {
                 .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
             } };
         },
+        .kw_adc => {
+            self.nextToken();
+            const dest = try self.parseExpression();
+            try self.expect_cur(.comma);
+            const lhs = try self.parseExpression();
+            try self.expect_cur(.comma);
+            const rhs = try self.parseExpression();
+            return .{ .adc = .{
+                .expr1 = dest,
+                .expr2 = lhs,
+                .expr3 = rhs,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
         .kw_sub => {
             self.nextToken();
             const dest = try self.parseExpression();
@@ -419,6 +645,20 @@ fn parseStatement(self: *Parser) !ast.Statement {
                 .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
             } };
         },
+        .kw_sbb => {
+            self.nextToken();
+            const dest = try self.parseExpression();
+            try self.expect_cur(.comma);
+            const lhs = try self.parseExpression();
+            try self.expect_cur(.comma);
+            const rhs = try self.parseExpression();
+            return .{ .sbb = .{
+                .expr1 = dest,
+                .expr2 = lhs,
+                .expr3 = rhs,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
         .kw_mul => {
             self.nextToken();
             const dest = try self.parseExpression();
@@ -447,6 +687,48 @@ fn parseStatement(self: *Parser) !ast.Statement {
                 .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
             } };
         },
+        .kw_imul => {
+            self.nextToken();
+            const dest = try self.parseExpression();
+            try self.expect_cur(.comma);
+            const lhs = try self.parseExpression();
+            try self.expect_cur(.comma);
+            const rhs = try self.parseExpression();
+            return .{ .imul = .{
+                .expr1 = dest,
+                .expr2 = lhs,
+                .expr3 = rhs,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
+        .kw_idiv => {
+            self.nextToken();
+            const dest = try self.parseExpression();
+            try self.expect_cur(.comma);
+            const lhs = try self.parseExpression();
+            try self.expect_cur(.comma);
+            const rhs = try self.parseExpression();
+            return .{ .idiv = .{
+                .expr1 = dest,
+                .expr2 = lhs,
+                .expr3 = rhs,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
+        .kw_imod => {
+            self.nextToken();
+            const dest = try self.parseExpression();
+            try self.expect_cur(.comma);
+            const lhs = try self.parseExpression();
+            try self.expect_cur(.comma);
+            const rhs = try self.parseExpression();
+            return .{ .imod = .{
+                .expr1 = dest,
+                .expr2 = lhs,
+                .expr3 = rhs,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
         .kw_and => {
             self.nextToken();
             const dest = try self.parseExpression();
@@ -489,6 +771,39 @@ fn parseStatement(self: *Parser) !ast.Statement {
                 .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
             } };
         },
+        .kw_movz => {
+            self.nextToken();
+            const dest = try self.parseExpression();
+            try self.expect_cur(.comma);
+            const src = try self.parseExpression();
+            return .{ .movz = .{
+                .expr1 = dest,
+                .expr2 = src,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
+        .kw_movs => {
+            self.nextToken();
+            const dest = try self.parseExpression();
+            try self.expect_cur(.comma);
+            const src = try self.parseExpression();
+            return .{ .movs = .{
+                .expr1 = dest,
+                .expr2 = src,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
+        .kw_not => {
+            self.nextToken();
+            const dest = try self.parseExpression();
+            try self.expect_cur(.comma);
+            const src = try self.parseExpression();
+            return .{ .not = .{
+                .expr1 = dest,
+                .expr2 = src,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
         .kw_shl => {
             self.nextToken();
             const dest = try self.parseExpression();
@@ -556,6 +871,31 @@ fn parseStatement(self: *Parser) !ast.Statement {
                 .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
             } };
         },
+        .kw_scmp => {
+            self.nextToken();
+            const lhs = try self.parseExpression();
+            try self.expect_cur(.comma);
+            const rhs = try self.parseExpression();
+            return .{ .scmp = .{
+                .expr1 = lhs,
+                .expr2 = rhs,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
+        .kw_schr => {
+            self.nextToken();
+            const ptr = try self.parseExpression();
+            try self.expect_cur(.comma);
+            const len = try self.parseExpression();
+            try self.expect_cur(.comma);
+            const byte = try self.parseExpression();
+            return .{ .schr = .{
+                .expr1 = ptr,
+                .expr2 = len,
+                .expr3 = byte,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
         .kw_jmp => {
             self.nextToken();
             const expr = try self.parseExpression();
@@ -612,6 +952,70 @@ fn parseStatement(self: *Parser) !ast.Statement {
                 .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
             } };
         },
+        .kw_seteq => {
+            self.nextToken();
+            const expr = try self.parseExpression();
+            return .{ .seteq = .{
+                .expr = expr,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
+        .kw_setne => {
+            self.nextToken();
+            const expr = try self.parseExpression();
+            return .{ .setne = .{
+                .expr = expr,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
+        .kw_setlt => {
+            self.nextToken();
+            const expr = try self.parseExpression();
+            return .{ .setlt = .{
+                .expr = expr,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
+        .kw_setgt => {
+            self.nextToken();
+            const expr = try self.parseExpression();
+            return .{ .setgt = .{
+                .expr = expr,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
+        .kw_setle => {
+            self.nextToken();
+            const expr = try self.parseExpression();
+            return .{ .setle = .{
+                .expr = expr,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
+        .kw_setge => {
+            self.nextToken();
+            const expr = try self.parseExpression();
+            return .{ .setge = .{
+                .expr = expr,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
+        .kw_setc => {
+            self.nextToken();
+            const expr = try self.parseExpression();
+            return .{ .setc = .{
+                .expr = expr,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
+        .kw_seto => {
+            self.nextToken();
+            const expr = try self.parseExpression();
+            return .{ .seto = .{
+                .expr = expr,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
         .kw_call => {
             self.nextToken();
             const expr = try self.parseExpression();
@@ -688,19 +1092,41 @@ fn parseStatement(self: *Parser) !ast.Statement {
             self.nextToken();
             return .{ .ret = .init(cur_span.start, self.prev_token.span.end, cur_span.filename) };
         },
+        .kw_enter => {
+            self.nextToken();
+            const expr = try self.parseExpression();
+            return .{ .enter = .{
+                .expr = expr,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
+        .kw_leave => {
+            self.nextToken();
+            return .{ .leave = .init(cur_span.start, self.prev_token.span.end, cur_span.filename) };
+        },
         .kw_inc => {
             self.nextToken();
             const expr = try self.parseExpression();
+            const amount = if (self.curTokenIs(.comma)) blk: {
+                self.nextToken();
+                break :blk try self.parseExpression();
+            } else null;
             return .{ .inc = .{
                 .expr = expr,
+                .amount = amount,
                 .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
             } };
         },
         .kw_dec => {
             self.nextToken();
             const expr = try self.parseExpression();
+            const amount = if (self.curTokenIs(.comma)) blk: {
+                self.nextToken();
+                break :blk try self.parseExpression();
+            } else null;
             return .{ .dec = .{
                 .expr = expr,
+                .amount = amount,
                 .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
             } };
         },
@@ -712,6 +1138,50 @@ fn parseStatement(self: *Parser) !ast.Statement {
                 .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
             } };
         },
+        .kw_cvtif => {
+            self.nextToken();
+            const dest = try self.parseExpression();
+            try self.expect_cur(.comma);
+            const src = try self.parseExpression();
+            return .{ .cvtif = .{
+                .expr1 = dest,
+                .expr2 = src,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
+        .kw_cvtfi => {
+            self.nextToken();
+            const dest = try self.parseExpression();
+            try self.expect_cur(.comma);
+            const src = try self.parseExpression();
+            return .{ .cvtfi = .{
+                .expr1 = dest,
+                .expr2 = src,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
+        .kw_cvtfd => {
+            self.nextToken();
+            const dest = try self.parseExpression();
+            try self.expect_cur(.comma);
+            const src = try self.parseExpression();
+            return .{ .cvtfd = .{
+                .expr1 = dest,
+                .expr2 = src,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
+        .kw_cvtdf => {
+            self.nextToken();
+            const dest = try self.parseExpression();
+            try self.expect_cur(.comma);
+            const src = try self.parseExpression();
+            return .{ .cvtdf = .{
+                .expr1 = dest,
+                .expr2 = src,
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
         .kw_syscall => {
             self.nextToken();
             return .{
@@ -724,6 +1194,21 @@ fn parseStatement(self: *Parser) !ast.Statement {
                 .hlt = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
             };
         },
+        .kw_ext => {
+            self.nextToken();
+            const subcode = try self.parseExpression();
+            var operands = ArrayList(*ast.Expression).init(self.arena.allocator());
+            while (self.curTokenIs(.comma)) {
+                self.nextToken();
+                try operands.append(try self.parseExpression());
+            }
+
+            return .{ .ext = .{
+                .subcode = subcode,
+                .operands = try operands.toOwnedSlice(),
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
         .kw_db => {
             self.nextToken();
             var exprs = ArrayList(*ast.Expression).init(self.arena.allocator());
@@ -796,6 +1281,42 @@ fn parseStatement(self: *Parser) !ast.Statement {
                 .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
             } };
         },
+        .kw_df => {
+            self.nextToken();
+            var exprs = ArrayList(*ast.Expression).init(self.arena.allocator());
+
+            while (true) {
+                try exprs.append(try self.parseExpression());
+                if (self.curTokenIs(.comma)) {
+                    self.nextToken();
+                    continue;
+                }
+                break;
+            }
+
+            return .{ .df = .{
+                .exprs = try exprs.toOwnedSlice(),
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
+        .kw_ddbl => {
+            self.nextToken();
+            var exprs = ArrayList(*ast.Expression).init(self.arena.allocator());
+
+            while (true) {
+                try exprs.append(try self.parseExpression());
+                if (self.curTokenIs(.comma)) {
+                    self.nextToken();
+                    continue;
+                }
+                break;
+            }
+
+            return .{ .ddbl = .{
+                .exprs = try exprs.toOwnedSlice(),
+                .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
+            } };
+        },
         .kw_resb => {
             self.nextToken();
             const expr = try self.parseExpression();
@@ -874,7 +1395,7 @@ fn parseStatement(self: *Parser) !ast.Statement {
             }
 
             if (!self.curTokenIs(.kw_endm)) {
-                self.report(.err, "expected #endm to close macro definition", self.cur_token.span, 1);
+                self.report(.err, "expected #endm or #endmacro to close macro definition", self.cur_token.span, 1);
                 return error.ParserError;
             }
             self.nextToken();
@@ -887,7 +1408,7 @@ fn parseStatement(self: *Parser) !ast.Statement {
             } };
         },
         .kw_endm => {
-            self.report(.err, "unexpected #endm without matching #macro", self.cur_token.span, 1);
+            self.report(.err, "unexpected #endm or #endmacro without matching #macro", self.cur_token.span, 1);
             return error.ParserError;
         },
         else => {
@@ -944,8 +1465,9 @@ fn parseBinaryExpression(self: *Parser, min_prec: u8) anyerror!ast.Expression {
 
 fn parsePrimary(self: *Parser) anyerror!ast.Expression {
     switch (self.cur_token.kind) {
-        .minus => {
+        .minus, .tilde => {
             const cur_span = self.cur_token.span;
+            const op: ast.Expression.UnaryOp.Op = if (self.cur_token.kind == .minus) .neg else .bit_not;
             self.nextToken();
             const expr = try self.parsePrimary();
 
@@ -953,7 +1475,7 @@ fn parsePrimary(self: *Parser) anyerror!ast.Expression {
             expr_ptr.* = expr;
 
             return .{ .unary_op = .{
-                .op = .neg,
+                .op = op,
                 .expr = expr_ptr,
                 .span = .init(cur_span.start, self.prev_token.span.end, cur_span.filename),
             } };
@@ -971,51 +1493,49 @@ fn parsePrimary(self: *Parser) anyerror!ast.Expression {
             self.nextToken();
             return .{ .register = reg };
         },
-        .integer => {
-            const int = fmt.parseInt(i64, self.cur_token.literal, 10) catch {
-                self.report(.err, "invalid integer", self.cur_token.span, 1);
-                return error.ParserError;
-            };
-            self.nextToken();
-            return .{ .integer_literal = int };
-        },
-        .hexadecimal => {
-            const int = fmt.parseInt(i64, self.cur_token.literal[2..], 16) catch {
-                self.report(.err, "invalid hexadecimal number", self.cur_token.span, 1);
+        .integer => return self.parseIntegerLiteral(10, 0, "invalid integer"),
+        .hexadecimal => return self.parseIntegerLiteral(16, 2, "invalid hexadecimal number"),
+        .binary => return self.parseIntegerLiteral(2, 2, "invalid binary number"),
+        .octal => return self.parseIntegerLiteral(8, 2, "invalid octal number"),
+        .character => {
+            const code_point = parseCharacter(self.cur_token.literal) orelse {
+                self.report(.err, "invalid character literal", self.cur_token.span, 1);
                 return error.ParserError;
             };
             self.nextToken();
-            return .{ .integer_literal = int };
-        },
-        .binary => {
-            const int = fmt.parseInt(i64, self.cur_token.literal[2..], 2) catch {
-                self.report(.err, "invalid binary number", self.cur_token.span, 1);
-                return error.ParserError;
-            };
-            self.nextToken();
-            return .{ .integer_literal = int };
-        },
-        .octal => {
-            const int = fmt.parseInt(i64, self.cur_token.literal[2..], 8) catch {
-                self.report(.err, "invalid octal number", self.cur_token.span, 1);
-                return error.ParserError;
-            };
-            self.nextToken();
-            return .{ .integer_literal = int };
+            return .{ .integer_literal = code_point };
         },
         .float => {
-            const float = fmt.parseFloat(f64, self.cur_token.literal) catch {
-                self.report(.err, "invalid float", self.cur_token.span, 1);
+            const literal = self.cur_token.literal;
+            const span = self.cur_token.span;
+            const is_single = literal[literal.len - 1] == 'f' or literal[literal.len - 1] == 'F';
+            const digits = if (is_single) literal[0 .. literal.len - 1] else literal;
+            const float = fmt.parseFloat(f64, digits) catch {
+                self.report(.err, "invalid float", span, 1);
                 return error.ParserError;
             };
             self.nextToken();
-            return .{ .float_literal = float };
+            if (!is_single) return .{ .float_literal = float };
+
+            const value = try self.arena.allocator().create(ast.Expression);
+            value.* = .{ .float_literal = float };
+            return .{ .sized_literal = .{ .value = value, .size = .float, .signed = true, .span = span } };
         },
         .string => {
             const id = self.cur_token.string_id;
             self.nextToken();
             return .{ .string_literal = id };
         },
+        .illegal => {
+            // The lexer turns a string with a bad escape into the escape alone.
+            if (mem.startsWith(u8, self.cur_token.literal, "\\")) {
+                const msg = try fmt.allocPrint(self.arena.allocator(), "invalid escape sequence \"{s}\"", .{self.cur_token.literal});
+                self.report(.err, msg, self.cur_token.span, 1);
+                return error.ParserError;
+            }
+            self.report(.err, "unexpected token", self.cur_token.span, 1);
+            return error.ParserError;
+        },
         .data_size => {
             const literal = self.cur_token.literal;
             const size = DataSize.fromString(literal) catch {
@@ -1073,6 +1593,78 @@ fn parsePrimary(self: *Parser) anyerror!ast.Expression {
     }
 }
 
+fn parseIntegerLiteral(self: *Parser, base: u8, prefix_len: usize, message: []const u8) !ast.Expression {
+    const literal = self.cur_token.literal;
+    const span = self.cur_token.span;
+
+    // Width suffix such as `u8` or `i32`. Neither letter is a valid digit in
+    // any supported base, so the last one marks the start of the suffix.
+    const suffix_start = mem.lastIndexOfAny(u8, literal[prefix_len..], "ui");
+    const digits_end = if (suffix_start) |i| prefix_len + i else literal.len;
+
+    const int = fmt.parseInt(i64, literal[prefix_len..digits_end], base) catch {
+        self.report(.err, message, span, 1);
+        return error.ParserError;
+    };
+    self.nextToken();
+
+    const i = suffix_start orelse return .{ .integer_literal = int };
+    const suffix = literal[prefix_len + i ..];
+    const size: DataSize = switch (fmt.parseInt(u8, suffix[1..], 10) catch 0) {
+        8 => .byte,
+        16 => .word,
+        32 => .dword,
+        64 => .qword,
+        else => {
+            self.report(.err, "invalid integer width suffix", span, 1);
+            return error.ParserError;
+        },
+    };
+
+    const value = try self.arena.allocator().create(ast.Expression);
+    value.* = .{ .integer_literal = int };
+    return .{ .sized_literal = .{
+        .value = value,
+        .size = size,
+        .signed = suffix[0] == 'i',
+        .span = span,
+    } };
+}
+
+/// Code point of a quoted character literal: one UTF-8 character, or one of
+/// the escapes `\n`, `\r`, `\t`, `\0`, `\\`, `\'`, `\"`, `\xHH` and `\uXXXX`.
+fn parseCharacter(literal: []const u8) ?i64 {
+    if (literal.len < 3 or literal[literal.len - 1] != '\'') return null;
+    const body = literal[1 .. literal.len - 1];
+
+    if (body[0] != '\\') {
+        const len = std.unicode.utf8ByteSequenceLength(body[0]) catch return null;
+        if (len != body.len) return null;
+        return std.unicode.utf8Decode(body) catch null;
+    }
+
+    if (body.len == 4 and body[1] == 'x') {
+        if (!std.ascii.isHex(body[2]) or !std.ascii.isHex(body[3])) return null;
+        return fmt.parseInt(u8, body[2..], 16) catch unreachable;
+    }
+    if (body.len == 6 and body[1] == 'u') {
+        for (body[2..]) |c| if (!std.ascii.isHex(c)) return null;
+        const code_point = fmt.parseInt(u16, body[2..], 16) catch unreachable;
+        // Surrogate halves are not characters on their own.
+        if (code_point >= 0xD800 and code_point <= 0xDFFF) return null;
+        return code_point;
+    }
+    if (body.len != 2) return null;
+    return switch (body[1]) {
+        'n' => '\n',
+        'r' => '\r',
+        't' => '\t',
+        '0' => 0,
+        '\\', '\'', '"' => body[1],
+        else => null,
+    };
+}
+
 fn report(
     self: *Parser,
     severity: fehler.Severity,
@@ -1080,14 +1672,11 @@ fn report(
     span: Span,
     status: ?u8,
 ) void {
-    const source = self.reporter.sources.get(span.filename).?;
-    self.reporter.report(.{
-        .severity = severity,
-        .message = message,
-        .range = span.toSourceRange(source),
-    });
+    span.report(self.reporter, severity, message);
     if (status) |code| {
-        process.exit(code);
+        self.error_count += 1;
+        // `parse` exits once it has seen the whole file.
+        if (self.exit_on_error and !self.recover) process.exit(code);
     }
 }
 
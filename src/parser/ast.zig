@@ -10,33 +10,55 @@ const Register = @import("../vm/register.zig").Register;
 pub const Statement = union(enum) {
     label: Label,
     @"error": Expr1,
+    warning: Expr1,
     define: Define,
+    define_env: DefineEnv,
     include: Expr1,
+    /// Put around the statements of an included file by the preprocessor,
+    /// with the span of the `#include`, so the compiler knows where a file
+    /// starts and ends. Never parsed.
+    include_start: Span,
+    include_end: Span,
+    pragma_once: Span,
     ifdef: Expr1,
     ifndef: Expr1,
     @"else": Span,
     endif: Span,
     section: Section,
     entry: Expr1,
+    requires: Requires,
+    @"align": Expr1,
     ascii: Expr1,
     asciz: Expr1,
     @"extern": Extern,
+    equ: Equ,
+    times: Times,
     nop: Span,
     mov: Mov,
+    movz: Expr2,
+    movs: Expr2,
     push: PushPop,
     pop: PushPop,
     add: Expr3,
+    adc: Expr3,
     sub: Expr3,
+    sbb: Expr3,
     mul: Expr3,
     div: Expr3,
+    imul: Expr3,
+    idiv: Expr3,
+    imod: Expr3,
     @"and": Expr3,
     @"or": Expr3,
     xor: Expr3,
+    not: Expr2,
     shl: Expr3,
     shr: Expr3,
     rol: Expr3,
     ror: Expr3,
     cmp: Expr2,
+    scmp: Expr2,
+    schr: Expr3,
     jmp: Expr1,
     jne: Expr1,
     jeq: Expr1,
@@ -44,17 +66,34 @@ pub const Statement = union(enum) {
     jgt: Expr1,
     jle: Expr1,
     jge: Expr1,
+    seteq: Expr1,
+    setne: Expr1,
+    setlt: Expr1,
+    setgt: Expr1,
+    setle: Expr1,
+    setge: Expr1,
+    setc: Expr1,
+    seto: Expr1,
     call: Expr1,
     ret: Span,
-    inc: Expr1,
-    dec: Expr1,
+    enter: Expr1,
+    leave: Span,
+    inc: IncDec,
+    dec: IncDec,
     neg: Expr1,
+    cvtif: Expr2,
+    cvtfi: Expr2,
+    cvtfd: Expr2,
+    cvtdf: Expr2,
     syscall: Span,
     hlt: Span,
+    ext: Ext,
     db: Db,
     dw: Db,
     dd: Db,
     dq: Db,
+    df: Db,
+    ddbl: Db,
     resb: Expr1,
     resw: Expr1,
     resd: Expr1,
@@ -92,18 +131,52 @@ pub const Statement = union(enum) {
         span: Span,
     };
 
+    /// `#define_env NAME ENV_VAR [default]`
+    pub const DefineEnv = struct {
+        name: *Expression,
+        env: StringId,
+        default: ?*Expression,
+        span: Span,
+    };
+
     pub const Section = struct {
         type: Type,
         span: Span,
         pub const Type = enum { text, data };
     };
 
+    /// `.requires float, net`
+    pub const Requires = struct {
+        features: []StringId,
+        span: Span,
+    };
+
+    /// `NAME equ expr` or `.equ NAME, expr`
+    pub const Equ = struct {
+        name: StringId,
+        expr: *Expression,
+        span: Span,
+    };
+
+    /// `times N stmt`, an instruction or data directive repeated `N` times.
+    pub const Times = struct {
+        count: *Expression,
+        stmt: *Statement,
+        span: Span,
+    };
+
     pub const PushPop = struct {
         data_size: ?*Expression,
         expr: *Expression,
         span: Span,
     };
 
+    pub const IncDec = struct {
+        expr: *Expression,
+        amount: ?*Expression,
+        span: Span,
+    };
+
     pub const Mov = struct {
         data_size: ?*Expression,
         expr1: *Expression,
@@ -117,6 +190,13 @@ pub const Statement = union(enum) {
         span: Span,
     };
 
+    /// `ext SUBCODE, operands...`, an extended instruction.
+    pub const Ext = struct {
+        subcode: *Expression,
+        operands: []*Expression,
+        span: Span,
+    };
+
     pub const MacroDef = struct {
         name: StringId,
         params: []StringId,
@@ -174,33 +254,52 @@ pub const Statement = union(enum) {
         return switch (self) {
             .label => |v| v.span,
             .@"error" => |v| v.span,
+            .warning => |v| v.span,
             .define => |v| v.span,
+            .define_env => |v| v.span,
             .include => |v| v.span,
+            .include_start => |v| v,
+            .include_end => |v| v,
+            .pragma_once => |v| v,
             .ifdef => |v| v.span,
             .ifndef => |v| v.span,
             .@"else" => |v| v,
             .endif => |v| v,
             .section => |v| v.span,
             .entry => |v| v.span,
+            .requires => |v| v.span,
+            .@"align" => |v| v.span,
             .ascii => |v| v.span,
             .asciz => |v| v.span,
             .@"extern" => |v| v.span,
+            .equ => |v| v.span,
+            .times => |v| v.span,
             .nop => |v| v,
             .mov => |v| v.span,
+            .movz => |v| v.span,
+            .movs => |v| v.span,
             .push => |v| v.span,
             .pop => |v| v.span,
             .add => |v| v.span,
+            .adc => |v| v.span,
             .sub => |v| v.span,
+            .sbb => |v| v.span,
             .mul => |v| v.span,
             .div => |v| v.span,
+            .imul => |v| v.span,
+            .idiv => |v| v.span,
+            .imod => |v| v.span,
             .@"and" => |v| v.span,
             .@"or" => |v| v.span,
             .xor => |v| v.span,
+            .not => |v| v.span,
             .shl => |v| v.span,
             .shr => |v| v.span,
             .rol => |v| v.span,
             .ror => |v| v.span,
             .cmp => |v| v.span,
+            .scmp => |v| v.span,
+            .schr => |v| v.span,
             .jmp => |v| v.span,
             .jne => |v| v.span,
             .jeq => |v| v.span,
@@ -208,17 +307,34 @@ pub const Statement = union(enum) {
             .jgt => |v| v.span,
             .jle => |v| v.span,
             .jge => |v| v.span,
+            .seteq => |v| v.span,
+            .setne => |v| v.span,
+            .setlt => |v| v.span,
+            .setgt => |v| v.span,
+            .setle => |v| v.span,
+            .setge => |v| v.span,
+            .setc => |v| v.span,
+            .seto => |v| v.span,
             .call => |v| v.span,
             .ret => |v| v,
+            .enter => |v| v.span,
+            .leave => |v| v,
             .inc => |v| v.span,
             .dec => |v| v.span,
             .neg => |v| v.span,
+            .cvtif => |v| v.span,
+            .cvtfi => |v| v.span,
+            .cvtfd => |v| v.span,
+            .cvtdf => |v| v.span,
             .syscall => |v| v,
             .hlt => |v| v,
+            .ext => |v| v.span,
             .db => |v| v.span,
             .dw => |v| v.span,
             .dd => |v| v.span,
             .dq => |v| v.span,
+            .df => |v| v.span,
+            .ddbl => |v| v.span,
             .resb => |v| v.span,
             .resw => |v| v.span,
             .resd => |v| v.span,
@@ -240,12 +356,21 @@ pub const Expression = union(enum) {
     address: Address,
     unary_op: UnaryOp,
     binary_op: BinaryOp,
+    sized_literal: SizedLiteral,
 
     pub const Address = struct {
         base: *Expression,
         offset: ?*Expression,
     };
 
+    /// A numeric literal with an explicit width suffix, e.g. `10u8` or `3.14f`.
+    pub const SizedLiteral = struct {
+        value: *Expression,
+        size: DataSize,
+        signed: bool,
+        span: Span,
+    };
+
     pub const UnaryOp = struct {
         expr: *Expression,
         op: Op,
@@ -253,6 +378,25 @@ pub const Expression = union(enum) {
 
         pub const Op = enum {
             neg,
+            bit_not,
+
+            /// The operator applied to `int` as a two's-complement integer
+            /// of `size`, or null if negating it overflows. `~` inverts only
+            /// the bits of `size`, read back as signed or unsigned like a
+            /// literal with that width suffix, so `~0u8` is 255 and `~0i8`
+            /// is -1.
+            pub fn apply(op: Op, int: i64, size: DataSize, signed: bool) ?i64 {
+                return switch (op) {
+                    .neg => std.math.negate(int) catch null,
+                    .bit_not => {
+                        const bits = 8 * size.sizeInBytes();
+                        if (bits >= 64) return ~int;
+                        const modulus = @as(i64, 1) << @intCast(bits);
+                        const low = ~int & (modulus - 1);
+                        return if (signed and low >= modulus >> 1) low - modulus else low;
+                    },
+                };
+            }
         };
     };
 
@@ -27,7 +27,7 @@ pub const DataSize = enum {
             .b0, .b1, .b2, .b3, .b4, .b5, .b6, .b7, .b8, .b9, .b10, .b11, .b12, .b13, .b14, .b15 => .byte,
             .w0, .w1, .w2, .w3, .w4, .w5, .w6, .w7, .w8, .w9, .w10, .w11, .w12, .w13, .w14, .w15 => .word,
             .d0, .d1, .d2, .d3, .d4, .d5, .d6, .d7, .d8, .d9, .d10, .d11, .d12, .d13, .d14, .d15 => .dword,
-            .q0, .q1, .q2, .q3, .q4, .q5, .q6, .q7, .q8, .q9, .q10, .q11, .q12, .q13, .q14, .q15, .ip, .sp, .bp => .qword,
+            .q0, .q1, .q2, .q3, .q4, .q5, .q6, .q7, .q8, .q9, .q10, .q11, .q12, .q13, .q14, .q15, .ip, .sp, .bp, .zr, .msz, .brk, .isp => .qword,
             .ff0, .ff1, .ff2, .ff3, .ff4, .ff5, .ff6, .ff7, .ff8, .ff9, .ff10, .ff11, .ff12, .ff13, .ff14, .ff15 => .float,
             .dd0, .dd1, .dd2, .dd3, .dd4, .dd5, .dd6, .dd7, .dd8, .dd9, .dd10, .dd11, .dd12, .dd13, .dd14, .dd15 => .double,
         };
@@ -60,6 +60,45 @@ pub const DataSize = enum {
     }
 };
 
+/// Why `Immediate.toExact` refused a conversion.
+pub const ConversionError = error{
+    /// The integer part does not fit in the destination.
+    OutOfRange,
+    /// A float with a fractional part was converted to an integer.
+    FractionalPart,
+    /// NaN or infinity was converted to an integer.
+    NotFinite,
+    /// The destination float cannot represent the value exactly.
+    PrecisionLoss,
+};
+
+/// The sizes involved in a failed conversion, filled in by `toExact`.
+pub const ConversionDiagnostic = struct {
+    from: DataSize,
+    to: DataSize,
+    err: ConversionError,
+
+    pub fn format(self: ConversionDiagnostic, writer: *std.Io.Writer) std.Io.Writer.Error!void {
+        try writer.print("cannot convert {s} to {s}: {s}", .{ @tagName(self.from), @tagName(self.to), @errorName(self.err) });
+    }
+};
+
+/// A value tagged with its size. Integers are unsigned; signedness only
+/// exists in the instructions that interpret them.
+///
+/// Conversions between sizes come in two flavors:
+///
+/// | from \ to    | integer                      | float / double               |
+/// |---------------|------------------------------|------------------------------|
+/// | integer       | exact if the value fits;     | exact if representable;      |
+/// |               | lossy truncates high bits    | lossy rounds to nearest      |
+/// | float/double  | exact if finite, integral    | widening is always exact;    |
+/// |               | and in range; lossy rounds   | narrowing is exact if it     |
+/// |               | toward zero and saturates,   | round-trips, lossy rounds    |
+/// |               | NaN becomes 0                |                              |
+///
+/// `toExact` returns a `ConversionError` instead of changing the value,
+/// `toLossy` never fails. The `asU8`..`asUsize` accessors are lossy.
 pub const Immediate = union(enum) {
     byte: u8,
     word: u16,
@@ -68,81 +107,108 @@ pub const Immediate = union(enum) {
     float: f32,
     double: f64,
 
-    pub fn asU8(self: Immediate) u8 {
-        return switch (self) {
-            .byte => |v| v,
-            .word => |v| @truncate(v),
-            .dword => |v| @truncate(v),
-            .qword => |v| @truncate(v),
-            .float => |v| @truncate(@as(u8, @intFromFloat(v))),
-            .double => |v| @truncate(@as(u8, @intFromFloat(v))),
+    /// Convert to `to` without changing the value. On failure `diagnostic`,
+    /// if given, records the sizes involved.
+    pub fn toExact(self: Immediate, to: DataSize, diagnostic: ?*ConversionDiagnostic) ConversionError!Immediate {
+        return self.convertExact(to) catch |err| {
+            if (diagnostic) |d| d.* = .{ .from = self.size(), .to = to, .err = err };
+            return err;
         };
     }
 
-    pub fn asU16(self: Immediate) u16 {
+    fn convertExact(self: Immediate, to: DataSize) ConversionError!Immediate {
         return switch (self) {
-            .byte => |v| @intCast(v),
-            .word => |v| v,
-            .dword => |v| @truncate(v),
-            .qword => |v| @truncate(v),
-            .float => |v| @truncate(@as(u16, @intFromFloat(v))),
-            .double => |v| @truncate(@as(u16, @intFromFloat(v))),
+            inline .byte, .word, .dword, .qword => |v| switch (to) {
+                inline .byte, .word, .dword, .qword => |t| @unionInit(
+                    Immediate,
+                    @tagName(t),
+                    std.math.cast(@FieldType(Immediate, @tagName(t)), v) orelse return error.OutOfRange,
+                ),
+                inline .float, .double => |t| blk: {
+                    const F = @FieldType(Immediate, @tagName(t));
+                    const f: F = @floatFromInt(v);
+                    // Values at the top of the range can round up past the
+                    // largest integer, which cannot be converted back.
+                    if (f >= limit(@TypeOf(v)) or @as(@TypeOf(v), @intFromFloat(f)) != v) return error.PrecisionLoss;
+                    break :blk @unionInit(Immediate, @tagName(t), f);
+                },
+            },
+            inline .float, .double => |v| switch (to) {
+                inline .byte, .word, .dword, .qword => |t| blk: {
+                    const I = @FieldType(Immediate, @tagName(t));
+                    if (!std.math.isFinite(v)) return error.NotFinite;
+                    if (@trunc(v) != v) return error.FractionalPart;
+                    if (v < 0 or v >= limit(I)) return error.OutOfRange;
+                    break :blk @unionInit(Immediate, @tagName(t), @as(I, @intFromFloat(v)));
+                },
+                .float => blk: {
+                    const f: f32 = @floatCast(v);
+                    if (@as(@TypeOf(v), f) != v and !std.math.isNan(v)) return error.PrecisionLoss;
+                    break :blk .{ .float = f };
+                },
+                .double => .{ .double = v },
+            },
         };
     }
 
-    pub fn asU32(self: Immediate) u32 {
+    /// One past the largest value of `I`, a power of two that every float
+    /// type represents exactly.
+    fn limit(comptime I: type) comptime_float {
+        return @floatFromInt(std.math.maxInt(I) + 1);
+    }
+
+    /// Convert to `to`, changing the value as described in the table above
+    /// when it does not fit.
+    pub fn toLossy(self: Immediate, to: DataSize) Immediate {
         return switch (self) {
-            .byte => |v| @intCast(v),
-            .word => |v| @intCast(v),
-            .dword => |v| v,
-            .qword => |v| @truncate(v),
-            .float => |v| @truncate(@as(u32, @intFromFloat(v))),
-            .double => |v| @truncate(@as(u32, @intFromFloat(v))),
+            inline .byte, .word, .dword, .qword => |v| switch (to) {
+                inline .byte, .word, .dword, .qword => |t| @unionInit(Immediate, @tagName(t), @truncate(v)),
+                inline .float, .double => |t| @unionInit(Immediate, @tagName(t), @floatFromInt(v)),
+            },
+            inline .float, .double => |v| switch (to) {
+                inline .byte, .word, .dword, .qword => |t| @unionInit(
+                    Immediate,
+                    @tagName(t),
+                    std.math.lossyCast(@FieldType(Immediate, @tagName(t)), v),
+                ),
+                .float => .{ .float = @floatCast(v) },
+                .double => .{ .double = v },
+            },
         };
     }
 
+    pub fn asU8(self: Immediate) u8 {
+        return self.toLossy(.byte).byte;
+    }
+
+    pub fn asU16(self: Immediate) u16 {
+        return self.toLossy(.word).word;
+    }
+
+    pub fn asU32(self: Immediate) u32 {
+        return self.toLossy(.dword).dword;
+    }
+
     pub fn asU64(self: Immediate) u64 {
-        return switch (self) {
-            .byte => |v| @intCast(v),
-            .word => |v| @intCast(v),
-            .dword => |v| @intCast(v),
-            .qword => |v| v,
-            .float => |v| @truncate(@as(u64, @intFromFloat(v))),
-            .double => |v| @truncate(@as(u64, @intFromFloat(v))),
-        };
+        return self.toLossy(.qword).qword;
     }
 
     pub fn asF32(self: Immediate) f32 {
-        return switch (self) {
-            .byte => |v| @floatFromInt(v),
-            .word => |v| @floatFromInt(v),
-            .dword => |v| @floatFromInt(v),
-            .qword => |v| @floatFromInt(v),
-            .float => |v| v,
-            .double => |v| @floatCast(v),
-        };
+        return self.toLossy(.float).float;
     }
 
     pub fn asF64(self: Immediate) f64 {
-        return switch (self) {
-            .byte => |v| @floatFromInt(v),
-            .word => |v| @floatFromInt(v),
-            .dword => |v| @floatFromInt(v),
-            .qword => |v| @floatFromInt(v),
-            .float => |v| @floatCast(v),
-            .double => |v| v,
-        };
+        return self.toLossy(.double).double;
     }
 
     pub fn asUsize(self: Immediate) usize {
-        return switch (self) {
-            .byte => |v| @intCast(v),
-            .word => |v| @intCast(v),
-            .dword => |v| @intCast(v),
-            .qword => |v| @intCast(v),
-            .float => |v| @intFromFloat(v),
-            .double => |v| @intFromFloat(v),
-        };
+        return @intCast(self.asU64());
+    }
+
+    /// An address or jump target held in the value. Floats are only accepted
+    /// if they hold an exact integer.
+    pub fn toAddress(self: Immediate) ConversionError!usize {
+        return @intCast((try self.toExact(.qword, null)).qword);
     }
 
     pub fn size(self: Immediate) DataSize {
@@ -0,0 +1,217 @@
+//! The AST as JSON, written by `nyx build --emit ast` for tools that want
+//! the parsed program without reimplementing the parser.
+//!
+//! Statements and expressions become objects whose `kind` is the variant
+//! name. A variant holding a struct has that struct's fields next to `kind`;
+//! one holding only a span (`nop`, `ret`, ...) has `span`, and any other
+//! payload is under `value`. Interned strings such as identifiers and label
+//! names are written as strings, enums by name, pointers as what they point
+//! at, and spans as `{"file", "start", "end"}` with byte offsets:
+//!
+//! ```json
+//! {"kind": "mov", "data_size": null,
+//!  "expr1": {"kind": "register", "value": "q0"},
+//!  "expr2": {"kind": "integer_literal", "value": 1},
+//!  "span": {"file": "main.nyx", "start": 10, "end": 20}}
+//! ```
+//!
+//! `readStatements` turns that JSON back into statements, so tools can also
+//! hand a program they built or rewrote to the compiler.
+
+const std = @import("std");
+const Io = std.Io;
+const Allocator = std.mem.Allocator;
+const Stringify = std.json.Stringify;
+const ast = @import("ast.zig");
+const Span = @import("../Span.zig");
+const StringInterner = @import("../StringInterner.zig");
+const StringId = StringInterner.StringId;
+
+/// Write `stmts` as a JSON array, resolving names through `interner`.
+pub fn writeStatements(stmts: []const ast.Statement, interner: *const StringInterner, writer: *Io.Writer) Io.Writer.Error!void {
+    var json: Stringify = .{ .writer = writer, .options = .{ .whitespace = .indent_2 } };
+    try writeValue(&json, interner, stmts);
+    try writer.writeByte('\n');
+}
+
+fn writeValue(json: *Stringify, interner: *const StringInterner, value: anytype) Io.Writer.Error!void {
+    const T = @TypeOf(value);
+    if (T == StringId) return json.write(interner.get(value));
+    if (T == Span) return writeSpan(json, value);
+
+    switch (@typeInfo(T)) {
+        .pointer => |pointer| switch (pointer.size) {
+            .one => try writeValue(json, interner, value.*),
+            .slice => {
+                try json.beginArray();
+                for (value) |item| try writeValue(json, interner, item);
+                try json.endArray();
+            },
+            else => @compileError("cannot write " ++ @typeName(T)),
+        },
+        .optional => if (value) |v| try writeValue(json, interner, v) else try json.write(null),
+        .@"struct" => {
+            try json.beginObject();
+            try writeFields(json, interner, value);
+            try json.endObject();
+        },
+        .@"union" => {
+            try json.beginObject();
+            try json.objectField("kind");
+            try json.write(@tagName(value));
+            switch (value) {
+                inline else => |payload| {
+                    const Payload = @TypeOf(payload);
+                    if (Payload == Span) {
+                        try json.objectField("span");
+                        try writeSpan(json, payload);
+                    } else if (@typeInfo(Payload) == .@"struct") {
+                        try writeFields(json, interner, payload);
+                    } else {
+                        try json.objectField("value");
+                        try writeValue(json, interner, payload);
+                    }
+                },
+            }
+            try json.endObject();
+        },
+        // `FfiType` has unnamed values for structs passed by value.
+        .@"enum" => if (std.enums.tagName(T, value)) |name| try json.write(name) else try json.write(@intFromEnum(value)),
+        else => try json.write(value),
+    }
+}
+
+fn writeFields(json: *Stringify, interner: *const StringInterner, value: anytype) Io.Writer.Error!void {
+    inline for (@typeInfo(@TypeOf(value)).@"struct".fields) |field| {
+        try json.objectField(field.name);
+        try writeValue(json, interner, @field(value, field.name));
+    }
+}
+
+fn writeSpan(json: *Stringify, span: Span) Io.Writer.Error!void {
+    try json.beginObject();
+    try json.objectField("file");
+    try json.write(span.filename);
+    try json.objectField("start");
+    try json.write(span.start);
+    try json.objectField("end");
+    try json.write(span.end);
+    try json.endObject();
+}
+
+pub const ReadError = error{InvalidAst} || Allocator.Error;
+
+/// Read statements written by `writeStatements`. Names are interned into
+/// `interner`; the statements and the file names in their spans are
+/// allocated with `arena`, which the caller frees in one go.
+pub fn readStatements(arena: Allocator, interner: *StringInterner, text: []const u8) ReadError![]ast.Statement {
+    const root = std.json.parseFromSliceLeaky(std.json.Value, arena, text, .{}) catch |err| switch (err) {
+        error.OutOfMemory => return error.OutOfMemory,
+        else => return error.InvalidAst,
+    };
+    return readValue([]ast.Statement, arena, interner, root);
+}
+
+fn readValue(comptime T: type, arena: Allocator, interner: *StringInterner, value: std.json.Value) ReadError!T {
+    if (T == StringId) return interner.intern(try expectString(value));
+    if (T == Span) return readSpan(arena, value);
+
+    switch (@typeInfo(T)) {
+        .pointer => |pointer| switch (pointer.size) {
+            .one => {
+                const result = try arena.create(pointer.child);
+                result.* = try readValue(pointer.child, arena, interner, value);
+                return result;
+            },
+            .slice => {
+                const items = switch (value) {
+                    .array => |array| array.items,
+                    else => return error.InvalidAst,
+                };
+                const result = try arena.alloc(pointer.child, items.len);
+                for (items, result) |item, *out| out.* = try readValue(pointer.child, arena, interner, item);
+                return result;
+            },
+            else => @compileError("cannot read " ++ @typeName(T)),
+        },
+        .optional => |optional| return if (value == .null) null else try readValue(optional.child, arena, interner, value),
+        .@"struct" => return readFields(T, arena, interner, try expectObject(value)),
+        .@"union" => |info| {
+            const object = try expectObject(value);
+            const kind = try expectString(object.get("kind") orelse return error.InvalidAst);
+            inline for (info.fields) |field| {
+                if (std.mem.eql(u8, field.name, kind)) {
+                    const payload: field.type = if (field.type == Span)
+                        try readSpan(arena, object.get("span") orelse return error.InvalidAst)
+                    else if (@typeInfo(field.type) == .@"struct")
+                        try readFields(field.type, arena, interner, object)
+                    else
+                        try readValue(field.type, arena, interner, object.get("value") orelse return error.InvalidAst);
+                    return @unionInit(T, field.name, payload);
+                }
+            }
+            return error.InvalidAst;
+        },
+        .@"enum" => |info| switch (value) {
+            .string => |name| return std.meta.stringToEnum(T, name) orelse error.InvalidAst,
+            // Unnamed values of `FfiType` are written as numbers.
+            .integer => |int| {
+                if (info.is_exhaustive) return error.InvalidAst;
+                return @enumFromInt(std.math.cast(info.tag_type, int) orelse return error.InvalidAst);
+            },
+            else => return error.InvalidAst,
+        },
+        .int => switch (value) {
+            .integer => |int| return std.math.cast(T, int) orelse error.InvalidAst,
+            else => return error.InvalidAst,
+        },
+        .float => switch (value) {
+            .float => |float| return @floatCast(float),
+            .integer => |int| return @floatFromInt(int),
+            else => return error.InvalidAst,
+        },
+        .bool => switch (value) {
+            .bool => |b| return b,
+            else => return error.InvalidAst,
+        },
+        else => @compileError("cannot read " ++ @typeName(T)),
+    }
+}
+
+fn readFields(comptime T: type, arena: Allocator, interner: *StringInterner, object: std.json.ObjectMap) ReadError!T {
+    var result: T = undefined;
+    inline for (@typeInfo(T).@"struct".fields) |field| {
+        const value = object.get(field.name) orelse return error.InvalidAst;
+        @field(result, field.name) = try readValue(field.type, arena, interner, value);
+    }
+    return result;
+}
+
+fn readSpan(arena: Allocator, value: std.json.Value) ReadError!Span {
+    const object = try expectObject(value);
+    const start = try expectInt(usize, object.get("start") orelse return error.InvalidAst);
+    const end = try expectInt(usize, object.get("end") orelse return error.InvalidAst);
+    const file = try expectString(object.get("file") orelse return error.InvalidAst);
+    return .init(start, end, try arena.dupe(u8, file));
+}
+
+fn expectObject(value: std.json.Value) ReadError!std.json.ObjectMap {
+    return switch (value) {
+        .object => |object| object,
+        else => error.InvalidAst,
+    };
+}
+
+fn expectString(value: std.json.Value) ReadError![]const u8 {
+    return switch (value) {
+        .string => |string| string,
+        else => error.InvalidAst,
+    };
+}
+
+fn expectInt(comptime T: type, value: std.json.Value) ReadError!T {
+    return switch (value) {
+        .integer => |int| std.math.cast(T, int) orelse error.InvalidAst,
+        else => error.InvalidAst,
+    };
+}
@@ -0,0 +1,127 @@
+//! The AST as RON (Rusty Object Notation), written by
+//! `nyx build --emit ast --format ron` when built with `-Dron`, which is the
+//! default. It carries the same data as `json.zig` in RON's own shapes: a
+//! statement or expression is its variant name with its fields, its span or
+//! its value in parentheses, structs are fields in parentheses, optional
+//! values are `Some(...)` or `None`, names are strings and enums are bare
+//! identifiers. Each statement is on its own line:
+//!
+//! ```ron
+//! [
+//!     mov(data_size: None, expr1: register(q0), expr2: integer_literal(1), span: (file: "main.nyx", start: 10, end: 20)),
+//! ]
+//! ```
+
+const std = @import("std");
+const Io = std.Io;
+const ast = @import("ast.zig");
+const Span = @import("../Span.zig");
+const StringInterner = @import("../StringInterner.zig");
+const StringId = StringInterner.StringId;
+
+/// Write `stmts` as a RON list, resolving names through `interner`.
+pub fn writeStatements(stmts: []const ast.Statement, interner: *const StringInterner, writer: *Io.Writer) Io.Writer.Error!void {
+    try writer.writeAll("[\n");
+    for (stmts) |stmt| {
+        try writer.writeAll("    ");
+        try writeValue(writer, interner, stmt);
+        try writer.writeAll(",\n");
+    }
+    try writer.writeAll("]\n");
+}
+
+fn writeValue(writer: *Io.Writer, interner: *const StringInterner, value: anytype) Io.Writer.Error!void {
+    const T = @TypeOf(value);
+    if (T == StringId) return writeString(writer, interner.get(value) orelse "");
+    if (T == Span) return writeSpan(writer, value);
+
+    switch (@typeInfo(T)) {
+        .pointer => |pointer| switch (pointer.size) {
+            .one => try writeValue(writer, interner, value.*),
+            .slice => {
+                try writer.writeByte('[');
+                for (value, 0..) |item, i| {
+                    if (i > 0) try writer.writeAll(", ");
+                    try writeValue(writer, interner, item);
+                }
+                try writer.writeByte(']');
+            },
+            else => @compileError("cannot write " ++ @typeName(T)),
+        },
+        .optional => if (value) |v| {
+            try writer.writeAll("Some(");
+            try writeValue(writer, interner, v);
+            try writer.writeByte(')');
+        } else try writer.writeAll("None"),
+        .@"struct" => {
+            try writer.writeByte('(');
+            try writeFields(writer, interner, value);
+            try writer.writeByte(')');
+        },
+        .@"union" => {
+            try writer.writeAll(@tagName(value));
+            try writer.writeByte('(');
+            switch (value) {
+                inline else => |payload| {
+                    const Payload = @TypeOf(payload);
+                    if (Payload == Span) {
+                        try writer.writeAll("span: ");
+                        try writeSpan(writer, payload);
+                    } else if (@typeInfo(Payload) == .@"struct") {
+                        try writeFields(writer, interner, payload);
+                    } else {
+                        try writeValue(writer, interner, payload);
+                    }
+                },
+            }
+            try writer.writeByte(')');
+        },
+        // `FfiType` has unnamed values for structs passed by value.
+        .@"enum" => if (std.enums.tagName(T, value)) |name| try writer.writeAll(name) else try writer.print("{d}", .{@intFromEnum(value)}),
+        .float => try writeFloat(writer, value),
+        .int, .comptime_int => try writer.print("{d}", .{value}),
+        .bool => try writer.writeAll(if (value) "true" else "false"),
+        else => @compileError("cannot write " ++ @typeName(T)),
+    }
+}
+
+fn writeFields(writer: *Io.Writer, interner: *const StringInterner, value: anytype) Io.Writer.Error!void {
+    inline for (@typeInfo(@TypeOf(value)).@"struct".fields, 0..) |field, i| {
+        if (i > 0) try writer.writeAll(", ");
+        try writer.writeAll(field.name ++ ": ");
+        try writeValue(writer, interner, @field(value, field.name));
+    }
+}
+
+fn writeSpan(writer: *Io.Writer, span: Span) Io.Writer.Error!void {
+    try writer.writeAll("(file: ");
+    try writeString(writer, span.filename);
+    try writer.print(", start: {d}, end: {d})", .{ span.start, span.end });
+}
+
+/// Write `bytes` as a quoted string with Rust's escapes.
+fn writeString(writer: *Io.Writer, bytes: []const u8) Io.Writer.Error!void {
+    try writer.writeByte('"');
+    for (bytes) |byte| switch (byte) {
+        '"' => try writer.writeAll("\\\""),
+        '\\' => try writer.writeAll("\\\\"),
+        '\n' => try writer.writeAll("\\n"),
+        '\r' => try writer.writeAll("\\r"),
+        '\t' => try writer.writeAll("\\t"),
+        0...0x08, 0x0b, 0x0c, 0x0e...0x1f, 0x7f => try writer.print("\\u{{{x}}}", .{byte}),
+        else => try writer.writeByte(byte),
+    };
+    try writer.writeByte('"');
+}
+
+/// RON reads a number without a point or exponent as an integer, so whole
+/// floats keep their `.0`.
+fn writeFloat(writer: *Io.Writer, value: anytype) Io.Writer.Error!void {
+    if (std.math.isNan(value)) return writer.writeAll("NaN");
+    if (std.math.isInf(value)) return writer.writeAll(if (value < 0) "-inf" else "inf");
+    // Whole numbers print every digit, up to 309 of them for an f64.
+    var buf: [512]u8 = undefined;
+    const text = std.fmt.bufPrint(&buf, "{d}", .{value}) catch unreachable;
+    try writer.writeAll(text);
+    if (std.mem.indexOfAny(u8, text, ".e") == null) try writer.writeAll(".0");
+}
@@ -7,7 +7,11 @@ const Token = @import("../lexer/Token.zig");
 const Lexer = @import("../lexer/Lexer.zig");
 const Parser = @import("Parser.zig");
 const ast = @import("ast.zig");
+const ast_json = @import("json.zig");
+const ast_ron = @import("ron.zig");
 const DataSize = @import("immediate.zig").DataSize;
+const Immediate = @import("immediate.zig").Immediate;
+const ConversionDiagnostic = @import("immediate.zig").ConversionDiagnostic;
 const fehler = @import("fehler");
 
 const ParseResult = struct {
@@ -191,6 +195,26 @@ test "instructions" {
                 }
             }.f,
         },
+        .{
+            .input = "inc q0, 8",
+            .check = struct {
+                fn f(stmt: ast.Statement, _: *const StringInterner) !void {
+                    try testing.expect(stmt == .inc);
+                    try testing.expect(stmt.inc.expr.* == .register);
+                    try testing.expectEqual(@as(i64, 8), stmt.inc.amount.?.integer_literal);
+                }
+            }.f,
+        },
+        .{
+            .input = "dec d1, 4",
+            .check = struct {
+                fn f(stmt: ast.Statement, _: *const StringInterner) !void {
+                    try testing.expect(stmt == .dec);
+                    try testing.expect(stmt.dec.expr.* == .register);
+                    try testing.expectEqual(@as(i64, 4), stmt.dec.amount.?.integer_literal);
+                }
+            }.f,
+        },
         .{
             .input = "ret",
             .check = struct {
@@ -477,6 +501,50 @@ test "expressions" {
                 }
             }.f,
         },
+        .{
+            .input = "mov b0, 0xFFu8",
+            .check = struct {
+                fn f(stmt: ast.Statement) !void {
+                    try testing.expect(stmt == .mov);
+                    try testing.expect(stmt.mov.expr2.* == .sized_literal);
+                    try testing.expectEqual(DataSize.byte, stmt.mov.expr2.sized_literal.size);
+                    try testing.expect(!stmt.mov.expr2.sized_literal.signed);
+                    try testing.expectEqual(@as(i64, 255), stmt.mov.expr2.sized_literal.value.integer_literal);
+                }
+            }.f,
+        },
+        .{
+            .input = "push 7i32",
+            .check = struct {
+                fn f(stmt: ast.Statement) !void {
+                    try testing.expect(stmt == .push);
+                    try testing.expectEqual(DataSize.dword, stmt.push.expr.sized_literal.size);
+                    try testing.expect(stmt.push.expr.sized_literal.signed);
+                }
+            }.f,
+        },
+        .{
+            .input = "mov q0, ~0xFF",
+            .check = struct {
+                fn f(stmt: ast.Statement) !void {
+                    try testing.expect(stmt == .mov);
+                    try testing.expect(stmt.mov.expr2.* == .unary_op);
+                    try testing.expectEqual(ast.Expression.UnaryOp.Op.bit_not, stmt.mov.expr2.unary_op.op);
+                    try testing.expectEqual(@as(i64, 255), stmt.mov.expr2.unary_op.expr.integer_literal);
+                }
+            }.f,
+        },
+        .{
+            .input = "push 3.14f",
+            .check = struct {
+                fn f(stmt: ast.Statement) !void {
+                    try testing.expect(stmt == .push);
+                    try testing.expect(stmt.push.expr.* == .sized_literal);
+                    try testing.expectEqual(DataSize.float, stmt.push.expr.sized_literal.size);
+                    try testing.expectEqual(@as(f64, 3.14), stmt.push.expr.sized_literal.value.float_literal);
+                }
+            }.f,
+        },
     };
 
     for (tests) |t| {
@@ -487,6 +555,41 @@ test "expressions" {
     }
 }
 
+test "character literals" {
+    const cases = [_]struct { input: []const u8, expected: i64 }{
+        .{ .input = "mov b0, 'A'", .expected = 'A' },
+        .{ .input = "mov b0, '\\n'", .expected = '\n' },
+        .{ .input = "mov b0, '\\0'", .expected = 0 },
+        .{ .input = "mov b0, '\\x41'", .expected = 0x41 },
+        .{ .input = "mov b0, '\\''", .expected = '\'' },
+        .{ .input = "mov w0, '\u{e9}'", .expected = 0xe9 },
+        .{ .input = "mov w0, '\\u20AC'", .expected = 0x20ac },
+    };
+
+    for (cases) |case| {
+        var res = try parse(testing.allocator, case.input);
+        defer res.deinit(testing.allocator);
+        try testing.expectEqual(@as(usize, 1), res.stmts.len);
+        try testing.expect(res.stmts[0].mov.expr2.* == .integer_literal);
+        try testing.expectEqual(case.expected, res.stmts[0].mov.expr2.integer_literal);
+    }
+
+    const invalid = [_][]const u8{ "mov b0, ''", "mov b0, 'AB'", "mov b0, '\\q'", "mov b0, '\\x4'", "mov w0, '\\uD800'", "mov b0, 'A" };
+    for (invalid) |input| {
+        var reporter = fehler.ErrorReporter.init(testing.allocator);
+        defer reporter.deinit();
+        try reporter.addSource("test.nyx", input);
+        var interner = StringInterner.init(testing.allocator);
+        defer interner.deinit();
+        var lexer = Lexer.init("test.nyx", input, &interner, testing.allocator);
+        var parser = Parser.init(&lexer, &reporter, testing.allocator);
+        defer parser.deinit();
+        parser.exit_on_error = false;
+
+        try testing.expectError(error.ParserError, parser.parse());
+    }
+}
+
 test "addressing modes" {
     const tests = [_]struct {
         input: []const u8,
@@ -630,6 +733,44 @@ test "sections" {
     }
 }
 
+test "equ" {
+    var res = try parse(testing.allocator, "LEN equ end - start\n.equ WORDS, LEN / 8");
+    defer res.deinit(testing.allocator);
+
+    try testing.expectEqual(@as(usize, 2), res.stmts.len);
+    try testing.expectEqualStrings("LEN", res.interner.get(res.stmts[0].equ.name).?);
+    try testing.expect(res.stmts[0].equ.expr.* == .binary_op);
+    try testing.expectEqual(@as(usize, 0), res.stmts[0].equ.span.start);
+    try testing.expectEqualStrings("WORDS", res.interner.get(res.stmts[1].equ.name).?);
+    try testing.expectEqual(ast.Expression.BinaryOp.Op.div, res.stmts[1].equ.expr.binary_op.op);
+}
+
+test "times" {
+    var res = try parse(testing.allocator, "times 64 - (end - start) db 0xFF\ntimes 3 nop");
+    defer res.deinit(testing.allocator);
+
+    try testing.expectEqual(@as(usize, 2), res.stmts.len);
+    try testing.expect(res.stmts[0].times.count.* == .binary_op);
+    try testing.expect(res.stmts[0].times.stmt.* == .db);
+    try testing.expectEqual(@as(i64, 0xFF), res.stmts[0].times.stmt.db.exprs[0].integer_literal);
+    try testing.expectEqual(@as(i64, 3), res.stmts[1].times.count.integer_literal);
+    try testing.expect(res.stmts[1].times.stmt.* == .nop);
+    try testing.expectEqual(@as(usize, 32), res.stmts[0].times.span.end);
+}
+
+test "define_env" {
+    var res = try parse(testing.allocator, "#define_env VERSION NYX_VERSION 0x10\n#define_env PREFIX HOME\nnop");
+    defer res.deinit(testing.allocator);
+
+    try testing.expectEqual(@as(usize, 3), res.stmts.len);
+    try testing.expect(res.stmts[0] == .define_env);
+    try testing.expectEqualStrings("VERSION", res.interner.get(res.stmts[0].define_env.name.identifier).?);
+    try testing.expectEqualStrings("NYX_VERSION", res.interner.get(res.stmts[0].define_env.env).?);
+    try testing.expectEqual(@as(i64, 0x10), res.stmts[0].define_env.default.?.integer_literal);
+    try testing.expect(res.stmts[1].define_env.default == null);
+    try testing.expect(res.stmts[2] == .nop);
+}
+
 test "complex program" {
     const input =
         \\
@@ -677,3 +818,216 @@ test "complex program" {
     try testing.expect(res.stmts[10].asciz.expr.* == .string_literal);
     try testing.expectEqualStrings("Hello, world!\n", res.interner.get(res.stmts[10].asciz.expr.string_literal).?);
 }
+
+test "every bad line is reported before failing" {
+    const input =
+        \\_start:
+        \\    mov q0 5
+        \\    add q1, q1, 1
+        \\    push ,
+        \\    .section nowhere
+        \\    hlt
+    ;
+    var reporter = fehler.ErrorReporter.init(testing.allocator);
+    defer reporter.deinit();
+    try reporter.addSource("test.nyx", input);
+    var interner = StringInterner.init(testing.allocator);
+    defer interner.deinit();
+    var lexer = Lexer.init("test.nyx", input, &interner, testing.allocator);
+    var parser = Parser.init(&lexer, &reporter, testing.allocator);
+    defer parser.deinit();
+    parser.exit_on_error = false;
+
+    try testing.expectError(error.ParserError, parser.parse());
+    try testing.expectEqual(@as(usize, 3), parser.error_count);
+
+    var strict_lexer = Lexer.init("test.nyx", input, &interner, testing.allocator);
+    var strict = Parser.init(&strict_lexer, &reporter, testing.allocator);
+    defer strict.deinit();
+    strict.exit_on_error = false;
+    strict.recover = false;
+
+    try testing.expectError(error.ParserError, strict.parse());
+    try testing.expectEqual(@as(usize, 1), strict.error_count);
+}
+
+test "immediate exact and lossy conversions" {
+    const big: Immediate = .{ .word = 300 };
+    try testing.expectEqual(Immediate{ .qword = 300 }, try big.toExact(.qword, null));
+    try testing.expectEqual(Immediate{ .double = 300 }, try big.toExact(.double, null));
+
+    var diagnostic: ConversionDiagnostic = undefined;
+    try testing.expectError(error.OutOfRange, big.toExact(.byte, &diagnostic));
+    try testing.expectEqual(DataSize.word, diagnostic.from);
+    try testing.expectEqual(DataSize.byte, diagnostic.to);
+    try testing.expectEqual(Immediate{ .byte = 44 }, big.toLossy(.byte));
+
+    const half: Immediate = .{ .double = 2.5 };
+    try testing.expectError(error.FractionalPart, half.toExact(.dword, null));
+    try testing.expectEqual(Immediate{ .dword = 2 }, half.toLossy(.dword));
+    try testing.expectEqual(Immediate{ .float = 2.5 }, try half.toExact(.float, null));
+
+    const negative: Immediate = .{ .float = -1 };
+    try testing.expectError(error.OutOfRange, negative.toExact(.qword, null));
+    try testing.expectEqual(Immediate{ .qword = 0 }, negative.toLossy(.qword));
+
+    const huge: Immediate = .{ .double = 1e300 };
+    try testing.expectEqual(Immediate{ .word = 0xFFFF }, huge.toLossy(.word));
+    try testing.expectError(error.PrecisionLoss, huge.toExact(.float, null));
+
+    const nan: Immediate = .{ .float = std.math.nan(f32) };
+    try testing.expectError(error.NotFinite, nan.toExact(.byte, null));
+    try testing.expectEqual(Immediate{ .byte = 0 }, nan.toLossy(.byte));
+
+    const odd: Immediate = .{ .qword = (1 << 53) + 1 };
+    try testing.expectError(error.PrecisionLoss, odd.toExact(.double, null));
+    const max: Immediate = .{ .qword = std.math.maxInt(u64) };
+    try testing.expectError(error.PrecisionLoss, max.toExact(.float, null));
+}
+
+test "statements are written as json" {
+    var res = try parse(testing.allocator, "loop:\nmov q0, 1\njmp loop\nret");
+    defer res.deinit(testing.allocator);
+
+    var output: std.Io.Writer.Allocating = .init(testing.allocator);
+    defer output.deinit();
+    try ast_json.writeStatements(res.stmts, res.interner, &output.writer);
+
+    const parsed = try std.json.parseFromSlice(std.json.Value, testing.allocator, output.written(), .{});
+    defer parsed.deinit();
+    const stmts = parsed.value.array.items;
+    try testing.expectEqual(@as(usize, 4), stmts.len);
+
+    try testing.expectEqualStrings("label", stmts[0].object.get("kind").?.string);
+    try testing.expectEqualStrings("loop", stmts[0].object.get("name").?.string);
+    const span = stmts[0].object.get("span").?.object;
+    try testing.expectEqualStrings("test.nyx", span.get("file").?.string);
+    try testing.expectEqual(@as(i64, 0), span.get("start").?.integer);
+
+    const mov = stmts[1].object;
+    try testing.expectEqualStrings("mov", mov.get("kind").?.string);
+    try testing.expect(mov.get("data_size").? == .null);
+    try testing.expectEqualStrings("register", mov.get("expr1").?.object.get("kind").?.string);
+    try testing.expectEqualStrings("q0", mov.get("expr1").?.object.get("value").?.string);
+    try testing.expectEqual(@as(i64, 1), mov.get("expr2").?.object.get("value").?.integer);
+
+    const target = stmts[2].object.get("expr").?.object;
+    try testing.expectEqualStrings("identifier", target.get("kind").?.string);
+    try testing.expectEqualStrings("loop", target.get("value").?.string);
+
+    try testing.expectEqualStrings("ret", stmts[3].object.get("kind").?.string);
+    try testing.expect(stmts[3].object.get("span").? == .object);
+}
+
+test "statements read back from json are written the same" {
+    var res = try parse(testing.allocator,
+        \\.section data
+        \\msg: db "hi", 0
+        \\.section text
+        \\.extern puts(ptr): i32
+        \\_start:
+        \\    mov q0, [msg + 8]
+        \\    push byte -1
+        \\    add q1, q1, (2 * 3) | 1
+        \\    mov dd0, 1.5
+        \\    inc q2
+        \\    times 2 nop
+        \\    hlt
+    );
+    defer res.deinit(testing.allocator);
+
+    var written: std.Io.Writer.Allocating = .init(testing.allocator);
+    defer written.deinit();
+    try ast_json.writeStatements(res.stmts, res.interner, &written.writer);
+
+    var arena = std.heap.ArenaAllocator.init(testing.allocator);
+    defer arena.deinit();
+    var interner = StringInterner.init(testing.allocator);
+    defer interner.deinit();
+    const stmts = try ast_json.readStatements(arena.allocator(), &interner, written.written());
+    try testing.expectEqual(res.stmts.len, stmts.len);
+
+    var rewritten: std.Io.Writer.Allocating = .init(testing.allocator);
+    defer rewritten.deinit();
+    try ast_json.writeStatements(stmts, &interner, &rewritten.writer);
+    try testing.expectEqualStrings(written.written(), rewritten.written());
+
+    const rejected = [_][]const u8{
+        "{}",
+        "[{\"kind\": \"jump\"}]",
+        "[{\"kind\": \"hlt\"}]",
+        "[{\"kind\": \"inc\", \"expr\": {\"kind\": \"register\", \"value\": \"q99\"}, \"amount\": null, \"span\": {\"file\": \"a\", \"start\": 0, \"end\": 1}}]",
+        "not json",
+    };
+    for (rejected) |text| {
+        try testing.expectError(error.InvalidAst, ast_json.readStatements(arena.allocator(), &interner, text));
+    }
+}
+
+test "statements are written as ron" {
+    var res = try parse(testing.allocator, "loop:\nmov q0, 1\nmov dd0, 2.0\njmp loop");
+    defer res.deinit(testing.allocator);
+
+    var output: std.Io.Writer.Allocating = .init(testing.allocator);
+    defer output.deinit();
+    try ast_ron.writeStatements(res.stmts, res.interner, &output.writer);
+
+    const text = output.written();
+    try testing.expect(std.mem.startsWith(u8, text, "[\n    label(name: \"loop\", span: (file: \"test.nyx\", start: "));
+    try testing.expect(std.mem.endsWith(u8, text, "),\n]\n"));
+    try testing.expectEqual(@as(usize, 6), std.mem.count(u8, text, "\n"));
+    try testing.expect(std.mem.indexOf(u8, text, "    mov(data_size: None, expr1: register(q0), expr2: integer_literal(1), span: (file: ") != null);
+    try testing.expect(std.mem.indexOf(u8, text, "expr2: float_literal(2.0)") != null);
+    try testing.expect(std.mem.indexOf(u8, text, "identifier(\"loop\")") != null);
+}
+
+test "bad string escapes are reported" {
+    for ([_][]const u8{ "db \"\\q\"", ".asciz \"a\\x\"", "db \"\\u00\"" }) |input| {
+        var reporter = fehler.ErrorReporter.init(testing.allocator);
+        defer reporter.deinit();
+        try reporter.addSource("test.nyx", input);
+        var interner = StringInterner.init(testing.allocator);
+        defer interner.deinit();
+        var lexer = Lexer.init("test.nyx", input, &interner, testing.allocator);
+        var parser = Parser.init(&lexer, &reporter, testing.allocator);
+        defer parser.deinit();
+        parser.exit_on_error = false;
+
+        try testing.expectError(error.ParserError, parser.parse());
+        try testing.expectEqual(@as(usize, 1), parser.error_count);
+    }
+}
+
+test "#line renumbers the lines after it" {
+    const input = "nop\n#line 42 \"gen.src\"\nnop\n  nop\n#line 7\nnop";
+    var res = try parse(testing.allocator, input);
+    defer res.deinit(testing.allocator);
+
+    try testing.expectEqual(@as(usize, 4), res.stmts.len);
+    const expected = [_]struct { file: []const u8, line: usize, column: usize }{
+        .{ .file = "test.nyx", .line = 1, .column = 1 },
+        .{ .file = "gen.src", .line = 42, .column = 1 },
+        .{ .file = "gen.src", .line = 43, .column = 3 },
+        .{ .file = "gen.src", .line = 7, .column = 1 },
+    };
+    for (res.stmts, expected) |stmt, want| {
+        const range = stmt.span().toSourceRange(input);
+        try testing.expectEqualStrings(want.file, range.file);
+        try testing.expectEqual(want.line, range.start.line);
+        try testing.expectEqual(want.column, range.start.column);
+    }
+
+    for ([_][]const u8{ "#line\nnop", "#line 0\nnop", "#line x\nnop", "#line 5 gen.src\nnop" }) |bad| {
+        var reporter = fehler.ErrorReporter.init(testing.allocator);
+        defer reporter.deinit();
+        try reporter.addSource("test.nyx", bad);
+        var interner = StringInterner.init(testing.allocator);
+        defer interner.deinit();
+        var lexer = Lexer.init("test.nyx", bad, &interner, testing.allocator);
+        var parser = Parser.init(&lexer, &reporter, testing.allocator);
+        defer parser.deinit();
+        parser.exit_on_error = false;
+
+        try testing.expectError(error.ParserError, parser.parse());
+    }
+}
@@ -10,6 +10,7 @@ const Parser = @import("../parser/Parser.zig");
 const Span = @import("../Span.zig");
 const ast = @import("../parser/ast.zig");
 const utils = @import("../utils.zig");
+const warning = @import("../warning.zig");
 const defaults = @import("defaults.zig");
 
 const Preprocessor = @This();
@@ -26,6 +27,28 @@ const ConditionalInfo = struct {
     span: Span,
 };
 
+/// A definition given on the command line with `-D NAME=VALUE`, applied
+/// before the first line is processed.
+pub const Define = struct {
+    name: []const u8,
+    /// Read the same way as `#define_env` values: an integer when it parses as
+    /// one, otherwise a string. Null defines the name without a value, like
+    /// `#define NAME`.
+    value: ?[]const u8,
+
+    /// Split `NAME=VALUE` or `NAME`. Returns null when NAME is not an
+    /// identifier.
+    pub fn parse(text: []const u8) ?Define {
+        const eq = std.mem.indexOfScalar(u8, text, '=');
+        const name = text[0 .. eq orelse text.len];
+        if (name.len == 0 or std.ascii.isDigit(name[0])) return null;
+        for (name) |ch| {
+            if (!std.ascii.isAlphanumeric(ch) and ch != '_' and ch != '$') return null;
+        }
+        return .{ .name = name, .value = if (eq) |i| text[i + 1 ..] else null };
+    }
+};
+
 const MacroInfo = struct {
     params: []StringId,
     body: []ast.Statement,
@@ -33,18 +56,37 @@ const MacroInfo = struct {
 };
 
 io: std.Io,
+/// Host environment for `#define_env`, or null to treat every variable as
+/// unset.
+env: ?std.process.Environ,
 filename: []const u8,
 input: []const u8,
 program: []ast.Statement,
 interner: *StringInterner,
 definitions: std.AutoHashMap(StringId, ?*ast.Expression),
 macros: std.AutoHashMap(StringId, MacroInfo),
+/// Number of macro expansions so far, used to give the labels of every
+/// expansion a unique name. Shared with included files.
+macro_expansions: usize,
 include_paths: ArrayList([]const u8),
+/// Files being preprocessed, from the one given to `init` down to this one,
+/// so a file that includes itself is told apart from one included twice.
+include_chain: ArrayList([]const u8),
+/// Files marked `#pragma once`, which later includes skip. Shared with
+/// included files.
+once_files: std.StringHashMap(void),
 reporter: *fehler.ErrorReporter,
 arena: std.heap.ArenaAllocator,
+/// Exit the process on the first error. Library callers turn this off and
+/// handle `error.PreProcessorError` themselves.
+exit_on_error: bool,
+/// Which `#warning` directives are reported. Denied warnings stop
+/// preprocessing like `#error`.
+warnings: warning.Policy,
 
 pub fn init(
     io: std.Io,
+    env: ?std.process.Environ,
     gpa: Allocator,
     filename: []const u8,
     input: []const u8,
@@ -52,6 +94,7 @@ pub fn init(
     interner: *StringInterner,
     reporter: *fehler.ErrorReporter,
     include_paths: ?[][]const u8,
+    defines: []const Define,
 ) !Preprocessor {
     var default_definitions = try defaults.getDefaultDefinitions(gpa, interner);
     defer default_definitions.deinit();
@@ -73,20 +116,39 @@ pub fn init(
         gpa.destroy(def.value_ptr.*);
     }
 
+    for (defines) |define| {
+        const value: ?*ast.Expression = if (define.value) |text| blk: {
+            const expr = try arena.allocator().create(ast.Expression);
+            expr.* = try valueExpression(interner, text);
+            break :blk expr;
+        } else null;
+        try definitions.put(try interner.intern(define.name), value);
+    }
+
+    var include_chain = ArrayList([]const u8).init(gpa);
+    errdefer include_chain.deinit();
+    try include_chain.append(try fs.path.resolve(arena.allocator(), &.{filename}));
+
     return Preprocessor{
         .io = io,
+        .env = env,
         .filename = filename,
         .input = input,
         .program = program,
         .interner = interner,
         .definitions = definitions,
         .macros = std.AutoHashMap(StringId, MacroInfo).init(gpa),
+        .macro_expansions = 0,
         .include_paths = if (include_paths) |paths|
             ArrayList([]const u8).fromOwnedSlice(gpa, paths)
         else
             ArrayList([]const u8).init(gpa),
+        .include_chain = include_chain,
+        .once_files = std.StringHashMap(void).init(gpa),
         .reporter = reporter,
         .arena = arena,
+        .exit_on_error = true,
+        .warnings = .{},
     };
 }
 
@@ -94,6 +156,8 @@ pub fn deinit(self: *Preprocessor) void {
     self.definitions.deinit();
     self.macros.deinit();
     self.include_paths.deinit();
+    self.include_chain.deinit();
+    self.once_files.deinit();
     self.arena.deinit();
 }
 
@@ -111,9 +175,14 @@ pub fn process(self: *Preprocessor) ![]ast.Statement {
                 };
                 const file_path = self.interner.get(file_path_id) orelse
                     return self.reportError("invalid include path", v.span);
-                const included_statements = try self.processInclude(file_path, v.span);
-                try processed_statements.appendSlice(included_statements);
+                if (std.mem.endsWith(u8, file_path, "/*")) {
+                    try self.processDirectoryInclude(file_path[0 .. file_path.len - 2], v.span, &processed_statements);
+                } else {
+                    const included_statements = try self.processInclude(file_path, v.span);
+                    try processed_statements.appendSlice(included_statements);
+                }
             },
+            .pragma_once => try self.once_files.put(self.include_chain.getLast(), {}),
             else => try processed_statements.append(stmt),
         }
     }
@@ -132,6 +201,13 @@ pub fn process(self: *Preprocessor) ![]ast.Statement {
                 };
                 try self.definitions.put(name_id, v.expr);
             },
+            .define_env => |v| {
+                const name_id = switch (v.name.*) {
+                    .identifier => |ident_id| ident_id,
+                    else => return self.reportError("invalid define key", v.span),
+                };
+                try self.definitions.put(name_id, try self.lookupEnv(v));
+            },
             .macro_def => |v| {
                 try self.macros.put(v.name, .{
                     .params = v.params,
@@ -182,6 +258,27 @@ fn expandMacro(self: *Preprocessor, call: ast.Statement.MacroCall) ![]ast.Statem
         try param_map.put(param_id, substituted_arg);
     }
 
+    // Labels defined in the body are renamed for every expansion, so a macro
+    // with a loop can be used more than once. `@` cannot appear in a source
+    // identifier, which keeps the new names from clashing with user labels.
+    self.macro_expansions += 1;
+    const macro_name = self.interner.get(call.name).?;
+    for (macro_info.body) |body_stmt| {
+        switch (body_stmt) {
+            .label => |v| {
+                if (param_map.contains(v.name)) continue;
+                const unique = try std.fmt.allocPrint(
+                    arena_alloc,
+                    "{s}@{s}.{d}",
+                    .{ self.interner.get(v.name).?, macro_name, self.macro_expansions },
+                );
+                const unique_id = try self.interner.intern(unique);
+                try param_map.put(v.name, try self.createExpr(.{ .identifier = unique_id }));
+            },
+            else => {},
+        }
+    }
+
     var expanded = try ArrayList(ast.Statement).initCapacity(arena_alloc, macro_info.body.len);
 
     for (macro_info.body) |body_stmt| {
@@ -201,18 +298,38 @@ fn substituteStatement(self: *Preprocessor, stmt: ast.Statement, param_map: *std
     const arena_alloc = self.arena.allocator();
 
     return switch (stmt) {
-        .label, .section, .nop, .ret, .syscall, .hlt, .@"else", .endif => stmt,
+        .label => |v| if (param_map.get(v.name)) |renamed| switch (renamed.*) {
+            .identifier => |id| .{ .label = .{ .name = id, .span = v.span } },
+            else => stmt,
+        } else stmt,
+        .section, .requires, .nop, .ret, .leave, .syscall, .hlt, .@"else", .endif, .include_start, .include_end => stmt,
         .@"error" => |v| .{ .@"error" = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
+        .warning => |v| .{ .warning = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
         .define => |v| .{ .define = .{
             .name = try self.substituteExprWithParams(v.name, param_map),
             .expr = if (v.expr) |expr| try self.substituteExprWithParams(expr, param_map) else null,
             .span = v.span,
         } },
-        .include, .ifdef, .ifndef => null,
+        .define_env => stmt,
+        .include, .pragma_once, .ifdef, .ifndef => null,
         .entry => |v| .{ .entry = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
+        .@"align" => |v| .{ .@"align" = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
         .ascii => |v| .{ .ascii = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
         .asciz => |v| .{ .asciz = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
         .@"extern" => |v| .{ .@"extern" = .{ .name = try self.substituteExprWithParams(v.name, param_map), .param_types = v.param_types, .return_type = v.return_type, .is_variadic = v.is_variadic, .span = v.span } },
+        .equ => |v| .{ .equ = .{
+            .name = if (param_map.get(v.name)) |renamed| switch (renamed.*) {
+                .identifier => |id| id,
+                else => v.name,
+            } else v.name,
+            .expr = try self.substituteExprWithParams(v.expr, param_map),
+            .span = v.span,
+        } },
+        .times => |v| .{ .times = .{
+            .count = try self.substituteExprWithParams(v.count, param_map),
+            .stmt = try self.createStatement(try self.substituteStatement(v.stmt.*, param_map) orelse return null),
+            .span = v.span,
+        } },
         .jmp => |v| .{ .jmp = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
         .jeq => |v| .{ .jeq = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
         .jne => |v| .{ .jne = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
@@ -220,11 +337,32 @@ fn substituteStatement(self: *Preprocessor, stmt: ast.Statement, param_map: *std
         .jgt => |v| .{ .jgt = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
         .jle => |v| .{ .jle = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
         .jge => |v| .{ .jge = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
+        .seteq => |v| .{ .seteq = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
+        .setne => |v| .{ .setne = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
+        .setlt => |v| .{ .setlt = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
+        .setgt => |v| .{ .setgt = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
+        .setle => |v| .{ .setle = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
+        .setge => |v| .{ .setge = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
+        .setc => |v| .{ .setc = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
+        .seto => |v| .{ .seto = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
         .call => |v| .{ .call = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
+        .enter => |v| .{ .enter = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
         .call_variadic => |v| .{ .call_variadic = .{ .name = try self.substituteExprWithParams(v.name, param_map), .variadic_types = v.variadic_types, .span = v.span } },
-        .inc => |v| .{ .inc = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
-        .dec => |v| .{ .dec = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
+        .inc => |v| .{ .inc = .{
+            .expr = try self.substituteExprWithParams(v.expr, param_map),
+            .amount = if (v.amount) |amount| try self.substituteExprWithParams(amount, param_map) else null,
+            .span = v.span,
+        } },
+        .dec => |v| .{ .dec = .{
+            .expr = try self.substituteExprWithParams(v.expr, param_map),
+            .amount = if (v.amount) |amount| try self.substituteExprWithParams(amount, param_map) else null,
+            .span = v.span,
+        } },
         .neg => |v| .{ .neg = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
+        .cvtif => |v| .{ .cvtif = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .span = v.span } },
+        .cvtfi => |v| .{ .cvtfi = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .span = v.span } },
+        .cvtfd => |v| .{ .cvtfd = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .span = v.span } },
+        .cvtdf => |v| .{ .cvtdf = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .span = v.span } },
         .mov => |v| .{ .mov = .{
             .data_size = if (v.data_size) |size| try self.substituteExprWithParams(size, param_map) else null,
             .expr1 = try self.substituteExprWithParams(v.expr1, param_map),
@@ -232,6 +370,8 @@ fn substituteStatement(self: *Preprocessor, stmt: ast.Statement, param_map: *std
             .span = v.span,
         } },
         .cmp => |v| .{ .cmp = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .span = v.span } },
+        .scmp => |v| .{ .scmp = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .span = v.span } },
+        .schr => |v| .{ .schr = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .expr3 = try self.substituteExprWithParams(v.expr3, param_map), .span = v.span } },
         .push => |v| .{ .push = .{
             .data_size = if (v.data_size) |size| try self.substituteExprWithParams(size, param_map) else null,
             .expr = try self.substituteExprWithParams(v.expr, param_map),
@@ -243,16 +383,35 @@ fn substituteStatement(self: *Preprocessor, stmt: ast.Statement, param_map: *std
             .span = v.span,
         } },
         .add => |v| .{ .add = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .expr3 = try self.substituteExprWithParams(v.expr3, param_map), .span = v.span } },
+        .adc => |v| .{ .adc = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .expr3 = try self.substituteExprWithParams(v.expr3, param_map), .span = v.span } },
         .sub => |v| .{ .sub = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .expr3 = try self.substituteExprWithParams(v.expr3, param_map), .span = v.span } },
+        .sbb => |v| .{ .sbb = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .expr3 = try self.substituteExprWithParams(v.expr3, param_map), .span = v.span } },
         .mul => |v| .{ .mul = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .expr3 = try self.substituteExprWithParams(v.expr3, param_map), .span = v.span } },
         .div => |v| .{ .div = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .expr3 = try self.substituteExprWithParams(v.expr3, param_map), .span = v.span } },
+        .imul => |v| .{ .imul = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .expr3 = try self.substituteExprWithParams(v.expr3, param_map), .span = v.span } },
+        .idiv => |v| .{ .idiv = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .expr3 = try self.substituteExprWithParams(v.expr3, param_map), .span = v.span } },
+        .imod => |v| .{ .imod = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .expr3 = try self.substituteExprWithParams(v.expr3, param_map), .span = v.span } },
         .@"and" => |v| .{ .@"and" = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .expr3 = try self.substituteExprWithParams(v.expr3, param_map), .span = v.span } },
         .@"or" => |v| .{ .@"or" = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .expr3 = try self.substituteExprWithParams(v.expr3, param_map), .span = v.span } },
         .xor => |v| .{ .xor = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .expr3 = try self.substituteExprWithParams(v.expr3, param_map), .span = v.span } },
+        .not => |v| .{ .not = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .span = v.span } },
+        .movz => |v| .{ .movz = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .span = v.span } },
+        .movs => |v| .{ .movs = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .span = v.span } },
         .shl => |v| .{ .shl = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .expr3 = try self.substituteExprWithParams(v.expr3, param_map), .span = v.span } },
         .shr => |v| .{ .shr = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .expr3 = try self.substituteExprWithParams(v.expr3, param_map), .span = v.span } },
         .rol => |v| .{ .rol = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .expr3 = try self.substituteExprWithParams(v.expr3, param_map), .span = v.span } },
         .ror => |v| .{ .ror = .{ .expr1 = try self.substituteExprWithParams(v.expr1, param_map), .expr2 = try self.substituteExprWithParams(v.expr2, param_map), .expr3 = try self.substituteExprWithParams(v.expr3, param_map), .span = v.span } },
+        .ext => |v| .{ .ext = .{
+            .subcode = try self.substituteExprWithParams(v.subcode, param_map),
+            .operands = blk: {
+                var new_exprs = try ArrayList(*ast.Expression).initCapacity(arena_alloc, v.operands.len);
+                for (v.operands) |expr| {
+                    new_exprs.appendAssumeCapacity(try self.substituteExprWithParams(expr, param_map));
+                }
+                break :blk try new_exprs.toOwnedSlice();
+            },
+            .span = v.span,
+        } },
         .db => |v| .{ .db = .{
             .exprs = blk: {
                 var new_exprs = try ArrayList(*ast.Expression).initCapacity(arena_alloc, v.exprs.len);
@@ -293,6 +452,26 @@ fn substituteStatement(self: *Preprocessor, stmt: ast.Statement, param_map: *std
             },
             .span = v.span,
         } },
+        .df => |v| .{ .df = .{
+            .exprs = blk: {
+                var new_exprs = try ArrayList(*ast.Expression).initCapacity(arena_alloc, v.exprs.len);
+                for (v.exprs) |expr| {
+                    new_exprs.appendAssumeCapacity(try self.substituteExprWithParams(expr, param_map));
+                }
+                break :blk try new_exprs.toOwnedSlice();
+            },
+            .span = v.span,
+        } },
+        .ddbl => |v| .{ .ddbl = .{
+            .exprs = blk: {
+                var new_exprs = try ArrayList(*ast.Expression).initCapacity(arena_alloc, v.exprs.len);
+                for (v.exprs) |expr| {
+                    new_exprs.appendAssumeCapacity(try self.substituteExprWithParams(expr, param_map));
+                }
+                break :blk try new_exprs.toOwnedSlice();
+            },
+            .span = v.span,
+        } },
         .resb => |v| .{ .resb = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
         .resw => |v| .{ .resw = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
         .resd => |v| .{ .resd = .{ .expr = try self.substituteExprWithParams(v.expr, param_map), .span = v.span } },
@@ -323,7 +502,7 @@ fn substituteExprWithParams(self: *Preprocessor, expr: *ast.Expression, param_ma
                 null;
             break :blk try self.createExpr(.{ .address = .{ .base = new_base, .offset = new_offset } });
         },
-        .register, .integer_literal, .float_literal, .string_literal, .data_size => expr,
+        .register, .integer_literal, .float_literal, .string_literal, .data_size, .sized_literal => expr,
         .unary_op => |v| blk: {
             const inner = try self.substituteExprWithParams(v.expr, param_map);
             break :blk try self.createExpr(.{ .unary_op = .{ .op = v.op, .expr = inner, .span = v.span } });
@@ -340,7 +519,7 @@ fn processStatement(self: *Preprocessor, stmt: ast.Statement) !?ast.Statement {
     const arena_alloc = self.arena.allocator();
 
     return switch (stmt) {
-        .label, .section, .nop, .ret, .syscall, .hlt => stmt,
+        .label, .section, .requires, .nop, .ret, .leave, .syscall, .hlt, .include_start, .include_end => stmt,
         .@"error" => |v| switch (v.expr.*) {
             .string_literal => |message_id| {
                 const message = self.interner.get(message_id) orelse
@@ -349,16 +528,35 @@ fn processStatement(self: *Preprocessor, stmt: ast.Statement) !?ast.Statement {
             },
             else => return self.reportError("expected string literal in #error directive", v.span),
         },
+        .warning => |v| switch (v.expr.*) {
+            .string_literal => |message_id| {
+                const message = self.interner.get(message_id) orelse
+                    return self.reportError("invalid warning message", v.span);
+                if (self.warnings.report(self.reporter, .directive, message, v.span) and self.warnings.deny) {
+                    if (self.exit_on_error) std.process.exit(1);
+                    return error.PreProcessorError;
+                }
+                return null;
+            },
+            else => return self.reportError("expected string literal in #warning directive", v.span),
+        },
         .define => |v| .{ .define = .{
             .name = try self.substituteExpr(v.name),
             .expr = if (v.expr) |expr| try self.substituteExpr(expr) else null,
             .span = v.span,
         } },
-        .include, .ifdef, .ifndef, .@"else", .endif => null,
+        .define_env, .include, .pragma_once, .ifdef, .ifndef, .@"else", .endif => null,
         .entry => |v| .{ .entry = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
+        .@"align" => |v| .{ .@"align" = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
         .ascii => |v| .{ .ascii = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
         .asciz => |v| .{ .asciz = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
         .@"extern" => |v| .{ .@"extern" = .{ .name = try self.substituteExpr(v.name), .param_types = v.param_types, .return_type = v.return_type, .is_variadic = v.is_variadic, .span = v.span } },
+        .equ => |v| .{ .equ = .{ .name = v.name, .expr = try self.substituteExpr(v.expr), .span = v.span } },
+        .times => |v| .{ .times = .{
+            .count = try self.substituteExpr(v.count),
+            .stmt = try self.createStatement(try self.processStatement(v.stmt.*) orelse return null),
+            .span = v.span,
+        } },
         .jmp => |v| .{ .jmp = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
         .jeq => |v| .{ .jeq = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
         .jne => |v| .{ .jne = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
@@ -366,11 +564,32 @@ fn processStatement(self: *Preprocessor, stmt: ast.Statement) !?ast.Statement {
         .jgt => |v| .{ .jgt = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
         .jle => |v| .{ .jle = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
         .jge => |v| .{ .jge = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
+        .seteq => |v| .{ .seteq = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
+        .setne => |v| .{ .setne = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
+        .setlt => |v| .{ .setlt = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
+        .setgt => |v| .{ .setgt = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
+        .setle => |v| .{ .setle = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
+        .setge => |v| .{ .setge = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
+        .setc => |v| .{ .setc = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
+        .seto => |v| .{ .seto = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
         .call => |v| .{ .call = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
+        .enter => |v| .{ .enter = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
         .call_variadic => |v| .{ .call_variadic = .{ .name = try self.substituteExpr(v.name), .variadic_types = v.variadic_types, .span = v.span } },
-        .inc => |v| .{ .inc = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
-        .dec => |v| .{ .dec = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
+        .inc => |v| .{ .inc = .{
+            .expr = try self.substituteExpr(v.expr),
+            .amount = if (v.amount) |amount| try self.substituteExpr(amount) else null,
+            .span = v.span,
+        } },
+        .dec => |v| .{ .dec = .{
+            .expr = try self.substituteExpr(v.expr),
+            .amount = if (v.amount) |amount| try self.substituteExpr(amount) else null,
+            .span = v.span,
+        } },
         .neg => |v| .{ .neg = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
+        .cvtif => |v| .{ .cvtif = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .span = v.span } },
+        .cvtfi => |v| .{ .cvtfi = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .span = v.span } },
+        .cvtfd => |v| .{ .cvtfd = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .span = v.span } },
+        .cvtdf => |v| .{ .cvtdf = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .span = v.span } },
         .mov => |v| .{ .mov = .{
             .data_size = if (v.data_size) |size| try self.substituteExpr(size) else null,
             .expr1 = try self.substituteExpr(v.expr1),
@@ -378,6 +597,8 @@ fn processStatement(self: *Preprocessor, stmt: ast.Statement) !?ast.Statement {
             .span = v.span,
         } },
         .cmp => |v| .{ .cmp = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .span = v.span } },
+        .scmp => |v| .{ .scmp = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .span = v.span } },
+        .schr => |v| .{ .schr = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .expr3 = try self.substituteExpr(v.expr3), .span = v.span } },
         .push => |v| .{ .push = .{
             .data_size = if (v.data_size) |size| try self.substituteExpr(size) else null,
             .expr = try self.substituteExpr(v.expr),
@@ -389,16 +610,35 @@ fn processStatement(self: *Preprocessor, stmt: ast.Statement) !?ast.Statement {
             .span = v.span,
         } },
         .add => |v| .{ .add = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .expr3 = try self.substituteExpr(v.expr3), .span = v.span } },
+        .adc => |v| .{ .adc = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .expr3 = try self.substituteExpr(v.expr3), .span = v.span } },
         .sub => |v| .{ .sub = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .expr3 = try self.substituteExpr(v.expr3), .span = v.span } },
+        .sbb => |v| .{ .sbb = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .expr3 = try self.substituteExpr(v.expr3), .span = v.span } },
         .mul => |v| .{ .mul = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .expr3 = try self.substituteExpr(v.expr3), .span = v.span } },
         .div => |v| .{ .div = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .expr3 = try self.substituteExpr(v.expr3), .span = v.span } },
+        .imul => |v| .{ .imul = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .expr3 = try self.substituteExpr(v.expr3), .span = v.span } },
+        .idiv => |v| .{ .idiv = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .expr3 = try self.substituteExpr(v.expr3), .span = v.span } },
+        .imod => |v| .{ .imod = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .expr3 = try self.substituteExpr(v.expr3), .span = v.span } },
         .@"and" => |v| .{ .@"and" = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .expr3 = try self.substituteExpr(v.expr3), .span = v.span } },
         .@"or" => |v| .{ .@"or" = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .expr3 = try self.substituteExpr(v.expr3), .span = v.span } },
         .xor => |v| .{ .xor = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .expr3 = try self.substituteExpr(v.expr3), .span = v.span } },
+        .not => |v| .{ .not = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .span = v.span } },
+        .movz => |v| .{ .movz = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .span = v.span } },
+        .movs => |v| .{ .movs = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .span = v.span } },
         .shl => |v| .{ .shl = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .expr3 = try self.substituteExpr(v.expr3), .span = v.span } },
         .shr => |v| .{ .shr = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .expr3 = try self.substituteExpr(v.expr3), .span = v.span } },
         .rol => |v| .{ .rol = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .expr3 = try self.substituteExpr(v.expr3), .span = v.span } },
         .ror => |v| .{ .ror = .{ .expr1 = try self.substituteExpr(v.expr1), .expr2 = try self.substituteExpr(v.expr2), .expr3 = try self.substituteExpr(v.expr3), .span = v.span } },
+        .ext => |v| .{ .ext = .{
+            .subcode = try self.substituteExpr(v.subcode),
+            .operands = blk: {
+                var new_exprs = try ArrayList(*ast.Expression).initCapacity(arena_alloc, v.operands.len);
+                for (v.operands) |expr| {
+                    new_exprs.appendAssumeCapacity(try self.substituteExpr(expr));
+                }
+                break :blk try new_exprs.toOwnedSlice();
+            },
+            .span = v.span,
+        } },
         .db => |v| .{ .db = .{
             .exprs = blk: {
                 var new_exprs = try ArrayList(*ast.Expression).initCapacity(arena_alloc, v.exprs.len);
@@ -439,6 +679,26 @@ fn processStatement(self: *Preprocessor, stmt: ast.Statement) !?ast.Statement {
             },
             .span = v.span,
         } },
+        .df => |v| .{ .df = .{
+            .exprs = blk: {
+                var new_exprs = try ArrayList(*ast.Expression).initCapacity(arena_alloc, v.exprs.len);
+                for (v.exprs) |expr| {
+                    new_exprs.appendAssumeCapacity(try self.substituteExpr(expr));
+                }
+                break :blk try new_exprs.toOwnedSlice();
+            },
+            .span = v.span,
+        } },
+        .ddbl => |v| .{ .ddbl = .{
+            .exprs = blk: {
+                var new_exprs = try ArrayList(*ast.Expression).initCapacity(arena_alloc, v.exprs.len);
+                for (v.exprs) |expr| {
+                    new_exprs.appendAssumeCapacity(try self.substituteExpr(expr));
+                }
+                break :blk try new_exprs.toOwnedSlice();
+            },
+            .span = v.span,
+        } },
         .resb => |v| .{ .resb = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
         .resw => |v| .{ .resw = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
         .resd => |v| .{ .resd = .{ .expr = try self.substituteExpr(v.expr), .span = v.span } },
@@ -448,18 +708,58 @@ fn processStatement(self: *Preprocessor, stmt: ast.Statement) !?ast.Statement {
     };
 }
 
+/// Search the include paths for `file_path`, which may name a file or a
+/// directory.
+fn findInclude(self: *Preprocessor, file_path: []const u8) !?[]const u8 {
+    for (self.include_paths.items) |include_dir| {
+        const candidate = try fs.path.join(self.arena.allocator(), &.{ include_dir, file_path });
+        if (utils.fileExists(self.io, candidate)) return candidate;
+    }
+    return null;
+}
+
 fn processInclude(self: *Preprocessor, file_path: []const u8, span: Span) anyerror![]ast.Statement {
+    const path = try self.findInclude(file_path) orelse return self.reportError("include file not found", span);
+    return self.includeFile(path, span);
+}
+
+/// `#include "dir/*"`: include every `.nyx` file in `dir`, in name order.
+/// Only the first include path that has the directory is used.
+fn processDirectoryInclude(
+    self: *Preprocessor,
+    dir_path: []const u8,
+    span: Span,
+    statements: *ArrayList(ast.Statement),
+) anyerror!void {
     const arena_alloc = self.arena.allocator();
 
-    var found_path: ?[]const u8 = null;
-    for (self.include_paths.items) |include_dir| {
-        const candidate = try fs.path.join(arena_alloc, &.{ include_dir, file_path });
-        if (!utils.fileExists(self.io, candidate)) continue;
-        found_path = candidate;
-        break;
+    const dir = try self.findInclude(dir_path) orelse return self.reportError("include directory not found", span);
+    const names = utils.listFiles(self.io, arena_alloc, dir, ".nyx") catch
+        return self.reportError("include directory could not be read", span);
+
+    for (names) |name| {
+        const path = try fs.path.join(arena_alloc, &.{ dir, name });
+        try statements.appendSlice(try self.includeFile(path, span));
     }
+}
+
+/// Preprocess the file at `path` on its own and return its statements between
+/// an `include_start` and an `include_end`. A file marked `#pragma once` that
+/// was already included gives no statements, and one that is still being
+/// preprocessed further up the chain is an error.
+fn includeFile(self: *Preprocessor, path: []const u8, span: Span) anyerror![]ast.Statement {
+    const arena_alloc = self.arena.allocator();
 
-    const path = found_path orelse return self.reportError("include file not found", span);
+    const resolved = try fs.path.resolve(arena_alloc, &.{path});
+    if (self.once_files.contains(resolved)) return &.{};
+    for (self.include_chain.items) |open| {
+        if (!std.mem.eql(u8, open, resolved)) continue;
+        var cycle: std.Io.Writer.Allocating = .init(arena_alloc);
+        for (self.include_chain.items) |file| try cycle.writer.print("{s} -> ", .{file});
+        try cycle.writer.writeAll(resolved);
+        const msg = try std.fmt.allocPrint(arena_alloc, "circular include: {s}", .{cycle.written()});
+        return self.reportError(msg, span);
+    }
 
     const content = try utils.readFromFile(self.io, arena_alloc, path);
     try self.reporter.addSource(path, content);
@@ -468,23 +768,33 @@ fn processInclude(self: *Preprocessor, file_path: []const u8, span: Span) anyerr
 
     var sub_preprocessor = Preprocessor{
         .io = self.io,
+        .env = self.env,
         .filename = path,
         .input = content,
         .program = included_statements,
         .interner = self.interner,
         .definitions = try self.definitions.clone(),
         .macros = try self.macros.clone(),
+        .macro_expansions = self.macro_expansions,
         .include_paths = try self.include_paths.clone(),
+        .include_chain = try self.include_chain.clone(),
+        .once_files = try self.once_files.clone(),
         .reporter = self.reporter,
         .arena = std.heap.ArenaAllocator.init(arena_alloc),
+        .exit_on_error = self.exit_on_error,
+        .warnings = self.warnings,
     };
     defer {
         sub_preprocessor.definitions.deinit();
         sub_preprocessor.macros.deinit();
         sub_preprocessor.include_paths.deinit();
+        sub_preprocessor.include_chain.deinit();
+        sub_preprocessor.once_files.deinit();
     }
+    try sub_preprocessor.include_chain.append(resolved);
 
     const processed = try sub_preprocessor.process();
+    self.macro_expansions = sub_preprocessor.macro_expansions;
 
     var definitions_iter = sub_preprocessor.definitions.iterator();
     while (definitions_iter.next()) |entry| {
@@ -496,12 +806,22 @@ fn processInclude(self: *Preprocessor, file_path: []const u8, span: Span) anyerr
         try self.macros.put(entry.key_ptr.*, entry.value_ptr.*);
     }
 
-    return processed;
+    var once_iter = sub_preprocessor.once_files.keyIterator();
+    while (once_iter.next()) |file| {
+        try self.once_files.put(file.*, {});
+    }
+
+    const wrapped = try arena_alloc.alloc(ast.Statement, processed.len + 2);
+    wrapped[0] = .{ .include_start = span };
+    @memcpy(wrapped[1 .. wrapped.len - 1], processed);
+    wrapped[wrapped.len - 1] = .{ .include_end = span };
+    return wrapped;
 }
 
 fn parseFileContent(self: *Preprocessor, content: []const u8, path: []const u8) ![]ast.Statement {
     var lexer = Lexer.init(path, content, self.interner, self.arena.allocator());
     var parser = Parser.init(&lexer, self.reporter, self.arena.allocator());
+    parser.exit_on_error = self.exit_on_error;
     return parser.parse();
 }
 
@@ -597,7 +917,7 @@ fn substituteExpr(self: *Preprocessor, expr: *ast.Expression) anyerror!*ast.Expr
                 null;
             break :blk try self.createExpr(.{ .address = .{ .base = new_base, .offset = new_offset } });
         },
-        .register, .integer_literal, .float_literal, .string_literal, .data_size => expr,
+        .register, .integer_literal, .float_literal, .string_literal, .data_size, .sized_literal => expr,
         .unary_op => |v| try self.evaluateUnaryOp(v),
         .binary_op => |v| try self.evaluateBinaryOp(v),
     };
@@ -607,22 +927,28 @@ fn evaluateUnaryOp(self: *Preprocessor, v: ast.Expression.UnaryOp) !*ast.Express
     const expr = try self.substituteExpr(v.expr);
     switch (expr.*) {
         .integer_literal => |int| {
-            const result = switch (v.op) {
-                .neg => blk: {
-                    if (int == std.math.minInt(i64)) {
-                        return self.reportError("integer overflow: cannot negate minimum value", v.span);
-                    }
-                    break :blk -int;
-                },
-            };
+            const result = v.op.apply(int, .qword, true) orelse
+                return self.reportError("integer overflow: cannot negate minimum value", v.span);
             return self.createExpr(.{ .integer_literal = result });
         },
         .float_literal => |float| {
             const result = switch (v.op) {
                 .neg => -float,
+                .bit_not => return self.reportError("cannot apply ~ to a float", v.span),
             };
             return self.createExpr(.{ .float_literal = result });
         },
+        .sized_literal => |sized| {
+            // `~` inverts only the bits of the literal's width.
+            const value = switch (sized.value.*) {
+                .integer_literal => |int| try self.createExpr(.{
+                    .integer_literal = v.op.apply(int, sized.size, sized.signed) orelse
+                        return self.reportError("integer overflow: cannot negate minimum value", v.span),
+                }),
+                else => try self.evaluateUnaryOp(.{ .expr = sized.value, .op = v.op, .span = v.span }),
+            };
+            return self.createExpr(.{ .sized_literal = .{ .value = value, .size = sized.size, .signed = sized.signed, .span = v.span } });
+        },
         else => {
             return self.reportError("cannot apply unary operator to non-literal expression", v.span);
         },
@@ -729,6 +1055,12 @@ inline fn createExpr(self: *Preprocessor, expr: ast.Expression) !*ast.Expression
     return new_expr;
 }
 
+inline fn createStatement(self: *Preprocessor, stmt: ast.Statement) !*ast.Statement {
+    const new_stmt = try self.arena.allocator().create(ast.Statement);
+    new_stmt.* = stmt;
+    return new_stmt;
+}
+
 fn report(
     self: *Preprocessor,
     severity: fehler.Severity,
@@ -736,14 +1068,38 @@ fn report(
     span: Span,
     status: ?u8,
 ) void {
-    const source = self.reporter.sources.get(span.filename).?;
-    self.reporter.report(.{
-        .severity = severity,
-        .message = message,
-        .range = span.toSourceRange(source),
-    });
+    span.report(self.reporter, severity, message);
     if (status) |code| {
-        std.process.exit(code);
+        if (self.exit_on_error) std.process.exit(code);
+    }
+}
+
+/// Resolve a `#define_env` to the value of its environment variable, falling
+/// back to the default expression. Values that parse as integers become
+/// integer literals; anything else becomes a string literal.
+fn lookupEnv(self: *Preprocessor, define: ast.Statement.DefineEnv) !*ast.Expression {
+    const arena_alloc = self.arena.allocator();
+    const env_name = self.interner.get(define.env).?;
+
+    const value = if (self.env) |env| env.getAlloc(arena_alloc, env_name) catch |err| switch (err) {
+        error.EnvironmentVariableMissing => null,
+        else => return err,
+    } else null;
+
+    if (value) |text| return self.createExpr(try valueExpression(self.interner, text));
+
+    if (define.default) |default| return self.substituteExpr(default);
+
+    const message = try std.fmt.allocPrint(arena_alloc, "environment variable is not set: {s}", .{env_name});
+    return self.reportError(message, define.span);
+}
+
+/// An integer literal when `text` parses as one, otherwise a string literal.
+fn valueExpression(interner: *StringInterner, text: []const u8) !ast.Expression {
+    if (std.fmt.parseInt(i64, text, 0)) |int| {
+        return .{ .integer_literal = int };
+    } else |_| {
+        return .{ .string_literal = try interner.intern(text) };
     }
 }
 
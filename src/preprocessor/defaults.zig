@@ -4,6 +4,7 @@ const Allocator = std.mem.Allocator;
 const StringInterner = @import("../StringInterner.zig");
 const StringId = StringInterner.StringId;
 const ast = @import("../parser/ast.zig");
+const syscall = @import("../vm/syscall.zig");
 
 pub fn getDefaultDefinitions(gpa: Allocator, interner: *StringInterner) !std.AutoHashMap(StringId, *ast.Expression) {
     const arch = switch (builtin.cpu.arch) {
@@ -126,5 +127,17 @@ pub fn getDefaultDefinitions(gpa: Allocator, interner: *StringInterner) !std.Aut
     try definitions.put(arch_id, arch_expr);
     try definitions.put(os_id, os_expr);
 
+    inline for (syscall.table) |entry| {
+        const name = comptime blk: {
+            var upper: [entry.name.len]u8 = undefined;
+            _ = std.ascii.upperString(&upper, entry.name);
+            const final = upper;
+            break :blk "SYS_" ++ final;
+        };
+        const expr = try gpa.create(ast.Expression);
+        expr.* = .{ .integer_literal = @intCast(entry.number) };
+        try definitions.put(try interner.intern(name), expr);
+    }
+
     return definitions;
 }
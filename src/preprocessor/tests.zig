@@ -0,0 +1,178 @@
+const std = @import("std");
+const testing = std.testing;
+const nyx = @import("../root.zig");
+
+fn run(source: []const u8) !nyx.Vm {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator, source, .{});
+    defer testing.allocator.free(bytecode);
+    var vm = try nyx.Vm.init(bytecode, 65536, &.{}, testing.allocator);
+    errdefer vm.deinit();
+    try vm.run();
+    return vm;
+}
+
+test "macro labels are unique per expansion" {
+    var vm = try run(
+        \\#macro count_down(reg, from)
+        \\    mov reg, from
+        \\loop:
+        \\    dec reg
+        \\    add q3, q3, 1
+        \\    cmp reg, 0
+        \\    jne loop
+        \\#endmacro
+        \\
+        \\_start:
+        \\    count_down q0, 3
+        \\    count_down q1, 4
+        \\    hlt
+    );
+    defer vm.deinit();
+
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q0).asU64());
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q1).asU64());
+    try testing.expectEqual(@as(u64, 7), vm.regs.get(.q3).asU64());
+}
+
+test "including a directory includes every file in it" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\#include "std/*"
+        \\
+        \\#ifndef NYX_NET
+        \\#error "socket.nyx was not included"
+        \\#endif
+        \\#ifndef NYX_STRING
+        \\#error "string.nyx was not included"
+        \\#endif
+        \\
+        \\_start:
+        \\    hlt
+    , .{ .include_paths = &.{"std"} });
+    defer testing.allocator.free(bytecode);
+
+    try testing.expectError(error.PreProcessorError, nyx.assemble(testing.io, testing.allocator, "#include \"no_such_dir/*\"\n", .{}));
+}
+
+test "#warning stops the build only when warnings are denied" {
+    const source =
+        \\#warning "this is a warning"
+        \\_start:
+        \\    hlt
+    ;
+    const bytecode = try nyx.assemble(testing.io, testing.allocator, source, .{});
+    testing.allocator.free(bytecode);
+
+    try testing.expectError(error.PreProcessorError, nyx.assemble(testing.io, testing.allocator, source, .{
+        .warnings = .{ .deny = true },
+    }));
+
+    var allowed: nyx.warning.Policy = .{ .deny = true };
+    allowed.allowed.insert(.directive);
+    const silenced = try nyx.assemble(testing.io, testing.allocator, source, .{ .warnings = allowed });
+    testing.allocator.free(silenced);
+}
+
+test "unary minus and ~ fold with two's complement per literal width" {
+    var vm = try run(
+        \\#define MASK 0x0F
+        \\_start:
+        \\    mov q0, -1
+        \\    mov b1, ~MASK
+        \\    mov b2, ~0u8
+        \\    mov d3, ~0xFFu32
+        \\    mov q4, -(2 + 3)
+        \\    mov w5, -~0
+        \\    hlt
+    );
+    defer vm.deinit();
+    try testing.expectEqual(@as(u64, std.math.maxInt(u64)), vm.regs.get(.q0).asU64());
+    try testing.expectEqual(@as(u8, 0xF0), vm.regs.get(.b1).asU8());
+    try testing.expectEqual(@as(u8, 0xFF), vm.regs.get(.b2).asU8());
+    try testing.expectEqual(@as(u32, 0xFFFFFF00), vm.regs.get(.d3).asU32());
+    try testing.expectEqual(@as(u64, @bitCast(@as(i64, -5))), vm.regs.get(.q4).asU64());
+    try testing.expectEqual(@as(u16, 1), vm.regs.get(.w5).asU16());
+
+    // ~0xFF is -256 in 64 bits, which a byte cannot hold.
+    try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator, "_start:\n    mov b0, ~0xFF\n    hlt\n", .{}));
+    try testing.expectError(error.PreProcessorError, nyx.assemble(testing.io, testing.allocator, "_start:\n    mov ff0, ~1.5\n    hlt\n", .{}));
+}
+
+test "a pragma once file reached through two includes is included once" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\#include "left.nyx"
+        \\#include "right.nyx"
+        \\
+        \\_start:
+        \\    mov q0, SHARED + LEFT + RIGHT
+        \\    hlt
+    , .{ .include_paths = &.{"src/preprocessor/testdata"} });
+    defer testing.allocator.free(bytecode);
+
+    var vm = try nyx.Vm.init(bytecode, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+    try testing.expectEqual(@as(u64, 10), vm.regs.get(.q0).asU64());
+}
+
+test "local labels are scoped per included file" {
+    // locals.nyx defines `helper.done`. The `.done` after the include is
+    // still under `_start`, not `helper`.
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\    call helper
+        \\    jmp .done
+        \\#include "locals.nyx"
+        \\.done:
+        \\    mov q0, 1
+        \\    hlt
+    , .{ .include_paths = &.{"src/preprocessor/testdata"} });
+    defer testing.allocator.free(bytecode);
+
+    var vm = try nyx.Vm.init(bytecode, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+    try testing.expectEqual(@as(u64, 1), vm.regs.get(.q0).asU64());
+    try testing.expectEqual(@as(u64, 5), vm.regs.get(.q1).asU64());
+
+    // An included file does not inherit the scope of the one including it.
+    try testing.expectError(error.CompilerError, nyx.assemble(testing.io, testing.allocator,
+        \\_start:
+        \\#include "orphan_local.nyx"
+        \\    hlt
+    , .{ .include_paths = &.{"src/preprocessor/testdata"} }));
+}
+
+test "a file that includes itself through another is an error" {
+    try testing.expectError(error.PreProcessorError, nyx.assemble(testing.io, testing.allocator,
+        \\#include "cycle_a.nyx"
+        \\
+        \\_start:
+        \\    hlt
+    , .{ .include_paths = &.{"src/preprocessor/testdata"} }));
+}
+
+test "command-line definitions are visible to the source" {
+    const bytecode = try nyx.assemble(testing.io, testing.allocator,
+        \\#ifndef DEBUG
+        \\#error "DEBUG was not defined"
+        \\#endif
+        \\
+        \\_start:
+        \\    mov q0, BUFSZ
+        \\    hlt
+    , .{ .defines = &.{
+        .{ .name = "DEBUG", .value = null },
+        .{ .name = "BUFSZ", .value = "4096" },
+    } });
+    defer testing.allocator.free(bytecode);
+
+    var vm = try nyx.Vm.init(bytecode, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+    try testing.expectEqual(@as(u64, 4096), vm.regs.get(.q0).asU64());
+
+    try testing.expectEqualDeep(nyx.Preprocessor.Define{ .name = "BUFSZ", .value = "0x10" }, nyx.Preprocessor.Define.parse("BUFSZ=0x10").?);
+    try testing.expectEqualDeep(nyx.Preprocessor.Define{ .name = "DEBUG", .value = null }, nyx.Preprocessor.Define.parse("DEBUG").?);
+    try testing.expectEqual(null, nyx.Preprocessor.Define.parse("1X=2"));
+    try testing.expectEqual(null, nyx.Preprocessor.Define.parse("=2"));
+}
@@ -0,0 +1,130 @@
+//! `nyx repl`: assemble and run one line at a time.
+//!
+//! Every instruction is assembled at the current `ip`, written into memory
+//! and executed until `ip` reaches the end of it, so a backward jump runs its
+//! loop to completion before the prompt comes back. A line that runs more
+//! than `max_steps` instructions is stopped, so a loop that never ends does
+//! not take the prompt with it. The registers and flags
+//! that changed are printed afterwards. Labels stay defined for later lines,
+//! but a label can only be used once it has been typed.
+
+const std = @import("std");
+const mem = std.mem;
+const Io = std.Io;
+const Allocator = mem.Allocator;
+const fehler = @import("fehler");
+const StringInterner = @import("../StringInterner.zig");
+const Lexer = @import("../lexer/Lexer.zig");
+const Parser = @import("../parser/Parser.zig");
+const Compiler = @import("../compiler/Compiler.zig");
+const ast = @import("../parser/ast.zig");
+const Opcode = @import("../compiler/opcode.zig").Opcode;
+const Vm = @import("../vm/Vm.zig");
+const LineEditor = @import("../LineEditor.zig");
+
+const Repl = @This();
+
+const filename = "<repl>";
+
+/// Bytecode for the VM to start from: an entry point of 0 and a `hlt` that
+/// the first line overwrites.
+pub const empty_program = [_]u8{0} ** 8 ++ [_]u8{Opcode.hlt.intoU8()};
+
+pub const default_max_steps = 1_000_000;
+
+vm: *Vm,
+compiler: Compiler,
+reporter: *fehler.ErrorReporter,
+/// Every line typed so far, which diagnostics point into.
+lines: std.heap.ArenaAllocator,
+gpa: Allocator,
+/// Instructions one line may run before it is stopped.
+max_steps: u64,
+
+pub fn init(vm: *Vm, interner: *StringInterner, reporter: *fehler.ErrorReporter, gpa: Allocator) !Repl {
+    var compiler = try Compiler.init(&.{}, interner, filename, "", reporter, gpa);
+    compiler.exit_on_error = false;
+    // Lines are written into memory after the program and run from there.
+    vm.disableProtection();
+    return Repl{
+        .vm = vm,
+        .compiler = compiler,
+        .reporter = reporter,
+        .lines = .init(gpa),
+        .gpa = gpa,
+        .max_steps = default_max_steps,
+    };
+}
+
+pub fn deinit(self: *Repl) void {
+    self.compiler.deinit();
+    self.lines.deinit();
+}
+
+/// Read lines from `editor` until `quit` or the end of input.
+pub fn run(self: *Repl, editor: *LineEditor, writer: *Io.Writer) !void {
+    while (try editor.readLine("nyx> ")) |line| {
+        try editor.addHistory(line);
+        if (!try self.execute(line, writer)) break;
+        try writer.flush();
+    }
+    try writer.flush();
+}
+
+/// Assemble and execute one line. Returns false when the session should end.
+pub fn execute(self: *Repl, line: []const u8, writer: *Io.Writer) !bool {
+    const trimmed = mem.trim(u8, line, " \t\r");
+    if (mem.eql(u8, trimmed, "quit")) return false;
+    if (trimmed.len == 0) return true;
+
+    const source = try self.lines.allocator().dupe(u8, trimmed);
+    try self.reporter.addSource(filename, source);
+
+    var lexer = Lexer.init(filename, source, self.compiler.interner, self.gpa);
+    var parser = Parser.init(&lexer, self.reporter, self.gpa);
+    defer parser.deinit();
+    parser.exit_on_error = false;
+
+    // Diagnostics have already been reported.
+    const stmts = parser.parse() catch return true;
+    for (stmts) |stmt| {
+        if (!try self.executeStatement(stmt, writer)) break;
+    }
+    return true;
+}
+
+fn executeStatement(self: *Repl, stmt: ast.Statement, writer: *Io.Writer) !bool {
+    const origin = self.vm.regs.ip();
+    const bytes = self.compiler.assembleStatement(stmt, origin) catch |err| switch (err) {
+        error.CompilerError => return false,
+        else => return err,
+    };
+    if (bytes.len == 0) return true;
+
+    self.vm.mmu.writeSlice(origin, bytes) catch {
+        try writer.print("no room for the instruction at 0x{x}\n", .{origin});
+        return false;
+    };
+
+    const end = origin + bytes.len;
+    const before = self.vm.snapshot();
+    self.vm.halted = false;
+    var steps: u64 = 0;
+    while (!self.vm.halted and self.vm.regs.ip() != end) : (steps += 1) {
+        if (steps == self.max_steps) {
+            try writer.print("stopped after {d} instructions (see --max-steps)\n", .{steps});
+            self.vm.regs.setIp(end);
+            break;
+        }
+        self.vm.step() catch |err| {
+            try writer.print("vm error: {s}\n", .{@errorName(err)});
+            // Carry on after the failed instruction.
+            self.vm.regs.setIp(end);
+            break;
+        };
+    }
+
+    try self.vm.writeChanges(before, writer);
+    if (self.vm.halted) try writer.writeAll("halted\n");
+    return true;
+}
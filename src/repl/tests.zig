@@ -0,0 +1,69 @@
+const std = @import("std");
+const testing = std.testing;
+const Io = std.Io;
+const fehler = @import("fehler");
+const StringInterner = @import("../StringInterner.zig");
+const Vm = @import("../vm/Vm.zig");
+const Repl = @import("Repl.zig");
+
+test "lines run as they are typed and print what changed" {
+    var reporter = fehler.ErrorReporter.init(testing.allocator);
+    defer reporter.deinit();
+    var interner = StringInterner.init(testing.allocator);
+    defer interner.deinit();
+
+    var vm = try Vm.init(&Repl.empty_program, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+
+    var repl = try Repl.init(&vm, &interner, &reporter, testing.allocator);
+    defer repl.deinit();
+
+    var output: Io.Writer.Allocating = .init(testing.allocator);
+    defer output.deinit();
+
+    const lines = [_][]const u8{ "mov q0, 3", "top:", "dec q0", "cmp q0, 0", "jne top" };
+    const expected = [_][]const u8{ "q0 = 0x3 (3)\n", "", "q0 = 0x2 (2)\n", "", "q0 = 0x0 (0)\neq = true\n" };
+    for (lines, expected) |line, want| {
+        output.clearRetainingCapacity();
+        try testing.expect(try repl.execute(line, &output.writer));
+        try testing.expectEqualStrings(want, output.written());
+    }
+
+    output.clearRetainingCapacity();
+    try testing.expect(try repl.execute("hlt", &output.writer));
+    try testing.expectEqualStrings("halted\n", output.written());
+
+    // Execution picks up after the halt.
+    output.clearRetainingCapacity();
+    try testing.expect(try repl.execute("mov q1, 7", &output.writer));
+    try testing.expectEqualStrings("q1 = 0x7 (7)\n", output.written());
+
+    try testing.expect(!try repl.execute("quit", &output.writer));
+}
+
+test "a line that loops forever is stopped after max_steps" {
+    var reporter = fehler.ErrorReporter.init(testing.allocator);
+    defer reporter.deinit();
+    var interner = StringInterner.init(testing.allocator);
+    defer interner.deinit();
+
+    var vm = try Vm.init(&Repl.empty_program, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+
+    var repl = try Repl.init(&vm, &interner, &reporter, testing.allocator);
+    defer repl.deinit();
+    repl.max_steps = 100;
+
+    var output: Io.Writer.Allocating = .init(testing.allocator);
+    defer output.deinit();
+
+    try testing.expect(try repl.execute("spin:", &output.writer));
+    try testing.expect(try repl.execute("jmp spin", &output.writer));
+    try testing.expectEqualStrings("stopped after 100 instructions (see --max-steps)\n", output.written());
+    try testing.expectEqual(@as(u64, 100), vm.counters.instructions);
+
+    // The next line runs after the loop.
+    output.clearRetainingCapacity();
+    try testing.expect(try repl.execute("mov q1, 7", &output.writer));
+    try testing.expectEqualStrings("q1 = 0x7 (7)\n", output.written());
+}
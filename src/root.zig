@@ -0,0 +1,146 @@
+//! Nyx as a library.
+//!
+//! Each stage of the toolchain can be used on its own:
+//!
+//! ```zig
+//! var lexer = nyx.Lexer.init(filename, source, &interner, gpa);
+//! var parser = nyx.Parser.init(&lexer, &reporter, gpa);
+//! var preprocessor = try nyx.Preprocessor.init(io, env, gpa, filename, source, try parser.parse(), &interner, &reporter, include_paths, &.{});
+//! var compiler = try nyx.Compiler.init(try preprocessor.process(), &interner, filename, source, &reporter, gpa);
+//! var vm = try nyx.Vm.init(try compiler.compile(), memory_size, &.{}, gpa);
+//! try vm.run();
+//! ```
+//!
+//! or `assemble` does everything up to the bytecode in one call. The stages
+//! exit the process on errors by default, like the command line tool does;
+//! set `exit_on_error` to false on the parser, preprocessor and compiler to
+//! get an error back instead. `assemble` always returns errors.
+
+const std = @import("std");
+const Allocator = std.mem.Allocator;
+const ArrayList = std.array_list.Managed;
+const fehler = @import("fehler");
+
+pub const StringInterner = @import("StringInterner.zig");
+pub const Span = @import("Span.zig");
+pub const Lexer = @import("lexer/Lexer.zig");
+pub const Token = @import("lexer/Token.zig");
+pub const Parser = @import("parser/Parser.zig");
+pub const ast = @import("parser/ast.zig");
+pub const ast_json = @import("parser/json.zig");
+pub const ast_ron = @import("parser/ron.zig");
+pub const Preprocessor = @import("preprocessor/Preprocessor.zig");
+pub const Compiler = @import("compiler/Compiler.zig");
+pub const Object = @import("compiler/Object.zig");
+pub const Linker = @import("compiler/Linker.zig");
+pub const Opcode = @import("compiler/opcode.zig").Opcode;
+pub const Vm = @import("vm/Vm.zig");
+pub const Trace = @import("vm/Trace.zig");
+pub const DebugInfo = @import("vm/DebugInfo.zig");
+pub const Clipboard = @import("vm/Clipboard.zig");
+pub const Control = @import("vm/Control.zig");
+pub const syscall = @import("vm/syscall.zig");
+pub const Register = @import("vm/register.zig").Register;
+pub const features = @import("vm/features.zig");
+pub const Disassembler = @import("disassembler/Disassembler.zig");
+pub const signature = @import("signature.zig");
+pub const diff = @import("diff.zig");
+pub const warning = @import("warning.zig");
+/// Builds raw bytecode for testing a VM or another backend without the
+/// assembler.
+pub const testing = @import("vm/testing.zig");
+
+pub const AssembleOptions = struct {
+    /// Name used for `source` in diagnostics.
+    filename: []const u8 = "<source>",
+    /// Directories searched by `#include`, after the current directory.
+    include_paths: []const []const u8 = &.{},
+    /// Run the preprocessor before compiling.
+    preprocess: bool = true,
+    /// Definitions made before the first line is processed, like `-D`.
+    defines: []const Preprocessor.Define = &.{},
+    /// Environment read by `#define_env`. Every variable is unset when null.
+    env: ?std.process.Environ = null,
+    /// Fold constants loaded into registers into the addresses that use them.
+    optimize: bool = false,
+    /// Append the source line of every instruction, like `-g`.
+    debug_info: bool = false,
+    /// Append the address of every label without the source lines, like
+    /// `--symbols`.
+    embed_symbols: bool = false,
+    /// Encode jumps and calls to labels with their absolute address instead
+    /// of the shortest displacement, like `--absolute-jumps`.
+    absolute_jumps: bool = false,
+    /// Encode the low bits of immediates that do not fit their operand, with
+    /// a warning, instead of failing, like `--truncate-immediates`.
+    truncate_immediates: bool = false,
+    /// Which warnings are reported and whether they fail, like `--allow` and
+    /// `--deny-warnings`.
+    warnings: warning.Policy = .{},
+    /// Receives diagnostics. A private reporter is used when null.
+    reporter: ?*fehler.ErrorReporter = null,
+    /// Receives the absolute address of every label when not null. Names are
+    /// copied with the map's allocator.
+    symbols: ?*std.StringArrayHashMap(u64) = null,
+};
+
+/// Assemble `source` into a bytecode image that can be passed to `Vm.init`.
+/// Returns `error.ParserError`, `error.PreProcessorError` or
+/// `error.CompilerError` after reporting a diagnostic. Caller owns the
+/// returned memory.
+pub fn assemble(io: std.Io, gpa: Allocator, source: []const u8, options: AssembleOptions) ![]u8 {
+    var own_reporter = fehler.ErrorReporter.init(gpa);
+    defer own_reporter.deinit();
+    const reporter = options.reporter orelse &own_reporter;
+    try reporter.addSource(options.filename, source);
+
+    var interner = StringInterner.init(gpa);
+    defer interner.deinit();
+
+    var lexer = Lexer.init(options.filename, source, &interner, gpa);
+    var parser = Parser.init(&lexer, reporter, gpa);
+    defer parser.deinit();
+    parser.exit_on_error = false;
+
+    var stmts = try parser.parse();
+
+    var preprocessor: ?Preprocessor = null;
+    defer if (preprocessor) |*p| p.deinit();
+    if (options.preprocess) {
+        var include_paths = ArrayList([]const u8).init(gpa);
+        errdefer include_paths.deinit();
+        try include_paths.append("");
+        try include_paths.appendSlice(options.include_paths);
+
+        preprocessor = try Preprocessor.init(
+            io,
+            options.env,
+            gpa,
+            options.filename,
+            source,
+            stmts,
+            &interner,
+            reporter,
+            try include_paths.toOwnedSlice(),
+            options.defines,
+        );
+        preprocessor.?.exit_on_error = false;
+        preprocessor.?.warnings = options.warnings;
+        stmts = try preprocessor.?.process();
+    }
+
+    var compiler = try Compiler.init(stmts, &interner, options.filename, source, reporter, gpa);
+    defer compiler.deinit();
+    compiler.exit_on_error = false;
+    compiler.optimize = options.optimize;
+    compiler.debug_info = options.debug_info;
+    compiler.symbols = options.embed_symbols;
+    compiler.absolute_jumps = options.absolute_jumps;
+    compiler.truncate_immediates = options.truncate_immediates;
+    compiler.warnings = options.warnings;
+
+    const bytecode = try compiler.compile();
+    errdefer gpa.free(bytecode);
+    if (options.symbols) |map| try compiler.exportSymbols(map);
+    return bytecode;
+}
@@ -0,0 +1,71 @@
+//! Ed25519 signing of compiled bytecode.
+//!
+//! A signed file is the magic bytes, followed by a 64-byte signature over the
+//! rest of the file, followed by the ordinary bytecode (entry point header,
+//! text and data). Unsigned files never start with the magic bytes because
+//! they would decode as an entry point far outside any program.
+
+const std = @import("std");
+const mem = std.mem;
+const Allocator = mem.Allocator;
+const Ed25519 = std.crypto.sign.Ed25519;
+
+pub const magic = "NYXSIG\x00\x01";
+pub const header_len = magic.len + Ed25519.Signature.encoded_length;
+
+pub const KeyPair = Ed25519.KeyPair;
+pub const PublicKey = Ed25519.PublicKey;
+
+pub fn isSigned(bytes: []const u8) bool {
+    return bytes.len >= header_len and mem.eql(u8, bytes[0..magic.len], magic);
+}
+
+/// Return the bytecode behind the signature header, or `bytes` unchanged if
+/// the file is not signed.
+pub fn payload(bytes: []const u8) []const u8 {
+    return if (isSigned(bytes)) bytes[header_len..] else bytes;
+}
+
+/// Prepend a signature header to `bytecode`. Caller owns the returned memory.
+pub fn sign(gpa: Allocator, bytecode: []const u8, key_pair: KeyPair) ![]u8 {
+    const signature = try key_pair.sign(bytecode, null);
+
+    const signed = try gpa.alloc(u8, header_len + bytecode.len);
+    @memcpy(signed[0..magic.len], magic);
+    @memcpy(signed[magic.len..header_len], &signature.toBytes());
+    @memcpy(signed[header_len..], bytecode);
+    return signed;
+}
+
+/// Check that `bytes` is signed by `public_key`.
+pub fn verify(bytes: []const u8, public_key: PublicKey) !void {
+    if (!isSigned(bytes)) return error.UnsignedBytecode;
+    const signature = Ed25519.Signature.fromBytes(bytes[magic.len..header_len].*);
+    signature.verify(bytes[header_len..], public_key) catch return error.InvalidSignature;
+}
+
+pub fn generateKeyPair() KeyPair {
+    var seed: [KeyPair.seed_length]u8 = undefined;
+    std.crypto.random.bytes(&seed);
+    return KeyPair.generateDeterministic(seed) catch unreachable;
+}
+
+/// Keys are stored as a single line of lowercase hex.
+pub fn encodeKey(comptime len: usize, key: [len]u8) [len * 2]u8 {
+    return std.fmt.bytesToHex(key, .lower);
+}
+
+pub fn decodeSecretKey(text: []const u8) !KeyPair {
+    var bytes: [Ed25519.SecretKey.encoded_length]u8 = undefined;
+    const decoded = std.fmt.hexToBytes(&bytes, mem.trim(u8, text, " \t\r\n")) catch return error.InvalidKey;
+    if (decoded.len != bytes.len) return error.InvalidKey;
+    const secret_key = Ed25519.SecretKey.fromBytes(bytes) catch return error.InvalidKey;
+    return KeyPair.fromSecretKey(secret_key) catch error.InvalidKey;
+}
+
+pub fn decodePublicKey(text: []const u8) !PublicKey {
+    var bytes: [PublicKey.encoded_length]u8 = undefined;
+    const decoded = std.fmt.hexToBytes(&bytes, mem.trim(u8, text, " \t\r\n")) catch return error.InvalidKey;
+    if (decoded.len != bytes.len) return error.InvalidKey;
+    return PublicKey.fromBytes(bytes) catch error.InvalidKey;
+}
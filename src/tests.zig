@@ -1,4 +1,11 @@
 test {
     _ = @import("lexer/tests.zig");
     _ = @import("parser/tests.zig");
+    _ = @import("preprocessor/tests.zig");
+    _ = @import("compiler/tests.zig");
+    _ = @import("vm/tests.zig");
+    _ = @import("debugger/tests.zig");
+    _ = @import("repl/tests.zig");
+    _ = @import("disassembler/tests.zig");
+    _ = @import("main.zig");
 }
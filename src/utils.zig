@@ -9,6 +9,14 @@ pub fn readFromFile(io: std.Io, gpa: Allocator, file_path: []const u8) ![]u8 {
     return try cwd.readFile(io, file_path, buffer);
 }
 
+/// Read `file_path`, or all of stdin when it is `-`.
+pub fn readInput(io: std.Io, gpa: Allocator, file_path: []const u8) ![]u8 {
+    if (!std.mem.eql(u8, file_path, "-")) return readFromFile(io, gpa, file_path);
+    var buffer: [4096]u8 = undefined;
+    var reader = Io.File.stdin().reader(io, &buffer);
+    return reader.interface.allocRemaining(gpa, .unlimited);
+}
+
 pub fn writeToFile(io: std.Io, file_path: []const u8, data: []const u8) !void {
     var cwd = Io.Dir.cwd();
     try cwd.writeFile(io, .{ .sub_path = file_path, .data = data });
@@ -19,3 +27,29 @@ pub fn fileExists(io: std.Io, file_path: []const u8) bool {
     cwd.access(io, file_path, .{}) catch return false;
     return true;
 }
+
+/// Names of the files in `dir_path` ending in `extension`, sorted so the
+/// result does not depend on the file system. Caller owns the returned memory.
+pub fn listFiles(io: std.Io, gpa: Allocator, dir_path: []const u8, extension: []const u8) ![][]const u8 {
+    var dir = try Io.Dir.cwd().openDir(io, dir_path, .{ .iterate = true });
+    defer dir.close(io);
+
+    var names = std.array_list.Managed([]const u8).init(gpa);
+    errdefer {
+        for (names.items) |name| gpa.free(name);
+        names.deinit();
+    }
+
+    var iter = dir.iterate();
+    while (try iter.next(io)) |entry| {
+        if (entry.kind != .file or !std.mem.endsWith(u8, entry.name, extension)) continue;
+        try names.append(try gpa.dupe(u8, entry.name));
+    }
+
+    std.mem.sort([]const u8, names.items, {}, struct {
+        fn lessThan(_: void, a: []const u8, b: []const u8) bool {
+            return std.mem.lessThan(u8, a, b);
+        }
+    }.lessThan);
+    return names.toOwnedSlice();
+}
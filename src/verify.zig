@@ -0,0 +1,339 @@
+//! Differential runs behind `nyx verify`: two programs that should behave
+//! the same, such as one built with `-O` and one without, run side by side
+//! and stop at every syscall so the calls can be compared one by one.
+//!
+//! Both programs read the same input on stdin and their writes to stdout
+//! and stderr are captured and compared instead of printed. `sys_exit` ends
+//! the run instead of the process. Every other syscall runs for real in
+//! both programs.
+//!
+//! Once both programs stop the same way their final state is compared:
+//! every register but `ip` and the configuration registers, the flags, and
+//! memory after the larger of the two programs, leaving out the part of the
+//! stack below `sp` that either program used and gave back. An optimized
+//! build that keeps different values in dead registers or places its data
+//! elsewhere differs there, so `Options.compare_state` turns this off.
+
+const std = @import("std");
+const Allocator = std.mem.Allocator;
+const ArrayList = std.array_list.Managed;
+const Io = std.Io;
+const Opcode = @import("compiler/opcode.zig").Opcode;
+const Vm = @import("vm/Vm.zig");
+const Register = @import("vm/register.zig").Register;
+const Flags = @import("vm/Flags.zig");
+const syscall = @import("vm/syscall.zig");
+
+const sys_read = 0x02;
+const sys_write = 0x03;
+const sys_read_line = 0x18;
+const sys_exit = 0xFF;
+
+/// What a program did at the point where the two runs are compared.
+pub const Event = union(enum) {
+    syscall: u64,
+    write: Write,
+    halted,
+    exited: u8,
+    trapped: anyerror,
+
+    pub const Write = struct {
+        fd: u32,
+        len: usize,
+    };
+
+    pub fn format(self: Event, writer: *Io.Writer) Io.Writer.Error!void {
+        switch (self) {
+            .syscall => |number| if (syscallName(number)) |name|
+                try writer.print("calls sys_{s}", .{name})
+            else
+                try writer.print("calls syscall 0x{x:0>2}", .{number}),
+            .write => |w| try writer.print("writes {d} bytes to fd {d}", .{ w.len, w.fd }),
+            .halted => try writer.writeAll("halts"),
+            .exited => |status| try writer.print("exits with status {d}", .{status}),
+            .trapped => |err| try writer.print("stops with {s}", .{@errorName(err)}),
+        }
+    }
+};
+
+/// The first difference between the final states of two programs that
+/// stopped the same way.
+pub const StateDifference = union(enum) {
+    register: struct { register: Register, values: [2]u64 },
+    flag: struct { name: []const u8, values: [2]bool },
+    memory_size: [2]usize,
+    memory: struct { addr: usize, values: [2]u8 },
+
+    pub fn format(self: StateDifference, writer: *Io.Writer) Io.Writer.Error!void {
+        switch (self) {
+            .register => |r| try writer.print("{s} is 0x{x} and 0x{x}", .{ @tagName(r.register), r.values[0], r.values[1] }),
+            .flag => |f| try writer.print("{s} is {} and {}", .{ f.name, f.values[0], f.values[1] }),
+            .memory_size => |sizes| try writer.print("memory is {d} and {d} bytes", .{ sizes[0], sizes[1] }),
+            .memory => |m| try writer.print("byte at 0x{x:0>4} is 0x{x:0>2} and 0x{x:0>2}", .{ m.addr, m.values[0], m.values[1] }),
+        }
+    }
+};
+
+pub const Divergence = struct {
+    /// Syscalls both programs made identically before they diverged.
+    matched: usize,
+    /// Where each program was when they diverged.
+    addresses: [2]usize,
+    events: [2]Event,
+    /// First byte that differs when both wrote to the same descriptor.
+    offset: ?usize,
+    /// Set when both programs stopped the same way but in different states.
+    state: ?StateDifference = null,
+};
+
+pub const Outcome = union(enum) {
+    /// Both programs made `matched` identical syscalls and ended the same
+    /// way, which `end` describes.
+    same: struct { matched: usize, end: Event },
+    diverged: Divergence,
+};
+
+/// One program under comparison, reachable from its syscalls through
+/// `Vm.host`.
+const Side = struct {
+    vm: *Vm,
+    input: []const u8,
+    input_pos: usize = 0,
+    /// Bytes written to stdout or stderr by the syscall that just ran.
+    written: ArrayList(u8),
+    written_fd: ?u32 = null,
+    exit_status: ?u8 = null,
+
+    fn of(vm: *Vm) *Side {
+        return @ptrCast(@alignCast(vm.host.?));
+    }
+};
+
+pub const Options = struct {
+    /// Compare registers, flags and memory once both programs stop.
+    compare_state: bool = true,
+};
+
+/// Run `a` and `b` until they diverge or both stop. Both get `input` on
+/// stdin. The VMs' syscalls for reading, writing and exiting are replaced
+/// and `host` is taken over, so the VMs are only good for inspection
+/// afterwards.
+pub fn compare(gpa: Allocator, a: *Vm, b: *Vm, input: []const u8, options: Options) !Outcome {
+    var sides = [2]Side{
+        .{ .vm = a, .input = input, .written = .init(gpa) },
+        .{ .vm = b, .input = input, .written = .init(gpa) },
+    };
+    defer for (&sides) |*side| {
+        side.written.deinit();
+        side.vm.host = null;
+    };
+
+    for (&sides) |*side| {
+        side.vm.host = side;
+        try side.vm.registerSyscall(sys_read, captureRead);
+        try side.vm.registerSyscall(sys_read_line, captureReadLine);
+        try side.vm.registerSyscall(sys_write, captureWrite);
+        try side.vm.registerSyscall(sys_exit, captureExit);
+    }
+
+    var matched: usize = 0;
+    while (true) {
+        const events = [2]Event{ advance(&sides[0]), advance(&sides[1]) };
+        const addresses = [2]usize{ a.instruction_start, b.instruction_start };
+        const diverged = Divergence{ .matched = matched, .addresses = addresses, .events = events, .offset = null };
+
+        if (std.meta.activeTag(events[0]) != std.meta.activeTag(events[1])) return .{ .diverged = diverged };
+        switch (events[0]) {
+            .syscall => |number| if (number != events[1].syscall) return .{ .diverged = diverged },
+            .exited => |status| {
+                if (status != events[1].exited) return .{ .diverged = diverged };
+                return finish(a, b, diverged, options);
+            },
+            .trapped => |err| {
+                if (err != events[1].trapped) return .{ .diverged = diverged };
+                return finish(a, b, diverged, options);
+            },
+            .halted => return finish(a, b, diverged, options),
+            .write => unreachable,
+        }
+
+        for (&sides) |*side| {
+            side.written.clearRetainingCapacity();
+            side.written_fd = null;
+        }
+        const results = [2]?anyerror{ runSyscall(a), runSyscall(b) };
+        if (compareWrites(&sides)) |offset| {
+            var divergence = diverged;
+            divergence.events = .{ writeEvent(&sides[0]), writeEvent(&sides[1]) };
+            divergence.offset = offset;
+            return .{ .diverged = divergence };
+        }
+        if (results[0] != null or results[1] != null) {
+            var divergence = diverged;
+            for (results, 0..) |result, i| {
+                if (result) |err| divergence.events[i] = .{ .trapped = err };
+            }
+            if (results[0] != null and results[1] != null and results[0].? == results[1].?) {
+                divergence.matched = matched + 1;
+                return finish(a, b, divergence, options);
+            }
+            return .{ .diverged = divergence };
+        }
+        matched += 1;
+    }
+}
+
+/// Both programs stopped the same way, which `stopped` describes. They are
+/// the same unless their final states differ.
+fn finish(a: *Vm, b: *Vm, stopped: Divergence, options: Options) !Outcome {
+    if (options.compare_state) {
+        if (compareRegisters(a, b) orelse compareFlags(a.flags, b.flags) orelse try compareMemory(a, b)) |difference| {
+            var divergence = stopped;
+            divergence.state = difference;
+            return .{ .diverged = divergence };
+        }
+    }
+    return .{ .same = .{ .matched = stopped.matched, .end = stopped.events[0] } };
+}
+
+fn compareRegisters(a: *Vm, b: *Vm) ?StateDifference {
+    for (std.enums.values(Register)) |register| {
+        const info = register.physicalInfo();
+        // Byte, word and dword views are part of their qword.
+        const values: [2]u64 = switch (info.type) {
+            .general_purpose => if (info.view != .qword) continue else .{ a.regs.gpr[info.index], b.regs.gpr[info.index] },
+            .floating_point => .{ a.regs.fpr[info.index], b.regs.fpr[info.index] },
+            .special => if (register == .ip) continue else .{ a.regs.special[info.index], b.regs.special[info.index] },
+            .zero, .config => continue,
+        };
+        if (values[0] != values[1]) return .{ .register = .{ .register = register, .values = values } };
+    }
+    return null;
+}
+
+fn compareFlags(a: Flags, b: Flags) ?StateDifference {
+    inline for (std.meta.fields(Flags)) |field| {
+        const values = [2]bool{ @field(a, field.name), @field(b, field.name) };
+        if (values[0] != values[1]) return .{ .flag = .{ .name = field.name, .values = values } };
+    }
+    return null;
+}
+
+/// Compare memory after both programs. `sp` is already known to match.
+fn compareMemory(a: *Vm, b: *Vm) !?StateDifference {
+    const sizes = [2]usize{ a.mmu.size(), b.mmu.size() };
+    if (sizes[0] != sizes[1]) return .{ .memory_size = sizes };
+
+    // The stack either program used below `sp` is garbage by now.
+    const top = a.regs.get(.isp).asUsize();
+    const dead_start = top -| @max(a.counters.peak_stack_depth, b.counters.peak_stack_depth);
+    const dead_end = a.regs.sp();
+
+    var buffers: [2][4096]u8 = undefined;
+    var addr = @max(a.program_len, b.program_len);
+    while (addr < sizes[0]) {
+        if (addr >= dead_start and addr < dead_end) {
+            addr = dead_end;
+            continue;
+        }
+        var len = @min(buffers[0].len, sizes[0] - addr);
+        if (addr < dead_start) len = @min(len, dead_start - addr);
+        _ = try a.mmu.readInto(addr, buffers[0][0..len]);
+        _ = try b.mmu.readInto(addr, buffers[1][0..len]);
+        if (std.mem.indexOfDiff(u8, buffers[0][0..len], buffers[1][0..len])) |i| {
+            return .{ .memory = .{ .addr = addr + i, .values = .{ buffers[0][i], buffers[1][i] } } };
+        }
+        addr += len;
+    }
+    return null;
+}
+
+/// Run `side` up to its next syscall without running it, or until it stops.
+fn advance(side: *Side) Event {
+    const vm = side.vm;
+    while (true) {
+        if (side.exit_status) |status| return .{ .exited = status };
+        if (vm.halted) return .halted;
+        const byte = vm.mmu.read(vm.regs.ip(), .byte) catch |err| return .{ .trapped = err };
+        if (byte.asU8() == Opcode.syscall.intoU8()) {
+            vm.instruction_start = vm.regs.ip();
+            return .{ .syscall = vm.regs.get(syscall.number_register).asU64() };
+        }
+        vm.step() catch |err| return .{ .trapped = err };
+    }
+}
+
+fn runSyscall(vm: *Vm) ?anyerror {
+    vm.step() catch |err| return err;
+    return null;
+}
+
+/// Offset of the first differing byte if the two programs did not write
+/// the same bytes to the same descriptor, or null if they did.
+fn compareWrites(sides: *[2]Side) ?usize {
+    if (sides[0].written_fd != sides[1].written_fd) return 0;
+    return std.mem.indexOfDiff(u8, sides[0].written.items, sides[1].written.items);
+}
+
+fn writeEvent(side: *Side) Event {
+    const fd = side.written_fd orelse return .{ .syscall = sys_write };
+    return .{ .write = .{ .fd = fd, .len = side.written.items.len } };
+}
+
+fn syscallName(number: u64) ?[]const u8 {
+    for (syscall.table) |entry| {
+        if (entry.number == number) return entry.name;
+    }
+    return null;
+}
+
+fn builtin(number: u64) syscall.SyscallFn {
+    for (syscall.table) |entry| {
+        if (entry.number == number) return entry.handler;
+    }
+    unreachable;
+}
+
+fn captureRead(vm: *Vm) anyerror!void {
+    const args = vm.syscallArgs();
+    if (@as(u32, @truncate(args[0])) != 0) return builtin(sys_read)(vm);
+    const side = Side.of(vm);
+    const addr: usize = @intCast(args[1]);
+    const count: usize = @intCast(args[2]);
+    const n = @min(count, side.input.len - side.input_pos);
+    try vm.mmu.writeSlice(addr, side.input[side.input_pos .. side.input_pos + n]);
+    side.input_pos += n;
+    vm.setSyscallResult(n);
+}
+
+fn captureReadLine(vm: *Vm) anyerror!void {
+    const args = vm.syscallArgs();
+    const side = Side.of(vm);
+    const addr: usize = @intCast(args[0]);
+    const capacity: usize = @intCast(args[1]);
+    const rest = side.input[side.input_pos..];
+    var n = @min(capacity, rest.len);
+    if (std.mem.indexOfScalar(u8, rest[0..n], '\n')) |newline| n = newline + 1;
+    try vm.mmu.writeSlice(addr, rest[0..n]);
+    side.input_pos += n;
+    const eof = n < capacity and (n == 0 or rest[n - 1] != '\n');
+    vm.regs.set(syscall.eof_register, .{ .qword = @intFromBool(eof) });
+    vm.setSyscallResult(n);
+}
+
+fn captureWrite(vm: *Vm) anyerror!void {
+    const args = vm.syscallArgs();
+    const fd: u32 = @truncate(args[0]);
+    if (fd != 1 and fd != 2) return builtin(sys_write)(vm);
+    const side = Side.of(vm);
+    const addr: usize = @intCast(args[1]);
+    const count: usize = @intCast(args[2]);
+    try side.written.appendSlice(try vm.mmu.readSlice(addr, count));
+    side.written_fd = fd;
+    vm.setSyscallResult(count);
+}
+
+fn captureExit(vm: *Vm) anyerror!void {
+    Side.of(vm).exit_status = @as(u8, @truncate(vm.syscallArgs()[0]));
+    vm.halted = true;
+}
@@ -0,0 +1,254 @@
+//! Pre-decoded block cache enabled with `zig build -Dpredecode`. Once
+//! execution has reached the same address `hot_threshold` times, the
+//! straight-line run of instructions starting there is decoded once into a
+//! `Block` of operations with their operands already read, up to and
+//! including the first jump. The VM then runs the block without fetching or
+//! decoding bytes. No native code is generated. Any instruction the decoder
+//! does not handle, such as a syscall, memory access or call, ends the block
+//! and is left to the interpreter.
+//!
+//! Only code in the program block is decoded. A write into code that has
+//! been decoded drops every block, so self-modifying programs keep working.
+
+const std = @import("std");
+const Allocator = std.mem.Allocator;
+const ArrayList = std.array_list.Managed;
+const Register = @import("register.zig").Register;
+const DataSize = @import("../parser/immediate.zig").DataSize;
+const Immediate = @import("../parser/immediate.zig").Immediate;
+const Mmu = @import("memory/Mmu.zig");
+const Opcode = @import("../compiler/opcode.zig").Opcode;
+
+const BlockCache = @This();
+
+/// Visits to an address before the block starting there is decoded.
+pub const hot_threshold = 16;
+
+/// Longest block, in instructions.
+pub const max_block_len = 256;
+
+/// One decoded instruction.
+pub const Op = struct {
+    opcode: Opcode,
+    /// Address of the instruction.
+    addr: usize,
+    /// Address of the next instruction, which is `ip` while it runs.
+    next: usize,
+    dest: Register = .q0,
+    lhs: Register = .q0,
+    rhs: Register = .q0,
+    /// Immediate operand, or the absolute target of a jump.
+    imm: Immediate = .{ .qword = 0 },
+};
+
+pub const Block = struct {
+    /// Empty when the first instruction could not be decoded.
+    ops: []const Op,
+};
+
+blocks: std.AutoHashMap(usize, Block),
+visits: std.AutoHashMap(usize, u32),
+/// `Mmu.code_writes` when the cache was last checked.
+code_writes: u64,
+gpa: Allocator,
+
+pub fn init(gpa: Allocator) BlockCache {
+    return BlockCache{
+        .blocks = .init(gpa),
+        .visits = .init(gpa),
+        .code_writes = 0,
+        .gpa = gpa,
+    };
+}
+
+pub fn deinit(self: *BlockCache) void {
+    self.flush();
+    self.blocks.deinit();
+    self.visits.deinit();
+}
+
+/// Number of blocks decoded so far that have instructions in them.
+pub fn blockCount(self: *const BlockCache) usize {
+    var count: usize = 0;
+    var it = self.blocks.valueIterator();
+    while (it.next()) |block| {
+        if (block.ops.len > 0) count += 1;
+    }
+    return count;
+}
+
+/// Drop every decoded block and visit count.
+pub fn flush(self: *BlockCache) void {
+    var it = self.blocks.valueIterator();
+    while (it.next()) |block| self.gpa.free(block.ops);
+    self.blocks.clearRetainingCapacity();
+    self.visits.clearRetainingCapacity();
+}
+
+/// The decoded block starting at `addr`, decoding it if the address has
+/// become hot. Null means the interpreter runs the next instruction.
+pub fn lookup(self: *BlockCache, mmu: *Mmu, addr: usize) !?*const Block {
+    if (mmu.code_writes != self.code_writes) {
+        self.flush();
+        mmu.code_end = 0;
+        self.code_writes = mmu.code_writes;
+    }
+
+    if (self.blocks.getPtr(addr)) |block| return if (block.ops.len > 0) block else null;
+    if (addr >= mmu.blocks.items[0].len) return null;
+
+    const visits = try self.visits.getOrPut(addr);
+    if (!visits.found_existing) visits.value_ptr.* = 0;
+    visits.value_ptr.* += 1;
+    if (visits.value_ptr.* < hot_threshold) return null;
+    _ = self.visits.remove(addr);
+
+    const ops = try self.decode(mmu, addr);
+    errdefer self.gpa.free(ops);
+    const entry = try self.blocks.getOrPut(addr);
+    entry.value_ptr.* = .{ .ops = ops };
+    if (ops.len > 0) mmu.code_end = @max(mmu.code_end, ops[ops.len - 1].next);
+    return if (ops.len > 0) entry.value_ptr else null;
+}
+
+fn decode(self: *BlockCache, mmu: *Mmu, entry: usize) ![]const Op {
+    var ops = ArrayList(Op).init(self.gpa);
+    errdefer ops.deinit();
+
+    var decoder = Decoder{ .mmu = mmu, .ip = entry, .end = mmu.blocks.items[0].len };
+    while (ops.items.len < max_block_len) {
+        const op = decoder.next() catch break orelse break;
+        try ops.append(op);
+        if (endsBlock(op.opcode)) break;
+    }
+    return ops.toOwnedSlice();
+}
+
+fn endsBlock(opcode: Opcode) bool {
+    return switch (opcode) {
+        .jmp_imm, .jeq_imm, .jne_imm, .jlt_imm, .jgt_imm, .jle_imm, .jge_imm => true,
+        .jmp_rel8, .jeq_rel8, .jne_rel8, .jlt_rel8, .jgt_rel8, .jle_rel8, .jge_rel8 => true,
+        .jmp_rel32, .jeq_rel32, .jne_rel32, .jlt_rel32, .jgt_rel32, .jle_rel32, .jge_rel32 => true,
+        else => false,
+    };
+}
+
+/// Reads instructions out of the program block. `next` returns null at the
+/// first instruction that is left to the interpreter, including ones the
+/// interpreter would reject, so it reports the error itself.
+const Decoder = struct {
+    mmu: *Mmu,
+    ip: usize,
+    end: usize,
+
+    fn next(self: *Decoder) !?Op {
+        const addr = self.ip;
+        const opcode = Opcode.fromU8(try self.byte()) catch return null;
+        var op = Op{ .opcode = opcode, .addr = addr, .next = addr };
+        switch (opcode) {
+            .nop => {},
+            .mov_reg_reg => {
+                op.dest = try self.destRegister() orelse return null;
+                op.lhs = try self.register();
+            },
+            .mov_reg_imm, .inc_reg_imm, .dec_reg_imm => {
+                op.dest = try self.destRegister() orelse return null;
+                op.imm = try self.immediate(DataSize.fromRegister(op.dest));
+            },
+            .inc, .dec => {
+                op.dest = try self.destRegister() orelse return null;
+            },
+            .cmp_reg_reg => {
+                op.lhs = try self.register();
+                op.rhs = try self.register();
+            },
+            .cmp_reg_imm => {
+                op.lhs = try self.register();
+                op.imm = try self.immediate(DataSize.fromRegister(op.lhs));
+            },
+            .add_reg_reg_reg,
+            .sub_reg_reg_reg,
+            .mul_reg_reg_reg,
+            .adc_reg_reg_reg,
+            .sbb_reg_reg_reg,
+            => {
+                op.dest = try self.destRegister() orelse return null;
+                op.lhs = try self.register();
+                op.rhs = try self.register();
+            },
+            .add_reg_reg_imm,
+            .sub_reg_reg_imm,
+            .mul_reg_reg_imm,
+            .adc_reg_reg_imm,
+            .sbb_reg_reg_imm,
+            => {
+                op.dest = try self.destRegister() orelse return null;
+                op.lhs = try self.register();
+                op.imm = try self.immediate(DataSize.fromRegister(op.dest));
+            },
+            .and_reg_reg_reg,
+            .or_reg_reg_reg,
+            .xor_reg_reg_reg,
+            .shl_reg_reg_reg,
+            .shr_reg_reg_reg,
+            => {
+                op.dest = try self.integerRegister() orelse return null;
+                op.lhs = try self.register();
+                op.rhs = try self.register();
+            },
+            .and_reg_reg_imm,
+            .or_reg_reg_imm,
+            .xor_reg_reg_imm,
+            .shl_reg_reg_imm,
+            .shr_reg_reg_imm,
+            => {
+                op.dest = try self.integerRegister() orelse return null;
+                op.lhs = try self.register();
+                op.imm = try self.immediate(DataSize.fromRegister(op.dest));
+            },
+            .jmp_imm, .jeq_imm, .jne_imm, .jlt_imm, .jgt_imm, .jle_imm, .jge_imm => {
+                op.imm = try self.immediate(.qword);
+            },
+            .jmp_rel8, .jeq_rel8, .jne_rel8, .jlt_rel8, .jgt_rel8, .jle_rel8, .jge_rel8 => {
+                const displacement: i8 = @bitCast(try self.byte());
+                op.imm = .{ .qword = Opcode.relativeTarget(self.ip, displacement) };
+            },
+            .jmp_rel32, .jeq_rel32, .jne_rel32, .jlt_rel32, .jgt_rel32, .jle_rel32, .jge_rel32 => {
+                const displacement: i32 = @bitCast((try self.immediate(.dword)).asU32());
+                op.imm = .{ .qword = Opcode.relativeTarget(self.ip, displacement) };
+            },
+            else => return null,
+        }
+        op.next = self.ip;
+        return op;
+    }
+
+    fn byte(self: *Decoder) !u8 {
+        return (try self.immediate(.byte)).asU8();
+    }
+
+    fn immediate(self: *Decoder, size: DataSize) !Immediate {
+        const len = size.sizeInBytes();
+        if (self.ip + len > self.end) return error.EndOfProgram;
+        const value = try self.mmu.read(self.ip, size);
+        self.ip += len;
+        return value;
+    }
+
+    fn register(self: *Decoder) !Register {
+        return Register.fromU8(try self.byte());
+    }
+
+    fn destRegister(self: *Decoder) !?Register {
+        const reg = try self.register();
+        return if (reg == .ip) null else reg;
+    }
+
+    fn integerRegister(self: *Decoder) !?Register {
+        const reg = try self.destRegister() orelse return null;
+        return switch (DataSize.fromRegister(reg)) {
+            .float, .double => null,
+            else => reg,
+        };
+    }
+};
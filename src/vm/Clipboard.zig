@@ -0,0 +1,118 @@
+//! Host clipboard reached by `sys_clipboard_read` and `sys_clipboard_write`.
+//!
+//! The VM only holds this interface, so an embedder can hand it whatever
+//! clipboard it has. `Command` talks to the desktop through the usual copy
+//! and paste tools, and `Memory` keeps the text in-process, which is what
+//! tests and sandboxes want. Without a clipboard the syscalls fail.
+
+const std = @import("std");
+const builtin = @import("builtin");
+const Allocator = std.mem.Allocator;
+
+const Clipboard = @This();
+
+ptr: *anyopaque,
+/// Return the current text, owned by the caller and allocated with `gpa`.
+readFn: *const fn (ptr: *anyopaque, gpa: Allocator) anyerror![]u8,
+/// Replace the text with `text`.
+writeFn: *const fn (ptr: *anyopaque, text: []const u8) anyerror!void,
+
+pub fn read(self: Clipboard, gpa: Allocator) ![]u8 {
+    return self.readFn(self.ptr, gpa);
+}
+
+pub fn write(self: Clipboard, text: []const u8) !void {
+    return self.writeFn(self.ptr, text);
+}
+
+/// A clipboard that lives and dies with the process.
+pub const Memory = struct {
+    text: std.array_list.Managed(u8),
+
+    pub fn init(gpa: Allocator) Memory {
+        return .{ .text = .init(gpa) };
+    }
+
+    pub fn deinit(self: *Memory) void {
+        self.text.deinit();
+    }
+
+    pub fn clipboard(self: *Memory) Clipboard {
+        return .{ .ptr = self, .readFn = readMemory, .writeFn = writeMemory };
+    }
+
+    fn readMemory(ptr: *anyopaque, gpa: Allocator) anyerror![]u8 {
+        const self: *Memory = @ptrCast(@alignCast(ptr));
+        return gpa.dupe(u8, self.text.items);
+    }
+
+    fn writeMemory(ptr: *anyopaque, text: []const u8) anyerror!void {
+        const self: *Memory = @ptrCast(@alignCast(ptr));
+        self.text.clearRetainingCapacity();
+        try self.text.appendSlice(text);
+    }
+};
+
+/// The desktop clipboard, through `pbcopy`/`pbpaste` on macOS and
+/// `wl-copy`/`wl-paste` or `xclip` elsewhere. Reads and writes fail when the
+/// tool is missing or exits with an error.
+pub const Command = struct {
+    io: std.Io,
+    copy: []const []const u8,
+    paste: []const []const u8,
+
+    /// Pick the tools for this host. `wayland` is whether `WAYLAND_DISPLAY`
+    /// is set, since an X11 tool cannot reach a Wayland clipboard.
+    pub fn detect(io: std.Io, wayland: bool) Command {
+        if (builtin.os.tag.isDarwin()) return .{
+            .io = io,
+            .copy = &.{"pbcopy"},
+            .paste = &.{"pbpaste"},
+        };
+        if (wayland) return .{
+            .io = io,
+            .copy = &.{"wl-copy"},
+            .paste = &.{ "wl-paste", "--no-newline" },
+        };
+        return .{
+            .io = io,
+            .copy = &.{ "xclip", "-selection", "clipboard", "-in" },
+            .paste = &.{ "xclip", "-selection", "clipboard", "-out" },
+        };
+    }
+
+    pub fn clipboard(self: *Command) Clipboard {
+        return .{ .ptr = self, .readFn = readCommand, .writeFn = writeCommand };
+    }
+
+    fn readCommand(ptr: *anyopaque, gpa: Allocator) anyerror![]u8 {
+        const self: *Command = @ptrCast(@alignCast(ptr));
+        const result = try std.process.run(gpa, self.io, .{ .argv = self.paste });
+        defer gpa.free(result.stderr);
+        errdefer gpa.free(result.stdout);
+        try checkExited(result.term);
+        return result.stdout;
+    }
+
+    fn writeCommand(ptr: *anyopaque, text: []const u8) anyerror!void {
+        const self: *Command = @ptrCast(@alignCast(ptr));
+        var child = try std.process.spawn(self.io, .{
+            .argv = self.copy,
+            .stdin = .pipe,
+            .stdout = .ignore,
+            .stderr = .ignore,
+        });
+        errdefer child.kill(self.io);
+        try child.stdin.?.writeStreamingAll(self.io, text);
+        child.stdin.?.close(self.io);
+        child.stdin = null;
+        try checkExited(try child.wait(self.io));
+    }
+
+    fn checkExited(term: std.process.Child.Term) !void {
+        switch (term) {
+            .exited => |code| if (code != 0) return error.ClipboardUnavailable,
+            else => return error.ClipboardUnavailable,
+        }
+    }
+};
@@ -0,0 +1,94 @@
+//! Memory-mapped text console, enabled with `--console ROWSxCOLS`.
+//!
+//! The screen is a grid of two-byte cells mapped right after the VM's memory,
+//! at the address held in `msz`. The first byte of a cell is the character
+//! and the second its attribute: bits 0-2 are the foreground color, bit 3 is
+//! bold and bits 4-6 are the background color, using the eight ANSI colors.
+//! An attribute of 0 means the terminal's default colors. Programs draw by
+//! writing to the grid and call `sys_present` to put it on screen.
+
+const std = @import("std");
+const Io = std.Io;
+
+const Console = @This();
+
+pub const bytes_per_cell = 2;
+
+pub const Size = struct {
+    rows: usize,
+    cols: usize,
+
+    /// Parse `ROWSxCOLS`, e.g. `25x80`. A grid whose size in bytes does not
+    /// fit a `usize` is `error.ConsoleTooLarge`.
+    pub fn parse(text: []const u8) !Size {
+        const x = std.mem.indexOfScalar(u8, text, 'x') orelse return error.InvalidConsoleSize;
+        const size = Size{
+            .rows = std.fmt.parseInt(usize, text[0..x], 10) catch return error.InvalidConsoleSize,
+            .cols = std.fmt.parseInt(usize, text[x + 1 ..], 10) catch return error.InvalidConsoleSize,
+        };
+        if (size.rows == 0 or size.cols == 0) return error.InvalidConsoleSize;
+        _ = try size.bytes();
+        return size;
+    }
+
+    pub fn bytes(self: Size) error{ConsoleTooLarge}!usize {
+        const cells = std.math.mul(usize, self.rows, self.cols) catch return error.ConsoleTooLarge;
+        return std.math.mul(usize, cells, bytes_per_cell) catch error.ConsoleTooLarge;
+    }
+};
+
+/// Address of the first cell.
+base: usize,
+size: Size,
+/// Cell storage, owned by the MMU block that maps it.
+cells: []u8,
+
+pub fn char(self: *const Console, row: usize, col: usize) u8 {
+    return self.cells[(row * self.size.cols + col) * bytes_per_cell];
+}
+
+pub fn attribute(self: *const Console, row: usize, col: usize) u8 {
+    return self.cells[(row * self.size.cols + col) * bytes_per_cell + 1];
+}
+
+/// Draw the whole screen from the top left corner of the terminal, with
+/// ANSI escape sequences for colors.
+pub fn render(self: *const Console, writer: *Io.Writer) Io.Writer.Error!void {
+    try writer.writeAll("\x1b[H");
+    for (0..self.size.rows) |row| {
+        var current: u8 = 0;
+        for (0..self.size.cols) |col| {
+            const attr = self.attribute(row, col) & 0x7F;
+            if (attr != current) {
+                try writeAttribute(writer, attr);
+                current = attr;
+            }
+            try writer.writeByte(printable(self.char(row, col)));
+        }
+        if (current != 0) try writer.writeAll("\x1b[0m");
+        try writer.writeAll("\x1b[K\r\n");
+    }
+}
+
+/// Write the characters only, without colors or trailing blanks. Used by
+/// the debugger.
+pub fn writeText(self: *const Console, writer: *Io.Writer) Io.Writer.Error!void {
+    for (0..self.size.rows) |row| {
+        var end = self.size.cols;
+        while (end > 0 and printable(self.char(row, end - 1)) == ' ') end -= 1;
+        for (0..end) |col| try writer.writeByte(printable(self.char(row, col)));
+        try writer.writeByte('\n');
+    }
+}
+
+fn writeAttribute(writer: *Io.Writer, attr: u8) Io.Writer.Error!void {
+    try writer.writeAll("\x1b[0");
+    if (attr & 0x08 != 0) try writer.writeAll(";1");
+    if (attr & 0x07 != 0) try writer.print(";3{d}", .{attr & 0x07});
+    if (attr & 0x70 != 0) try writer.print(";4{d}", .{(attr >> 4) & 0x07});
+    try writer.writeByte('m');
+}
+
+fn printable(c: u8) u8 {
+    return if (c >= 0x20 and c < 0x7F) c else ' ';
+}
@@ -0,0 +1,65 @@
+//! Pause, resume and stop for a VM running on another thread. A frontend or
+//! watchdog shares a `Control` with the VM through `Vm.setControl`, and
+//! `Vm.run` looks at it before each instruction or compiled block, so the
+//! VM only ever stops between instructions. Only these methods may be
+//! called from other threads; everything else about the VM belongs to the
+//! thread running it.
+
+const std = @import("std");
+const Futex = std.Thread.Futex;
+
+const Control = @This();
+
+pub const State = enum(u32) {
+    running,
+    paused,
+    stopped,
+};
+
+state: std.atomic.Value(u32) = .init(@intFromEnum(State.running)),
+/// Whether the VM is blocked in `checkpoint`, so its state is safe to read.
+parked: std.atomic.Value(bool) = .init(false),
+
+pub fn get(self: *const Control) State {
+    return @enumFromInt(self.state.load(.acquire));
+}
+
+/// Hold the VM at its next checkpoint. Does nothing once stopped.
+pub fn pause(self: *Control) void {
+    _ = self.state.cmpxchgStrong(@intFromEnum(State.running), @intFromEnum(State.paused), .acq_rel, .acquire);
+}
+
+/// Let a paused VM carry on.
+pub fn unpause(self: *Control) void {
+    if (self.state.cmpxchgStrong(@intFromEnum(State.paused), @intFromEnum(State.running), .acq_rel, .acquire) == null) {
+        Futex.wake(&self.state, 1);
+    }
+}
+
+/// Make `run` return `error.Stopped` at its next checkpoint, even while
+/// paused. A stopped VM stays stopped.
+pub fn stop(self: *Control) void {
+    self.state.store(@intFromEnum(State.stopped), .release);
+    Futex.wake(&self.state, 1);
+}
+
+/// Whether the VM has reached a checkpoint since `pause` and is waiting.
+pub fn isParked(self: *const Control) bool {
+    return self.parked.load(.acquire);
+}
+
+/// Called by the VM between instructions. Blocks while paused.
+pub fn checkpoint(self: *Control) error{Stopped}!void {
+    while (true) {
+        const state = self.state.load(.acquire);
+        switch (@as(State, @enumFromInt(state))) {
+            .running => return,
+            .stopped => return error.Stopped,
+            .paused => {
+                self.parked.store(true, .release);
+                defer self.parked.store(false, .release);
+                Futex.wait(&self.state, state);
+            },
+        }
+    }
+}
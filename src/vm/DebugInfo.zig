@@ -0,0 +1,189 @@
+//! Source locations embedded in bytecode built with `-g`, so a runtime error
+//! can point at the line of source that raised it. Bytecode built with
+//! `--symbols` carries the same section with the labels but no files or
+//! lines.
+//!
+//! The section follows the data section and is not loaded into memory. The
+//! header records its size next to the text and data sizes (see
+//! `features.Layout`). Layout, all integers little-endian:
+//!
+//! | Field | Encoding |
+//! |---|---|
+//! | text length | `u32`, where the last line ends |
+//! | files | `u32` count, each a file name |
+//! | symbols | `u32` count, each a label name and its `u64` address |
+//! | lines | `u32` count, each a `u32` text offset, `u32` file index, `u32` line and `u32` column |
+//!
+//! Strings are a `u32` length and the bytes. Symbols are in address order and
+//! lines in offset order, one for every statement that emitted text, so the
+//! line of an instruction is the last one at or before its address.
+
+const std = @import("std");
+const Io = std.Io;
+const mem = std.mem;
+const Allocator = mem.Allocator;
+
+const DebugInfo = @This();
+
+pub const Symbol = struct {
+    name: []const u8,
+    /// Address of the label once loaded, counting the data section from the
+    /// end of the text section.
+    addr: u64,
+};
+
+pub const Line = struct {
+    /// Offset of the first byte of the statement in the text section.
+    offset: u32,
+    /// Index into `files`.
+    file: u32,
+    line: u32,
+    column: u32,
+};
+
+pub const Location = struct {
+    file: []const u8,
+    line: usize,
+    column: usize,
+
+    pub fn format(self: Location, writer: *Io.Writer) Io.Writer.Error!void {
+        try writer.print("{s}:{d}:{d}", .{ self.file, self.line, self.column });
+    }
+};
+
+/// Owns every slice below.
+arena: std.heap.ArenaAllocator,
+text_len: u32,
+files: []const []const u8,
+symbols: []const Symbol,
+lines: []const Line,
+
+pub fn deinit(self: *DebugInfo) void {
+    self.arena.deinit();
+}
+
+/// Source location of the instruction at `addr`, or null for an address
+/// before the first line or past the text section.
+pub fn locate(self: *const DebugInfo, addr: usize) ?Location {
+    if (addr >= self.text_len) return null;
+    // Index of the first line after `addr`.
+    const after = std.sort.upperBound(Line, self.lines, addr, struct {
+        fn order(target: usize, line: Line) std.math.Order {
+            return std.math.order(target, line.offset);
+        }
+    }.order);
+    if (after == 0) return null;
+    const line = self.lines[after - 1];
+    return .{ .file = self.files[line.file], .line = line.line, .column = line.column };
+}
+
+/// The last label at or before `addr`, or null when every label is past it.
+pub fn nearestSymbol(self: *const DebugInfo, addr: usize) ?Symbol {
+    // Index of the first symbol after `addr`.
+    const after = std.sort.upperBound(Symbol, self.symbols, addr, struct {
+        fn order(target: usize, symbol: Symbol) std.math.Order {
+            return std.math.order(target, symbol.addr);
+        }
+    }.order);
+    return if (after == 0) null else self.symbols[after - 1];
+}
+
+pub const SymbolOrder = enum { address, name };
+
+/// Write every label on its own line as its address, `text` or `data` for
+/// the section it is in, and its name, in `order`.
+pub fn writeSymbols(self: *const DebugInfo, gpa: Allocator, writer: *Io.Writer, order: SymbolOrder) !void {
+    const sorted = try gpa.dupe(Symbol, self.symbols);
+    defer gpa.free(sorted);
+    if (order == .name) mem.sort(Symbol, sorted, {}, struct {
+        fn lessThan(_: void, a: Symbol, b: Symbol) bool {
+            return mem.lessThan(u8, a.name, b.name);
+        }
+    }.lessThan);
+    for (sorted) |symbol| {
+        const section = if (symbol.addr < self.text_len) "text" else "data";
+        try writer.print("0x{x:0>4}  {s}  {s}\n", .{ symbol.addr, section, symbol.name });
+    }
+}
+
+pub fn write(self: *const DebugInfo, writer: *Io.Writer) Io.Writer.Error!void {
+    try writer.writeInt(u32, self.text_len, .little);
+    try writer.writeInt(u32, @intCast(self.files.len), .little);
+    for (self.files) |file| try writeString(writer, file);
+
+    try writer.writeInt(u32, @intCast(self.symbols.len), .little);
+    for (self.symbols) |symbol| {
+        try writeString(writer, symbol.name);
+        try writer.writeInt(u64, symbol.addr, .little);
+    }
+
+    try writer.writeInt(u32, @intCast(self.lines.len), .little);
+    for (self.lines) |line| {
+        try writer.writeInt(u32, line.offset, .little);
+        try writer.writeInt(u32, line.file, .little);
+        try writer.writeInt(u32, line.line, .little);
+        try writer.writeInt(u32, line.column, .little);
+    }
+}
+
+/// Decode a section written by `write`. Returns `error.InvalidDebugInfo`
+/// for anything else. Copies what it needs out of `bytes`.
+pub fn read(gpa: Allocator, bytes: []const u8) !DebugInfo {
+    var arena = std.heap.ArenaAllocator.init(gpa);
+    errdefer arena.deinit();
+    var decoder = Decoder{ .reader = .fixed(bytes), .gpa = arena.allocator() };
+
+    const text_len = try decoder.int(u32);
+    const files = try decoder.gpa.alloc([]const u8, try decoder.count(4));
+    for (files) |*file| file.* = try decoder.string();
+
+    const symbols = try decoder.gpa.alloc(Symbol, try decoder.count(4 + 8));
+    for (symbols, 0..) |*symbol, i| {
+        symbol.* = .{ .name = try decoder.string(), .addr = try decoder.int(u64) };
+        if (i > 0 and symbol.addr < symbols[i - 1].addr) return error.InvalidDebugInfo;
+    }
+
+    const lines = try decoder.gpa.alloc(Line, try decoder.count(4 * 4));
+    for (lines, 0..) |*line, i| {
+        line.* = .{
+            .offset = try decoder.int(u32),
+            .file = try decoder.int(u32),
+            .line = try decoder.int(u32),
+            .column = try decoder.int(u32),
+        };
+        if (line.file >= files.len) return error.InvalidDebugInfo;
+        if (line.offset >= text_len) return error.InvalidDebugInfo;
+        if (i > 0 and line.offset < lines[i - 1].offset) return error.InvalidDebugInfo;
+    }
+
+    if (decoder.reader.seek != decoder.reader.end) return error.InvalidDebugInfo;
+    return .{ .arena = arena, .text_len = text_len, .files = files, .symbols = symbols, .lines = lines };
+}
+
+fn writeString(writer: *Io.Writer, bytes: []const u8) Io.Writer.Error!void {
+    try writer.writeInt(u32, @intCast(bytes.len), .little);
+    try writer.writeAll(bytes);
+}
+
+const Decoder = struct {
+    reader: Io.Reader,
+    gpa: Allocator,
+
+    fn int(self: *Decoder, comptime T: type) !T {
+        return self.reader.takeInt(T, .little) catch error.InvalidDebugInfo;
+    }
+
+    /// A `u32` count of records at least `record_size` bytes each, which
+    /// must fit in what is left of the input.
+    fn count(self: *Decoder, record_size: usize) !usize {
+        const n = try self.int(u32);
+        if (n > (self.reader.end - self.reader.seek) / record_size) return error.InvalidDebugInfo;
+        return n;
+    }
+
+    fn string(self: *Decoder) ![]const u8 {
+        const len = try self.int(u32);
+        const bytes = self.reader.take(len) catch return error.InvalidDebugInfo;
+        return self.gpa.dupe(u8, bytes);
+    }
+};
@@ -0,0 +1,185 @@
+//! Host window that `sys_display` shows the framebuffer in and that
+//! `sys_poll_key` reads key events from.
+//!
+//! The VM only holds this interface, like `Clipboard`, so it never links a
+//! windowing library itself. `Window` opens one with SDL2 in builds made
+//! with `-Ddisplay=true`; in other builds it fails to open with
+//! `error.DisplayUnsupported`.
+
+const std = @import("std");
+const build_options = @import("build_options");
+const Framebuffer = @import("Framebuffer.zig");
+
+const Display = @This();
+
+ptr: *anyopaque,
+/// Show `pixels`, `Framebuffer.width` by `Framebuffer.height` RGBA pixels.
+/// Fails with `error.DisplayClosed` once the user has closed the window.
+presentFn: *const fn (ptr: *anyopaque, pixels: []const u8) anyerror!void,
+/// Take the oldest key event that has not been polled yet, if any.
+pollKeyFn: *const fn (ptr: *anyopaque) ?Key = noKeys,
+
+pub fn present(self: Display, pixels: []const u8) !void {
+    return self.presentFn(self.ptr, pixels);
+}
+
+pub fn pollKey(self: Display) ?Key {
+    return self.pollKeyFn(self.ptr);
+}
+
+fn noKeys(_: *anyopaque) ?Key {
+    return null;
+}
+
+/// A key pressed or released. `code` is the ASCII character the key types,
+/// with Enter as `'\n'`, or one of the codes below for keys that type none.
+pub const Key = struct {
+    code: u32,
+    pressed: bool,
+
+    pub const up = 0x100;
+    pub const down = 0x101;
+    pub const left = 0x102;
+    pub const right = 0x103;
+};
+
+pub const Window = if (build_options.display) SdlWindow else NoWindow;
+
+pub const OpenError = error{
+    /// Built without `-Ddisplay=true`.
+    DisplayUnsupported,
+    /// SDL could not create the window, e.g. without a graphical session.
+    DisplayUnavailable,
+};
+
+const c = if (build_options.display) @cImport(@cInclude("SDL2/SDL.h")) else struct {};
+
+const SdlWindow = struct {
+    window: *c.SDL_Window,
+    renderer: *c.SDL_Renderer,
+    texture: *c.SDL_Texture,
+    closed: bool,
+    /// Key events seen while pumping events, oldest at `keys_start`. Once
+    /// it is full, new events are dropped until the program polls.
+    keys: [64]Key,
+    keys_start: usize,
+    keys_len: usize,
+
+    /// Every framebuffer pixel is drawn as a square this many pixels wide.
+    const scale = 3;
+
+    pub fn open(title: [:0]const u8) OpenError!SdlWindow {
+        if (c.SDL_Init(c.SDL_INIT_VIDEO) != 0) return error.DisplayUnavailable;
+        errdefer c.SDL_Quit();
+
+        const window = c.SDL_CreateWindow(
+            title.ptr,
+            c.SDL_WINDOWPOS_UNDEFINED,
+            c.SDL_WINDOWPOS_UNDEFINED,
+            Framebuffer.width * scale,
+            Framebuffer.height * scale,
+            0,
+        ) orelse return error.DisplayUnavailable;
+        errdefer c.SDL_DestroyWindow(window);
+        const renderer = c.SDL_CreateRenderer(window, -1, 0) orelse return error.DisplayUnavailable;
+        errdefer c.SDL_DestroyRenderer(renderer);
+        const texture = c.SDL_CreateTexture(
+            renderer,
+            c.SDL_PIXELFORMAT_RGBA32,
+            c.SDL_TEXTUREACCESS_STREAMING,
+            Framebuffer.width,
+            Framebuffer.height,
+        ) orelse return error.DisplayUnavailable;
+
+        return .{
+            .window = window,
+            .renderer = renderer,
+            .texture = texture,
+            .closed = false,
+            .keys = undefined,
+            .keys_start = 0,
+            .keys_len = 0,
+        };
+    }
+
+    pub fn close(self: *SdlWindow) void {
+        c.SDL_DestroyTexture(self.texture);
+        c.SDL_DestroyRenderer(self.renderer);
+        c.SDL_DestroyWindow(self.window);
+        c.SDL_Quit();
+    }
+
+    pub fn display(self: *SdlWindow) Display {
+        return .{ .ptr = self, .presentFn = presentSdl, .pollKeyFn = pollKeySdl };
+    }
+
+    fn presentSdl(ptr: *anyopaque, pixels: []const u8) anyerror!void {
+        const self: *SdlWindow = @ptrCast(@alignCast(ptr));
+        self.pumpEvents();
+        if (self.closed) return error.DisplayClosed;
+
+        _ = c.SDL_UpdateTexture(self.texture, null, pixels.ptr, Framebuffer.width * Framebuffer.bytes_per_pixel);
+        _ = c.SDL_RenderClear(self.renderer);
+        _ = c.SDL_RenderCopy(self.renderer, self.texture, null, null);
+        c.SDL_RenderPresent(self.renderer);
+    }
+
+    fn pollKeySdl(ptr: *anyopaque) ?Key {
+        const self: *SdlWindow = @ptrCast(@alignCast(ptr));
+        self.pumpEvents();
+        if (self.keys_len == 0) return null;
+        const key = self.keys[self.keys_start];
+        self.keys_start = (self.keys_start + 1) % self.keys.len;
+        self.keys_len -= 1;
+        return key;
+    }
+
+    fn pumpEvents(self: *SdlWindow) void {
+        var event: c.SDL_Event = undefined;
+        while (c.SDL_PollEvent(&event) != 0) {
+            switch (event.type) {
+                c.SDL_QUIT => self.closed = true,
+                c.SDL_KEYDOWN, c.SDL_KEYUP => {
+                    if (event.key.repeat != 0 or self.keys_len == self.keys.len) continue;
+                    const code = keyCode(event.key.keysym.sym) orelse continue;
+                    self.keys[(self.keys_start + self.keys_len) % self.keys.len] = .{
+                        .code = code,
+                        .pressed = event.type == c.SDL_KEYDOWN,
+                    };
+                    self.keys_len += 1;
+                },
+                else => {},
+            }
+        }
+    }
+
+    fn keyCode(sym: c.SDL_Keycode) ?u32 {
+        return switch (sym) {
+            c.SDLK_RETURN => '\n',
+            c.SDLK_UP => Key.up,
+            c.SDLK_DOWN => Key.down,
+            c.SDLK_LEFT => Key.left,
+            c.SDLK_RIGHT => Key.right,
+            else => if (sym > 0 and sym < 0x80) @intCast(sym) else null,
+        };
+    }
+};
+
+const NoWindow = struct {
+    pub fn open(title: [:0]const u8) OpenError!NoWindow {
+        _ = title;
+        return error.DisplayUnsupported;
+    }
+
+    pub fn close(self: *NoWindow) void {
+        _ = self;
+    }
+
+    pub fn display(self: *NoWindow) Display {
+        return .{ .ptr = self, .presentFn = presentNothing };
+    }
+
+    fn presentNothing(_: *anyopaque, _: []const u8) anyerror!void {
+        return error.DisplayUnsupported;
+    }
+};
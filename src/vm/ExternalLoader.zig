@@ -3,6 +3,7 @@ const Allocator = std.mem.Allocator;
 const DynLib = std.DynLib;
 const Vm = @import("Vm.zig");
 const Mmu = @import("memory/Mmu.zig");
+const Block = @import("memory/Block.zig");
 const Register = @import("register.zig").Register;
 const DataSize = @import("../parser/immediate.zig").DataSize;
 const Immediate = @import("../parser/immediate.zig").Immediate;
@@ -155,6 +156,10 @@ const float_arg_regs_dd = [6]Register{ .dd0, .dd1, .dd2, .dd3, .dd4, .dd5 };
 /// overflow).
 const MAX_ARGS = 64;
 
+/// Bytes after a `ptr` argument into VM memory that native code may use.
+/// They are contiguous in host memory even where a page boundary falls.
+pub const ptr_window = Block.page_size;
+
 fn popVm(vm: *Vm, size: DataSize) !Immediate {
     const current_sp = vm.regs.sp();
     if (current_sp + size.sizeInBytes() > vm.mmu.size()) {
@@ -208,6 +213,10 @@ pub fn call(func_ptr: *anyopaque, ret_type: FfiType, arg_types: []const FfiType,
 
     var arg_ptrs: [MAX_ARGS]?*anyopaque = undefined;
 
+    var loans: [MAX_ARGS]Mmu.Loan = undefined;
+    var loan_count: usize = 0;
+    defer for (loans[0..loan_count]) |loan| loan.deinit(vm.mmu.gpa);
+
     var int_count: usize = 0;
     var float_count: usize = 0;
     var total_overflow: usize = 0;
@@ -291,9 +300,15 @@ pub fn call(func_ptr: *anyopaque, ret_type: FfiType, arg_types: []const FfiType,
                 if (vm_addr == 0) {
                     arg_values_ptr[i] = null;
                 } else if (vm_addr < vm.mmu.size()) {
-                    // Address falls within VM memory, resolve to host pointer
-                    const host = vm.mmu.resolveHostPtr(@intCast(vm_addr));
-                    arg_values_ptr[i] = if (host) |h| @ptrCast(h) else null;
+                    // Address falls within VM memory, lend it to the native
+                    // function as one contiguous range
+                    if (try vm.mmu.lend(@intCast(vm_addr), ptr_window)) |loan| {
+                        loans[loan_count] = loan;
+                        loan_count += 1;
+                        arg_values_ptr[i] = @ptrCast(loan.ptr);
+                    } else {
+                        arg_values_ptr[i] = null;
+                    }
                 } else {
                     // Address is outside VM memory, treat as a raw host pointer
                     // (e.g. returned by a previous FFI call like TextFormat)
@@ -333,8 +348,8 @@ pub fn call(func_ptr: *anyopaque, ret_type: FfiType, arg_types: []const FfiType,
                         stack_read_idx += 1;
                         break :blk v;
                     };
-                    const host_ptr = vm.mmu.resolveHostPtr(@intCast(vm_addr)) orelse return error.AddressOutOfBounds;
-                    @memcpy(arg_struct_bufs[i][0..struct_sz], host_ptr[0..struct_sz]);
+                    const bytes = try vm.mmu.readSlice(@intCast(vm_addr), struct_sz);
+                    @memcpy(arg_struct_bufs[i][0..struct_sz], bytes);
                     arg_ptrs[i] = @ptrCast(&arg_struct_bufs[i]);
                 } else {
                     return error.VoidArgumentType;
@@ -362,6 +377,7 @@ pub fn call(func_ptr: *anyopaque, ret_type: FfiType, arg_types: []const FfiType,
         ret_storage,
         if (arg_types.len > 0) @ptrCast(&arg_ptrs) else null,
     );
+    for (loans[0..loan_count]) |loan| try vm.mmu.settle(loan);
 
     const ret_v = @intFromEnum(ret_type);
     if (ret_v == @intFromEnum(FfiType.void)) {
@@ -381,7 +397,6 @@ pub fn call(func_ptr: *anyopaque, ret_type: FfiType, arg_types: []const FfiType,
     } else if (ret_type.isStruct()) {
         const dest_addr = vm.regs.get(.q0).asU64();
         const struct_sz = ret_type.structSize();
-        const host_ptr = vm.mmu.resolveHostPtr(@intCast(dest_addr)) orelse return error.AddressOutOfBounds;
-        @memcpy(host_ptr[0..struct_sz], ret_struct_buf[0..struct_sz]);
+        try vm.mmu.writeSlice(@intCast(dest_addr), ret_struct_buf[0..struct_sz]);
     }
 }
@@ -0,0 +1,136 @@
+//! Descriptor table mapping the file descriptors seen by a program to host
+//! descriptors. Descriptors 0, 1 and 2 start out bound to the host's stdin,
+//! stdout and stderr, so programs can redirect them like any other file.
+
+const std = @import("std");
+const posix = std.posix.system;
+const Allocator = std.mem.Allocator;
+
+const FileTable = @This();
+
+pub const Fd = i32;
+
+/// A descriptor and the host descriptor behind it.
+pub const Binding = struct {
+    fd: Fd,
+    host: Fd,
+};
+
+entries: std.AutoHashMap(Fd, Fd),
+
+pub fn init(gpa: Allocator) !FileTable {
+    var entries = std.AutoHashMap(Fd, Fd).init(gpa);
+    errdefer entries.deinit();
+
+    try entries.put(0, 0);
+    try entries.put(1, 1);
+    try entries.put(2, 2);
+
+    return FileTable{ .entries = entries };
+}
+
+pub fn deinit(self: *FileTable) void {
+    var iter = self.entries.valueIterator();
+    while (iter.next()) |host| closeHost(host.*);
+    self.entries.deinit();
+}
+
+/// Look up the host descriptor behind `fd`.
+pub fn get(self: *FileTable, fd: Fd) ?Fd {
+    return self.entries.get(fd);
+}
+
+/// Register `host` under the lowest unused descriptor and return it.
+pub fn add(self: *FileTable, host: Fd) !Fd {
+    var fd: Fd = 0;
+    while (self.entries.contains(fd)) fd += 1;
+    try self.entries.put(fd, host);
+    return fd;
+}
+
+/// Unmap `fd` and close its host descriptor. Returns false if `fd` was not
+/// open.
+pub fn close(self: *FileTable, fd: Fd) bool {
+    const entry = self.entries.fetchRemove(fd) orelse return false;
+    closeHost(entry.value);
+    return true;
+}
+
+/// Duplicate `fd` onto the lowest unused descriptor.
+pub fn dup(self: *FileTable, fd: Fd) !?Fd {
+    const host = self.get(fd) orelse return null;
+    const copy = toFd(posix.dup(host)) orelse return null;
+    errdefer closeHost(copy);
+    return try self.add(copy);
+}
+
+/// Make `new` refer to the same file as `old`, closing whatever `new`
+/// referred to before.
+pub fn dup2(self: *FileTable, old: Fd, new: Fd) !?Fd {
+    if (new < 0) return null;
+    const host = self.get(old) orelse return null;
+    if (old == new) return new;
+
+    const copy = toFd(posix.dup(host)) orelse return null;
+    errdefer closeHost(copy);
+    if (try self.entries.fetchPut(new, copy)) |previous| closeHost(previous.value);
+    return new;
+}
+
+/// Every descriptor in order, bound to a duplicate of its host descriptor
+/// that stays open when the program closes its own, or to the same one for
+/// the standard streams. Release them with `closeBindings`.
+pub fn save(self: *FileTable, gpa: Allocator) ![]Binding {
+    const bindings = try gpa.alloc(Binding, self.entries.count());
+    var len: usize = 0;
+    errdefer {
+        closeBindings(bindings[0..len]);
+        gpa.free(bindings);
+    }
+    var iter = self.entries.iterator();
+    while (iter.next()) |entry| : (len += 1) {
+        const host = entry.value_ptr.*;
+        const copy = if (host <= 2) host else toFd(posix.dup(host)) orelse return error.DescriptorUnavailable;
+        bindings[len] = .{ .fd = entry.key_ptr.*, .host = copy };
+    }
+    std.mem.sort(Binding, bindings, {}, struct {
+        fn lessThan(_: void, a: Binding, b: Binding) bool {
+            return a.fd < b.fd;
+        }
+    }.lessThan);
+    return bindings;
+}
+
+/// Close the host descriptors `save` duplicated.
+pub fn closeBindings(bindings: []const Binding) void {
+    for (bindings) |binding| closeHost(binding.host);
+}
+
+/// Close every descriptor and open `bindings` in their place, duplicating
+/// their host descriptors again so the same bindings can be restored more
+/// than once.
+pub fn restore(self: *FileTable, bindings: []const Binding) !void {
+    var iter = self.entries.valueIterator();
+    while (iter.next()) |host| closeHost(host.*);
+    self.entries.clearRetainingCapacity();
+    for (bindings) |binding| {
+        const host = if (binding.host <= 2) binding.host else toFd(posix.dup(binding.host)) orelse return error.DescriptorUnavailable;
+        errdefer closeHost(host);
+        try self.entries.put(binding.fd, host);
+    }
+}
+
+/// Convert the result of a host call that returns a descriptor, treating
+/// negative values (or raw `-errno` results) as failure.
+pub fn toFd(rc: anytype) ?Fd {
+    const signed: isize = switch (@typeInfo(@TypeOf(rc)).int.signedness) {
+        .signed => @intCast(rc),
+        .unsigned => @bitCast(@as(usize, @intCast(rc))),
+    };
+    return if (signed < 0) null else @intCast(signed);
+}
+
+/// The host's own standard streams stay open for the lifetime of the process.
+fn closeHost(host: Fd) void {
+    if (host > 2) _ = posix.close(host);
+}
@@ -2,10 +2,16 @@ const Flags = @This();
 
 eq: bool,
 lt: bool,
+/// Unsigned carry or borrow out of the last integer add, sub or mul.
+carry: bool,
+/// Signed overflow of the last integer add, sub or mul.
+overflow: bool,
 
 pub fn init() Flags {
     return Flags{
         .eq = false,
         .lt = false,
+        .carry = false,
+        .overflow = false,
     };
 }
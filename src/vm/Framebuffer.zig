@@ -0,0 +1,85 @@
+//! Graphical framebuffer, enabled with `--display`.
+//!
+//! A `width` by `height` grid of 32-bit pixels mapped at the start of the
+//! MMIO window, row by row from the top left. Each pixel is four bytes in
+//! memory order red, green, blue and alpha, so a dword written to it reads
+//! as `0xAABBGGRR`. Programs draw by writing pixels and call `sys_display`
+//! to show them in the window.
+
+const std = @import("std");
+const mem = std.mem;
+const Allocator = mem.Allocator;
+const Device = @import("memory/Device.zig");
+const DataSize = @import("../parser/immediate.zig").DataSize;
+const Immediate = @import("../parser/immediate.zig").Immediate;
+
+const Framebuffer = @This();
+
+pub const width = 320;
+pub const height = 200;
+pub const bytes_per_pixel = 4;
+pub const size = width * height * bytes_per_pixel;
+
+pixels: []u8,
+gpa: Allocator,
+
+pub fn init(gpa: Allocator) !Framebuffer {
+    const pixels = try gpa.alloc(u8, size);
+    @memset(pixels, 0);
+    return .{ .pixels = pixels, .gpa = gpa };
+}
+
+pub fn deinit(self: *Framebuffer) void {
+    self.gpa.free(self.pixels);
+}
+
+/// The pixel at `x`, `y` as `0xAABBGGRR`.
+pub fn pixel(self: *const Framebuffer, x: usize, y: usize) u32 {
+    const at = (y * width + x) * bytes_per_pixel;
+    return mem.readInt(u32, self.pixels[at..][0..bytes_per_pixel], .little);
+}
+
+const vtable = Device.VTable{
+    .name = name,
+    .size = deviceSize,
+    .read = read,
+    .write = write,
+};
+
+pub fn device(self: *Framebuffer) Device {
+    return .{ .ptr = self, .vtable = &vtable };
+}
+
+fn name(_: *anyopaque) []const u8 {
+    return "Framebuffer";
+}
+
+fn deviceSize(_: *anyopaque) usize {
+    return size;
+}
+
+fn read(ptr: *anyopaque, offset: usize, sz: DataSize) anyerror!Immediate {
+    const self: *Framebuffer = @ptrCast(@alignCast(ptr));
+    const bytes = self.pixels[offset..];
+    return switch (sz) {
+        .byte => .{ .byte = bytes[0] },
+        .word => .{ .word = mem.readInt(u16, bytes[0..2], .little) },
+        .dword => .{ .dword = mem.readInt(u32, bytes[0..4], .little) },
+        .qword => .{ .qword = mem.readInt(u64, bytes[0..8], .little) },
+        .float => .{ .float = @bitCast(mem.readInt(u32, bytes[0..4], .little)) },
+        .double => .{ .double = @bitCast(mem.readInt(u64, bytes[0..8], .little)) },
+    };
+}
+
+fn write(ptr: *anyopaque, offset: usize, value: Immediate, sz: DataSize) anyerror!void {
+    const self: *Framebuffer = @ptrCast(@alignCast(ptr));
+    const bytes = self.pixels[offset..];
+    switch (sz) {
+        .byte => bytes[0] = value.asU8(),
+        .word => mem.writeInt(u16, bytes[0..2], value.asU16(), .little),
+        .dword => mem.writeInt(u32, bytes[0..4], value.asU32(), .little),
+        .qword => mem.writeInt(u64, bytes[0..8], value.asU64(), .little),
+        .float => mem.writeInt(u32, bytes[0..4], @bitCast(value.asF32()), .little),
+        .double => mem.writeInt(u64, bytes[0..8], @bitCast(value.asF64()), .little),
+    }
+}
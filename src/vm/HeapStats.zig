@@ -0,0 +1,84 @@
+//! Allocation bookkeeping for `sys_malloc` and `sys_free` and for the
+//! `sys_alloc` and `sys_dealloc` heap, enabled with `--debug-heap`. Every live allocation remembers its size and the address
+//! of the `syscall` instruction that created it so leaks can be traced back
+//! to the source.
+
+const std = @import("std");
+const Allocator = std.mem.Allocator;
+const Heap = @import("memory/Heap.zig");
+
+const HeapStats = @This();
+
+pub const Allocation = struct {
+    size: usize,
+    call_site: usize,
+};
+
+live: std.AutoArrayHashMap(usize, Allocation),
+total_allocs: usize,
+total_frees: usize,
+invalid_frees: usize,
+bytes_in_use: usize,
+peak_bytes: usize,
+
+pub fn init(gpa: Allocator) HeapStats {
+    return HeapStats{
+        .live = .init(gpa),
+        .total_allocs = 0,
+        .total_frees = 0,
+        .invalid_frees = 0,
+        .bytes_in_use = 0,
+        .peak_bytes = 0,
+    };
+}
+
+pub fn deinit(self: *HeapStats) void {
+    self.live.deinit();
+}
+
+pub fn recordAlloc(self: *HeapStats, addr: usize, size: usize, call_site: usize) !void {
+    try self.live.put(addr, .{ .size = size, .call_site = call_site });
+    self.total_allocs += 1;
+    self.bytes_in_use += size;
+    self.peak_bytes = @max(self.peak_bytes, self.bytes_in_use);
+}
+
+pub fn recordFree(self: *HeapStats, addr: usize) void {
+    if (self.live.fetchOrderedRemove(addr)) |entry| {
+        self.total_frees += 1;
+        self.bytes_in_use -= entry.value.size;
+    } else {
+        self.invalid_frees += 1;
+    }
+}
+
+/// Print a summary, with how fragmented `heap` is, followed by one line per
+/// leaked allocation to stderr.
+pub fn print(self: *HeapStats, heap: *const Heap) void {
+    std.debug.print("heap: {d} allocations, {d} frees, {d} invalid frees\n", .{
+        self.total_allocs,
+        self.total_frees,
+        self.invalid_frees,
+    });
+    std.debug.print("heap: peak usage {d} bytes, {d} bytes in {d} blocks still allocated\n", .{
+        self.peak_bytes,
+        self.bytes_in_use,
+        self.live.count(),
+    });
+    const fragmentation = heap.fragmentation();
+    std.debug.print("heap: {d} free bytes in {d} blocks, largest free block {d} bytes, {d:.1}% external fragmentation\n", .{
+        fragmentation.free_bytes,
+        fragmentation.free_blocks,
+        fragmentation.largest_free,
+        fragmentation.external(),
+    });
+
+    var iter = self.live.iterator();
+    while (iter.next()) |entry| {
+        std.debug.print("heap: leaked {d} bytes at 0x{x} allocated by syscall at 0x{x}\n", .{
+            entry.value_ptr.size,
+            entry.key_ptr.*,
+            entry.value_ptr.call_site,
+        });
+    }
+}
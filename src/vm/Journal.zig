@@ -0,0 +1,103 @@
+//! Bounded history of executed instructions used for reverse stepping.
+//!
+//! Before every instruction the VM records its registers and flags, and the
+//! MMU records the previous contents of every byte range it overwrites. Only
+//! the most recent `capacity` instructions are kept. Syscalls have effects on
+//! the host that cannot be undone, so the VM clears the history after each
+//! one.
+
+const std = @import("std");
+const Allocator = std.mem.Allocator;
+const ArrayList = std.array_list.Managed;
+const Registers = @import("register.zig").Registers;
+const Flags = @import("Flags.zig");
+
+const Journal = @This();
+
+pub const default_capacity: usize = 1024;
+
+pub const MemoryWrite = struct {
+    addr: usize,
+    old: []u8,
+};
+
+pub const Entry = struct {
+    regs: Registers,
+    flags: Flags,
+    halted: bool,
+    writes: ArrayList(MemoryWrite),
+
+    pub fn deinit(self: *Entry, gpa: Allocator) void {
+        for (self.writes.items) |write| gpa.free(write.old);
+        self.writes.deinit();
+    }
+};
+
+entries: []Entry,
+start: usize,
+len: usize,
+gpa: Allocator,
+
+pub fn init(capacity: usize, gpa: Allocator) !Journal {
+    return Journal{
+        .entries = try gpa.alloc(Entry, @max(capacity, 1)),
+        .start = 0,
+        .len = 0,
+        .gpa = gpa,
+    };
+}
+
+pub fn deinit(self: *Journal) void {
+    self.clear();
+    self.gpa.free(self.entries);
+}
+
+/// Number of instructions that can currently be undone.
+pub fn count(self: *Journal) usize {
+    return self.len;
+}
+
+/// Start a new entry for the instruction about to execute, evicting the
+/// oldest one if the journal is full.
+pub fn begin(self: *Journal, regs: Registers, flags: Flags, halted: bool) void {
+    if (self.len == self.entries.len) {
+        self.entries[self.start].deinit(self.gpa);
+        self.start = (self.start + 1) % self.entries.len;
+        self.len -= 1;
+    }
+
+    const index = (self.start + self.len) % self.entries.len;
+    self.entries[index] = .{
+        .regs = regs,
+        .flags = flags,
+        .halted = halted,
+        .writes = .init(self.gpa),
+    };
+    self.len += 1;
+}
+
+/// Remember the bytes at `addr` before they are overwritten by the current
+/// instruction. Takes ownership of `old`, which must have been allocated
+/// with the journal's allocator.
+pub fn recordWrite(self: *Journal, addr: usize, old: []u8) !void {
+    errdefer self.gpa.free(old);
+    if (self.len == 0) return self.gpa.free(old);
+    const entry = &self.entries[(self.start + self.len - 1) % self.entries.len];
+    try entry.writes.append(.{ .addr = addr, .old = old });
+}
+
+/// Remove and return the most recent entry. The caller owns the entry and
+/// must call `Entry.deinit` on it.
+pub fn pop(self: *Journal) ?Entry {
+    if (self.len == 0) return null;
+    self.len -= 1;
+    return self.entries[(self.start + self.len) % self.entries.len];
+}
+
+pub fn clear(self: *Journal) void {
+    while (self.pop()) |entry| {
+        var e = entry;
+        e.deinit(self.gpa);
+    }
+    self.start = 0;
+}
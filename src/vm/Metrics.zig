@@ -0,0 +1,30 @@
+//! Counters kept on every run and read with `Vm.metrics`, so a program
+//! embedding the VM can report what a run did without tracing it.
+
+const Metrics = @This();
+
+/// Syscalls are counted by number up to here. Every syscall in the table is
+/// below it.
+pub const syscall_slots = 0x100;
+
+/// Instructions executed, including the one that halted the VM.
+instructions: u64 = 0,
+/// Completed calls to each syscall number.
+syscalls: [syscall_slots]u64 = [_]u64{0} ** syscall_slots,
+/// Furthest `sp` has been below `isp`, in bytes.
+peak_stack_depth: usize = 0,
+/// Most bytes mapped at once: the program, memory, the console and every
+/// live `sys_malloc` block.
+peak_memory: usize = 0,
+
+/// Calls made to syscall `number`.
+pub fn syscallCount(self: *const Metrics, number: usize) u64 {
+    return if (number < syscall_slots) self.syscalls[number] else 0;
+}
+
+/// Calls made to every syscall together.
+pub fn totalSyscalls(self: *const Metrics) u64 {
+    var total: u64 = 0;
+    for (self.syscalls) |count| total += count;
+    return total;
+}
@@ -0,0 +1,307 @@
+//! Everything a program can observe of a VM at one point: its registers,
+//! flags, memory, heap bookkeeping, open descriptors and whether it halted.
+//! `Vm.saveState` takes one and `Vm.restoreState` puts a VM loaded with the
+//! same program and options back in it, so a run can be resumed or replayed
+//! from there. Devices in the MMIO window are not part of it.
+//!
+//! `write` and `read` keep a state in a file, for `--snapshot-on-exit` and
+//! `--resume`. Layout, all integers little-endian:
+//!
+//! | Field | Encoding |
+//! |---|---|
+//! | magic | `NYXS` |
+//! | version | `u32`, 2 |
+//! | registers | `u64` for each general-purpose, floating-point, special and config register |
+//! | flags | `u8` with `eq`, `lt`, `carry` and `overflow` from the lowest bit |
+//! | halted | `u8` |
+//! | exit status | `u16`, `0xffff` until the program exits |
+//! | blocks | `u32` count, each a `u8` name length, the name and a `u64` length |
+//! | memory | `u8` fill and a `u32` count of pages, each a `u64` address, a `u32` length and the bytes |
+//! | heap | `u64` base, `u64` break, then the free ranges and the allocations, each a `u32` count of `u64` address and `u64` length pairs |
+//! | files | `u32` count, each an `i32` descriptor and the `i32` host descriptor |
+//!
+//! Only pages holding something other than the fill byte are kept. Blocks
+//! that `sys_malloc` added after the VM was loaded are listed with the rest
+//! and added again on restore. A file
+//! only lists descriptors bound to the host's standard streams, since other
+//! host descriptors mean nothing to another process.
+
+const std = @import("std");
+const Io = std.Io;
+const mem = std.mem;
+const Allocator = mem.Allocator;
+const ArrayList = std.array_list.Managed;
+const Registers = @import("register.zig").Registers;
+const Flags = @import("Flags.zig");
+const FileTable = @import("FileTable.zig");
+const Heap = @import("memory/Heap.zig");
+const Block = @import("memory/Block.zig");
+const Vm = @import("Vm.zig");
+
+const State = @This();
+
+const magic = "NYXS";
+const version: u32 = 2;
+const no_exit_status: u16 = 0xffff;
+
+/// Bytes of memory starting at `addr`, at most `Block.page_size` of them.
+pub const Page = struct {
+    addr: usize,
+    bytes: []const u8,
+};
+
+/// A block of memory backing part of the address space.
+pub const BlockInfo = struct {
+    name: []const u8,
+    len: usize,
+};
+
+/// Owns every slice below.
+arena: std.heap.ArenaAllocator,
+regs: Registers,
+flags: Flags,
+halted: bool,
+exit_status: ?u8,
+/// Blocks in address order. Those a VM is loaded with must match the VM the
+/// state is restored into; any after them came from `sys_malloc`.
+blocks: []const BlockInfo,
+/// Total length of `blocks`.
+memory_size: usize,
+/// What every byte outside `pages` reads as.
+fill: u8,
+/// In address order.
+pages: []const Page,
+heap_base: usize,
+heap_brk: usize,
+/// Unused heap ranges in address order.
+free_ranges: []const Heap.Range,
+/// Live heap allocations in address order.
+allocations: []const Heap.Range,
+/// Host descriptors other than the standard streams are duplicates owned
+/// by the state.
+files: []const FileTable.Binding,
+
+/// Copy the state of `vm`. Release it with `deinit`.
+pub fn capture(vm: *Vm, gpa: Allocator) !State {
+    var arena = std.heap.ArenaAllocator.init(gpa);
+    errdefer arena.deinit();
+    const allocator = arena.allocator();
+
+    const memory_size = vm.mmu.size();
+    const fill = vm.mmu.fill;
+    const blocks = try allocator.alloc(BlockInfo, vm.mmu.blocks.items.len);
+    for (blocks, vm.mmu.blocks.items) |*info, block| {
+        info.* = .{ .name = try allocator.dupe(u8, block.block_name), .len = block.len };
+    }
+    var pages = ArrayList(Page).init(allocator);
+    const buffer = try gpa.alloc(u8, Block.page_size);
+    defer gpa.free(buffer);
+    var addr: usize = 0;
+    while (addr < memory_size) : (addr += Block.page_size) {
+        const len = try vm.mmu.readInto(addr, buffer[0..@min(Block.page_size, memory_size - addr)]);
+        const bytes = buffer[0..len];
+        if (mem.allEqual(u8, bytes, fill)) continue;
+        try pages.append(.{ .addr = addr, .bytes = try allocator.dupe(u8, bytes) });
+    }
+
+    const free_ranges = try allocator.dupe(Heap.Range, vm.heap.free_list.items);
+    const allocations = try vm.heap.allocations(allocator);
+    const files = try vm.files.save(allocator);
+
+    return .{
+        .arena = arena,
+        .regs = vm.regs,
+        .flags = vm.flags,
+        .halted = vm.halted,
+        .exit_status = vm.exit_status,
+        .blocks = blocks,
+        .memory_size = memory_size,
+        .fill = fill,
+        .pages = pages.items,
+        .heap_base = vm.heap.base,
+        .heap_brk = vm.heap.brk,
+        .free_ranges = free_ranges,
+        .allocations = allocations,
+        .files = files,
+    };
+}
+
+pub fn deinit(self: *State) void {
+    FileTable.closeBindings(self.files);
+    self.arena.deinit();
+}
+
+pub fn write(self: *const State, writer: *Io.Writer) Io.Writer.Error!void {
+    try writer.writeAll(magic);
+    try writer.writeInt(u32, version, .little);
+
+    for (self.regs.gpr) |value| try writer.writeInt(u64, value, .little);
+    for (self.regs.fpr) |value| try writer.writeInt(u64, value, .little);
+    for (self.regs.special) |value| try writer.writeInt(u64, value, .little);
+    for (self.regs.config) |value| try writer.writeInt(u64, value, .little);
+    try writer.writeByte(@as(u8, @intFromBool(self.flags.eq)) |
+        @as(u8, @intFromBool(self.flags.lt)) << 1 |
+        @as(u8, @intFromBool(self.flags.carry)) << 2 |
+        @as(u8, @intFromBool(self.flags.overflow)) << 3);
+    try writer.writeByte(@intFromBool(self.halted));
+    try writer.writeInt(u16, if (self.exit_status) |status| status else no_exit_status, .little);
+
+    try writer.writeInt(u32, @intCast(self.blocks.len), .little);
+    for (self.blocks) |block| {
+        try writer.writeByte(@intCast(block.name.len));
+        try writer.writeAll(block.name);
+        try writer.writeInt(u64, block.len, .little);
+    }
+    try writer.writeByte(self.fill);
+    try writer.writeInt(u32, @intCast(self.pages.len), .little);
+    for (self.pages) |page| {
+        try writer.writeInt(u64, page.addr, .little);
+        try writer.writeInt(u32, @intCast(page.bytes.len), .little);
+        try writer.writeAll(page.bytes);
+    }
+
+    try writer.writeInt(u64, self.heap_base, .little);
+    try writer.writeInt(u64, self.heap_brk, .little);
+    try writeRanges(writer, self.free_ranges);
+    try writeRanges(writer, self.allocations);
+
+    var standard: u32 = 0;
+    for (self.files) |file| standard += @intFromBool(file.host <= 2);
+    try writer.writeInt(u32, standard, .little);
+    for (self.files) |file| {
+        if (file.host > 2) continue;
+        try writer.writeInt(i32, file.fd, .little);
+        try writer.writeInt(i32, file.host, .little);
+    }
+}
+
+/// Decode a state written by `write`. Returns `error.InvalidState` for
+/// anything else. Copies what it needs out of `bytes`.
+pub fn read(gpa: Allocator, bytes: []const u8) !State {
+    var arena = std.heap.ArenaAllocator.init(gpa);
+    errdefer arena.deinit();
+    var decoder = Decoder{ .reader = .fixed(bytes), .gpa = arena.allocator() };
+
+    const header = decoder.reader.take(magic.len) catch return error.InvalidState;
+    if (!mem.eql(u8, header, magic)) return error.InvalidState;
+    if (try decoder.int(u32) != version) return error.InvalidState;
+
+    var regs = Registers.init();
+    for (&regs.gpr) |*value| value.* = try decoder.int(u64);
+    for (&regs.fpr) |*value| value.* = try decoder.int(u64);
+    for (&regs.special) |*value| value.* = try decoder.address();
+    for (&regs.config) |*value| value.* = try decoder.address();
+    const flag_bits = try decoder.int(u8);
+    if (flag_bits > 0xf) return error.InvalidState;
+    const flags = Flags{
+        .eq = flag_bits & 1 != 0,
+        .lt = flag_bits & 2 != 0,
+        .carry = flag_bits & 4 != 0,
+        .overflow = flag_bits & 8 != 0,
+    };
+    const halted = switch (try decoder.int(u8)) {
+        0 => false,
+        1 => true,
+        else => return error.InvalidState,
+    };
+    const exit_status: ?u8 = switch (try decoder.int(u16)) {
+        no_exit_status => null,
+        0...0xff => |status| @intCast(status),
+        else => return error.InvalidState,
+    };
+
+    const blocks = try decoder.gpa.alloc(BlockInfo, try decoder.count(1 + 8));
+    var memory_size: usize = 0;
+    for (blocks) |*block| {
+        block.name = try decoder.bytes(try decoder.int(u8));
+        block.len = try decoder.address();
+        memory_size = std.math.add(usize, memory_size, block.len) catch return error.InvalidState;
+    }
+    const fill = try decoder.int(u8);
+    const pages = try decoder.gpa.alloc(Page, try decoder.count(8 + 4));
+    for (pages, 0..) |*page, i| {
+        const addr = try decoder.address();
+        const len = try decoder.int(u32);
+        if (len > Block.page_size or addr > memory_size or len > memory_size - addr) return error.InvalidState;
+        if (i > 0 and addr < pages[i - 1].addr + pages[i - 1].bytes.len) return error.InvalidState;
+        page.* = .{ .addr = addr, .bytes = try decoder.bytes(len) };
+    }
+
+    const heap_base = try decoder.address();
+    const heap_brk = try decoder.address();
+    if (heap_brk < heap_base or heap_brk > memory_size) return error.InvalidState;
+    const free_ranges = try decoder.ranges(heap_base, heap_brk);
+    const allocations = try decoder.ranges(heap_base, heap_brk);
+
+    const files = try decoder.gpa.alloc(FileTable.Binding, try decoder.count(4 + 4));
+    for (files) |*file| {
+        file.* = .{ .fd = try decoder.int(i32), .host = try decoder.int(i32) };
+        if (file.fd < 0 or file.host < 0 or file.host > 2) return error.InvalidState;
+    }
+
+    if (decoder.reader.seek != decoder.reader.end) return error.InvalidState;
+    return .{
+        .arena = arena,
+        .regs = regs,
+        .flags = flags,
+        .halted = halted,
+        .exit_status = exit_status,
+        .blocks = blocks,
+        .memory_size = memory_size,
+        .fill = fill,
+        .pages = pages,
+        .heap_base = heap_base,
+        .heap_brk = heap_brk,
+        .free_ranges = free_ranges,
+        .allocations = allocations,
+        .files = files,
+    };
+}
+
+fn writeRanges(writer: *Io.Writer, ranges: []const Heap.Range) Io.Writer.Error!void {
+    try writer.writeInt(u32, @intCast(ranges.len), .little);
+    for (ranges) |range| {
+        try writer.writeInt(u64, range.addr, .little);
+        try writer.writeInt(u64, range.len, .little);
+    }
+}
+
+const Decoder = struct {
+    reader: Io.Reader,
+    gpa: Allocator,
+
+    fn int(self: *Decoder, comptime T: type) !T {
+        return self.reader.takeInt(T, .little) catch error.InvalidState;
+    }
+
+    fn address(self: *Decoder) !usize {
+        return std.math.cast(usize, try self.int(u64)) orelse error.InvalidState;
+    }
+
+    /// A `u32` count of records at least `record_size` bytes each, which
+    /// must fit in what is left of the input, so a corrupt count cannot ask
+    /// for a huge allocation.
+    fn count(self: *Decoder, record_size: usize) !usize {
+        const n = try self.int(u32);
+        if (n > (self.reader.end - self.reader.seek) / record_size) return error.InvalidState;
+        return n;
+    }
+
+    fn bytes(self: *Decoder, len: usize) ![]const u8 {
+        const slice = self.reader.take(len) catch return error.InvalidState;
+        return self.gpa.dupe(u8, slice);
+    }
+
+    /// Ranges in address order that do not overlap and lie between `start`
+    /// and `end`.
+    fn ranges(self: *Decoder, start: usize, end: usize) ![]const Heap.Range {
+        const result = try self.gpa.alloc(Heap.Range, try self.count(8 + 8));
+        var last = start;
+        for (result) |*range| {
+            range.* = .{ .addr = try self.address(), .len = try self.address() };
+            if (range.addr < last or range.addr > end or range.len > end - range.addr) return error.InvalidState;
+            last = range.addr + range.len;
+        }
+        return result;
+    }
+};
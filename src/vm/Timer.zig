@@ -0,0 +1,73 @@
+//! Programmable timer started with `sys_timer`. Every `period` instructions,
+//! or every `period` milliseconds of host time, it fires once and counts it
+//! as pending until the program collects it with `sys_timer_poll`. Counting
+//! instructions gives a time base that does not depend on how fast the host
+//! is; counting milliseconds keeps a game loop at a steady real-time rate.
+
+const std = @import("std");
+const posix = std.posix.system;
+
+const Timer = @This();
+
+pub const Unit = enum(u8) {
+    instructions = 0,
+    milliseconds = 1,
+};
+
+/// Instructions between clock reads in millisecond mode. Reading the clock
+/// after every instruction would cost more than the instruction itself.
+const clock_interval = 1024;
+
+/// Zero while the timer is stopped.
+period: u64 = 0,
+unit: Unit = .instructions,
+/// Instruction count or clock reading at which the timer fires next.
+deadline: u64 = 0,
+/// Times the timer fired since the program last polled it.
+pending: u64 = 0,
+
+/// Start counting from now, or stop the timer when `period` is 0. Pending
+/// expirations of the previous period are dropped.
+pub fn start(self: *Timer, period: u64, unit: Unit, instructions: u64) void {
+    self.* = .{ .period = period, .unit = unit };
+    if (period == 0) return;
+    self.deadline = switch (unit) {
+        .instructions => instructions,
+        .milliseconds => milliseconds(),
+    } +| period;
+}
+
+/// Return the expirations since the last poll and clear them.
+pub fn poll(self: *Timer, instructions: u64) u64 {
+    self.check(instructions, true);
+    defer self.pending = 0;
+    return self.pending;
+}
+
+/// Called by the VM after every instruction.
+pub inline fn update(self: *Timer, instructions: u64) void {
+    if (self.period != 0) self.check(instructions, false);
+}
+
+fn check(self: *Timer, instructions: u64, force: bool) void {
+    if (self.period == 0) return;
+    const now = switch (self.unit) {
+        .instructions => instructions,
+        .milliseconds => blk: {
+            if (!force and instructions % clock_interval != 0) return;
+            break :blk milliseconds();
+        },
+    };
+    if (now < self.deadline) return;
+    // Catch up on every period that passed, so a slow host still sees the
+    // right number of expirations.
+    const fired = (now - self.deadline) / self.period + 1;
+    self.pending +|= fired;
+    self.deadline +|= fired *| self.period;
+}
+
+fn milliseconds() u64 {
+    var ts: posix.timespec = undefined;
+    _ = posix.clock_gettime(posix.CLOCK.MONOTONIC, &ts);
+    return @as(u64, @intCast(ts.sec)) * 1000 + @as(u64, @intCast(ts.nsec)) / std.time.ns_per_ms;
+}
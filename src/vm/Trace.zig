@@ -0,0 +1,80 @@
+//! Prints every instruction the VM executes, with the registers and flags it
+//! changed, for `nyx run --trace`. Instructions are decoded by the
+//! disassembler, so they read the same as `nyx disasm` output:
+//!
+//! ```
+//! 0x0000  mov q0, 3                        ; q0 = 0x3 (3)
+//! 0x000a  cmp q0, 3                        ; eq = true
+//! ```
+
+const std = @import("std");
+const Io = std.Io;
+const mem = std.mem;
+const Allocator = mem.Allocator;
+const Vm = @import("Vm.zig");
+const Disassembler = @import("../disassembler/Disassembler.zig");
+
+const Trace = @This();
+
+/// Column at which the changes start.
+const comment_column = 40;
+
+disassembler: Disassembler,
+/// Always empty: jump and call targets are printed as addresses.
+labels: std.AutoHashMap(u64, void),
+writer: *Io.Writer,
+
+/// `bytecode` is the program image the VM was loaded from.
+pub fn init(bytecode: []const u8, writer: *Io.Writer, gpa: Allocator) !Trace {
+    return .{
+        .disassembler = try .init(bytecode, gpa),
+        .labels = .init(gpa),
+        .writer = writer,
+    };
+}
+
+pub fn deinit(self: *Trace) void {
+    self.labels.deinit();
+}
+
+/// Pass to `Vm.setTracer` to start printing.
+pub fn tracer(self: *Trace) Vm.Tracer {
+    return .{ .ptr = self, .traceFn = trace };
+}
+
+fn trace(ptr: *anyopaque, vm: *Vm, addr: usize, before: Vm.Snapshot) void {
+    const self: *Trace = @ptrCast(@alignCast(ptr));
+    self.writeStep(vm, addr, before) catch {};
+}
+
+/// Write one line for the instruction at `addr`, which has just run. The
+/// writer is flushed so the trace stays in step with the program's output.
+pub fn writeStep(self: *Trace, vm: *Vm, addr: usize, before: Vm.Snapshot) !void {
+    var inst_buffer: [256]u8 = undefined;
+    var inst: Io.Writer = .fixed(&inst_buffer);
+    if (self.disassembler.decode(addr)) |decoded| {
+        self.disassembler.writeInstruction(&inst, decoded, &self.labels) catch {};
+    } else |_| {
+        // Code written at runtime or outside the program cannot be decoded.
+        inst.writeAll("(unknown)") catch {};
+    }
+    try self.writer.print("0x{x:0>4}  {s}", .{ addr, inst.buffered() });
+
+    var changes_buffer: [1024]u8 = undefined;
+    var changes: Io.Writer = .fixed(&changes_buffer);
+    vm.writeChanges(before, &changes) catch {};
+    const text = mem.trimEnd(u8, changes.buffered(), "\n");
+    if (text.len > 0) {
+        try self.writer.splatByteAll(' ', comment_column -| (inst.buffered().len + 8) + 1);
+        try self.writer.writeAll("; ");
+        var lines = mem.splitScalar(u8, text, '\n');
+        var first = true;
+        while (lines.next()) |line| {
+            if (!first) try self.writer.writeAll(", ");
+            first = false;
+            try self.writer.writeAll(line);
+        }
+    }
+    try self.writer.writeByte('\n');
+    try self.writer.flush();
+}
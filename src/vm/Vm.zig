@@ -1,5 +1,7 @@
 const std = @import("std");
+const build_options = @import("build_options");
 const mem = std.mem;
+const Io = std.Io;
 const Allocator = mem.Allocator;
 const Registers = @import("register.zig").Registers;
 const Register = @import("register.zig").Register;
@@ -7,7 +9,23 @@ const DataSize = @import("../parser/immediate.zig").DataSize;
 const Immediate = @import("../parser/immediate.zig").Immediate;
 const Mmu = @import("memory/Mmu.zig");
 const Block = @import("memory/Block.zig");
+pub const Device = @import("memory/Device.zig");
+const Heap = @import("memory/Heap.zig");
 const Flags = @import("Flags.zig");
+const FileTable = @import("FileTable.zig");
+const HeapStats = @import("HeapStats.zig");
+const Metrics = @import("Metrics.zig");
+const Journal = @import("Journal.zig");
+pub const State = @import("State.zig");
+const Console = @import("Console.zig");
+const Framebuffer = @import("Framebuffer.zig");
+const Display = @import("Display.zig");
+const Clipboard = @import("Clipboard.zig");
+const Control = @import("Control.zig");
+const Timer = @import("Timer.zig");
+const BlockCache = @import("BlockCache.zig");
+const features = @import("features.zig");
+const DebugInfo = @import("DebugInfo.zig");
 const syscall = @import("syscall.zig");
 const ExternalLoader = @import("ExternalLoader.zig");
 const Opcode = @import("../compiler/opcode.zig").Opcode;
@@ -16,12 +34,114 @@ const addressing_variant_2 = @import("../compiler/Compiler.zig").addressing_vari
 
 const Vm = @This();
 
+/// Called after every instruction that completes, for tracing.
+pub const Tracer = struct {
+    ptr: *anyopaque,
+    /// `addr` is where the instruction started and `before` is the state
+    /// just before it ran.
+    traceFn: *const fn (ptr: *anyopaque, vm: *Vm, addr: usize, before: Snapshot) void,
+};
+
+/// Called before every instruction, for profilers and tracers outside the
+/// VM. `addr` is where the instruction starts.
+pub const Hook = struct {
+    ptr: *anyopaque,
+    hookFn: *const fn (ptr: *anyopaque, vm: *const Vm, addr: usize, opcode: Opcode) void,
+};
+
+/// Something the program did that still runs but should be fixed.
+pub const Warning = union(enum) {
+    /// `value` is a retired number for `opcode`.
+    retired_opcode: struct { value: u8, opcode: Opcode },
+
+    pub fn format(self: Warning, writer: *std.Io.Writer) std.Io.Writer.Error!void {
+        switch (self) {
+            .retired_opcode => |r| try writer.print("0x{x:0>2} is a retired opcode number for {f}; update the file with nyx migrate", .{ r.value, r.opcode }),
+        }
+    }
+};
+
+/// Called with each `Warning`, which the VM otherwise drops.
+pub const Warner = struct {
+    ptr: *anyopaque,
+    warnFn: *const fn (ptr: *anyopaque, vm: *const Vm, warning: Warning) void,
+};
+
+/// Runs an extended instruction. `operands` are the bytes after its length
+/// byte and `ip` already points past them.
+pub const ExtensionFn = *const fn (vm: *Vm, operands: []const u8) anyerror!void;
+
+/// What integer division does when the quotient does not exist. `imod`
+/// by zero gives the dividend in every mode but `trap`.
+pub const Division = enum {
+    /// Stop with `error.DivisionByZero` or `error.DivisionOverflow`.
+    trap,
+    /// Give the representable value closest to the true quotient: the
+    /// largest value for a zero divisor, or the smallest when a signed
+    /// dividend is negative.
+    saturate,
+    /// Give 0 for a zero divisor and the dividend for `minInt / -1`.
+    wrap,
+    /// Give what `wrap` gives and set `carry` and `overflow`, so the program
+    /// can check for a division that failed instead of stopping.
+    flag,
+};
+
 regs: Registers,
 mmu: Mmu,
 flags: Flags,
 syscalls: syscall.Syscalls,
+/// Handlers for extended instructions by subcode.
+extensions: std.AutoHashMap(u8, ExtensionFn),
+files: FileTable,
+heap_stats: ?HeapStats,
+/// Allocations made with `sys_alloc`, in memory between the program and
+/// the stack.
+heap: Heap,
+counters: Metrics,
+journal: ?*Journal,
+console: ?Console,
+/// Mapped at the start of the MMIO window by `attachFramebuffer`.
+framebuffer: ?*Framebuffer,
+/// Shown the framebuffer by `sys_display`, which fails while it is null.
+display: ?Display,
+/// Started by `sys_timer`, stopped until then.
+timer: Timer,
+/// Pre-decoded blocks of hot code, used while `predecode` is set.
+block_cache: BlockCache,
+/// Run hot code from `block_cache`. Set when built with `-Dpredecode`.
+predecode: bool,
 external_loader: ExternalLoader,
 halted: bool,
+/// Passed to `sys_exit`, which halts the VM, or null if the program has not
+/// called it.
+exit_status: ?u8,
+division: Division,
+tracer: ?Tracer,
+hook: ?Hook,
+warner: ?Warner,
+/// Read and written by the clipboard syscalls, which fail while it is null.
+clipboard: ?Clipboard,
+/// Shared with other threads that pause, resume or stop `run`.
+control: ?*Control,
+/// Where `setArguments` put the program's arguments and environment.
+arguments: Arguments,
+/// Instruction count at which `run` stops with `error.StepLimitExceeded`.
+/// Set by `runWithFuel`.
+step_limit: ?u64,
+/// Left for the embedder, so syscalls it registers can reach its own state.
+host: ?*anyopaque,
+/// Retired opcode numbers that still run. Tests swap in their own table.
+aliases: []const Opcode.Alias,
+/// Set once a retired opcode has run, so the warning is given once.
+ran_alias: bool,
+/// Address of the instruction being executed, or of the last one once the
+/// VM stops, so errors can point at it.
+instruction_start: usize,
+/// Source lines and labels of a program built with `-g`.
+debug_info: ?DebugInfo,
+/// Bytes of program loaded at address 0. The heap starts after them.
+program_len: usize,
 
 pub fn init(
     program: []const u8,
@@ -29,25 +149,36 @@ pub fn init(
     external_libraries: [][]const u8,
     gpa: Allocator,
 ) !Vm {
-    if (program.len < 8) return error.ProgramTooSmall;
+    const header = try features.read(program);
     if (program.len >= mem_size) return error.ProgramTooLarge;
+    if (header.unknown != 0 or !features.supported().supersetOf(header.required)) return error.UnsupportedFeature;
 
-    const entry_point: usize = @intCast(mem.readInt(u64, program[0..8], .little));
+    const entry_point: usize = @intCast(header.entry);
     if (entry_point >= program.len) return error.InvalidEntryPoint;
 
-    const program_data = program[8..];
+    const program_data = header.program(program);
+    if (header.layout) |layout| try checkLayout(layout, entry_point, program_data.len);
+    var debug_info: ?DebugInfo = if (header.debugSection(program)) |bytes| try DebugInfo.read(gpa, bytes) else null;
+    errdefer if (debug_info) |*info| info.deinit();
 
     var regs = Registers.init();
     regs.setSp(mem_size);
     regs.setBp(0);
     regs.setIp(entry_point);
+    regs.setConfig(mem_size, program_data.len, mem_size);
 
     var mmu = Mmu.init(gpa);
     errdefer mmu.deinit();
 
     _ = try mmu.addBlock("Program", program_data.len);
-    _ = try mmu.addBlock("Memory", mem_size - program_data.len);
+    _ = try mmu.addPagedBlock("Memory", mem_size - program_data.len);
     try mmu.writeSlice(0x00, program_data);
+    try mmu.setRegion("memory", program_data.len, std.math.maxInt(usize), .rw);
+    try setProgramRegions(&mmu, program_data.len, if (header.layout) |layout| layout.text_len else null);
+
+    var heap = try Heap.init(program_data.len, defaultHeapSize(mem_size - program_data.len), gpa);
+    errdefer heap.deinit();
+    regs.setBrk(heap.brk);
 
     var external_loader = ExternalLoader.init(gpa);
     for (external_libraries) |lib| try external_loader.load(lib);
@@ -57,33 +188,134 @@ pub fn init(
         .mmu = mmu,
         .flags = .init(),
         .syscalls = try syscall.collectSyscalls(gpa),
+        .extensions = .init(gpa),
+        .files = try .init(gpa),
+        .heap_stats = null,
+        .heap = heap,
+        .counters = .{ .peak_memory = mem_size },
+        .journal = null,
+        .console = null,
+        .framebuffer = null,
+        .display = null,
+        .timer = .{},
+        .block_cache = .init(gpa),
+        .predecode = build_options.predecode,
         .external_loader = external_loader,
         .halted = false,
+        .exit_status = null,
+        .division = .trap,
+        .tracer = null,
+        .warner = null,
+        .hook = null,
+        .clipboard = null,
+        .control = null,
+        .arguments = .{},
+        .step_limit = null,
+        .host = null,
+        .aliases = &Opcode.aliases,
+        .ran_alias = false,
+        .instruction_start = entry_point,
+        .debug_info = debug_info,
+        .program_len = program_data.len,
     };
 }
 
 pub fn deinit(self: *Vm) void {
     self.mmu.deinit();
     self.syscalls.deinit();
+    self.extensions.deinit();
+    self.files.deinit();
+    if (self.heap_stats) |*stats| stats.deinit();
+    self.heap.deinit();
+    if (self.journal) |journal| {
+        journal.deinit();
+        self.mmu.gpa.destroy(journal);
+    }
     self.external_loader.deinit();
+    self.block_cache.deinit();
+    if (self.debug_info) |*info| info.deinit();
+    if (self.framebuffer) |framebuffer| {
+        framebuffer.deinit();
+        self.mmu.gpa.destroy(framebuffer);
+    }
 }
 
 pub fn step(self: *Vm) !void {
     if (self.halted) return;
+    if (self.hook) |hook| self.callHook(hook);
+    const tracer = self.tracer orelse return self.execute();
+    const before = self.snapshot();
+    const addr = self.regs.ip();
+    try self.execute();
+    tracer.traceFn(tracer.ptr, self, addr, before);
+}
 
+fn execute(self: *Vm) !void {
+    if (self.journal) |journal| journal.begin(self.regs, self.flags, self.halted);
+    self.instruction_start = self.regs.ip();
+    self.counters.instructions += 1;
+    self.timer.update(self.counters.instructions);
+    defer self.counters.peak_stack_depth = @max(self.counters.peak_stack_depth, self.regs.get(.isp).asUsize() -| self.regs.sp());
+
+    try self.mmu.checkExecute(self.instruction_start);
     const byte = try self.readByte();
-    if (byte > @as(u8, @intFromEnum(Opcode.hlt))) return error.InvalidOpcode;
-    const opcode: Opcode = @enumFromInt(byte);
+    const handle = dispatch_table[byte] orelse blk: {
+        const current = try Opcode.fromU8OrAlias(byte, self.aliases);
+        if (!self.ran_alias) {
+            self.ran_alias = true;
+            if (self.warner) |warner| warner.warnFn(warner.ptr, self, .{ .retired_opcode = .{ .value = byte, .opcode = current } });
+        }
+        break :blk dispatch_table[@intFromEnum(current)].?;
+    };
+    try handle(self);
+}
+
+/// Show `hook` the instruction about to run. An address that cannot be
+/// executed or a byte that is no opcode is skipped; `execute` reports it.
+fn callHook(self: *Vm, hook: Hook) void {
+    const addr = self.regs.ip();
+    self.mmu.checkExecute(addr) catch return;
+    const byte = self.mmu.read(addr, .byte) catch return;
+    const opcode = Opcode.fromU8OrAlias(byte.asU8(), self.aliases) catch return;
+    hook.hookFn(hook.ptr, self, addr, opcode);
+}
+
+const Handler = *const fn (self: *Vm) anyerror!void;
 
+/// One handler per opcode byte, each specialized to its opcode so the byte
+/// is validated once by the lookup instead of again by a switch. Unassigned
+/// bytes are null and fall back to the alias table.
+const dispatch_table = blk: {
+    var table = [_]?Handler{null} ** 256;
+    for (std.enums.values(Opcode)) |opcode| {
+        table[@intFromEnum(opcode)] = opcodeHandler(opcode);
+    }
+    break :blk table;
+};
+
+fn opcodeHandler(comptime opcode: Opcode) Handler {
+    return struct {
+        fn handle(self: *Vm) anyerror!void {
+            return self.exec(opcode);
+        }
+    }.handle;
+}
+
+inline fn exec(self: *Vm, comptime opcode: Opcode) !void {
     switch (opcode) {
         .nop => {},
         .mov_reg_reg => {
-            const dest = try self.readRegister();
+            const dest = try self.readDestRegister();
             const src = try self.readRegister();
             self.regs.set(dest, self.regs.get(src));
         },
+        .movz_reg_reg, .movs_reg_reg => {
+            const dest = try self.readDestRegister();
+            const src = try self.readRegister();
+            try self.regs.extend(dest, src, opcode == .movs_reg_reg);
+        },
         .mov_reg_imm => {
-            const dest = try self.readRegister();
+            const dest = try self.readDestRegister();
             const src: Immediate = switch (DataSize.fromRegister(dest)) {
                 .byte => .{ .byte = try self.readByte() },
                 .word => .{ .word = try self.readWord() },
@@ -95,29 +327,15 @@ pub fn step(self: *Vm) !void {
             self.regs.set(dest, src);
         },
         .mov_reg_addr => {
-            const dest = try self.readRegister();
-            const variant = try self.readByte();
-            const base: i64 = @bitCast(switch (variant) {
-                addressing_variant_1 => self.regs.get(try self.readRegister()).asU64(),
-                addressing_variant_2 => try self.readQword(),
-                else => return error.UnknownAddressingVariant,
-            });
-            const offset: i64 = @bitCast(try self.readQword());
-            const addr: usize = @intCast(base + offset);
+            const dest = try self.readDestRegister();
+            const addr = try self.readEffectiveAddress();
             const imm = try self.mmu.read(addr, DataSize.fromRegister(dest));
             self.regs.set(dest, imm);
         },
         .mov_addr_reg => {
             const src = try self.readRegister();
             const value = self.regs.get(src);
-            const variant = try self.readByte();
-            const base: i64 = @bitCast(switch (variant) {
-                addressing_variant_1 => self.regs.get(try self.readRegister()).asU64(),
-                addressing_variant_2 => try self.readQword(),
-                else => return error.UnknownAddressingVariant,
-            });
-            const offset: i64 = @bitCast(try self.readQword());
-            const addr: usize = @intCast(base + offset);
+            const addr = try self.readEffectiveAddress();
             try self.mmu.write(addr, value, DataSize.fromRegister(src));
         },
         .mov_addr_imm => {
@@ -130,35 +348,14 @@ pub fn step(self: *Vm) !void {
                 .float => .{ .float = try self.readFloat() },
                 .double => .{ .double = try self.readDouble() },
             };
-            const variant = try self.readByte();
-            const base = switch (variant) {
-                addressing_variant_1 => self.regs.get(try self.readRegister()).asU64(),
-                addressing_variant_2 => try self.readQword(),
-                else => return error.UnknownAddressingVariant,
-            };
-            const offset = try self.readQword();
-            const addr: usize = @intCast(base + offset);
+            const addr = try self.readEffectiveAddress();
             try self.mmu.write(addr, value, size);
         },
         .mov_addr_addr => {
             const size = try self.readDataSize();
-            const src_variant = try self.readByte();
-            const src_base: i64 = @bitCast(switch (src_variant) {
-                addressing_variant_1 => self.regs.get(try self.readRegister()).asU64(),
-                addressing_variant_2 => try self.readQword(),
-                else => return error.UnknownAddressingVariant,
-            });
-            const src_offset: i64 = @bitCast(try self.readQword());
-            const src_addr: usize = @intCast(src_base + src_offset);
+            const src_addr = try self.readEffectiveAddress();
             const value = try self.mmu.read(src_addr, size);
-            const dest_variant = try self.readByte();
-            const dest_base: i64 = @bitCast(switch (dest_variant) {
-                addressing_variant_1 => self.regs.get(try self.readRegister()).asU64(),
-                addressing_variant_2 => try self.readQword(),
-                else => return error.UnknownAddressingVariant,
-            });
-            const dest_offset: i64 = @bitCast(try self.readQword());
-            const dest_addr: usize = @intCast(dest_base + dest_offset);
+            const dest_addr = try self.readEffectiveAddress();
             try self.mmu.write(dest_addr, value, size);
         },
         .push_imm => {
@@ -188,33 +385,19 @@ pub fn step(self: *Vm) !void {
         },
         .push_addr => {
             const size = try self.readDataSize();
-            const variant = try self.readByte();
-            const base: i64 = @bitCast(switch (variant) {
-                addressing_variant_1 => self.regs.get(try self.readRegister()).asU64(),
-                addressing_variant_2 => try self.readQword(),
-                else => return error.UnknownAddressingVariant,
-            });
-            const offset: i64 = @bitCast(try self.readQword());
-            const addr: usize = @intCast(base + offset);
+            const addr = try self.readEffectiveAddress();
             const value = try self.mmu.read(addr, size);
             try self.push(value);
         },
         .pop_reg => {
             const size = try self.readDataSize();
-            const dest = try self.readRegister();
+            const dest = try self.readDestRegister();
             const value = try self.pop(size);
             self.regs.set(dest, value);
         },
         .pop_addr => {
             const size = try self.readDataSize();
-            const variant = try self.readByte();
-            const base: i64 = @bitCast(switch (variant) {
-                addressing_variant_1 => self.regs.get(try self.readRegister()).asU64(),
-                addressing_variant_2 => try self.readQword(),
-                else => return error.UnknownAddressingVariant,
-            });
-            const offset: i64 = @bitCast(try self.readQword());
-            const addr: usize = @intCast(base + offset);
+            const addr = try self.readEffectiveAddress();
             const value = try self.pop(size);
             try self.mmu.write(addr, value, size);
         },
@@ -242,6 +425,24 @@ pub fn step(self: *Vm) !void {
         .div_reg_addr_reg => try self.executeBinaryOpRegAddrReg(div),
         .div_reg_addr_imm => try self.executeBinaryOpRegAddrImm(div),
         .div_reg_addr_addr => try self.executeBinaryOpRegAddrAddr(div),
+        .imul_reg_reg_reg => try self.executeBinaryOp(imul, true),
+        .imul_reg_reg_imm => try self.executeBinaryOp(imul, false),
+        .imul_reg_reg_addr => try self.executeBinaryOpRegRegAddr(imul),
+        .imul_reg_addr_reg => try self.executeBinaryOpRegAddrReg(imul),
+        .imul_reg_addr_imm => try self.executeBinaryOpRegAddrImm(imul),
+        .imul_reg_addr_addr => try self.executeBinaryOpRegAddrAddr(imul),
+        .idiv_reg_reg_reg => try self.executeBinaryOp(idiv, true),
+        .idiv_reg_reg_imm => try self.executeBinaryOp(idiv, false),
+        .idiv_reg_reg_addr => try self.executeBinaryOpRegRegAddr(idiv),
+        .idiv_reg_addr_reg => try self.executeBinaryOpRegAddrReg(idiv),
+        .idiv_reg_addr_imm => try self.executeBinaryOpRegAddrImm(idiv),
+        .idiv_reg_addr_addr => try self.executeBinaryOpRegAddrAddr(idiv),
+        .imod_reg_reg_reg => try self.executeBinaryOp(imod, true),
+        .imod_reg_reg_imm => try self.executeBinaryOp(imod, false),
+        .imod_reg_reg_addr => try self.executeBinaryOpRegRegAddr(imod),
+        .imod_reg_addr_reg => try self.executeBinaryOpRegAddrReg(imod),
+        .imod_reg_addr_imm => try self.executeBinaryOpRegAddrImm(imod),
+        .imod_reg_addr_addr => try self.executeBinaryOpRegAddrAddr(imod),
         .and_reg_reg_reg => try self.executeBitwiseOp(bitAnd, true),
         .and_reg_reg_imm => try self.executeBitwiseOp(bitAnd, false),
         .and_reg_reg_addr => try self.executeBitwiseOpRegRegAddr(bitAnd),
@@ -309,7 +510,11 @@ pub fn step(self: *Vm) !void {
             self.regs.setIp(addr);
         },
         .jmp_reg => {
-            const addr = self.regs.get(try self.readRegister()).asUsize();
+            const addr = try self.regs.get(try self.readRegister()).toAddress();
+            self.regs.setIp(addr);
+        },
+        .jmp_rel8, .jmp_rel32 => {
+            const addr = try self.readRelative(opcode);
             self.regs.setIp(addr);
         },
         .jeq_imm => {
@@ -317,7 +522,11 @@ pub fn step(self: *Vm) !void {
             if (self.flags.eq) self.regs.setIp(addr);
         },
         .jeq_reg => {
-            const addr = self.regs.get(try self.readRegister()).asUsize();
+            const addr = try self.regs.get(try self.readRegister()).toAddress();
+            if (self.flags.eq) self.regs.setIp(addr);
+        },
+        .jeq_rel8, .jeq_rel32 => {
+            const addr = try self.readRelative(opcode);
             if (self.flags.eq) self.regs.setIp(addr);
         },
         .jne_imm => {
@@ -325,7 +534,11 @@ pub fn step(self: *Vm) !void {
             if (!self.flags.eq) self.regs.setIp(addr);
         },
         .jne_reg => {
-            const addr = self.regs.get(try self.readRegister()).asUsize();
+            const addr = try self.regs.get(try self.readRegister()).toAddress();
+            if (!self.flags.eq) self.regs.setIp(addr);
+        },
+        .jne_rel8, .jne_rel32 => {
+            const addr = try self.readRelative(opcode);
             if (!self.flags.eq) self.regs.setIp(addr);
         },
         .jlt_imm => {
@@ -333,7 +546,11 @@ pub fn step(self: *Vm) !void {
             if (self.flags.lt) self.regs.setIp(addr);
         },
         .jlt_reg => {
-            const addr = self.regs.get(try self.readRegister()).asUsize();
+            const addr = try self.regs.get(try self.readRegister()).toAddress();
+            if (self.flags.lt) self.regs.setIp(addr);
+        },
+        .jlt_rel8, .jlt_rel32 => {
+            const addr = try self.readRelative(opcode);
             if (self.flags.lt) self.regs.setIp(addr);
         },
         .jgt_imm => {
@@ -341,7 +558,11 @@ pub fn step(self: *Vm) !void {
             if (!self.flags.lt) self.regs.setIp(addr);
         },
         .jgt_reg => {
-            const addr = self.regs.get(try self.readRegister()).asUsize();
+            const addr = try self.regs.get(try self.readRegister()).toAddress();
+            if (!self.flags.lt) self.regs.setIp(addr);
+        },
+        .jgt_rel8, .jgt_rel32 => {
+            const addr = try self.readRelative(opcode);
             if (!self.flags.lt) self.regs.setIp(addr);
         },
         .jle_imm => {
@@ -349,7 +570,11 @@ pub fn step(self: *Vm) !void {
             if (self.flags.lt or self.flags.eq) self.regs.setIp(addr);
         },
         .jle_reg => {
-            const addr = self.regs.get(try self.readRegister()).asUsize();
+            const addr = try self.regs.get(try self.readRegister()).toAddress();
+            if (self.flags.lt or self.flags.eq) self.regs.setIp(addr);
+        },
+        .jle_rel8, .jle_rel32 => {
+            const addr = try self.readRelative(opcode);
             if (self.flags.lt or self.flags.eq) self.regs.setIp(addr);
         },
         .jge_imm => {
@@ -357,7 +582,11 @@ pub fn step(self: *Vm) !void {
             if (!self.flags.lt or self.flags.eq) self.regs.setIp(addr);
         },
         .jge_reg => {
-            const addr = self.regs.get(try self.readRegister()).asUsize();
+            const addr = try self.regs.get(try self.readRegister()).toAddress();
+            if (!self.flags.lt or self.flags.eq) self.regs.setIp(addr);
+        },
+        .jge_rel8, .jge_rel32 => {
+            const addr = try self.readRelative(opcode);
             if (!self.flags.lt or self.flags.eq) self.regs.setIp(addr);
         },
         .call_imm => {
@@ -367,7 +596,12 @@ pub fn step(self: *Vm) !void {
         },
         .call_reg => {
             const reg = try self.readRegister();
-            const addr = self.regs.get(reg).asUsize();
+            const addr = try self.regs.get(reg).toAddress();
+            try self.push(.{ .qword = @intCast(self.regs.ip()) });
+            self.regs.setIp(addr);
+        },
+        .call_rel8, .call_rel32 => {
+            const addr = try self.readRelative(opcode);
             try self.push(.{ .qword = @intCast(self.regs.ip()) });
             self.regs.setIp(addr);
         },
@@ -387,33 +621,33 @@ pub fn step(self: *Vm) !void {
             try ExternalLoader.call(func_ptr, ret_type, arg_types[0..total_arg_count], fixed_arg_count, self);
         },
         .inc => {
-            const reg = try self.readRegister();
+            const reg = try self.readDestRegister();
             const value = self.regs.get(reg);
             const new_value: Immediate = switch (value) {
-                .byte => |imm| .{ .byte = imm + 1 },
-                .word => |imm| .{ .word = imm + 1 },
-                .dword => |imm| .{ .dword = imm + 1 },
-                .qword => |imm| .{ .qword = imm + 1 },
+                .byte => |imm| .{ .byte = imm +% 1 },
+                .word => |imm| .{ .word = imm +% 1 },
+                .dword => |imm| .{ .dword = imm +% 1 },
+                .qword => |imm| .{ .qword = imm +% 1 },
                 .float => |imm| .{ .float = imm + 1.0 },
                 .double => |imm| .{ .double = imm + 1.0 },
             };
             self.regs.set(reg, new_value);
         },
         .dec => {
-            const reg = try self.readRegister();
+            const reg = try self.readDestRegister();
             const value = self.regs.get(reg);
             const new_value: Immediate = switch (value) {
-                .byte => |imm| .{ .byte = imm - 1 },
-                .word => |imm| .{ .word = imm - 1 },
-                .dword => |imm| .{ .dword = imm - 1 },
-                .qword => |imm| .{ .qword = imm - 1 },
+                .byte => |imm| .{ .byte = imm -% 1 },
+                .word => |imm| .{ .word = imm -% 1 },
+                .dword => |imm| .{ .dword = imm -% 1 },
+                .qword => |imm| .{ .qword = imm -% 1 },
                 .float => |imm| .{ .float = imm - 1.0 },
                 .double => |imm| .{ .double = imm - 1.0 },
             };
             self.regs.set(reg, new_value);
         },
         .neg => {
-            const reg = try self.readRegister();
+            const reg = try self.readDestRegister();
             const value = self.regs.get(reg);
             const new_value: Immediate = switch (value) {
                 .byte => |imm| .{ .byte = @intCast(-@as(i8, @intCast(imm))) },
@@ -429,21 +663,742 @@ pub fn step(self: *Vm) !void {
             const addr = (try self.pop(.qword)).asUsize();
             self.regs.setIp(addr);
         },
+        .enter_imm => {
+            const size = try self.readQword();
+            try self.push(.{ .qword = self.regs.bp() });
+            const frame = self.regs.sp();
+            if (frame < size) return error.StackOverflow;
+            self.regs.setBp(frame);
+            self.regs.setSp(frame - size);
+        },
+        .leave => {
+            self.regs.setSp(self.regs.bp());
+            self.regs.setBp((try self.pop(.qword)).asUsize());
+        },
         .syscall => {
-            const index = self.regs.get(.q15).asUsize();
+            const index = self.regs.get(syscall.number_register).asUsize();
             if (self.syscalls.get(index)) |sc| {
+                self.regs.set(syscall.error_register, .{ .qword = 0 });
                 try sc(self);
+                if (self.journal) |journal| journal.clear();
+                if (index < Metrics.syscall_slots) self.counters.syscalls[index] += 1;
+                self.counters.peak_memory = @max(self.counters.peak_memory, self.mmu.size());
             } else {
                 return error.UnknownSyscall;
             }
         },
         .hlt => self.halted = true,
+        .inc_reg_imm => try self.executeStepOp(.inc),
+        .dec_reg_imm => try self.executeStepOp(.dec),
+        .adc_reg_reg_reg => try self.executeBinaryOp(adc, true),
+        .adc_reg_reg_imm => try self.executeBinaryOp(adc, false),
+        .adc_reg_reg_addr => try self.executeBinaryOpRegRegAddr(adc),
+        .adc_reg_addr_reg => try self.executeBinaryOpRegAddrReg(adc),
+        .adc_reg_addr_imm => try self.executeBinaryOpRegAddrImm(adc),
+        .adc_reg_addr_addr => try self.executeBinaryOpRegAddrAddr(adc),
+        .sbb_reg_reg_reg => try self.executeBinaryOp(sbb, true),
+        .sbb_reg_reg_imm => try self.executeBinaryOp(sbb, false),
+        .sbb_reg_reg_addr => try self.executeBinaryOpRegRegAddr(sbb),
+        .sbb_reg_addr_reg => try self.executeBinaryOpRegAddrReg(sbb),
+        .sbb_reg_addr_imm => try self.executeBinaryOpRegAddrImm(sbb),
+        .sbb_reg_addr_addr => try self.executeBinaryOpRegAddrAddr(sbb),
+        .not_reg_reg => {
+            const dest = try self.readDestRegister();
+            const src = try self.readRegister();
+            self.regs.set(dest, try complement(self.regs.get(src), DataSize.fromRegister(dest)));
+        },
+        .not_reg_addr => {
+            const dest = try self.readDestRegister();
+            const data_size = DataSize.fromRegister(dest);
+            self.regs.set(dest, try complement(try self.readAddress(data_size), data_size));
+        },
+        .cvtif_reg_reg => {
+            const dest = try self.readDestRegister();
+            const src = try self.readRegister();
+            self.regs.set(dest, try intToFloat(self.regs.get(src), DataSize.fromRegister(dest)));
+        },
+        .cvtfi_reg_reg => {
+            const dest = try self.readDestRegister();
+            const src = try self.readRegister();
+            self.regs.set(dest, try floatToInt(self.regs.get(src), DataSize.fromRegister(dest)));
+        },
+        .cvtfd_reg_reg => {
+            const dest = try self.readDestRegister();
+            const value = self.regs.get(try self.readRegister());
+            if (value != .float or DataSize.fromRegister(dest) != .double) return error.InvalidDataSize;
+            self.regs.set(dest, .{ .double = value.float });
+        },
+        .cvtdf_reg_reg => {
+            const dest = try self.readDestRegister();
+            const value = self.regs.get(try self.readRegister());
+            if (value != .double or DataSize.fromRegister(dest) != .float) return error.InvalidDataSize;
+            self.regs.set(dest, .{ .float = @floatCast(value.double) });
+        },
+        .extended => {
+            const subcode = try self.readByte();
+            const len = try self.readByte();
+            const start = self.regs.ip();
+            const handler = self.extensions.get(subcode) orelse return error.UnknownExtension;
+            var buf: [std.math.maxInt(u8)]u8 = undefined;
+            try self.mmu.checkRead(start, len);
+            if (try self.mmu.readInto(start, buf[0..len]) != len) return error.AddressOutOfBounds;
+            self.regs.setIp(start + len);
+            try handler(self, buf[0..len]);
+        },
+        .scmp_reg_reg => {
+            const lhs = try self.regs.get(try self.readRegister()).toAddress();
+            const rhs = try self.regs.get(try self.readRegister()).toAddress();
+            const order = try self.compareStrings(lhs, rhs);
+            self.flags.eq = order == .eq;
+            self.flags.lt = order == .lt;
+        },
+        .schr_reg_reg_reg, .schr_reg_reg_imm => {
+            const ptr = try self.readDestRegister();
+            const len = try self.readDestRegister();
+            const byte: u8 = if (opcode == .schr_reg_reg_reg)
+                self.regs.get(try self.readRegister()).asU8()
+            else
+                @truncate(try self.readQword());
+            try self.scanString(ptr, len, byte);
+        },
+        .seteq_reg => try self.setFlag(self.flags.eq),
+        .setne_reg => try self.setFlag(!self.flags.eq),
+        .setlt_reg => try self.setFlag(self.flags.lt),
+        .setgt_reg => try self.setFlag(!self.flags.lt and !self.flags.eq),
+        .setle_reg => try self.setFlag(self.flags.lt or self.flags.eq),
+        .setge_reg => try self.setFlag(!self.flags.lt or self.flags.eq),
+        .setc_reg => try self.setFlag(self.flags.carry),
+        .seto_reg => try self.setFlag(self.flags.overflow),
         // else => return error.UnhandledOpcode,
     }
 }
 
+/// Counters for everything the VM has done so far.
+pub fn metrics(self: *Vm) Metrics {
+    var result = self.counters;
+    result.peak_memory = @max(result.peak_memory, self.mmu.size());
+    return result;
+}
+
+/// Make the `sys_alloc` heap `size` bytes long. Fails with
+/// `error.HeapTooLarge` if it would reach the stack or its guard. Call
+/// before running.
+pub fn setHeapSize(self: *Vm, size: usize) !void {
+    var heap = try Heap.init(self.program_len, size, self.mmu.gpa);
+    const isp = self.regs.get(.isp).asUsize();
+    const stack_bottom = if (self.mmu.guard) |guard| @min(guard.start, isp) else isp;
+    if (heap.brk > stack_bottom) {
+        heap.deinit();
+        return error.HeapTooLarge;
+    }
+    self.heap.deinit();
+    self.heap = heap;
+    self.regs.setBrk(heap.brk);
+}
+
+/// Bytes that `setStackGuard` makes unusable below the stack.
+pub const stack_guard_size = 4096;
+
+/// Make the `stack_guard_size` bytes that end `slack` bytes below the
+/// initial stack pointer fault with `error.StackGuardHit`, so a stack that
+/// outgrows its slack stops before it overwrites the heap or data. The
+/// faulting address is left in `mmu.guard_hit`. Call before running.
+pub fn setStackGuard(self: *Vm, slack: usize) !void {
+    const top = self.regs.get(.isp).asUsize();
+    const end = std.math.sub(usize, top, slack) catch return error.StackGuardTooLarge;
+    const start = std.math.sub(usize, end, stack_guard_size) catch return error.StackGuardTooLarge;
+    if (start < self.heap.brk) return error.StackGuardTooLarge;
+    self.mmu.guard = .{ .start = start, .end = end };
+}
+
+/// Most bytes `randomizeLayout` moves the stack or the heap by.
+pub const layout_jitter = 0x10000;
+
+/// Move the initial stack pointer down and the heap up by random 16-byte
+/// multiples, each at most `layout_jitter` and a quarter of the memory
+/// between them, so stack and heap addresses differ from run to run. The
+/// program is moved, when it is, by `Linker.linkAt` before it is loaded.
+/// Call before running and before `setStackGuard`.
+pub fn randomizeLayout(self: *Vm, random: std.Random) !void {
+    const top = self.regs.get(.isp).asUsize();
+    const room = top -| self.heap.brk;
+    const limit = @min(layout_jitter, room / 4) / 16;
+
+    const heap_shift = random.uintAtMost(usize, limit) * 16;
+    var heap = try Heap.init(self.heap.base + heap_shift, self.heap.brk - self.heap.base, self.mmu.gpa);
+    errdefer heap.deinit();
+    self.heap.deinit();
+    self.heap = heap;
+    self.regs.setBrk(heap.brk);
+
+    const stack_shift = random.uintAtMost(usize, limit) * 16;
+    self.regs.setSp(top - stack_shift);
+    self.regs.setConfig(self.regs.get(.msz).asUsize(), self.regs.get(.brk).asUsize(), top - stack_shift);
+}
+
+/// Addresses of the tables `setArguments` writes, which `sys_args` and
+/// `sys_getenv` read. Both are 0 until it is called.
+pub const Arguments = struct {
+    argc: u64 = 0,
+    argv: u64 = 0,
+    envp: u64 = 0,
+};
+
+/// Copy `args`, and `env` as `NAME=VALUE` strings, to the top of the stack
+/// and start the stack below them, so at entry `sp` points at argc,
+/// followed by the argv pointers and the envp pointers, each list ending
+/// with a null pointer. The strings are null-terminated and sit above the
+/// tables. Call before running, after `randomizeLayout` and before
+/// `setStackGuard` and `setFillPattern`.
+pub fn setArguments(self: *Vm, args: []const []const u8, env: []const []const u8) !void {
+    const top = self.regs.get(.isp).asUsize();
+    var strings_len: usize = 0;
+    for (args) |arg| strings_len += arg.len + 1;
+    for (env) |entry| strings_len += entry.len + 1;
+    const table_len = 8 * (args.len + env.len + 3);
+    const strings = std.math.sub(usize, top, strings_len) catch return error.ArgumentsTooLarge;
+    const base = std.mem.alignBackward(usize, std.math.sub(usize, strings, table_len) catch return error.ArgumentsTooLarge, 16);
+    if (base < self.heap.brk) return error.ArgumentsTooLarge;
+
+    try self.mmu.write(base, .{ .qword = args.len }, .qword);
+    var string = strings;
+    const envp = try self.writeStringTable(base + 8, &string, args);
+    _ = try self.writeStringTable(envp, &string, env);
+
+    self.regs.setSp(base);
+    self.regs.setConfig(self.regs.get(.msz).asUsize(), self.regs.get(.brk).asUsize(), base);
+    self.arguments = .{ .argc = args.len, .argv = base + 8, .envp = envp };
+}
+
+/// Write each of `strings` at `string.*`, advancing it, and a pointer to it
+/// at `table`, then a null pointer. Returns the address after the table.
+fn writeStringTable(self: *Vm, table: usize, string: *usize, strings: []const []const u8) !usize {
+    var slot = table;
+    for (strings) |bytes| {
+        try self.mmu.writeSlice(string.*, bytes);
+        try self.mmu.write(string.* + bytes.len, .{ .byte = 0 }, .byte);
+        try self.mmu.write(slot, .{ .qword = string.* }, .qword);
+        string.* += bytes.len + 1;
+        slot += 8;
+    }
+    try self.mmu.write(slot, .{ .qword = 0 }, .qword);
+    return slot + 8;
+}
+
+/// Make the first `text_len` bytes of the program read-only and executable
+/// and the rest of it, the data section, readable and writable only. Memory
+/// after the program is never executable, so code that writes over itself
+/// or jumps into the stack faults with `error.ProtectionFault`, described in
+/// `mmu.fault`. Call before running.
+pub fn protectText(self: *Vm, text_len: usize) !void {
+    if (text_len > self.program_len) return error.TextTooLarge;
+    try setProgramRegions(&self.mmu, self.program_len, text_len);
+}
+
+/// Split the program into `text` and `data` regions, or leave all of it
+/// writable and executable when where the text ends is not known.
+fn setProgramRegions(mmu: *Mmu, program_len: usize, text_len: ?usize) !void {
+    const end = text_len orelse return mmu.setRegion("program", 0, program_len, .rwx);
+    try mmu.setRegion("text", 0, end, .rx);
+    if (end < program_len) try mmu.setRegion("data", end, program_len, .rw);
+}
+
+/// Errors `init` returns for a program it refuses to load.
+pub const LoadError = error{
+    ProgramTooSmall,
+    ProgramTooLarge,
+    UnsupportedFeature,
+    InvalidEntryPoint,
+    /// The sections in the header do not add up to the program after it.
+    LayoutMismatch,
+    /// The entry point is in the data section.
+    EntryOutsideText,
+    /// The debug section is malformed.
+    InvalidDebugInfo,
+};
+
+/// Check that the sections a program's header declares cover exactly the
+/// bytes after it and that execution starts in the text section.
+fn checkLayout(layout: features.Layout, entry: usize, program_len: usize) LoadError!void {
+    if (@as(usize, layout.text_len) + layout.data_len != program_len) return error.LayoutMismatch;
+    if (entry >= layout.text_len) return error.EntryOutsideText;
+}
+
+/// Let the program read, write and execute any of its memory, as it could
+/// before regions existed.
+pub fn disableProtection(self: *Vm) void {
+    self.mmu.protect = false;
+}
+
+/// `Heap.default_size`, or half of the free memory if that is smaller, so
+/// the stack keeps the rest.
+fn defaultHeapSize(free_memory: usize) usize {
+    return @min(Heap.default_size, free_memory / 2);
+}
+
+/// Start tracking heap allocations made through syscalls.
+pub fn enableHeapStats(self: *Vm) void {
+    if (self.heap_stats == null) self.heap_stats = .init(self.mmu.gpa);
+}
+
+/// Fill memory that was not loaded from the program, and every block
+/// allocated later, with `pattern` instead of zeros. Call before running.
+pub fn setFillPattern(self: *Vm, pattern: u8) void {
+    self.mmu.fill = pattern;
+    for (self.mmu.blocks.items[1..]) |block| {
+        if (self.console) |console| if (block.storage.ptr == console.cells.ptr) continue;
+        block.setFill(pattern);
+    }
+}
+
+/// Map a blank console of `size` right after memory, at the address in
+/// `msz`. Call before running, while no heap blocks exist.
+pub fn attachConsole(self: *Vm, size: Console.Size) !void {
+    if (self.console != null) return;
+    const base = try self.mmu.addBlock("Console", try size.bytes());
+    const block = self.mmu.blocks.items[self.mmu.blocks.items.len - 1];
+    @memset(block.storage, 0);
+    self.console = .{ .base = base, .size = size, .cells = block.storage };
+}
+
+/// Map `device` at `offset` into the MMIO window and return its address.
+/// Reads and writes there go to the device instead of memory. The window
+/// starts at `DeviceBus.default_base` unless the embedder moved it with
+/// `mmu.devices.setWindow`.
+pub fn mapDevice(self: *Vm, device: Device, offset: usize) !usize {
+    return self.mmu.devices.map(device, offset);
+}
+
+/// Map a black `Framebuffer` at the start of the MMIO window. Call before
+/// running.
+pub fn attachFramebuffer(self: *Vm) !void {
+    if (self.framebuffer != null) return;
+    const framebuffer = try self.mmu.gpa.create(Framebuffer);
+    errdefer self.mmu.gpa.destroy(framebuffer);
+    framebuffer.* = try .init(self.mmu.gpa);
+    errdefer framebuffer.deinit();
+    _ = try self.mapDevice(framebuffer.device(), 0);
+    self.framebuffer = framebuffer;
+}
+
+/// Number of blocks that are always mapped: the program, memory and the
+/// console if there is one. Heap blocks come after them.
+pub fn fixedBlockCount(self: *const Vm) usize {
+    return if (self.console != null) 3 else 2;
+}
+
+/// Call `tracer` after every instruction, or stop tracing with null.
+pub fn setTracer(self: *Vm, tracer: ?Tracer) void {
+    self.tracer = tracer;
+}
+
+/// Call `warner` with each warning, or drop them with null.
+pub fn setWarner(self: *Vm, warner: ?Warner) void {
+    self.warner = warner;
+}
+
+/// Call `hook` before every instruction, or remove it with null. Without a
+/// hook each step only checks for one.
+pub fn setHook(self: *Vm, hook: ?Hook) void {
+    self.hook = hook;
+}
+
+/// Where the instruction at `addr` came from, for a program built with `-g`.
+pub fn sourceLocation(self: *const Vm, addr: usize) ?DebugInfo.Location {
+    const info = if (self.debug_info) |*info| info else return null;
+    return info.locate(addr);
+}
+
+/// Let `control` pause, resume and stop `run` from other threads, or
+/// detach it with null. `control` must outlive the run.
+pub fn setControl(self: *Vm, control: ?*Control) void {
+    self.control = control;
+}
+
+/// Run `handler` for syscall `number`, replacing the built-in one if there
+/// is one. Handlers get the VM, so they read arguments with `syscallArgs`
+/// and return results with `setSyscallResult` like the built-in ones do,
+/// and can reach the embedder's state through `host`.
+pub fn registerSyscall(self: *Vm, number: usize, handler: syscall.SyscallFn) !void {
+    try self.syscalls.put(number, handler);
+}
+
+/// The arguments of the syscall being made, in the registers the syscall
+/// convention assigns them. A syscall that takes a narrower argument
+/// truncates it.
+pub fn syscallArgs(self: *Vm) [syscall.max_args]u64 {
+    var args: [syscall.max_args]u64 = undefined;
+    for (&args, syscall.arg_registers) |*arg, reg| arg.* = self.regs.get(reg).asU64();
+    return args;
+}
+
+/// Return `value` to the program from the syscall being made.
+pub fn setSyscallResult(self: *Vm, value: u64) void {
+    self.regs.set(syscall.result_register, .{ .qword = value });
+}
+
+/// Make the syscall being made fail: the result is -1 and the error
+/// register is set.
+pub fn setSyscallError(self: *Vm) void {
+    self.setSyscallResult(@bitCast(@as(i64, -1)));
+    self.regs.set(syscall.error_register, .{ .qword = 1 });
+}
+
+/// Make syscall `number` fail with `error.UnknownSyscall`.
+pub fn removeSyscall(self: *Vm, number: usize) void {
+    _ = self.syscalls.remove(number);
+}
+
+/// Remove every built-in syscall that needs `permission`, e.g. `.fs` to
+/// keep a program away from the host's files. Syscalls registered with
+/// `registerSyscall` under the same numbers are removed too.
+pub fn denySyscalls(self: *Vm, permission: syscall.Permission) void {
+    for (syscall.table) |entry| {
+        if (entry.permission == permission) self.removeSyscall(entry.number);
+    }
+}
+
+/// Run `handler` for extended instructions with `subcode`. Subcodes from
+/// `Opcode.first_custom_subcode` up are free for embedders; the ones below are
+/// reserved for future core instructions.
+pub fn registerExtension(self: *Vm, subcode: u8, handler: ExtensionFn) !void {
+    try self.extensions.put(subcode, handler);
+}
+
+/// Make extended instructions with `subcode` fail with
+/// `error.UnknownExtension`.
+pub fn removeExtension(self: *Vm, subcode: u8) void {
+    _ = self.extensions.remove(subcode);
+}
+
+/// Record the last `capacity` instructions so they can be undone with
+/// `reverseStep`.
+pub fn enableJournal(self: *Vm, capacity: usize) !void {
+    if (self.journal != null) return;
+    const journal = try self.mmu.gpa.create(Journal);
+    errdefer self.mmu.gpa.destroy(journal);
+    journal.* = try .init(capacity, self.mmu.gpa);
+    self.journal = journal;
+    self.mmu.journal = journal;
+}
+
+/// Undo the most recently executed instruction. Returns false when there is
+/// no recorded history left.
+pub fn reverseStep(self: *Vm) !bool {
+    const journal = self.journal orelse return false;
+    var entry = journal.pop() orelse return false;
+    defer entry.deinit(journal.gpa);
+
+    self.mmu.journal = null;
+    defer self.mmu.journal = journal;
+    // Undoing a write to read-only memory puts back bytes that were there.
+    const protect = self.mmu.protect;
+    self.mmu.protect = false;
+    defer self.mmu.protect = protect;
+
+    var i = entry.writes.items.len;
+    while (i > 0) {
+        i -= 1;
+        const write = entry.writes.items[i];
+        try self.mmu.writeSlice(write.addr, write.old);
+    }
+
+    self.regs = entry.regs;
+    self.flags = entry.flags;
+    self.halted = entry.halted;
+    return true;
+}
+
+/// Copy the registers, flags, memory, heap and descriptors, for
+/// `restoreState` on this VM or on another loaded with the same program and
+/// options. The caller must call `State.deinit` on it.
+pub fn saveState(self: *Vm) !State {
+    return State.capture(self, self.mmu.gpa);
+}
+
+/// Name of the blocks `sys_malloc` adds.
+pub const malloc_block_name = "Block";
+
+/// Put the VM back in `state`. Blocks `sys_malloc` added are dropped or added
+/// to match it. Fails with `error.StateMismatch` if any other block differs
+/// from the ones the state was saved with. Clears the journal, since the
+/// history it holds led somewhere else.
+pub fn restoreState(self: *Vm, state: *const State) !void {
+    const current = self.mmu.blocks.items;
+    for (0..@max(current.len, state.blocks.len)) |i| {
+        if (i < current.len and i < state.blocks.len) {
+            const saved = state.blocks[i];
+            if (current[i].len != saved.len or !mem.eql(u8, current[i].block_name, saved.name)) return error.StateMismatch;
+        } else {
+            const name = if (i < current.len) current[i].block_name else state.blocks[i].name;
+            if (!mem.eql(u8, name, malloc_block_name)) return error.StateMismatch;
+        }
+    }
+
+    const journal = self.mmu.journal;
+    self.mmu.journal = null;
+    defer self.mmu.journal = journal;
+    if (self.journal) |history| history.clear();
+    // Saved memory goes back in text and the stack guard like any other.
+    const protect = self.mmu.protect;
+    self.mmu.protect = false;
+    defer self.mmu.protect = protect;
+    const guard = self.mmu.guard;
+    self.mmu.guard = null;
+    defer self.mmu.guard = guard;
+
+    self.mmu.fill = state.fill;
+    self.mmu.truncateBlocks(@min(current.len, state.blocks.len));
+    for (self.mmu.blocks.items) |block| block.clear(state.fill);
+    for (state.blocks[self.mmu.blocks.items.len..]) |block| _ = try self.mmu.addBlock(malloc_block_name, block.len);
+    for (state.pages) |page| try self.mmu.writeSlice(page.addr, page.bytes);
+    self.mmu.code_writes +%= 1;
+
+    try self.heap.restore(state.heap_base, state.heap_brk, state.free_ranges, state.allocations);
+    try self.files.restore(state.files);
+    self.regs = state.regs;
+    self.flags = state.flags;
+    self.halted = state.halted;
+    self.exit_status = state.exit_status;
+    self.instruction_start = self.regs.ip();
+}
+
 pub fn run(self: *Vm) !void {
-    while (!self.halted) try self.step();
+    while (!self.halted) {
+        if (self.control) |control| try control.checkpoint();
+        if (self.step_limit) |limit| {
+            if (self.counters.instructions >= limit) {
+                self.instruction_start = self.regs.ip();
+                return error.StepLimitExceeded;
+            }
+        }
+        // Tracing, hooks and the journal need to see every instruction on its own.
+        if (self.predecode and self.tracer == null and self.hook == null and self.journal == null) {
+            self.mmu.checkExecute(self.regs.ip()) catch |err| {
+                self.instruction_start = self.regs.ip();
+                return err;
+            };
+            if (try self.block_cache.lookup(&self.mmu, self.regs.ip())) |block| {
+                // A block that would run past the limit is interpreted instead.
+                const fuel = if (self.step_limit) |limit| limit - self.counters.instructions else block.ops.len;
+                if (block.ops.len <= fuel) {
+                    if (self.runBlock(block)) |_| {
+                        continue;
+                    } else |err| {
+                        if (err != error.NotPredecoded) return err;
+                    }
+                }
+            }
+        }
+        try self.step();
+    }
+}
+
+/// Like `run`, but stop with `error.StepLimitExceeded` before running more
+/// than `max_instructions` instructions, so a program that never halts cannot
+/// hang its host. A VM stopped this way can be given more fuel and resumed.
+pub fn runWithFuel(self: *Vm, max_instructions: u64) !void {
+    const previous = self.step_limit;
+    self.step_limit = self.counters.instructions +| max_instructions;
+    defer self.step_limit = previous;
+    try self.run();
+}
+
+/// Run a block from `BlockCache` exactly as the interpreter would run its
+/// instructions one at a time. An op it has no case for stops the block
+/// with `error.NotPredecoded` before it has any effect, leaving `ip` on it
+/// for the interpreter.
+fn runBlock(self: *Vm, block: *const BlockCache.Block) !void {
+    for (block.ops) |op| {
+        self.instruction_start = op.addr;
+        try self.mmu.checkExecute(op.addr);
+        self.counters.instructions += 1;
+        self.timer.update(self.counters.instructions);
+        self.regs.setIp(op.next);
+        switch (op.opcode) {
+            .nop => {},
+            .mov_reg_reg => self.regs.set(op.dest, self.regs.get(op.lhs)),
+            .mov_reg_imm => self.regs.set(op.dest, op.imm),
+            inline .inc, .dec, .inc_reg_imm, .dec_reg_imm => |opcode| {
+                const data_size = DataSize.fromRegister(op.dest);
+                const one: Immediate = switch (data_size) {
+                    .byte => .{ .byte = 1 },
+                    .word => .{ .word = 1 },
+                    .dword => .{ .dword = 1 },
+                    .qword => .{ .qword = 1 },
+                    .float => .{ .float = 1.0 },
+                    .double => .{ .double = 1.0 },
+                };
+                const amount = if (opcode == .inc or opcode == .dec) one else op.imm;
+                const apply = comptime if (opcode == .inc or opcode == .inc_reg_imm) stepUp else stepDown;
+                self.regs.set(op.dest, try self.binaryResult(apply, data_size, self.regs.get(op.dest), amount));
+            },
+            .cmp_reg_reg, .cmp_reg_imm => {
+                const lhs = self.regs.get(op.lhs);
+                const rhs = if (op.opcode == .cmp_reg_reg) self.regs.get(op.rhs) else op.imm;
+                self.flags.eq = lhs.eql(rhs);
+                self.flags.lt = lhs.lessThan(rhs);
+            },
+            inline .add_reg_reg_reg,
+            .add_reg_reg_imm,
+            .sub_reg_reg_reg,
+            .sub_reg_reg_imm,
+            .mul_reg_reg_reg,
+            .mul_reg_reg_imm,
+            .adc_reg_reg_reg,
+            .adc_reg_reg_imm,
+            .sbb_reg_reg_reg,
+            .sbb_reg_reg_imm,
+            => |opcode| {
+                const rhs = if (comptime std.mem.endsWith(u8, @tagName(opcode), "_reg_reg_reg")) self.regs.get(op.rhs) else op.imm;
+                const apply = comptime switch (opcode) {
+                    .add_reg_reg_reg, .add_reg_reg_imm => add,
+                    .sub_reg_reg_reg, .sub_reg_reg_imm => sub,
+                    .mul_reg_reg_reg, .mul_reg_reg_imm => mul,
+                    .adc_reg_reg_reg, .adc_reg_reg_imm => adc,
+                    else => sbb,
+                };
+                self.regs.set(op.dest, try self.binaryResult(apply, DataSize.fromRegister(op.dest), self.regs.get(op.lhs), rhs));
+            },
+            inline .and_reg_reg_reg,
+            .and_reg_reg_imm,
+            .or_reg_reg_reg,
+            .or_reg_reg_imm,
+            .xor_reg_reg_reg,
+            .xor_reg_reg_imm,
+            .shl_reg_reg_reg,
+            .shl_reg_reg_imm,
+            .shr_reg_reg_reg,
+            .shr_reg_reg_imm,
+            => |opcode| {
+                const rhs = if (comptime std.mem.endsWith(u8, @tagName(opcode), "_reg_reg_reg")) self.regs.get(op.rhs) else op.imm;
+                const apply = comptime switch (opcode) {
+                    .and_reg_reg_reg, .and_reg_reg_imm => bitAnd,
+                    .or_reg_reg_reg, .or_reg_reg_imm => bitOr,
+                    .xor_reg_reg_reg, .xor_reg_reg_imm => bitXor,
+                    .shl_reg_reg_reg, .shl_reg_reg_imm => shl,
+                    else => shr,
+                };
+                self.regs.set(op.dest, try bitwiseResult(apply, DataSize.fromRegister(op.dest), self.regs.get(op.lhs), rhs));
+            },
+            .jmp_imm, .jmp_rel8, .jmp_rel32 => self.regs.setIp(op.imm.asUsize()),
+            .jeq_imm, .jeq_rel8, .jeq_rel32 => if (self.flags.eq) self.regs.setIp(op.imm.asUsize()),
+            .jne_imm, .jne_rel8, .jne_rel32 => if (!self.flags.eq) self.regs.setIp(op.imm.asUsize()),
+            .jlt_imm, .jlt_rel8, .jlt_rel32 => if (self.flags.lt) self.regs.setIp(op.imm.asUsize()),
+            .jgt_imm, .jgt_rel8, .jgt_rel32 => if (!self.flags.lt) self.regs.setIp(op.imm.asUsize()),
+            .jle_imm, .jle_rel8, .jle_rel32 => if (self.flags.lt or self.flags.eq) self.regs.setIp(op.imm.asUsize()),
+            .jge_imm, .jge_rel8, .jge_rel32 => if (!self.flags.lt or self.flags.eq) self.regs.setIp(op.imm.asUsize()),
+            else => {
+                self.counters.instructions -= 1;
+                self.regs.setIp(op.addr);
+                return error.NotPredecoded;
+            },
+        }
+        self.counters.peak_stack_depth = @max(self.counters.peak_stack_depth, self.regs.get(.isp).asUsize() -| self.regs.sp());
+    }
+}
+
+/// Registers and flags at one point in time.
+pub const Snapshot = struct {
+    regs: Registers,
+    flags: Flags,
+};
+
+pub fn snapshot(self: *const Vm) Snapshot {
+    return .{ .regs = self.regs, .flags = self.flags };
+}
+
+/// Write every register and flag that differs from `before`, one per line.
+/// `ip` is left out because almost every instruction changes it.
+pub fn writeChanges(self: *Vm, before: Snapshot, writer: *Io.Writer) Io.Writer.Error!void {
+    var old = before.regs;
+    for (self.regs.gpr, old.gpr, 0..) |new, prev, i| {
+        if (new != prev) try writer.print("q{d} = 0x{x} ({d})\n", .{ i, new, new });
+    }
+    for (self.regs.fpr, old.fpr, 0..) |new, prev, i| {
+        if (new == prev) continue;
+        if (i < 16) {
+            try writer.print("ff{d} = {d}\n", .{ i, @as(f32, @bitCast(@as(u32, @truncate(new)))) });
+        } else {
+            try writer.print("dd{d} = {d}\n", .{ i - 16, @as(f64, @bitCast(new)) });
+        }
+    }
+    if (self.regs.sp() != old.sp()) try writer.print("sp = 0x{x}\n", .{self.regs.sp()});
+    if (self.regs.bp() != old.bp()) try writer.print("bp = 0x{x}\n", .{self.regs.bp()});
+    if (self.flags.eq != before.flags.eq) try writer.print("eq = {}\n", .{self.flags.eq});
+    if (self.flags.lt != before.flags.lt) try writer.print("lt = {}\n", .{self.flags.lt});
+    if (self.flags.carry != before.flags.carry) try writer.print("carry = {}\n", .{self.flags.carry});
+    if (self.flags.overflow != before.flags.overflow) try writer.print("overflow = {}\n", .{self.flags.overflow});
+}
+
+/// A call found on the stack by `backtrace`.
+pub const Frame = struct {
+    /// Address of the `call` instruction.
+    call_site: usize,
+    /// Address the call jumped to, when it was an immediate.
+    target: ?usize,
+};
+
+/// Best-effort list of the calls that led to the current instruction,
+/// innermost first. Nothing records frames, so every qword between `sp` and
+/// the initial stack pointer that points right after a `call` in the program
+/// is taken as a return address. A value that only looks like one shows up
+/// as an extra frame.
+pub fn backtrace(self: *Vm, frames: []Frame) []Frame {
+    const program_end = self.program_len;
+    const stack_top = self.regs.get(.isp).asUsize();
+
+    var count: usize = 0;
+    var addr = self.regs.sp();
+    while (addr + 8 <= stack_top and count < frames.len) : (addr += 8) {
+        const value = (self.mmu.read(addr, .qword) catch break).asU64();
+        if (value > program_end) continue;
+        if (self.callEndingAt(@intCast(value))) |frame| {
+            frames[count] = frame;
+            count += 1;
+        }
+    }
+    return frames[0..count];
+}
+
+fn callEndingAt(self: *Vm, return_address: usize) ?Frame {
+    // call_imm is the opcode and an 8-byte target, call_rel32 and call_rel8
+    // the opcode and a 4- or 1-byte displacement, call_reg the opcode and a
+    // register.
+    if (return_address >= 9 and self.opcodeAt(return_address - 9) == .call_imm) {
+        const target = self.mmu.read(return_address - 8, .qword) catch return null;
+        return .{ .call_site = return_address - 9, .target = target.asUsize() };
+    }
+    if (return_address >= 5 and self.opcodeAt(return_address - 5) == .call_rel32) {
+        const displacement = self.mmu.read(return_address - 4, .dword) catch return null;
+        const target = Opcode.relativeTarget(return_address, @bitCast(displacement.asU32()));
+        return .{ .call_site = return_address - 5, .target = @intCast(target) };
+    }
+    if (return_address >= 2 and self.opcodeAt(return_address - 2) == .call_rel8) {
+        const displacement = self.mmu.read(return_address - 1, .byte) catch return null;
+        const target = Opcode.relativeTarget(return_address, @as(i8, @bitCast(displacement.asU8())));
+        return .{ .call_site = return_address - 2, .target = @intCast(target) };
+    }
+    if (return_address >= 2 and self.opcodeAt(return_address - 2) == .call_reg) {
+        return .{ .call_site = return_address - 2, .target = null };
+    }
+    return null;
+}
+
+fn opcodeAt(self: *Vm, addr: usize) ?Opcode {
+    const byte = self.mmu.read(addr, .byte) catch return null;
+    return Opcode.fromU8(byte.asU8()) catch null;
+}
+
+/// Read the displacement of a relative jump or call and return the address
+/// it lands on, counted from the end of the instruction.
+inline fn readRelative(self: *Vm, comptime opcode: Opcode) !usize {
+    const displacement: i32 = switch (comptime opcode.operands()[0]) {
+        .rel8 => @as(i8, @bitCast(try self.readByte())),
+        .rel32 => @bitCast(try self.readDword()),
+        else => @compileError("not a relative jump"),
+    };
+    return @intCast(Opcode.relativeTarget(self.regs.ip(), displacement));
 }
 
 inline fn readByte(self: *Vm) !u8 {
@@ -501,6 +1456,20 @@ inline fn readRegister(self: *Vm) !Register {
     return Register.fromU8(byte);
 }
 
+/// Read a register operand that is about to be written. `ip` can only be
+/// changed through control flow instructions.
+inline fn readDestRegister(self: *Vm) !Register {
+    const reg = try self.readRegister();
+    if (reg == .ip) return error.InstructionPointerWrite;
+    return reg;
+}
+
+/// Write 1 or 0 to the register operand of a flag query like `seteq`.
+inline fn setFlag(self: *Vm, value: bool) !void {
+    const dest = try self.readDestRegister();
+    self.regs.set(dest, .{ .qword = @intFromBool(value) });
+}
+
 inline fn readDataSize(self: *Vm) !DataSize {
     const byte = try self.readByte();
     return DataSize.fromU8(byte);
@@ -546,7 +1515,7 @@ fn executeBinaryOp(
     comptime op: anytype,
     read_rhs_from_reg: bool,
 ) !void {
-    const dest = try self.readRegister();
+    const dest = try self.readDestRegister();
     const lhs = try self.readRegister();
     const lhs_val = self.regs.get(lhs);
 
@@ -564,40 +1533,195 @@ fn executeBinaryOp(
         };
     };
 
-    const result: Immediate = switch (DataSize.fromRegister(dest)) {
-        .byte => .{ .byte = op(lhs_val.asU8(), rhs_val.asU8()) },
-        .word => .{ .word = op(lhs_val.asU16(), rhs_val.asU16()) },
-        .dword => .{ .dword = op(lhs_val.asU32(), rhs_val.asU32()) },
-        .qword => .{ .qword = op(lhs_val.asU64(), rhs_val.asU64()) },
-        .float => .{ .float = op(lhs_val.asF32(), rhs_val.asF32()) },
-        .double => .{ .double = op(lhs_val.asF64(), rhs_val.asF64()) },
+    self.regs.set(dest, try self.binaryResult(op, DataSize.fromRegister(dest), lhs_val, rhs_val));
+}
+
+inline fn binaryResult(self: *Vm, comptime op: anytype, data_size: DataSize, lhs: Immediate, rhs: Immediate) !Immediate {
+    return switch (data_size) {
+        .byte => .{ .byte = try op(self, lhs.asU8(), rhs.asU8()) },
+        .word => .{ .word = try op(self, lhs.asU16(), rhs.asU16()) },
+        .dword => .{ .dword = try op(self, lhs.asU32(), rhs.asU32()) },
+        .qword => .{ .qword = try op(self, lhs.asU64(), rhs.asU64()) },
+        .float => .{ .float = try op(self, lhs.asF32(), rhs.asF32()) },
+        .double => .{ .double = try op(self, lhs.asF64(), rhs.asF64()) },
+    };
+}
+
+fn executeStepOp(self: *Vm, comptime op: enum { inc, dec }) !void {
+    const reg = try self.readDestRegister();
+    const value = self.regs.get(reg);
+    const new_value: Immediate = switch (op) {
+        .inc => switch (value) {
+            .byte => |imm| .{ .byte = imm +% try self.readByte() },
+            .word => |imm| .{ .word = imm +% try self.readWord() },
+            .dword => |imm| .{ .dword = imm +% try self.readDword() },
+            .qword => |imm| .{ .qword = imm +% try self.readQword() },
+            .float => |imm| .{ .float = imm + try self.readFloat() },
+            .double => |imm| .{ .double = imm + try self.readDouble() },
+        },
+        .dec => switch (value) {
+            .byte => |imm| .{ .byte = imm -% try self.readByte() },
+            .word => |imm| .{ .word = imm -% try self.readWord() },
+            .dword => |imm| .{ .dword = imm -% try self.readDword() },
+            .qword => |imm| .{ .qword = imm -% try self.readQword() },
+            .float => |imm| .{ .float = imm - try self.readFloat() },
+            .double => |imm| .{ .double = imm - try self.readDouble() },
+        },
     };
+    self.regs.set(reg, new_value);
+}
 
-    self.regs.set(dest, result);
+// Integer arithmetic wraps and sets `carry` and `overflow`: carry is the
+// unsigned carry or borrow out of the top bit, overflow a signed result that
+// does not fit. Floating-point arithmetic leaves both flags alone.
+
+inline fn add(self: *Vm, a: anytype, b: anytype) !@TypeOf(a, b) {
+    return self.addWithCarry(a, b, false);
+}
+
+inline fn adc(self: *Vm, a: anytype, b: anytype) !@TypeOf(a, b) {
+    return self.addWithCarry(a, b, self.flags.carry);
+}
+
+inline fn sub(self: *Vm, a: anytype, b: anytype) !@TypeOf(a, b) {
+    return self.subWithBorrow(a, b, false);
+}
+
+inline fn sbb(self: *Vm, a: anytype, b: anytype) !@TypeOf(a, b) {
+    return self.subWithBorrow(a, b, self.flags.carry);
+}
+
+inline fn mul(self: *Vm, a: anytype, b: anytype) !@TypeOf(a, b) {
+    const T = @TypeOf(a, b);
+    if (@typeInfo(T) == .float) return a * b;
+    const S = std.meta.Int(.signed, @bitSizeOf(T));
+    const product = @mulWithOverflow(a, b);
+    self.flags.carry = product[1] != 0;
+    self.flags.overflow = @mulWithOverflow(@as(S, @bitCast(a)), @as(S, @bitCast(b)))[1] != 0;
+    return product[0];
+}
+
+inline fn div(self: *Vm, a: anytype, b: anytype) !@TypeOf(a, b) {
+    const T = @TypeOf(a, b);
+    if (@typeInfo(T) == .float) return @divTrunc(a, b);
+    self.flags.carry = false;
+    self.flags.overflow = false;
+    return self.divideInt(T, a, b);
 }
 
-inline fn add(a: anytype, b: anytype) @TypeOf(a, b) {
-    return a + b;
+// `imul`, `idiv` and `imod` read the bits of integer registers as two's
+// complement. They are not defined for floating-point registers.
+
+inline fn imul(self: *Vm, a: anytype, b: anytype) !@TypeOf(a, b) {
+    const T = @TypeOf(a, b);
+    if (@typeInfo(T) == .float) return error.InvalidDataSize;
+    const S = std.meta.Int(.signed, @bitSizeOf(T));
+    const product = @mulWithOverflow(@as(S, @bitCast(a)), @as(S, @bitCast(b)));
+    // The signed product did not fit, so both flags say the same thing.
+    self.flags.carry = product[1] != 0;
+    self.flags.overflow = product[1] != 0;
+    return @bitCast(product[0]);
 }
 
-inline fn sub(a: anytype, b: anytype) @TypeOf(a, b) {
-    return a - b;
+inline fn idiv(self: *Vm, a: anytype, b: anytype) !@TypeOf(a, b) {
+    const T = @TypeOf(a, b);
+    if (@typeInfo(T) == .float) return error.InvalidDataSize;
+    const S = std.meta.Int(.signed, @bitSizeOf(T));
+    self.flags.carry = false;
+    self.flags.overflow = false;
+    return @bitCast(try self.divideInt(S, @bitCast(a), @bitCast(b)));
 }
 
-inline fn mul(a: anytype, b: anytype) @TypeOf(a, b) {
-    return a * b;
+/// The remainder of `idiv`, with the sign of the dividend.
+inline fn imod(self: *Vm, a: anytype, b: anytype) !@TypeOf(a, b) {
+    const T = @TypeOf(a, b);
+    if (@typeInfo(T) == .float) return error.InvalidDataSize;
+    const S = std.meta.Int(.signed, @bitSizeOf(T));
+    self.flags.carry = false;
+    self.flags.overflow = false;
+    return @bitCast(try self.remainderInt(S, @bitCast(a), @bitCast(b)));
 }
 
-inline fn div(a: anytype, b: anytype) @TypeOf(a, b) {
+/// Truncating integer division with the quotients that do not exist, a zero
+/// divisor and `minInt / -1` for signed types, handled as `division` says.
+fn divideInt(self: *Vm, comptime T: type, a: T, b: T) !T {
+    const signed = @typeInfo(T).int.signedness == .signed;
+    if (b == 0) return switch (self.division) {
+        .trap => error.DivisionByZero,
+        .saturate => if (signed and a < 0) std.math.minInt(T) else std.math.maxInt(T),
+        .wrap => 0,
+        .flag => self.flagDivision(T, 0),
+    };
+    if (comptime signed) {
+        if (a == std.math.minInt(T) and b == -1) return switch (self.division) {
+            .trap => error.DivisionOverflow,
+            .saturate => std.math.maxInt(T),
+            .wrap => a,
+            .flag => self.flagDivision(T, a),
+        };
+    }
     return @divTrunc(a, b);
 }
 
+/// Remainder of a truncating signed division. `minInt % -1` is 0; a zero
+/// divisor gives the dividend unless `division` traps.
+fn remainderInt(self: *Vm, comptime T: type, a: T, b: T) !T {
+    if (b == 0) return switch (self.division) {
+        .trap => error.DivisionByZero,
+        .saturate, .wrap => a,
+        .flag => self.flagDivision(T, a),
+    };
+    if (b == -1) return 0;
+    return @rem(a, b);
+}
+
+fn flagDivision(self: *Vm, comptime T: type, result: T) T {
+    self.flags.carry = true;
+    self.flags.overflow = true;
+    return result;
+}
+
+inline fn addWithCarry(self: *Vm, a: anytype, b: anytype, carry: bool) @TypeOf(a, b) {
+    const T = @TypeOf(a, b);
+    if (@typeInfo(T) == .float) return a + b;
+    const sum = @addWithOverflow(a, b);
+    const result = @addWithOverflow(sum[0], @intFromBool(carry));
+    self.flags.carry = sum[1] | result[1] != 0;
+    self.flags.overflow = signBit(T, (a ^ result[0]) & (b ^ result[0]));
+    return result[0];
+}
+
+inline fn subWithBorrow(self: *Vm, a: anytype, b: anytype, borrow: bool) @TypeOf(a, b) {
+    const T = @TypeOf(a, b);
+    if (@typeInfo(T) == .float) return a - b;
+    const difference = @subWithOverflow(a, b);
+    const result = @subWithOverflow(difference[0], @intFromBool(borrow));
+    self.flags.carry = difference[1] | result[1] != 0;
+    self.flags.overflow = signBit(T, (a ^ b) & (a ^ result[0]));
+    return result[0];
+}
+
+// Wrapping steps for `inc` and `dec` in decoded blocks, matching
+// `executeStepOp`: they leave the flags alone.
+
+inline fn stepUp(_: *Vm, a: anytype, b: anytype) !@TypeOf(a, b) {
+    return if (@typeInfo(@TypeOf(a, b)) == .float) a + b else a +% b;
+}
+
+inline fn stepDown(_: *Vm, a: anytype, b: anytype) !@TypeOf(a, b) {
+    return if (@typeInfo(@TypeOf(a, b)) == .float) a - b else a -% b;
+}
+
+inline fn signBit(comptime T: type, value: T) bool {
+    return value >> (@bitSizeOf(T) - 1) != 0;
+}
+
 fn executeBitwiseOp(
     self: *Vm,
     comptime op: anytype,
     read_rhs_from_reg: bool,
 ) !void {
-    const dest = try self.readRegister();
+    const dest = try self.readDestRegister();
     const lhs = try self.readRegister();
     const lhs_val = self.regs.get(lhs);
 
@@ -614,15 +1738,17 @@ fn executeBitwiseOp(
         };
     };
 
-    const result: Immediate = switch (DataSize.fromRegister(dest)) {
-        .byte => .{ .byte = op(lhs_val.asU8(), rhs_val.asU8()) },
-        .word => .{ .word = op(lhs_val.asU16(), rhs_val.asU16()) },
-        .dword => .{ .dword = op(lhs_val.asU32(), rhs_val.asU32()) },
-        .qword => .{ .qword = op(lhs_val.asU64(), rhs_val.asU64()) },
-        else => return error.InvalidDataSize,
-    };
+    self.regs.set(dest, try bitwiseResult(op, DataSize.fromRegister(dest), lhs_val, rhs_val));
+}
 
-    self.regs.set(dest, result);
+inline fn bitwiseResult(comptime op: anytype, data_size: DataSize, lhs: Immediate, rhs: Immediate) !Immediate {
+    return switch (data_size) {
+        .byte => .{ .byte = op(lhs.asU8(), rhs.asU8()) },
+        .word => .{ .word = op(lhs.asU16(), rhs.asU16()) },
+        .dword => .{ .dword = op(lhs.asU32(), rhs.asU32()) },
+        .qword => .{ .qword = op(lhs.asU64(), rhs.asU64()) },
+        else => error.InvalidDataSize,
+    };
 }
 
 inline fn bitAnd(a: anytype, b: anytype) @TypeOf(a, b) {
@@ -637,6 +1763,103 @@ inline fn bitXor(a: anytype, b: anytype) @TypeOf(a, b) {
     return a ^ b;
 }
 
+/// Bytes `scmp` and `schr` copy out of memory at a time.
+const string_chunk = 64;
+
+/// Compare the null-terminated strings at `lhs` and `rhs` byte by byte as
+/// unsigned values, like C's `strcmp`.
+fn compareStrings(self: *Vm, lhs: usize, rhs: usize) !std.math.Order {
+    var a: [string_chunk]u8 = undefined;
+    var b: [string_chunk]u8 = undefined;
+    var offset: usize = 0;
+    while (true) {
+        const len = @min(
+            try self.mmu.readInto(lhs +| offset, &a),
+            try self.mmu.readInto(rhs +| offset, &b),
+        );
+        if (len == 0) return error.AddressOutOfBounds;
+        const differ = std.mem.indexOfDiff(u8, a[0..len], b[0..len]) orelse len;
+        const end = std.mem.indexOfScalar(u8, a[0..differ], 0) orelse differ;
+        if (end < len) {
+            try self.mmu.checkRead(lhs, offset + end + 1);
+            try self.mmu.checkRead(rhs, offset + end + 1);
+            return std.math.order(a[end], b[end]);
+        }
+        offset += len;
+    }
+}
+
+/// Advance the address in `ptr` to the first `byte` among the number of
+/// bytes in `len`, taking the bytes skipped off `len`. Sets `eq` when it
+/// finds one; otherwise `ptr` ends up just past the range and `len` at 0.
+fn scanString(self: *Vm, ptr: Register, len: Register, byte: u8) !void {
+    const start = try self.regs.get(ptr).toAddress();
+    const count: usize = @intCast(self.regs.get(len).asU64());
+    var buf: [string_chunk]u8 = undefined;
+    var scanned: usize = 0;
+    const found = while (scanned < count) {
+        const n = try self.mmu.readInto(start +| scanned, buf[0..@min(buf.len, count - scanned)]);
+        if (n == 0) return error.AddressOutOfBounds;
+        if (std.mem.indexOfScalar(u8, buf[0..n], byte)) |i| {
+            scanned += i;
+            break true;
+        }
+        scanned += n;
+    } else false;
+
+    try self.mmu.checkRead(start, if (found) scanned + 1 else scanned);
+    self.regs.set(ptr, .{ .qword = start + scanned });
+    self.regs.set(len, .{ .qword = count - scanned });
+    self.flags.eq = found;
+    self.flags.lt = false;
+}
+
+fn complement(value: Immediate, data_size: DataSize) !Immediate {
+    return switch (data_size) {
+        .byte => .{ .byte = ~value.asU8() },
+        .word => .{ .word = ~value.asU16() },
+        .dword => .{ .dword = ~value.asU32() },
+        .qword => .{ .qword = ~value.asU64() },
+        else => error.InvalidDataSize,
+    };
+}
+
+/// `cvtif`: `value` read as a signed integer, rounded to the nearest
+/// `data_size` float.
+fn intToFloat(value: Immediate, data_size: DataSize) !Immediate {
+    const signed: i64 = switch (value) {
+        .byte => |v| @as(i8, @bitCast(v)),
+        .word => |v| @as(i16, @bitCast(v)),
+        .dword => |v| @as(i32, @bitCast(v)),
+        .qword => |v| @bitCast(v),
+        .float, .double => return error.InvalidDataSize,
+    };
+    return switch (data_size) {
+        .float => .{ .float = @floatFromInt(signed) },
+        .double => .{ .double = @floatFromInt(signed) },
+        else => error.InvalidDataSize,
+    };
+}
+
+/// `cvtfi`: `value` truncated toward zero to a signed `data_size` integer,
+/// clamped to its range. NaN becomes 0.
+fn floatToInt(value: Immediate, data_size: DataSize) !Immediate {
+    const float: f64 = switch (value) {
+        .float => |v| v,
+        .double => |v| v,
+        else => return error.InvalidDataSize,
+    };
+    return switch (data_size) {
+        inline .byte, .word, .dword, .qword => |t| blk: {
+            const Unsigned = @FieldType(Immediate, @tagName(t));
+            const Signed = std.meta.Int(.signed, @bitSizeOf(Unsigned));
+            const truncated: Signed = if (std.math.isNan(float)) 0 else std.math.lossyCast(Signed, float);
+            break :blk @unionInit(Immediate, @tagName(t), @bitCast(truncated));
+        },
+        else => error.InvalidDataSize,
+    };
+}
+
 inline fn shl(a: anytype, b: anytype) @TypeOf(a, b) {
     return a << @intCast(b);
 }
@@ -653,7 +1876,10 @@ inline fn ror(a: anytype, b: anytype) @TypeOf(a, b) {
     return std.math.rotr(@TypeOf(a), a, @as(u32, @intCast(b)));
 }
 
-fn readAddress(self: *Vm, data_size: DataSize) !Immediate {
+/// Decode an address operand: a register or qword base and a qword offset,
+/// both taken as signed so `[bp, -8]` reaches below the base. An address
+/// below zero is out of bounds.
+fn readEffectiveAddress(self: *Vm) !usize {
     const variant = try self.readByte();
     const base: i64 = @bitCast(switch (variant) {
         addressing_variant_1 => self.regs.get(try self.readRegister()).asU64(),
@@ -661,46 +1887,50 @@ fn readAddress(self: *Vm, data_size: DataSize) !Immediate {
         else => return error.UnknownAddressingVariant,
     });
     const offset: i64 = @bitCast(try self.readQword());
-    const addr: usize = @intCast(base + offset);
+    return std.math.cast(usize, base +% offset) orelse error.AddressOutOfBounds;
+}
+
+fn readAddress(self: *Vm, data_size: DataSize) !Immediate {
+    const addr = try self.readEffectiveAddress();
     return try self.mmu.read(addr, data_size);
 }
 
 fn executeBinaryOpRegRegAddr(self: *Vm, comptime op: anytype) !void {
-    const dest = try self.readRegister();
+    const dest = try self.readDestRegister();
     const lhs = try self.readRegister();
     const lhs_val = self.regs.get(lhs);
     const data_size = DataSize.fromRegister(dest);
     const rhs_val = try self.readAddress(data_size);
     const result: Immediate = switch (data_size) {
-        .byte => .{ .byte = op(lhs_val.asU8(), rhs_val.asU8()) },
-        .word => .{ .word = op(lhs_val.asU16(), rhs_val.asU16()) },
-        .dword => .{ .dword = op(lhs_val.asU32(), rhs_val.asU32()) },
-        .qword => .{ .qword = op(lhs_val.asU64(), rhs_val.asU64()) },
-        .float => .{ .float = op(lhs_val.asF32(), rhs_val.asF32()) },
-        .double => .{ .double = op(lhs_val.asF64(), rhs_val.asF64()) },
+        .byte => .{ .byte = try op(self, lhs_val.asU8(), rhs_val.asU8()) },
+        .word => .{ .word = try op(self, lhs_val.asU16(), rhs_val.asU16()) },
+        .dword => .{ .dword = try op(self, lhs_val.asU32(), rhs_val.asU32()) },
+        .qword => .{ .qword = try op(self, lhs_val.asU64(), rhs_val.asU64()) },
+        .float => .{ .float = try op(self, lhs_val.asF32(), rhs_val.asF32()) },
+        .double => .{ .double = try op(self, lhs_val.asF64(), rhs_val.asF64()) },
     };
     self.regs.set(dest, result);
 }
 
 fn executeBinaryOpRegAddrReg(self: *Vm, comptime op: anytype) !void {
-    const dest = try self.readRegister();
+    const dest = try self.readDestRegister();
     const data_size = DataSize.fromRegister(dest);
     const lhs_val = try self.readAddress(data_size);
     const rhs = try self.readRegister();
     const rhs_val = self.regs.get(rhs);
     const result: Immediate = switch (data_size) {
-        .byte => .{ .byte = op(lhs_val.asU8(), rhs_val.asU8()) },
-        .word => .{ .word = op(lhs_val.asU16(), rhs_val.asU16()) },
-        .dword => .{ .dword = op(lhs_val.asU32(), rhs_val.asU32()) },
-        .qword => .{ .qword = op(lhs_val.asU64(), rhs_val.asU64()) },
-        .float => .{ .float = op(lhs_val.asF32(), rhs_val.asF32()) },
-        .double => .{ .double = op(lhs_val.asF64(), rhs_val.asF64()) },
+        .byte => .{ .byte = try op(self, lhs_val.asU8(), rhs_val.asU8()) },
+        .word => .{ .word = try op(self, lhs_val.asU16(), rhs_val.asU16()) },
+        .dword => .{ .dword = try op(self, lhs_val.asU32(), rhs_val.asU32()) },
+        .qword => .{ .qword = try op(self, lhs_val.asU64(), rhs_val.asU64()) },
+        .float => .{ .float = try op(self, lhs_val.asF32(), rhs_val.asF32()) },
+        .double => .{ .double = try op(self, lhs_val.asF64(), rhs_val.asF64()) },
     };
     self.regs.set(dest, result);
 }
 
 fn executeBinaryOpRegAddrImm(self: *Vm, comptime op: anytype) !void {
-    const dest = try self.readRegister();
+    const dest = try self.readDestRegister();
     const data_size = DataSize.fromRegister(dest);
     const lhs_val = try self.readAddress(data_size);
     const rhs_val: Immediate = switch (data_size) {
@@ -712,34 +1942,34 @@ fn executeBinaryOpRegAddrImm(self: *Vm, comptime op: anytype) !void {
         .double => .{ .double = try self.readDouble() },
     };
     const result: Immediate = switch (data_size) {
-        .byte => .{ .byte = op(lhs_val.asU8(), rhs_val.asU8()) },
-        .word => .{ .word = op(lhs_val.asU16(), rhs_val.asU16()) },
-        .dword => .{ .dword = op(lhs_val.asU32(), rhs_val.asU32()) },
-        .qword => .{ .qword = op(lhs_val.asU64(), rhs_val.asU64()) },
-        .float => .{ .float = op(lhs_val.asF32(), rhs_val.asF32()) },
-        .double => .{ .double = op(lhs_val.asF64(), rhs_val.asF64()) },
+        .byte => .{ .byte = try op(self, lhs_val.asU8(), rhs_val.asU8()) },
+        .word => .{ .word = try op(self, lhs_val.asU16(), rhs_val.asU16()) },
+        .dword => .{ .dword = try op(self, lhs_val.asU32(), rhs_val.asU32()) },
+        .qword => .{ .qword = try op(self, lhs_val.asU64(), rhs_val.asU64()) },
+        .float => .{ .float = try op(self, lhs_val.asF32(), rhs_val.asF32()) },
+        .double => .{ .double = try op(self, lhs_val.asF64(), rhs_val.asF64()) },
     };
     self.regs.set(dest, result);
 }
 
 fn executeBinaryOpRegAddrAddr(self: *Vm, comptime op: anytype) !void {
-    const dest = try self.readRegister();
+    const dest = try self.readDestRegister();
     const data_size = DataSize.fromRegister(dest);
     const lhs_val = try self.readAddress(data_size);
     const rhs_val = try self.readAddress(data_size);
     const result: Immediate = switch (data_size) {
-        .byte => .{ .byte = op(lhs_val.asU8(), rhs_val.asU8()) },
-        .word => .{ .word = op(lhs_val.asU16(), rhs_val.asU16()) },
-        .dword => .{ .dword = op(lhs_val.asU32(), rhs_val.asU32()) },
-        .qword => .{ .qword = op(lhs_val.asU64(), rhs_val.asU64()) },
-        .float => .{ .float = op(lhs_val.asF32(), rhs_val.asF32()) },
-        .double => .{ .double = op(lhs_val.asF64(), rhs_val.asF64()) },
+        .byte => .{ .byte = try op(self, lhs_val.asU8(), rhs_val.asU8()) },
+        .word => .{ .word = try op(self, lhs_val.asU16(), rhs_val.asU16()) },
+        .dword => .{ .dword = try op(self, lhs_val.asU32(), rhs_val.asU32()) },
+        .qword => .{ .qword = try op(self, lhs_val.asU64(), rhs_val.asU64()) },
+        .float => .{ .float = try op(self, lhs_val.asF32(), rhs_val.asF32()) },
+        .double => .{ .double = try op(self, lhs_val.asF64(), rhs_val.asF64()) },
     };
     self.regs.set(dest, result);
 }
 
 fn executeBitwiseOpRegRegAddr(self: *Vm, comptime op: anytype) !void {
-    const dest = try self.readRegister();
+    const dest = try self.readDestRegister();
     const lhs = try self.readRegister();
     const lhs_val = self.regs.get(lhs);
     const data_size = DataSize.fromRegister(dest);
@@ -755,7 +1985,7 @@ fn executeBitwiseOpRegRegAddr(self: *Vm, comptime op: anytype) !void {
 }
 
 fn executeBitwiseOpRegAddrReg(self: *Vm, comptime op: anytype) !void {
-    const dest = try self.readRegister();
+    const dest = try self.readDestRegister();
     const data_size = DataSize.fromRegister(dest);
     const lhs_val = try self.readAddress(data_size);
     const rhs = try self.readRegister();
@@ -771,7 +2001,7 @@ fn executeBitwiseOpRegAddrReg(self: *Vm, comptime op: anytype) !void {
 }
 
 fn executeBitwiseOpRegAddrImm(self: *Vm, comptime op: anytype) !void {
-    const dest = try self.readRegister();
+    const dest = try self.readDestRegister();
     const data_size = DataSize.fromRegister(dest);
     const lhs_val = try self.readAddress(data_size);
     const rhs_val: Immediate = switch (data_size) {
@@ -792,7 +2022,7 @@ fn executeBitwiseOpRegAddrImm(self: *Vm, comptime op: anytype) !void {
 }
 
 fn executeBitwiseOpRegAddrAddr(self: *Vm, comptime op: anytype) !void {
-    const dest = try self.readRegister();
+    const dest = try self.readDestRegister();
     const data_size = DataSize.fromRegister(dest);
     const lhs_val = try self.readAddress(data_size);
     const rhs_val = try self.readAddress(data_size);
@@ -0,0 +1,148 @@
+//! Optional capabilities a program can declare with `.requires`, and the
+//! header that carries them.
+//!
+//! Required features are stored in the top 16 bits of the entry point
+//! header, which no real entry point reaches. A VM that lacks one of them,
+//! or sees a bit it does not know, refuses to load the program instead of
+//! failing later with an unknown syscall.
+//!
+//! One of those bits instead says that a second word follows with
+//! the size of the text and data sections, so the VM can check the program
+//! against them when loading it. Another says that a third word follows
+//! with the size of the debug section after the data.
+
+const std = @import("std");
+const mem = std.mem;
+const build_options = @import("build_options");
+
+pub const Feature = enum(u4) {
+    /// Floating-point registers and instructions.
+    float,
+    /// The socket syscalls.
+    net,
+    /// Calling into shared libraries with `.extern`.
+    ffi,
+    /// The clipboard and named pipe syscalls.
+    ipc,
+};
+
+pub const Set = std.EnumSet(Feature);
+
+const shift = 48;
+const entry_mask: u64 = (1 << shift) - 1;
+/// Set in the top bits when a `Layout` word follows the header. It is not a
+/// feature, so a VM from before layouts existed refuses such a program as
+/// one that needs something it does not know. Features must not use it.
+const layout_bit: u16 = 1 << 14;
+/// Set with `layout_bit` when a third word follows with the size of the
+/// debug section. Like the layout bit it is not a feature.
+const debug_bit: u16 = 1 << 13;
+
+/// Sizes of the sections that follow the header. The text section starts
+/// at address 0 and the data section right after it.
+pub const Layout = struct {
+    text_len: u32,
+    data_len: u32,
+    /// Bytes of `DebugInfo` after the data section, which are not loaded.
+    debug_len: u32 = 0,
+};
+
+/// The features this build of the VM provides.
+pub fn supported() Set {
+    var set = Set.initMany(&.{ .float, .ffi });
+    if (build_options.net) set.insert(.net);
+    if (build_options.ipc) set.insert(.ipc);
+    return set;
+}
+
+pub const Header = struct {
+    entry: u64,
+    required: Set,
+    /// Bits that do not name any feature, from a newer toolchain.
+    unknown: u16,
+    layout: ?Layout = null,
+
+    /// Bytes before the program starts.
+    pub fn size(self: Header) usize {
+        const layout = self.layout orelse return 8;
+        return if (layout.debug_len > 0) 24 else 16;
+    }
+
+    /// The text and data sections of `bytecode`, which the header was read
+    /// from, without the debug section.
+    pub fn program(self: Header, bytecode: []const u8) []const u8 {
+        const debug_len = if (self.layout) |layout| layout.debug_len else 0;
+        return bytecode[self.size() .. bytecode.len - debug_len];
+    }
+
+    /// The debug section at the end of `bytecode`, if it has one.
+    pub fn debugSection(self: Header, bytecode: []const u8) ?[]const u8 {
+        const layout = self.layout orelse return null;
+        if (layout.debug_len == 0) return null;
+        return bytecode[bytecode.len - layout.debug_len ..];
+    }
+};
+
+pub fn encode(entry: u64, required: Set) u64 {
+    var bits: u64 = 0;
+    var iter = required.iterator();
+    while (iter.next()) |feature| bits |= @as(u64, 1) << @intFromEnum(feature);
+    return entry | (bits << shift);
+}
+
+pub fn decode(header: u64) Header {
+    var bits: u16 = @intCast(header >> shift);
+    var required = Set.initEmpty();
+    inline for (comptime std.enums.values(Feature)) |feature| {
+        const bit = @as(u16, 1) << @intFromEnum(feature);
+        if (bits & bit != 0) {
+            required.insert(feature);
+            bits &= ~bit;
+        }
+    }
+    return .{ .entry = header & entry_mask, .required = required, .unknown = bits };
+}
+
+/// Read the header at the start of `bytecode`, with its layout if it has
+/// one.
+pub fn read(bytecode: []const u8) error{ProgramTooSmall}!Header {
+    if (bytecode.len < 8) return error.ProgramTooSmall;
+    const word = mem.readInt(u64, bytecode[0..8], .little);
+    const layout_mask = @as(u64, layout_bit) << shift;
+    if (word & layout_mask == 0) return decode(word);
+
+    if (bytecode.len < 16) return error.ProgramTooSmall;
+    const debug_mask = @as(u64, debug_bit) << shift;
+    var header = decode(word & ~layout_mask & ~debug_mask);
+    const layout = mem.readInt(u64, bytecode[8..16], .little);
+    header.layout = .{ .text_len = @truncate(layout), .data_len = @truncate(layout >> 32) };
+    if (word & debug_mask != 0) {
+        if (bytecode.len < 24) return error.ProgramTooSmall;
+        const debug_len = mem.readInt(u64, bytecode[16..24], .little);
+        if (debug_len == 0 or debug_len > bytecode.len - 24) return error.ProgramTooSmall;
+        header.layout.?.debug_len = @intCast(debug_len);
+    }
+    return header;
+}
+
+/// Write the header for a program, followed by its layout if given. The
+/// caller writes the debug section, if the layout has one, after the data.
+pub fn write(writer: *std.Io.Writer, entry: u64, required: Set, layout: ?Layout) std.Io.Writer.Error!void {
+    const word = encode(entry, required);
+    const sections = layout orelse return writer.writeInt(u64, word, .little);
+    const bits = if (sections.debug_len > 0) layout_bit | debug_bit else layout_bit;
+    try writer.writeInt(u64, word | @as(u64, bits) << shift, .little);
+    try writer.writeInt(u64, @as(u64, sections.data_len) << 32 | sections.text_len, .little);
+    if (sections.debug_len > 0) try writer.writeInt(u64, sections.debug_len, .little);
+}
+
+/// Write the names of `set` separated by ", ".
+pub fn writeNames(set: Set, writer: *std.Io.Writer) std.Io.Writer.Error!void {
+    var iter = set.iterator();
+    var first = true;
+    while (iter.next()) |feature| {
+        if (!first) try writer.writeAll(", ");
+        first = false;
+        try writer.writeAll(@tagName(feature));
+    }
+}
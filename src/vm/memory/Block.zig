@@ -1,28 +1,147 @@
+//! A contiguous range of the address space. A flat block owns every byte up
+//! front. A paged block allocates its bytes a page at a time, the first time
+//! a page is written or its host address is taken, so a large and mostly
+//! empty memory only costs the pages the program touches. Pages that were
+//! never touched read as `fill`.
+
 const std = @import("std");
 const mem = std.mem;
 const Allocator = mem.Allocator;
+const ArrayList = std.array_list.Managed;
 const Bus = @import("Bus.zig");
 const DataSize = @import("../../parser/immediate.zig").DataSize;
 const Immediate = @import("../../parser/immediate.zig").Immediate;
 
 const Block = @This();
 
+pub const page_size = 64 * 1024;
+
+const Page = *[page_size]u8;
+
 block_name: []const u8,
+len: usize,
+/// Byte that untouched memory reads as.
+fill: u8,
+/// Bytes of a flat block. Empty when the block is paged.
 storage: []u8,
+/// Pages of a paged block by index, null until first touched. Empty when
+/// the block is flat.
+pages: []?Page,
+/// Holds ranges returned by `readSlice` that are not in one resident page.
+scratch: ArrayList(u8),
 gpa: Allocator,
 
-pub fn init(block_name: []const u8, len: usize, gpa: Allocator) !Block {
-    var storage = try gpa.alloc(u8, len);
-    @memset(storage[0..], 0x00);
+pub fn init(block_name: []const u8, len: usize, fill: u8, gpa: Allocator) !Block {
+    const storage = try gpa.alloc(u8, len);
+    @memset(storage, fill);
     return Block{
         .block_name = block_name,
+        .len = len,
+        .fill = fill,
         .storage = storage,
+        .pages = &.{},
+        .scratch = .init(gpa),
+        .gpa = gpa,
+    };
+}
+
+pub fn initPaged(block_name: []const u8, len: usize, fill: u8, gpa: Allocator) !Block {
+    const pages = try gpa.alloc(?Page, std.math.divCeil(usize, len, page_size) catch unreachable);
+    @memset(pages, null);
+    return Block{
+        .block_name = block_name,
+        .len = len,
+        .fill = fill,
+        .storage = &.{},
+        .pages = pages,
+        .scratch = .init(gpa),
         .gpa = gpa,
     };
 }
 
 pub fn deinit(self: *Block) void {
     self.gpa.free(self.storage);
+    for (self.pages) |page| if (page) |p| self.gpa.destroy(p);
+    self.gpa.free(self.pages);
+    self.scratch.deinit();
+}
+
+pub fn isPaged(self: *const Block) bool {
+    return self.pages.len > 0;
+}
+
+/// Bytes of host memory holding the block's contents.
+pub fn residentBytes(self: *const Block) usize {
+    var count: usize = 0;
+    for (self.pages) |page| {
+        if (page != null) count += page_size;
+    }
+    return self.storage.len + count;
+}
+
+/// Make every byte of the block, touched or not, read as `pattern`.
+pub fn setFill(self: *Block, pattern: u8) void {
+    self.fill = pattern;
+    @memset(self.storage, pattern);
+    for (self.pages) |page| if (page) |p| @memset(p, pattern);
+}
+
+/// Make every byte read as `pattern` again, giving back the pages of a paged
+/// block.
+pub fn clear(self: *Block, pattern: u8) void {
+    self.fill = pattern;
+    @memset(self.storage, pattern);
+    for (self.pages) |*page| {
+        if (page.*) |p| self.gpa.destroy(p);
+        page.* = null;
+    }
+}
+
+/// Host bytes from `offset` that are contiguous: the rest of a flat block, or
+/// the rest of the page in a paged block.
+pub fn hostSlice(self: *Block, offset: usize) !?[]u8 {
+    if (offset >= self.len) return null;
+    if (!self.isPaged()) return self.storage[offset..];
+    const page = try self.touch(offset / page_size);
+    const page_start = offset - offset % page_size;
+    return page[offset % page_size .. @min(page_size, self.len - page_start)];
+}
+
+fn touch(self: *Block, index: usize) !Page {
+    if (self.pages[index]) |page| return page;
+    const page = try self.gpa.create([page_size]u8);
+    @memset(page, self.fill);
+    self.pages[index] = page;
+    return page;
+}
+
+fn copyOut(self: *Block, addr: usize, dest: []u8) void {
+    if (!self.isPaged()) return @memcpy(dest, self.storage[addr .. addr + dest.len]);
+    var done: usize = 0;
+    while (done < dest.len) {
+        const at = addr + done;
+        const offset = at % page_size;
+        const n = @min(page_size - offset, dest.len - done);
+        if (self.pages[at / page_size]) |page| {
+            @memcpy(dest[done .. done + n], page[offset .. offset + n]);
+        } else {
+            @memset(dest[done .. done + n], self.fill);
+        }
+        done += n;
+    }
+}
+
+fn copyIn(self: *Block, addr: usize, src: []const u8) !void {
+    if (!self.isPaged()) return @memcpy(self.storage[addr .. addr + src.len], src);
+    var done: usize = 0;
+    while (done < src.len) {
+        const at = addr + done;
+        const offset = at % page_size;
+        const n = @min(page_size - offset, src.len - done);
+        const page = try self.touch(at / page_size);
+        @memcpy(page[offset .. offset + n], src[done .. done + n]);
+        done += n;
+    }
 }
 
 fn name(ptr: *anyopaque) []const u8 {
@@ -32,93 +151,58 @@ fn name(ptr: *anyopaque) []const u8 {
 
 fn size(ptr: *anyopaque) usize {
     const self: *Block = @ptrCast(@alignCast(ptr));
-    return self.storage.len;
+    return self.len;
 }
 
 fn read(ptr: *anyopaque, addr: usize, sz: DataSize) anyerror!Immediate {
     const self: *Block = @ptrCast(@alignCast(ptr));
-    switch (sz) {
-        .byte => {
-            if (addr + 1 > size(ptr)) return error.AddressOutOfBounds;
-            return .{ .byte = self.storage[addr] };
-        },
-        .word => {
-            if (addr + 2 > size(ptr)) return error.AddressOutOfBounds;
-            return .{
-                .word = mem.readInt(u16, self.storage[addr .. addr + 2][0..2], .little),
-            };
-        },
-        .dword => {
-            if (addr + 4 > size(ptr)) return error.AddressOutOfBounds;
-            return .{
-                .dword = mem.readInt(u32, self.storage[addr .. addr + 4][0..4], .little),
-            };
-        },
-        .qword => {
-            if (addr + 8 > size(ptr)) return error.AddressOutOfBounds;
-            return .{
-                .qword = mem.readInt(u64, self.storage[addr .. addr + 8][0..8], .little),
-            };
-        },
-        .float => {
-            if (addr + 4 > size(ptr)) return error.AddressOutOfBounds;
-            const bytes = self.storage[addr .. addr + 4][0..4];
-            return .{
-                .float = @bitCast(mem.readInt(u32, bytes, .little)),
-            };
-        },
-        .double => {
-            if (addr + 8 > size(ptr)) return error.AddressOutOfBounds;
-            const bytes = self.storage[addr .. addr + 8][0..8];
-            return .{
-                .double = @bitCast(mem.readInt(u64, bytes, .little)),
-            };
-        },
-    }
+    if (addr + sz.sizeInBytes() > self.len) return error.AddressOutOfBounds;
+    var bytes: [8]u8 = undefined;
+    self.copyOut(addr, bytes[0..sz.sizeInBytes()]);
+    return switch (sz) {
+        .byte => .{ .byte = bytes[0] },
+        .word => .{ .word = mem.readInt(u16, bytes[0..2], .little) },
+        .dword => .{ .dword = mem.readInt(u32, bytes[0..4], .little) },
+        .qword => .{ .qword = mem.readInt(u64, bytes[0..8], .little) },
+        .float => .{ .float = @bitCast(mem.readInt(u32, bytes[0..4], .little)) },
+        .double => .{ .double = @bitCast(mem.readInt(u64, bytes[0..8], .little)) },
+    };
 }
 
 fn readSlice(ptr: *anyopaque, start: usize, end: usize) anyerror![]const u8 {
     const self: *Block = @ptrCast(@alignCast(ptr));
     if (start > end) return error.InvalidRange;
-    if (end > self.storage.len) return error.AddressOutOfBounds;
-    return self.storage[start..end];
+    if (end > self.len) return error.AddressOutOfBounds;
+    if (!self.isPaged()) return self.storage[start..end];
+
+    const offset = start % page_size;
+    if (end - start <= page_size - offset) {
+        if (self.pages[start / page_size]) |page| return page[offset .. offset + (end - start)];
+    }
+    try self.scratch.resize(end - start);
+    self.copyOut(start, self.scratch.items);
+    return self.scratch.items;
 }
 
 fn write(ptr: *anyopaque, addr: usize, value: Immediate, sz: DataSize) anyerror!void {
     const self: *Block = @ptrCast(@alignCast(ptr));
+    if (addr + sz.sizeInBytes() > self.len) return error.AddressOutOfBounds;
+    var bytes: [8]u8 = undefined;
     switch (sz) {
-        .byte => {
-            if (addr + 1 > size(ptr)) return error.AddressOutOfBounds;
-            self.storage[addr] = value.asU8();
-        },
-        .word => {
-            if (addr + 2 > size(ptr)) return error.AddressOutOfBounds;
-            @memcpy(self.storage[addr .. addr + 2], &mem.toBytes(value.asU16()));
-        },
-        .dword => {
-            if (addr + 4 > size(ptr)) return error.AddressOutOfBounds;
-            @memcpy(self.storage[addr .. addr + 4], &mem.toBytes(value.asU32()));
-        },
-        .qword => {
-            if (addr + 8 > size(ptr)) return error.AddressOutOfBounds;
-            @memcpy(self.storage[addr .. addr + 8], &mem.toBytes(value.asU64()));
-        },
-        .float => {
-            if (addr + 4 > size(ptr)) return error.AddressOutOfBounds;
-            @memcpy(self.storage[addr .. addr + 4], &mem.toBytes(value.asF32()));
-        },
-        .double => {
-            if (addr + 8 > size(ptr)) return error.AddressOutOfBounds;
-            @memcpy(self.storage[addr .. addr + 8], &mem.toBytes(value.asF64()));
-        },
+        .byte => bytes[0] = value.asU8(),
+        .word => mem.writeInt(u16, bytes[0..2], value.asU16(), .little),
+        .dword => mem.writeInt(u32, bytes[0..4], value.asU32(), .little),
+        .qword => mem.writeInt(u64, bytes[0..8], value.asU64(), .little),
+        .float => mem.writeInt(u32, bytes[0..4], @bitCast(value.asF32()), .little),
+        .double => mem.writeInt(u64, bytes[0..8], @bitCast(value.asF64()), .little),
     }
+    try self.copyIn(addr, bytes[0..sz.sizeInBytes()]);
 }
 
 fn writeSlice(ptr: *anyopaque, start: usize, data: []const u8) anyerror!void {
     const self: *Block = @ptrCast(@alignCast(ptr));
-    const end = start + data.len;
-    if (end > self.storage.len) return error.AddressOutOfBounds;
-    @memcpy(self.storage[start..end], data);
+    if (start + data.len > self.len) return error.AddressOutOfBounds;
+    try self.copyIn(start, data);
 }
 
 pub fn bus(self: *Block) Bus {
@@ -0,0 +1,36 @@
+//! A peripheral mapped into the MMIO window by a `DeviceBus`. Unlike a
+//! `Bus`, a device sees every access as it happens and may answer or react
+//! to it however it likes, e.g. a status register that reads differently
+//! each time, so the journal never reads it to record a write.
+
+const DataSize = @import("../../parser/immediate.zig").DataSize;
+const Immediate = @import("../../parser/immediate.zig").Immediate;
+
+const Device = @This();
+
+ptr: *anyopaque,
+vtable: *const VTable,
+
+pub const VTable = struct {
+    name: *const fn (*anyopaque) []const u8,
+    /// Bytes of the window the device answers for.
+    size: *const fn (*anyopaque) usize,
+    read: *const fn (*anyopaque, offset: usize, sz: DataSize) anyerror!Immediate,
+    write: *const fn (*anyopaque, offset: usize, value: Immediate, sz: DataSize) anyerror!void,
+};
+
+pub fn name(self: Device) []const u8 {
+    return self.vtable.name(self.ptr);
+}
+
+pub fn size(self: Device) usize {
+    return self.vtable.size(self.ptr);
+}
+
+pub fn read(self: Device, offset: usize, sz: DataSize) anyerror!Immediate {
+    return self.vtable.read(self.ptr, offset, sz);
+}
+
+pub fn write(self: Device, offset: usize, value: Immediate, sz: DataSize) anyerror!void {
+    return self.vtable.write(self.ptr, offset, value, sz);
+}
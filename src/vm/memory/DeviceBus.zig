@@ -0,0 +1,106 @@
+//! The MMIO window: a range of addresses, far above memory by default, that
+//! the MMU hands to devices instead of blocks. Each device is mapped at an
+//! offset into the window and answers for `size()` bytes from there. An
+//! access that starts in the window but not in a device, or runs past the
+//! end of the device it starts in, fails with `error.AddressOutOfBounds`.
+
+const std = @import("std");
+const Allocator = std.mem.Allocator;
+const ArrayList = std.array_list.Managed;
+const Device = @import("Device.zig");
+const DataSize = @import("../../parser/immediate.zig").DataSize;
+const Immediate = @import("../../parser/immediate.zig").Immediate;
+
+const DeviceBus = @This();
+
+/// Where the window starts unless `setWindow` moves it: 2^48, beyond any
+/// memory size a host could back. A 32-bit host has no such room, so there
+/// it takes the 256 MiB from 3 GiB, above what a VM can allocate in
+/// practice.
+pub const default_base: usize = if (@bitSizeOf(usize) >= 64) 1 << 48 else 0xC000_0000;
+pub const default_size: usize = if (@bitSizeOf(usize) >= 64) 1 << 32 else 0x1000_0000;
+
+base: usize,
+len: usize,
+/// Sorted by offset, not overlapping.
+mappings: ArrayList(Mapping),
+
+pub const Mapping = struct {
+    offset: usize,
+    device: Device,
+
+    pub fn end(self: Mapping) usize {
+        return self.offset + self.device.size();
+    }
+};
+
+pub fn init(gpa: Allocator) DeviceBus {
+    return DeviceBus{
+        .base = default_base,
+        .len = default_size,
+        .mappings = .init(gpa),
+    };
+}
+
+pub fn deinit(self: *DeviceBus) void {
+    self.mappings.deinit();
+}
+
+/// Move the window to `base..base + len`. Only while no device is mapped.
+pub fn setWindow(self: *DeviceBus, base: usize, len: usize) !void {
+    if (self.mappings.items.len != 0) return error.DevicesMapped;
+    _ = std.math.add(usize, base, len) catch return error.DeviceOutsideWindow;
+    self.base = base;
+    self.len = len;
+}
+
+/// Map `device` at `offset` into the window and return its address.
+pub fn map(self: *DeviceBus, device: Device, offset: usize) !usize {
+    const mapping = Mapping{ .offset = offset, .device = device };
+    if (device.size() == 0 or offset > self.len or device.size() > self.len - offset) return error.DeviceOutsideWindow;
+    const at = for (self.mappings.items, 0..) |other, i| {
+        if (other.offset >= mapping.end()) break i;
+        if (other.end() > offset) return error.DeviceOverlap;
+    } else self.mappings.items.len;
+    try self.mappings.insert(at, mapping);
+    return self.base + offset;
+}
+
+pub fn contains(self: *const DeviceBus, addr: usize) bool {
+    return addr >= self.base and addr - self.base < self.len;
+}
+
+/// The device `addr..addr + len` falls in and the offset of `addr` in it.
+fn find(self: *DeviceBus, addr: usize, len: usize) !struct { Device, usize } {
+    const offset = addr - self.base;
+    for (self.mappings.items) |mapping| {
+        if (offset < mapping.offset) break;
+        if (offset >= mapping.end()) continue;
+        if (len > mapping.end() - offset) return error.AddressOutOfBounds;
+        return .{ mapping.device, offset - mapping.offset };
+    }
+    return error.AddressOutOfBounds;
+}
+
+pub fn read(self: *DeviceBus, addr: usize, sz: DataSize) anyerror!Immediate {
+    const device, const offset = try self.find(addr, sz.sizeInBytes());
+    return device.read(offset, sz);
+}
+
+pub fn write(self: *DeviceBus, addr: usize, value: Immediate, sz: DataSize) anyerror!void {
+    const device, const offset = try self.find(addr, sz.sizeInBytes());
+    return device.write(offset, value, sz);
+}
+
+/// Read `buf.len` bytes one byte access at a time, the way a program
+/// copying from the device would.
+pub fn readBytes(self: *DeviceBus, addr: usize, buf: []u8) anyerror!void {
+    const device, const offset = try self.find(addr, buf.len);
+    for (buf, offset..) |*byte, at| byte.* = (try device.read(at, .byte)).asU8();
+}
+
+/// Write `data` one byte access at a time.
+pub fn writeBytes(self: *DeviceBus, addr: usize, data: []const u8) anyerror!void {
+    const device, const offset = try self.find(addr, data.len);
+    for (data, offset..) |byte, at| try device.write(at, .{ .byte = byte }, .byte);
+}
@@ -0,0 +1,172 @@
+//! First-fit free-list allocator behind `sys_alloc`, `sys_dealloc` and
+//! `sys_brk`. It hands out addresses in the region that starts at the end
+//! of the program and ends at the current break (the `brk` register), so
+//! allocations live in ordinary memory below the stack instead of in extra
+//! blocks. The allocator only keeps bookkeeping; it never touches memory.
+
+const std = @import("std");
+const mem = std.mem;
+const Allocator = mem.Allocator;
+const ArrayList = std.array_list.Managed;
+
+const Heap = @This();
+
+/// Every allocation starts and ends on this boundary.
+pub const alignment = 8;
+/// Heap size when none is configured, shrunk to fit small memories.
+pub const default_size: usize = 16 * 1024;
+
+pub const Range = struct {
+    addr: usize,
+    len: usize,
+
+    fn end(self: Range) usize {
+        return self.addr + self.len;
+    }
+};
+
+/// First address of the heap.
+base: usize,
+/// One past the last address of the heap.
+brk: usize,
+/// Unused ranges ordered by address, never adjacent to each other.
+free_list: ArrayList(Range),
+/// Size of every live allocation by address.
+live: std.AutoHashMap(usize, usize),
+
+pub fn init(base: usize, size: usize, gpa: Allocator) !Heap {
+    var heap = Heap{
+        // Address 0 is never handed out, so `sys_alloc` can use it for failure.
+        .base = mem.alignForward(usize, @max(base, 1), alignment),
+        .brk = 0,
+        .free_list = .init(gpa),
+        .live = .init(gpa),
+    };
+    heap.brk = heap.base;
+    errdefer heap.deinit();
+    const end = std.math.add(usize, heap.base, mem.alignBackward(usize, size, alignment)) catch return error.HeapTooLarge;
+    _ = try heap.setBreak(end);
+    return heap;
+}
+
+pub fn deinit(self: *Heap) void {
+    self.free_list.deinit();
+    self.live.deinit();
+}
+
+/// Reserve `size` bytes, or return null if no free range is large enough.
+pub fn alloc(self: *Heap, size: usize) !?usize {
+    const len = mem.alignForward(usize, @max(size, 1), alignment);
+    for (self.free_list.items, 0..) |*range, i| {
+        if (range.len < len) continue;
+        const addr = range.addr;
+        try self.live.put(addr, len);
+        if (range.len == len) {
+            _ = self.free_list.orderedRemove(i);
+        } else {
+            range.addr += len;
+            range.len -= len;
+        }
+        return addr;
+    }
+    return null;
+}
+
+/// Return an allocation made by `alloc` to the free list.
+pub fn free(self: *Heap, addr: usize) !void {
+    const entry = self.live.fetchRemove(addr) orelse return error.InvalidFreeAddress;
+    try self.release(.{ .addr = addr, .len = entry.value });
+}
+
+/// Move the end of the heap to `new_brk`. Growing adds free space; shrinking
+/// only succeeds when nothing is allocated past the new end. Returns whether
+/// the break moved.
+pub fn setBreak(self: *Heap, new_brk: usize) !bool {
+    if (new_brk < self.base or new_brk % alignment != 0) return false;
+    if (new_brk > self.brk) {
+        try self.release(.{ .addr = self.brk, .len = new_brk - self.brk });
+    } else if (new_brk < self.brk) {
+        const last = if (self.free_list.items.len > 0) &self.free_list.items[self.free_list.items.len - 1] else return false;
+        if (last.end() != self.brk or last.addr > new_brk) return false;
+        last.len = new_brk - last.addr;
+        if (last.len == 0) _ = self.free_list.pop();
+    }
+    self.brk = new_brk;
+    return true;
+}
+
+/// Every live allocation in address order. Caller owns the returned memory.
+pub fn allocations(self: *const Heap, gpa: Allocator) ![]Range {
+    const result = try gpa.alloc(Range, self.live.count());
+    var iter = self.live.iterator();
+    var i: usize = 0;
+    while (iter.next()) |entry| : (i += 1) result[i] = .{ .addr = entry.key_ptr.*, .len = entry.value_ptr.* };
+    mem.sort(Range, result, {}, struct {
+        fn lessThan(_: void, a: Range, b: Range) bool {
+            return a.addr < b.addr;
+        }
+    }.lessThan);
+    return result;
+}
+
+/// Replace the bookkeeping with a heap from `base` to `brk` that has
+/// `free_ranges` unused and `live` allocated, as `allocations` lists them.
+pub fn restore(self: *Heap, base: usize, brk: usize, free_ranges: []const Range, live: []const Range) !void {
+    self.free_list.clearRetainingCapacity();
+    try self.free_list.appendSlice(free_ranges);
+    self.live.clearRetainingCapacity();
+    for (live) |range| try self.live.put(range.addr, range.len);
+    self.base = base;
+    self.brk = brk;
+}
+
+/// Bytes currently allocated.
+pub fn bytesInUse(self: *const Heap) usize {
+    var total: usize = 0;
+    var iter = self.live.valueIterator();
+    while (iter.next()) |len| total += len.*;
+    return total;
+}
+
+pub const Fragmentation = struct {
+    free_blocks: usize,
+    free_bytes: usize,
+    largest_free: usize,
+
+    /// Percentage of free memory outside the largest free block. Zero means
+    /// one allocation can use all of it.
+    pub fn external(self: Fragmentation) f64 {
+        if (self.free_bytes == 0) return 0;
+        const outside: f64 = @floatFromInt(self.free_bytes - self.largest_free);
+        return outside * 100 / @as(f64, @floatFromInt(self.free_bytes));
+    }
+};
+
+/// How the free memory up to the break is split.
+pub fn fragmentation(self: *const Heap) Fragmentation {
+    var result = Fragmentation{ .free_blocks = self.free_list.items.len, .free_bytes = 0, .largest_free = 0 };
+    for (self.free_list.items) |range| {
+        result.free_bytes += range.len;
+        result.largest_free = @max(result.largest_free, range.len);
+    }
+    return result;
+}
+
+/// Insert `range` into the free list, merging it with its neighbours.
+fn release(self: *Heap, range: Range) !void {
+    var index: usize = 0;
+    while (index < self.free_list.items.len and self.free_list.items[index].addr < range.addr) index += 1;
+
+    var merged = range;
+    if (index > 0 and self.free_list.items[index - 1].end() == merged.addr) {
+        index -= 1;
+        merged.addr = self.free_list.items[index].addr;
+        merged.len += self.free_list.items[index].len;
+        _ = self.free_list.orderedRemove(index);
+    }
+    if (index < self.free_list.items.len and self.free_list.items[index].addr == merged.end()) {
+        merged.len += self.free_list.items[index].len;
+        _ = self.free_list.orderedRemove(index);
+    }
+    try self.free_list.insert(index, merged);
+}
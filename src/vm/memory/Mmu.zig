@@ -3,21 +3,99 @@ const Allocator = std.mem.Allocator;
 const ArrayList = std.array_list.Managed;
 const Bus = @import("Bus.zig");
 const Block = @import("Block.zig");
+const DeviceBus = @import("DeviceBus.zig");
 const DataSize = @import("../../parser/immediate.zig").DataSize;
 const Immediate = @import("../../parser/immediate.zig").Immediate;
+const Journal = @import("../Journal.zig");
 
 const Mmu = @This();
 
 buses: ArrayList(Bus),
 blocks: ArrayList(*Block),
 allocated_slices: ArrayList([]u8),
+/// Devices that answer for the MMIO window in place of blocks.
+devices: DeviceBus,
+/// Byte that new blocks are initialized with.
+fill: u8,
+/// When set, the previous contents of every write are recorded here.
+journal: ?*Journal,
+/// Addresses that fault with `error.StackGuardHit` when read or written.
+guard: ?Guard,
+/// First guarded address the last faulting access touched.
+guard_hit: usize,
+/// End of the code in the block cache. Writes below it bump `code_writes`
+/// so the decoded blocks get dropped.
+code_end: usize,
+code_writes: u64,
+/// Ranges of memory and the accesses they allow. Addresses outside every
+/// region allow any access.
+regions: ArrayList(Region),
+/// Refuse accesses that `regions` do not allow.
+protect: bool,
+/// What the last access refused by a region tried to do.
+fault: Fault,
 gpa: Allocator,
 
+pub const Guard = struct {
+    start: usize,
+    end: usize,
+};
+
+pub const Access = enum { read, write, execute };
+
+pub const Permissions = packed struct {
+    read: bool = false,
+    write: bool = false,
+    execute: bool = false,
+
+    pub const rw: Permissions = .{ .read = true, .write = true };
+    pub const rx: Permissions = .{ .read = true, .execute = true };
+    pub const rwx: Permissions = .{ .read = true, .write = true, .execute = true };
+
+    pub fn allows(self: Permissions, access: Access) bool {
+        return switch (access) {
+            .read => self.read,
+            .write => self.write,
+            .execute => self.execute,
+        };
+    }
+
+    /// Write the permissions the way `ls -l` does, e.g. `r-x`.
+    pub fn format(self: Permissions, writer: *std.Io.Writer) std.Io.Writer.Error!void {
+        try writer.writeByte(if (self.read) 'r' else '-');
+        try writer.writeByte(if (self.write) 'w' else '-');
+        try writer.writeByte(if (self.execute) 'x' else '-');
+    }
+};
+
+pub const Region = struct {
+    name: []const u8,
+    start: usize,
+    end: usize,
+    permissions: Permissions,
+};
+
+pub const Fault = struct {
+    addr: usize = 0,
+    access: Access = .read,
+    region: Region = .{ .name = "", .start = 0, .end = 0, .permissions = .{} },
+};
+
 pub fn init(gpa: Allocator) Mmu {
     return Mmu{
         .buses = .init(gpa),
         .blocks = .init(gpa),
         .allocated_slices = ArrayList([]u8).init(gpa),
+        .devices = .init(gpa),
+        .fill = 0x00,
+        .journal = null,
+        .guard = null,
+        .guard_hit = 0,
+        .code_end = 0,
+        .code_writes = 0,
+        .regions = .init(gpa),
+        .protect = true,
+        .fault = .{},
         .gpa = gpa,
     };
 }
@@ -34,16 +112,28 @@ pub fn deinit(self: *Mmu) void {
     }
     self.allocated_slices.deinit();
     self.buses.deinit();
+    self.devices.deinit();
+    self.regions.deinit();
 }
 
 pub fn addBlock(self: *Mmu, block_name: []const u8, len: usize) !usize {
+    return self.appendBlock(try Block.init(block_name, len, self.fill, self.gpa));
+}
+
+/// Like `addBlock`, but pages are only allocated when they are first
+/// written, so a large block that is mostly unused stays cheap.
+pub fn addPagedBlock(self: *Mmu, block_name: []const u8, len: usize) !usize {
+    return self.appendBlock(try Block.initPaged(block_name, len, self.fill, self.gpa));
+}
+
+fn appendBlock(self: *Mmu, initial: Block) !usize {
     const start = self.size();
 
+    var owned = initial;
+    errdefer owned.deinit();
     const block = try self.gpa.create(Block);
     errdefer self.gpa.destroy(block);
-
-    block.* = try Block.init(block_name, len, self.gpa);
-    errdefer block.deinit();
+    block.* = owned;
 
     try self.blocks.append(block);
     try self.buses.append(block.bus());
@@ -51,11 +141,81 @@ pub fn addBlock(self: *Mmu, block_name: []const u8, len: usize) !usize {
     return start;
 }
 
+/// Drop every block after the first `count`.
+pub fn truncateBlocks(self: *Mmu, count: usize) void {
+    while (self.blocks.items.len > count) {
+        const block = self.blocks.pop().?;
+        _ = self.buses.pop();
+        block.deinit();
+        self.gpa.destroy(block);
+    }
+}
+
 pub fn addBus(self: *Mmu, bus: Bus) !void {
     return self.buses.append(bus);
 }
 
+/// Fail with `error.StackGuardHit` if `addr..addr + len` touches the guard.
+fn checkGuard(self: *Mmu, addr: usize, len: usize) !void {
+    const guard = self.guard orelse return;
+    if (addr < guard.end and addr +| len > guard.start) {
+        self.guard_hit = @max(addr, guard.start);
+        return error.StackGuardHit;
+    }
+}
+
+/// Give `start..end` the `permissions`, in place of whatever regions it
+/// overlapped.
+pub fn setRegion(self: *Mmu, name: []const u8, start: usize, end: usize, permissions: Permissions) !void {
+    var i: usize = 0;
+    while (i < self.regions.items.len) {
+        const region = self.regions.items[i];
+        if (region.end <= start or region.start >= end) {
+            i += 1;
+            continue;
+        }
+        _ = self.regions.orderedRemove(i);
+        if (region.start < start) {
+            var before = region;
+            before.end = start;
+            try self.regions.insert(i, before);
+            i += 1;
+        }
+        if (region.end > end) {
+            var after = region;
+            after.start = end;
+            try self.regions.insert(i, after);
+            i += 1;
+        }
+    }
+    const at = for (self.regions.items, 0..) |region, j| {
+        if (region.start >= end) break j;
+    } else self.regions.items.len;
+    try self.regions.insert(at, .{ .name = name, .start = start, .end = end, .permissions = permissions });
+}
+
+/// Fail with `error.ProtectionFault` if a region covering any of
+/// `addr..addr + len` does not allow `access`.
+fn checkAccess(self: *Mmu, addr: usize, len: usize, access: Access) !void {
+    if (!self.protect) return;
+    for (self.regions.items) |region| {
+        if (addr < region.end and addr +| len > region.start and !region.permissions.allows(access)) {
+            self.fault = .{ .addr = @max(addr, region.start), .access = access, .region = region };
+            return error.ProtectionFault;
+        }
+    }
+}
+
+/// Fail with `error.ProtectionFault` if the instruction at `addr` may not be
+/// executed.
+pub fn checkExecute(self: *Mmu, addr: usize) !void {
+    return self.checkAccess(addr, 1, .execute);
+}
+
 pub fn read(self: *Mmu, addr: usize, sz: DataSize) anyerror!Immediate {
+    try self.checkGuard(addr, sz.sizeInBytes());
+    try self.checkAccess(addr, sz.sizeInBytes(), .read);
+    if (self.devices.contains(addr)) return self.devices.read(addr, sz);
     var start: usize = 0;
     for (self.buses.items) |*bus| {
         const end = start + bus.size();
@@ -69,8 +229,15 @@ pub fn read(self: *Mmu, addr: usize, sz: DataSize) anyerror!Immediate {
 }
 
 pub fn readSlice(self: *Mmu, addr: usize, len: usize) anyerror![]const u8 {
+    try self.checkGuard(addr, len);
+    try self.checkAccess(addr, len, .read);
     var result = try self.gpa.alloc(u8, len);
     errdefer self.gpa.free(result);
+    if (self.devices.contains(addr)) {
+        try self.devices.readBytes(addr, result);
+        try self.allocated_slices.append(result);
+        return result;
+    }
 
     var bytes_read: usize = 0;
     var current_addr = addr;
@@ -103,7 +270,43 @@ pub fn readSlice(self: *Mmu, addr: usize, len: usize) anyerror![]const u8 {
     return result;
 }
 
+/// Copy the bytes at `addr` into `buf`, stopping early at the end of memory,
+/// and return how many were copied. Unlike `readSlice` it does not allocate
+/// and does not check the stack guard or the regions, so callers that scan
+/// ahead check the bytes they end up using with `checkRead`. Nothing is
+/// copied from the MMIO window, where reading ahead would reach devices.
+pub fn readInto(self: *Mmu, addr: usize, buf: []u8) anyerror!usize {
+    if (self.devices.contains(addr)) return 0;
+    var copied: usize = 0;
+    var start: usize = 0;
+    for (self.buses.items) |*bus| {
+        const end = start + bus.size();
+        const current_addr = addr + copied;
+        if (current_addr >= start and current_addr < end) {
+            const offset = current_addr - start;
+            const to_read = @min(end - current_addr, buf.len - copied);
+            @memcpy(buf[copied .. copied + to_read], try bus.readSlice(offset, offset + to_read));
+            copied += to_read;
+            if (copied == buf.len) break;
+        }
+        start = end;
+    }
+    return copied;
+}
+
+/// Fail like `read` would if any of the `len` bytes at `addr` may not be
+/// read.
+pub fn checkRead(self: *Mmu, addr: usize, len: usize) !void {
+    try self.checkGuard(addr, len);
+    try self.checkAccess(addr, len, .read);
+}
+
 pub fn write(self: *Mmu, addr: usize, value: Immediate, sz: DataSize) anyerror!void {
+    try self.checkGuard(addr, sz.sizeInBytes());
+    try self.checkAccess(addr, sz.sizeInBytes(), .write);
+    if (self.devices.contains(addr)) return self.devices.write(addr, value, sz);
+    try self.recordOld(addr, sz.sizeInBytes());
+    if (addr < self.code_end) self.code_writes +%= 1;
     var start: usize = 0;
     for (self.buses.items) |*bus| {
         const end = start + bus.size();
@@ -117,6 +320,11 @@ pub fn write(self: *Mmu, addr: usize, value: Immediate, sz: DataSize) anyerror!v
 }
 
 pub fn writeSlice(self: *Mmu, addr: usize, data: []const u8) anyerror!void {
+    try self.checkGuard(addr, data.len);
+    try self.checkAccess(addr, data.len, .write);
+    if (self.devices.contains(addr)) return self.devices.writeBytes(addr, data);
+    try self.recordOld(addr, data.len);
+    if (addr < self.code_end) self.code_writes +%= 1;
     var bytes_written: usize = 0;
     var current_addr = addr;
 
@@ -143,6 +351,18 @@ pub fn writeSlice(self: *Mmu, addr: usize, data: []const u8) anyerror!void {
     }
 }
 
+fn recordOld(self: *Mmu, addr: usize, len: usize) !void {
+    const journal = self.journal orelse return;
+    const old = try journal.gpa.alloc(u8, len);
+    for (old, 0..) |*byte, i| {
+        byte.* = (self.read(addr + i, .byte) catch {
+            journal.gpa.free(old);
+            return;
+        }).asU8();
+    }
+    try journal.recordWrite(addr, old);
+}
+
 pub fn size(self: *Mmu) usize {
     var sz: usize = 0;
     for (self.buses.items) |*bus| {
@@ -151,19 +371,70 @@ pub fn size(self: *Mmu) usize {
     return sz;
 }
 
-pub fn resolveHostPtr(self: *Mmu, addr: usize) ?[*]u8 {
+/// Guest memory handed to native code, which expects the bytes after a
+/// pointer to be contiguous. A paged block only keeps them contiguous up to
+/// the end of a page, so a range that crosses one is copied into `bounce`
+/// and `settle` writes back the bytes that changed.
+pub const Loan = struct {
+    addr: usize,
+    ptr: [*]u8,
+    bounce: ?[]u8 = null,
+    original: ?[]u8 = null,
+
+    pub fn deinit(self: Loan, gpa: Allocator) void {
+        if (self.bounce) |bytes| gpa.free(bytes);
+        if (self.original) |bytes| gpa.free(bytes);
+    }
+};
+
+/// Lend the `len` bytes at `addr`, or fewer at the end of their block, to
+/// native code. Returns null when `addr` is not in a block. Call `settle`
+/// after the native code returns, then `Loan.deinit`.
+pub fn lend(self: *Mmu, addr: usize, len: usize) !?Loan {
     var start: usize = 0;
     for (self.blocks.items) |block| {
-        const end = start + block.storage.len;
+        const end = start + block.len;
         if (addr >= start and addr < end) {
-            const offset = addr - start;
-            return block.storage.ptr + offset;
+            const wanted = @min(len, end - addr);
+            const host = (try block.hostSlice(addr - start)) orelse return null;
+            if (host.len >= wanted) return .{ .addr = addr, .ptr = host.ptr };
+
+            const bounce = try self.gpa.alloc(u8, wanted);
+            errdefer self.gpa.free(bounce);
+            _ = try self.readInto(addr, bounce);
+            const original = try self.gpa.dupe(u8, bounce);
+            return .{ .addr = addr, .ptr = bounce.ptr, .bounce = bounce, .original = original };
         }
         start = end;
     }
     return null;
 }
 
+/// Write back every run of bytes that native code changed in a bounced
+/// loan. Loans of contiguous memory were changed in place.
+pub fn settle(self: *Mmu, loan: Loan) !void {
+    const bounce = loan.bounce orelse return;
+    const original = loan.original.?;
+    var i: usize = 0;
+    while (i < bounce.len) {
+        if (bounce[i] == original[i]) {
+            i += 1;
+            continue;
+        }
+        var j = i + 1;
+        while (j < bounce.len and bounce[j] != original[j]) j += 1;
+        try self.writeSlice(loan.addr + i, bounce[i..j]);
+        i = j;
+    }
+}
+
+/// Bytes of host memory backing every block.
+pub fn residentBytes(self: *Mmu) usize {
+    var total: usize = 0;
+    for (self.blocks.items) |block| total += block.residentBytes();
+    return total;
+}
+
 pub fn debug(self: *Mmu) void {
     var start: usize = 0;
     for (self.buses.items) |*bus| {
@@ -1,6 +1,7 @@
 const std = @import("std");
 const mem = std.mem;
 const Immediate = @import("../parser/immediate.zig").Immediate;
+const DataSize = @import("../parser/immediate.zig").DataSize;
 
 const gpr0: usize = 0x00;
 const gpr1: usize = 0x01;
@@ -57,6 +58,12 @@ const ip_reg: usize = 0;
 const sp_reg: usize = 1;
 const bp_reg: usize = 2;
 
+const zero_reg: usize = 0;
+
+const msz_reg: usize = 0;
+const brk_reg: usize = 1;
+const isp_reg: usize = 2;
+
 pub const Register = enum {
     b0,
     w0,
@@ -157,6 +164,10 @@ pub const Register = enum {
     ip,
     sp,
     bp,
+    zr,
+    msz,
+    brk,
+    isp,
 
     pub fn fromString(value: []const u8) !Register {
         if (mem.eql(u8, value, "b0")) return .b0;
@@ -275,11 +286,17 @@ pub const Register = enum {
         if (mem.eql(u8, value, "sp")) return .sp;
         if (mem.eql(u8, value, "bp")) return .bp;
 
+        if (mem.eql(u8, value, "zr")) return .zr;
+
+        if (mem.eql(u8, value, "msz")) return .msz;
+        if (mem.eql(u8, value, "brk")) return .brk;
+        if (mem.eql(u8, value, "isp")) return .isp;
+
         return error.InvalidRegister;
     }
 
     pub fn fromU8(value: u8) !Register {
-        if (value > @intFromEnum(Register.bp)) {
+        if (value > @intFromEnum(Register.isp)) {
             return error.InvalidRegister;
         }
         return @enumFromInt(value);
@@ -402,6 +419,12 @@ pub const Register = enum {
             .ip => PhysicalInfo.init(.special, ip_reg, .qword),
             .sp => PhysicalInfo.init(.special, sp_reg, .qword),
             .bp => PhysicalInfo.init(.special, bp_reg, .qword),
+
+            .zr => PhysicalInfo.init(.zero, zero_reg, .qword),
+
+            .msz => PhysicalInfo.init(.config, msz_reg, .qword),
+            .brk => PhysicalInfo.init(.config, brk_reg, .qword),
+            .isp => PhysicalInfo.init(.config, isp_reg, .qword),
         };
     }
 };
@@ -424,6 +447,8 @@ const PhysicalRegisterType = enum {
     general_purpose,
     floating_point,
     special,
+    zero,
+    config,
 };
 
 const RegisterView = enum {
@@ -439,12 +464,14 @@ pub const Registers = struct {
     gpr: [16]u64,
     fpr: [32]u64,
     special: [3]usize,
+    config: [3]usize,
 
     pub fn init() Registers {
         return Registers{
             .gpr = mem.zeroes([16]u64),
             .fpr = mem.zeroes([32]u64),
             .special = mem.zeroes([3]usize),
+            .config = mem.zeroes([3]usize),
         };
     }
 
@@ -477,53 +504,60 @@ pub const Registers = struct {
                     else => unreachable,
                 };
             },
+            .zero => return .{ .qword = 0 },
+            .config => return .{ .qword = @intCast(self.config[info.index]) },
         }
     }
 
+    /// Store `imm` in `reg`, converting it to the register's size with
+    /// `Immediate.toLossy`.
     pub fn set(self: *Registers, reg: Register, imm: Immediate) void {
         const info = reg.physicalInfo();
+        const value = imm.toLossy(switch (info.view) {
+            .byte => DataSize.byte,
+            .word => .word,
+            .dword => .dword,
+            .qword => .qword,
+            .float => .float,
+            .double => .double,
+        });
 
         switch (info.type) {
-            .general_purpose => switch (info.view) {
-                .byte => {
-                    const new_value = imm.asU8();
-                    self.gpr[info.index] = (self.gpr[info.index] & 0xFFFFFFFFFFFFFF00) | (@as(u64, @intCast(new_value)));
-                },
-                .word => {
-                    const new_value = imm.asU16();
-                    self.gpr[info.index] = (self.gpr[info.index] & 0xFFFFFFFFFFFF0000) | (@as(u64, @intCast(new_value)));
-                },
-                .dword => {
-                    const new_value = imm.asU32();
-                    self.gpr[info.index] = @intCast(new_value);
-                },
-                .qword => {
-                    const new_value = imm.asU64();
-                    self.gpr[info.index] = new_value;
-                },
-                else => unreachable,
-            },
-            .floating_point => switch (info.view) {
-                .float => {
-                    const new_value = imm.asF32();
-                    self.fpr[info.index] = @as(u64, @as(u32, @bitCast(new_value)));
-                },
-                .double => {
-                    const new_value = imm.asF64();
-                    self.fpr[info.index] = @bitCast(new_value);
-                },
+            .general_purpose => switch (value) {
+                .byte => |v| self.gpr[info.index] = (self.gpr[info.index] & 0xFFFFFFFFFFFFFF00) | @as(u64, v),
+                .word => |v| self.gpr[info.index] = (self.gpr[info.index] & 0xFFFFFFFFFFFF0000) | @as(u64, v),
+                .dword => |v| self.gpr[info.index] = @as(u64, v),
+                .qword => |v| self.gpr[info.index] = v,
                 else => unreachable,
             },
-            .special => switch (info.view) {
-                .qword => {
-                    const new_value = imm.asUsize();
-                    self.special[info.index] = new_value;
-                },
+            .floating_point => switch (value) {
+                .float => |v| self.fpr[info.index] = @as(u64, @as(u32, @bitCast(v))),
+                .double => |v| self.fpr[info.index] = @bitCast(v),
                 else => unreachable,
             },
+            .special => self.special[info.index] = @intCast(value.qword),
+            .zero, .config => {},
         }
     }
 
+    /// Copy the integer register `src` into `dest`, filling the bits above
+    /// it with zeros or, when `signed`, with copies of its top bit. Like
+    /// `set`, a byte or word `dest` keeps the rest of its register.
+    pub fn extend(self: *Registers, dest: Register, src: Register, signed: bool) !void {
+        switch (dest.physicalInfo().view) {
+            .float, .double => return error.InvalidDataSize,
+            else => {},
+        }
+        const value: u64 = switch (self.get(src)) {
+            .byte => |v| if (signed) @bitCast(@as(i64, @as(i8, @bitCast(v)))) else v,
+            .word => |v| if (signed) @bitCast(@as(i64, @as(i16, @bitCast(v)))) else v,
+            .dword => |v| if (signed) @bitCast(@as(i64, @as(i32, @bitCast(v)))) else v,
+            .qword => |v| v,
+            .float, .double => return error.InvalidDataSize,
+        };
+        self.set(dest, .{ .qword = value });
+    }
+
     pub fn ip(self: *Registers) usize {
         return self.special[ip_reg];
     }
@@ -547,4 +581,17 @@ pub const Registers = struct {
     pub fn setBp(self: *Registers, val: usize) void {
         self.special[bp_reg] = val;
     }
+
+    /// Set the read-only configuration registers. These are only written
+    /// by the VM; writes from bytecode are ignored.
+    pub fn setConfig(self: *Registers, mem_size: usize, brk: usize, initial_sp: usize) void {
+        self.config[msz_reg] = mem_size;
+        self.config[brk_reg] = brk;
+        self.config[isp_reg] = initial_sp;
+    }
+
+    /// Move `brk` to the end of the heap, wherever the VM puts it.
+    pub fn setBrk(self: *Registers, val: usize) void {
+        self.config[brk_reg] = val;
+    }
 };
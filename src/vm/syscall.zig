@@ -1,61 +1,515 @@
-// TODO: err register so program doesn't crash on a zig error
 const std = @import("std");
 const builtin = @import("builtin");
 const native_os = builtin.os.tag;
 const posix = std.posix.system;
 const Allocator = std.mem.Allocator;
+const Register = @import("register.zig").Register;
+const FileTable = @import("FileTable.zig");
+const Timer = @import("Timer.zig");
+const Framebuffer = @import("Framebuffer.zig");
+const Display = @import("Display.zig");
 const Vm = @import("Vm.zig");
+const build_options = @import("build_options");
 
 pub const SyscallFn = *const fn (self: *Vm) anyerror!void;
 pub const Syscalls = std.AutoHashMap(usize, SyscallFn);
 
+/// Host capability a syscall needs in order to do its work.
+pub const Permission = enum {
+    none,
+    fs,
+    net,
+    memory,
+    process,
+    /// The host clipboard and named pipes.
+    ipc,
+};
+
+pub const Arg = struct {
+    register: []const u8,
+    description: []const u8,
+};
+
+pub const Descriptor = struct {
+    number: usize,
+    /// Lowercase name without the `sys_` prefix, e.g. `write`. The
+    /// preprocessor defines `SYS_<NAME>` for every entry.
+    name: []const u8,
+    description: []const u8,
+    args: []const Arg,
+    returns: ?Arg,
+    permission: Permission,
+    handler: SyscallFn,
+};
+
+/// The syscall number is read from `number_register`. Argument N is passed
+/// in general purpose register N, of which a syscall may use only the low
+/// bytes, e.g. `d1` for a 32-bit second argument. The result comes back in
+/// `result_register`. `error_register` is cleared by every syscall and set
+/// to 1 when it fails, in which case the result is -1. Other registers are
+/// preserved unless the syscall documents more outputs.
+pub const number_register: Register = .q15;
+pub const result_register: Register = .q0;
+pub const error_register: Register = .q14;
+pub const arg_registers = [_]Register{ .q0, .q1, .q2, .q3, .q4, .q5 };
+pub const max_args = arg_registers.len;
+
+/// Every syscall known to the VM. This is the single source of truth for
+/// syscall numbers: the dispatch map, the `SYS_*` preprocessor definitions
+/// and `nyx syscalls` are all derived from it.
+pub const table = [_]Descriptor{
+    .{
+        .number = 0x00,
+        .name = "open",
+        .description = "Open a file",
+        .args = &.{
+            .{ .register = "q0", .description = "pointer to a null-terminated path" },
+            .{ .register = "d1", .description = "flags" },
+            .{ .register = "w2", .description = "mode" },
+        },
+        .returns = .{ .register = "q0", .description = "file descriptor" },
+        .permission = .fs,
+        .handler = sysOpen,
+    },
+    .{
+        .number = 0x01,
+        .name = "close",
+        .description = "Close a file descriptor",
+        .args = &.{
+            .{ .register = "d0", .description = "file descriptor" },
+        },
+        .returns = .{ .register = "q0", .description = "result" },
+        .permission = .fs,
+        .handler = sysClose,
+    },
+    .{
+        .number = 0x02,
+        .name = "read",
+        .description = "Read from a file descriptor",
+        .args = &.{
+            .{ .register = "d0", .description = "file descriptor" },
+            .{ .register = "q1", .description = "buffer address" },
+            .{ .register = "q2", .description = "byte count" },
+        },
+        .returns = .{ .register = "q0", .description = "bytes read" },
+        .permission = .fs,
+        .handler = sysRead,
+    },
+    .{
+        .number = 0x03,
+        .name = "write",
+        .description = "Write to a file descriptor",
+        .args = &.{
+            .{ .register = "d0", .description = "file descriptor" },
+            .{ .register = "q1", .description = "buffer address" },
+            .{ .register = "q2", .description = "byte count" },
+        },
+        .returns = .{ .register = "q0", .description = "bytes written" },
+        .permission = .fs,
+        .handler = sysWrite,
+    },
+    .{
+        .number = 0x04,
+        .name = "malloc",
+        .description = "Allocate dynamic memory",
+        .args = &.{
+            .{ .register = "q0", .description = "size in bytes" },
+        },
+        .returns = .{ .register = "q0", .description = "block address" },
+        .permission = .memory,
+        .handler = sysMalloc,
+    },
+    .{
+        .number = 0x05,
+        .name = "free",
+        .description = "Free dynamic memory",
+        .args = &.{
+            .{ .register = "q0", .description = "block address" },
+        },
+        .returns = null,
+        .permission = .memory,
+        .handler = sysFree,
+    },
+    .{
+        .number = 0x06,
+        .name = "socket",
+        .description = "Create a network socket",
+        .args = &.{
+            .{ .register = "d0", .description = "domain" },
+            .{ .register = "d1", .description = "socket type" },
+            .{ .register = "d2", .description = "protocol" },
+        },
+        .returns = .{ .register = "d0", .description = "socket file descriptor" },
+        .permission = .net,
+        .handler = sysSocket,
+    },
+    .{
+        .number = 0x07,
+        .name = "connect",
+        .description = "Connect a socket",
+        .args = &.{
+            .{ .register = "d0", .description = "socket file descriptor" },
+            .{ .register = "q1", .description = "sockaddr_in address" },
+        },
+        .returns = .{ .register = "q0", .description = "result" },
+        .permission = .net,
+        .handler = sysConnect,
+    },
+    .{
+        .number = 0x08,
+        .name = "bind",
+        .description = "Bind a socket to an address",
+        .args = &.{
+            .{ .register = "d0", .description = "socket file descriptor" },
+            .{ .register = "q1", .description = "sockaddr_in address" },
+        },
+        .returns = .{ .register = "q0", .description = "result" },
+        .permission = .net,
+        .handler = sysBind,
+    },
+    .{
+        .number = 0x09,
+        .name = "listen",
+        .description = "Listen on a socket",
+        .args = &.{
+            .{ .register = "d0", .description = "socket file descriptor" },
+            .{ .register = "d1", .description = "backlog" },
+        },
+        .returns = .{ .register = "d0", .description = "result" },
+        .permission = .net,
+        .handler = sysListen,
+    },
+    .{
+        .number = 0x0A,
+        .name = "accept",
+        .description = "Accept a connection on a socket",
+        .args = &.{
+            .{ .register = "d0", .description = "socket file descriptor" },
+            .{ .register = "q1", .description = "sockaddr_in address to fill" },
+        },
+        .returns = .{ .register = "q0", .description = "client file descriptor" },
+        .permission = .net,
+        .handler = sysAccept,
+    },
+    .{
+        .number = 0x0B,
+        .name = "dup",
+        .description = "Duplicate a file descriptor",
+        .args = &.{
+            .{ .register = "d0", .description = "file descriptor" },
+        },
+        .returns = .{ .register = "q0", .description = "new file descriptor" },
+        .permission = .fs,
+        .handler = sysDup,
+    },
+    .{
+        .number = 0x0C,
+        .name = "dup2",
+        .description = "Redirect a file descriptor to another file",
+        .args = &.{
+            .{ .register = "d0", .description = "file descriptor to copy" },
+            .{ .register = "d1", .description = "file descriptor to replace" },
+        },
+        .returns = .{ .register = "q0", .description = "replaced file descriptor" },
+        .permission = .fs,
+        .handler = sysDup2,
+    },
+    .{
+        .number = 0x0D,
+        .name = "present",
+        .description = "Draw the console buffer on the terminal",
+        .args = &.{},
+        .returns = .{ .register = "q0", .description = "result" },
+        .permission = .none,
+        .handler = sysPresent,
+    },
+    .{
+        .number = 0x0E,
+        .name = "console",
+        .description = "Get the console buffer, or zeros if there is none",
+        .args = &.{},
+        .returns = .{ .register = "q0", .description = "buffer address (q1 rows, q2 columns)" },
+        .permission = .none,
+        .handler = sysConsole,
+    },
+    .{
+        .number = 0x0F,
+        .name = "alloc",
+        .description = "Allocate memory from the heap below the stack",
+        .args = &.{
+            .{ .register = "q0", .description = "size in bytes" },
+        },
+        .returns = .{ .register = "q0", .description = "address, or 0 if the heap is full" },
+        .permission = .memory,
+        .handler = sysAlloc,
+    },
+    .{
+        .number = 0x10,
+        .name = "dealloc",
+        .description = "Return memory from sys_alloc to the heap",
+        .args = &.{
+            .{ .register = "q0", .description = "address" },
+        },
+        .returns = null,
+        .permission = .memory,
+        .handler = sysDealloc,
+    },
+    .{
+        .number = 0x11,
+        .name = "brk",
+        .description = "Move the end of the heap, or read it when q0 is 0",
+        .args = &.{
+            .{ .register = "q0", .description = "new end of the heap, or 0" },
+        },
+        .returns = .{ .register = "q0", .description = "end of the heap afterwards" },
+        .permission = .memory,
+        .handler = sysBrk,
+    },
+    .{
+        .number = 0x12,
+        .name = "ticks",
+        .description = "Read the number of instructions executed so far",
+        .args = &.{},
+        .returns = .{ .register = "q0", .description = "instructions executed, counting this syscall" },
+        .permission = .none,
+        .handler = sysTicks,
+    },
+    .{
+        .number = 0x13,
+        .name = "timer",
+        .description = "Fire a timer every q0 instructions or milliseconds, or stop it when q0 is 0",
+        .args = &.{
+            .{ .register = "q0", .description = "period, or 0 to stop" },
+            .{ .register = "b1", .description = "unit: 0 instructions, 1 milliseconds" },
+        },
+        .returns = .{ .register = "q0", .description = "result" },
+        .permission = .none,
+        .handler = sysTimer,
+    },
+    .{
+        .number = 0x14,
+        .name = "timer_poll",
+        .description = "Collect the times the timer fired since the last poll",
+        .args = &.{},
+        .returns = .{ .register = "q0", .description = "expirations, 0 if none" },
+        .permission = .none,
+        .handler = sysTimerPoll,
+    },
+    .{
+        .number = 0x15,
+        .name = "clipboard_read",
+        .description = "Copy up to q1 bytes of the host clipboard into a buffer",
+        .args = &.{
+            .{ .register = "q0", .description = "pointer to buffer" },
+            .{ .register = "q1", .description = "buffer size" },
+        },
+        .returns = .{ .register = "q0", .description = "length of the clipboard text, which may exceed q1" },
+        .permission = .ipc,
+        .handler = sysClipboardRead,
+    },
+    .{
+        .number = 0x16,
+        .name = "clipboard_write",
+        .description = "Replace the host clipboard with a buffer",
+        .args = &.{
+            .{ .register = "q0", .description = "pointer to buffer" },
+            .{ .register = "q1", .description = "number of bytes" },
+        },
+        .returns = .{ .register = "q0", .description = "result" },
+        .permission = .ipc,
+        .handler = sysClipboardWrite,
+    },
+    .{
+        .number = 0x17,
+        .name = "mkfifo",
+        .description = "Create a named pipe, which sys_open then opens like a file",
+        .args = &.{
+            .{ .register = "q0", .description = "pointer to null-terminated path" },
+            .{ .register = "w1", .description = "mode" },
+        },
+        .returns = .{ .register = "q0", .description = "result" },
+        .permission = .ipc,
+        .handler = sysMkfifo,
+    },
+    .{
+        .number = 0x18,
+        .name = "read_line",
+        .description = "Read a line from stdin, up to q1 bytes, keeping the newline; q1 is 1 afterwards if stdin ended",
+        .args = &.{
+            .{ .register = "q0", .description = "buffer address" },
+            .{ .register = "q1", .description = "buffer size" },
+        },
+        .returns = .{ .register = "q0", .description = "bytes read, 0 at the end of input" },
+        .permission = .fs,
+        .handler = sysReadLine,
+    },
+    .{
+        .number = 0x19,
+        .name = "args",
+        .description = "Get the program's arguments, or zeros if it was given none",
+        .args = &.{},
+        .returns = .{ .register = "q0", .description = "argc (q1 argv address)" },
+        .permission = .none,
+        .handler = sysArgs,
+    },
+    .{
+        .number = 0x1A,
+        .name = "getenv",
+        .description = "Look up an environment variable passed to the program",
+        .args = &.{
+            .{ .register = "q0", .description = "address of the null-terminated name" },
+        },
+        .returns = .{ .register = "q0", .description = "address of the null-terminated value" },
+        .permission = .none,
+        .handler = sysGetenv,
+    },
+    .{
+        .number = 0x1B,
+        .name = "framebuffer",
+        .description = "Get the framebuffer, or zeros if there is none",
+        .args = &.{},
+        .returns = .{ .register = "q0", .description = "pixels address (q1 width, q2 height)" },
+        .permission = .none,
+        .handler = sysFramebuffer,
+    },
+    .{
+        .number = 0x1C,
+        .name = "display",
+        .description = "Show the framebuffer in the display window",
+        .args = &.{},
+        .returns = .{ .register = "q0", .description = "result" },
+        .permission = .none,
+        .handler = sysDisplay,
+    },
+    .{
+        .number = 0x1D,
+        .name = "poll_key",
+        .description = "Take the next key event without waiting, or 0 if there is none",
+        .args = &.{},
+        .returns = .{ .register = "q0", .description = "key code (q1 1 if pressed, 0 if released)" },
+        .permission = .none,
+        .handler = sysPollKey,
+    },
+    .{
+        .number = 0xFF,
+        .name = "exit",
+        .description = "Exit the program",
+        .args = &.{
+            .{ .register = "b0", .description = "exit status" },
+        },
+        .returns = null,
+        .permission = .process,
+        .handler = sysExit,
+    },
+};
+
 pub fn collectSyscalls(gpa: Allocator) !Syscalls {
     var syscalls = Syscalls.init(gpa);
-
-    try syscalls.put(0x00, sysOpen);
-    try syscalls.put(0x01, sysClose);
-    try syscalls.put(0x02, sysRead);
-    try syscalls.put(0x03, sysWrite);
-    try syscalls.put(0x04, sysMalloc);
-    try syscalls.put(0x05, sysFree);
-    try syscalls.put(0x06, sysSocket);
-    try syscalls.put(0x07, sysConnect);
-    try syscalls.put(0x08, sysBind);
-    try syscalls.put(0x09, sysListen);
-    try syscalls.put(0x0A, sysAccept);
-    try syscalls.put(0xFF, sysExit);
+    errdefer syscalls.deinit();
+
+    for (table) |entry| {
+        // Builds without networking leave the socket calls unregistered;
+        // programs that need them declare `.requires net`.
+        if (entry.permission == .net and !build_options.net) continue;
+        if (entry.permission == .ipc and !build_options.ipc) continue;
+        try syscalls.put(entry.number, entry.handler);
+    }
 
     return syscalls;
 }
 
+/// Print the syscall table in a human readable form.
+pub fn printTable(writer: *std.Io.Writer) std.Io.Writer.Error!void {
+    for (table) |entry| {
+        try writer.print("0x{X:0>2}  sys_{s:<10} {s} [{s}]\n", .{
+            entry.number,
+            entry.name,
+            entry.description,
+            @tagName(entry.permission),
+        });
+        for (entry.args) |arg| {
+            try writer.print("        in   {s:<4} {s}\n", .{ arg.register, arg.description });
+        }
+        if (entry.returns) |ret| {
+            try writer.print("        out  {s:<4} {s}\n", .{ ret.register, ret.description });
+        }
+    }
+}
+
+/// Write an include file with a `sys_<name>` macro for every syscall, which
+/// moves its arguments into the registers of the syscall convention in
+/// order, then makes the call. This is `std/syscall.nyx`.
+pub fn writeHeader(writer: *std.Io.Writer) std.Io.Writer.Error!void {
+    try writer.writeAll(
+        \\; Generated by `nyx syscalls --header`, do not edit.
+        \\;
+        \\; Arguments are moved into q0, q1, ... in order, so an argument must
+        \\; not be a register that an earlier argument overwrites. The result is
+        \\; in q0, and q14 is 1 if the call failed and 0 otherwise.
+        \\
+        \\#ifndef NYX_SYSCALL
+        \\#define NYX_SYSCALL
+        \\
+    );
+    for (table) |entry| {
+        try writer.print("\n; {s}\n; sys_{s}(", .{ entry.description, entry.name });
+        for (entry.args, 0..) |arg, i| {
+            if (i > 0) try writer.writeAll(", ");
+            try writer.print("{s}: {s}", .{ arg.register, arg.description });
+        }
+        try writer.writeAll(")");
+        if (entry.returns) |ret| try writer.print(" -> {s}: {s}", .{ ret.register, ret.description });
+        try writer.print("\n#macro sys_{s} (", .{entry.name});
+        for (0..entry.args.len) |i| {
+            if (i > 0) try writer.writeAll(", ");
+            try writer.print("$arg{d}", .{i});
+        }
+        try writer.writeAll(")\n");
+        for (0..entry.args.len) |i| {
+            try writer.print("    mov {s}, $arg{d}\n", .{ @tagName(arg_registers[i]), i });
+        }
+        try writer.print("    mov {s}, SYS_", .{@tagName(number_register)});
+        for (entry.name) |c| try writer.writeByte(std.ascii.toUpper(c));
+        try writer.writeAll("\n    syscall\n#endm\n");
+    }
+    try writer.writeAll("\n#endif ; NYX_SYSCALL\n");
+}
+
 fn sysOpen(self: *Vm) anyerror!void {
-    const path_addr = self.regs.get(.q0).asUsize();
-    const flags = self.regs.get(.d1).asU32();
-    const mode = self.regs.get(.w2).asU16();
+    const args = self.syscallArgs();
+    const path_addr: usize = @intCast(args[0]);
+    const flags: u32 = @truncate(args[1]);
+    const mode: u16 = @truncate(args[2]);
 
-    if (path_addr >= self.mmu.size()) return error.AddressOutOfBounds;
+    const path = try readPath(self, path_addr);
 
-    const path = blk: {
-        var i = path_addr;
-        while ((try self.mmu.read(i, .byte)).asU8() != 0) i += 1;
-        break :blk try self.mmu.readSlice(path_addr, i - path_addr);
-    };
+    const host = FileTable.toFd(posix.open(path.ptr, @bitCast(flags), mode)) orelse
+        return self.setSyscallError();
+    const fd = try self.files.add(host);
 
-    const fd = posix.open(@ptrCast(path), @bitCast(flags), mode);
+    self.setSyscallResult(@intCast(fd));
+}
 
-    self.regs.set(.q0, .{ .qword = @intCast(fd) });
+/// The null-terminated string at `addr`, terminator included.
+fn readPath(self: *Vm, addr: usize) ![:0]const u8 {
+    if (addr >= self.mmu.size()) return error.AddressOutOfBounds;
+    var i = addr;
+    while ((try self.mmu.read(i, .byte)).asU8() != 0) i += 1;
+    const bytes = try self.mmu.readSlice(addr, i - addr + 1);
+    return bytes[0 .. bytes.len - 1 :0];
 }
 
 fn sysClose(self: *Vm) anyerror!void {
-    const fd: i32 = @intCast(self.regs.get(.d0).asU32());
-    const res = posix.close(fd);
-    self.regs.set(.q0, .{ .qword = @intCast(res) });
+    const fd = guestFd(self.syscallArgs()[0]);
+    if (!self.files.close(fd)) return self.setSyscallError();
+    self.setSyscallResult(0);
 }
 
 fn sysRead(self: *Vm) anyerror!void {
-    const fd: i32 = @intCast(self.regs.get(.d0).asU32());
-    const addr = self.regs.get(.q1).asUsize();
-    const count = self.regs.get(.q2).asUsize();
+    const args = self.syscallArgs();
+    const fd = hostFd(self, args[0]) orelse return self.setSyscallError();
+    const addr: usize = @intCast(args[1]);
+    const count: usize = @intCast(args[2]);
 
     if (addr + count >= self.mmu.size()) return error.AddressOutOfBounds;
 
@@ -70,42 +524,179 @@ fn sysRead(self: *Vm) anyerror!void {
 
     try self.mmu.writeSlice(addr, buf[0..n]);
 
-    self.regs.set(.q0, .{ .qword = @intCast(n) });
+    self.setSyscallResult(@intCast(n));
+}
+
+/// Set in the second argument register by `sys_read_line` when stdin ended
+/// before a newline.
+pub const eof_register = arg_registers[1];
+
+fn sysReadLine(self: *Vm) anyerror!void {
+    const args = self.syscallArgs();
+    const addr: usize = @intCast(args[0]);
+    const capacity: usize = @intCast(args[1]);
+    const fd = self.files.get(0) orelse return self.setSyscallError();
+
+    // One byte at a time, so nothing after the newline is taken from stdin
+    // before the program asks for it. The bytes go into the program's buffer
+    // a chunk at a time, so the capacity it asks for costs the host nothing.
+    var chunk: [256]u8 = undefined;
+    var chunk_len: usize = 0;
+    var stored: usize = 0;
+    var eof = false;
+    while (stored + chunk_len < capacity) {
+        var byte: [1]u8 = undefined;
+        const rc = posix.read(fd, &byte, 1);
+        if (std.posix.errno(rc) != .SUCCESS) return self.setSyscallError();
+        if (rc == 0) {
+            eof = true;
+            break;
+        }
+        chunk[chunk_len] = byte[0];
+        chunk_len += 1;
+        if (chunk_len == chunk.len) {
+            try self.mmu.writeSlice(addr + stored, &chunk);
+            stored += chunk_len;
+            chunk_len = 0;
+        }
+        if (byte[0] == '\n') break;
+    }
+
+    try self.mmu.writeSlice(addr + stored, chunk[0..chunk_len]);
+    stored += chunk_len;
+    self.regs.set(eof_register, .{ .qword = @intFromBool(eof) });
+    self.setSyscallResult(stored);
 }
 
 fn sysWrite(self: *Vm) anyerror!void {
-    const fd: i32 = @intCast(self.regs.get(.d0).asU32());
-    const addr = self.regs.get(.q1).asUsize();
-    const count = self.regs.get(.q2).asUsize();
+    const args = self.syscallArgs();
+    const fd = hostFd(self, args[0]) orelse return self.setSyscallError();
+    const addr: usize = @intCast(args[1]);
+    const count: usize = @intCast(args[2]);
 
     if (addr + count >= self.mmu.size()) return error.AddressOutOfBounds;
 
     const buf = try self.mmu.readSlice(addr, count);
     const n = posix.write(fd, @ptrCast(buf), buf.len);
 
-    self.regs.set(.q0, .{ .qword = @intCast(n) });
+    self.setSyscallResult(@intCast(n));
 }
 
 fn sysMalloc(self: *Vm) anyerror!void {
-    const size: usize = self.regs.get(.q0).asUsize();
-    const addr = try self.mmu.addBlock("Block", size);
-    self.regs.set(.q0, .{ .qword = @intCast(addr) });
+    const size: usize = @intCast(self.syscallArgs()[0]);
+    const addr = try self.mmu.addBlock(Vm.malloc_block_name, size);
+    if (self.heap_stats) |*stats| try stats.recordAlloc(addr, size, self.regs.ip() - 1);
+    self.setSyscallResult(@intCast(addr));
+}
+
+fn sysAlloc(self: *Vm) anyerror!void {
+    const size: usize = @intCast(self.syscallArgs()[0]);
+    const addr = try self.heap.alloc(size) orelse 0;
+    if (addr != 0) if (self.heap_stats) |*stats| try stats.recordAlloc(addr, size, self.regs.ip() - 1);
+    self.setSyscallResult(@intCast(addr));
+}
+
+fn sysDealloc(self: *Vm) anyerror!void {
+    const addr: usize = @intCast(self.syscallArgs()[0]);
+    const len = self.heap.live.get(addr) orelse 0;
+    self.heap.free(addr) catch |err| {
+        if (err == error.InvalidFreeAddress) if (self.heap_stats) |*stats| {
+            stats.invalid_frees += 1;
+        };
+        return err;
+    };
+    if (self.heap_stats) |*stats| stats.recordFree(addr);
+
+    // With `--fill`, freed memory reads as the pattern again so a use after
+    // free stands out like a read of memory that was never written.
+    if (self.mmu.fill == 0) return;
+    var pattern: [256]u8 = undefined;
+    @memset(&pattern, self.mmu.fill);
+    var offset: usize = 0;
+    while (offset < len) {
+        const n = @min(len - offset, pattern.len);
+        try self.mmu.writeSlice(addr + offset, pattern[0..n]);
+        offset += n;
+    }
+}
+
+fn sysBrk(self: *Vm) anyerror!void {
+    const requested: usize = @intCast(self.syscallArgs()[0]);
+    // The heap may not grow into the stack or its guard.
+    const limit = if (self.mmu.guard) |guard| @min(guard.start, self.regs.sp()) else self.regs.sp();
+    if (requested != 0 and requested <= limit) _ = try self.heap.setBreak(requested);
+    self.regs.setBrk(self.heap.brk);
+    self.setSyscallResult(@intCast(self.heap.brk));
+}
+
+fn sysTicks(self: *Vm) anyerror!void {
+    self.setSyscallResult(self.counters.instructions);
+}
+
+fn sysTimer(self: *Vm) anyerror!void {
+    const args = self.syscallArgs();
+    const period = args[0];
+    const unit = std.enums.fromInt(Timer.Unit, @as(u8, @truncate(args[1]))) orelse return self.setSyscallError();
+    self.timer.start(period, unit, self.counters.instructions);
+    self.setSyscallResult(0);
+}
+
+fn sysTimerPoll(self: *Vm) anyerror!void {
+    self.setSyscallResult(self.timer.poll(self.counters.instructions));
+}
+
+fn sysClipboardRead(self: *Vm) anyerror!void {
+    const args = self.syscallArgs();
+    const addr: usize = @intCast(args[0]);
+    const capacity: usize = @intCast(args[1]);
+    const clipboard = self.clipboard orelse return self.setSyscallError();
+
+    const text = clipboard.read(self.mmu.gpa) catch return self.setSyscallError();
+    defer self.mmu.gpa.free(text);
+
+    try self.mmu.writeSlice(addr, text[0..@min(text.len, capacity)]);
+    self.setSyscallResult(text.len);
+}
+
+fn sysClipboardWrite(self: *Vm) anyerror!void {
+    const args = self.syscallArgs();
+    const addr: usize = @intCast(args[0]);
+    const len: usize = @intCast(args[1]);
+    const clipboard = self.clipboard orelse return self.setSyscallError();
+
+    const text = try self.mmu.readSlice(addr, len);
+    clipboard.write(text) catch return self.setSyscallError();
+    self.setSyscallResult(0);
+}
+
+fn sysMkfifo(self: *Vm) anyerror!void {
+    const args = self.syscallArgs();
+    const path = try readPath(self, @intCast(args[0]));
+    const mode: u32 = @as(u16, @truncate(args[1]));
+
+    const rc = posix.mknodat(posix.AT.FDCWD, path.ptr, posix.S.IFIFO | mode, 0);
+    if (std.posix.errno(rc) != .SUCCESS) return self.setSyscallError();
+    self.setSyscallResult(0);
 }
 
 fn sysFree(self: *Vm) !void {
-    const addr: usize = self.regs.get(.q0).asUsize();
+    const addr: usize = @intCast(self.syscallArgs()[0]);
 
-    if (self.mmu.blocks.items.len <= 2) return error.NoDynamicBlocks;
+    const fixed = self.fixedBlockCount();
+    if (self.mmu.blocks.items.len <= fixed) {
+        if (self.heap_stats) |*stats| stats.invalid_frees += 1;
+        return error.NoDynamicBlocks;
+    }
 
     var start: usize = blk: {
         var s: usize = 0;
-        for (self.mmu.blocks.items[0..2]) |b| {
+        for (self.mmu.blocks.items[0..fixed]) |b| {
             var bus = b.bus();
             s += bus.size();
         }
         break :blk s;
     };
-    var i: usize = 2;
+    var i: usize = fixed;
     while (i < self.mmu.blocks.items.len) : (i += 1) {
         const block = self.mmu.blocks.items[i];
         var bus = block.bus();
@@ -116,28 +707,34 @@ fn sysFree(self: *Vm) !void {
             _ = self.mmu.blocks.orderedRemove(i);
             block.deinit();
             self.mmu.gpa.destroy(block);
+            if (self.heap_stats) |*stats| stats.recordFree(addr);
             return;
         }
 
         start = end;
     }
 
+    if (self.heap_stats) |*stats| stats.invalid_frees += 1;
     return error.InvalidFreeAddress;
 }
 
 fn sysSocket(self: *Vm) anyerror!void {
-    const domain = self.regs.get(.d0).asU32();
-    const socket_type = self.regs.get(.d1).asU32();
-    const protocol = self.regs.get(.d2).asU32();
+    const args = self.syscallArgs();
+    const domain: u32 = @truncate(args[0]);
+    const socket_type: u32 = @truncate(args[1]);
+    const protocol: u32 = @truncate(args[2]);
 
-    const sockfd = posix.socket(domain, socket_type, protocol);
+    const host = FileTable.toFd(posix.socket(domain, socket_type, protocol)) orelse
+        return self.setSyscallError();
+    const sockfd = try self.files.add(host);
 
-    self.regs.set(.d0, .{ .dword = @bitCast(@as(i32, @intCast(sockfd))) });
+    self.setSyscallResult(@intCast(sockfd));
 }
 
 fn sysConnect(self: *Vm) anyerror!void {
-    const sockfd: i32 = @intCast(self.regs.get(.d0).asU32());
-    const sockaddr_ptr = self.regs.get(.q1).asUsize();
+    const args = self.syscallArgs();
+    const sockfd = hostFd(self, args[0]) orelse return self.setSyscallError();
+    const sockaddr_ptr: usize = @intCast(args[1]);
     const sockaddr_family = switch (native_os) {
         .linux, .emscripten, .windows, .illumos, .serenity => (try self.mmu.read(sockaddr_ptr, .word)).asU16(),
         else => (try self.mmu.read(sockaddr_ptr, .word)).asU8(),
@@ -155,12 +752,13 @@ fn sysConnect(self: *Vm) anyerror!void {
 
     const res = posix.connect(sockfd, @ptrCast(&sockaddr_in), @sizeOf(@TypeOf(sockaddr_in)));
 
-    self.regs.set(.q0, .{ .qword = @intCast(res) });
+    self.setSyscallResult(@intCast(res));
 }
 
 fn sysBind(self: *Vm) anyerror!void {
-    const sockfd: i32 = @intCast(self.regs.get(.d0).asU32());
-    const sockaddr_ptr = self.regs.get(.q1).asUsize();
+    const args = self.syscallArgs();
+    const sockfd = hostFd(self, args[0]) orelse return self.setSyscallError();
+    const sockaddr_ptr: usize = @intCast(args[1]);
     const sockaddr_family = switch (native_os) {
         .linux, .emscripten, .windows, .illumos, .serenity => (try self.mmu.read(sockaddr_ptr, .word)).asU16(),
         else => (try self.mmu.read(sockaddr_ptr, .word)).asU8(),
@@ -178,35 +776,152 @@ fn sysBind(self: *Vm) anyerror!void {
 
     const res = posix.bind(sockfd, @ptrCast(&sockaddr_in), @sizeOf(@TypeOf(sockaddr_in)));
 
-    self.regs.set(.q0, .{ .qword = @intCast(res) });
+    self.setSyscallResult(@intCast(res));
 }
 
 fn sysListen(self: *Vm) anyerror!void {
-    const sockfd: i32 = @intCast(self.regs.get(.d0).asU32());
-    const backlog: c_uint = @intCast(self.regs.get(.d1).asU32());
+    const args = self.syscallArgs();
+    const sockfd = hostFd(self, args[0]) orelse return self.setSyscallError();
+    const backlog: c_uint = @truncate(args[1]);
 
     const res = posix.listen(sockfd, backlog);
 
-    self.regs.set(.d0, .{ .dword = @bitCast(@as(i32, @intCast(res))) });
+    self.setSyscallResult(@intCast(res));
 }
 
 fn sysAccept(self: *Vm) anyerror!void {
-    const sockfd: i32 = @intCast(self.regs.get(.d0).asU32());
-    const sockaddr_ptr = self.regs.get(.q1).asUsize();
+    const args = self.syscallArgs();
+    const sockfd = hostFd(self, args[0]) orelse return self.setSyscallError();
+    const sockaddr_ptr: usize = @intCast(args[1]);
 
     var sockaddr_in: posix.sockaddr.in = undefined;
     var sockaddr_in_len: u32 = @sizeOf(posix.sockaddr.in);
-    const res = posix.accept(sockfd, @ptrCast(&sockaddr_in), &sockaddr_in_len);
+    const host = FileTable.toFd(posix.accept(sockfd, @ptrCast(&sockaddr_in), &sockaddr_in_len)) orelse
+        return self.setSyscallError();
+    const res = try self.files.add(host);
 
     try self.mmu.write(sockaddr_ptr, .{ .word = sockaddr_in.family }, .word);
     try self.mmu.write(sockaddr_ptr + 2, .{ .word = sockaddr_in.port }, .word);
     try self.mmu.write(sockaddr_ptr + 4, .{ .dword = sockaddr_in.addr }, .dword);
     try self.mmu.writeSlice(sockaddr_ptr + 8, &sockaddr_in.zero);
 
-    self.regs.set(.q0, .{ .qword = @intCast(res) });
+    self.setSyscallResult(@intCast(res));
+}
+
+fn sysDup(self: *Vm) anyerror!void {
+    const fd = guestFd(self.syscallArgs()[0]);
+    const new = try self.files.dup(fd) orelse return self.setSyscallError();
+    self.setSyscallResult(@intCast(new));
+}
+
+fn sysDup2(self: *Vm) anyerror!void {
+    const args = self.syscallArgs();
+    const res = try self.files.dup2(guestFd(args[0]), guestFd(args[1])) orelse return self.setSyscallError();
+    self.setSyscallResult(@intCast(res));
+}
+
+fn sysPresent(self: *Vm) anyerror!void {
+    const console = self.console orelse return self.setSyscallError();
+    const fd = self.files.get(1) orelse return self.setSyscallError();
+
+    var output: std.Io.Writer.Allocating = .init(self.mmu.gpa);
+    defer output.deinit();
+    try console.render(&output.writer);
+
+    const screen = output.written();
+    _ = posix.write(fd, @ptrCast(screen), screen.len);
+    self.setSyscallResult(0);
+}
+
+fn sysConsole(self: *Vm) anyerror!void {
+    const console = self.console orelse {
+        self.setSyscallResult(0);
+        self.regs.set(.q1, .{ .qword = 0 });
+        self.regs.set(.q2, .{ .qword = 0 });
+        return;
+    };
+    self.setSyscallResult(@intCast(console.base));
+    self.regs.set(.q1, .{ .qword = @intCast(console.size.rows) });
+    self.regs.set(.q2, .{ .qword = @intCast(console.size.cols) });
+}
+
+fn sysFramebuffer(self: *Vm) anyerror!void {
+    if (self.framebuffer == null) {
+        self.setSyscallResult(0);
+        self.regs.set(.q1, .{ .qword = 0 });
+        self.regs.set(.q2, .{ .qword = 0 });
+        return;
+    }
+    self.setSyscallResult(self.mmu.devices.base);
+    self.regs.set(.q1, .{ .qword = Framebuffer.width });
+    self.regs.set(.q2, .{ .qword = Framebuffer.height });
+}
+
+fn sysDisplay(self: *Vm) anyerror!void {
+    const framebuffer = self.framebuffer orelse return self.setSyscallError();
+    const display = self.display orelse return self.setSyscallError();
+    display.present(framebuffer.pixels) catch return self.setSyscallError();
+    self.setSyscallResult(0);
+}
+
+/// Register `sys_poll_key` sets to 1 for a key press and 0 for a release.
+pub const pressed_register = arg_registers[1];
+
+fn sysPollKey(self: *Vm) anyerror!void {
+    const key = if (self.display) |display| display.pollKey() else pollStdinKey(self);
+    const event = key orelse Display.Key{ .code = 0, .pressed = false };
+    self.regs.set(pressed_register, .{ .qword = @intFromBool(event.pressed) });
+    self.setSyscallResult(event.code);
+}
+
+/// A byte from stdin if one can be read without waiting. Terminals only
+/// send keys as they are typed in raw mode, and never send releases.
+fn pollStdinKey(self: *Vm) ?Display.Key {
+    const fd = self.files.get(0) orelse return null;
+    var fds = [_]posix.pollfd{.{ .fd = fd, .events = posix.POLL.IN, .revents = 0 }};
+    const ready = posix.poll(&fds, fds.len, 0);
+    if (std.posix.errno(ready) != .SUCCESS or ready == 0) return null;
+
+    var byte: [1]u8 = undefined;
+    const rc = posix.read(fd, &byte, 1);
+    if (std.posix.errno(rc) != .SUCCESS or rc == 0) return null;
+    return .{ .code = if (byte[0] == '\r') '\n' else byte[0], .pressed = true };
+}
+
+/// Register `sys_args` leaves the address of the argv pointers in.
+pub const argv_register = arg_registers[1];
+
+fn sysArgs(self: *Vm) anyerror!void {
+    self.regs.set(argv_register, .{ .qword = self.arguments.argv });
+    self.setSyscallResult(self.arguments.argc);
+}
+
+fn sysGetenv(self: *Vm) anyerror!void {
+    const name = try readPath(self, @intCast(self.syscallArgs()[0]));
+    if (self.arguments.envp == 0) return self.setSyscallError();
+
+    var slot: usize = @intCast(self.arguments.envp);
+    while (true) : (slot += 8) {
+        const entry_addr = (try self.mmu.read(slot, .qword)).asUsize();
+        if (entry_addr == 0) return self.setSyscallError();
+        const entry = try readPath(self, entry_addr);
+        if (entry.len > name.len and std.mem.startsWith(u8, entry, name) and entry[name.len] == '=') {
+            return self.setSyscallResult(entry_addr + name.len + 1);
+        }
+    }
 }
 
 fn sysExit(self: *Vm) anyerror!void {
-    const status = self.regs.get(.b0).asU8();
-    posix.exit(status);
+    self.exit_status = @truncate(self.syscallArgs()[0]);
+    self.halted = true;
+}
+
+/// The program's descriptor in the low 32 bits of `arg`.
+fn guestFd(arg: u64) FileTable.Fd {
+    return @bitCast(@as(u32, @truncate(arg)));
+}
+
+/// Translate the program's descriptor in `arg` into a host descriptor.
+fn hostFd(self: *Vm, arg: u64) ?FileTable.Fd {
+    return self.files.get(guestFd(arg));
 }
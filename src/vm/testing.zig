@@ -0,0 +1,112 @@
+//! Helpers for building raw bytecode without going through the assembler.
+//!
+//! `ProgramBuilder` emits opcodes and operands exactly as the VM decodes them,
+//! so VM behavior can be tested independently of the text syntax, including
+//! encodings the compiler would never produce.
+
+const std = @import("std");
+const mem = std.mem;
+const Allocator = mem.Allocator;
+const ArrayList = std.array_list.Managed;
+const Opcode = @import("../compiler/opcode.zig").Opcode;
+const Register = @import("register.zig").Register;
+const DataSize = @import("../parser/immediate.zig").DataSize;
+const addressing_variant_1 = @import("../compiler/Compiler.zig").addressing_variant_1;
+const addressing_variant_2 = @import("../compiler/Compiler.zig").addressing_variant_2;
+const Vm = @import("Vm.zig");
+
+pub const default_memory_size: usize = 65536;
+
+pub const ProgramBuilder = struct {
+    bytes: ArrayList(u8),
+    entry: u64,
+
+    pub fn init(gpa: Allocator) ProgramBuilder {
+        return ProgramBuilder{
+            .bytes = .init(gpa),
+            .entry = 0,
+        };
+    }
+
+    pub fn deinit(self: *ProgramBuilder) void {
+        self.bytes.deinit();
+    }
+
+    /// Current offset into the program, usable as a jump or call target.
+    pub fn here(self: *ProgramBuilder) u64 {
+        return @intCast(self.bytes.items.len);
+    }
+
+    pub fn op(self: *ProgramBuilder, opcode: Opcode) !void {
+        try self.bytes.append(opcode.intoU8());
+    }
+
+    pub fn reg(self: *ProgramBuilder, register: Register) !void {
+        try self.bytes.append(@intFromEnum(register));
+    }
+
+    pub fn size(self: *ProgramBuilder, data_size: DataSize) !void {
+        try self.bytes.append(data_size.toU8());
+    }
+
+    pub fn byte(self: *ProgramBuilder, value: u8) !void {
+        try self.bytes.append(value);
+    }
+
+    pub fn word(self: *ProgramBuilder, value: u16) !void {
+        try self.bytes.appendSlice(&mem.toBytes(mem.nativeToLittle(u16, value)));
+    }
+
+    pub fn dword(self: *ProgramBuilder, value: u32) !void {
+        try self.bytes.appendSlice(&mem.toBytes(mem.nativeToLittle(u32, value)));
+    }
+
+    pub fn qword(self: *ProgramBuilder, value: u64) !void {
+        try self.bytes.appendSlice(&mem.toBytes(mem.nativeToLittle(u64, value)));
+    }
+
+    pub fn float(self: *ProgramBuilder, value: f32) !void {
+        try self.dword(@bitCast(value));
+    }
+
+    pub fn double(self: *ProgramBuilder, value: f64) !void {
+        try self.qword(@bitCast(value));
+    }
+
+    /// Emit a `[register + offset]` memory operand.
+    pub fn addrReg(self: *ProgramBuilder, base: Register, offset: i64) !void {
+        try self.byte(addressing_variant_1);
+        try self.reg(base);
+        try self.qword(@bitCast(offset));
+    }
+
+    /// Emit a `[immediate + offset]` memory operand.
+    pub fn addrImm(self: *ProgramBuilder, base: u64, offset: i64) !void {
+        try self.byte(addressing_variant_2);
+        try self.qword(base);
+        try self.qword(@bitCast(offset));
+    }
+
+    /// Append arbitrary bytes, e.g. to craft malformed encodings.
+    pub fn raw(self: *ProgramBuilder, bytes: []const u8) !void {
+        try self.bytes.appendSlice(bytes);
+    }
+
+    /// Return the complete program (entry point header followed by the
+    /// emitted code) in the format accepted by `Vm.init`. Caller owns the
+    /// returned memory.
+    pub fn finish(self: *ProgramBuilder) ![]u8 {
+        var program = ArrayList(u8).init(self.bytes.allocator);
+        errdefer program.deinit();
+        try program.appendSlice(&mem.toBytes(mem.nativeToLittle(u64, self.entry)));
+        try program.appendSlice(self.bytes.items);
+        return program.toOwnedSlice();
+    }
+
+    /// Build the program and load it into a fresh VM without running it.
+    pub fn load(self: *ProgramBuilder, gpa: Allocator) !Vm {
+        const program = try self.finish();
+        defer gpa.free(program);
+        return Vm.init(program, default_memory_size, &.{}, gpa);
+    }
+};
@@ -0,0 +1,2833 @@
+const std = @import("std");
+const testing = std.testing;
+const build_options = @import("build_options");
+const Opcode = @import("../compiler/opcode.zig").Opcode;
+const Register = @import("register.zig").Register;
+const DataSize = @import("../parser/immediate.zig").DataSize;
+const Immediate = @import("../parser/immediate.zig").Immediate;
+const ProgramBuilder = @import("testing.zig").ProgramBuilder;
+const syscall = @import("syscall.zig");
+const FileTable = @import("FileTable.zig");
+const HeapStats = @import("HeapStats.zig");
+const Heap = @import("memory/Heap.zig");
+const Block = @import("memory/Block.zig");
+const Mmu = @import("memory/Mmu.zig");
+const DebugInfo = @import("DebugInfo.zig");
+const DeviceBus = @import("memory/DeviceBus.zig");
+const Vm = @import("Vm.zig");
+const ExternalLoader = @import("ExternalLoader.zig");
+const features = @import("features.zig");
+const Console = @import("Console.zig");
+const Framebuffer = @import("Framebuffer.zig");
+const Display = @import("Display.zig");
+const Clipboard = @import("Clipboard.zig");
+const Control = @import("Control.zig");
+const Trace = @import("Trace.zig");
+const signature = @import("../signature.zig");
+const migrate = @import("../migrate.zig");
+const verify = @import("../verify.zig");
+const batch = @import("../batch.zig");
+
+test "mov register immediate" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(0xDEADBEEF);
+    try b.op(.mov_reg_imm);
+    try b.reg(.b1);
+    try b.byte(0x7F);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, 0xDEADBEEF), vm.regs.get(.q0).asU64());
+    try testing.expectEqual(@as(u8, 0x7F), vm.regs.get(.b1).asU8());
+}
+
+test "movz and movs extend into wider registers" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.b0);
+    try b.byte(0x80);
+    try b.op(.movz_reg_reg);
+    try b.reg(.q1);
+    try b.reg(.b0);
+    try b.op(.movs_reg_reg);
+    try b.reg(.q2);
+    try b.reg(.b0);
+    try b.op(.movs_reg_reg);
+    try b.reg(.w3);
+    try b.reg(.b0);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    vm.regs.set(.q1, .{ .qword = 0xFFFF_FFFF_FFFF_FFFF });
+    vm.regs.set(.q3, .{ .qword = 0x1111_2222_3333_4444 });
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, 0x80), vm.regs.get(.q1).asU64());
+    try testing.expectEqual(@as(u64, 0xFFFF_FFFF_FFFF_FF80), vm.regs.get(.q2).asU64());
+    // A word destination keeps the rest of its register.
+    try testing.expectEqual(@as(u64, 0x1111_2222_3333_FF80), vm.regs.get(.q3).asU64());
+}
+
+test "arithmetic" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.q1);
+    try b.qword(40);
+    try b.op(.add_reg_reg_imm);
+    try b.reg(.q0);
+    try b.reg(.q1);
+    try b.qword(2);
+    try b.op(.mov_reg_imm);
+    try b.reg(.dd0);
+    try b.double(1.5);
+    try b.op(.mul_reg_reg_imm);
+    try b.reg(.dd1);
+    try b.reg(.dd0);
+    try b.double(4.0);
+    try b.op(.inc_reg_imm);
+    try b.reg(.q2);
+    try b.qword(8);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, 42), vm.regs.get(.q0).asU64());
+    try testing.expectEqual(@as(f64, 6.0), vm.regs.get(.dd1).asF64());
+    try testing.expectEqual(@as(u64, 8), vm.regs.get(.q2).asU64());
+}
+
+test "inc and dec wrap at the register size like inc_reg_imm and dec_reg_imm" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    // b0 = 255 + 1 and b1 = 0 - 1 with inc and dec, b2 and b3 the same
+    // with an explicit step of 1
+    const cases = [_]struct { reg: Register, start: u8, op: Opcode }{
+        .{ .reg = .b0, .start = 255, .op = .inc },
+        .{ .reg = .b1, .start = 0, .op = .dec },
+        .{ .reg = .b2, .start = 255, .op = .inc_reg_imm },
+        .{ .reg = .b3, .start = 0, .op = .dec_reg_imm },
+    };
+    for (cases) |case| {
+        try b.op(.mov_reg_imm);
+        try b.reg(case.reg);
+        try b.byte(case.start);
+        try b.op(case.op);
+        try b.reg(case.reg);
+        if (case.op != .inc and case.op != .dec) try b.byte(1);
+    }
+    try b.op(.mov_reg_imm);
+    try b.reg(.q4);
+    try b.qword(std.math.maxInt(u64));
+    try b.op(.inc);
+    try b.reg(.q4);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    try testing.expectEqual(@as(u8, 0), vm.regs.get(.b0).asU8());
+    try testing.expectEqual(@as(u8, 255), vm.regs.get(.b1).asU8());
+    try testing.expectEqual(vm.regs.get(.b0).asU8(), vm.regs.get(.b2).asU8());
+    try testing.expectEqual(vm.regs.get(.b1).asU8(), vm.regs.get(.b3).asU8());
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q4).asU64());
+}
+
+test "carry and overflow chain wide arithmetic" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    // q1:q0 = 0x0:0xffff_ffff_ffff_ffff + 1
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(std.math.maxInt(u64));
+    try b.op(.add_reg_reg_imm);
+    try b.reg(.q0);
+    try b.reg(.q0);
+    try b.qword(1);
+    try b.op(.adc_reg_reg_imm);
+    try b.reg(.q1);
+    try b.reg(.q1);
+    try b.qword(0);
+    // q3:q2 = 0x1:0x0 - 1
+    try b.op(.mov_reg_imm);
+    try b.reg(.q3);
+    try b.qword(1);
+    try b.op(.sub_reg_reg_imm);
+    try b.reg(.q2);
+    try b.reg(.q2);
+    try b.qword(1);
+    try b.op(.sbb_reg_reg_imm);
+    try b.reg(.q3);
+    try b.reg(.q3);
+    try b.qword(0);
+    // 0x7f + 1 overflows a signed byte without carrying.
+    try b.op(.mov_reg_imm);
+    try b.reg(.b4);
+    try b.byte(0x7f);
+    try b.op(.add_reg_reg_imm);
+    try b.reg(.b4);
+    try b.reg(.b4);
+    try b.byte(1);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q0).asU64());
+    try testing.expectEqual(@as(u64, 1), vm.regs.get(.q1).asU64());
+    try testing.expectEqual(@as(u64, std.math.maxInt(u64)), vm.regs.get(.q2).asU64());
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q3).asU64());
+    try testing.expectEqual(@as(u8, 0x80), vm.regs.get(.b4).asU8());
+    try testing.expect(!vm.flags.carry);
+    try testing.expect(vm.flags.overflow);
+}
+
+test "not complements registers and memory" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.b0);
+    try b.byte(0x0f);
+    try b.op(.not_reg_reg);
+    try b.reg(.b1);
+    try b.reg(.b0);
+    try b.op(.mov_addr_imm);
+    try b.size(.dword);
+    try b.dword(0x1234_5678);
+    try b.addrImm(0x1000, 0);
+    try b.op(.not_reg_addr);
+    try b.reg(.d2);
+    try b.addrImm(0x1000, 0);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    try testing.expectEqual(@as(u8, 0xf0), vm.regs.get(.b1).asU8());
+    try testing.expectEqual(@as(u32, 0xedcb_a987), vm.regs.get(.d2).asU32());
+}
+
+test "cvt converts between integer and floating-point registers" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    const Conversion = struct { op: Opcode, dest: Register, src: Register };
+    const conversions = [_]Conversion{
+        .{ .op = .cvtif_reg_reg, .dest = .ff0, .src = .d0 },
+        .{ .op = .cvtif_reg_reg, .dest = .dd1, .src = .q1 },
+        .{ .op = .cvtfi_reg_reg, .dest = .q2, .src = .dd2 },
+        .{ .op = .cvtfi_reg_reg, .dest = .b3, .src = .ff3 },
+        .{ .op = .cvtfi_reg_reg, .dest = .q4, .src = .dd4 },
+        .{ .op = .cvtfd_reg_reg, .dest = .dd5, .src = .ff5 },
+        .{ .op = .cvtdf_reg_reg, .dest = .ff6, .src = .dd6 },
+    };
+    for (conversions) |conversion| {
+        try b.op(conversion.op);
+        try b.reg(conversion.dest);
+        try b.reg(conversion.src);
+    }
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    vm.regs.set(.d0, .{ .dword = @bitCast(@as(i32, -7)) });
+    vm.regs.set(.q1, .{ .qword = @bitCast(@as(i64, -3)) });
+    vm.regs.set(.dd2, .{ .double = -2.9 });
+    vm.regs.set(.ff3, .{ .float = 1000.0 });
+    vm.regs.set(.q4, .{ .qword = 99 });
+    vm.regs.set(.dd4, .{ .double = std.math.nan(f64) });
+    vm.regs.set(.ff5, .{ .float = 1.5 });
+    vm.regs.set(.dd6, .{ .double = 0.1 });
+    try vm.run();
+
+    // Integers are signed, floats truncate toward zero and saturate.
+    try testing.expectEqual(@as(f32, -7.0), vm.regs.get(.ff0).asF32());
+    try testing.expectEqual(@as(f64, -3.0), vm.regs.get(.dd1).asF64());
+    try testing.expectEqual(@as(i64, -2), @as(i64, @bitCast(vm.regs.get(.q2).asU64())));
+    try testing.expectEqual(@as(u8, 127), vm.regs.get(.b3).asU8());
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q4).asU64());
+    try testing.expectEqual(@as(f64, 1.5), vm.regs.get(.dd5).asF64());
+    try testing.expectEqual(@as(f32, 0.1), vm.regs.get(.ff6).asF32());
+}
+
+test "malformed: cvt between the wrong register classes" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.cvtfd_reg_reg);
+    try b.reg(.dd0);
+    try b.reg(.q0);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try testing.expectError(error.InvalidDataSize, vm.run());
+}
+
+test "scmp compares strings and schr scans for a byte" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    // "apple" at 0x1000, "apply" at 0x1100 and "apple" again at 0x1200.
+    const strings = [_][]const u8{ "apple", "apply", "apple" };
+    for ([_]u64{ 0x1000, 0x1100, 0x1200 }, strings) |addr, text| {
+        for (text, 0..) |c, i| {
+            try b.op(.mov_addr_imm);
+            try b.size(.byte);
+            try b.byte(c);
+            try b.addrImm(addr, @intCast(i));
+        }
+    }
+    for ([_]Register{ .q0, .q1, .q2 }, [_]u64{ 0x1000, 0x1100, 0x1200 }) |register, addr| {
+        try b.op(.mov_reg_imm);
+        try b.reg(register);
+        try b.qword(addr);
+    }
+    try b.op(.scmp_reg_reg);
+    try b.reg(.q0);
+    try b.reg(.q1);
+    try b.op(.scmp_reg_reg);
+    try b.reg(.q0);
+    try b.reg(.q2);
+    // Find 'l' in "apple", then 'z' in "apply", which has none.
+    try b.op(.mov_reg_imm);
+    try b.reg(.q6);
+    try b.qword(5);
+    try b.op(.schr_reg_reg_imm);
+    try b.reg(.q0);
+    try b.reg(.q6);
+    try b.qword('l');
+    try b.op(.mov_reg_imm);
+    try b.reg(.q9);
+    try b.qword(5);
+    try b.op(.mov_reg_imm);
+    try b.reg(.b8);
+    try b.byte('z');
+    try b.op(.schr_reg_reg_reg);
+    try b.reg(.q1);
+    try b.reg(.q9);
+    try b.reg(.b8);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    for (0..18) |_| try vm.step();
+
+    try vm.step();
+    try testing.expect(!vm.flags.eq);
+    try testing.expect(vm.flags.lt);
+    try vm.step();
+    try testing.expect(vm.flags.eq);
+    try testing.expect(!vm.flags.lt);
+
+    try vm.step();
+    try vm.step();
+    try testing.expect(vm.flags.eq);
+    try testing.expectEqual(@as(u64, 0x1003), vm.regs.get(.q0).asU64());
+    try testing.expectEqual(@as(u64, 2), vm.regs.get(.q6).asU64());
+
+    try vm.run();
+    try testing.expect(!vm.flags.eq);
+    try testing.expectEqual(@as(u64, 0x1105), vm.regs.get(.q1).asU64());
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q9).asU64());
+}
+
+test "flag queries write 1 or 0" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    const queries = [_]Opcode{ .seteq_reg, .setne_reg, .setlt_reg, .setgt_reg, .setle_reg, .setge_reg, .setc_reg, .seto_reg };
+    const dests = [_]Register{ .q0, .q1, .q2, .q3, .q4, .q5, .q6, .b7 };
+    for (queries, dests) |query, dest| {
+        try b.op(query);
+        try b.reg(dest);
+    }
+    try b.op(.hlt);
+
+    const Case = struct { eq: bool, lt: bool, expected: [6]u64 };
+    // eq, ne, lt, gt, le, ge
+    const cases = [_]Case{
+        .{ .eq = true, .lt = false, .expected = .{ 1, 0, 0, 0, 1, 1 } },
+        .{ .eq = false, .lt = true, .expected = .{ 0, 1, 1, 0, 1, 0 } },
+        .{ .eq = false, .lt = false, .expected = .{ 0, 1, 0, 1, 0, 1 } },
+    };
+    for (cases) |case| {
+        var vm = try b.load(testing.allocator);
+        defer vm.deinit();
+        vm.regs.set(.q7, .{ .qword = 0xFF00 });
+        vm.flags.eq = case.eq;
+        vm.flags.lt = case.lt;
+        vm.flags.carry = true;
+        try vm.run();
+
+        for (dests[0..6], case.expected) |dest, expected| {
+            try testing.expectEqual(expected, vm.regs.get(dest).asU64());
+        }
+        try testing.expectEqual(@as(u64, 1), vm.regs.get(.q6).asU64());
+        // A byte register only has its low byte replaced.
+        try testing.expectEqual(@as(u64, 0xFF00), vm.regs.get(.q7).asU64());
+    }
+}
+
+test "division by zero follows the division mode" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.d0);
+    try b.dword(7);
+    try b.op(.div_reg_reg_imm);
+    try b.reg(.d1);
+    try b.reg(.d0);
+    try b.dword(0);
+    try b.op(.hlt);
+
+    const expected = [_]struct { Vm.Division, u32 }{
+        .{ .saturate, std.math.maxInt(u32) },
+        .{ .wrap, 0 },
+    };
+    for (expected) |case| {
+        var vm = try b.load(testing.allocator);
+        defer vm.deinit();
+        vm.division = case[0];
+        try vm.run();
+        try testing.expectEqual(case[1], vm.regs.get(.d1).asU32());
+    }
+
+    var flagged = try b.load(testing.allocator);
+    defer flagged.deinit();
+    flagged.division = .flag;
+    try flagged.run();
+    try testing.expectEqual(@as(u32, 0), flagged.regs.get(.d1).asU32());
+    try testing.expect(flagged.flags.carry and flagged.flags.overflow);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try testing.expectError(error.DivisionByZero, vm.run());
+    try testing.expectEqual(@as(usize, 6), vm.instruction_start);
+}
+
+test "signed multiply, divide and remainder" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    // -7 / 2 and -7 % 2 in bytes.
+    try b.op(.mov_reg_imm);
+    try b.reg(.b0);
+    try b.byte(@bitCast(@as(i8, -7)));
+    try b.op(.idiv_reg_reg_imm);
+    try b.reg(.b1);
+    try b.reg(.b0);
+    try b.byte(2);
+    try b.op(.imod_reg_reg_imm);
+    try b.reg(.b2);
+    try b.reg(.b0);
+    try b.byte(2);
+    // 7 / -2 and 7 % -2 in words, with the divisor in a register.
+    try b.op(.mov_reg_imm);
+    try b.reg(.w3);
+    try b.word(7);
+    try b.op(.mov_reg_imm);
+    try b.reg(.w4);
+    try b.word(@bitCast(@as(i16, -2)));
+    try b.op(.idiv_reg_reg_reg);
+    try b.reg(.w5);
+    try b.reg(.w3);
+    try b.reg(.w4);
+    try b.op(.imod_reg_reg_reg);
+    try b.reg(.w6);
+    try b.reg(.w3);
+    try b.reg(.w4);
+    // -100 * -3 in dwords.
+    try b.op(.mov_reg_imm);
+    try b.reg(.d7);
+    try b.dword(@bitCast(@as(i32, -100)));
+    try b.op(.imul_reg_reg_imm);
+    try b.reg(.d8);
+    try b.reg(.d7);
+    try b.dword(@bitCast(@as(i32, -3)));
+    // minInt * -1 does not fit in a signed qword.
+    try b.op(.mov_reg_imm);
+    try b.reg(.q9);
+    try b.qword(@bitCast(@as(i64, std.math.minInt(i64))));
+    try b.op(.imul_reg_reg_imm);
+    try b.reg(.q10);
+    try b.reg(.q9);
+    try b.qword(@bitCast(@as(i64, -1)));
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    try testing.expectEqual(@as(i8, -3), @as(i8, @bitCast(vm.regs.get(.b1).asU8())));
+    try testing.expectEqual(@as(i8, -1), @as(i8, @bitCast(vm.regs.get(.b2).asU8())));
+    try testing.expectEqual(@as(i16, -3), @as(i16, @bitCast(vm.regs.get(.w5).asU16())));
+    try testing.expectEqual(@as(i16, 1), @as(i16, @bitCast(vm.regs.get(.w6).asU16())));
+    try testing.expectEqual(@as(u32, 300), vm.regs.get(.d8).asU32());
+    try testing.expectEqual(@as(i64, std.math.minInt(i64)), @as(i64, @bitCast(vm.regs.get(.q10).asU64())));
+    try testing.expect(vm.flags.carry and vm.flags.overflow);
+}
+
+test "signed division of the smallest value by -1 follows the division mode" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.b0);
+    try b.byte(0x80);
+    try b.op(.imod_reg_reg_imm);
+    try b.reg(.b1);
+    try b.reg(.b0);
+    try b.byte(0xFF);
+    try b.op(.idiv_reg_reg_imm);
+    try b.reg(.b3);
+    try b.reg(.b0);
+    try b.byte(0xFF);
+    // Dividing by zero leaves the dividend as the remainder.
+    try b.op(.imod_reg_reg_imm);
+    try b.reg(.b2);
+    try b.reg(.b0);
+    try b.byte(0);
+    try b.op(.hlt);
+
+    const expected = [_]struct { Vm.Division, u8 }{
+        .{ .saturate, 0x7F },
+        .{ .wrap, 0x80 },
+        .{ .flag, 0x80 },
+    };
+    for (expected) |case| {
+        var vm = try b.load(testing.allocator);
+        defer vm.deinit();
+        vm.division = case[0];
+        try vm.run();
+        try testing.expectEqual(@as(u8, 0), vm.regs.get(.b1).asU8());
+        try testing.expectEqual(@as(u8, 0x80), vm.regs.get(.b2).asU8());
+        try testing.expectEqual(case[1], vm.regs.get(.b3).asU8());
+        try testing.expectEqual(case[0] == .flag, vm.flags.carry and vm.flags.overflow);
+    }
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try testing.expectError(error.DivisionOverflow, vm.run());
+    try testing.expectEqual(@as(usize, 7), vm.instruction_start);
+}
+
+test "stack push and pop" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.push_imm);
+    try b.size(.word);
+    try b.word(0x1234);
+    try b.op(.pop_reg);
+    try b.size(.word);
+    try b.reg(.w3);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    try testing.expectEqual(@as(u16, 0x1234), vm.regs.get(.w3).asU16());
+    try testing.expectEqual(@as(usize, 65536), vm.regs.sp());
+}
+
+test "memory store and load" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_addr_imm);
+    try b.size(.dword);
+    try b.dword(0xCAFEBABE);
+    try b.addrImm(0x1000, 0x10);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q4);
+    try b.qword(0x1000);
+    try b.op(.mov_reg_addr);
+    try b.reg(.d5);
+    try b.addrReg(.q4, 0x10);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    try testing.expectEqual(@as(u32, 0xCAFEBABE), vm.regs.get(.d5).asU32());
+}
+
+test "compare and conditional jump" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.cmp_reg_imm);
+    try b.reg(.q0);
+    try b.qword(0);
+    try b.op(.jeq_imm);
+    const target_fixup = b.here();
+    try b.qword(0);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q1);
+    try b.qword(1);
+    const target = b.here();
+    try b.op(.hlt);
+
+    std.mem.writeInt(u64, b.bytes.items[@intCast(target_fixup)..][0..8], target, .little);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q1).asU64());
+}
+
+test "call and return" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.call_imm);
+    try b.qword(10);
+    try b.op(.hlt);
+    try b.op(.nop);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(7);
+    try b.op(.ret);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, 7), vm.regs.get(.q0).asU64());
+}
+
+test "relative jumps and calls count from the end of the instruction" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    // 0x00: mov q0, 3
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(3);
+    // 0x0a: inc q1; dec q0; cmp q0, 0
+    try b.op(.inc);
+    try b.reg(.q1);
+    try b.op(.dec);
+    try b.reg(.q0);
+    try b.op(.cmp_reg_imm);
+    try b.reg(.q0);
+    try b.qword(0);
+    // 0x18: jne 0x0a, from 0x1a
+    try b.op(.jne_rel8);
+    try b.byte(@bitCast(@as(i8, -16)));
+    // 0x1a: call 0x20, from 0x1f; 0x1f: hlt
+    try b.op(.call_rel32);
+    try b.dword(1);
+    try b.op(.hlt);
+    // 0x20: mov q2, 7; ret
+    try b.op(.mov_reg_imm);
+    try b.reg(.q2);
+    try b.qword(7);
+    try b.op(.ret);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, 3), vm.regs.get(.q1).asU64());
+    try testing.expectEqual(@as(u64, 7), vm.regs.get(.q2).asU64());
+    try testing.expectEqual(@as(usize, 0x1f), vm.instruction_start);
+}
+
+test "malformed: invalid opcode" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.byte(0xFE);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try testing.expectError(error.InvalidOpcode, vm.step());
+}
+
+test "malformed: invalid register" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_reg);
+    try b.byte(0xF0);
+    try b.reg(.q0);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try testing.expectError(error.InvalidRegister, vm.step());
+}
+
+test "malformed: unknown addressing variant" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_addr);
+    try b.reg(.q0);
+    try b.byte(0x07);
+    try b.qword(0);
+    try b.qword(0);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try testing.expectError(error.UnknownAddressingVariant, vm.step());
+}
+
+test "malformed: instruction pointer out of bounds" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.jmp_imm);
+    try b.qword(0xFFFF_FFFF);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.step();
+    try testing.expectError(error.InstructionPointerOutOfBounds, vm.step());
+}
+
+test "malformed: unknown data size" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.push_imm);
+    try b.byte(0x42);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try testing.expectError(error.UnknownDataSize, vm.step());
+}
+
+test "writing ip traps" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.ip);
+    try b.qword(0);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try testing.expectError(error.InstructionPointerWrite, vm.step());
+}
+
+test "writing sp adjusts the stack" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.sub_reg_reg_imm);
+    try b.reg(.sp);
+    try b.reg(.sp);
+    try b.qword(16);
+    try b.op(.push_imm);
+    try b.size(.qword);
+    try b.qword(99);
+    try b.op(.mov_reg_addr);
+    try b.reg(.q0);
+    try b.addrImm(65536 - 24, 0);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    try testing.expectEqual(@as(usize, 65536 - 24), vm.regs.sp());
+    try testing.expectEqual(@as(u64, 99), vm.regs.get(.q0).asU64());
+}
+
+test "enter and leave build and tear down a stack frame" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.enter_imm);
+    try b.qword(16);
+    try b.op(.mov_addr_imm);
+    try b.size(.qword);
+    try b.qword(42);
+    try b.addrReg(.bp, -8);
+    try b.op(.mov_reg_addr);
+    try b.reg(.q0);
+    try b.addrReg(.bp, -8);
+    try b.op(.mov_reg_reg);
+    try b.reg(.q1);
+    try b.reg(.bp);
+    try b.op(.mov_reg_reg);
+    try b.reg(.q2);
+    try b.reg(.sp);
+    try b.op(.leave);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    vm.regs.setBp(0x1234);
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, 42), vm.regs.get(.q0).asU64());
+    try testing.expectEqual(@as(u64, 65536 - 8), vm.regs.get(.q1).asU64());
+    try testing.expectEqual(@as(u64, 65536 - 24), vm.regs.get(.q2).asU64());
+    try testing.expectEqual(@as(usize, 65536), vm.regs.sp());
+    try testing.expectEqual(@as(usize, 0x1234), vm.regs.bp());
+}
+
+test "an address below zero is out of bounds" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_addr);
+    try b.reg(.q0);
+    try b.addrImm(8, -16);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try testing.expectError(error.AddressOutOfBounds, vm.step());
+}
+
+test "writes to read-only registers are ignored" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.msz);
+    try b.qword(1);
+    try b.op(.mov_reg_imm);
+    try b.reg(.zr);
+    try b.qword(1);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, 65536), vm.regs.get(.msz).asU64());
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.zr).asU64());
+}
+
+test "opcode round trip" {
+    inline for (std.meta.fields(Opcode)) |field| {
+        const opcode: Opcode = @enumFromInt(field.value);
+        try testing.expectEqual(opcode, try Opcode.fromU8(opcode.intoU8()));
+    }
+}
+
+test "syscall table has unique numbers and names" {
+    for (syscall.table, 0..) |a, i| {
+        for (syscall.table[i + 1 ..]) |b| {
+            try testing.expect(a.number != b.number);
+            try testing.expect(!std.mem.eql(u8, a.name, b.name));
+        }
+    }
+
+    var syscalls = try syscall.collectSyscalls(testing.allocator);
+    defer syscalls.deinit();
+    try testing.expectEqual(@as(u32, syscall.table.len), syscalls.count());
+}
+
+test "syscall table follows the calling convention" {
+    const result = syscall.result_register.physicalInfo().index;
+    for (syscall.table) |entry| {
+        try testing.expect(entry.args.len <= syscall.max_args);
+        for (entry.args, syscall.arg_registers[0..entry.args.len]) |arg, expected| {
+            const reg = try Register.fromString(arg.register);
+            try testing.expectEqual(expected.physicalInfo().index, reg.physicalInfo().index);
+        }
+        if (entry.returns) |ret| {
+            try testing.expectEqual(result, (try Register.fromString(ret.register)).physicalInfo().index);
+        }
+    }
+}
+
+test "failed syscalls set the error register" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    // sys_close(42) fails, then sys_ticks succeeds.
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x01);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(42);
+    try b.op(.syscall);
+    try b.op(.mov_reg_reg);
+    try b.reg(.q1);
+    try b.reg(.q0);
+    try b.op(.mov_reg_reg);
+    try b.reg(.q2);
+    try b.reg(.q14);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x12);
+    try b.op(.syscall);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    try testing.expectEqual(@as(i64, -1), @as(i64, @bitCast(vm.regs.get(.q1).asU64())));
+    try testing.expectEqual(@as(u64, 1), vm.regs.get(.q2).asU64());
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q14).asU64());
+}
+
+test "clipboard syscalls read and replace the clipboard" {
+    if (!build_options.ipc) return error.SkipZigTest;
+
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    // sys_clipboard_read(0x1000, 2), keeping the length in q2, then
+    // sys_clipboard_write(0x1000, 2).
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x15);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(0x1000);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q1);
+    try b.qword(2);
+    try b.op(.syscall);
+    try b.op(.mov_reg_reg);
+    try b.reg(.q2);
+    try b.reg(.q0);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x16);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(0x1000);
+    try b.op(.syscall);
+    try b.op(.hlt);
+
+    var memory = Clipboard.Memory.init(testing.allocator);
+    defer memory.deinit();
+    try memory.text.appendSlice("nyx");
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    vm.clipboard = memory.clipboard();
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, 3), vm.regs.get(.q2).asU64());
+    try testing.expectEqualStrings("ny", try vm.mmu.readSlice(0x1000, 2));
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q0).asU64());
+    try testing.expectEqualStrings("ny", memory.text.items);
+
+    // Without a clipboard both calls fail.
+    var bare = try b.load(testing.allocator);
+    defer bare.deinit();
+    try bare.run();
+    try testing.expectEqual(@as(i64, -1), @as(i64, @bitCast(bare.regs.get(.q2).asU64())));
+    try testing.expectEqual(@as(u64, 1), bare.regs.get(.q14).asU64());
+}
+
+test "control pauses, resumes and stops a vm on another thread" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    const loop = b.here();
+    try b.op(.jmp_imm);
+    try b.qword(loop);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    var control: Control = .{};
+    vm.setControl(&control);
+
+    const Runner = struct {
+        fn run(v: *Vm, result: *?anyerror) void {
+            v.run() catch |err| {
+                result.* = err;
+            };
+        }
+    };
+    var result: ?anyerror = null;
+    const thread = try std.Thread.spawn(.{}, Runner.run, .{ &vm, &result });
+
+    control.pause();
+    while (!control.isParked()) std.atomic.spinLoopHint();
+    const paused_at = vm.counters.instructions;
+    try testing.expectEqual(Control.State.paused, control.get());
+    try testing.expectEqual(paused_at, vm.counters.instructions);
+
+    control.unpause();
+    control.stop();
+    thread.join();
+
+    try testing.expectEqual(@as(?anyerror, error.Stopped), result);
+    try testing.expectEqual(@as(usize, loop), vm.regs.ip());
+}
+
+test "file table redirects standard descriptors" {
+    var files = try FileTable.init(testing.allocator);
+    defer files.deinit();
+
+    try testing.expectEqual(@as(?FileTable.Fd, 1), files.get(1));
+
+    const fd = (try files.dup2(1, 5)) orelse return error.TestUnexpectedResult;
+    try testing.expectEqual(@as(FileTable.Fd, 5), fd);
+    try testing.expect(files.get(5).? > 2);
+
+    try testing.expect(files.close(5));
+    try testing.expect(!files.close(5));
+    try testing.expectEqual(@as(?FileTable.Fd, null), try files.dup(42));
+    try testing.expectEqual(@as(FileTable.Fd, 3), try files.add(0));
+}
+
+test "heap stats track leaks" {
+    var stats = HeapStats.init(testing.allocator);
+    defer stats.deinit();
+
+    try stats.recordAlloc(0x1000, 64, 0x10);
+    try stats.recordAlloc(0x1040, 32, 0x20);
+    stats.recordFree(0x1000);
+    stats.recordFree(0x2000);
+
+    try testing.expectEqual(@as(usize, 2), stats.total_allocs);
+    try testing.expectEqual(@as(usize, 1), stats.total_frees);
+    try testing.expectEqual(@as(usize, 1), stats.invalid_frees);
+    try testing.expectEqual(@as(usize, 96), stats.peak_bytes);
+    try testing.expectEqual(@as(usize, 32), stats.bytes_in_use);
+    try testing.expectEqual(@as(usize, 0x20), stats.live.get(0x1040).?.call_site);
+}
+
+test "heap stats only record a dealloc the heap accepted" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    // q0 = sys_malloc(16), then sys_dealloc(q0), which is not a heap address
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x04);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(16);
+    try b.op(.syscall);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x10);
+    try b.op(.syscall);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    vm.enableHeapStats();
+    try testing.expectError(error.InvalidFreeAddress, vm.run());
+
+    const stats = &vm.heap_stats.?;
+    const addr = vm.regs.get(.q0).asUsize();
+    try testing.expectEqual(@as(usize, 0), stats.total_frees);
+    try testing.expectEqual(@as(usize, 1), stats.invalid_frees);
+    try testing.expectEqual(@as(usize, 16), stats.bytes_in_use);
+    try testing.expect(stats.live.contains(addr));
+}
+
+test "heap stats only record a free the block list accepted" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    // q1 = sys_malloc(8), q0 = sys_alloc(16), then sys_free(q0), which is
+    // not a malloc block
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x04);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(8);
+    try b.op(.syscall);
+    try b.op(.mov_reg_reg);
+    try b.reg(.q1);
+    try b.reg(.q0);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x0F);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(16);
+    try b.op(.syscall);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x05);
+    try b.op(.syscall);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    vm.enableHeapStats();
+    try testing.expectError(error.InvalidFreeAddress, vm.run());
+
+    const stats = &vm.heap_stats.?;
+    try testing.expectEqual(@as(usize, 0), stats.total_frees);
+    try testing.expectEqual(@as(usize, 1), stats.invalid_frees);
+    try testing.expectEqual(@as(usize, 24), stats.bytes_in_use);
+    try testing.expect(stats.live.contains(vm.regs.get(.q0).asUsize()));
+    try testing.expect(stats.live.contains(vm.regs.get(.q1).asUsize()));
+}
+
+test "metrics count instructions, syscalls, stack and memory" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.push_reg);
+    try b.size(.qword);
+    try b.reg(.q0);
+    try b.op(.push_reg);
+    try b.size(.qword);
+    try b.reg(.q0);
+    try b.op(.pop_reg);
+    try b.size(.qword);
+    try b.reg(.q0);
+    // sys_malloc(256), twice
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x04);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(256);
+    try b.op(.syscall);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(256);
+    try b.op(.syscall);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    const metrics = vm.metrics();
+    try testing.expectEqual(@as(u64, 9), metrics.instructions);
+    try testing.expectEqual(@as(u64, 2), metrics.syscallCount(0x04));
+    try testing.expectEqual(@as(u64, 0), metrics.syscallCount(0x05));
+    try testing.expectEqual(@as(u64, 2), metrics.totalSyscalls());
+    try testing.expectEqual(@as(usize, 16), metrics.peak_stack_depth);
+    try testing.expectEqual(@as(usize, 65536 + 512), metrics.peak_memory);
+}
+
+test "heap reuses and merges freed ranges" {
+    var heap = try Heap.init(0x13, 64, testing.allocator);
+    defer heap.deinit();
+
+    const a = (try heap.alloc(10)).?;
+    const b = (try heap.alloc(8)).?;
+    try testing.expectEqual(@as(usize, 0x18), a);
+    try testing.expectEqual(@as(usize, 0x28), b);
+    try testing.expectEqual(@as(?usize, null), try heap.alloc(64));
+
+    try heap.free(a);
+    try heap.free(b);
+    try testing.expectError(error.InvalidFreeAddress, heap.free(b));
+    // Both ranges merged back into one that fills the heap.
+    try testing.expectEqual(@as(?usize, 0x18), try heap.alloc(64));
+
+    // Shrinking past a live allocation fails, growing adds room.
+    try testing.expect(!try heap.setBreak(0x30));
+    try testing.expect(try heap.setBreak(0x68));
+    try testing.expectEqual(@as(?usize, 0x58), try heap.alloc(16));
+}
+
+test "heap fragmentation comes from the free list" {
+    var heap = try Heap.init(0, 64, testing.allocator);
+    defer heap.deinit();
+
+    try testing.expectEqual(@as(f64, 0), heap.fragmentation().external());
+    const a = (try heap.alloc(8)).?;
+    _ = (try heap.alloc(8)).?;
+    const c = (try heap.alloc(16)).?;
+    _ = (try heap.alloc(8)).?;
+    try heap.free(a);
+    try heap.free(c);
+
+    // Free: 8 bytes at the start, 16 in the middle and 24 at the end.
+    const fragmentation = heap.fragmentation();
+    try testing.expectEqual(@as(usize, 3), fragmentation.free_blocks);
+    try testing.expectEqual(@as(usize, 48), fragmentation.free_bytes);
+    try testing.expectEqual(@as(usize, 24), fragmentation.largest_free);
+    try testing.expectEqual(@as(f64, 50), fragmentation.external());
+}
+
+test "alloc and brk syscalls use the heap after the program" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    // q1 = sys_alloc(100)
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x0F);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(100);
+    try b.op(.syscall);
+    try b.op(.mov_reg_reg);
+    try b.reg(.q1);
+    try b.reg(.q0);
+    // q2 = sys_brk(0)
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x11);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(0);
+    try b.op(.syscall);
+    try b.op(.mov_reg_reg);
+    try b.reg(.q2);
+    try b.reg(.q0);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.setHeapSize(256);
+    try vm.run();
+
+    try testing.expectEqual(std.mem.alignForward(u64, vm.program_len, 8), vm.regs.get(.q1).asU64());
+    try testing.expectEqual(vm.regs.get(.q1).asU64() + 256, vm.regs.get(.q2).asU64());
+    try testing.expectEqual(vm.regs.get(.q2).asU64(), vm.regs.get(.brk).asU64());
+    try testing.expectEqual(@as(usize, 104), vm.heap.bytesInUse());
+    try testing.expectError(error.HeapTooLarge, vm.setHeapSize(1 << 20));
+    try testing.expectError(error.HeapTooLarge, vm.setHeapSize(std.math.maxInt(usize)));
+
+    // The heap stops below the stack guard, not at the end of memory.
+    var guarded = try b.load(testing.allocator);
+    defer guarded.deinit();
+    try guarded.setStackGuard(1024);
+    try testing.expectError(error.HeapTooLarge, guarded.setHeapSize(guarded.mmu.guard.?.start));
+    try guarded.setHeapSize(1024);
+}
+
+test "brk register follows the end of the heap" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    // q1 = brk before, q0 = sys_brk(brk + 64), q2 = brk after
+    try b.op(.mov_reg_reg);
+    try b.reg(.q1);
+    try b.reg(.brk);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x11);
+    try b.op(.mov_reg_reg);
+    try b.reg(.q0);
+    try b.reg(.brk);
+    try b.op(.inc_reg_imm);
+    try b.reg(.q0);
+    try b.qword(64);
+    try b.op(.syscall);
+    try b.op(.mov_reg_reg);
+    try b.reg(.q2);
+    try b.reg(.brk);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try testing.expectEqual(vm.heap.brk, vm.regs.get(.brk).asUsize());
+    try vm.setHeapSize(256);
+    try testing.expectEqual(vm.heap.brk, vm.regs.get(.brk).asUsize());
+    try vm.run();
+
+    const before = vm.regs.get(.q1).asU64();
+    try testing.expectEqual(std.mem.alignForward(u64, vm.program_len, 8) + 256, before);
+    try testing.expectEqual(before + 64, vm.regs.get(.q0).asU64());
+    try testing.expectEqual(before + 64, vm.regs.get(.q2).asU64());
+    try testing.expectEqual(vm.heap.brk, vm.regs.get(.brk).asUsize());
+}
+
+test "ticks syscall reads the instruction counter" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x12);
+    try b.op(.syscall);
+    try b.op(.mov_reg_reg);
+    try b.reg(.q1);
+    try b.reg(.q0);
+    try b.op(.nop);
+    try b.op(.nop);
+    try b.op(.syscall);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, 2), vm.regs.get(.q1).asU64());
+    try testing.expectEqual(@as(u64, 6), vm.regs.get(.q0).asU64());
+}
+
+test "exit syscall halts with the status instead of ending the process" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0xFF);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(0x1_2A);
+    try b.op(.syscall);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q1);
+    try b.qword(1);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    // Only the low byte is the status, and nothing after the call runs.
+    try testing.expectEqual(@as(?u8, 0x2A), vm.exit_status);
+    try testing.expect(vm.halted);
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q1).asU64());
+}
+
+test "arguments and environment are on the stack and reachable by syscall" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x19);
+    try b.op(.syscall);
+    try b.op(.mov_reg_reg);
+    try b.reg(.q2);
+    try b.reg(.q0);
+    try b.op(.mov_reg_reg);
+    try b.reg(.q3);
+    try b.reg(.q1);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x1A);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(0x1000);
+    try b.op(.syscall);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.mmu.writeSlice(0x1000, "HOME\x00");
+    try vm.setArguments(&.{ "prog.nyb", "hello" }, &.{ "USER=nyx", "HOME=/home/nyx" });
+    try testing.expect(vm.regs.sp() % 16 == 0);
+    try testing.expectEqual(vm.regs.sp(), vm.regs.get(.isp).asUsize());
+
+    const sp = vm.regs.sp();
+    try testing.expectEqual(@as(u64, 2), (try vm.mmu.read(sp, .qword)).asU64());
+    const argv1 = (try vm.mmu.read(sp + 16, .qword)).asUsize();
+    try testing.expectEqualStrings("hello\x00", try vm.mmu.readSlice(argv1, 6));
+    try testing.expectEqual(@as(u64, 0), (try vm.mmu.read(sp + 24, .qword)).asU64());
+    const envp1 = (try vm.mmu.read(sp + 40, .qword)).asUsize();
+    try testing.expectEqualStrings("HOME=/home/nyx", try vm.mmu.readSlice(envp1, 14));
+    try testing.expectEqual(@as(u64, 0), (try vm.mmu.read(sp + 48, .qword)).asU64());
+
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, 2), vm.regs.get(.q2).asU64());
+    try testing.expectEqual(@as(u64, sp + 8), vm.regs.get(.q3).asU64());
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q14).asU64());
+    try testing.expectEqualStrings("/home/nyx", try vm.mmu.readSlice(vm.regs.get(.q0).asUsize(), 9));
+}
+
+test "getenv fails for a variable that was not passed" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x1A);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(0x1000);
+    try b.op(.syscall);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.mmu.writeSlice(0x1000, "PATH\x00");
+    try vm.setArguments(&.{"prog.nyb"}, &.{"HOME=/home/nyx"});
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, 1), vm.regs.get(.q14).asU64());
+}
+
+/// A device with a register at offset 0 that keeps the last value written
+/// and one at offset 4 that counts how often it has been read.
+const CounterDevice = struct {
+    value: u32 = 0,
+    reads: u32 = 0,
+
+    const vtable = Vm.Device.VTable{
+        .name = name,
+        .size = size,
+        .read = read,
+        .write = write,
+    };
+
+    fn device(self: *CounterDevice) Vm.Device {
+        return .{ .ptr = self, .vtable = &vtable };
+    }
+
+    fn name(_: *anyopaque) []const u8 {
+        return "Counter";
+    }
+
+    fn size(_: *anyopaque) usize {
+        return 8;
+    }
+
+    fn read(ptr: *anyopaque, offset: usize, _: DataSize) anyerror!Immediate {
+        const self: *CounterDevice = @ptrCast(@alignCast(ptr));
+        if (offset == 0) return .{ .dword = self.value };
+        self.reads += 1;
+        return .{ .dword = self.reads };
+    }
+
+    fn write(ptr: *anyopaque, offset: usize, value: Immediate, _: DataSize) anyerror!void {
+        const self: *CounterDevice = @ptrCast(@alignCast(ptr));
+        if (offset == 0) self.value = @truncate(value.asU64());
+    }
+};
+
+test "accesses in the MMIO window go to the mapped device" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    var counter = CounterDevice{};
+    const base = DeviceBus.default_base + 0x100;
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.d2);
+    try b.dword(5);
+    try b.op(.mov_addr_reg);
+    try b.reg(.d2);
+    try b.addrImm(base, 0);
+    try b.op(.mov_reg_addr);
+    try b.reg(.d3);
+    try b.addrImm(base, 4);
+    try b.op(.mov_reg_addr);
+    try b.reg(.d4);
+    try b.addrImm(base, 4);
+    try b.op(.mov_reg_addr);
+    try b.reg(.d5);
+    try b.addrImm(base, 0);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try testing.expectEqual(base, try vm.mapDevice(counter.device(), 0x100));
+    try vm.run();
+
+    try testing.expectEqual(@as(u32, 5), counter.value);
+    try testing.expectEqual(@as(u64, 1), vm.regs.get(.d3).asU64());
+    try testing.expectEqual(@as(u64, 2), vm.regs.get(.d4).asU64());
+    try testing.expectEqual(@as(u64, 5), vm.regs.get(.d5).asU64());
+}
+
+test "MMIO window rejects overlapping devices and unmapped addresses" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_addr);
+    try b.reg(.d0);
+    try b.addrImm(DeviceBus.default_base + 0x10, 0);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+
+    var first = CounterDevice{};
+    var second = CounterDevice{};
+    _ = try vm.mapDevice(first.device(), 0);
+    try testing.expectError(error.DeviceOverlap, vm.mapDevice(second.device(), 4));
+    try testing.expectError(error.DeviceOutsideWindow, vm.mapDevice(second.device(), DeviceBus.default_size - 4));
+    // A dword at offset 6 would run past the end of the first device.
+    try testing.expectError(error.AddressOutOfBounds, vm.mmu.read(DeviceBus.default_base + 6, .dword));
+    try testing.expectError(error.AddressOutOfBounds, vm.run());
+}
+
+test "framebuffer pixels are written through the MMIO window and displayed" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    // Ask where the framebuffer is, set the pixel at (1, 2) and show it.
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x1B);
+    try b.op(.syscall);
+    try b.op(.mov_reg_reg);
+    try b.reg(.q5);
+    try b.reg(.q1);
+    try b.op(.mov_reg_imm);
+    try b.reg(.d3);
+    try b.dword(0xFF336699);
+    try b.op(.mov_addr_reg);
+    try b.reg(.d3);
+    try b.addrReg(.q0, (2 * Framebuffer.width + 1) * Framebuffer.bytes_per_pixel);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x1C);
+    try b.op(.syscall);
+    try b.op(.hlt);
+
+    const Recorder = struct {
+        presented: u32 = 0,
+        pixel: u32 = 0,
+
+        fn present(ptr: *anyopaque, pixels: []const u8) anyerror!void {
+            const self: *@This() = @ptrCast(@alignCast(ptr));
+            self.presented += 1;
+            const at = (2 * Framebuffer.width + 1) * Framebuffer.bytes_per_pixel;
+            self.pixel = std.mem.readInt(u32, pixels[at..][0..4], .little);
+        }
+    };
+    var recorder = Recorder{};
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.attachFramebuffer();
+    vm.display = .{ .ptr = &recorder, .presentFn = Recorder.present };
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, Framebuffer.width), vm.regs.get(.q5).asU64());
+    try testing.expectEqual(@as(u64, Framebuffer.height), vm.regs.get(.q2).asU64());
+    try testing.expectEqual(@as(u32, 0xFF336699), vm.framebuffer.?.pixel(1, 2));
+    try testing.expectEqual(@as(u32, 1), recorder.presented);
+    try testing.expectEqual(@as(u32, 0xFF336699), recorder.pixel);
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q0).asU64());
+}
+
+test "poll key returns queued key events and then 0" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    // Poll three times, keeping each code and pressed flag.
+    for ([_][2]Register{ .{ .q2, .q3 }, .{ .q4, .q5 }, .{ .q6, .q7 } }) |saved| {
+        try b.op(.mov_reg_imm);
+        try b.reg(.q15);
+        try b.qword(0x1D);
+        try b.op(.syscall);
+        try b.op(.mov_reg_reg);
+        try b.reg(saved[0]);
+        try b.reg(.q0);
+        try b.op(.mov_reg_reg);
+        try b.reg(saved[1]);
+        try b.reg(.q1);
+    }
+    try b.op(.hlt);
+
+    const Keys = struct {
+        events: []const Display.Key,
+
+        fn present(_: *anyopaque, _: []const u8) anyerror!void {}
+
+        fn pollKey(ptr: *anyopaque) ?Display.Key {
+            const self: *@This() = @ptrCast(@alignCast(ptr));
+            if (self.events.len == 0) return null;
+            defer self.events = self.events[1..];
+            return self.events[0];
+        }
+    };
+    var keys = Keys{ .events = &.{
+        .{ .code = Display.Key.left, .pressed = true },
+        .{ .code = Display.Key.left, .pressed = false },
+    } };
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    vm.display = .{ .ptr = &keys, .presentFn = Keys.present, .pollKeyFn = Keys.pollKey };
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, Display.Key.left), vm.regs.get(.q2).asU64());
+    try testing.expectEqual(@as(u64, 1), vm.regs.get(.q3).asU64());
+    try testing.expectEqual(@as(u64, Display.Key.left), vm.regs.get(.q4).asU64());
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q5).asU64());
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q6).asU64());
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q7).asU64());
+}
+
+test "display fails without a framebuffer" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x1C);
+    try b.op(.syscall);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, 1), vm.regs.get(.q14).asU64());
+}
+
+test "stack guard stops a runaway stack" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    const loop = b.here();
+    try b.op(.push_imm);
+    try b.size(.qword);
+    try b.qword(0);
+    try b.op(.jmp_imm);
+    try b.qword(loop);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.setStackGuard(256);
+
+    // 32 pushes fill the slack, the 33rd lands in the guard.
+    try testing.expectError(error.StackGuardHit, vm.run());
+    try testing.expectEqual(@as(usize, 65536 - 256 - 8), vm.mmu.guard_hit);
+    try testing.expectEqual(@as(usize, loop), vm.instruction_start);
+    try testing.expectError(error.StackGuardTooLarge, vm.setStackGuard(65536));
+}
+
+test "randomized layout moves the stack and heap but not the program" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+    try b.op(.hlt);
+
+    var plain = try b.load(testing.allocator);
+    defer plain.deinit();
+
+    var seen_sp = false;
+    var seen_heap = false;
+    for (0..8) |seed| {
+        var prng = std.Random.DefaultPrng.init(seed);
+        var vm = try b.load(testing.allocator);
+        defer vm.deinit();
+        try vm.randomizeLayout(prng.random());
+
+        const sp = vm.regs.sp();
+        try testing.expectEqual(@as(usize, 0), sp % 16);
+        try testing.expect(sp <= plain.regs.sp() and plain.regs.sp() - sp <= Vm.layout_jitter);
+        try testing.expectEqual(sp, vm.regs.get(.isp).asUsize());
+        try testing.expect(vm.heap.base >= plain.heap.base and vm.heap.base - plain.heap.base <= Vm.layout_jitter);
+        try testing.expectEqual(plain.heap.brk - plain.heap.base, vm.heap.brk - vm.heap.base);
+        try testing.expect(vm.heap.brk < sp);
+        try testing.expectEqual(plain.regs.ip(), vm.regs.ip());
+        try testing.expectEqual(vm.heap.brk, vm.regs.get(.brk).asUsize());
+
+        seen_sp = seen_sp or sp != plain.regs.sp();
+        seen_heap = seen_heap or vm.heap.base != plain.heap.base;
+    }
+    try testing.expect(seen_sp and seen_heap);
+}
+
+test "protected text cannot be written" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_addr_imm);
+    try b.size(.byte);
+    try b.byte(0);
+    try b.addrImm(0, 0);
+    try b.op(.hlt);
+    const text_len: usize = @intCast(b.here());
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.protectText(text_len);
+
+    try testing.expectError(error.ProtectionFault, vm.run());
+    try testing.expectEqual(Mmu.Access.write, vm.mmu.fault.access);
+    try testing.expectEqual(@as(usize, 0), vm.mmu.fault.addr);
+    try testing.expectEqualStrings("text", vm.mmu.fault.region.name);
+    try testing.expectError(error.TextTooLarge, vm.protectText(text_len + 1));
+
+    var unprotected = try b.load(testing.allocator);
+    defer unprotected.deinit();
+    try unprotected.protectText(text_len);
+    unprotected.disableProtection();
+    try unprotected.run();
+}
+
+test "memory after the program cannot be executed" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(0x8000);
+    try b.op(.jmp_reg);
+    try b.reg(.q0);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+
+    try testing.expectError(error.ProtectionFault, vm.run());
+    try testing.expectEqual(Mmu.Access.execute, vm.mmu.fault.access);
+    try testing.expectEqual(@as(usize, 0x8000), vm.mmu.fault.addr);
+    try testing.expectEqualStrings("memory", vm.mmu.fault.region.name);
+}
+
+test "timer counts expirations between polls" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    // Started by the 4th instruction, the timer fires after the 8th, 12th
+    // and 16th.
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x13);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(4);
+    try b.op(.mov_reg_imm);
+    try b.reg(.b1);
+    try b.byte(0);
+    try b.op(.syscall);
+    for (0..10) |_| try b.op(.nop);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x14);
+    try b.op(.syscall);
+    try b.op(.mov_reg_reg);
+    try b.reg(.q1);
+    try b.reg(.q0);
+    try b.op(.syscall);
+    try b.op(.mov_reg_reg);
+    try b.reg(.q2);
+    try b.reg(.q0);
+    // An unknown unit is refused.
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x13);
+    try b.op(.mov_reg_imm);
+    try b.reg(.b1);
+    try b.byte(2);
+    try b.op(.syscall);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, 3), vm.regs.get(.q1).asU64());
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q2).asU64());
+    try testing.expectEqual(@as(i64, -1), @as(i64, @bitCast(vm.regs.get(.q0).asU64())));
+}
+
+test "fill pattern covers unused memory only" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_addr);
+    try b.reg(.b0);
+    try b.addrImm(0x1000, 0);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    vm.setFillPattern(0xAA);
+    try vm.run();
+
+    try testing.expectEqual(@as(u8, 0xAA), vm.regs.get(.b0).asU8());
+    try testing.expectEqual(@as(u8, Opcode.mov_reg_addr.intoU8()), (try vm.mmu.read(0, .byte)).asU8());
+}
+
+test "fill pattern repaints freed heap blocks" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    // q1 = sys_alloc(16)
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x0F);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(16);
+    try b.op(.syscall);
+    try b.op(.mov_reg_reg);
+    try b.reg(.q1);
+    try b.reg(.q0);
+    // [q1] = 0x1122334455667788, q2 = [q1]
+    try b.op(.mov_addr_imm);
+    try b.size(.qword);
+    try b.qword(0x1122334455667788);
+    try b.addrReg(.q1, 0);
+    try b.op(.mov_reg_addr);
+    try b.reg(.q2);
+    try b.addrReg(.q1, 0);
+    // sys_dealloc(q1), q3 = [q1]
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x10);
+    try b.op(.mov_reg_reg);
+    try b.reg(.q0);
+    try b.reg(.q1);
+    try b.op(.syscall);
+    try b.op(.mov_reg_addr);
+    try b.reg(.q3);
+    try b.addrReg(.q1, 0);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    vm.setFillPattern(0xAA);
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, 0x1122334455667788), vm.regs.get(.q2).asU64());
+    try testing.expectEqual(@as(u64, 0xAAAAAAAAAAAAAAAA), vm.regs.get(.q3).asU64());
+}
+
+fn buildStraddlingStore(b: *ProgramBuilder, addr: u64) !void {
+    try b.op(.mov_addr_imm);
+    try b.size(.qword);
+    try b.qword(0x1122334455667788);
+    try b.addrImm(addr, 0);
+    try b.op(.mov_reg_addr);
+    try b.reg(.q5);
+    try b.addrImm(addr, 0);
+    try b.op(.mov_reg_addr);
+    try b.reg(.q6);
+    try b.addrImm(0x8000_0000, 0);
+    try b.op(.hlt);
+}
+
+test "large memory only allocates the pages that are written" {
+    // Pages are counted from the start of memory, right after the program,
+    // so measure the program first to find a page boundary near 3 GiB.
+    var sizing = ProgramBuilder.init(testing.allocator);
+    defer sizing.deinit();
+    try buildStraddlingStore(&sizing, 0);
+    const program_len = sizing.bytes.items.len;
+
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+    try buildStraddlingStore(&b, program_len + 0xC000_0000 - 4);
+    const program = try b.finish();
+    defer testing.allocator.free(program);
+
+    var vm = try Vm.init(program, 4 << 30, &.{}, testing.allocator);
+    defer vm.deinit();
+    vm.setFillPattern(0xAA);
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, 0x1122334455667788), vm.regs.get(.q5).asU64());
+    try testing.expectEqual(@as(u64, 0xAAAAAAAAAAAAAAAA), vm.regs.get(.q6).asU64());
+    try testing.expectEqual(program_len + 2 * Block.page_size, vm.mmu.residentBytes());
+}
+
+test "pointer arguments across a page boundary are lent as one range" {
+    var mmu = Mmu.init(testing.allocator);
+    defer mmu.deinit();
+    _ = try mmu.addPagedBlock("Memory", 3 * Block.page_size);
+
+    const addr = Block.page_size - 4;
+    try mmu.writeSlice(addr, "abcdefgh");
+
+    const loan = (try mmu.lend(addr, ExternalLoader.ptr_window)).?;
+    defer loan.deinit(testing.allocator);
+    try testing.expect(loan.bounce != null);
+    try testing.expectEqualStrings("abcdefgh", loan.ptr[0..8]);
+    // Native code writes across the boundary, like a C function filling a
+    // buffer, and only the bytes it changed go back.
+    @memcpy(loan.ptr[2..6], "WXYZ");
+    try mmu.settle(loan);
+    try testing.expectEqualStrings("abWXYZgh", try mmu.readSlice(addr, 8));
+    try testing.expectEqual(2 * Block.page_size, mmu.residentBytes());
+
+    // A range inside one page is lent in place.
+    const inside = (try mmu.lend(0, 16)).?;
+    defer inside.deinit(testing.allocator);
+    try testing.expect(inside.bounce == null);
+    inside.ptr[0] = 'q';
+    try testing.expectEqual(@as(u8, 'q'), (try mmu.read(0, .byte)).asU8());
+}
+
+test "signed bytecode verifies and loads" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(7);
+    try b.op(.hlt);
+
+    const program = try b.finish();
+    defer testing.allocator.free(program);
+
+    const key_pair = signature.generateKeyPair();
+    const signed = try signature.sign(testing.allocator, program, key_pair);
+    defer testing.allocator.free(signed);
+
+    try testing.expect(signature.isSigned(signed));
+    try testing.expect(!signature.isSigned(program));
+    try signature.verify(signed, key_pair.public_key);
+    try testing.expectError(error.UnsignedBytecode, signature.verify(program, key_pair.public_key));
+
+    const other = signature.generateKeyPair();
+    try testing.expectError(error.InvalidSignature, signature.verify(signed, other.public_key));
+
+    signed[signed.len - 1] ^= 0xFF;
+    try testing.expectError(error.InvalidSignature, signature.verify(signed, key_pair.public_key));
+    signed[signed.len - 1] ^= 0xFF;
+
+    var vm = try Vm.init(signature.payload(signed), 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+    try testing.expectEqual(@as(u64, 7), vm.regs.get(.q0).asU64());
+}
+
+test "console is mapped after memory" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x0E);
+    try b.op(.syscall);
+    try b.op(.mov_addr_imm);
+    try b.size(.word);
+    try b.word(0x0248); // green 'H'
+    try b.addrReg(.q0, 0);
+    try b.op(.mov_addr_imm);
+    try b.size(.byte);
+    try b.byte('i');
+    try b.addrReg(.q0, 2);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    vm.setFillPattern(0xAA);
+    try vm.attachConsole(try Console.Size.parse("2x3"));
+    vm.setFillPattern(0xAA);
+    try vm.run();
+
+    const console = vm.console.?;
+    try testing.expectEqual(vm.regs.get(.msz).asUsize(), console.base);
+    try testing.expectEqual(@as(u64, 2), vm.regs.get(.q1).asU64());
+    try testing.expectEqual(@as(u64, 3), vm.regs.get(.q2).asU64());
+    try testing.expectEqual(@as(u8, 'H'), console.char(0, 0));
+    try testing.expectEqual(@as(u8, 0x02), console.attribute(0, 0));
+
+    var text: std.Io.Writer.Allocating = .init(testing.allocator);
+    defer text.deinit();
+    try console.writeText(&text.writer);
+    try testing.expectEqualStrings("Hi\n\n", text.written());
+
+    var screen: std.Io.Writer.Allocating = .init(testing.allocator);
+    defer screen.deinit();
+    try console.render(&screen.writer);
+    try testing.expectEqualStrings("\x1b[H\x1b[0;32mH\x1b[0mi \x1b[K\r\n   \x1b[K\r\n", screen.written());
+
+    try testing.expectError(error.InvalidConsoleSize, Console.Size.parse("80"));
+    try testing.expectError(error.InvalidConsoleSize, Console.Size.parse("0x80"));
+    try testing.expectError(error.ConsoleTooLarge, Console.Size.parse("18446744073709551615x2"));
+    try testing.expectError(error.ConsoleTooLarge, Console.Size.parse("4294967296x4294967296"));
+    try testing.expectError(error.ConsoleTooLarge, (Console.Size{ .rows = std.math.maxInt(usize), .cols = 1 }).bytes());
+}
+
+test "programs with unknown required features are refused" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+    try b.op(.hlt);
+
+    const program = try b.finish();
+    defer testing.allocator.free(program);
+    program[7] |= 0x80;
+
+    try testing.expectError(error.UnsupportedFeature, Vm.init(program, 65536, &.{}, testing.allocator));
+}
+
+/// A program of `code` behind a header with `layout`.
+fn programWithLayout(entry: u64, layout: features.Layout, code: []const u8) ![]u8 {
+    var program: std.Io.Writer.Allocating = .init(testing.allocator);
+    errdefer program.deinit();
+    try features.write(&program.writer, entry, .initEmpty(), layout);
+    try program.writer.writeAll(code);
+    return program.toOwnedSlice();
+}
+
+test "the layout in the header is checked when loading" {
+    const hlt = Opcode.hlt.intoU8();
+
+    const good = try programWithLayout(0, .{ .text_len = 1, .data_len = 2 }, &.{ hlt, 0xAA, 0xBB });
+    defer testing.allocator.free(good);
+    var vm = try Vm.init(good, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try testing.expectEqualStrings("text", vm.mmu.regions.items[0].name);
+    try testing.expectEqualStrings("data", vm.mmu.regions.items[1].name);
+    try vm.run();
+
+    const short = try programWithLayout(0, .{ .text_len = 1, .data_len = 4 }, &.{ hlt, 0xAA, 0xBB });
+    defer testing.allocator.free(short);
+    try testing.expectError(error.LayoutMismatch, Vm.init(short, 65536, &.{}, testing.allocator));
+
+    const into_data = try programWithLayout(1, .{ .text_len = 1, .data_len = 2 }, &.{ hlt, 0xAA, 0xBB });
+    defer testing.allocator.free(into_data);
+    try testing.expectError(error.EntryOutsideText, Vm.init(into_data, 65536, &.{}, testing.allocator));
+}
+
+test "a malformed debug section is refused" {
+    // A one-byte text section followed by a debug section whose only line
+    // is in a file it does not list.
+    var code: [29]u8 = undefined;
+    var writer: std.Io.Writer = .fixed(&code);
+    try writer.writeByte(Opcode.hlt.intoU8());
+    try writer.writeInt(u32, 1, .little);
+    try writer.writeInt(u32, 0, .little);
+    try writer.writeInt(u32, 0, .little);
+    try writer.writeInt(u32, 1, .little);
+    inline for (.{ 0, 0, 1, 1 }) |field| try writer.writeInt(u32, field, .little);
+    try testing.expectEqual(code.len, writer.buffered().len);
+
+    const program = try programWithLayout(0, .{ .text_len = 1, .data_len = 0, .debug_len = code.len - 1 }, &code);
+    defer testing.allocator.free(program);
+    try testing.expectError(error.InvalidDebugInfo, Vm.init(program, 65536, &.{}, testing.allocator));
+
+    // Symbols out of address order, which `nearestSymbol` cannot search.
+    var unsorted: std.Io.Writer.Allocating = .init(testing.allocator);
+    defer unsorted.deinit();
+    try unsorted.writer.writeInt(u32, 1, .little);
+    try unsorted.writer.writeInt(u32, 0, .little);
+    try unsorted.writer.writeInt(u32, 2, .little);
+    inline for (.{ .{ "b", 8 }, .{ "a", 0 } }) |symbol| {
+        try unsorted.writer.writeInt(u32, symbol[0].len, .little);
+        try unsorted.writer.writeAll(symbol[0]);
+        try unsorted.writer.writeInt(u64, symbol[1], .little);
+    }
+    try unsorted.writer.writeInt(u32, 0, .little);
+    try testing.expectError(error.InvalidDebugInfo, DebugInfo.read(testing.allocator, unsorted.written()));
+
+    // A count far larger than the section is refused before allocating.
+    const huge = [_]u8{ 1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff };
+    try testing.expectError(error.InvalidDebugInfo, DebugInfo.read(testing.allocator, &huge));
+}
+
+test "backtrace finds the calls on the stack" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    // 0x00: call 0x0a; 0x09: hlt
+    try b.op(.call_imm);
+    try b.qword(0x0a);
+    try b.op(.hlt);
+    // 0x0a: push 0x1234; call 0x1e; 0x1d: hlt
+    try b.op(.push_imm);
+    try b.size(.qword);
+    try b.qword(0x1234);
+    try b.op(.call_imm);
+    try b.qword(0x1e);
+    try b.op(.hlt);
+    // 0x1e: unknown syscall
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0xFFFF);
+    try b.op(.syscall);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try testing.expectError(error.UnknownSyscall, vm.run());
+    try testing.expectEqual(@as(usize, 0x28), vm.instruction_start);
+
+    var frames: [8]Vm.Frame = undefined;
+    const trace = vm.backtrace(&frames);
+    try testing.expectEqual(@as(usize, 2), trace.len);
+    try testing.expectEqual(@as(usize, 0x14), trace[0].call_site);
+    try testing.expectEqual(@as(?usize, 0x1e), trace[0].target);
+    try testing.expectEqual(@as(usize, 0x00), trace[1].call_site);
+    try testing.expectEqual(@as(?usize, 0x0a), trace[1].target);
+}
+
+test "trace prints each instruction with its changes" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(3);
+    try b.op(.cmp_reg_imm);
+    try b.reg(.q0);
+    try b.qword(3);
+    try b.op(.hlt);
+
+    const program = try b.finish();
+    defer testing.allocator.free(program);
+    var vm = try Vm.init(program, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+
+    var output: std.Io.Writer.Allocating = .init(testing.allocator);
+    defer output.deinit();
+    var trace = try Trace.init(program, &output.writer, testing.allocator);
+    defer trace.deinit();
+    vm.setTracer(trace.tracer());
+    try vm.run();
+
+    var lines = std.mem.splitScalar(u8, output.written(), '\n');
+    try testing.expect(std.mem.startsWith(u8, lines.next().?, "0x0000  mov q0, 3"));
+    const cmp = lines.next().?;
+    try testing.expect(std.mem.startsWith(u8, cmp, "0x000a  cmp q0, 3"));
+    try testing.expect(std.mem.endsWith(u8, cmp, "; eq = true"));
+    try testing.expectEqualStrings("0x0014  hlt", lines.next().?);
+    try testing.expectEqualStrings("", lines.next().?);
+}
+
+test "hooks see every instruction before it runs" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(0);
+    const loop = b.here();
+    try b.op(.inc);
+    try b.reg(.q0);
+    try b.op(.cmp_reg_imm);
+    try b.reg(.q0);
+    try b.qword(100);
+    try b.op(.jne_imm);
+    try b.qword(loop);
+    try b.op(.hlt);
+
+    const Profile = struct {
+        counts: [256]u32 = @splat(0),
+        /// Value of `q0` when the last `cmp` was about to run.
+        last_q0: u64 = 0,
+        last_addr: usize = 0,
+
+        fn hook(ptr: *anyopaque, vm: *const Vm, addr: usize, opcode: Opcode) void {
+            const self: *@This() = @ptrCast(@alignCast(ptr));
+            self.counts[opcode.intoU8()] += 1;
+            if (opcode == .cmp_reg_imm) self.last_q0 = vm.regs.get(.q0).asU64();
+            self.last_addr = addr;
+        }
+    };
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    var profile: Profile = .{};
+    vm.setHook(.{ .ptr = &profile, .hookFn = Profile.hook });
+    try vm.run();
+
+    try testing.expectEqual(@as(u32, 1), profile.counts[Opcode.mov_reg_imm.intoU8()]);
+    try testing.expectEqual(@as(u32, 100), profile.counts[Opcode.inc.intoU8()]);
+    try testing.expectEqual(@as(u32, 100), profile.counts[Opcode.cmp_reg_imm.intoU8()]);
+    try testing.expectEqual(@as(u32, 100), profile.counts[Opcode.jne_imm.intoU8()]);
+    try testing.expectEqual(@as(u32, 1), profile.counts[Opcode.hlt.intoU8()]);
+    try testing.expectEqual(@as(u64, 100), profile.last_q0);
+    try testing.expectEqual(@as(usize, b.here() - 1), profile.last_addr);
+
+    var unhooked = try b.load(testing.allocator);
+    defer unhooked.deinit();
+    unhooked.setHook(.{ .ptr = &profile, .hookFn = Profile.hook });
+    unhooked.setHook(null);
+    try unhooked.run();
+    try testing.expectEqual(@as(u32, 1), profile.counts[Opcode.hlt.intoU8()]);
+}
+
+test "restoring a saved state puts back registers, memory, heap and descriptors" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(1);
+    try b.op(.inc);
+    try b.reg(.q0);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.step();
+    try vm.mmu.write(0x9000, .{ .qword = 7 }, .qword);
+    const kept = (try vm.heap.alloc(16)).?;
+
+    var state = try vm.saveState();
+    defer state.deinit();
+
+    try vm.run();
+    try vm.mmu.write(0x8000, .{ .qword = 42 }, .qword);
+    _ = try vm.heap.alloc(32);
+    try vm.heap.free(kept);
+    _ = try vm.files.dup(1);
+    try testing.expect(vm.halted);
+
+    try vm.restoreState(&state);
+    try testing.expect(!vm.halted);
+    try testing.expectEqual(@as(u64, 1), vm.regs.get(.q0).asU64());
+    try testing.expectEqual(@as(u64, 0), (try vm.mmu.read(0x8000, .qword)).asU64());
+    try testing.expectEqual(@as(u64, 7), (try vm.mmu.read(0x9000, .qword)).asU64());
+    try testing.expectEqual(@as(usize, 16), vm.heap.bytesInUse());
+    try testing.expectEqual(@as(?FileTable.Fd, null), vm.files.get(3));
+    try vm.run();
+    try testing.expectEqual(@as(u64, 2), vm.regs.get(.q0).asU64());
+
+    // A state read back from its file resumes in a fresh VM.
+    var output: std.Io.Writer.Allocating = .init(testing.allocator);
+    defer output.deinit();
+    try state.write(&output.writer);
+    var loaded = try Vm.State.read(testing.allocator, output.written());
+    defer loaded.deinit();
+
+    var fresh = try b.load(testing.allocator);
+    defer fresh.deinit();
+    try fresh.restoreState(&loaded);
+    try testing.expectEqual(@as(u64, 7), (try fresh.mmu.read(0x9000, .qword)).asU64());
+    try testing.expectEqual(@as(usize, 16), fresh.heap.bytesInUse());
+    try fresh.run();
+    try testing.expectEqual(@as(u64, 2), fresh.regs.get(.q0).asU64());
+
+    const program = try b.finish();
+    defer testing.allocator.free(program);
+    var smaller = try Vm.init(program, 32768, &.{}, testing.allocator);
+    defer smaller.deinit();
+    try testing.expectError(error.StateMismatch, smaller.restoreState(&loaded));
+    try testing.expectError(error.InvalidState, Vm.State.read(testing.allocator, output.written()[0 .. output.written().len - 1]));
+    try testing.expectError(error.InvalidState, Vm.State.read(testing.allocator, "NYXB"));
+}
+
+test "a state saved after sys_malloc resumes with the added block" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    // sys_malloc(64)
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x04);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(64);
+    try b.op(.syscall);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    var before = try vm.saveState();
+    defer before.deinit();
+    try vm.run();
+    const addr = vm.regs.get(.q0).asUsize();
+    try vm.mmu.write(addr + 8, .{ .qword = 9 }, .qword);
+
+    var state = try vm.saveState();
+    defer state.deinit();
+    var output: std.Io.Writer.Allocating = .init(testing.allocator);
+    defer output.deinit();
+    try state.write(&output.writer);
+    var loaded = try Vm.State.read(testing.allocator, output.written());
+    defer loaded.deinit();
+
+    var fresh = try b.load(testing.allocator);
+    defer fresh.deinit();
+    try fresh.restoreState(&loaded);
+    try testing.expectEqual(vm.mmu.size(), fresh.mmu.size());
+    try testing.expectEqual(@as(u64, 9), (try fresh.mmu.read(addr + 8, .qword)).asU64());
+
+    // Going back to before the call drops the block again.
+    try fresh.restoreState(&before);
+    try testing.expectEqual(before.memory_size, fresh.mmu.size());
+    try fresh.run();
+    try testing.expectEqual(addr, fresh.regs.get(.q0).asUsize());
+
+    // A count larger than the rest of the file is rejected before allocating.
+    const bytes = try testing.allocator.dupe(u8, output.written());
+    defer testing.allocator.free(bytes);
+    const regs = vm.regs;
+    const blocks_at = 8 + 8 * (regs.gpr.len + regs.fpr.len + regs.special.len + regs.config.len) + 4;
+    std.mem.writeInt(u32, bytes[blocks_at..][0..4], std.math.maxInt(u32), .little);
+    try testing.expectError(error.InvalidState, Vm.State.read(testing.allocator, bytes));
+}
+
+fn collectWarning(ptr: *anyopaque, _: *const Vm, warning: Vm.Warning) void {
+    const warnings: *std.array_list.Managed(Vm.Warning) = @ptrCast(@alignCast(ptr));
+    warnings.append(warning) catch {};
+}
+
+test "retired opcode numbers run and migrate to the current ones" {
+    const retired: u8 = 0xF0;
+    const table = [_]Opcode.Alias{.{ .value = retired, .opcode = .mov_reg_imm }};
+
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+    try b.raw(&.{retired});
+    try b.reg(.q0);
+    try b.qword(7);
+    try b.raw(&.{retired});
+    try b.reg(.q1);
+    try b.qword(8);
+    try b.op(.hlt);
+    const program = try b.finish();
+    defer testing.allocator.free(program);
+
+    var vm = try Vm.init(program, 65536, &.{}, testing.allocator);
+    defer vm.deinit();
+    try testing.expectError(error.InvalidOpcode, vm.step());
+
+    var aliased = try Vm.init(program, 65536, &.{}, testing.allocator);
+    defer aliased.deinit();
+    aliased.aliases = &table;
+    var warnings = std.array_list.Managed(Vm.Warning).init(testing.allocator);
+    defer warnings.deinit();
+    aliased.setWarner(.{ .ptr = &warnings, .warnFn = collectWarning });
+    try aliased.run();
+    try testing.expectEqual(@as(u64, 7), aliased.regs.get(.q0).asU64());
+    try testing.expectEqual(@as(u64, 8), aliased.regs.get(.q1).asU64());
+    try testing.expectEqual(@as(usize, 1), warnings.items.len);
+    try testing.expectEqual(retired, warnings.items[0].retired_opcode.value);
+    try testing.expectEqual(Opcode.mov_reg_imm, warnings.items[0].retired_opcode.opcode);
+
+    const result = try migrate.migrate(testing.allocator, program, &table);
+    defer testing.allocator.free(result.bytecode);
+    try testing.expectEqual(@as(usize, 2), result.rewritten);
+    try testing.expectEqual(Opcode.mov_reg_imm.intoU8(), result.bytecode[8]);
+    try testing.expectEqualSlices(u8, program[9..18], result.bytecode[9..18]);
+    try testing.expectEqual(Opcode.mov_reg_imm.intoU8(), result.bytecode[18]);
+    try testing.expectEqualSlices(u8, program[19..], result.bytecode[19..]);
+}
+
+test "hot loops give the same results with the block cache" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(0);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q1);
+    try b.qword(0);
+    const loop = b.here();
+    try b.op(.inc);
+    try b.reg(.q1);
+    try b.op(.add_reg_reg_reg);
+    try b.reg(.q0);
+    try b.reg(.q0);
+    try b.reg(.q1);
+    try b.op(.cmp_reg_imm);
+    try b.reg(.q1);
+    try b.qword(1000);
+    try b.op(.jne_imm);
+    try b.qword(loop);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, 500500), vm.regs.get(.q0).asU64());
+    try testing.expect(vm.flags.eq);
+    try testing.expectEqual(@as(u64, 2 + 1000 * 4 + 1), vm.metrics().instructions);
+    if (vm.predecode) try testing.expect(vm.block_cache.blockCount() > 0);
+}
+
+/// Append a loop that runs `count` random instructions 32 times, enough for
+/// its blocks to get hot. Arithmetic traps on overflow, so the body only
+/// moves, masks, shifts and compares, with stack round trips and skipped
+/// instructions that the block cache leaves to the interpreter.
+fn randomLoop(b: *ProgramBuilder, random: std.Random, count: usize) !void {
+    const regs = [_]Register{ .q0, .q1, .q2, .q3, .q4, .q5, .q6, .q7 };
+    const logic = [_][2]Opcode{
+        .{ .and_reg_reg_reg, .and_reg_reg_imm },
+        .{ .or_reg_reg_reg, .or_reg_reg_imm },
+        .{ .xor_reg_reg_reg, .xor_reg_reg_imm },
+    };
+    const shifts = [_]Opcode{ .shl_reg_reg_imm, .shr_reg_reg_imm, .rol_reg_reg_imm, .ror_reg_reg_imm };
+    const branches = [_]Opcode{ .jeq_imm, .jne_imm, .jlt_imm, .jgt_imm };
+
+    for (regs) |reg| {
+        try b.op(.mov_reg_imm);
+        try b.reg(reg);
+        try b.qword(random.int(u64));
+    }
+    try b.op(.mov_reg_imm);
+    try b.reg(.q8);
+    try b.qword(0);
+
+    const loop = b.here();
+    for (0..count) |_| {
+        const dest = regs[random.uintLessThan(usize, regs.len)];
+        const src = regs[random.uintLessThan(usize, regs.len)];
+        const imm = random.int(u64);
+        // Skip the instruction that follows on some flag states.
+        const skip: ?usize = if (random.uintLessThan(u8, 8) == 0) blk: {
+            try b.op(branches[random.uintLessThan(usize, branches.len)]);
+            const at = b.bytes.items.len;
+            try b.qword(0);
+            break :blk at;
+        } else null;
+        switch (random.uintLessThan(u8, 6)) {
+            0 => {
+                try b.op(.mov_reg_imm);
+                try b.reg(dest);
+                try b.qword(imm);
+            },
+            1 => {
+                try b.op(.mov_reg_reg);
+                try b.reg(dest);
+                try b.reg(src);
+            },
+            2 => {
+                const pair = logic[random.uintLessThan(usize, logic.len)];
+                const with_imm = random.boolean();
+                try b.op(if (with_imm) pair[1] else pair[0]);
+                try b.reg(dest);
+                try b.reg(src);
+                if (with_imm) try b.qword(imm) else try b.reg(regs[random.uintLessThan(usize, regs.len)]);
+            },
+            3 => {
+                try b.op(shifts[random.uintLessThan(usize, shifts.len)]);
+                try b.reg(dest);
+                try b.reg(src);
+                try b.qword(random.uintLessThan(u64, 64));
+            },
+            4 => {
+                try b.op(.cmp_reg_reg);
+                try b.reg(dest);
+                try b.reg(src);
+            },
+            else => {
+                try b.op(.push_reg);
+                try b.size(.qword);
+                try b.reg(src);
+                try b.op(.pop_reg);
+                try b.size(.qword);
+                try b.reg(dest);
+            },
+        }
+        if (skip) |at| std.mem.writeInt(u64, b.bytes.items[at..][0..8], b.here(), .little);
+    }
+    try b.op(.inc);
+    try b.reg(.q8);
+    try b.op(.cmp_reg_imm);
+    try b.reg(.q8);
+    try b.qword(32);
+    try b.op(.jne_imm);
+    try b.qword(loop);
+    try b.op(.hlt);
+}
+
+test "random loops give the same results with and without the block cache" {
+    var prng = std.Random.DefaultPrng.init(0x6e7978);
+    const random = prng.random();
+
+    for (0..64) |_| {
+        var b = ProgramBuilder.init(testing.allocator);
+        defer b.deinit();
+        try randomLoop(&b, random, 1 + random.uintLessThan(usize, 32));
+
+        var interpreted = try b.load(testing.allocator);
+        defer interpreted.deinit();
+        interpreted.predecode = false;
+        try interpreted.run();
+
+        var predecoded = try b.load(testing.allocator);
+        defer predecoded.deinit();
+        predecoded.predecode = true;
+        try predecoded.run();
+
+        try testing.expect(predecoded.block_cache.blockCount() > 0);
+        try testing.expectEqualSlices(u64, &interpreted.regs.gpr, &predecoded.regs.gpr);
+        try testing.expectEqual(interpreted.flags, predecoded.flags);
+        try testing.expectEqual(interpreted.metrics().instructions, predecoded.metrics().instructions);
+    }
+}
+
+test "fuel stops a program after exactly that many instructions" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.q1);
+    try b.qword(0);
+    const loop = b.here();
+    try b.op(.inc);
+    try b.reg(.q1);
+    try b.op(.cmp_reg_imm);
+    try b.reg(.q1);
+    try b.qword(1000);
+    try b.op(.jne_imm);
+    try b.qword(loop);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try testing.expectError(error.StepLimitExceeded, vm.runWithFuel(100));
+    try testing.expectEqual(@as(u64, 100), vm.metrics().instructions);
+    try testing.expectEqual(@as(?u64, null), vm.step_limit);
+    try testing.expect(!vm.halted);
+
+    // Resuming with exactly the instructions left lets it halt.
+    try vm.runWithFuel(1 + 1000 * 3 + 1 - 100);
+    try testing.expect(vm.halted);
+    try testing.expectEqual(@as(u64, 1000), vm.regs.get(.q1).asU64());
+}
+
+test "writing into compiled code drops the compiled blocks" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(0);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q1);
+    try b.qword(0);
+    const loop = b.here();
+    try b.op(.inc);
+    try b.reg(.q1);
+    try b.op(.add_reg_reg_imm);
+    try b.reg(.q0);
+    try b.reg(.q0);
+    const step = b.here();
+    try b.qword(1);
+    try b.op(.cmp_reg_imm);
+    try b.reg(.q1);
+    try b.qword(50);
+    try b.op(.jne_imm);
+    // Skip the 27-byte store below.
+    try b.qword(b.here() + 8 + 27);
+    try b.op(.mov_addr_imm);
+    try b.size(.qword);
+    try b.qword(10);
+    try b.addrImm(step, 0);
+    try b.op(.cmp_reg_imm);
+    try b.reg(.q1);
+    try b.qword(100);
+    try b.op(.jne_imm);
+    try b.qword(loop);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.run();
+
+    try testing.expectEqual(@as(u64, 50 * 1 + 50 * 10), vm.regs.get(.q0).asU64());
+}
+
+fn hostAnswer(vm: *Vm) anyerror!void {
+    const answer: *const u64 = @ptrCast(@alignCast(vm.host.?));
+    vm.setSyscallResult(answer.*);
+}
+
+test "embedders register and deny syscalls" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x80);
+    try b.op(.syscall);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x03);
+    try b.op(.syscall);
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+
+    var answer: u64 = 42;
+    vm.host = &answer;
+    try vm.registerSyscall(0x80, hostAnswer);
+    vm.denySyscalls(.fs);
+
+    try testing.expectError(error.UnknownSyscall, vm.run());
+    try testing.expectEqual(@as(u64, 42), vm.regs.get(.q0).asU64());
+    try testing.expectEqual(@as(u64, 1), vm.metrics().syscallCount(0x80));
+}
+
+/// `ext 0x80, dest, src, scale`: add `src * scale` to `dest`.
+fn multiplyAdd(vm: *Vm, operands: []const u8) anyerror!void {
+    if (operands.len != 3) return error.InvalidOperands;
+    const dest = try Register.fromU8(operands[0]);
+    const src = try Register.fromU8(operands[1]);
+    const sum = vm.regs.get(dest).asU64() + vm.regs.get(src).asU64() * operands[2];
+    vm.regs.set(dest, .{ .qword = sum });
+}
+
+test "extended instructions run registered handlers" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+
+    try b.op(.mov_reg_imm);
+    try b.reg(.q0);
+    try b.qword(2);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q1);
+    try b.qword(5);
+    try b.op(.extended);
+    try b.raw(&.{ Opcode.first_custom_subcode, 3, @intFromEnum(Register.q0), @intFromEnum(Register.q1), 4 });
+    try b.op(.inc);
+    try b.reg(.q0);
+    try b.op(.extended);
+    try b.raw(&.{ Opcode.first_custom_subcode + 1, 0 });
+    try b.op(.hlt);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    try vm.registerExtension(Opcode.first_custom_subcode, multiplyAdd);
+
+    try testing.expectError(error.UnknownExtension, vm.run());
+    try testing.expectEqual(@as(u64, 2 + 5 * 4 + 1), vm.regs.get(.q0).asU64());
+}
+
+/// A program that writes `text` to stdout and halts.
+fn buildGreeting(b: *ProgramBuilder, text: []const u8) !void {
+    const code_len = 10 + 6 + 10 + 10 + 1 + 1;
+    try b.op(.mov_reg_imm);
+    try b.reg(.q15);
+    try b.qword(0x03);
+    try b.op(.mov_reg_imm);
+    try b.reg(.d0);
+    try b.dword(1);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q1);
+    try b.qword(code_len);
+    try b.op(.mov_reg_imm);
+    try b.reg(.q2);
+    try b.qword(text.len);
+    try b.op(.syscall);
+    try b.op(.hlt);
+    try b.raw(text);
+}
+
+test "verify reports the first output that differs" {
+    var b1 = ProgramBuilder.init(testing.allocator);
+    defer b1.deinit();
+    try buildGreeting(&b1, "hello\n");
+    var b2 = ProgramBuilder.init(testing.allocator);
+    defer b2.deinit();
+    try buildGreeting(&b2, "hello\n");
+    var b3 = ProgramBuilder.init(testing.allocator);
+    defer b3.deinit();
+    try buildGreeting(&b3, "help\n");
+
+    {
+        var a = try b1.load(testing.allocator);
+        defer a.deinit();
+        var b = try b2.load(testing.allocator);
+        defer b.deinit();
+        const outcome = try verify.compare(testing.allocator, &a, &b, "", .{});
+        try testing.expectEqual(@as(usize, 1), outcome.same.matched);
+        try testing.expect(outcome.same.end == .halted);
+    }
+    {
+        var a = try b1.load(testing.allocator);
+        defer a.deinit();
+        var b = try b3.load(testing.allocator);
+        defer b.deinit();
+        const outcome = try verify.compare(testing.allocator, &a, &b, "", .{});
+        try testing.expectEqual(@as(usize, 0), outcome.diverged.matched);
+        try testing.expectEqual(@as(?usize, 3), outcome.diverged.offset);
+        try testing.expectEqual(@as(usize, 6), outcome.diverged.events[0].write.len);
+        try testing.expectEqual(@as(usize, 5), outcome.diverged.events[1].write.len);
+    }
+}
+
+/// `q2 = reg`, `[0x8000] = mem`, then `hlt`.
+fn buildFinalState(b: *ProgramBuilder, reg: u64, mem: u64) !void {
+    try b.op(.mov_reg_imm);
+    try b.reg(.q2);
+    try b.qword(reg);
+    try b.op(.mov_addr_imm);
+    try b.size(.qword);
+    try b.qword(mem);
+    try b.addrImm(0x8000, 0);
+    try b.op(.hlt);
+}
+
+test "verify compares the final registers and memory" {
+    const Case = struct { a: [2]u64, b: [2]u64 };
+    const cases = [_]Case{
+        .{ .a = .{ 16, 7 }, .b = .{ 0, 7 } },
+        .{ .a = .{ 16, 7 }, .b = .{ 16, 8 } },
+    };
+    var outcomes: [cases.len]verify.Outcome = undefined;
+    for (cases, &outcomes) |case, *outcome| {
+        var b1 = ProgramBuilder.init(testing.allocator);
+        defer b1.deinit();
+        try buildFinalState(&b1, case.a[0], case.a[1]);
+        var b2 = ProgramBuilder.init(testing.allocator);
+        defer b2.deinit();
+        try buildFinalState(&b2, case.b[0], case.b[1]);
+
+        var a = try b1.load(testing.allocator);
+        defer a.deinit();
+        var b = try b2.load(testing.allocator);
+        defer b.deinit();
+        outcome.* = try verify.compare(testing.allocator, &a, &b, "", .{});
+
+        var c = try b1.load(testing.allocator);
+        defer c.deinit();
+        var d = try b2.load(testing.allocator);
+        defer d.deinit();
+        const loose = try verify.compare(testing.allocator, &c, &d, "", .{ .compare_state = false });
+        try testing.expect(loose.same.end == .halted);
+    }
+
+    const register = outcomes[0].diverged.state.?.register;
+    try testing.expect(outcomes[0].diverged.events[0] == .halted);
+    try testing.expectEqual(Register.q2, register.register);
+    try testing.expectEqual([2]u64{ 16, 0 }, register.values);
+
+    const memory = outcomes[1].diverged.state.?.memory;
+    try testing.expectEqual(@as(usize, 0x8000), memory.addr);
+    try testing.expectEqual([2]u8{ 7, 8 }, memory.values);
+}
+
+/// Four `sys_read_line` calls, saving each count and end-of-input flag in
+/// q3 to q10.
+fn buildLineReader(b: *ProgramBuilder) !void {
+    const calls = [_]struct { addr: u64, capacity: u64, count: Register, eof: Register }{
+        .{ .addr = 0x8000, .capacity = 4, .count = .q3, .eof = .q4 },
+        .{ .addr = 0x8100, .capacity = 1000, .count = .q5, .eof = .q6 },
+        .{ .addr = 0x8600, .capacity = 16, .count = .q7, .eof = .q8 },
+        .{ .addr = 0x8600, .capacity = 16, .count = .q9, .eof = .q10 },
+    };
+    for (calls) |call| {
+        try b.op(.mov_reg_imm);
+        try b.reg(.q15);
+        try b.qword(0x18);
+        try b.op(.mov_reg_imm);
+        try b.reg(.q0);
+        try b.qword(call.addr);
+        try b.op(.mov_reg_imm);
+        try b.reg(.q1);
+        try b.qword(call.capacity);
+        try b.op(.syscall);
+        try b.op(.mov_reg_reg);
+        try b.reg(call.count);
+        try b.reg(.q0);
+        try b.op(.mov_reg_reg);
+        try b.reg(call.eof);
+        try b.reg(.q1);
+    }
+    try b.op(.hlt);
+}
+
+/// A line longer than the buffer, one longer than a host chunk ending in
+/// `\r\n`, and a last line without a newline.
+const line_reader_input = "abcd" ++ "x" ** 300 ++ "\r\n" ++ "yz";
+
+fn expectLinesRead(vm: *Vm) !void {
+    try testing.expectEqual(@as(u64, 4), vm.regs.get(.q3).asU64());
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q4).asU64());
+    try testing.expectEqualStrings("abcd", try vm.mmu.readSlice(0x8000, 4));
+    try testing.expectEqual(@as(u64, 302), vm.regs.get(.q5).asU64());
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q6).asU64());
+    try testing.expectEqualStrings("x" ** 300 ++ "\r\n", try vm.mmu.readSlice(0x8100, 302));
+    try testing.expectEqual(@as(u64, 2), vm.regs.get(.q7).asU64());
+    try testing.expectEqual(@as(u64, 1), vm.regs.get(.q8).asU64());
+    try testing.expectEqualStrings("yz", try vm.mmu.readSlice(0x8600, 2));
+    try testing.expectEqual(@as(u64, 0), vm.regs.get(.q9).asU64());
+    try testing.expectEqual(@as(u64, 1), vm.regs.get(.q10).asU64());
+}
+
+test "read line stops at the buffer, keeps \\r\\n and reports the end of input" {
+    var b = ProgramBuilder.init(testing.allocator);
+    defer b.deinit();
+    try buildLineReader(&b);
+
+    var fds: [2]i32 = undefined;
+    try testing.expectEqual(std.posix.E.SUCCESS, std.posix.errno(std.posix.system.pipe(&fds)));
+    const written = std.posix.system.write(fds[1], line_reader_input, line_reader_input.len);
+    try testing.expectEqual(line_reader_input.len, @as(usize, @intCast(written)));
+    _ = std.posix.system.close(fds[1]);
+
+    var vm = try b.load(testing.allocator);
+    defer vm.deinit();
+    // The table closes the read end with the VM.
+    try vm.files.entries.put(0, fds[0]);
+    try vm.run();
+    try expectLinesRead(&vm);
+
+    // `verify` feeds stdin from memory and must read lines the same way.
+    var a = try b.load(testing.allocator);
+    defer a.deinit();
+    var other = try b.load(testing.allocator);
+    defer other.deinit();
+    const outcome = try verify.compare(testing.allocator, &a, &other, line_reader_input, .{});
+    try testing.expectEqual(@as(usize, 4), outcome.same.matched);
+    try expectLinesRead(&a);
+}
+
+test "batch runs report exit status, steps and the first error" {
+    var exits = ProgramBuilder.init(testing.allocator);
+    defer exits.deinit();
+    try exits.op(.mov_reg_imm);
+    try exits.reg(.q15);
+    try exits.qword(0xFF);
+    try exits.op(.mov_reg_imm);
+    try exits.reg(.q0);
+    try exits.qword(3);
+    try exits.op(.syscall);
+    const exits_program = try exits.finish();
+    defer testing.allocator.free(exits_program);
+
+    var traps = ProgramBuilder.init(testing.allocator);
+    defer traps.deinit();
+    try traps.op(.mov_reg_imm);
+    try traps.reg(.d0);
+    try traps.dword(7);
+    try traps.op(.div_reg_reg_imm);
+    try traps.reg(.d1);
+    try traps.reg(.d0);
+    try traps.dword(0);
+    try traps.op(.hlt);
+    const traps_program = try traps.finish();
+    defer testing.allocator.free(traps_program);
+
+    const limits = batch.Limits{ .memory_size = 65536 };
+    const results = [_]batch.Result{
+        batch.run(testing.allocator, "exits.nyb", exits_program, limits),
+        batch.run(testing.allocator, "traps.nyb", traps_program, limits),
+        batch.run(testing.allocator, "empty.nyb", &.{}, limits),
+    };
+
+    try testing.expectEqual(@as(?u8, 3), results[0].status);
+    try testing.expectEqual(@as(u64, 3), results[0].steps);
+    try testing.expect(results[0].failure == null);
+
+    try testing.expectEqual(@as(?u8, null), results[1].status);
+    try testing.expectEqual(error.DivisionByZero, results[1].failure.?.err);
+    try testing.expectEqual(@as(?usize, 6), results[1].failure.?.addr);
+
+    try testing.expectEqual(error.ProgramTooSmall, results[2].failure.?.err);
+    try testing.expectEqual(@as(?usize, null), results[2].failure.?.addr);
+
+    var output: std.Io.Writer.Allocating = .init(testing.allocator);
+    defer output.deinit();
+    try batch.writeResults(&results, &output.writer);
+    const parsed = try std.json.parseFromSlice(std.json.Value, testing.allocator, output.written(), .{});
+    defer parsed.deinit();
+    const items = parsed.value.array.items;
+    try testing.expectEqual(@as(usize, 3), items.len);
+    try testing.expectEqualStrings("exits.nyb", items[0].object.get("file").?.string);
+    try testing.expectEqual(@as(i64, 3), items[0].object.get("status").?.integer);
+    try testing.expectEqualStrings("DivisionByZero", items[1].object.get("error").?.object.get("name").?.string);
+    try testing.expect(items[2].object.get("error").?.object.get("address").? == .null);
+}
+
+test "batch lists skip blank lines and comments" {
+    const paths = try batch.parseList(testing.allocator, "a.nyb\n\n# broken\n  b.nyb \r\n");
+    defer testing.allocator.free(paths);
+    try testing.expectEqual(@as(usize, 2), paths.len);
+    try testing.expectEqualStrings("a.nyb", paths[0]);
+    try testing.expectEqualStrings("b.nyb", paths[1]);
+}
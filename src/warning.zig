@@ -0,0 +1,40 @@
+//! Non-fatal diagnostics from the preprocessor and the compiler. Each
+//! warning has a code, shown after its message, that `--allow` takes to
+//! silence it; `--deny-warnings` reports the rest as errors and fails the
+//! build once they have all been reported.
+
+const std = @import("std");
+const fehler = @import("fehler");
+const Span = @import("Span.zig");
+
+pub const Code = enum {
+    /// A `#warning` directive.
+    directive,
+    /// A label in the main file that nothing refers to. An error instead in
+    /// strict mode.
+    unused_label,
+    /// An instruction after `hlt`, `ret` or `jmp` that no label leads to.
+    unreachable_code,
+    /// An entry point outside the text section.
+    entry_outside_text,
+    /// An immediate that does not fit its operand, encoded with only its
+    /// low bits under `--truncate-immediates`.
+    truncated_immediate,
+};
+
+/// Which warnings are reported, and whether as errors.
+pub const Policy = struct {
+    allowed: std.EnumSet(Code) = .initEmpty(),
+    deny: bool = false,
+
+    /// Report `message` at `span` tagged with `code`, as an error when
+    /// warnings are denied. Returns false, reporting nothing, when `code`
+    /// is allowed.
+    pub fn report(self: Policy, reporter: *fehler.ErrorReporter, code: Code, message: []const u8, span: Span) bool {
+        if (self.allowed.contains(code)) return false;
+        var buf: [1024]u8 = undefined;
+        const tagged = std.fmt.bufPrint(&buf, "{s} [{s}]", .{ message, @tagName(code) }) catch message;
+        span.report(reporter, if (self.deny) .err else .warning, tagged);
+        return true;
+    }
+};